@@ -71,6 +71,7 @@ data TOption = Help
              | RunRustfmt
              | RustFlatBuffers
              | NestedTS32
+             | WeightWidth String
              | D3log
 
 options :: [OptDescr TOption]
@@ -98,6 +99,7 @@ options = [ Option ['h'] ["help"]             (NoArg Help)
           , Option []    ["run-rustfmt"]      (NoArg RunRustfmt)                "Run rustfmt on the generated code"
           , Option []    ["rust-flatbuffers"] (NoArg RustFlatBuffers)           "Build flatbuffers bindings for Rust"
           , Option []    ["nested-ts-32"]     (NoArg NestedTS32)                "Use 32-bit instead of 16-bit nested timestamps. Supports recursive programs that may perform >65,536 iterations. Slightly increases the memory footprint of the program."
+          , Option []    ["weight-width"]     (ReqArg WeightWidth "BITS")       "Width of the weights (multiplicities) of facts: 32 (default), 64, or 128 bits. Wider weights support programs whose facts have more than 2^31 derivations, at the cost of memory."
           , Option []    ["d3log"]            (NoArg D3log)                     "Compile the input program to execute in the distributed DDlog (D3log) environment."
           ]
 
@@ -130,6 +132,12 @@ addOption config OmitWorkspace    = return config { confOmitWorkspace = True }
 addOption config RunRustfmt       = return config { confRunRustfmt = True }
 addOption config RustFlatBuffers  = return config { confRustFlatBuffers = True }
 addOption config NestedTS32       = return config { confNestedTS32 = True }
+addOption config (WeightWidth w)  = do w' <- case w of
+                                                  "32"  -> return 32
+                                                  "64"  -> return 64
+                                                  "128" -> return 128
+                                                  _     -> errorWithoutStackTrace "invalid weight width"
+                                       return config { confWeightWidth = w' }
 addOption config D3log            = return config { confD3log = True }
 
 validateConfig :: Config -> IO ()
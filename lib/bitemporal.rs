@@ -0,0 +1,59 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::{tuple2, Group, Option as DDlogOption, Vec as DDlogVec};
+
+fn coalesce_intervals(mut intervals: Vec<Interval>) -> DDlogVec<Interval> {
+    intervals.retain(|i| i.from < i.to);
+    intervals.sort_by_key(|i| (i.from, i.to));
+    let mut res: Vec<Interval> = Vec::with_capacity(intervals.len());
+    for i in intervals {
+        match res.last_mut() {
+            Some(last) if i.from <= last.to => last.to = last.to.max(i.to),
+            _ => res.push(i),
+        }
+    }
+    DDlogVec::from(res)
+}
+
+pub fn coalesce(intervals: &DDlogVec<Interval>) -> DDlogVec<Interval> {
+    coalesce_intervals(intervals.iter().cloned().collect())
+}
+
+pub fn group_coalesce<K>(g: &Group<K, Interval>) -> DDlogVec<Interval> {
+    coalesce_intervals(
+        g.iter()
+            .filter(|tuple2(_, w)| *w > 0)
+            .map(|tuple2(i, _)| i)
+            .collect(),
+    )
+}
+
+pub fn group_as_of<K, V: Clone>(g: &Group<K, tuple2<V, Interval>>, t: &u64) -> DDlogOption<V> {
+    g.iter()
+        .filter(|tuple2(tuple2(_, i), w)| *w > 0 && i.from <= *t && *t < i.to)
+        .max_by_key(|tuple2(tuple2(_, i), _)| i.from)
+        .map_or(DDlogOption::None, |tuple2(tuple2(v, _), _)| {
+            DDlogOption::Some { x: v }
+        })
+}
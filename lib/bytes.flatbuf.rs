@@ -0,0 +1,47 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use types__bytes::Bytes;
+
+// For scalar types, the FlatBuffers API returns slice instead of 'Vector'.
+impl<'a> FromFlatBuffer<&'a [u8]> for Bytes {
+    fn from_flatbuf(fb: &'a [u8]) -> Result<Self, String> {
+        Ok(Bytes::new(fb))
+    }
+}
+
+impl<'b> ToFlatBuffer<'b> for Bytes {
+    type Target = fbrt::WIPOffset<fbrt::Vector<'b, u8>>;
+
+    fn to_flatbuf(&self, fbb: &mut fbrt::FlatBufferBuilder<'b>) -> Self::Target {
+        fbb.create_vector(self.as_slice())
+    }
+}
+
+impl<'b> ToFlatBufferVectorElement<'b> for Bytes {
+    type Target = <Bytes as ToFlatBuffer<'b>>::Target;
+
+    fn to_flatbuf_vector_element(&self, fbb: &mut fbrt::FlatBufferBuilder<'b>) -> Self::Target {
+        self.to_flatbuf(fbb)
+    }
+}
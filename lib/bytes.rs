@@ -0,0 +1,278 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::{Option as DDlogOption, Result as DDlogResult, Vec as DDlogVec};
+use differential_datalog::record::{CollectionKind, Record};
+use serde::{
+    de::{Deserializer, SeqAccess, Visitor},
+    ser::Serializer,
+};
+use std::{
+    cmp,
+    fmt::{self, Debug, Display, Formatter, Result as FmtResult},
+    iter::Copied,
+    slice,
+    sync::Arc,
+};
+
+/// Immutable reference-counted byte string.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bytes {
+    bytes: Arc<[u8]>,
+}
+
+impl Bytes {
+    pub fn new(bytes: &[u8]) -> Self {
+        Self {
+            bytes: Arc::from(bytes),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Iterator over bytes; used by DDlog to iterate over `Bytes` values.
+    pub fn iter(&self) -> Copied<slice::Iter<'_, u8>> {
+        self.bytes.iter().copied()
+    }
+}
+
+impl Default for Bytes {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.bytes
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(bytes: &[u8]) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes: Arc::from(bytes),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Bytes {
+    type Item = u8;
+    type IntoIter = Copied<slice::Iter<'a, u8>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Formats the byte string as a Rust byte string literal, e.g., `b"foo\x00"`.
+impl Debug for Bytes {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "b\"")?;
+        for b in self.bytes.iter() {
+            for c in std::ascii::escape_default(*b) {
+                write!(f, "{}", c as char)?;
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+impl Display for Bytes {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        Debug::fmt(self, f)
+    }
+}
+
+impl Serialize for Bytes {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(&self.bytes)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Bytes;
+
+    fn expecting(&self, formatter: &mut Formatter) -> fmt::Result {
+        formatter.write_str("a byte array")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(Bytes::new(v))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(Bytes::from(v))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+        Ok(Bytes::from(bytes))
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(BytesVisitor)
+    }
+}
+
+impl IntoRecord for Bytes {
+    fn into_record(self) -> Record {
+        Record::Array(
+            CollectionKind::Vector,
+            self.iter().map(IntoRecord::into_record).collect(),
+        )
+    }
+}
+
+/// `Bytes` can be constructed from an array of integers or from a string,
+/// in which case the UTF-8 encoding of the string is used.
+impl FromRecord for Bytes {
+    fn from_record(record: &Record) -> Result<Self, String> {
+        match record {
+            Record::Array(_, bytes) => Ok(Bytes::from(
+                bytes
+                    .iter()
+                    .map(u8::from_record)
+                    .collect::<Result<Vec<u8>, String>>()?,
+            )),
+            Record::String(s) => Ok(Bytes::new(s.as_bytes())),
+            error => Err(format!("not a valid byte string: {:?}", error)),
+        }
+    }
+}
+
+impl Mutator<Bytes> for Record {
+    fn mutate(&self, bytes: &mut Bytes) -> Result<(), String> {
+        *bytes = Bytes::from_record(self)?;
+        Ok(())
+    }
+}
+
+pub fn bytes_empty() -> Bytes {
+    Bytes::default()
+}
+
+pub fn bytes_from_vec(v: &DDlogVec<u8>) -> Bytes {
+    Bytes::new(v.as_slice())
+}
+
+pub fn bytes_to_vec(b: &Bytes) -> DDlogVec<u8> {
+    DDlogVec::from(b.as_slice())
+}
+
+pub fn bytes_from_string(s: &String) -> Bytes {
+    Bytes::new(s.as_bytes())
+}
+
+pub fn bytes_to_string(b: &Bytes) -> DDlogResult<String, String> {
+    ddlog_std::res2std(std::str::from_utf8(b.as_slice()).map(|s| s.to_string()))
+}
+
+pub fn bytes_len(b: &Bytes) -> std_usize {
+    b.len() as std_usize
+}
+
+pub fn bytes_is_empty(b: &Bytes) -> bool {
+    b.is_empty()
+}
+
+pub fn bytes_nth(b: &Bytes, n: &std_usize) -> DDlogOption<u8> {
+    ddlog_std::option2std(b.get(*n as usize).copied())
+}
+
+pub fn bytes_slice(b: &Bytes, start: &std_usize, end: &std_usize) -> Bytes {
+    let len = b.len();
+    let from = cmp::min(*start as usize, len);
+    let to = cmp::max(from, cmp::min(*end as usize, len));
+
+    if from == 0 && to == len {
+        b.clone()
+    } else {
+        Bytes::new(&b[from..to])
+    }
+}
+
+pub fn bytes_concat(b1: &Bytes, b2: &Bytes) -> Bytes {
+    if b2.is_empty() {
+        return b1.clone();
+    }
+    if b1.is_empty() {
+        return b2.clone();
+    }
+
+    let mut bytes = Vec::with_capacity(b1.len() + b2.len());
+    bytes.extend_from_slice(b1);
+    bytes.extend_from_slice(b2);
+    Bytes::from(bytes)
+}
+
+pub fn bytes_starts_with(b: &Bytes, prefix: &Bytes) -> bool {
+    b.starts_with(prefix)
+}
+
+pub fn bytes_ends_with(b: &Bytes, suffix: &Bytes) -> bool {
+    b.ends_with(suffix)
+}
+
+pub fn bytes_find(b: &Bytes, needle: &Bytes) -> DDlogOption<std_usize> {
+    let pos = if needle.is_empty() {
+        Some(0)
+    } else {
+        b.windows(needle.len())
+            .position(|window| window == needle.as_slice())
+    };
+
+    ddlog_std::option2std(pos.map(|pos| pos as std_usize))
+}
@@ -0,0 +1,141 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use cel_interpreter::objects::Key;
+use cel_interpreter::{Context, Program, Value};
+use ddlog_std::Result as DDlogResult;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use serde_json::{Map as JsonMap, Number, Value as Json};
+use std::{collections::HashMap, sync::Arc, sync::Mutex};
+use types__json::JsonValue;
+
+/// Maximal number of compiled expressions kept in `PROGRAM_CACHE`.
+const PROGRAM_CACHE_CAPACITY: usize = 1024;
+
+/// Compiled expressions keyed by expression text.  Policies are typically
+/// a small set of expressions evaluated against many records.
+static PROGRAM_CACHE: Lazy<Mutex<LruCache<String, Arc<Program>>>> =
+    Lazy::new(|| Mutex::new(LruCache::new(PROGRAM_CACHE_CAPACITY)));
+
+fn compile(expr: &str) -> Result<Arc<Program>, String> {
+    if let Some(program) = PROGRAM_CACHE.lock().unwrap().get(expr) {
+        return Ok(program.clone());
+    }
+
+    let program =
+        Arc::new(Program::compile(expr).map_err(|e| format!("invalid CEL expression: {}", e))?);
+    PROGRAM_CACHE
+        .lock()
+        .unwrap()
+        .put(expr.to_string(), program.clone());
+    Ok(program)
+}
+
+fn json_to_cel(json: Json) -> Value {
+    match json {
+        Json::Null => Value::Null,
+        Json::Bool(b) => Value::Bool(b),
+        Json::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Value::Int(i)
+            } else if let Some(u) = n.as_u64() {
+                Value::UInt(u)
+            } else {
+                Value::Float(n.as_f64().unwrap_or_default())
+            }
+        }
+        Json::String(s) => Value::String(Arc::new(s)),
+        Json::Array(a) => Value::List(Arc::new(a.into_iter().map(json_to_cel).collect())),
+        Json::Object(o) => Value::from(
+            o.into_iter()
+                .map(|(k, v)| (k, json_to_cel(v)))
+                .collect::<HashMap<String, Value>>(),
+        ),
+    }
+}
+
+fn cel_to_json(value: &Value) -> Result<Json, String> {
+    Ok(match value {
+        Value::Null => Json::Null,
+        Value::Bool(b) => Json::Bool(*b),
+        Value::Int(i) => Json::from(*i),
+        Value::UInt(u) => Json::from(*u),
+        // NaN and infinity have no JSON representation.
+        Value::Float(f) => Number::from_f64(*f).map_or(Json::Null, Json::Number),
+        Value::String(s) => Json::String(s.as_ref().clone()),
+        Value::List(l) => Json::Array(l.iter().map(cel_to_json).collect::<Result<_, _>>()?),
+        Value::Map(m) => {
+            let mut object = JsonMap::new();
+            for (k, v) in m.map.iter() {
+                let key = match k {
+                    Key::String(s) => s.as_ref().clone(),
+                    Key::Int(i) => i.to_string(),
+                    Key::Uint(u) => u.to_string(),
+                    Key::Bool(b) => b.to_string(),
+                };
+                object.insert(key, cel_to_json(v)?);
+            }
+            Json::Object(object)
+        }
+        v => return Err(format!("CEL value {:?} cannot be converted to JSON", v)),
+    })
+}
+
+fn eval(expr: &str, vars: &JsonValue) -> Result<Value, String> {
+    let program = compile(expr)?;
+    let mut context = Context::default();
+    match Json::from(vars.clone()) {
+        Json::Object(vars) => {
+            for (name, value) in vars {
+                context.add_variable_from_value(name, json_to_cel(value));
+            }
+        }
+        Json::Null => (),
+        _ => return Err("CEL variables must be a JSON object".to_string()),
+    }
+    program
+        .execute(&context)
+        .map_err(|e| format!("failed to evaluate CEL expression: {}", e))
+}
+
+pub fn cel_eval(expr: &String, vars: &JsonValue) -> DDlogResult<JsonValue, String> {
+    eval(expr, vars)
+        .and_then(|v| cel_to_json(&v))
+        .map(JsonValue::from)
+        .into()
+}
+
+pub fn cel_eval_bool(expr: &String, vars: &JsonValue) -> DDlogResult<bool, String> {
+    match eval(expr, vars) {
+        Ok(Value::Bool(b)) => DDlogResult::Ok { res: b },
+        Ok(v) => DDlogResult::Err {
+            err: format!("CEL expression evaluated to {:?} instead of a Boolean", v),
+        },
+        Err(err) => DDlogResult::Err { err },
+    }
+}
+
+pub fn cel_check(expr: &String) -> DDlogResult<(), String> {
+    compile(expr).map(|_| ()).into()
+}
@@ -0,0 +1,103 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use base64::DecodeError as Base64DecodeError;
+use ddlog_std::Vec as DDlogVec;
+use hex::FromHexError;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
+
+/// Characters that are not percent-encoded: unreserved URI characters
+/// (RFC 3986, section 2.3).
+const URI_UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn base64_error(e: Base64DecodeError) -> String {
+    match e {
+        Base64DecodeError::InvalidByte(p, b) => format!("Invalid byte {} at position {}", b, p),
+        Base64DecodeError::InvalidLength => "Invalid length".to_string(),
+        Base64DecodeError::InvalidLastSymbol(p, b) => {
+            format!("Invalid last byte {} at position {}", b, p)
+        }
+    }
+}
+
+fn hex_error(e: FromHexError) -> String {
+    match e {
+        FromHexError::InvalidHexCharacter { c, index } => {
+            format!("Invalid character {:?} at position {}", c, index)
+        }
+        FromHexError::OddLength => "Odd number of digits".to_string(),
+        FromHexError::InvalidStringLength => "Invalid string length".to_string(),
+    }
+}
+
+pub fn base64_encode(bytes: &DDlogVec<u8>) -> String {
+    base64::encode(&bytes.vec)
+}
+
+pub fn base64_decode(s: &String) -> ddlog_std::Result<DDlogVec<u8>, String> {
+    ddlog_std::res2std(base64::decode(s).map(DDlogVec::from).map_err(base64_error))
+}
+
+pub fn base64_url_encode(bytes: &DDlogVec<u8>) -> String {
+    base64::encode_config(&bytes.vec, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn base64_url_decode(s: &String) -> ddlog_std::Result<DDlogVec<u8>, String> {
+    ddlog_std::res2std(
+        base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+            .map(DDlogVec::from)
+            .map_err(base64_error),
+    )
+}
+
+pub fn hex_encode(bytes: &DDlogVec<u8>) -> String {
+    hex::encode(&bytes.vec)
+}
+
+pub fn hex_decode(s: &String) -> ddlog_std::Result<DDlogVec<u8>, String> {
+    ddlog_std::res2std(hex::decode(s).map(DDlogVec::from).map_err(hex_error))
+}
+
+pub fn percent_encode(bytes: &DDlogVec<u8>) -> String {
+    percent_encoding::percent_encode(&bytes.vec, URI_UNRESERVED).to_string()
+}
+
+pub fn percent_decode(s: &String) -> DDlogVec<u8> {
+    percent_encoding::percent_decode_str(s).collect()
+}
+
+pub fn percent_encode_str(s: &String) -> String {
+    percent_encoding::utf8_percent_encode(s, URI_UNRESERVED).to_string()
+}
+
+pub fn percent_decode_str(s: &String) -> ddlog_std::Result<String, String> {
+    ddlog_std::res2std(
+        percent_encoding::percent_decode_str(s)
+            .decode_utf8()
+            .map(|decoded| decoded.into_owned()),
+    )
+}
@@ -0,0 +1,80 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::Result as DDlogResult;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    cmp,
+    io::{self, Read, Write},
+};
+use types__bytes::Bytes;
+
+pub fn gzip_compress(data: &Bytes) -> Bytes {
+    gzip(data, Compression::default())
+}
+
+pub fn gzip_compress_level(data: &Bytes, level: &u32) -> Bytes {
+    gzip(data, Compression::new(cmp::min(*level, 9)))
+}
+
+fn gzip(data: &Bytes, level: Compression) -> Bytes {
+    let mut encoder = GzEncoder::new(Vec::new(), level);
+    // Writing to a vector cannot fail.
+    encoder.write_all(data).unwrap();
+    Bytes::from(encoder.finish().unwrap())
+}
+
+pub fn gzip_decompress_max(data: &Bytes, max_size: &u64) -> DDlogResult<Bytes, String> {
+    ddlog_std::res2std(read_max(GzDecoder::new(data.as_slice()), *max_size))
+}
+
+pub fn zstd_compress(data: &Bytes) -> DDlogResult<Bytes, String> {
+    zstd_compress_level(data, &0)
+}
+
+pub fn zstd_compress_level(data: &Bytes, level: &i32) -> DDlogResult<Bytes, String> {
+    ddlog_std::res2std(zstd::stream::encode_all(data.as_slice(), *level).map(Bytes::from))
+}
+
+pub fn zstd_decompress_max(data: &Bytes, max_size: &u64) -> DDlogResult<Bytes, String> {
+    ddlog_std::res2std(
+        zstd::stream::read::Decoder::new(data.as_slice())
+            .and_then(|decoder| read_max(decoder, *max_size)),
+    )
+}
+
+/// Read the decompressed stream, failing as soon as it exceeds `max_size`
+/// bytes rather than after decompressing all of it.
+fn read_max<R: Read>(reader: R, max_size: u64) -> io::Result<Bytes> {
+    let mut decoded = Vec::new();
+    reader
+        .take(max_size.saturating_add(1))
+        .read_to_end(&mut decoded)?;
+    if decoded.len() as u64 > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("decompressed data exceeds {} bytes", max_size),
+        ));
+    }
+    Ok(Bytes::from(decoded))
+}
@@ -0,0 +1,93 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use hmac::{Hmac, Mac, NewMac};
+use md5::Md5;
+use sha2::{Digest, Sha256, Sha512};
+use types__bytes::Bytes;
+
+type HmacSha256 = Hmac<Sha256>;
+type HmacSha512 = Hmac<Sha512>;
+
+pub fn sha256(data: &Bytes) -> Bytes {
+    Bytes::new(&Sha256::digest(data))
+}
+
+pub fn sha256_hex(data: &Bytes) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+pub fn sha512(data: &Bytes) -> Bytes {
+    Bytes::new(&Sha512::digest(data))
+}
+
+pub fn sha512_hex(data: &Bytes) -> String {
+    hex::encode(Sha512::digest(data))
+}
+
+pub fn blake3(data: &Bytes) -> Bytes {
+    Bytes::new(blake3::hash(data).as_bytes())
+}
+
+pub fn blake3_hex(data: &Bytes) -> String {
+    blake3::hash(data).to_hex().to_string()
+}
+
+pub fn md5(data: &Bytes) -> Bytes {
+    Bytes::new(&Md5::digest(data))
+}
+
+pub fn md5_hex(data: &Bytes) -> String {
+    hex::encode(Md5::digest(data))
+}
+
+// HMAC accepts keys of any length, so `new_from_slice` never fails.
+fn new_hmac<M: Mac + NewMac>(key: &Bytes, msg: &Bytes) -> M {
+    let mut mac = M::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(msg);
+    mac
+}
+
+pub fn hmac_sha256(key: &Bytes, msg: &Bytes) -> Bytes {
+    Bytes::new(&new_hmac::<HmacSha256>(key, msg).finalize().into_bytes())
+}
+
+pub fn hmac_sha256_hex(key: &Bytes, msg: &Bytes) -> String {
+    hex::encode(new_hmac::<HmacSha256>(key, msg).finalize().into_bytes())
+}
+
+pub fn hmac_sha512(key: &Bytes, msg: &Bytes) -> Bytes {
+    Bytes::new(&new_hmac::<HmacSha512>(key, msg).finalize().into_bytes())
+}
+
+pub fn hmac_sha512_hex(key: &Bytes, msg: &Bytes) -> String {
+    hex::encode(new_hmac::<HmacSha512>(key, msg).finalize().into_bytes())
+}
+
+pub fn hmac_sha256_verify(key: &Bytes, msg: &Bytes, tag: &Bytes) -> bool {
+    new_hmac::<HmacSha256>(key, msg).verify(tag).is_ok()
+}
+
+pub fn hmac_sha512_verify(key: &Bytes, msg: &Bytes, tag: &Bytes) -> bool {
+    new_hmac::<HmacSha512>(key, msg).verify(tag).is_ok()
+}
@@ -0,0 +1,633 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::{Result as DDlogResult, Vec as DDlogVec};
+use fixed_decimal::FixedDecimal;
+use icu::calendar::{Date, Gregorian};
+use icu::datetime::{options::length, TypedDateFormatter};
+use icu::decimal::FixedDecimalFormatter;
+use ordered_float::OrderedFloat;
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Display,
+    LowerHex,
+    UpperHex,
+    Octal,
+    Binary,
+    Fixed,
+    Exp { upper: bool },
+}
+
+/* Parsed format specification shared by `format_string` and `sprintf`. */
+#[derive(Clone, Copy)]
+struct Spec {
+    fill: char,
+    align: Option<Align>,
+    plus: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    separator: Option<char>,
+    precision: Option<usize>,
+    kind: Kind,
+    /* The spec came from an integer-only `sprintf` conversion (`%d`, `%x`). */
+    integer_only: bool,
+}
+
+impl Default for Spec {
+    fn default() -> Self {
+        Spec {
+            fill: ' ',
+            align: None,
+            plus: false,
+            alternate: false,
+            zero: false,
+            width: 0,
+            separator: None,
+            precision: None,
+            kind: Kind::Display,
+            integer_only: false,
+        }
+    }
+}
+
+/* Integer represented as sign and big-endian magnitude bytes, so that all
+ * integer types, including `bigint`, share the same formatting code. */
+struct Integer {
+    negative: bool,
+    magnitude: Vec<u8>,
+}
+
+impl Integer {
+    fn from_i128(i: i128) -> Integer {
+        Integer {
+            negative: i < 0,
+            magnitude: (i.wrapping_abs() as u128).to_be_bytes().to_vec(),
+        }
+    }
+
+    fn from_u128(u: u128) -> Integer {
+        Integer {
+            negative: false,
+            magnitude: u.to_be_bytes().to_vec(),
+        }
+    }
+
+    fn from_bigint(n: &ddlog_bigint::Int) -> Integer {
+        Integer {
+            negative: *n < ddlog_bigint::Int::default(),
+            magnitude: n.to_bytes_be().1,
+        }
+    }
+
+    /* Digits of the magnitude in the given radix, most significant first. */
+    fn digits(&self, radix: u32, upper: bool) -> String {
+        let mut magnitude: Vec<u32> = self.magnitude.iter().map(|b| *b as u32).collect();
+        let mut digits = Vec::new();
+        loop {
+            let mut remainder = 0;
+            for byte in magnitude.iter_mut() {
+                let acc = (remainder << 8) | *byte;
+                *byte = acc / radix;
+                remainder = acc % radix;
+            }
+            let digit = std::char::from_digit(remainder, radix).unwrap();
+            digits.push(if upper {
+                digit.to_ascii_uppercase()
+            } else {
+                digit
+            });
+            if magnitude.iter().all(|b| *b == 0) {
+                break;
+            }
+        }
+        digits.iter().rev().collect()
+    }
+}
+
+fn fmt_err<T>(msg: String) -> DDlogResult<T, String> {
+    DDlogResult::Err { err: msg }
+}
+
+/* Insert `sep` between groups of `group` digits, counting from the right. */
+fn group_digits(digits: &str, sep: &str, group: usize) -> String {
+    let len = digits.chars().count();
+    let mut res = String::with_capacity(len + len / group);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (len - i) % group == 0 {
+            res.push_str(sep);
+        }
+        res.push(c);
+    }
+    res
+}
+
+fn pad(s: &str, width: usize, fill: char, align: Align) -> String {
+    let len = s.chars().count();
+    if len >= width {
+        return s.to_string();
+    }
+    let padding = width - len;
+    let (left, right) = match align {
+        Align::Left => (0, padding),
+        Align::Right => (padding, 0),
+        Align::Center => (padding / 2, padding - padding / 2),
+    };
+    let mut res = String::with_capacity(s.len() + padding * fill.len_utf8());
+    res.extend(std::iter::repeat(fill).take(left));
+    res.push_str(s);
+    res.extend(std::iter::repeat(fill).take(right));
+    res
+}
+
+/* Pad a number consisting of `sign`, `prefix`, and `body`. */
+fn pad_number(sign: &str, prefix: &str, body: &str, spec: &Spec) -> String {
+    if spec.zero && spec.align.is_none() {
+        let len = sign.len() + prefix.len() + body.chars().count();
+        let zeros = spec.width.saturating_sub(len);
+        format!("{}{}{}{}", sign, prefix, "0".repeat(zeros), body)
+    } else {
+        pad(
+            &format!("{}{}{}", sign, prefix, body),
+            spec.width,
+            spec.fill,
+            spec.align.unwrap_or(Align::Right),
+        )
+    }
+}
+
+fn format_integer(i: &Integer, spec: &Spec) -> Result<String, String> {
+    let (radix, upper, prefix) = match spec.kind {
+        Kind::Display => (10, false, ""),
+        Kind::LowerHex => (16, false, "0x"),
+        Kind::UpperHex => (16, true, "0X"),
+        Kind::Octal => (8, false, "0o"),
+        Kind::Binary => (2, false, "0b"),
+        Kind::Fixed | Kind::Exp { .. } => {
+            let f = i.digits(10, false).parse::<f64>().unwrap();
+            return format_float(if i.negative { -f } else { f }, spec);
+        }
+    };
+    if spec.precision.is_some() {
+        return Err("precision is not allowed for integer arguments".to_string());
+    }
+    let digits = i.digits(radix, upper);
+    let body = match spec.separator {
+        Some(sep) => group_digits(&digits, &sep.to_string(), if radix == 10 { 3 } else { 4 }),
+        None => digits,
+    };
+    let sign = if i.negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else {
+        ""
+    };
+    let prefix = if spec.alternate { prefix } else { "" };
+    Ok(pad_number(sign, prefix, &body, spec))
+}
+
+fn format_float(f: f64, spec: &Spec) -> Result<String, String> {
+    if spec.integer_only {
+        return Err("integer conversion applied to a floating point argument".to_string());
+    }
+    let sign = if f.is_sign_negative() && !f.is_nan() {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else {
+        ""
+    };
+    let abs = f.abs();
+    let body = if !abs.is_finite() {
+        abs.to_string()
+    } else {
+        let body = match (spec.kind, spec.precision) {
+            (Kind::Display, None) => abs.to_string(),
+            (Kind::Display, Some(p)) | (Kind::Fixed, Some(p)) => format!("{:.*}", p, abs),
+            (Kind::Fixed, None) => format!("{:.6}", abs),
+            (Kind::Exp { upper }, p) => {
+                let s = match p {
+                    Some(p) => format!("{:.*e}", p, abs),
+                    None => format!("{:e}", abs),
+                };
+                if upper {
+                    s.to_uppercase()
+                } else {
+                    s
+                }
+            }
+            _ => {
+                return Err(
+                    "hexadecimal, octal, and binary formats require an integer argument"
+                        .to_string(),
+                )
+            }
+        };
+        match spec.separator {
+            Some(sep) => {
+                let int_len = body
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or_else(|| body.len());
+                format!(
+                    "{}{}",
+                    group_digits(&body[..int_len], &sep.to_string(), 3),
+                    &body[int_len..]
+                )
+            }
+            None => body,
+        }
+    };
+    Ok(pad_number(sign, "", &body, spec))
+}
+
+fn format_text(s: &str, spec: &Spec) -> Result<String, String> {
+    if spec.integer_only || spec.kind != Kind::Display {
+        return Err(format!(
+            "numeric format applied to non-numeric argument '{}'",
+            s
+        ));
+    }
+    let truncated: String = match spec.precision {
+        Some(p) => s.chars().take(p).collect(),
+        None => s.to_string(),
+    };
+    Ok(pad(
+        &truncated,
+        spec.width,
+        spec.fill,
+        spec.align.unwrap_or(Align::Left),
+    ))
+}
+
+fn format_arg(arg: &FmtArg, spec: &Spec) -> Result<String, String> {
+    match arg {
+        FmtArg::FmtString { s } => format_text(s, spec),
+        FmtArg::FmtBool { b } => format_text(if *b { "true" } else { "false" }, spec),
+        FmtArg::FmtSigned { i } => format_integer(&Integer::from_i128(*i), spec),
+        FmtArg::FmtUnsigned { u } => format_integer(&Integer::from_u128(*u), spec),
+        FmtArg::FmtBigInt { n } => format_integer(&Integer::from_bigint(n), spec),
+        /* Convert via the shortest decimal representation of the `f32`
+         * value, so that, e.g., `0.1f32` is not printed as `0.10000000149`. */
+        FmtArg::FmtFloat { f } => {
+            format_float(f.into_inner().to_string().parse::<f64>().unwrap(), spec)
+        }
+        FmtArg::FmtDouble { d } => format_float(d.into_inner(), spec),
+    }
+}
+
+fn parse_number(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut res: Option<usize> = None;
+    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+        chars.next();
+        res = Some(
+            res.unwrap_or(0)
+                .saturating_mul(10)
+                .saturating_add(d as usize),
+        );
+    }
+    res
+}
+
+fn parse_align(c: char) -> Option<Align> {
+    match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    }
+}
+
+/* Parse the part of a `format_string` placeholder after the colon. */
+fn parse_format_spec(s: &str) -> Result<Spec, String> {
+    let mut spec = Spec::default();
+    let mut first_two = s.chars().take(2);
+    let (c0, c1) = (first_two.next(), first_two.next());
+    let mut chars = s.chars().peekable();
+    if let Some(align) = c1.and_then(parse_align) {
+        spec.fill = c0.unwrap();
+        spec.align = Some(align);
+        chars.next();
+        chars.next();
+    } else if let Some(align) = c0.and_then(parse_align) {
+        spec.align = Some(align);
+        chars.next();
+    }
+    if chars.peek() == Some(&'+') {
+        spec.plus = true;
+        chars.next();
+    }
+    if chars.peek() == Some(&'#') {
+        spec.alternate = true;
+        chars.next();
+    }
+    if chars.peek() == Some(&'0') {
+        spec.zero = true;
+        chars.next();
+    }
+    spec.width = parse_number(&mut chars).unwrap_or(0);
+    if let Some(&sep) = chars.peek().filter(|c| **c == ',' || **c == '_') {
+        spec.separator = Some(sep);
+        chars.next();
+    }
+    if chars.peek() == Some(&'.') {
+        chars.next();
+        spec.precision =
+            Some(parse_number(&mut chars).ok_or_else(|| format!("missing precision in '{}'", s))?);
+    }
+    spec.kind = match chars.next() {
+        None => Kind::Display,
+        Some('x') => Kind::LowerHex,
+        Some('X') => Kind::UpperHex,
+        Some('o') => Kind::Octal,
+        Some('b') => Kind::Binary,
+        Some('f') => Kind::Fixed,
+        Some('e') => Kind::Exp { upper: false },
+        Some('E') => Kind::Exp { upper: true },
+        Some(c) => return Err(format!("unknown format type '{}' in '{}'", c, s)),
+    };
+    if chars.next().is_some() {
+        return Err(format!("invalid format specification '{}'", s));
+    }
+    Ok(spec)
+}
+
+fn next_arg<'a>(args: &'a DDlogVec<FmtArg>, idx: usize) -> Result<&'a FmtArg, String> {
+    args.get(idx).ok_or_else(|| {
+        format!(
+            "format string refers to argument {}, but only {} argument(s) supplied",
+            idx,
+            args.len()
+        )
+    })
+}
+
+fn do_format_string(fmt: &str, args: &DDlogVec<FmtArg>) -> Result<String, String> {
+    let mut res = String::with_capacity(fmt.len());
+    let mut chars = fmt.chars().peekable();
+    let mut next_idx = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                res.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                res.push('}');
+            }
+            '}' => return Err("unmatched '}' in format string".to_string()),
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err("unterminated '{' in format string".to_string()),
+                    }
+                }
+                let (index, spec) = match placeholder.find(':') {
+                    Some(colon) => (&placeholder[..colon], &placeholder[colon + 1..]),
+                    None => (placeholder.as_str(), ""),
+                };
+                let idx = if index.is_empty() {
+                    next_idx += 1;
+                    next_idx - 1
+                } else {
+                    index
+                        .parse::<usize>()
+                        .map_err(|_| format!("invalid argument index '{}'", index))?
+                };
+                res.push_str(&format_arg(
+                    next_arg(args, idx)?,
+                    &parse_format_spec(spec)?,
+                )?);
+            }
+            c => res.push(c),
+        }
+    }
+    Ok(res)
+}
+
+fn do_sprintf(fmt: &str, args: &DDlogVec<FmtArg>) -> Result<String, String> {
+    let mut res = String::with_capacity(fmt.len());
+    let mut chars = fmt.chars().peekable();
+    let mut next_idx = 0;
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            res.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            res.push('%');
+            continue;
+        }
+        let mut spec = Spec::default();
+        let mut left = false;
+        while let Some(&flag) = chars.peek().filter(|c| "-+#0',".contains(**c)) {
+            chars.next();
+            match flag {
+                '-' => left = true,
+                '+' => spec.plus = true,
+                '#' => spec.alternate = true,
+                '0' => spec.zero = true,
+                _ => spec.separator = Some(','),
+            }
+        }
+        spec.width = parse_number(&mut chars).unwrap_or(0);
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            spec.precision = Some(parse_number(&mut chars).unwrap_or(0));
+        }
+        let (kind, integer_only) = match chars.next() {
+            Some('d') | Some('i') | Some('u') => (Kind::Display, true),
+            Some('s') => (Kind::Display, false),
+            Some('x') => (Kind::LowerHex, true),
+            Some('X') => (Kind::UpperHex, true),
+            Some('o') => (Kind::Octal, true),
+            Some('b') => (Kind::Binary, true),
+            Some('f') | Some('F') => (Kind::Fixed, false),
+            Some('e') => (Kind::Exp { upper: false }, false),
+            Some('E') => (Kind::Exp { upper: true }, false),
+            Some(c) => return Err(format!("unknown conversion '%{}' in format string", c)),
+            None => return Err("incomplete conversion at the end of format string".to_string()),
+        };
+        spec.kind = kind;
+        spec.integer_only = integer_only;
+        /* C `printf` right-aligns strings by default. */
+        spec.align = if left {
+            Some(Align::Left)
+        } else if spec.zero {
+            None
+        } else {
+            Some(Align::Right)
+        };
+        let arg = next_arg(args, next_idx)?;
+        next_idx += 1;
+        res.push_str(&format_arg(arg, &spec)?);
+    }
+    Ok(res)
+}
+
+pub fn format_string(fmt: &String, args: &DDlogVec<FmtArg>) -> DDlogResult<String, String> {
+    match do_format_string(fmt, args) {
+        Ok(res) => DDlogResult::Ok { res },
+        Err(e) => fmt_err(e),
+    }
+}
+
+pub fn sprintf(fmt: &String, args: &DDlogVec<FmtArg>) -> DDlogResult<String, String> {
+    match do_sprintf(fmt, args) {
+        Ok(res) => DDlogResult::Ok { res },
+        Err(e) => fmt_err(e),
+    }
+}
+
+fn fill_char(fill: &str) -> char {
+    fill.chars().next().unwrap_or(' ')
+}
+
+pub fn pad_left(s: &String, width: &std_usize, fill: &String) -> String {
+    pad(s, *width as usize, fill_char(fill), Align::Right)
+}
+
+pub fn pad_right(s: &String, width: &std_usize, fill: &String) -> String {
+    pad(s, *width as usize, fill_char(fill), Align::Left)
+}
+
+pub fn pad_center(s: &String, width: &std_usize, fill: &String) -> String {
+    pad(s, *width as usize, fill_char(fill), Align::Center)
+}
+
+pub fn fmt_hex(x: &u128) -> String {
+    format!("{:x}", x)
+}
+
+pub fn fmt_oct(x: &u128) -> String {
+    format!("{:o}", x)
+}
+
+pub fn fmt_bin(x: &u128) -> String {
+    format!("{:b}", x)
+}
+
+pub fn fmt_thousands(x: &i128, sep: &String) -> String {
+    let digits = (x.wrapping_abs() as u128).to_string();
+    let sign = if *x < 0 { "-" } else { "" };
+    format!("{}{}", sign, group_digits(&digits, sep, 3))
+}
+
+pub fn fmt_fixed(x: &OrderedFloat<f64>, precision: &std_usize) -> String {
+    format!("{:.*}", *precision as usize, x.into_inner())
+}
+
+fn parse_locale(locale: &str) -> Result<icu::locid::Locale, String> {
+    locale
+        .parse::<icu::locid::Locale>()
+        .map_err(|e| format!("invalid locale '{}': {}", locale, e))
+}
+
+fn locale_decimal_formatter(locale: &str) -> Result<FixedDecimalFormatter, String> {
+    let locale = parse_locale(locale)?;
+    FixedDecimalFormatter::try_new(&(&locale).into(), Default::default())
+        .map_err(|e| format!("no decimal format data for locale '{}': {}", locale, e))
+}
+
+fn do_fmt_locale_int(x: i128, locale: &str) -> Result<String, String> {
+    Ok(locale_decimal_formatter(locale)?.format_to_string(&FixedDecimal::from(x)))
+}
+
+fn do_fmt_locale_double(x: f64, precision: usize, locale: &str) -> Result<String, String> {
+    if !x.is_finite() {
+        return Err(format!("cannot format non-finite value {}", x));
+    }
+    /* Round using Rust's formatter, which preserves trailing zeros in the
+     * resulting `FixedDecimal`. */
+    let decimal = format!("{:.*}", precision, x)
+        .parse::<FixedDecimal>()
+        .map_err(|e| format!("cannot format {}: {}", x, e))?;
+    Ok(locale_decimal_formatter(locale)?.format_to_string(&decimal))
+}
+
+fn do_fmt_locale_date(
+    year: i32,
+    month: u8,
+    day: u8,
+    style: &DateStyle,
+    locale: &str,
+) -> Result<String, String> {
+    let date = Date::try_new_gregorian_date(year, month, day)
+        .map_err(|e| format!("invalid date {}-{}-{}: {}", year, month, day, e))?;
+    let length = match style {
+        DateStyle::DateFull => length::Date::Full,
+        DateStyle::DateLong => length::Date::Long,
+        DateStyle::DateMedium => length::Date::Medium,
+        DateStyle::DateShort => length::Date::Short,
+    };
+    let locale = parse_locale(locale)?;
+    let formatter = TypedDateFormatter::<Gregorian>::try_new_with_length(&(&locale).into(), length)
+        .map_err(|e| format!("no date format data for locale '{}': {}", locale, e))?;
+    Ok(formatter.format_to_string(&date))
+}
+
+pub fn fmt_locale_int(x: &i128, locale: &String) -> DDlogResult<String, String> {
+    match do_fmt_locale_int(*x, locale) {
+        Ok(res) => DDlogResult::Ok { res },
+        Err(e) => fmt_err(e),
+    }
+}
+
+pub fn fmt_locale_double(
+    x: &OrderedFloat<f64>,
+    precision: &std_usize,
+    locale: &String,
+) -> DDlogResult<String, String> {
+    match do_fmt_locale_double(x.into_inner(), *precision as usize, locale) {
+        Ok(res) => DDlogResult::Ok { res },
+        Err(e) => fmt_err(e),
+    }
+}
+
+pub fn fmt_locale_date(
+    year: &i32,
+    month: &u8,
+    day: &u8,
+    style: &DateStyle,
+    locale: &String,
+) -> DDlogResult<String, String> {
+    match do_fmt_locale_date(*year, *month, *day, style, locale) {
+        Ok(res) => DDlogResult::Ok { res },
+        Err(e) => fmt_err(e),
+    }
+}
@@ -0,0 +1,363 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::{tuple2, Group, Option as DDlogOption, Result as DDlogResult, Vec as DDlogVec};
+use differential_datalog::record::{CollectionKind, Record};
+use ordered_float::OrderedFloat;
+use rstar::{
+    primitives::{GeomWithData, Rectangle},
+    AABB,
+};
+use serde::{de::Deserializer, ser::Serializer};
+use std::{
+    cmp::Ordering,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+/// Mean radius of the Earth in meters.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+const GEOHASH_MAX_PRECISION: u64 = 12;
+
+pub fn haversine_distance(p1: &Point, p2: &Point) -> OrderedFloat<f64> {
+    let (lat1, lat2) = (p1.lat.to_radians(), p2.lat.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (p2.lon.0 - p1.lon.0).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    OrderedFloat(2.0 * EARTH_RADIUS * a.sqrt().min(1.0).asin())
+}
+
+pub fn bbox_union(b1: &BBox, b2: &BBox) -> BBox {
+    BBox {
+        south: std::cmp::min(b1.south, b2.south),
+        west: std::cmp::min(b1.west, b2.west),
+        north: std::cmp::max(b1.north, b2.north),
+        east: std::cmp::max(b1.east, b2.east),
+    }
+}
+
+pub fn bbox_around(center: &Point, meters: &OrderedFloat<f64>) -> BBox {
+    let dlat = (meters.0 / EARTH_RADIUS).to_degrees();
+    let south = (center.lat.0 - dlat).max(-90.0);
+    let north = (center.lat.0 + dlat).min(90.0);
+    /* Longitude degrees shrink towards the poles; use the latitude closest
+     * to the pole to make sure the box covers the entire circle. */
+    let max_lat = south.abs().max(north.abs());
+    let (west, east) = if max_lat >= 90.0 {
+        (-180.0, 180.0)
+    } else {
+        let dlon = dlat / max_lat.to_radians().cos();
+        (
+            (center.lon.0 - dlon).max(-180.0),
+            (center.lon.0 + dlon).min(180.0),
+        )
+    };
+    BBox {
+        south: OrderedFloat(south),
+        west: OrderedFloat(west),
+        north: OrderedFloat(north),
+        east: OrderedFloat(east),
+    }
+}
+
+pub fn polygon_bbox(p: &Polygon) -> DDlogOption<BBox> {
+    let mut vertices = p.vertices.iter();
+    let first = match vertices.next() {
+        None => return DDlogOption::None,
+        Some(v) => v,
+    };
+    let mut bbox = BBox {
+        south: first.lat,
+        west: first.lon,
+        north: first.lat,
+        east: first.lon,
+    };
+    for v in vertices {
+        bbox.south = std::cmp::min(bbox.south, v.lat);
+        bbox.west = std::cmp::min(bbox.west, v.lon);
+        bbox.north = std::cmp::max(bbox.north, v.lat);
+        bbox.east = std::cmp::max(bbox.east, v.lon);
+    }
+    DDlogOption::Some { x: bbox }
+}
+
+/* Ray casting: count the edges crossed by a ray going from the point in the
+ * direction of increasing longitude. */
+pub fn polygon_contains(p: &Polygon, pt: &Point) -> bool {
+    let vertices: &[Point] = &p.vertices;
+    let (x, y) = (pt.lon.0, pt.lat.0);
+    let mut inside = false;
+    let mut j = match vertices.len() {
+        0 => return false,
+        n => n - 1,
+    };
+    for (i, vi) in vertices.iter().enumerate() {
+        let vj = &vertices[j];
+        let (xi, yi, xj, yj) = (vi.lon.0, vi.lat.0, vj.lon.0, vj.lat.0);
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+pub fn geohash_encode(p: &Point, precision: &u64) -> String {
+    let precision = (*precision).max(1).min(GEOHASH_MAX_PRECISION);
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let lat = p.lat.0.max(-90.0).min(90.0);
+    let lon = p.lon.0.max(-180.0).min(180.0);
+    let mut hash = String::with_capacity(precision as usize);
+    let mut even = true;
+    for _ in 0..precision {
+        let mut idx = 0;
+        for _ in 0..5 {
+            let (range, x): (&mut (f64, f64), f64) = if even {
+                (&mut lon_range, lon)
+            } else {
+                (&mut lat_range, lat)
+            };
+            let mid = (range.0 + range.1) / 2.0;
+            idx <<= 1;
+            if x >= mid {
+                idx |= 1;
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even = !even;
+        }
+        hash.push(GEOHASH_ALPHABET[idx] as char);
+    }
+    hash
+}
+
+pub fn geohash_decode(hash: &String) -> DDlogResult<BBox, String> {
+    if hash.is_empty() {
+        return DDlogResult::Err {
+            err: "empty geohash".to_string(),
+        };
+    }
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut even = true;
+    for c in hash.chars() {
+        let idx = match GEOHASH_ALPHABET
+            .iter()
+            .position(|a| c.is_ascii() && *a == c.to_ascii_lowercase() as u8)
+        {
+            Some(idx) => idx,
+            None => {
+                return DDlogResult::Err {
+                    err: format!("invalid character '{}' in geohash \"{}\"", c, hash),
+                }
+            }
+        };
+        for bit in (0..5).rev() {
+            let range: &mut (f64, f64) = if even { &mut lon_range } else { &mut lat_range };
+            let mid = (range.0 + range.1) / 2.0;
+            if (idx >> bit) & 1 == 1 {
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even = !even;
+        }
+    }
+    DDlogResult::Ok {
+        res: BBox {
+            south: OrderedFloat(lat_range.0),
+            west: OrderedFloat(lon_range.0),
+            north: OrderedFloat(lat_range.1),
+            east: OrderedFloat(lon_range.1),
+        },
+    }
+}
+
+/* R-tree */
+
+/// Bounding box in the R-tree along with the position of the entry in
+/// `RTree::entries`.
+type IndexEntry = GeomWithData<Rectangle<[f64; 2]>, usize>;
+
+fn bbox2aabb(b: &BBox) -> AABB<[f64; 2]> {
+    AABB::from_corners([b.west.0, b.south.0], [b.east.0, b.north.0])
+}
+
+fn valid_bbox(b: &BBox) -> bool {
+    !(b.south.is_nan() || b.west.is_nan() || b.north.is_nan() || b.east.is_nan())
+}
+
+#[derive(Clone)]
+pub struct RTree<T> {
+    /// Entries sorted by bounding box and value.  The R-tree stores indexes
+    /// into this vector.  Comparison, hashing, and serialization only look
+    /// at the entries.
+    entries: Arc<Vec<tuple2<BBox, T>>>,
+    index: Arc<rstar::RTree<IndexEntry>>,
+}
+
+impl<T: Ord> RTree<T> {
+    pub fn new(mut entries: Vec<tuple2<BBox, T>>) -> Self {
+        entries.sort();
+        entries.dedup();
+        let index_entries = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| valid_bbox(&e.0))
+            .map(|(i, e)| GeomWithData::new(Rectangle::from_aabb(bbox2aabb(&e.0)), i))
+            .collect();
+        RTree {
+            entries: Arc::new(entries),
+            index: Arc::new(rstar::RTree::bulk_load(index_entries)),
+        }
+    }
+}
+
+impl<T: Clone> RTree<T> {
+    fn query(&self, envelope: &AABB<[f64; 2]>) -> DDlogVec<T> {
+        let mut matches: Vec<usize> = self
+            .index
+            .locate_in_envelope_intersecting(envelope)
+            .map(|e| e.data)
+            .collect();
+        matches.sort_unstable();
+        matches
+            .into_iter()
+            .map(|i| self.entries[i].1.clone())
+            .collect()
+    }
+}
+
+impl<T: Ord> Default for RTree<T> {
+    fn default() -> Self {
+        RTree::new(Vec::new())
+    }
+}
+
+impl<T: PartialEq> PartialEq for RTree<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.entries, &other.entries) || self.entries == other.entries
+    }
+}
+
+impl<T: Eq> Eq for RTree<T> {}
+
+impl<T: PartialOrd> PartialOrd for RTree<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.entries.partial_cmp(&other.entries)
+    }
+}
+
+impl<T: Ord> Ord for RTree<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.entries.cmp(&other.entries)
+    }
+}
+
+impl<T: Hash> Hash for RTree<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.entries.hash(state);
+    }
+}
+
+impl<T: Debug> Debug for RTree<T> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_list().entries(self.entries.iter()).finish()
+    }
+}
+
+impl<T: Serialize> Serialize for RTree<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.entries.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Ord> Deserialize<'de> for RTree<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::deserialize(deserializer).map(RTree::new)
+    }
+}
+
+impl<T: IntoRecord + Clone> IntoRecord for RTree<T> {
+    fn into_record(self) -> Record {
+        Record::Array(
+            CollectionKind::Vector,
+            self.entries
+                .iter()
+                .map(|e| e.clone().into_record())
+                .collect(),
+        )
+    }
+}
+
+impl<T: FromRecord + Ord> FromRecord for RTree<T> {
+    fn from_record(val: &Record) -> Result<Self, String> {
+        Vec::from_record(val).map(RTree::new)
+    }
+}
+
+impl<T: FromRecord + Ord> Mutator<RTree<T>> for Record {
+    fn mutate(&self, t: &mut RTree<T>) -> Result<(), String> {
+        *t = RTree::from_record(self)?;
+        Ok(())
+    }
+}
+
+pub fn rtree_empty<T: Ord>() -> RTree<T> {
+    RTree::default()
+}
+
+pub fn rtree_from_vec<T: Ord + Clone>(entries: &DDlogVec<tuple2<BBox, T>>) -> RTree<T> {
+    RTree::new(entries.iter().cloned().collect())
+}
+
+pub fn rtree_size<T>(t: &RTree<T>) -> std_usize {
+    t.entries.len() as std_usize
+}
+
+pub fn rtree_query_point<T: Clone>(t: &RTree<T>, p: &Point) -> DDlogVec<T> {
+    if p.lat.is_nan() || p.lon.is_nan() {
+        return DDlogVec::new();
+    }
+    t.query(&AABB::from_point([p.lon.0, p.lat.0]))
+}
+
+pub fn rtree_query_bbox<T: Clone>(t: &RTree<T>, b: &BBox) -> DDlogVec<T> {
+    if !valid_bbox(b) {
+        return DDlogVec::new();
+    }
+    t.query(&bbox2aabb(b))
+}
+
+pub fn group_rtree<K, T: Ord + Clone>(g: &Group<K, tuple2<BBox, T>>) -> RTree<T> {
+    RTree::new(g.val_iter().collect())
+}
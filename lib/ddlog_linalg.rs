@@ -0,0 +1,281 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::{Option as DDlogOption, Result as DDlogResult, Vec as DDlogVec};
+use nalgebra::{DMatrix, DVector};
+use ordered_float::OrderedFloat;
+
+fn to_dvector(v: &Vector) -> DVector<f64> {
+    DVector::from_iterator(v.elems.len(), v.elems.iter().map(|x| x.0))
+}
+
+fn from_dvector(v: DVector<f64>) -> Vector {
+    Vector {
+        elems: v.iter().map(|x| OrderedFloat(*x)).collect(),
+    }
+}
+
+fn to_dmatrix(m: &Matrix) -> Result<DMatrix<f64>, String> {
+    let (nrows, ncols) = (m.nrows as usize, m.ncols as usize);
+    if m.elems.len() != nrows * ncols {
+        return Err(format!(
+            "invalid {}x{} matrix with {} elements",
+            nrows,
+            ncols,
+            m.elems.len()
+        ));
+    }
+    Ok(DMatrix::from_row_iterator(
+        nrows,
+        ncols,
+        m.elems.iter().map(|x| x.0),
+    ))
+}
+
+fn from_dmatrix(m: DMatrix<f64>) -> Matrix {
+    let mut elems = Vec::with_capacity(m.len());
+    for row in m.row_iter() {
+        elems.extend(row.iter().map(|x| OrderedFloat(*x)));
+    }
+    Matrix {
+        nrows: m.nrows() as std_usize,
+        ncols: m.ncols() as std_usize,
+        elems: DDlogVec::from(elems),
+    }
+}
+
+fn check_same_len(a: &Vector, b: &Vector) -> Result<(), String> {
+    if a.elems.len() != b.elems.len() {
+        Err(format!(
+            "vector dimensions do not match: {} vs {}",
+            a.elems.len(),
+            b.elems.len()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_same_shape(a: &DMatrix<f64>, b: &DMatrix<f64>) -> Result<(), String> {
+    if a.shape() != b.shape() {
+        Err(format!(
+            "matrix dimensions do not match: {}x{} vs {}x{}",
+            a.nrows(),
+            a.ncols(),
+            b.nrows(),
+            b.ncols()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_square(m: &DMatrix<f64>) -> Result<(), String> {
+    if !m.is_square() {
+        Err(format!("not a square matrix: {}x{}", m.nrows(), m.ncols()))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn vector_zeros(n: &std_usize) -> Vector {
+    from_dvector(DVector::zeros(*n as usize))
+}
+
+pub fn vector_add(a: &Vector, b: &Vector) -> DDlogResult<Vector, String> {
+    ddlog_std::res2std(check_same_len(a, b).map(|()| from_dvector(to_dvector(a) + to_dvector(b))))
+}
+
+pub fn vector_sub(a: &Vector, b: &Vector) -> DDlogResult<Vector, String> {
+    ddlog_std::res2std(check_same_len(a, b).map(|()| from_dvector(to_dvector(a) - to_dvector(b))))
+}
+
+pub fn vector_scale(a: &Vector, k: &OrderedFloat<f64>) -> Vector {
+    from_dvector(to_dvector(a) * k.0)
+}
+
+pub fn vector_dot(a: &Vector, b: &Vector) -> DDlogResult<OrderedFloat<f64>, String> {
+    ddlog_std::res2std(
+        check_same_len(a, b).map(|()| OrderedFloat(to_dvector(a).dot(&to_dvector(b)))),
+    )
+}
+
+pub fn vector_norm(a: &Vector) -> OrderedFloat<f64> {
+    OrderedFloat(to_dvector(a).norm())
+}
+
+pub fn vector_norm_l1(a: &Vector) -> OrderedFloat<f64> {
+    OrderedFloat(to_dvector(a).lp_norm(1))
+}
+
+pub fn vector_norm_inf(a: &Vector) -> OrderedFloat<f64> {
+    OrderedFloat(to_dvector(a).amax())
+}
+
+pub fn vector_normalize(a: &Vector) -> DDlogOption<Vector> {
+    ddlog_std::option2std(to_dvector(a).try_normalize(0.0).map(from_dvector))
+}
+
+pub fn matrix_zeros(nrows: &std_usize, ncols: &std_usize) -> Matrix {
+    from_dmatrix(DMatrix::zeros(*nrows as usize, *ncols as usize))
+}
+
+pub fn matrix_identity(n: &std_usize) -> Matrix {
+    from_dmatrix(DMatrix::identity(*n as usize, *n as usize))
+}
+
+pub fn matrix_from_rows(
+    rows: &DDlogVec<DDlogVec<OrderedFloat<f64>>>,
+) -> DDlogResult<Matrix, String> {
+    let ncols = rows.first().map(|row| row.len()).unwrap_or(0);
+    if let Some(row) = rows.iter().find(|row| row.len() != ncols) {
+        return DDlogResult::Err {
+            err: format!(
+                "matrix rows have different lengths: {} vs {}",
+                ncols,
+                row.len()
+            ),
+        };
+    }
+    DDlogResult::Ok {
+        res: Matrix {
+            nrows: rows.len() as std_usize,
+            ncols: ncols as std_usize,
+            elems: rows.iter().flat_map(|row| row.iter().cloned()).collect(),
+        },
+    }
+}
+
+pub fn matrix_to_rows(m: &Matrix) -> DDlogResult<DDlogVec<DDlogVec<OrderedFloat<f64>>>, String> {
+    ddlog_std::res2std(to_dmatrix(m).map(|_| {
+        let ncols = m.ncols as usize;
+        (0..m.nrows as usize)
+            .map(|i| DDlogVec::from(&m.elems[i * ncols..(i + 1) * ncols]))
+            .collect()
+    }))
+}
+
+pub fn matrix_get(m: &Matrix, i: &std_usize, j: &std_usize) -> DDlogOption<OrderedFloat<f64>> {
+    let (i, j) = (*i as usize, *j as usize);
+    let (nrows, ncols) = (m.nrows as usize, m.ncols as usize);
+    if i >= nrows || j >= ncols {
+        return DDlogOption::None;
+    }
+    ddlog_std::option2std(m.elems.get(i * ncols + j).cloned())
+}
+
+pub fn matrix_transpose(m: &Matrix) -> DDlogResult<Matrix, String> {
+    ddlog_std::res2std(to_dmatrix(m).map(|m| from_dmatrix(m.transpose())))
+}
+
+pub fn matrix_add(a: &Matrix, b: &Matrix) -> DDlogResult<Matrix, String> {
+    ddlog_std::res2std((|| {
+        let (a, b) = (to_dmatrix(a)?, to_dmatrix(b)?);
+        check_same_shape(&a, &b)?;
+        Ok(from_dmatrix(a + b))
+    })())
+}
+
+pub fn matrix_sub(a: &Matrix, b: &Matrix) -> DDlogResult<Matrix, String> {
+    ddlog_std::res2std((|| {
+        let (a, b) = (to_dmatrix(a)?, to_dmatrix(b)?);
+        check_same_shape(&a, &b)?;
+        Ok(from_dmatrix(a - b))
+    })())
+}
+
+pub fn matrix_scale(m: &Matrix, k: &OrderedFloat<f64>) -> DDlogResult<Matrix, String> {
+    ddlog_std::res2std(to_dmatrix(m).map(|m| from_dmatrix(m * k.0)))
+}
+
+pub fn matrix_mul(a: &Matrix, b: &Matrix) -> DDlogResult<Matrix, String> {
+    ddlog_std::res2std((|| {
+        let (a, b) = (to_dmatrix(a)?, to_dmatrix(b)?);
+        if a.ncols() != b.nrows() {
+            return Err(format!(
+                "cannot multiply {}x{} matrix by {}x{} matrix",
+                a.nrows(),
+                a.ncols(),
+                b.nrows(),
+                b.ncols()
+            ));
+        }
+        Ok(from_dmatrix(a * b))
+    })())
+}
+
+pub fn matrix_mul_vector(m: &Matrix, v: &Vector) -> DDlogResult<Vector, String> {
+    ddlog_std::res2std((|| {
+        let m = to_dmatrix(m)?;
+        if m.ncols() != v.elems.len() {
+            return Err(format!(
+                "cannot multiply {}x{} matrix by vector of length {}",
+                m.nrows(),
+                m.ncols(),
+                v.elems.len()
+            ));
+        }
+        Ok(from_dvector(m * to_dvector(v)))
+    })())
+}
+
+pub fn matrix_norm(m: &Matrix) -> DDlogResult<OrderedFloat<f64>, String> {
+    ddlog_std::res2std(to_dmatrix(m).map(|m| OrderedFloat(m.norm())))
+}
+
+pub fn matrix_determinant(m: &Matrix) -> DDlogResult<OrderedFloat<f64>, String> {
+    ddlog_std::res2std((|| {
+        let m = to_dmatrix(m)?;
+        check_square(&m)?;
+        Ok(OrderedFloat(m.determinant()))
+    })())
+}
+
+pub fn matrix_inverse(m: &Matrix) -> DDlogResult<Matrix, String> {
+    ddlog_std::res2std((|| {
+        let m = to_dmatrix(m)?;
+        check_square(&m)?;
+        m.try_inverse()
+            .map(from_dmatrix)
+            .ok_or_else(|| "singular matrix".to_string())
+    })())
+}
+
+pub fn matrix_solve(m: &Matrix, b: &Vector) -> DDlogResult<Vector, String> {
+    ddlog_std::res2std((|| {
+        let m = to_dmatrix(m)?;
+        check_square(&m)?;
+        if m.nrows() != b.elems.len() {
+            return Err(format!(
+                "cannot solve {}x{} system with right-hand side of length {}",
+                m.nrows(),
+                m.ncols(),
+                b.elems.len()
+            ));
+        }
+        m.lu()
+            .solve(&to_dvector(b))
+            .map(from_dvector)
+            .ok_or_else(|| "singular matrix".to_string())
+    })())
+}
@@ -55,3 +55,30 @@ extern void ddlog_log_set_default_callback(
         void (*cb)(uintptr_t arg, int level, const char* msg),
         uintptr_t cb_arg,
         int max_level);
+
+/*
+ * Set the maximal log level of messages from `module` delivered to log sinks.
+ * Unlike `ddlog_log_set_callback`, this does not affect the module's callback
+ * and can be called at any time to change the level dynamically.
+ */
+extern void ddlog_log_set_level(int module, int max_level);
+
+/*
+ * Set the maximal log level of messages delivered to log sinks from modules
+ * whose level was not set via `ddlog_log_set_level`.
+ */
+extern void ddlog_log_set_default_level(int max_level);
+
+/*
+ * Add a log sink named `name` that appends messages from all modules to the
+ * file at `path`, replacing the sink previously added under `name`, if any.
+ *
+ * Returns 0 on success, -1 if the file cannot be opened.
+ */
+extern int ddlog_log_add_file_sink(const char* name, const char* path);
+
+/*
+ * Remove the log sink added under `name`.  Returns `false` if there is no
+ * such sink.
+ */
+extern bool ddlog_log_remove_sink(const char* name);
@@ -21,20 +21,48 @@ OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
 */
 
-/* Logging Configuration API, (detailed documentation in `ddlog_log.h`) */
+/* Logging Configuration API, (detailed documentation in `ddlog_log.h`)
+ *
+ * Besides the per-module callbacks, log messages are delivered to any
+ * number of named sinks (`log_add_sink`), such as `FileSink`, `SyslogSink`,
+ * or a user-defined `LogSink` forwarding messages to another logging
+ * framework like `tracing`.  Which messages reach sinks is controlled per
+ * module with `log_set_level`, which can be called at any time. */
 
 use once_cell::sync::Lazy;
 use std::collections;
 use std::ffi;
+use std::fs;
+use std::io::{self, Write};
 use std::os::raw;
+use std::path::Path;
 use std::sync;
 
 type log_callback_t = Box<dyn Fn(i32, &str) + Send + Sync>;
 
+/// Destination of log messages.
+pub trait LogSink: Send + Sync {
+    fn log(&self, module: i32, level: i32, msg: &str);
+}
+
+impl<F> LogSink for F
+where
+    F: Fn(i32, i32, &str) + Send + Sync,
+{
+    fn log(&self, module: i32, level: i32, msg: &str) {
+        self(module, level, msg)
+    }
+}
+
 struct LogConfig {
     default_callback: Option<log_callback_t>,
     default_level: i32,
     mod_callbacks: collections::HashMap<i32, (log_callback_t, i32)>,
+    /// Sinks in the order they were added.
+    sinks: Vec<(String, Box<dyn LogSink>)>,
+    /// Maximal log level of messages delivered to sinks, per module.
+    sink_levels: collections::HashMap<i32, i32>,
+    sink_default_level: i32,
 }
 
 impl LogConfig {
@@ -43,8 +71,18 @@ impl LogConfig {
             default_callback: None,
             default_level: std::i32::MAX,
             mod_callbacks: collections::HashMap::new(),
+            sinks: Vec::new(),
+            sink_levels: collections::HashMap::new(),
+            sink_default_level: std::i32::MAX,
         }
     }
+
+    fn sink_level(&self, module: i32) -> i32 {
+        self.sink_levels
+            .get(&module)
+            .copied()
+            .unwrap_or(self.sink_default_level)
+    }
 }
 
 /// Logger configuration for each module consists of the maximal enabled
@@ -64,6 +102,11 @@ pub fn log(module: &i32, level: &i32, msg: &String) {
     } else if *level <= cfg.default_level && cfg.default_callback.is_some() {
         cfg.default_callback.as_ref().unwrap()(*level, msg.as_str());
     }
+    if !cfg.sinks.is_empty() && *level <= cfg.sink_level(*module) {
+        for (_, sink) in cfg.sinks.iter() {
+            sink.log(*module, *level, msg.as_str());
+        }
+    }
 }
 
 /// `cb = None` - disables logging for the given module.
@@ -89,6 +132,111 @@ pub fn log_set_default_callback(cb: Option<log_callback_t>, max_level: i32) {
     cfg.default_level = max_level;
 }
 
+/// Add a sink that receives log messages from all modules, replacing the
+/// sink previously added under `name`, if any.
+pub fn log_add_sink(name: &str, sink: Box<dyn LogSink>) {
+    let mut cfg = LOG_CONFIG.write().unwrap();
+    match cfg.sinks.iter_mut().find(|(n, _)| n == name) {
+        Some(entry) => entry.1 = sink,
+        None => cfg.sinks.push((name.to_string(), sink)),
+    }
+}
+
+/// Remove the sink added under `name`.  Returns `false` if there is no such
+/// sink.
+pub fn log_remove_sink(name: &str) -> bool {
+    let mut cfg = LOG_CONFIG.write().unwrap();
+    let len = cfg.sinks.len();
+    cfg.sinks.retain(|(n, _)| n != name);
+    cfg.sinks.len() != len
+}
+
+/// Set the maximal level of messages from `module` delivered to sinks;
+/// `None` reverts the module to the default level.
+pub fn log_set_level(module: i32, max_level: Option<i32>) {
+    let mut cfg = LOG_CONFIG.write().unwrap();
+    match max_level {
+        Some(max_level) => {
+            cfg.sink_levels.insert(module, max_level);
+        }
+        None => {
+            cfg.sink_levels.remove(&module);
+        }
+    }
+}
+
+/// Set the maximal level of messages delivered to sinks from modules whose
+/// level was not set via `log_set_level`.
+pub fn log_set_default_level(max_level: i32) {
+    LOG_CONFIG.write().unwrap().sink_default_level = max_level;
+}
+
+/// The maximal level of messages from `module` delivered to sinks.
+pub fn log_level(module: i32) -> i32 {
+    LOG_CONFIG.read().unwrap().sink_level(module)
+}
+
+/// Sink that appends log messages to a file, one line per message.
+pub struct FileSink {
+    file: sync::Mutex<io::LineWriter<fs::File>>,
+}
+
+impl FileSink {
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            file: sync::Mutex::new(io::LineWriter::new(file)),
+        })
+    }
+}
+
+impl LogSink for FileSink {
+    fn log(&self, module: i32, level: i32, msg: &str) {
+        // Logging must not fail the rule that emitted the message.
+        let _ = writeln!(
+            self.file.lock().unwrap(),
+            "[module {}] level {}: {}",
+            module,
+            level,
+            msg
+        );
+    }
+}
+
+/// Sink that sends log messages to syslog.  DDlog log levels, like syslog
+/// priorities, decrease with severity; levels above `LOG_DEBUG` (7) are
+/// logged as `LOG_DEBUG`.
+#[cfg(unix)]
+pub struct SyslogSink {
+    facility: raw::c_int,
+}
+
+#[cfg(unix)]
+impl SyslogSink {
+    /// Log to `facility`, e.g., `libc::LOG_USER`.
+    pub fn new(facility: raw::c_int) -> Self {
+        Self { facility }
+    }
+}
+
+#[cfg(unix)]
+impl LogSink for SyslogSink {
+    fn log(&self, module: i32, level: i32, msg: &str) {
+        let priority = self.facility | level.max(libc::LOG_EMERG).min(libc::LOG_DEBUG);
+        let msg = ffi::CString::new(format!("[module {}] {}", module, msg)).unwrap_or_default();
+        unsafe {
+            libc::syslog(
+                priority,
+                b"%s\0".as_ptr() as *const raw::c_char,
+                msg.as_ptr(),
+            );
+        }
+    }
+}
+
 /// C bindings for the config API
 #[no_mangle]
 #[cfg(feature = "c_api")]
@@ -135,3 +283,142 @@ pub unsafe extern "C" fn ddlog_log_set_default_callback(
         None => log_set_default_callback(None, max_level as i32),
     }
 }
+
+#[no_mangle]
+#[cfg(feature = "c_api")]
+pub unsafe extern "C" fn ddlog_log_set_level(module: raw::c_int, max_level: raw::c_int) {
+    log_set_level(module as i32, Some(max_level as i32))
+}
+
+#[no_mangle]
+#[cfg(feature = "c_api")]
+pub unsafe extern "C" fn ddlog_log_set_default_level(max_level: raw::c_int) {
+    log_set_default_level(max_level as i32)
+}
+
+#[no_mangle]
+#[cfg(feature = "c_api")]
+pub unsafe extern "C" fn ddlog_log_add_file_sink(
+    name: *const raw::c_char,
+    path: *const raw::c_char,
+) -> raw::c_int {
+    if name.is_null() || path.is_null() {
+        return -1;
+    }
+    let (name, path) = match (
+        ffi::CStr::from_ptr(name).to_str(),
+        ffi::CStr::from_ptr(path).to_str(),
+    ) {
+        (Ok(name), Ok(path)) => (name, path),
+        _ => return -1,
+    };
+    match FileSink::new(path) {
+        Ok(sink) => {
+            log_add_sink(name, Box::new(sink));
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+#[no_mangle]
+#[cfg(feature = "c_api")]
+pub unsafe extern "C" fn ddlog_log_remove_sink(name: *const raw::c_char) -> bool {
+    if name.is_null() {
+        return false;
+    }
+    match ffi::CStr::from_ptr(name).to_str() {
+        Ok(name) => log_remove_sink(name),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Log configuration is global and tests run concurrently, so each test
+     * uses its own module ids and sink names. */
+
+    type Messages = sync::Arc<sync::Mutex<Vec<(i32, i32, String)>>>;
+
+    fn collect(name: &str, modules: &'static [i32]) -> Messages {
+        let messages = Messages::default();
+        let messages2 = messages.clone();
+        log_add_sink(
+            name,
+            Box::new(move |module: i32, level: i32, msg: &str| {
+                if modules.contains(&module) {
+                    messages2
+                        .lock()
+                        .unwrap()
+                        .push((module, level, msg.to_string()));
+                }
+            }),
+        );
+        messages
+    }
+
+    #[test]
+    fn sink_levels() {
+        let messages = collect("test_sink_levels", &[1001, 1002]);
+        log_set_level(1001, Some(2));
+        assert_eq!(log_level(1001), 2);
+
+        log(&1001, &3, &"filtered".to_string());
+        log(&1001, &2, &"delivered".to_string());
+        log(&1002, &5, &"default level".to_string());
+        assert_eq!(
+            *messages.lock().unwrap(),
+            vec![
+                (1001, 2, "delivered".to_string()),
+                (1002, 5, "default level".to_string())
+            ]
+        );
+
+        // Reverting to the default level.
+        log_set_level(1001, None);
+        assert_eq!(log_level(1001), log_level(1002));
+        log(&1001, &3, &"no longer filtered".to_string());
+        assert_eq!(messages.lock().unwrap().len(), 3);
+        assert!(log_remove_sink("test_sink_levels"));
+    }
+
+    #[test]
+    fn replace_and_remove_sinks() {
+        let first = collect("test_replace", &[2001]);
+        let second = collect("test_replace", &[2001]);
+        log(&2001, &1, &"to the second sink".to_string());
+        assert!(first.lock().unwrap().is_empty());
+        assert_eq!(second.lock().unwrap().len(), 1);
+
+        assert!(log_remove_sink("test_replace"));
+        assert!(!log_remove_sink("test_replace"));
+        log(&2001, &1, &"to no sink".to_string());
+        assert_eq!(second.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn file_sink() {
+        let path = std::env::temp_dir().join(format!("ddlog_log_test_{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        log_add_sink("test_file", Box::new(FileSink::new(&path).unwrap()));
+        log(&3001, &4, &"first".to_string());
+        log(&3001, &6, &"second".to_string());
+        assert!(log_remove_sink("test_file"));
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let lines: Vec<&str> = contents
+            .lines()
+            .filter(|l| l.starts_with("[module 3001]"))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "[module 3001] level 4: first",
+                "[module 3001] level 6: second"
+            ]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::{tuple2, Group, Vec as DDlogVec};
+use ordered_float::OrderedFloat;
+use std::cmp::Ordering;
+use std::f64::consts::PI;
+use std::hash::Hash;
+
+pub fn random_seed() -> u64 {
+    differential_datalog::program::config::random_seed()
+}
+
+/// SplitMix64 finalizer, used to scramble the output of the FNV hash.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Random bits determined by the seed, `key`, and `stream`.  Functions that
+/// need several random numbers per key use different streams.
+fn random_bits<K: Hash + ?Sized>(key: &K, stream: u64) -> u64 {
+    mix(ddlog_std::hash64(&(random_seed(), stream, key)))
+}
+
+/// Uniformly distributed number in `[0, 1)`.
+fn bits2double(bits: u64) -> f64 {
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+pub fn random_u64<K: Hash>(key: &K) -> u64 {
+    random_bits(key, 0)
+}
+
+pub fn random_range<K: Hash>(key: &K, from: &u64, to: &u64) -> u64 {
+    if *to <= *from {
+        return *from;
+    }
+    let span = *to - *from;
+    *from + ((random_bits(key, 0) as u128 * span as u128) >> 64) as u64
+}
+
+pub fn random_double<K: Hash>(key: &K) -> OrderedFloat<f64> {
+    OrderedFloat(bits2double(random_bits(key, 0)))
+}
+
+/* Box-Muller transform. */
+pub fn random_normal<K: Hash>(
+    key: &K,
+    mean: &OrderedFloat<f64>,
+    stddev: &OrderedFloat<f64>,
+) -> OrderedFloat<f64> {
+    let u1 = 1.0 - bits2double(random_bits(key, 0));
+    let u2 = bits2double(random_bits(key, 1));
+    let z = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+    OrderedFloat(mean.0 + stddev.0 * z)
+}
+
+/* Sort elements by random keys. */
+pub fn random_shuffle<K: Hash, A: Hash + Clone>(key: &K, v: &DDlogVec<A>) -> DDlogVec<A> {
+    let mut keyed: Vec<(u64, usize)> = v
+        .iter()
+        .enumerate()
+        .map(|(i, x)| (random_bits(&(key, i as u64, x), 2), i))
+        .collect();
+    keyed.sort_unstable();
+    keyed.into_iter().map(|(_, i)| v[i].clone()).collect()
+}
+
+/// Select `n` elements with the largest priorities, in the order of
+/// decreasing priority.
+fn top_n<P: PartialOrd, V: Clone>(mut prioritized: Vec<(P, V)>, n: usize) -> DDlogVec<V> {
+    prioritized.sort_by(|(p1, _), (p2, _)| p2.partial_cmp(p1).unwrap_or(Ordering::Equal));
+    prioritized.truncate(n);
+    prioritized.into_iter().map(|(_, v)| v).collect()
+}
+
+/* Bottom-k sampling: each element gets a pseudo-random priority derived from
+ * the group key and the element itself, and the sample consists of the `n`
+ * elements with the highest priorities. */
+pub fn group_sample<K: Hash, V: Hash + Clone>(g: &Group<K, V>, n: &u64) -> DDlogVec<V> {
+    let key = g.key_ref();
+    let prioritized = g
+        .val_iter()
+        .map(|v| (random_bits(&(key, &v), 3), v))
+        .collect();
+    top_n(prioritized, *n as usize)
+}
+
+/* The A-ES algorithm (Efraimidis and Spirakis, "Weighted random sampling with
+ * a reservoir", 2006): element with weight `w` gets priority `u^(1/w)`, where
+ * `u` is uniformly distributed in `(0, 1]`.  We use the equivalent priority
+ * `ln(u)/w` to avoid underflow. */
+pub fn group_sample_weighted<K: Hash, V: Hash + Clone>(
+    g: &Group<K, tuple2<V, OrderedFloat<f64>>>,
+    n: &u64,
+) -> DDlogVec<V> {
+    let key = g.key_ref();
+    let prioritized = g
+        .val_iter()
+        .filter(|tuple2(_, w)| w.0 > 0.0)
+        .map(|tuple2(v, w)| {
+            let u = 1.0 - bits2double(random_bits(&(key, &v), 4));
+            (u.ln() / w.0, v)
+        })
+        .collect();
+    top_n(prioritized, *n as usize)
+}
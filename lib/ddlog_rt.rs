@@ -128,6 +128,99 @@ macro_rules! deserialize_map_from_array {
     };
 }
 
+/* Runtime support for plain-old-data types. */
+
+/// Types whose values can be compared and hashed as raw bytes.
+///
+/// The DDlog compiler implements this trait (using `plain_data_traits!`)
+/// for non-generic struct types whose fields are all fixed-width integers,
+/// Booleans, or other plain-old-data structs.  `NO_PADDING` is computed at
+/// compile time: it is `true` if the type and all its fields have no padding
+/// bytes, in which case two values are equal if and only if their in-memory
+/// representations are equal.  Such values are compared with a single
+/// `memcmp` and passed to the hasher as one byte slice instead of field by
+/// field.
+///
+/// # Safety
+///
+/// `NO_PADDING` must only be `true` if every byte of a value of the type is
+/// initialized and two values are equal exactly when their bytes are equal.
+pub unsafe trait PlainData: Sized {
+    const NO_PADDING: bool;
+}
+
+macro_rules! primitive_plain_data {
+    ( $( $t:ty ),* ) => {
+        $( unsafe impl PlainData for $t {
+            const NO_PADDING: bool = true;
+        } )*
+    };
+}
+
+primitive_plain_data!(bool, u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+/// Returns the in-memory representation of a plain-old-data value.
+///
+/// Must only be used when `T::NO_PADDING` is `true`.
+pub fn plain_data_bytes<T: PlainData>(x: &T) -> &[u8] {
+    debug_assert!(T::NO_PADDING);
+    unsafe { std::slice::from_raw_parts(x as *const T as *const u8, std::mem::size_of::<T>()) }
+}
+
+/// Used by generated Rust code to implement `PlainData`, `PartialEq`, and
+/// `Hash` for plain-old-data struct types.  Takes the name of the type
+/// followed by the names and types of all its fields.  Types with padding
+/// bytes fall back to comparing and hashing values field by field.
+///
+/// `NO_PADDING` is only sound if the fields are listed exactly once each,
+/// which is checked at compile time by matching the type against an
+/// exhaustive pattern of the listed fields, and if all field types implement
+/// `PlainData`, which excludes floating-point numbers, whose equal values
+/// (`0.0` and `-0.0`) can have different representations.
+///
+/// Example:
+/// ```ignore
+/// ddlog_rt::plain_data_traits!(Point, x: u32, y: u32);
+/// ```
+#[macro_export]
+macro_rules! plain_data_traits {
+    ( $tname:ident $( , $field:ident : $ftype:ty )* ) => {
+        unsafe impl $crate::PlainData for $tname {
+            const NO_PADDING: bool = {
+                // Fails to compile unless the fields are exactly the fields
+                // of the type.
+                #[allow(dead_code)]
+                fn all_fields(x: &$tname) {
+                    let $tname { $( $field: _ ),* } = x;
+                }
+                ::std::mem::size_of::<$tname>()
+                    == 0 $( + ::std::mem::size_of::<$ftype>() )*
+                    $( && <$ftype as $crate::PlainData>::NO_PADDING )*
+            };
+        }
+
+        impl ::std::cmp::PartialEq for $tname {
+            fn eq(&self, other: &Self) -> bool {
+                if <Self as $crate::PlainData>::NO_PADDING {
+                    $crate::plain_data_bytes(self) == $crate::plain_data_bytes(other)
+                } else {
+                    true $( && self.$field == other.$field )*
+                }
+            }
+        }
+
+        impl ::std::hash::Hash for $tname {
+            fn hash<H: ::std::hash::Hasher>(&self, state: &mut H) {
+                if <Self as $crate::PlainData>::NO_PADDING {
+                    state.write($crate::plain_data_bytes(self));
+                } else {
+                    $( ::std::hash::Hash::hash(&self.$field, state); )*
+                }
+            }
+        }
+    };
+}
+
 /* Runtime support for DDlog closures. */
 
 /* DDlog's equivalent of Rust's `Fn` trait.  This is necessary, as Rust does not allow manual
@@ -379,8 +472,10 @@ impl<Args: 'static + Clone, Output: 'static + Clone> Abomonation
 mod tests {
     use super::Closure;
     use super::ClosureImpl;
+    use super::PlainData;
     use serde::Deserialize;
     use serde::Serialize;
+    use std::hash::{Hash, Hasher};
 
     #[test]
     fn closure_test() {
@@ -438,6 +533,119 @@ mod tests {
         assert_eq!(closure1.eq_dyn(&closure2), false);
     }
 
+    #[derive(Debug, Clone, Copy, Eq)]
+    struct Packed {
+        x: u32,
+        y: u32,
+        flag: u64,
+    }
+    crate::plain_data_traits!(Packed, x: u32, y: u32, flag: u64);
+
+    #[derive(Debug, Clone, Copy, Eq)]
+    struct Padded {
+        b: bool,
+        x: u32,
+    }
+    crate::plain_data_traits!(Padded, b: bool, x: u32);
+
+    #[derive(Debug, Clone, Copy)]
+    struct Nested {
+        p: Packed,
+        q: Padded,
+    }
+    crate::plain_data_traits!(Nested, p: Packed, q: Padded);
+
+    fn hash_of<T: Hash>(x: &T) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        x.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn plain_data_padding() {
+        assert!(<Packed as PlainData>::NO_PADDING);
+        assert!(!<Padded as PlainData>::NO_PADDING);
+        // A field with padding makes the containing struct padded.
+        assert!(!<Nested as PlainData>::NO_PADDING);
+    }
+
+    #[test]
+    fn plain_data_eq_hash() {
+        let p1 = Packed {
+            x: 1,
+            y: 2,
+            flag: 3,
+        };
+        let p2 = Packed {
+            x: 1,
+            y: 2,
+            flag: 3,
+        };
+        let p3 = Packed { y: 3, ..p1 };
+        assert_eq!(p1, p2);
+        assert_eq!(hash_of(&p1), hash_of(&p2));
+        assert_ne!(p1, p3);
+
+        // Padded values are compared field by field, so values that only
+        // differ in their padding bytes are equal and hash the same.
+        let padded = |fill: u8| unsafe {
+            let mut q = std::mem::MaybeUninit::<Padded>::uninit();
+            std::ptr::write_bytes(q.as_mut_ptr(), fill, 1);
+            (*q.as_mut_ptr()).b = true;
+            (*q.as_mut_ptr()).x = 7;
+            q.assume_init()
+        };
+        let q1 = padded(0);
+        let q2 = padded(0xff);
+        assert_eq!(q1, q2);
+        assert_eq!(hash_of(&q1), hash_of(&q2));
+        assert_ne!(q1, Padded { b: false, ..q1 });
+
+        let n1 = Nested { p: p1, q: q1 };
+        let n2 = Nested { p: p2, q: q2 };
+        assert_eq!(n1, n2);
+        assert_eq!(hash_of(&n1), hash_of(&n2));
+    }
+
+    #[test]
+    fn plain_data_matches_field_wise() {
+        use std::collections::HashSet;
+
+        // Comparing and hashing as bytes must partition values exactly like
+        // comparing and hashing their fields does.
+        let packed: Vec<Packed> = (0..3)
+            .flat_map(|x| (0..3).flat_map(move |y| (0..3).map(move |flag| (x, y, flag))))
+            .map(|(x, y, flag)| Packed { x, y, flag })
+            .collect();
+        for a in &packed {
+            for b in &packed {
+                assert_eq!(a == b, (a.x, a.y, a.flag) == (b.x, b.y, b.flag));
+                if a == b {
+                    assert_eq!(hash_of(a), hash_of(b));
+                }
+            }
+        }
+        let fields: HashSet<(u32, u32, u64)> = packed.iter().map(|p| (p.x, p.y, p.flag)).collect();
+        assert_eq!(packed.iter().collect::<HashSet<_>>().len(), fields.len());
+
+        let padded: Vec<Padded> = [false, true]
+            .iter()
+            .flat_map(|&b| (0..3).map(move |x| Padded { b, x }))
+            .collect();
+        for a in &padded {
+            for b in &padded {
+                assert_eq!(a == b, (a.b, a.x) == (b.b, b.x));
+                if a == b {
+                    assert_eq!(hash_of(a), hash_of(b));
+                }
+            }
+            // Hashed field by field, exactly like a tuple of the fields.
+            assert_eq!(hash_of(a), hash_of(&(a.b, a.x)));
+        }
+        let fields: HashSet<(bool, u32)> = padded.iter().map(|p| (p.b, p.x)).collect();
+        assert_eq!(padded.iter().collect::<HashSet<_>>().len(), fields.len());
+    }
+
     /* Make sure that auto-derives work for closures. */
 
     #[derive(Eq, PartialEq, Ord, Clone, Hash, PartialOrd, Default, Serialize, Deserialize)]
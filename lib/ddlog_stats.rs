@@ -0,0 +1,436 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::{DDWeight, Group, Option as DDlogOption, Result as DDlogResult, Vec as DDlogVec};
+use ordered_float::OrderedFloat;
+use std::f64::consts::PI;
+
+fn some(x: f64) -> DDlogOption<OrderedFloat<f64>> {
+    DDlogOption::Some { x: OrderedFloat(x) }
+}
+
+/// Group elements with non-positive weights do not contribute to statistics.
+fn weight2count(w: DDWeight) -> u64 {
+    if w > 0 {
+        w as u64
+    } else {
+        0
+    }
+}
+
+/* Histogram */
+
+fn histogram_init(bounds: &[OrderedFloat<f64>]) -> Histogram {
+    let mut bounds: Vec<OrderedFloat<f64>> =
+        bounds.iter().filter(|b| !b.is_nan()).cloned().collect();
+    bounds.sort();
+    bounds.dedup();
+    Histogram {
+        counts: DDlogVec::from(vec![0; bounds.len() + 1]),
+        bounds: DDlogVec::from(bounds),
+        count: 0,
+        sum: OrderedFloat(0.0),
+        min: OrderedFloat(0.0),
+        max: OrderedFloat(0.0),
+    }
+}
+
+fn histogram_insert(h: &mut Histogram, x: f64, n: u64) {
+    if x.is_nan() || n == 0 {
+        return;
+    }
+    let nbuckets = h.bounds.len() + 1;
+    if h.counts.len() != nbuckets {
+        h.counts.resize(nbuckets, &0);
+    }
+    let bucket = match h.bounds.binary_search(&OrderedFloat(x)) {
+        Ok(i) => i,
+        Err(i) => i,
+    };
+    h.counts[bucket] += n;
+    if h.count == 0 {
+        h.min = OrderedFloat(x);
+        h.max = OrderedFloat(x);
+    } else {
+        h.min = OrderedFloat(h.min.0.min(x));
+        h.max = OrderedFloat(h.max.0.max(x));
+    }
+    h.count += n;
+    h.sum = OrderedFloat(h.sum.0 + x * (n as f64));
+}
+
+fn histogram_merge_into(h: &mut Histogram, other: &Histogram) -> Result<(), String> {
+    if h.bounds != other.bounds {
+        return Err("cannot merge histograms with different bucket bounds".to_string());
+    }
+    if h.counts.len() != h.bounds.len() + 1 || other.counts.len() != other.bounds.len() + 1 {
+        return Err("malformed histogram: number of buckets does not match bounds".to_string());
+    }
+    if other.count == 0 {
+        return Ok(());
+    }
+    for (c, o) in h.counts.iter_mut().zip(other.counts.iter()) {
+        *c += *o;
+    }
+    if h.count == 0 {
+        h.min = other.min;
+        h.max = other.max;
+    } else {
+        h.min = std::cmp::min(h.min, other.min);
+        h.max = std::cmp::max(h.max, other.max);
+    }
+    h.count += other.count;
+    h.sum = OrderedFloat(h.sum.0 + other.sum.0);
+    Ok(())
+}
+
+pub fn histogram_new(bounds: &DDlogVec<OrderedFloat<f64>>) -> Histogram {
+    histogram_init(bounds)
+}
+
+pub fn histogram_add(h: &Histogram, x: &OrderedFloat<f64>) -> Histogram {
+    let mut res = h.clone();
+    histogram_insert(&mut res, x.0, 1);
+    res
+}
+
+pub fn histogram_merge(h1: &Histogram, h2: &Histogram) -> DDlogResult<Histogram, String> {
+    let mut res = h1.clone();
+    ddlog_std::res2std(histogram_merge_into(&mut res, h2).map(|()| res))
+}
+
+pub fn histogram_quantile(h: &Histogram, q: &OrderedFloat<f64>) -> DDlogOption<OrderedFloat<f64>> {
+    let q = q.0;
+    if h.count == 0 || !(0.0..=1.0).contains(&q) || h.counts.len() != h.bounds.len() + 1 {
+        return DDlogOption::None;
+    }
+    if q == 0.0 {
+        return some(h.min.0);
+    }
+    let rank = q * (h.count as f64);
+    let mut seen = 0.0;
+    for (i, c) in h.counts.iter().enumerate() {
+        let c = *c as f64;
+        if c == 0.0 || seen + c < rank {
+            seen += c;
+            continue;
+        }
+        let lower = if i == 0 {
+            h.min.0
+        } else {
+            h.bounds[i - 1].0.max(h.min.0)
+        };
+        let upper = if i == h.bounds.len() {
+            h.max.0
+        } else {
+            h.bounds[i].0.min(h.max.0)
+        };
+        return some(lower + (upper - lower) * (rank - seen) / c);
+    }
+    some(h.max.0)
+}
+
+pub fn group_histogram<K>(
+    g: &Group<K, OrderedFloat<f64>>,
+    bounds: &DDlogVec<OrderedFloat<f64>>,
+) -> Histogram {
+    let mut res = histogram_init(bounds);
+    for ddlog_std::tuple2(x, w) in g.iter() {
+        histogram_insert(&mut res, x.0, weight2count(w));
+    }
+    res
+}
+
+pub fn group_histogram_merge<K>(g: &Group<K, Histogram>) -> DDlogResult<Histogram, String> {
+    let mut res = histogram_init(&g.first().bounds);
+    for ddlog_std::tuple2(h, w) in g.iter() {
+        for _ in 0..weight2count(w) {
+            if let Err(e) = histogram_merge_into(&mut res, &h) {
+                return DDlogResult::Err { err: e };
+            }
+        }
+    }
+    DDlogResult::Ok { res }
+}
+
+/* T-digest
+ *
+ * This is the merging variant of the t-digest (Dunning and Ertl, "Computing
+ * extremely accurate quantiles using t-digests", 2019) with the `k1` scale
+ * function.  Centroids are kept sorted by mean.  New values are added as
+ * singleton centroids; once the number of centroids exceeds twice the
+ * compression factor, adjacent centroids are merged as long as each merged
+ * centroid spans at most one unit of the scale function.
+ */
+
+fn scale_k(q: f64, compression: f64) -> f64 {
+    compression / (2.0 * PI) * (2.0 * q - 1.0).asin()
+}
+
+fn scale_k_inv(k: f64, compression: f64) -> f64 {
+    let x = k * 2.0 * PI / compression;
+    if x >= PI / 2.0 {
+        1.0
+    } else {
+        ((x.sin()) + 1.0) / 2.0
+    }
+}
+
+fn valid_compression(compression: f64) -> f64 {
+    if compression.is_nan() || compression < 1.0 {
+        1.0
+    } else {
+        compression
+    }
+}
+
+/// Merge adjacent centroids of a digest whose centroids are sorted by mean.
+fn tdigest_compress(t: &mut TDigest) {
+    if t.centroids.is_empty() {
+        return;
+    }
+    let compression = valid_compression(t.compression.0);
+    let total = t.count as f64;
+    let mut result: Vec<Centroid> = Vec::with_capacity(compression as usize);
+    let mut cur = t.centroids[0].clone();
+    let mut weight_before = 0.0;
+    let mut q_limit = scale_k_inv(scale_k(0.0, compression) + 1.0, compression);
+    for c in t.centroids.iter().skip(1) {
+        let q = (weight_before + (cur.weight + c.weight) as f64) / total;
+        if q <= q_limit {
+            let weight = cur.weight + c.weight;
+            cur.mean = OrderedFloat(
+                cur.mean.0 + (c.mean.0 - cur.mean.0) * (c.weight as f64) / (weight as f64),
+            );
+            cur.weight = weight;
+        } else {
+            weight_before += cur.weight as f64;
+            q_limit = scale_k_inv(
+                scale_k(weight_before / total, compression) + 1.0,
+                compression,
+            );
+            result.push(std::mem::replace(&mut cur, c.clone()));
+        }
+    }
+    result.push(cur);
+    t.centroids = DDlogVec::from(result);
+}
+
+fn tdigest_insert(t: &mut TDigest, x: f64, n: u64) {
+    if x.is_nan() || n == 0 {
+        return;
+    }
+    let pos = t
+        .centroids
+        .iter()
+        .position(|c| c.mean.0 > x)
+        .unwrap_or_else(|| t.centroids.len());
+    t.centroids.insert(
+        pos,
+        Centroid {
+            mean: OrderedFloat(x),
+            weight: n,
+        },
+    );
+    if t.count == 0 {
+        t.min = OrderedFloat(x);
+        t.max = OrderedFloat(x);
+    } else {
+        t.min = OrderedFloat(t.min.0.min(x));
+        t.max = OrderedFloat(t.max.0.max(x));
+    }
+    t.count += n;
+    t.sum = OrderedFloat(t.sum.0 + x * (n as f64));
+}
+
+fn tdigest_maybe_compress(t: &mut TDigest) {
+    if t.centroids.len() as f64 > 2.0 * valid_compression(t.compression.0) {
+        tdigest_compress(t);
+    }
+}
+
+fn tdigest_merge_into(t: &mut TDigest, other: &TDigest) {
+    if other.count == 0 {
+        return;
+    }
+    if t.count == 0 {
+        t.min = other.min;
+        t.max = other.max;
+    } else {
+        t.min = std::cmp::min(t.min, other.min);
+        t.max = std::cmp::max(t.max, other.max);
+    }
+    t.compression = std::cmp::max(t.compression, other.compression);
+    t.count += other.count;
+    t.sum = OrderedFloat(t.sum.0 + other.sum.0);
+    t.centroids.extend(other.centroids.iter().cloned());
+    t.centroids.sort_by(|c1, c2| c1.mean.cmp(&c2.mean));
+    tdigest_maybe_compress(t);
+}
+
+pub fn tdigest_new(compression: &OrderedFloat<f64>) -> TDigest {
+    TDigest {
+        compression: OrderedFloat(valid_compression(compression.0)),
+        centroids: DDlogVec::new(),
+        count: 0,
+        sum: OrderedFloat(0.0),
+        min: OrderedFloat(0.0),
+        max: OrderedFloat(0.0),
+    }
+}
+
+pub fn tdigest_add(t: &TDigest, x: &OrderedFloat<f64>) -> TDigest {
+    let mut res = t.clone();
+    tdigest_insert(&mut res, x.0, 1);
+    tdigest_maybe_compress(&mut res);
+    res
+}
+
+pub fn tdigest_merge(t1: &TDigest, t2: &TDigest) -> TDigest {
+    let mut res = t1.clone();
+    tdigest_merge_into(&mut res, t2);
+    res
+}
+
+/// Cumulative weight at the center of each centroid.
+fn centroid_centers(centroids: &[Centroid]) -> Vec<f64> {
+    let mut seen = 0.0;
+    centroids
+        .iter()
+        .map(|c| {
+            let center = seen + (c.weight as f64) / 2.0;
+            seen += c.weight as f64;
+            center
+        })
+        .collect()
+}
+
+fn interpolate(x: f64, x0: f64, x1: f64, y0: f64, y1: f64) -> f64 {
+    if x1 <= x0 {
+        y0
+    } else {
+        y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+    }
+}
+
+pub fn tdigest_quantile(t: &TDigest, q: &OrderedFloat<f64>) -> DDlogOption<OrderedFloat<f64>> {
+    let q = q.0;
+    if t.count == 0 || t.centroids.is_empty() || !(0.0..=1.0).contains(&q) {
+        return DDlogOption::None;
+    }
+    let (min, max) = (t.min.0, t.max.0);
+    let total = t.count as f64;
+    let rank = q * total;
+    let centroids: &[Centroid] = &t.centroids;
+    let centers = centroid_centers(centroids);
+    let last = centroids.len() - 1;
+    let res = if rank <= centers[0] {
+        interpolate(rank, 0.0, centers[0], min, centroids[0].mean.0)
+    } else if rank >= centers[last] {
+        interpolate(rank, centers[last], total, centroids[last].mean.0, max)
+    } else {
+        let i = centers.iter().rposition(|c| *c <= rank).unwrap_or(0);
+        interpolate(
+            rank,
+            centers[i],
+            centers[i + 1],
+            centroids[i].mean.0,
+            centroids[i + 1].mean.0,
+        )
+    };
+    some(res.max(min).min(max))
+}
+
+pub fn tdigest_cdf(t: &TDigest, x: &OrderedFloat<f64>) -> DDlogOption<OrderedFloat<f64>> {
+    let x = x.0;
+    if t.count == 0 || t.centroids.is_empty() || x.is_nan() {
+        return DDlogOption::None;
+    }
+    let (min, max) = (t.min.0, t.max.0);
+    if x < min {
+        return some(0.0);
+    }
+    if x >= max {
+        return some(1.0);
+    }
+    let total = t.count as f64;
+    let centroids: &[Centroid] = &t.centroids;
+    let centers = centroid_centers(centroids);
+    let last = centroids.len() - 1;
+    let rank = if x <= centroids[0].mean.0 {
+        interpolate(x, min, centroids[0].mean.0, 0.0, centers[0])
+    } else if x >= centroids[last].mean.0 {
+        interpolate(x, centroids[last].mean.0, max, centers[last], total)
+    } else {
+        let i = centroids.iter().rposition(|c| c.mean.0 <= x).unwrap_or(0);
+        interpolate(
+            x,
+            centroids[i].mean.0,
+            centroids[i + 1].mean.0,
+            centers[i],
+            centers[i + 1],
+        )
+    };
+    some((rank / total).max(0.0).min(1.0))
+}
+
+pub fn group_tdigest<K>(
+    g: &Group<K, OrderedFloat<f64>>,
+    compression: &OrderedFloat<f64>,
+) -> TDigest {
+    let mut res = tdigest_new(compression);
+    /* Build the list of singleton centroids and compress it once instead of
+     * compressing after every insertion. */
+    let mut centroids = Vec::with_capacity(g.size() as usize);
+    for ddlog_std::tuple2(x, w) in g.iter() {
+        let n = weight2count(w);
+        if x.is_nan() || n == 0 {
+            continue;
+        }
+        if res.count == 0 {
+            res.min = x;
+            res.max = x;
+        } else {
+            res.min = std::cmp::min(res.min, x);
+            res.max = std::cmp::max(res.max, x);
+        }
+        res.count += n;
+        res.sum = OrderedFloat(res.sum.0 + x.0 * (n as f64));
+        centroids.push(Centroid { mean: x, weight: n });
+    }
+    centroids.sort_by(|c1, c2| c1.mean.cmp(&c2.mean));
+    res.centroids = DDlogVec::from(centroids);
+    tdigest_compress(&mut res);
+    res
+}
+
+pub fn group_tdigest_merge<K>(g: &Group<K, TDigest>) -> TDigest {
+    let mut res = tdigest_new(&OrderedFloat(1.0));
+    for ddlog_std::tuple2(t, w) in g.iter() {
+        for _ in 0..weight2count(w) {
+            tdigest_merge_into(&mut res, &t);
+        }
+    }
+    tdigest_compress(&mut res);
+    res
+}
@@ -149,6 +149,86 @@ where
     }
 }
 
+impl<'a, T, F> FromFlatBuffer<fbrt::Vector<'a, F>> for ddlog_std::SmallVec<T>
+where
+    T: Ord + FromFlatBuffer<F::Inner>,
+    F: fbrt::Follow<'a> + 'a,
+{
+    fn from_flatbuf(fb: fbrt::Vector<'a, F>) -> ::std::result::Result<Self, String> {
+        let mut vec = ddlog_std::SmallVec::with_capacity(fb.len());
+        for x in FBIter::from_vector(fb) {
+            vec.vec.push(T::from_flatbuf(x)?);
+        }
+        Ok(vec)
+    }
+}
+
+// For scalar types, the FlatBuffers API returns slice instead of 'Vector'.
+impl<'a, T> FromFlatBuffer<&'a [T]> for ddlog_std::SmallVec<T>
+where
+    T: Clone,
+{
+    fn from_flatbuf(fb: &'a [T]) -> ::std::result::Result<Self, String> {
+        let mut vec = ddlog_std::SmallVec::with_capacity(fb.len());
+        vec.vec.extend(fb.iter().cloned());
+        Ok(vec)
+    }
+}
+
+impl<'b, T> ToFlatBuffer<'b> for ddlog_std::SmallVec<T>
+where
+    T: ToFlatBufferVectorElement<'b>,
+{
+    type Target = fbrt::WIPOffset<fbrt::Vector<'b, <T::Target as fbrt::Push>::Output>>;
+
+    fn to_flatbuf(&self, fbb: &mut fbrt::FlatBufferBuilder<'b>) -> Self::Target {
+        let vec: ::std::vec::Vec<T::Target> = self
+            .iter()
+            .map(|x| x.to_flatbuf_vector_element(fbb))
+            .collect();
+        fbb.create_vector(vec.as_slice())
+    }
+}
+
+impl<'a, T, F> FromFlatBuffer<fbrt::Vector<'a, F>> for ddlog_std::SmallSet<T>
+where
+    T: Ord + FromFlatBuffer<F::Inner>,
+    F: fbrt::Follow<'a> + 'a,
+{
+    fn from_flatbuf(fb: fbrt::Vector<'a, F>) -> ::std::result::Result<Self, String> {
+        let mut set = ddlog_std::SmallSet::new();
+        for x in FBIter::from_vector(fb) {
+            set.insert(T::from_flatbuf(x)?);
+        }
+        Ok(set)
+    }
+}
+
+// For scalar types, the FlatBuffers API returns slice instead of 'Vector'.
+impl<'a, T> FromFlatBuffer<&'a [T]> for ddlog_std::SmallSet<T>
+where
+    T: Ord + Clone,
+{
+    fn from_flatbuf(fb: &'a [T]) -> ::std::result::Result<Self, String> {
+        Ok(fb.iter().cloned().collect())
+    }
+}
+
+impl<'b, T> ToFlatBuffer<'b> for ddlog_std::SmallSet<T>
+where
+    T: Ord + ToFlatBufferVectorElement<'b>,
+{
+    type Target = fbrt::WIPOffset<fbrt::Vector<'b, <T::Target as fbrt::Push>::Output>>;
+
+    fn to_flatbuf(&self, fbb: &mut fbrt::FlatBufferBuilder<'b>) -> Self::Target {
+        let vec: ::std::vec::Vec<T::Target> = self
+            .iter()
+            .map(|x| x.to_flatbuf_vector_element(fbb))
+            .collect();
+        fbb.create_vector(vec.as_slice())
+    }
+}
+
 impl<'a, K, V, F> FromFlatBuffer<fbrt::Vector<'a, F>> for ddlog_std::Map<K, V>
 where
     F: fbrt::Follow<'a> + 'a,
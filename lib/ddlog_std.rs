@@ -33,11 +33,11 @@ use serde::{
 use std::{
     borrow,
     cmp::{self, Ordering},
-    collections::{btree_map, btree_set, BTreeMap, BTreeSet},
+    collections::{btree_map, btree_set, BTreeMap, BTreeSet, BinaryHeap},
     fmt::{self, Debug, Display, Formatter, Result as FmtResult},
     hash::{Hash, Hasher},
     io,
-    iter::FromIterator,
+    iter::{FromIterator, FusedIterator},
     mem,
     ops::{self, Add, DerefMut},
     option::Option as StdOption,
@@ -73,6 +73,160 @@ pub fn result_unwrap_or_default<T: Default + Clone, E>(res: &Result<T, E>) -> T
     }
 }
 
+/// Convert Rust result type to DDlog's std::Result, preserving the error
+/// type (see also `res2std`).
+pub fn result2std<T, E>(res: StdResult<T, E>) -> Result<T, E> {
+    match res {
+        Ok(res) => Result::Ok { res },
+        Err(err) => Result::Err { err },
+    }
+}
+
+pub fn std2result<T, E>(res: Result<T, E>) -> StdResult<T, E> {
+    match res {
+        Result::Ok { res } => Ok(res),
+        Result::Err { err } => Err(err),
+    }
+}
+
+impl<T, E> From<StdResult<T, E>> for Result<T, E> {
+    fn from(res: StdResult<T, E>) -> Self {
+        result2std(res)
+    }
+}
+
+impl<T, E> From<Result<T, E>> for StdResult<T, E> {
+    fn from(res: Result<T, E>) -> Self {
+        std2result(res)
+    }
+}
+
+impl<T, E> Result<T, E> {
+    /// Convert to Rust's `Result`, e.g., to use the `?` operator in extern
+    /// functions.
+    pub fn into_std(self) -> StdResult<T, E> {
+        std2result(self)
+    }
+
+    pub fn as_std(&self) -> StdResult<&T, &E> {
+        match self {
+            Result::Ok { res } => Ok(res),
+            Result::Err { err } => Err(err),
+        }
+    }
+}
+
+/// Collects an iterator over results into a result of a collection, stopping
+/// at the first error, like the `FromIterator` implementation of Rust's
+/// `Result`.
+impl<A, E, V: FromIterator<A>> FromIterator<Result<A, E>> for Result<V, E> {
+    fn from_iter<I: IntoIterator<Item = Result<A, E>>>(iter: I) -> Self {
+        result2std(iter.into_iter().map(std2result).collect())
+    }
+}
+
+/// Conversion of Rust's `Option` and `Result` types (and iterators over
+/// them) into their DDlog counterparts, e.g.,
+/// `s.parse::<u64>().ok().into_ddlog()`.
+pub trait IntoDDlog {
+    type DDlog;
+    fn into_ddlog(self) -> Self::DDlog;
+}
+
+impl<T> IntoDDlog for StdOption<T> {
+    type DDlog = Option<T>;
+    fn into_ddlog(self) -> Option<T> {
+        option2std(self)
+    }
+}
+
+impl<T, E> IntoDDlog for StdResult<T, E> {
+    type DDlog = Result<T, E>;
+    fn into_ddlog(self) -> Result<T, E> {
+        result2std(self)
+    }
+}
+
+/// Iterator adapters that convert the items of an iterator between Rust and
+/// DDlog `Option`s and `Result`s.
+pub trait ConvertIter: Iterator + Sized {
+    /// Convert Rust `Option`s and `Result`s to DDlog.
+    fn into_ddlog(self) -> std::iter::Map<Self, fn(Self::Item) -> <Self::Item as IntoDDlog>::DDlog>
+    where
+        Self::Item: IntoDDlog,
+    {
+        self.map(IntoDDlog::into_ddlog as fn(_) -> _)
+    }
+
+    /// Convert DDlog `Option`s and `Result`s to Rust.
+    fn into_std(self) -> std::iter::Map<Self, fn(Self::Item) -> <Self::Item as IntoStd>::Std>
+    where
+        Self::Item: IntoStd,
+    {
+        self.map(IntoStd::into_std as fn(_) -> _)
+    }
+}
+
+impl<I: Iterator> ConvertIter for I {}
+
+/// Conversion of DDlog's `Option` and `Result` types into their Rust
+/// counterparts; the inverse of `IntoDDlog`.
+pub trait IntoStd {
+    type Std;
+    fn into_std(self) -> Self::Std;
+}
+
+impl<T> IntoStd for Option<T> {
+    type Std = StdOption<T>;
+    fn into_std(self) -> StdOption<T> {
+        std2option(self)
+    }
+}
+
+impl<T, E> IntoStd for Result<T, E> {
+    type Std = StdResult<T, E>;
+    fn into_std(self) -> StdResult<T, E> {
+        std2result(self)
+    }
+}
+
+#[test]
+fn test_result_conversions() {
+    let ok: StdResult<u32, String> = Ok(5);
+    let err: StdResult<u32, String> = Err("bad".to_string());
+    assert_eq!(ok.clone().into_ddlog(), Result::Ok { res: 5 });
+    assert_eq!(
+        Result::from(err.clone()),
+        Result::Err {
+            err: "bad".to_string()
+        }
+    );
+    assert_eq!(StdResult::from(ok.clone().into_ddlog()), ok);
+    assert_eq!(err.clone().into_ddlog().into_std(), err);
+    assert_eq!(Result::<u32, String>::Ok { res: 5 }.as_std(), Ok(&5));
+
+    let collected: Result<Vec<u32>, String> = vec![ok.clone(), ok.clone()]
+        .into_iter()
+        .into_ddlog()
+        .collect();
+    assert_eq!(collected, Result::Ok { res: vec![5, 5] });
+    let collected: Result<Vec<u32>, String> = vec![ok.clone(), err.clone(), ok.clone()]
+        .into_iter()
+        .into_ddlog()
+        .collect();
+    assert_eq!(
+        collected,
+        Result::Err {
+            err: "bad".to_string()
+        }
+    );
+    let back: Vec<StdResult<u32, String>> = vec![ok.clone().into_ddlog(), err.clone().into_ddlog()]
+        .into_iter()
+        .into_std()
+        .collect();
+    assert_eq!(back, vec![ok, err]);
+}
+
 // Ref
 
 /// An atomically reference counted reference
@@ -252,6 +406,70 @@ impl<T> From<Option<T>> for StdOption<T> {
     }
 }
 
+impl<T> Option<T> {
+    pub fn into_std(self) -> StdOption<T> {
+        std2option(self)
+    }
+
+    pub fn as_std(&self) -> StdOption<&T> {
+        match self {
+            Option::None => StdOption::None,
+            Option::Some { x } => StdOption::Some(x),
+        }
+    }
+}
+
+/// Iterates over the value of a `Some`, like Rust's `Option`.
+impl<T> IntoIterator for Option<T> {
+    type Item = T;
+    type IntoIter = std::option::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        std2option(self).into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Option<T> {
+    type Item = &'a T;
+    type IntoIter = std::option::IntoIter<&'a T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_std().into_iter()
+    }
+}
+
+/// Collects an iterator over options into an option of a collection, which
+/// is `None` if any element is `None`.
+impl<A, V: FromIterator<A>> FromIterator<Option<A>> for Option<V> {
+    fn from_iter<I: IntoIterator<Item = Option<A>>>(iter: I) -> Self {
+        option2std(iter.into_iter().map(std2option).collect())
+    }
+}
+
+#[test]
+fn test_option_conversions() {
+    assert_eq!(Some(5).into_ddlog(), Option::Some { x: 5 });
+    assert_eq!(Option::from(StdOption::<u32>::None), Option::None);
+    assert_eq!(Option::Some { x: 5 }.into_std(), Some(5));
+    assert_eq!(StdOption::from(Option::<u32>::None), None);
+    assert_eq!(Option::Some { x: 5 }.as_std(), Some(&5));
+
+    let some = Option::Some { x: 5 };
+    assert_eq!((&some).into_iter().collect::<Vec<_>>(), vec![&5]);
+    assert_eq!(some.into_iter().collect::<Vec<_>>(), vec![5]);
+    assert_eq!(Option::<u32>::None.into_iter().count(), 0);
+
+    let collected: Option<Vec<u32>> = vec![Some(1), Some(2)].into_iter().into_ddlog().collect();
+    assert_eq!(collected, Option::Some { x: vec![1, 2] });
+    let collected: Option<Vec<u32>> = vec![Some(1), None].into_iter().into_ddlog().collect();
+    assert_eq!(collected, Option::None);
+    let back: Vec<StdOption<u32>> = vec![Option::Some { x: 1 }, Option::None]
+        .into_iter()
+        .into_std()
+        .collect();
+    assert_eq!(back, vec![Some(1), None]);
+}
+
 impl<T> FromRecord for Option<T>
 where
     T: FromRecord + DeserializeOwned + Default,
@@ -470,7 +688,10 @@ impl<T: IntoRecord> IntoRecord for Vec<T> {
     }
 }
 
-impl<T: FromRecord> Mutator<Vec<T>> for Record {
+impl<T: FromRecord> Mutator<Vec<T>> for Record
+where
+    Record: Mutator<T>,
+{
     fn mutate(&self, vec: &mut Vec<T>) -> StdResult<(), String> {
         self.mutate(&mut vec.vec)
     }
@@ -775,41 +996,490 @@ pub fn set_is_empty<X: Ord>(s: &Set<X>) -> bool {
     s.x.is_empty()
 }
 
-pub fn set_nth<X: Ord + Clone>(s: &Set<X>, n: &std_usize) -> Option<X> {
-    option2std(s.x.iter().nth(*n as usize).cloned())
+pub fn set_nth<X: Ord + Clone>(s: &Set<X>, n: &std_usize) -> Option<X> {
+    option2std(s.x.iter().nth(*n as usize).cloned())
+}
+
+pub fn set_to_vec<X: Ord + Clone>(set: &Set<X>) -> Vec<X> {
+    Vec {
+        vec: set.x.iter().cloned().collect(),
+    }
+}
+
+pub fn set_union<X: Ord + Clone>(s1: &Set<X>, s2: &Set<X>) -> Set<X> {
+    let mut s = s1.clone();
+    s.x.append(&mut s2.x.clone());
+    s
+}
+
+pub fn set_unions<X: Ord + Clone>(sets: &Vec<Set<X>>) -> Set<X> {
+    let mut s = BTreeSet::new();
+    for set in sets.iter() {
+        s.append(&mut set.x.clone());
+    }
+
+    Set { x: s }
+}
+
+pub fn set_intersection<X: Ord + Clone>(s1: &Set<X>, s2: &Set<X>) -> Set<X> {
+    Set {
+        x: s1.x.intersection(&s2.x).cloned().collect(),
+    }
+}
+
+pub fn set_difference<X: Ord + Clone>(s1: &Set<X>, s2: &Set<X>) -> Set<X> {
+    Set {
+        x: s1.x.difference(&s2.x).cloned().collect(),
+    }
+}
+
+// SmallVec
+
+/// Number of elements that `SmallVec` and `SmallSet` store inline before
+/// spilling to the heap.
+pub const SMALL_INLINE_CAPACITY: usize = 4;
+
+type SmallStorage<T> = smallvec::SmallVec<[T; SMALL_INLINE_CAPACITY]>;
+
+/// A vector that keeps up to `SMALL_INLINE_CAPACITY` elements inline.
+///
+/// Behaves exactly like [`Vec`], but does not allocate until it outgrows
+/// its inline storage, which makes it a better fit for the many tiny
+/// vectors found in wide fact tables.
+#[derive(Eq, Ord, Clone, Hash, PartialEq, PartialOrd, Default)]
+pub struct SmallVec<T> {
+    pub vec: SmallStorage<T>,
+}
+
+impl<T> SmallVec<T> {
+    /// Creates a new, empty vector
+    pub fn new() -> Self {
+        SmallVec {
+            vec: SmallStorage::new(),
+        }
+    }
+
+    /// Creates a new, empty vector with the specified capacity
+    pub fn with_capacity(capacity: usize) -> Self {
+        SmallVec {
+            vec: SmallStorage::with_capacity(capacity),
+        }
+    }
+
+    /// Returns an iterator over the vector
+    pub fn iter(&self) -> slice::Iter<'_, T> {
+        self.vec.iter()
+    }
+
+    /// Returns `true` if the vector has outgrown its inline storage
+    pub fn spilled(&self) -> bool {
+        self.vec.spilled()
+    }
+}
+
+impl<T> From<StdVec<T>> for SmallVec<T> {
+    fn from(vec: StdVec<T>) -> Self {
+        SmallVec {
+            vec: SmallStorage::from_vec(vec),
+        }
+    }
+}
+
+impl<T> FromIterator<T> for SmallVec<T> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        SmallVec {
+            vec: SmallStorage::from_iter(iter),
+        }
+    }
+}
+
+impl<T> Deref for SmallVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        self.vec.as_slice()
+    }
+}
+
+impl<T> DerefMut for SmallVec<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.vec.as_mut_slice()
+    }
+}
+
+impl<T: Serialize> Serialize for SmallVec<T> {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.vec.as_slice().serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for SmallVec<T> {
+    fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        StdVec::deserialize(deserializer).map(SmallVec::from)
+    }
+}
+
+impl<T: FromRecord> FromRecord for SmallVec<T> {
+    fn from_record(val: &Record) -> StdResult<Self, String> {
+        StdVec::from_record(val).map(SmallVec::from)
+    }
+}
+
+impl<T: IntoRecord> IntoRecord for SmallVec<T> {
+    fn into_record(self) -> Record {
+        self.vec.into_vec().into_record()
+    }
+}
+
+impl<T: FromRecord> Mutator<SmallVec<T>> for Record
+where
+    Record: Mutator<T>,
+{
+    fn mutate(&self, vec: &mut SmallVec<T>) -> StdResult<(), String> {
+        let mut v = mem::take(&mut vec.vec).into_vec();
+        let res = self.mutate(&mut v);
+        vec.vec = SmallStorage::from_vec(v);
+        res
+    }
+}
+
+impl<T: Debug> Debug for SmallVec<T> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_list().entries(self.vec.iter()).finish()
+    }
+}
+
+impl<T> IntoIterator for SmallVec<T> {
+    type Item = T;
+    type IntoIter = smallvec::IntoIter<[T; SMALL_INLINE_CAPACITY]>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.vec.into_iter()
+    }
+}
+
+pub fn smallvec_empty<T>() -> SmallVec<T> {
+    SmallVec::new()
+}
+
+pub fn smallvec_with_length<T: Clone>(len: &std_usize, splat: &T) -> SmallVec<T> {
+    SmallVec {
+        vec: SmallStorage::from_elem(splat.clone(), *len as usize),
+    }
+}
+
+pub fn smallvec_with_capacity<T>(len: &std_usize) -> SmallVec<T> {
+    SmallVec::with_capacity(*len as usize)
+}
+
+pub fn smallvec_singleton<T: Clone>(value: &T) -> SmallVec<T> {
+    let mut res = SmallVec::new();
+    res.vec.push(value.clone());
+    res
+}
+
+pub fn smallvec_len<T>(vec: &SmallVec<T>) -> std_usize {
+    vec.vec.len() as std_usize
+}
+
+pub fn smallvec_push<T: Clone>(vec: &mut SmallVec<T>, elem: &T) {
+    vec.vec.push(elem.clone());
+}
+
+pub fn smallvec_pop<T: Clone>(vec: &mut SmallVec<T>) -> Option<T> {
+    option2std(vec.vec.pop())
+}
+
+pub fn smallvec_append<T: Clone>(vec: &mut SmallVec<T>, other: &SmallVec<T>) {
+    vec.vec.extend(other.vec.iter().cloned());
+}
+
+pub fn smallvec_push_imm<T: Clone>(vec: &SmallVec<T>, x: &T) -> SmallVec<T> {
+    let mut res = SmallVec::with_capacity(vec.vec.len() + 1);
+    res.vec.extend(vec.vec.iter().cloned());
+    res.vec.push(x.clone());
+    res
+}
+
+pub fn smallvec_contains<T: PartialEq>(vec: &SmallVec<T>, x: &T) -> bool {
+    vec.vec.contains(x)
+}
+
+pub fn smallvec_is_empty<T>(vec: &SmallVec<T>) -> bool {
+    vec.vec.is_empty()
+}
+
+pub fn smallvec_nth<T: Clone>(vec: &SmallVec<T>, nth: &std_usize) -> Option<T> {
+    vec.vec.get(*nth as usize).cloned().into()
+}
+
+pub fn smallvec_sort<T: Ord>(vec: &mut SmallVec<T>) {
+    vec.vec.sort();
+}
+
+pub fn smallvec_sort_imm<T: Ord + Clone>(vec: &SmallVec<T>) -> SmallVec<T> {
+    let mut res = vec.clone();
+    res.vec.sort();
+    res
+}
+
+pub fn smallvec_resize<T: Clone>(vec: &mut SmallVec<T>, new_len: &std_usize, value: &T) {
+    vec.vec.resize(*new_len as usize, value.clone())
+}
+
+pub fn smallvec_truncate<T>(vec: &mut SmallVec<T>, new_len: &std_usize) {
+    vec.vec.truncate(*new_len as usize)
+}
+
+pub fn smallvec_swap_nth<T: Clone>(vec: &mut SmallVec<T>, idx: &std_usize, value: &mut T) -> bool {
+    match vec.vec.get_mut(*idx as usize) {
+        Some(x) => {
+            mem::swap(x, value);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn smallvec_update_nth<T: Clone>(vec: &mut SmallVec<T>, idx: &std_usize, value: &T) -> bool {
+    match vec.vec.get_mut(*idx as usize) {
+        Some(x) => {
+            *x = value.clone();
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn smallvec_to_vec<T: Clone>(vec: &SmallVec<T>) -> Vec<T> {
+    Vec::from(vec.vec.as_slice())
+}
+
+pub fn smallvec_to_set<T: Ord + Clone>(vec: &SmallVec<T>) -> Set<T> {
+    vec.vec.iter().cloned().collect()
+}
+
+pub fn smallvec_to_smallset<T: Ord + Clone>(vec: &SmallVec<T>) -> SmallSet<T> {
+    vec.vec.iter().cloned().collect()
+}
+
+pub fn vec_to_smallvec<T: Clone>(vec: &Vec<T>) -> SmallVec<T> {
+    vec.iter().cloned().collect()
+}
+
+// SmallSet
+
+/// An ordered set that keeps up to `SMALL_INLINE_CAPACITY` elements inline.
+///
+/// Behaves exactly like [`Set`]; elements are stored in a sorted `SmallVec`
+/// without duplicates, so the iteration order, comparison and serialized
+/// form are the same as for `Set`.
+#[derive(Eq, Ord, Clone, Hash, PartialEq, PartialOrd, Default)]
+pub struct SmallSet<T> {
+    pub x: SmallStorage<T>,
+}
+
+impl<T: Ord> SmallSet<T> {
+    pub fn new() -> Self {
+        SmallSet {
+            x: SmallStorage::new(),
+        }
+    }
+
+    /// Inserts `v` into the set; returns `false` if it was already there.
+    pub fn insert(&mut self, v: T) -> bool {
+        match self.x.binary_search(&v) {
+            Ok(_) => false,
+            Err(idx) => {
+                self.x.insert(idx, v);
+                true
+            }
+        }
+    }
+
+    pub fn contains(&self, v: &T) -> bool {
+        self.x.binary_search(v).is_ok()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, T> {
+        self.x.iter()
+    }
+
+    /// Construct a set from a vector, sorting and deduplicating its elements.
+    fn from_unsorted(mut x: SmallStorage<T>) -> Self {
+        x.sort();
+        x.dedup();
+        SmallSet { x }
+    }
+}
+
+impl<T: Ord> FromIterator<T> for SmallSet<T> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        SmallSet::from_unsorted(SmallStorage::from_iter(iter))
+    }
+}
+
+impl<T: Ord> IntoIterator for SmallSet<T> {
+    type Item = T;
+    type IntoIter = smallvec::IntoIter<[T; SMALL_INLINE_CAPACITY]>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.x.into_iter()
+    }
+}
+
+impl<T: Serialize> Serialize for SmallSet<T> {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.x.as_slice().serialize(serializer)
+    }
+}
+
+impl<'de, T: Ord + Deserialize<'de>> Deserialize<'de> for SmallSet<T> {
+    fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        StdVec::deserialize(deserializer)
+            .map(|v| SmallSet::from_unsorted(SmallStorage::from_vec(v)))
+    }
+}
+
+impl<T: FromRecord + Ord> FromRecord for SmallSet<T> {
+    fn from_record(val: &Record) -> StdResult<Self, String> {
+        BTreeSet::from_record(val).map(|s| s.into_iter().collect())
+    }
+}
+
+impl<T: IntoRecord + Ord> IntoRecord for SmallSet<T> {
+    fn into_record(self) -> Record {
+        self.x.into_iter().collect::<BTreeSet<T>>().into_record()
+    }
+}
+
+impl<T: FromRecord + Ord> Mutator<SmallSet<T>> for Record {
+    fn mutate(&self, set: &mut SmallSet<T>) -> StdResult<(), String> {
+        let mut s: BTreeSet<T> = mem::take(&mut set.x).into_iter().collect();
+        let res = self.mutate(&mut s);
+        *set = s.into_iter().collect();
+        res
+    }
+}
+
+impl<T: Debug> Debug for SmallSet<T> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_set().entries(self.x.iter()).finish()
+    }
+}
+
+pub fn smallset_empty<T: Ord>() -> SmallSet<T> {
+    SmallSet::new()
+}
+
+pub fn smallset_singleton<T: Ord + Clone>(v: &T) -> SmallSet<T> {
+    let mut s = SmallSet::new();
+    s.x.push(v.clone());
+    s
+}
+
+pub fn smallset_size<T>(s: &SmallSet<T>) -> std_usize {
+    s.x.len() as std_usize
+}
+
+pub fn smallset_insert<T: Ord + Clone>(s: &mut SmallSet<T>, v: &T) {
+    s.insert(v.clone());
+}
+
+pub fn smallset_insert_imm<T: Ord + Clone>(s: &SmallSet<T>, v: &T) -> SmallSet<T> {
+    let mut s2 = s.clone();
+    s2.insert(v.clone());
+    s2
+}
+
+pub fn smallset_contains<T: Ord>(s: &SmallSet<T>, v: &T) -> bool {
+    s.contains(v)
+}
+
+pub fn smallset_is_empty<T>(s: &SmallSet<T>) -> bool {
+    s.x.is_empty()
+}
+
+pub fn smallset_nth<T: Clone>(s: &SmallSet<T>, n: &std_usize) -> Option<T> {
+    s.x.get(*n as usize).cloned().into()
+}
+
+pub fn smallset_union<T: Ord + Clone>(s1: &SmallSet<T>, s2: &SmallSet<T>) -> SmallSet<T> {
+    let mut x = SmallStorage::with_capacity(s1.x.len() + s2.x.len());
+    let (mut i, mut j) = (0, 0);
+    while i < s1.x.len() && j < s2.x.len() {
+        match s1.x[i].cmp(&s2.x[j]) {
+            Ordering::Less => {
+                x.push(s1.x[i].clone());
+                i += 1;
+            }
+            Ordering::Greater => {
+                x.push(s2.x[j].clone());
+                j += 1;
+            }
+            Ordering::Equal => {
+                x.push(s1.x[i].clone());
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    x.extend(s1.x[i..].iter().cloned());
+    x.extend(s2.x[j..].iter().cloned());
+    SmallSet { x }
+}
+
+pub fn smallset_intersection<T: Ord + Clone>(s1: &SmallSet<T>, s2: &SmallSet<T>) -> SmallSet<T> {
+    SmallSet {
+        x: s1.x.iter().filter(|v| s2.contains(v)).cloned().collect(),
+    }
 }
 
-pub fn set_to_vec<X: Ord + Clone>(set: &Set<X>) -> Vec<X> {
-    Vec {
-        vec: set.x.iter().cloned().collect(),
+pub fn smallset_difference<T: Ord + Clone>(s1: &SmallSet<T>, s2: &SmallSet<T>) -> SmallSet<T> {
+    SmallSet {
+        x: s1.x.iter().filter(|v| !s2.contains(v)).cloned().collect(),
     }
 }
 
-pub fn set_union<X: Ord + Clone>(s1: &Set<X>, s2: &Set<X>) -> Set<X> {
-    let mut s = s1.clone();
-    s.x.append(&mut s2.x.clone());
-    s
+pub fn smallset_to_vec<T: Clone>(s: &SmallSet<T>) -> Vec<T> {
+    Vec::from(s.x.as_slice())
 }
 
-pub fn set_unions<X: Ord + Clone>(sets: &Vec<Set<X>>) -> Set<X> {
-    let mut s = BTreeSet::new();
-    for set in sets.iter() {
-        s.append(&mut set.x.clone());
-    }
+pub fn smallset_to_smallvec<T: Clone>(s: &SmallSet<T>) -> SmallVec<T> {
+    SmallVec { vec: s.x.clone() }
+}
 
-    Set { x: s }
+pub fn smallset_to_set<T: Ord + Clone>(s: &SmallSet<T>) -> Set<T> {
+    s.x.iter().cloned().collect()
 }
 
-pub fn set_intersection<X: Ord + Clone>(s1: &Set<X>, s2: &Set<X>) -> Set<X> {
-    Set {
-        x: s1.x.intersection(&s2.x).cloned().collect(),
+pub fn set_to_smallset<T: Ord + Clone>(s: &Set<T>) -> SmallSet<T> {
+    // `BTreeSet` iterates in order, so the result is already sorted.
+    SmallSet {
+        x: s.x.iter().cloned().collect(),
     }
 }
 
-pub fn set_difference<X: Ord + Clone>(s1: &Set<X>, s2: &Set<X>) -> Set<X> {
-    Set {
-        x: s1.x.difference(&s2.x).cloned().collect(),
-    }
+pub fn vec_to_smallset<T: Ord + Clone>(vec: &Vec<T>) -> SmallSet<T> {
+    vec.iter().cloned().collect()
 }
 
 // Map
@@ -888,7 +1558,10 @@ impl<K: IntoRecord + Ord, V: IntoRecord> IntoRecord for Map<K, V> {
     }
 }
 
-impl<K: FromRecord + Ord, V: FromRecord + PartialEq> Mutator<Map<K, V>> for Record {
+impl<K: FromRecord + Ord, V: FromRecord + PartialEq> Mutator<Map<K, V>> for Record
+where
+    Record: Mutator<V>,
+{
     fn mutate(&self, map: &mut Map<K, V>) -> StdResult<(), String> {
         self.mutate(&mut map.x)
     }
@@ -1087,6 +1760,240 @@ pub fn string_reverse(s: &String) -> String {
     s.chars().rev().collect()
 }
 
+// Collection views
+
+/// Borrowed, read-only view of a DDlog `Vec`.
+///
+/// Extern functions declared with the `#[view_args]` attribute receive their
+/// `Vec`, `Set`, and `Map` arguments as views instead of references to the
+/// collection, e.g.:
+///
+/// ```text
+/// #[view_args]
+/// extern function sum_lengths(v: Vec<string>): usize
+/// ```
+///
+/// ```ignore
+/// pub fn sum_lengths(v: VecView<String>) -> u64 {
+///     v.iter().map(|s| s.len() as u64).sum()
+/// }
+/// ```
+///
+/// Views are `Copy` and dereference to the underlying standard collection
+/// (`&[T]`, `&BTreeSet<T>`, `&BTreeMap<K, V>`), so library code can read
+/// collection fields in place without cloning them out of the DDlog value.
+#[derive(Eq, Ord, Hash, PartialEq, PartialOrd)]
+pub struct VecView<'a, T> {
+    slice: &'a [T],
+}
+
+impl<'a, T> Clone for VecView<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for VecView<'a, T> {}
+
+impl<'a, T> VecView<'a, T> {
+    pub fn as_slice(&self) -> &'a [T] {
+        self.slice
+    }
+
+    pub fn iter(&self) -> slice::Iter<'a, T> {
+        self.slice.iter()
+    }
+
+    /// Clone the viewed vector.
+    pub fn to_vec(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        Vec::from(self.slice)
+    }
+}
+
+impl<'a, T> Deref for VecView<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        self.slice
+    }
+}
+
+impl<'a, T> From<&'a Vec<T>> for VecView<'a, T> {
+    fn from(vec: &'a Vec<T>) -> Self {
+        VecView {
+            slice: vec.vec.as_slice(),
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for VecView<'a, T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.slice.iter()
+    }
+}
+
+impl<'a, T: Debug> Debug for VecView<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_list().entries(self.slice.iter()).finish()
+    }
+}
+
+impl<T> Vec<T> {
+    pub fn view(&self) -> VecView<'_, T> {
+        VecView::from(self)
+    }
+}
+
+/// Borrowed, read-only view of a DDlog `Set` (see [`VecView`]).
+#[derive(Eq, Ord, Hash, PartialEq, PartialOrd)]
+pub struct SetView<'a, T> {
+    set: &'a BTreeSet<T>,
+}
+
+impl<'a, T> Clone for SetView<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for SetView<'a, T> {}
+
+impl<'a, T: Ord> SetView<'a, T> {
+    pub fn as_btree_set(&self) -> &'a BTreeSet<T> {
+        self.set
+    }
+
+    pub fn iter(&self) -> btree_set::Iter<'a, T> {
+        self.set.iter()
+    }
+
+    /// Clone the viewed set.
+    pub fn to_set(&self) -> Set<T>
+    where
+        T: Clone,
+    {
+        Set {
+            x: self.set.clone(),
+        }
+    }
+}
+
+impl<'a, T> Deref for SetView<'a, T> {
+    type Target = BTreeSet<T>;
+
+    fn deref(&self) -> &Self::Target {
+        self.set
+    }
+}
+
+impl<'a, T: Ord> From<&'a Set<T>> for SetView<'a, T> {
+    fn from(set: &'a Set<T>) -> Self {
+        SetView { set: &set.x }
+    }
+}
+
+impl<'a, T> IntoIterator for SetView<'a, T> {
+    type Item = &'a T;
+    type IntoIter = btree_set::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.set.iter()
+    }
+}
+
+impl<'a, T: Debug> Debug for SetView<'a, T> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_set().entries(self.set.iter()).finish()
+    }
+}
+
+impl<T: Ord> Set<T> {
+    pub fn view(&self) -> SetView<'_, T> {
+        SetView::from(self)
+    }
+}
+
+/// Borrowed, read-only view of a DDlog `Map` (see [`VecView`]).
+#[derive(Eq, Ord, Hash, PartialEq, PartialOrd)]
+pub struct MapView<'a, K, V> {
+    map: &'a BTreeMap<K, V>,
+}
+
+impl<'a, K, V> Clone for MapView<'a, K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, K, V> Copy for MapView<'a, K, V> {}
+
+impl<'a, K: Ord, V> MapView<'a, K, V> {
+    pub fn as_btree_map(&self) -> &'a BTreeMap<K, V> {
+        self.map
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'a, K, V> {
+        self.map.iter()
+    }
+
+    /// Look up `key` without cloning the value.
+    pub fn get(&self, key: &K) -> StdOption<&'a V> {
+        self.map.get(key)
+    }
+
+    /// Clone the viewed map.
+    pub fn to_map(&self) -> Map<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        Map {
+            x: self.map.clone(),
+        }
+    }
+}
+
+impl<'a, K, V> Deref for MapView<'a, K, V> {
+    type Target = BTreeMap<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        self.map
+    }
+}
+
+impl<'a, K: Ord, V> From<&'a Map<K, V>> for MapView<'a, K, V> {
+    fn from(map: &'a Map<K, V>) -> Self {
+        MapView { map: &map.x }
+    }
+}
+
+impl<'a, K, V> IntoIterator for MapView<'a, K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = btree_map::Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.map.iter()
+    }
+}
+
+impl<'a, K: Debug, V: Debug> Debug for MapView<'a, K, V> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_map().entries(self.map.iter()).finish()
+    }
+}
+
+impl<K: Ord, V> Map<K, V> {
+    pub fn view(&self) -> MapView<'_, K, V> {
+        MapView::from(self)
+    }
+}
+
 // Hashing
 
 pub fn hash64<T: Hash>(x: &T) -> u64 {
@@ -1308,6 +2215,19 @@ impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for Group<K
     }
 }
 
+/* Group iterators.
+ *
+ * Extern functions receive groups by reference (`&Group<K, V>`) and should
+ * consume them through `iter()` (values with weights), `val_iter()` (values
+ * only), or the for-loop `IntoIterator` implementation rather than by
+ * collecting the group into a vector.  Iterators over groups created by
+ * `group_by` are lazy: each element is projected out of the underlying
+ * arrangement only when the iterator reaches it, so an extern function can
+ * stop early (e.g., `any`) or keep a bounded amount of state (e.g.,
+ * `group_top_k`) without cloning the entire group.  Skipping elements with
+ * `nth()`, `skip()`, or `count()` does not project the skipped elements.
+ */
+
 /* The iterator used to implement for-loops over `Group`'s. */
 pub enum GroupIter<'a, V> {
     ByRef {
@@ -1353,8 +2273,40 @@ impl<'a, V: Clone> Iterator for GroupIter<'a, V> {
             GroupIter::ByVal { iter } => iter.size_hint(),
         }
     }
+
+    fn nth(&mut self, n: usize) -> StdOption<Self::Item> {
+        match self {
+            GroupIter::ByRef { iter, project } => {
+                iter.nth(n).map(|(x, w)| tuple2(project(x), *w as DDWeight))
+            }
+            GroupIter::ByVal { iter } => iter.nth(n).cloned(),
+        }
+    }
+
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    fn last(mut self) -> StdOption<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl<'a, V: Clone> DoubleEndedIterator for GroupIter<'a, V> {
+    fn next_back(&mut self) -> StdOption<Self::Item> {
+        match self {
+            GroupIter::ByRef { iter, project } => iter
+                .next_back()
+                .map(|(x, w)| tuple2(project(x), *w as DDWeight)),
+            GroupIter::ByVal { iter } => iter.next_back().cloned(),
+        }
+    }
 }
 
+impl<'a, V: Clone> ExactSizeIterator for GroupIter<'a, V> {}
+
+impl<'a, V: Clone> FusedIterator for GroupIter<'a, V> {}
+
 /* Iterator over group values ignoring element weights. */
 pub enum GroupValIter<'a, V> {
     ByRef {
@@ -1400,8 +2352,36 @@ impl<'a, V: Clone> Iterator for GroupValIter<'a, V> {
             GroupValIter::ByVal { iter } => iter.size_hint(),
         }
     }
+
+    fn nth(&mut self, n: usize) -> StdOption<Self::Item> {
+        match self {
+            GroupValIter::ByRef { iter, project } => iter.nth(n).map(|(x, _)| project(x)),
+            GroupValIter::ByVal { iter } => iter.nth(n).map(|x| x.0.clone()),
+        }
+    }
+
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    fn last(mut self) -> StdOption<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl<'a, V: Clone> DoubleEndedIterator for GroupValIter<'a, V> {
+    fn next_back(&mut self) -> StdOption<Self::Item> {
+        match self {
+            GroupValIter::ByRef { iter, project } => iter.next_back().map(|(x, _)| project(x)),
+            GroupValIter::ByVal { iter } => iter.next_back().map(|x| x.0.clone()),
+        }
+    }
 }
 
+impl<'a, V: Clone> ExactSizeIterator for GroupValIter<'a, V> {}
+
+impl<'a, V: Clone> FusedIterator for GroupValIter<'a, V> {}
+
 /* The iterator used to implement FlatMap over `Group`'s. */
 pub enum GroupIntoIter<V> {
     ByRef {
@@ -1449,8 +2429,40 @@ impl<V: Clone> Iterator for GroupIntoIter<V> {
             GroupIntoIter::ByVal { iter } => iter.size_hint(),
         }
     }
+
+    fn nth(&mut self, n: usize) -> StdOption<Self::Item> {
+        match self {
+            GroupIntoIter::ByRef { iter, project } => {
+                iter.nth(n).map(|(x, w)| tuple2(project(x), *w as DDWeight))
+            }
+            GroupIntoIter::ByVal { iter } => iter.nth(n),
+        }
+    }
+
+    fn count(self) -> usize {
+        self.len()
+    }
+
+    fn last(mut self) -> StdOption<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl<V: Clone> DoubleEndedIterator for GroupIntoIter<V> {
+    fn next_back(&mut self) -> StdOption<Self::Item> {
+        match self {
+            GroupIntoIter::ByRef { iter, project } => iter
+                .next_back()
+                .map(|(x, w)| tuple2(project(x), *w as DDWeight)),
+            GroupIntoIter::ByVal { iter } => iter.next_back(),
+        }
+    }
 }
 
+impl<V: Clone> ExactSizeIterator for GroupIntoIter<V> {}
+
+impl<V: Clone> FusedIterator for GroupIntoIter<V> {}
+
 impl<K, V> Group<K, V> {
     /* Unsafe constructor for use in auto-generated code only. */
     pub unsafe fn new_by_ref<'a>(
@@ -1482,6 +2494,13 @@ impl<K, V> Group<K, V> {
             GroupEnum::ByVal { group, .. } => group.len() as std_usize,
         }
     }
+
+    fn weight(&self) -> DDWeight {
+        match self {
+            GroupEnum::ByRef { group, .. } => group.iter().map(|(_, w)| *w as DDWeight).sum(),
+            GroupEnum::ByVal { group, .. } => group.iter().map(|tuple2(_, w)| *w).sum(),
+        }
+    }
 }
 
 impl<K: Clone, V> Group<K, V> {
@@ -1563,6 +2582,10 @@ pub fn group_count<K, V>(g: &Group<K, V>) -> std_usize {
     g.size()
 }
 
+pub fn group_weight<K, V>(g: &Group<K, V>) -> DDWeight {
+    g.weight()
+}
+
 pub fn group_first<K, V: Clone>(g: &Group<K, V>) -> V {
     g.first()
 }
@@ -1615,6 +2638,14 @@ pub fn group_to_vec<K, V: Ord + Clone>(g: &Group<K, V>) -> Vec<V> {
     res
 }
 
+pub fn group_to_smallvec<K, V: Clone>(g: &Group<K, V>) -> SmallVec<V> {
+    g.val_iter().collect()
+}
+
+pub fn group_to_smallset<K, V: Ord + Clone>(g: &Group<K, V>) -> SmallSet<V> {
+    g.val_iter().collect()
+}
+
 pub fn group_to_map<K1, K2: Ord + Clone, V: Clone>(g: &Group<K1, tuple2<K2, V>>) -> Map<K2, V> {
     let mut res = Map::new();
     for tuple2(k, v) in g.val_iter() {
@@ -1649,6 +2680,39 @@ pub fn group_max<K, V: Clone + Ord>(g: &Group<K, V>) -> V {
     g.val_iter().max().unwrap()
 }
 
+pub fn group_top_k<K, V: Clone + Ord>(g: &Group<K, V>, k: &std_usize) -> Vec<V> {
+    /* Min-heap of the `k` largest elements seen so far. */
+    let mut heap = BinaryHeap::with_capacity(cmp::min(*k as usize, g.size() as usize));
+    for v in g.val_iter() {
+        if heap.len() < *k as usize {
+            heap.push(cmp::Reverse(v));
+        } else if let StdOption::Some(mut smallest) = heap.peek_mut() {
+            if v > smallest.0 {
+                *smallest = cmp::Reverse(v);
+            }
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|cmp::Reverse(v)| v)
+        .collect()
+}
+
+pub fn group_bottom_k<K, V: Clone + Ord>(g: &Group<K, V>, k: &std_usize) -> Vec<V> {
+    /* Max-heap of the `k` smallest elements seen so far. */
+    let mut heap = BinaryHeap::with_capacity(cmp::min(*k as usize, g.size() as usize));
+    for v in g.val_iter() {
+        if heap.len() < *k as usize {
+            heap.push(v);
+        } else if let StdOption::Some(mut largest) = heap.peek_mut() {
+            if v < *largest {
+                *largest = v;
+            }
+        }
+    }
+    heap.into_sorted_vec().into()
+}
+
 pub fn group_sum<K, V: Clone + ops::Add<Output = V>>(g: &Group<K, V>) -> V {
     let mut res = group_first(g);
     for v in g.val_iter().skip(1) {
@@ -1660,6 +2724,7 @@ pub fn group_sum<K, V: Clone + ops::Add<Output = V>>(g: &Group<K, V>) -> V {
 
 /* Tuples */
 #[derive(Copy, Eq, Ord, Clone, Hash, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[cfg_attr(feature = "proptest", derive(::ddlog_derive::Arbitrary))]
 pub struct tuple0;
 
 impl Debug for tuple0 {
@@ -1701,6 +2766,7 @@ macro_rules! declare_tuples {
     ) => {
         $(
             #[derive(Default, Eq, Ord, Clone, Hash, PartialEq, PartialOrd, Serialize, Deserialize)]
+            #[cfg_attr(feature = "proptest", derive(::ddlog_derive::Arbitrary))]
             pub struct $tuple_name<$($element,)*>($(pub $element,)*);
 
             impl<$($element),*> From<($($element,)*)> for $tuple_name<$($element,)*> {
@@ -1787,6 +2853,89 @@ declare_tuples! {
     tuple30<T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, T16, T17, T18, T19, T20, T21, T22, T23, T24, T25, T26, T27, T28, T29, T30>,
 }
 
+// Property-based testing
+
+/// `Arbitrary` implementations for the collection types declared in this
+/// module.  Generated collections are kept small, so that random facts stay
+/// readable when a test fails.
+#[cfg(feature = "proptest")]
+mod arbitrary {
+    use super::{Map, Ref, Set, SmallSet, SmallVec, Vec};
+    use differential_datalog::proptest::{
+        arbitrary::{any, Arbitrary},
+        collection,
+        strategy::{BoxedStrategy, Strategy},
+    };
+    use std::ops::Range;
+
+    /// Number of elements in generated collections.
+    const SIZE: Range<usize> = 0..8;
+
+    impl<T: Arbitrary + 'static> Arbitrary for Ref<T> {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_args: ()) -> Self::Strategy {
+            any::<T>().prop_map(Ref::from).boxed()
+        }
+    }
+
+    impl<T: Arbitrary + 'static> Arbitrary for Vec<T> {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_args: ()) -> Self::Strategy {
+            collection::vec(any::<T>(), SIZE)
+                .prop_map(Vec::from)
+                .boxed()
+        }
+    }
+
+    impl<T: Arbitrary + 'static> Arbitrary for SmallVec<T> {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_args: ()) -> Self::Strategy {
+            collection::vec(any::<T>(), SIZE)
+                .prop_map(SmallVec::from)
+                .boxed()
+        }
+    }
+
+    impl<T: Arbitrary + Ord + 'static> Arbitrary for Set<T> {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_args: ()) -> Self::Strategy {
+            collection::btree_set(any::<T>(), SIZE)
+                .prop_map(|x| Set { x })
+                .boxed()
+        }
+    }
+
+    impl<T: Arbitrary + Ord + 'static> Arbitrary for SmallSet<T> {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_args: ()) -> Self::Strategy {
+            collection::vec(any::<T>(), SIZE)
+                .prop_map(|v| v.into_iter().collect())
+                .boxed()
+        }
+    }
+
+    impl<K: Arbitrary + Ord + 'static, V: Arbitrary + 'static> Arbitrary for Map<K, V> {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_args: ()) -> Self::Strategy {
+            collection::btree_map(any::<K>(), any::<V>(), SIZE)
+                .prop_map(|x| Map { x })
+                .boxed()
+        }
+    }
+}
+
 // Endianness
 pub fn ntohl(x: &u32) -> u32 {
     u32::from_be(*x)
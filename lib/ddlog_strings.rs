@@ -0,0 +1,133 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::Vec as DDlogVec;
+use ordered_float::OrderedFloat;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+pub fn case_fold(s: &String) -> String {
+    caseless::default_case_fold_str(s)
+}
+
+pub fn nfc(s: &String) -> String {
+    s.nfc().collect()
+}
+
+pub fn nfd(s: &String) -> String {
+    s.nfd().collect()
+}
+
+pub fn nfkc(s: &String) -> String {
+    s.nfkc().collect()
+}
+
+pub fn nfkd(s: &String) -> String {
+    s.nfkd().collect()
+}
+
+pub fn grapheme_len(s: &String) -> std_usize {
+    s.graphemes(true).count() as std_usize
+}
+
+pub fn graphemes(s: &String) -> DDlogVec<String> {
+    s.graphemes(true).map(|g| g.to_string()).collect()
+}
+
+pub fn grapheme_substr(s: &String, start: &std_usize, end: &std_usize) -> String {
+    if end <= start {
+        return String::new();
+    }
+
+    s.graphemes(true)
+        .skip(*start as usize)
+        .take((*end - *start) as usize)
+        .collect()
+}
+
+pub fn levenshtein(s1: &String, s2: &String) -> std_usize {
+    strsim::levenshtein(s1, s2) as std_usize
+}
+
+pub fn jaro(s1: &String, s2: &String) -> OrderedFloat<f64> {
+    OrderedFloat(strsim::jaro(s1, s2))
+}
+
+pub fn jaro_winkler(s1: &String, s2: &String) -> OrderedFloat<f64> {
+    OrderedFloat(strsim::jaro_winkler(s1, s2))
+}
+
+/// Soundex digit for an uppercase ASCII letter; `None` for vowels and
+/// `H`, `W`, `Y`.
+fn soundex_code(c: char) -> Option<char> {
+    match c {
+        'B' | 'F' | 'P' | 'V' => Some('1'),
+        'C' | 'G' | 'J' | 'K' | 'Q' | 'S' | 'X' | 'Z' => Some('2'),
+        'D' | 'T' => Some('3'),
+        'L' => Some('4'),
+        'M' | 'N' => Some('5'),
+        'R' => Some('6'),
+        _ => None,
+    }
+}
+
+pub fn soundex(s: &String) -> String {
+    let mut letters = s
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_uppercase());
+
+    let first = match letters.next() {
+        Some(c) => c,
+        None => return String::new(),
+    };
+
+    let mut code = String::with_capacity(4);
+    code.push(first);
+
+    let mut last = soundex_code(first);
+    for c in letters {
+        if code.len() == 4 {
+            break;
+        }
+
+        let digit = soundex_code(c);
+        if let Some(d) = digit {
+            if digit != last {
+                code.push(d);
+            }
+        }
+
+        // Consonants separated by 'H' or 'W' are coded as a single digit;
+        // vowels reset the previous code.
+        if c != 'H' && c != 'W' {
+            last = digit;
+        }
+    }
+
+    while code.len() < 4 {
+        code.push('0');
+    }
+
+    code
+}
@@ -22,6 +22,7 @@ SOFTWARE.
 */
 
 use ddlog_bigint::*;
+use ddlog_std::IntoDDlog;
 use num::bigint::BigInt;
 use num::traits::FromPrimitive;
 use ordered_float::OrderedFloat;
@@ -341,41 +342,23 @@ pub fn nan_d() -> OrderedFloat<f64> {
 }
 
 pub fn int_from_f(v: &OrderedFloat<f32>) -> ddlog_std::Option<Int> {
-    match (BigInt::from_f32(**v)) {
-        None => ddlog_std::Option::None,
-        Some(x) => ddlog_std::Option::Some {
-            x: Int::from_bigint(x),
-        },
-    }
+    BigInt::from_f32(**v).map(Int::from_bigint).into_ddlog()
 }
 
 pub fn int_from_d(v: &OrderedFloat<f64>) -> ddlog_std::Option<Int> {
-    match (BigInt::from_f64(**v)) {
-        None => ddlog_std::Option::None,
-        Some(x) => ddlog_std::Option::Some {
-            x: Int::from_bigint(x),
-        },
-    }
+    BigInt::from_f64(**v).map(Int::from_bigint).into_ddlog()
 }
 
 pub fn parse_f(s: &String) -> ddlog_std::Result<OrderedFloat<f32>, String> {
-    match (s.parse::<f32>()) {
-        Ok(res) => ddlog_std::Result::Ok {
-            res: OrderedFloat::<f32>(res),
-        },
-        Err(err) => ddlog_std::Result::Err {
-            err: format!("{}", err),
-        },
-    }
+    s.parse::<f32>()
+        .map(OrderedFloat::<f32>)
+        .map_err(|err| format!("{}", err))
+        .into_ddlog()
 }
 
 pub fn parse_d(s: &String) -> ddlog_std::Result<OrderedFloat<f64>, String> {
-    match (s.parse::<f64>()) {
-        Ok(res) => ddlog_std::Result::Ok {
-            res: OrderedFloat::<f64>(res),
-        },
-        Err(err) => ddlog_std::Result::Err {
-            err: format!("{}", err),
-        },
-    }
+    s.parse::<f64>()
+        .map(OrderedFloat::<f64>)
+        .map_err(|err| format!("{}", err))
+        .into_ddlog()
 }
@@ -27,8 +27,14 @@ use differential_dataflow::algorithms::graphs::propagate;
 use differential_dataflow::algorithms::graphs::scc;
 use differential_dataflow::collection::Collection;
 use differential_dataflow::lattice::Lattice;
+use differential_dataflow::operators::arrange::ArrangeByKey;
 use differential_dataflow::operators::consolidate::Consolidate;
+use differential_dataflow::operators::iterate::Iterate;
+use differential_dataflow::operators::JoinCore;
+use differential_dataflow::operators::Reduce;
 use differential_dataflow::operators::ThresholdTotal;
+use differential_datalog::program::diff_distinct;
+use std::collections::HashMap;
 use std::mem;
 use timely::dataflow::scopes::Scope;
 use timely::order::TotalOrder;
@@ -158,3 +164,375 @@ where
     let bidirectional = bidirectional.concat(&bidirectional.map(|(x, y)| (y.clone(), x.clone())));
     bidirectional.map(move |(n1, n2)| _biedges(ddlog_std::tuple2(n1, n2)))
 }
+
+/* Extend each `(root, node)` pair in `seeds` along `pairs` until fixed point.
+ * `pairs` is arranged once, outside of the loop. */
+fn reach_from<S, N>(
+    pairs: &Collection<S, (N, N), Weight>,
+    seeds: &Collection<S, (N, N), Weight>,
+) -> Collection<S, (N, N), Weight>
+where
+    S: Scope,
+    S::Timestamp: Lattice + Ord,
+    N: differential_dataflow::ExchangeData + std::hash::Hash,
+{
+    let by_src = pairs.arrange_by_key();
+    seeds.iterate(|reach| {
+        let by_src = by_src.enter(&reach.scope());
+        let seeds = seeds.enter(&reach.scope());
+        let next = reach
+            .map(|(r, n)| (n, r))
+            .join_core(&by_src, |_, r, m| Some((r.clone(), m.clone())));
+        diff_distinct(&seeds.concat(&next))
+    })
+}
+
+pub fn TransitiveClosure<S, V, E, N, EF, LF>(
+    edges: &Collection<S, V, Weight>,
+    _edges: EF,
+    from: fn(&E) -> N,
+    to: fn(&E) -> N,
+    _reach: LF,
+) -> (Collection<S, V, Weight>)
+where
+    S: Scope,
+    S::Timestamp: Lattice + Ord,
+    V: differential_dataflow::Data,
+    N: differential_dataflow::ExchangeData + std::hash::Hash,
+    E: differential_dataflow::ExchangeData,
+    EF: Fn(V) -> E + 'static,
+    LF: Fn(ddlog_std::tuple2<N, N>) -> V + 'static,
+{
+    let pairs = edges.map(move |v| {
+        let e = _edges(v);
+        (from(&e), to(&e))
+    });
+
+    /* Every edge is a path of length 1 */
+    let reach = reach_from(&pairs, &pairs);
+    reach.map(move |(x, y)| _reach(ddlog_std::tuple2(x, y)))
+}
+
+pub fn Reachable<S, V, E, N, R, EF, RF, LF>(
+    edges: &Collection<S, V, Weight>,
+    _edges: EF,
+    from: fn(&E) -> N,
+    to: fn(&E) -> N,
+    roots: &Collection<S, V, Weight>,
+    _roots: RF,
+    root: fn(&R) -> N,
+    _reached: LF,
+) -> (Collection<S, V, Weight>)
+where
+    S: Scope,
+    S::Timestamp: Lattice + Ord,
+    V: differential_dataflow::Data,
+    N: differential_dataflow::ExchangeData + std::hash::Hash,
+    E: differential_dataflow::ExchangeData,
+    R: differential_dataflow::ExchangeData,
+    EF: Fn(V) -> E + 'static,
+    RF: Fn(V) -> R + 'static,
+    LF: Fn(ddlog_std::tuple2<N, N>) -> V + 'static,
+{
+    let pairs = edges.map(move |v| {
+        let e = _edges(v);
+        (from(&e), to(&e))
+    });
+
+    /* Each root is reachable from itself */
+    let seeds = roots.map(move |v| {
+        let n = root(&_roots(v));
+        (n.clone(), n)
+    });
+    let reached = reach_from(&pairs, &seeds);
+    reached.map(move |(r, n)| _reached(ddlog_std::tuple2(r, n)))
+}
+
+pub fn ShortestPathLengths<S, V, E, N, R, EF, RF, LF>(
+    edges: &Collection<S, V, Weight>,
+    _edges: EF,
+    from: fn(&E) -> N,
+    to: fn(&E) -> N,
+    roots: &Collection<S, V, Weight>,
+    _roots: RF,
+    root: fn(&R) -> N,
+    _distances: LF,
+) -> (Collection<S, V, Weight>)
+where
+    S: Scope,
+    S::Timestamp: Lattice + Ord,
+    V: differential_dataflow::Data,
+    N: differential_dataflow::ExchangeData + std::hash::Hash,
+    E: differential_dataflow::ExchangeData,
+    R: differential_dataflow::ExchangeData,
+    EF: Fn(V) -> E + 'static,
+    RF: Fn(V) -> R + 'static,
+    LF: Fn(ddlog_std::tuple3<N, N, u64>) -> V + 'static,
+{
+    let by_src = edges
+        .map(move |v| {
+            let e = _edges(v);
+            (from(&e), to(&e))
+        })
+        .arrange_by_key();
+
+    let seeds = roots.map(move |v| {
+        let n = root(&_roots(v));
+        ((n.clone(), n), 0)
+    });
+
+    /* Relax distances along edges, retaining the smallest distance
+     * for each `(root, node)` pair. */
+    let distances = seeds.iterate(|dists| {
+        let by_src = by_src.enter(&dists.scope());
+        let seeds = seeds.enter(&dists.scope());
+        dists
+            .map(|((r, n), d)| (n, (r, d)))
+            .join_core(&by_src, |_, (r, d), m| {
+                Some(((r.clone(), m.clone()), d + 1))
+            })
+            .concat(&seeds)
+            .reduce(|_, input, output| output.push((*input[0].0, 1)))
+    });
+    distances.map(move |((r, n), d)| _distances(ddlog_std::tuple3(r, n, d)))
+}
+
+/* Union-find forest with path compression.  The root of each set is its
+ * smallest element. */
+struct UnionFind<N> {
+    index: HashMap<N, usize>,
+    nodes: Vec<N>,
+    parents: Vec<usize>,
+}
+
+impl<N: Clone + Ord + std::hash::Hash> UnionFind<N> {
+    fn new() -> Self {
+        UnionFind {
+            index: HashMap::new(),
+            nodes: Vec::new(),
+            parents: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, n: &N) -> usize {
+        if let Some(i) = self.index.get(n) {
+            return *i;
+        }
+        let i = self.nodes.len();
+        self.index.insert(n.clone(), i);
+        self.nodes.push(n.clone());
+        self.parents.push(i);
+        i
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        let mut root = i;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        while self.parents[i] != root {
+            let next = self.parents[i];
+            self.parents[i] = root;
+            i = next;
+        }
+        root
+    }
+
+    fn union(&mut self, x: &N, y: &N) {
+        let xi = self.insert(x);
+        let yi = self.insert(y);
+        let xr = self.find(xi);
+        let yr = self.find(yi);
+        if xr != yr {
+            if self.nodes[xr] < self.nodes[yr] {
+                self.parents[yr] = xr;
+            } else {
+                self.parents[xr] = yr;
+            }
+        }
+    }
+}
+
+pub fn UnionFindComponents<S, V, E, N, EF, LF>(
+    edges: &Collection<S, V, Weight>,
+    _edges: EF,
+    from: fn(&E) -> N,
+    to: fn(&E) -> N,
+    _cclabels: LF,
+) -> (Collection<S, V, Weight>)
+where
+    S: Scope,
+    S::Timestamp: Lattice + Ord,
+    V: differential_dataflow::Data,
+    N: differential_dataflow::ExchangeData + std::hash::Hash,
+    E: differential_dataflow::ExchangeData,
+    EF: Fn(V) -> E + 'static,
+    LF: Fn(ddlog_std::tuple2<N, N>) -> V + 'static,
+{
+    let pairs = edges.map(move |v| {
+        let e = _edges(v);
+        ((), (from(&e), to(&e)))
+    });
+
+    /* `reduce` hands us the complete current set of edges whenever it
+     * changes, so we rebuild the forest and let differential dataflow
+     * compute the difference between old and new labels. */
+    let labels = pairs.reduce(|_, input, output| {
+        let mut uf = UnionFind::new();
+        for ((x, y), w) in input.iter() {
+            if *w > 0 {
+                uf.union(x, y);
+            }
+        }
+        let mut labels = Vec::with_capacity(uf.nodes.len());
+        for i in 0..uf.nodes.len() {
+            let root = uf.find(i);
+            labels.push((uf.nodes[i].clone(), uf.nodes[root].clone()));
+        }
+        output.extend(labels.into_iter().map(|l| (l, 1)));
+    });
+    labels.map(move |(_, (n, l))| _cclabels(ddlog_std::tuple2(n, l)))
+}
+
+pub fn WeightedShortestPaths<S, V, E, N, W, R, EF, RF, LF>(
+    edges: &Collection<S, V, Weight>,
+    _edges: EF,
+    from: fn(&E) -> N,
+    to: fn(&E) -> N,
+    weight: fn(&E) -> W,
+    roots: &Collection<S, V, Weight>,
+    _roots: RF,
+    root: fn(&R) -> N,
+    _paths: LF,
+) -> (Collection<S, V, Weight>)
+where
+    S: Scope,
+    S::Timestamp: Lattice + Ord,
+    V: differential_dataflow::Data,
+    N: differential_dataflow::ExchangeData + std::hash::Hash,
+    W: differential_dataflow::ExchangeData + Default + std::ops::Add<Output = W>,
+    E: differential_dataflow::ExchangeData,
+    R: differential_dataflow::ExchangeData,
+    EF: Fn(V) -> E + 'static,
+    RF: Fn(V) -> R + 'static,
+    LF: Fn(ddlog_std::tuple4<N, N, W, N>) -> V + 'static,
+{
+    let by_src = edges
+        .map(move |v| {
+            let e = _edges(v);
+            (from(&e), (to(&e), weight(&e)))
+        })
+        .arrange_by_key();
+
+    /* Each `(root, node)` pair is labeled with `(distance, hops, predecessor)`. */
+    let seeds = roots.map(move |v| {
+        let n = root(&_roots(v));
+        ((n.clone(), n.clone()), (W::default(), 0u64, n))
+    });
+
+    /* Relax edges, retaining the lexicographically smallest label for each
+     * `(root, node)` pair. */
+    let paths = seeds.iterate(|paths| {
+        let by_src = by_src.enter(&paths.scope());
+        let seeds = seeds.enter(&paths.scope());
+        paths
+            .map(|((r, n), (d, h, _))| (n, (r, d, h)))
+            .join_core(&by_src, |n, (r, d, h), (m, w)| {
+                Some((
+                    (r.clone(), m.clone()),
+                    (d.clone() + w.clone(), h + 1, n.clone()),
+                ))
+            })
+            .concat(&seeds)
+            .reduce(|_, input, output| output.push((input[0].0.clone(), 1)))
+    });
+    paths.map(move |((r, n), (d, _, p))| _paths(ddlog_std::tuple4(r, n, d, p)))
+}
+
+pub fn TopologicalOrder<S, V, E, N, EF, LF>(
+    edges: &Collection<S, V, Weight>,
+    _edges: EF,
+    from: fn(&E) -> N,
+    to: fn(&E) -> N,
+    _order: LF,
+) -> (Collection<S, V, Weight>)
+where
+    S: Scope,
+    S::Timestamp: Lattice + Ord,
+    V: differential_dataflow::Data,
+    N: differential_dataflow::ExchangeData + std::hash::Hash,
+    E: differential_dataflow::ExchangeData,
+    EF: Fn(V) -> E + 'static,
+    LF: Fn(ddlog_std::tuple2<N, u64>) -> V + 'static,
+{
+    let pairs = edges.map(move |v| {
+        let e = _edges(v);
+        ((), (from(&e), to(&e)))
+    });
+
+    /* Kahn's algorithm over the complete current set of edges. */
+    let order = pairs.reduce(|_, input, output| {
+        let mut index: HashMap<&N, usize> = HashMap::new();
+        let mut nodes: Vec<&N> = Vec::new();
+        let mut arcs: Vec<(usize, usize)> = Vec::with_capacity(input.len());
+        for ((x, y), w) in input.iter() {
+            if *w > 0 {
+                let mut idx = |n| {
+                    *index.entry(n).or_insert_with(|| {
+                        nodes.push(n);
+                        nodes.len() - 1
+                    })
+                };
+                arcs.push((idx(x), idx(y)));
+            }
+        }
+        let mut succs: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+        let mut indegree: Vec<usize> = vec![0; nodes.len()];
+        for (x, y) in arcs.into_iter() {
+            succs[x].push(y);
+            indegree[y] += 1;
+        }
+        let mut ranks: Vec<u64> = vec![0; nodes.len()];
+        let mut ready: Vec<usize> = (0..nodes.len()).filter(|i| indegree[*i] == 0).collect();
+        while let Some(i) = ready.pop() {
+            output.push(((nodes[i].clone(), ranks[i]), 1));
+            for j in succs[i].iter() {
+                ranks[*j] = std::cmp::max(ranks[*j], ranks[i] + 1);
+                indegree[*j] -= 1;
+                if indegree[*j] == 0 {
+                    ready.push(*j);
+                }
+            }
+        }
+    });
+    order.map(move |(_, (n, rank))| _order(ddlog_std::tuple2(n, rank)))
+}
+
+pub fn DependencyCycles<S, V, E, N, EF, LF>(
+    edges: &Collection<S, V, Weight>,
+    _edges: EF,
+    from: fn(&E) -> N,
+    to: fn(&E) -> N,
+    _cycle_edges: LF,
+) -> (Collection<S, V, Weight>)
+where
+    S: Scope,
+    S::Timestamp: Lattice + Ord,
+    V: differential_dataflow::Data,
+    N: differential_dataflow::ExchangeData + std::hash::Hash,
+    E: differential_dataflow::ExchangeData,
+    EF: Fn(V) -> E + 'static,
+    LF: Fn(ddlog_std::tuple2<N, N>) -> V + 'static,
+{
+    let pairs = edges.map(move |v| {
+        let e = _edges(v);
+        (from(&e), to(&e))
+    });
+
+    /* Recursively trim nodes without incoming and outgoing edges */
+    let trimmed = scc::trim(&scc::trim(&pairs).map_in_place(|x| mem::swap(&mut x.0, &mut x.1)))
+        .map_in_place(|x| mem::swap(&mut x.0, &mut x.1));
+    /* Edges that form cycles */
+    let cycles = diff_distinct(&scc::strongly_connected(&trimmed));
+    cycles.map(move |(x, y)| _cycle_edges(ddlog_std::tuple2(x, y)))
+}
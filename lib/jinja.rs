@@ -0,0 +1,78 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::Result as DDlogResult;
+use lru::LruCache;
+use minijinja::{Environment, UndefinedBehavior};
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use types__json::JsonValue;
+
+/// Maximal number of compiled templates kept in `TEMPLATE_CACHE`.
+const TEMPLATE_CACHE_CAPACITY: usize = 256;
+
+/// Name of the only template in each cached environment.
+const TEMPLATE_NAME: &str = "template";
+
+/// Compiled templates keyed by template text.  Each template is compiled
+/// into its own environment, which owns the template source.
+static TEMPLATE_CACHE: Lazy<Mutex<LruCache<String, Arc<Environment<'static>>>>> =
+    Lazy::new(|| Mutex::new(LruCache::new(TEMPLATE_CACHE_CAPACITY)));
+
+fn compile(template: &str) -> Result<Arc<Environment<'static>>, String> {
+    if let Some(env) = TEMPLATE_CACHE.lock().unwrap().get(template) {
+        return Ok(env.clone());
+    }
+
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.add_template_owned(TEMPLATE_NAME, template.to_string())
+        .map_err(|e| format!("invalid template: {}", e))?;
+    let env = Arc::new(env);
+    TEMPLATE_CACHE
+        .lock()
+        .unwrap()
+        .put(template.to_string(), env.clone());
+    Ok(env)
+}
+
+fn render(template: &str, vars: &JsonValue) -> Result<String, String> {
+    let vars = match Value::from(vars.clone()) {
+        Value::Null => Value::Object(Default::default()),
+        vars @ Value::Object(_) => vars,
+        _ => return Err("template variables must be a JSON object".to_string()),
+    };
+    compile(template)?
+        .get_template(TEMPLATE_NAME)
+        .and_then(|t| t.render(vars))
+        .map_err(|e| format!("failed to render template: {}", e))
+}
+
+pub fn jinja_render(template: &String, vars: &JsonValue) -> DDlogResult<String, String> {
+    render(template, vars).into()
+}
+
+pub fn jinja_check(template: &String) -> DDlogResult<(), String> {
+    compile(template).map(|_| ()).into()
+}
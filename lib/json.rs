@@ -47,6 +47,55 @@ pub fn to_json_value<T: serde::Serialize>(x: &T) -> ddlog_std::Result<JsonValue,
     res2std(serde_json::to_value(x.clone()).map(JsonValue::from))
 }
 
+pub fn yaml_parse(yaml: &String) -> ddlog_std::Result<JsonValue, String> {
+    res2std(serde_yaml::from_str::<serde_json::value::Value>(yaml).map(JsonValue::from))
+}
+
+pub fn yaml_parse_all(yaml: &String) -> ddlog_std::Result<ddlog_std::Vec<JsonValue>, String> {
+    res2std(
+        serde_yaml::Deserializer::from_str(yaml)
+            .map(|doc| serde_json::value::Value::deserialize(doc).map(JsonValue::from))
+            .collect::<Result<ddlog_std::Vec<JsonValue>, _>>(),
+    )
+}
+
+pub fn toml_parse(toml: &String) -> ddlog_std::Result<JsonValue, String> {
+    res2std(toml::from_str::<toml::Value>(toml).map(|v| JsonValue::from(toml2json(v))))
+}
+
+fn toml2json(v: toml::Value) -> serde_json::value::Value {
+    match v {
+        toml::Value::String(s) => serde_json::value::Value::String(s),
+        toml::Value::Integer(i) => serde_json::value::Value::from(i),
+        toml::Value::Float(f) => serde_json::Number::from_f64(f)
+            .map(serde_json::value::Value::Number)
+            .unwrap_or(serde_json::value::Value::Null),
+        toml::Value::Boolean(b) => serde_json::value::Value::Bool(b),
+        toml::Value::Datetime(d) => serde_json::value::Value::String(d.to_string()),
+        toml::Value::Array(a) => {
+            serde_json::value::Value::Array(a.into_iter().map(toml2json).collect())
+        }
+        toml::Value::Table(t) => serde_json::value::Value::Object(
+            t.into_iter().map(|(k, v)| (k, toml2json(v))).collect(),
+        ),
+    }
+}
+
+pub fn json_path(
+    jval: &JsonValue,
+    path: &String,
+) -> ddlog_std::Result<ddlog_std::Vec<JsonValue>, String> {
+    let steps = match json_path::parse(path) {
+        Ok(steps) => steps,
+        Err(e) => return ddlog_std::Result::Err { err: e },
+    };
+    let root = serde_json::value::Value::from(jval.clone());
+    let matches = json_path::eval(&root, &steps);
+    ddlog_std::Result::Ok {
+        res: matches.into_iter().cloned().map(JsonValue::from).collect(),
+    }
+}
+
 pub struct ValueWrapper(serde_json::value::Value);
 
 impl serde::Serialize for ValueWrapper {
@@ -253,3 +302,175 @@ impl<'de, T: DeserializeOwned> Deserialize<'de> for JsonWrapper<T> {
         }
     }
 }
+
+/* A minimal JSONPath evaluator used by `json_path()`. */
+mod json_path {
+    use serde_json::value::Value;
+
+    #[derive(Debug, PartialEq)]
+    pub enum Step {
+        Key(String),
+        Index(i64),
+        Wildcard,
+        RecursiveKey(String),
+        RecursiveWildcard,
+    }
+
+    pub fn parse(path: &str) -> Result<Vec<Step>, String> {
+        let chars: Vec<char> = path.chars().collect();
+        let mut pos = 0;
+        let mut steps = Vec::new();
+
+        if chars.first() == Some(&'$') {
+            pos += 1;
+        } else if !chars.is_empty() && chars[0] != '.' && chars[0] != '[' {
+            // Bare attribute name at the start of the path.
+            let name = parse_name(&chars, &mut pos);
+            steps.push(Step::Key(name));
+        }
+
+        while pos < chars.len() {
+            match chars[pos] {
+                '.' if chars.get(pos + 1) == Some(&'.') => {
+                    pos += 2;
+                    if chars.get(pos) == Some(&'*') {
+                        pos += 1;
+                        steps.push(Step::RecursiveWildcard);
+                    } else {
+                        let name = parse_name(&chars, &mut pos);
+                        if name.is_empty() {
+                            return Err(format!(
+                                "invalid JSON path '{}': expected attribute name at position {}",
+                                path, pos
+                            ));
+                        }
+                        steps.push(Step::RecursiveKey(name));
+                    }
+                }
+                '.' => {
+                    pos += 1;
+                    if chars.get(pos) == Some(&'*') {
+                        pos += 1;
+                        steps.push(Step::Wildcard);
+                    } else {
+                        let name = parse_name(&chars, &mut pos);
+                        if name.is_empty() {
+                            return Err(format!(
+                                "invalid JSON path '{}': expected attribute name at position {}",
+                                path, pos
+                            ));
+                        }
+                        steps.push(Step::Key(name));
+                    }
+                }
+                '[' => {
+                    pos += 1;
+                    let end = match chars[pos..].iter().position(|c| *c == ']') {
+                        Some(end) => pos + end,
+                        None => {
+                            return Err(format!("invalid JSON path '{}': unterminated '['", path))
+                        }
+                    };
+                    let selector: String = chars[pos..end].iter().collect();
+                    let selector = selector.trim();
+                    pos = end + 1;
+
+                    if selector == "*" {
+                        steps.push(Step::Wildcard);
+                    } else if selector.len() >= 2
+                        && ((selector.starts_with('\'') && selector.ends_with('\''))
+                            || (selector.starts_with('"') && selector.ends_with('"')))
+                    {
+                        steps.push(Step::Key(selector[1..selector.len() - 1].to_string()));
+                    } else {
+                        match selector.parse::<i64>() {
+                            Ok(idx) => steps.push(Step::Index(idx)),
+                            Err(_) => {
+                                return Err(format!(
+                                    "invalid JSON path '{}': invalid selector '[{}]'",
+                                    path, selector
+                                ))
+                            }
+                        }
+                    }
+                }
+                c => {
+                    return Err(format!(
+                        "invalid JSON path '{}': unexpected character '{}' at position {}",
+                        path, c, pos
+                    ))
+                }
+            }
+        }
+
+        Ok(steps)
+    }
+
+    fn parse_name(chars: &[char], pos: &mut usize) -> String {
+        let start = *pos;
+        while *pos < chars.len() && chars[*pos] != '.' && chars[*pos] != '[' {
+            *pos += 1;
+        }
+        chars[start..*pos].iter().collect()
+    }
+
+    pub fn eval<'a>(root: &'a Value, steps: &[Step]) -> Vec<&'a Value> {
+        let mut current = vec![root];
+        for step in steps {
+            let mut next = Vec::new();
+            for val in current.into_iter() {
+                match step {
+                    Step::Key(key) => {
+                        if let Some(v) = val.as_object().and_then(|o| o.get(key)) {
+                            next.push(v);
+                        }
+                    }
+                    Step::Index(idx) => {
+                        if let Some(a) = val.as_array() {
+                            let idx = if *idx < 0 {
+                                a.len() as i64 + *idx
+                            } else {
+                                *idx
+                            };
+                            if idx >= 0 {
+                                if let Some(v) = a.get(idx as usize) {
+                                    next.push(v);
+                                }
+                            }
+                        }
+                    }
+                    Step::Wildcard => children(val, &mut next),
+                    Step::RecursiveKey(key) => {
+                        let mut descendants = Vec::new();
+                        self_and_descendants(val, &mut descendants);
+                        for d in descendants.into_iter() {
+                            if let Some(v) = d.as_object().and_then(|o| o.get(key)) {
+                                next.push(v);
+                            }
+                        }
+                    }
+                    Step::RecursiveWildcard => self_and_descendants(val, &mut next),
+                }
+            }
+            current = next;
+        }
+        current
+    }
+
+    fn children<'a>(val: &'a Value, out: &mut Vec<&'a Value>) {
+        match val {
+            Value::Array(a) => out.extend(a.iter()),
+            Value::Object(o) => out.extend(o.values()),
+            _ => (),
+        }
+    }
+
+    fn self_and_descendants<'a>(val: &'a Value, out: &mut Vec<&'a Value>) {
+        out.push(val);
+        let mut children_vals = Vec::new();
+        children(val, &mut children_vals);
+        for child in children_vals.into_iter() {
+            self_and_descendants(child, out);
+        }
+    }
+}
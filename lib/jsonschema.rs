@@ -0,0 +1,124 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::{Result as DDlogResult, Vec as DDlogVec};
+use jsonschema::JSONSchema;
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use types__json::JsonValue;
+
+/// Maximal number of compiled schemas kept in `SCHEMA_CACHE`.
+const SCHEMA_CACHE_CAPACITY: usize = 256;
+
+/// Compiled schemas keyed by the schema text.
+static SCHEMA_CACHE: Lazy<Mutex<LruCache<String, Arc<JSONSchema>>>> =
+    Lazy::new(|| Mutex::new(LruCache::new(SCHEMA_CACHE_CAPACITY)));
+
+/// Look up the compiled schema for `text` in the cache, or compile the
+/// schema returned by `parse`.
+fn compile_text<F>(text: &str, parse: F) -> Result<Arc<JSONSchema>, String>
+where
+    F: FnOnce() -> Result<Value, String>,
+{
+    if let Some(compiled) = SCHEMA_CACHE.lock().unwrap().get(text) {
+        return Ok(compiled.clone());
+    }
+
+    let schema = parse()?;
+    let compiled =
+        Arc::new(JSONSchema::compile(&schema).map_err(|e| format!("invalid JSON schema: {}", e))?);
+    SCHEMA_CACHE
+        .lock()
+        .unwrap()
+        .put(text.to_string(), compiled.clone());
+    Ok(compiled)
+}
+
+fn compile(schema: &JsonValue) -> Result<Arc<JSONSchema>, String> {
+    let schema = Value::from(schema.clone());
+    compile_text(&schema.to_string(), || Ok(schema))
+}
+
+fn validate(schema: &JSONSchema, value: &Value) -> Vec<ValidationError> {
+    match schema.validate(value) {
+        Ok(()) => Vec::new(),
+        Err(errors) => errors
+            .map(|e| ValidationError {
+                instance_path: e.instance_path.to_string(),
+                schema_path: e.schema_path.to_string(),
+                message: e.to_string(),
+            })
+            .collect(),
+    }
+}
+
+pub fn jsonschema_validate(
+    schema: &JsonValue,
+    value: &JsonValue,
+) -> DDlogResult<DDlogVec<ValidationError>, String> {
+    compile(schema)
+        .map(|compiled| DDlogVec::from(validate(&compiled, &Value::from(value.clone()))))
+        .into()
+}
+
+pub fn jsonschema_check(schema: &JsonValue) -> DDlogResult<(), String> {
+    compile(schema).map(|_| ()).into()
+}
+
+/// Validate a record of an input relation annotated with the `json_schema`
+/// attribute against the schema text of the attribute.  Called by the
+/// generated `relval_validate()` function before the record is inserted.
+pub fn validate_relval(schema: &str, relname: &str, val: &DDValue) -> Result<(), String> {
+    let compiled = compile_text(schema, || {
+        serde_json::from_str(schema).map_err(|e| format!("invalid JSON schema: {}", e))
+    })
+    .map_err(|e| format!("relation {}: {}", relname, e))?;
+    let value = serde_json::to_value(val).map_err(|e| {
+        format!(
+            "failed to convert record of relation {} to JSON: {}",
+            relname, e
+        )
+    })?;
+
+    let errors = validate(&compiled, &value);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let details: Vec<String> = errors
+        .iter()
+        .map(|e| {
+            format!(
+                "  at '{}' (schema '{}'): {}",
+                e.instance_path, e.schema_path, e.message
+            )
+        })
+        .collect();
+    Err(format!(
+        "record {} does not conform to the JSON schema of relation {}:\n{}",
+        value,
+        relname,
+        details.join("\n")
+    ))
+}
@@ -0,0 +1,202 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::{tuple2, Group, Result as DDlogResult};
+use differential_datalog::record;
+use rust_decimal::Decimal as InnerDecimal;
+use serde::{
+    de::{Deserializer, Error},
+    ser::Serializer,
+};
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    str::FromStr,
+};
+
+/// Maximal scale supported by `rust_decimal`.
+const MAX_SCALE: u32 = 28;
+
+#[derive(Eq, Ord, Clone, Copy, Hash, PartialEq, PartialOrd, Debug, Default)]
+pub struct Decimal {
+    d: InnerDecimal,
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        Display::fmt(&self.d, f)
+    }
+}
+
+impl Serialize for Decimal {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.d.to_string().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        InnerDecimal::from_str(&s)
+            .map(|d| Decimal { d })
+            .map_err(Error::custom)
+    }
+}
+
+/* Decimals are represented as strings in records to avoid loss of
+ * precision. */
+impl FromRecord for Decimal {
+    fn from_record(val: &record::Record) -> Result<Self, String> {
+        match val {
+            record::Record::String(s) => InnerDecimal::from_str(s)
+                .map(|d| Decimal { d })
+                .map_err(|e| e.to_string()),
+            record::Record::Int(i) => InnerDecimal::from_str(&i.to_string())
+                .map(|d| Decimal { d })
+                .map_err(|e| e.to_string()),
+            error => Err(format!("not a valid decimal: {:?}", error)),
+        }
+    }
+}
+
+impl IntoRecord for Decimal {
+    fn into_record(self) -> record::Record {
+        record::Record::String(self.d.to_string())
+    }
+}
+
+impl record::Mutator<Decimal> for record::Record {
+    fn mutate(&self, d: &mut Decimal) -> Result<(), String> {
+        *d = Decimal::from_record(self)?;
+        Ok(())
+    }
+}
+
+fn checked(
+    d: Option<InnerDecimal>,
+    what: &str,
+    a: &Decimal,
+    b: &Decimal,
+) -> DDlogResult<Decimal, String> {
+    match d {
+        Some(d) => DDlogResult::Ok { res: Decimal { d } },
+        None => DDlogResult::Err {
+            err: format!("decimal {} failed: {}, {}", what, a, b),
+        },
+    }
+}
+
+pub fn decimal_parse(s: &String) -> DDlogResult<Decimal, String> {
+    match InnerDecimal::from_str(s.trim()) {
+        Ok(d) => DDlogResult::Ok { res: Decimal { d } },
+        Err(e) => DDlogResult::Err {
+            err: format!("invalid decimal \"{}\": {}", s, e),
+        },
+    }
+}
+
+pub fn decimal(mantissa: &i64, scale: &u32) -> DDlogResult<Decimal, String> {
+    if *scale > MAX_SCALE {
+        return DDlogResult::Err {
+            err: format!(
+                "decimal scale {} exceeds the maximum of {}",
+                scale, MAX_SCALE
+            ),
+        };
+    }
+    DDlogResult::Ok {
+        res: Decimal {
+            d: InnerDecimal::new(*mantissa, *scale),
+        },
+    }
+}
+
+pub fn decimal_to_string(d: &Decimal) -> String {
+    d.d.to_string()
+}
+
+pub fn decimal_add(a: &Decimal, b: &Decimal) -> DDlogResult<Decimal, String> {
+    checked(a.d.checked_add(b.d), "addition", a, b)
+}
+
+pub fn decimal_sub(a: &Decimal, b: &Decimal) -> DDlogResult<Decimal, String> {
+    checked(a.d.checked_sub(b.d), "subtraction", a, b)
+}
+
+pub fn decimal_mul(a: &Decimal, b: &Decimal) -> DDlogResult<Decimal, String> {
+    checked(a.d.checked_mul(b.d), "multiplication", a, b)
+}
+
+pub fn decimal_div(a: &Decimal, b: &Decimal) -> DDlogResult<Decimal, String> {
+    checked(a.d.checked_div(b.d), "division", a, b)
+}
+
+pub fn decimal_neg(d: &Decimal) -> Decimal {
+    Decimal { d: -d.d }
+}
+
+pub fn decimal_is_zero(d: &Decimal) -> bool {
+    d.d.is_zero()
+}
+
+pub fn decimal_round(d: &Decimal, dp: &u32) -> Decimal {
+    Decimal {
+        d: d.d.round_dp(*dp),
+    }
+}
+
+pub fn is_currency_code(currency: &String) -> bool {
+    currency.len() == 3 && currency.bytes().all(|c| c.is_ascii_uppercase())
+}
+
+pub fn group_money_sum<K>(g: &Group<K, Money>) -> DDlogResult<Money, String> {
+    let currency = g.first().currency;
+    let mut sum = InnerDecimal::ZERO;
+    for tuple2(m, w) in g.iter() {
+        if m.currency != currency {
+            return DDlogResult::Err {
+                err: format!("currency mismatch: {} vs {}", currency, m.currency),
+            };
+        }
+        let total = InnerDecimal::from(w).checked_mul(m.amount.d);
+        match total.and_then(|t| sum.checked_add(t)) {
+            Some(s) => sum = s,
+            None => {
+                return DDlogResult::Err {
+                    err: format!("decimal overflow when summing {} amounts", currency),
+                }
+            }
+        }
+    }
+    DDlogResult::Ok {
+        res: Money {
+            amount: Decimal { d: sum },
+            currency,
+        },
+    }
+}
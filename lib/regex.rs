@@ -21,9 +21,11 @@ OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 SOFTWARE.
 */
 
-use ddlog_std::{Option as DDlogOption, Result as DDlogResult, Vec as DDlogVec};
+use ddlog_std::{Map as DDlogMap, Option as DDlogOption, Result as DDlogResult, Vec as DDlogVec};
 use differential_datalog::record::{CollectionKind, Record};
-use regex::{Error as RegexError, Regex as InnerRegex, RegexSet as InnerRegexSet};
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use regex::{Captures, Error as RegexError, Regex as InnerRegex, RegexSet as InnerRegexSet};
 use serde::{
     de::{Deserializer, Error},
     ser::Serializer,
@@ -33,8 +35,19 @@ use std::{
     fmt::{Display, Formatter, Result as FmtResult},
     hash::{Hash, Hasher},
     iter::{self, IntoIterator},
+    sync::Mutex,
 };
 
+/// Maximal number of compiled patterns kept in `REGEX_CACHE`.
+const REGEX_CACHE_CAPACITY: usize = 1024;
+
+/// Compiled regular expressions keyed by pattern string.  DDlog rules tend to
+/// compile the same handful of patterns over and over again
+/// (e.g., `regex_match(regex("..."), x)`), so we keep recently used regexes
+/// around instead of recompiling them for each record.
+static REGEX_CACHE: Lazy<Mutex<LruCache<String, Regex>>> =
+    Lazy::new(|| Mutex::new(LruCache::new(REGEX_CACHE_CAPACITY)));
+
 #[derive(Debug, Clone)]
 pub struct Regex {
     regex: InnerRegex,
@@ -46,6 +59,22 @@ impl Regex {
             regex: InnerRegex::new(regex)?,
         })
     }
+
+    /// Like `new`, but looks up the pattern in the global regex cache first
+    /// and stores successfully compiled regexes in it.  Cloning a `Regex`
+    /// is cheap, as the compiled program is reference counted.
+    pub fn cached(regex: &str) -> Result<Self, RegexError> {
+        if let Some(re) = REGEX_CACHE.lock().unwrap().get(regex) {
+            return Ok(re.clone());
+        }
+
+        let re = Self::new(regex)?;
+        REGEX_CACHE
+            .lock()
+            .unwrap()
+            .put(regex.to_string(), re.clone());
+        Ok(re)
+    }
 }
 
 impl Display for Regex {
@@ -138,13 +167,13 @@ impl Mutator<Regex> for Record {
 /// If the provided string is not a valid regex, this function will return
 /// a regex that will not match any string
 pub fn regex(regex: &String) -> Regex {
-    Regex::new(regex).unwrap_or_else(|_| Regex::new(r"a^").unwrap())
+    Regex::cached(regex).unwrap_or_else(|_| Regex::cached(r"a^").unwrap())
 }
 
 /// Attempts to create a regex from the given string, returning an error
 /// if it is invalid
 pub fn try_regex(regex: &String) -> DDlogResult<Regex, String> {
-    ddlog_std::res2std(Regex::new(regex))
+    ddlog_std::res2std(Regex::cached(regex))
 }
 
 /// Returns true if the regex matches the given text
@@ -168,6 +197,49 @@ pub fn regex_all_matches(regex: &Regex, text: &String) -> DDlogVec<String> {
         .collect()
 }
 
+fn captures2vec(captures: &Captures) -> DDlogVec<DDlogOption<String>> {
+    captures
+        .iter()
+        .map(|group| group.map(|group| group.as_str().to_string()).into())
+        .collect()
+}
+
+/// Returns capture groups of the leftmost-first match.  The first element
+/// of the vector is the entire match; unmatched groups are `None`.
+pub fn regex_captures(regex: &Regex, text: &String) -> DDlogOption<DDlogVec<DDlogOption<String>>> {
+    regex
+        .captures(&text)
+        .map(|captures| captures2vec(&captures))
+        .into()
+}
+
+/// Returns capture groups of all successive non-overlapping matches
+pub fn regex_all_captures(regex: &Regex, text: &String) -> DDlogVec<DDlogVec<DDlogOption<String>>> {
+    regex
+        .captures_iter(&text)
+        .map(|captures| captures2vec(&captures))
+        .collect()
+}
+
+/// Returns named capture groups of the leftmost-first match.  Groups that did
+/// not participate in the match are omitted from the map.
+pub fn regex_named_captures(regex: &Regex, text: &String) -> DDlogOption<DDlogMap<String, String>> {
+    regex
+        .captures(&text)
+        .map(|captures| {
+            regex
+                .capture_names()
+                .flatten()
+                .filter_map(|name| {
+                    captures
+                        .name(name)
+                        .map(|group| (name.to_string(), group.as_str().to_string()))
+                })
+                .collect()
+        })
+        .into()
+}
+
 #[derive(Debug, Clone)]
 pub struct RegexSet {
     set: InnerRegexSet,
@@ -0,0 +1,231 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::{Group, Option as DDlogOption, Result as DDlogResult};
+use differential_datalog::record;
+use serde::{
+    de::{Deserializer, Error},
+    ser::Serializer,
+};
+use std::{
+    cmp::Ordering,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+#[derive(Eq, Ord, Clone, Hash, PartialEq, PartialOrd, Debug)]
+pub struct SemVer {
+    version: ::semver::Version,
+}
+
+impl Default for SemVer {
+    fn default() -> Self {
+        SemVer {
+            version: ::semver::Version::new(0, 0, 0),
+        }
+    }
+}
+
+impl Display for SemVer {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        Display::fmt(&self.version, f)
+    }
+}
+
+impl Serialize for SemVer {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.version.to_string().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SemVer {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        ::semver::Version::parse(&s)
+            .map(|version| SemVer { version })
+            .map_err(Error::custom)
+    }
+}
+
+impl FromRecord for SemVer {
+    fn from_record(val: &record::Record) -> Result<Self, String> {
+        match val {
+            record::Record::String(s) => ::semver::Version::parse(s)
+                .map(|version| SemVer { version })
+                .map_err(|e| e.to_string()),
+            error => Err(format!("not a valid semantic version: {:?}", error)),
+        }
+    }
+}
+
+impl IntoRecord for SemVer {
+    fn into_record(self) -> record::Record {
+        record::Record::String(self.version.to_string())
+    }
+}
+
+impl record::Mutator<SemVer> for record::Record {
+    fn mutate(&self, v: &mut SemVer) -> Result<(), String> {
+        *v = SemVer::from_record(self)?;
+        Ok(())
+    }
+}
+
+/// `semver::VersionReq` does not implement `Ord`; we order requirements by
+/// their string representation instead.
+#[derive(Eq, Clone, Hash, PartialEq, Debug, Default)]
+pub struct SemVerReq {
+    req: ::semver::VersionReq,
+}
+
+impl PartialOrd for SemVerReq {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SemVerReq {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.req.to_string().cmp(&other.req.to_string())
+    }
+}
+
+impl Display for SemVerReq {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        Display::fmt(&self.req, f)
+    }
+}
+
+impl Serialize for SemVerReq {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.req.to_string().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SemVerReq {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        ::semver::VersionReq::parse(&s)
+            .map(|req| SemVerReq { req })
+            .map_err(Error::custom)
+    }
+}
+
+impl FromRecord for SemVerReq {
+    fn from_record(val: &record::Record) -> Result<Self, String> {
+        match val {
+            record::Record::String(s) => ::semver::VersionReq::parse(s)
+                .map(|req| SemVerReq { req })
+                .map_err(|e| e.to_string()),
+            error => Err(format!("not a valid version requirement: {:?}", error)),
+        }
+    }
+}
+
+impl IntoRecord for SemVerReq {
+    fn into_record(self) -> record::Record {
+        record::Record::String(self.req.to_string())
+    }
+}
+
+impl record::Mutator<SemVerReq> for record::Record {
+    fn mutate(&self, req: &mut SemVerReq) -> Result<(), String> {
+        *req = SemVerReq::from_record(self)?;
+        Ok(())
+    }
+}
+
+pub fn semver_parse(s: &String) -> DDlogResult<SemVer, String> {
+    match ::semver::Version::parse(s.trim()) {
+        Ok(version) => DDlogResult::Ok {
+            res: SemVer { version },
+        },
+        Err(e) => DDlogResult::Err {
+            err: format!("invalid version \"{}\": {}", s, e),
+        },
+    }
+}
+
+pub fn semver(major: &u64, minor: &u64, patch: &u64) -> SemVer {
+    SemVer {
+        version: ::semver::Version::new(*major, *minor, *patch),
+    }
+}
+
+pub fn semver_major(v: &SemVer) -> u64 {
+    v.version.major
+}
+
+pub fn semver_minor(v: &SemVer) -> u64 {
+    v.version.minor
+}
+
+pub fn semver_patch(v: &SemVer) -> u64 {
+    v.version.patch
+}
+
+pub fn semver_pre(v: &SemVer) -> String {
+    v.version.pre.as_str().to_string()
+}
+
+pub fn semver_build(v: &SemVer) -> String {
+    v.version.build.as_str().to_string()
+}
+
+pub fn semver_to_string(v: &SemVer) -> String {
+    v.version.to_string()
+}
+
+pub fn semver_req_parse(s: &String) -> DDlogResult<SemVerReq, String> {
+    match ::semver::VersionReq::parse(s) {
+        Ok(req) => DDlogResult::Ok {
+            res: SemVerReq { req },
+        },
+        Err(e) => DDlogResult::Err {
+            err: format!("invalid version requirement \"{}\": {}", s, e),
+        },
+    }
+}
+
+pub fn semver_req_to_string(req: &SemVerReq) -> String {
+    req.req.to_string()
+}
+
+pub fn semver_matches(v: &SemVer, req: &SemVerReq) -> bool {
+    req.req.matches(&v.version)
+}
+
+pub fn group_max_matching<K>(g: &Group<K, SemVer>, req: &SemVerReq) -> DDlogOption<SemVer> {
+    ddlog_std::option2std(g.val_iter().filter(|v| semver_matches(v, req)).max())
+}
@@ -121,3 +121,44 @@ pub fn query(url: &Url) -> ddlog_std::Option<String> {
 pub fn fragment(url: &Url) -> ddlog_std::Option<String> {
     ddlog_std::option2std(url.url.fragment().map(|x| x.to_string()))
 }
+pub fn path_segments(url: &Url) -> ddlog_std::Vec<String> {
+    url.url
+        .path_segments()
+        .map(|segments| segments.map(|x| x.to_string()).collect())
+        .unwrap_or_default()
+}
+pub fn query_pairs(url: &Url) -> ddlog_std::Vec<ddlog_std::tuple2<String, String>> {
+    url.url
+        .query_pairs()
+        .map(|(k, v)| ddlog_std::tuple2(k.into_owned(), v.into_owned()))
+        .collect()
+}
+pub fn query_map(url: &Url) -> ddlog_std::Map<String, String> {
+    url.url
+        .query_pairs()
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect()
+}
+pub fn query_param(url: &Url, name: &String) -> ddlog_std::Option<String> {
+    ddlog_std::option2std(
+        url.url
+            .query_pairs()
+            .find(|(k, _)| k == name.as_str())
+            .map(|(_, v)| v.into_owned()),
+    )
+}
+pub fn normalize(url: &Url) -> Url {
+    let mut normalized = url.url.clone();
+    normalized.set_fragment(None);
+
+    let mut pairs: Vec<(String, String)> = url.url.query_pairs().into_owned().collect();
+    if pairs.is_empty() {
+        normalized.set_query(None);
+    } else {
+        // Stable sort preserves the relative order of repeated parameters.
+        pairs.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+        normalized.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    Url { url: normalized }
+}
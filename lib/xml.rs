@@ -0,0 +1,337 @@
+/*
+Copyright (c) 2021 VMware, Inc.
+SPDX-License-Identifier: MIT
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+use ddlog_std::{Option as DDlogOption, Result as DDlogResult, Vec as DDlogVec};
+
+pub fn xml_parse(xml: &String) -> DDlogResult<XmlNode, String> {
+    ddlog_std::res2std(
+        roxmltree::Document::parse(xml).map(|doc| convert_element(doc.root_element())),
+    )
+}
+
+fn convert_element(node: roxmltree::Node) -> XmlNode {
+    let children = node
+        .children()
+        .filter_map(|child| {
+            if child.is_element() {
+                Some(convert_element(child))
+            } else if child.is_text() {
+                child
+                    .text()
+                    .filter(|text| !text.trim().is_empty())
+                    .map(|text| XmlNode::XmlText {
+                        text: text.to_string(),
+                    })
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    XmlNode::XmlElement {
+        name: node.tag_name().name().to_string(),
+        namespace: ddlog_std::option2std(node.tag_name().namespace().map(|ns| ns.to_string())),
+        attrs: node
+            .attributes()
+            .iter()
+            .map(|attr| (attr.name().to_string(), attr.value().to_string()))
+            .collect(),
+        children,
+    }
+}
+
+fn element_children(n: &XmlNode) -> impl Iterator<Item = &XmlNode> {
+    let children: &[XmlNode] = match n {
+        XmlNode::XmlElement { children, .. } => children.as_slice(),
+        XmlNode::XmlText { .. } => &[],
+    };
+    children
+        .iter()
+        .filter(|child| matches!(child, XmlNode::XmlElement { .. }))
+}
+
+pub fn xml_children(n: &XmlNode) -> DDlogVec<XmlNode> {
+    element_children(n).cloned().collect()
+}
+
+pub fn xml_child(n: &XmlNode, name: &String) -> DDlogOption<XmlNode> {
+    ddlog_std::option2std(
+        element_children(n)
+            .find(|child| match child {
+                XmlNode::XmlElement { name: n, .. } => n == name,
+                _ => false,
+            })
+            .cloned(),
+    )
+}
+
+fn append_text(n: &XmlNode, out: &mut String) {
+    match n {
+        XmlNode::XmlText { text } => out.push_str(text),
+        XmlNode::XmlElement { children, .. } => {
+            for child in children.iter() {
+                append_text(child, out);
+            }
+        }
+    }
+}
+
+pub fn xml_text(n: &XmlNode) -> String {
+    let mut text = String::new();
+    append_text(n, &mut text);
+    text
+}
+
+pub fn xml_select(n: &XmlNode, path: &String) -> DDlogResult<DDlogVec<XmlNode>, String> {
+    let path = match xpath::parse(path) {
+        Ok(path) => path,
+        Err(e) => return DDlogResult::Err { err: e },
+    };
+    DDlogResult::Ok {
+        res: xpath::eval(n, &path).into_iter().cloned().collect(),
+    }
+}
+
+pub fn xml_select_text(n: &XmlNode, path: &String) -> DDlogResult<DDlogVec<String>, String> {
+    // Strip the trailing `@attr` or `text()` step.
+    let (elements, last) = match path.rfind('/') {
+        Some(pos) if path[pos + 1..].starts_with('@') || &path[pos + 1..] == "text()" => {
+            (&path[..pos], Some(&path[pos + 1..]))
+        }
+        None if path.starts_with('@') || path == "text()" => ("", Some(path.as_str())),
+        _ => (path.as_str(), None),
+    };
+
+    let nodes = if elements.is_empty() {
+        vec![n]
+    } else {
+        match xpath::parse(elements) {
+            Ok(path) => xpath::eval(n, &path),
+            Err(e) => return DDlogResult::Err { err: e },
+        }
+    };
+
+    let res = match last {
+        Some(attr) if attr.starts_with('@') => nodes
+            .into_iter()
+            .filter_map(|node| match node {
+                XmlNode::XmlElement { attrs, .. } => attrs.x.get(&attr[1..]).cloned(),
+                _ => None,
+            })
+            .collect(),
+        _ => nodes.into_iter().map(xml_text).collect(),
+    };
+    DDlogResult::Ok { res }
+}
+
+/* A minimal XPath evaluator used by `xml_select()`. */
+mod xpath {
+    use super::XmlNode;
+
+    pub enum Axis {
+        Child,
+        Descendant,
+    }
+
+    pub enum Predicate {
+        Index(usize),
+        HasAttr(String),
+        AttrEq(String, String),
+    }
+
+    pub struct Step {
+        axis: Axis,
+        // `None` matches any element.
+        name: Option<String>,
+        predicate: Option<Predicate>,
+    }
+
+    pub struct Path {
+        absolute: bool,
+        steps: Vec<Step>,
+    }
+
+    pub fn parse(path: &str) -> Result<Path, String> {
+        let err = |msg: &str| format!("invalid XML path '{}': {}", path, msg);
+
+        let mut absolute = false;
+        let mut steps = Vec::new();
+        let mut axis = Axis::Child;
+        for (i, segment) in path.split('/').enumerate() {
+            if segment.is_empty() {
+                if i == 0 {
+                    absolute = true;
+                    continue;
+                }
+                // `//`: the next step selects descendants.
+                if matches!(axis, Axis::Descendant) {
+                    return Err(err("unexpected '/'"));
+                }
+                axis = Axis::Descendant;
+                continue;
+            }
+
+            let (name, predicate) = match segment.find('[') {
+                Some(pos) => {
+                    if !segment.ends_with(']') {
+                        return Err(err("unterminated '['"));
+                    }
+                    let predicate = parse_predicate(&segment[pos + 1..segment.len() - 1])
+                        .ok_or_else(|| err(&format!("invalid predicate in '{}'", segment)))?;
+                    (&segment[..pos], Some(predicate))
+                }
+                None => (segment, None),
+            };
+            if name.is_empty() || name.starts_with('@') || name.contains(']') {
+                return Err(err(&format!("invalid step '{}'", segment)));
+            }
+
+            steps.push(Step {
+                axis,
+                name: if name == "*" {
+                    None
+                } else {
+                    Some(name.to_string())
+                },
+                predicate,
+            });
+            axis = Axis::Child;
+        }
+
+        if steps.is_empty() || matches!(axis, Axis::Descendant) {
+            return Err(err("path must end with an element name"));
+        }
+
+        Ok(Path { absolute, steps })
+    }
+
+    fn parse_predicate(pred: &str) -> Option<Predicate> {
+        let pred = pred.trim();
+        if let Some(attr) = pred.strip_prefix('@') {
+            match attr.find('=') {
+                Some(pos) => {
+                    let value = attr[pos + 1..].trim();
+                    let quoted = value.len() >= 2
+                        && ((value.starts_with('\'') && value.ends_with('\''))
+                            || (value.starts_with('"') && value.ends_with('"')));
+                    if !quoted {
+                        return None;
+                    }
+                    Some(Predicate::AttrEq(
+                        attr[..pos].trim().to_string(),
+                        value[1..value.len() - 1].to_string(),
+                    ))
+                }
+                None => Some(Predicate::HasAttr(attr.to_string())),
+            }
+        } else {
+            match pred.parse::<usize>() {
+                Ok(k) if k > 0 => Some(Predicate::Index(k)),
+                _ => None,
+            }
+        }
+    }
+
+    fn matches_name(node: &XmlNode, name: &Option<String>) -> bool {
+        match node {
+            XmlNode::XmlElement { name: n, .. } => name.as_ref().map_or(true, |name| n == name),
+            XmlNode::XmlText { .. } => false,
+        }
+    }
+
+    fn descendants<'a>(node: &'a XmlNode, out: &mut Vec<&'a XmlNode>) {
+        if let XmlNode::XmlElement { children, .. } = node {
+            for child in children.iter() {
+                out.push(child);
+                descendants(child, out);
+            }
+        }
+    }
+
+    fn apply_predicate<'a>(
+        nodes: Vec<&'a XmlNode>,
+        predicate: &Option<Predicate>,
+    ) -> Vec<&'a XmlNode> {
+        match predicate {
+            None => nodes,
+            Some(Predicate::Index(k)) => nodes.into_iter().skip(k - 1).take(1).collect(),
+            Some(Predicate::HasAttr(attr)) => nodes
+                .into_iter()
+                .filter(|node| match node {
+                    XmlNode::XmlElement { attrs, .. } => attrs.x.contains_key(attr),
+                    _ => false,
+                })
+                .collect(),
+            Some(Predicate::AttrEq(attr, value)) => nodes
+                .into_iter()
+                .filter(|node| match node {
+                    XmlNode::XmlElement { attrs, .. } => attrs.x.get(attr) == Some(value),
+                    _ => false,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn eval<'a>(context: &'a XmlNode, path: &Path) -> Vec<&'a XmlNode> {
+        let mut steps = path.steps.iter();
+        let mut current: Vec<&'a XmlNode> = if path.absolute {
+            // The first step of an absolute path is matched against the
+            // context node itself.
+            let first = steps.next().unwrap();
+            let mut candidates = vec![context];
+            if matches!(first.axis, Axis::Descendant) {
+                descendants(context, &mut candidates);
+            }
+            let candidates = candidates
+                .into_iter()
+                .filter(|node| matches_name(node, &first.name))
+                .collect();
+            apply_predicate(candidates, &first.predicate)
+        } else {
+            vec![context]
+        };
+
+        for step in steps {
+            let mut next = Vec::new();
+            for node in current.into_iter() {
+                let mut candidates = Vec::new();
+                match step.axis {
+                    Axis::Child => {
+                        if let XmlNode::XmlElement { children, .. } = node {
+                            candidates.extend(children.iter());
+                        }
+                    }
+                    Axis::Descendant => descendants(node, &mut candidates),
+                }
+                let candidates = candidates
+                    .into_iter()
+                    .filter(|node| matches_name(node, &step.name))
+                    .collect();
+                next.extend(apply_predicate(candidates, &step.predicate));
+            }
+            current = next;
+        }
+
+        current
+    }
+}
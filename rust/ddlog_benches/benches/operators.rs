@@ -0,0 +1,39 @@
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, SamplingMode,
+};
+use ddlog_benches::operators;
+
+const SAMPLES: usize = 1_000_000;
+
+/// Compares single- and multi-tenant evaluation of the same rules, to
+/// measure the cost of lifting rule functions to tenant-tagged values
+fn operators(c: &mut Criterion) {
+    let mut group = c.benchmark_group("operators");
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+
+    let dataset = operators::dataset(SAMPLES);
+    for &multi_tenant in &[false, true] {
+        group.bench_with_input(
+            BenchmarkId::new(
+                if multi_tenant {
+                    "multi-tenant"
+                } else {
+                    "single-tenant"
+                },
+                format!("{} records", dataset.len()),
+            ),
+            &dataset,
+            |b, dataset| {
+                b.iter_batched(
+                    || (operators::init(1, multi_tenant), dataset.clone()),
+                    |(ddlog, data)| operators::run(black_box(ddlog), multi_tenant, black_box(data)),
+                    BatchSize::PerIteration,
+                )
+            },
+        );
+    }
+}
+
+criterion_group!(benches, operators);
+criterion_main!(benches);
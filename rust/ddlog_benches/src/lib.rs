@@ -1,3 +1,4 @@
 pub mod live_journal;
+pub mod operators;
 pub mod twitter;
 pub mod utils;
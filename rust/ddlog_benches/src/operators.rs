@@ -0,0 +1,49 @@
+use benchmarks_ddlog::{api::HDDlog, typedefs::operators::Number, Relations};
+use benchmarks_differential_datalog::{
+    ddval::{DDValConvert, DDValue},
+    program::{config::Config, tenant::TenantId, RelId, Update},
+    DDlog, DDlogDynamic,
+};
+
+/// The tenant that owns the dataset in multi-tenant runs
+const TENANT: TenantId = 1;
+
+pub fn dataset(samples: usize) -> Vec<Update<DDValue>> {
+    (0..samples as u64)
+        .map(|n| Update::Insert {
+            relid: Relations::operators_Number as RelId,
+            v: Number { n }.into_ddvalue(),
+        })
+        .collect()
+}
+
+pub fn init(workers: usize, multi_tenant: bool) -> HDDlog {
+    let config = Config {
+        num_timely_workers: workers,
+        multi_tenant,
+        ..Config::new()
+    };
+    let (ddlog, _) =
+        HDDlog::run_with_config(config, false).expect("failed to create DDlog instance");
+    ddlog
+}
+
+pub fn run(ddlog: HDDlog, multi_tenant: bool, dataset: Vec<Update<DDValue>>) -> HDDlog {
+    ddlog
+        .transaction_start()
+        .expect("failed to start transaction");
+    if multi_tenant {
+        ddlog
+            .apply_tenant_updates(TENANT, &mut dataset.into_iter())
+            .expect("failed to give transaction input");
+    } else {
+        ddlog
+            .apply_updates(&mut dataset.into_iter())
+            .expect("failed to give transaction input");
+    }
+    ddlog
+        .transaction_commit()
+        .expect("failed to commit transaction");
+
+    ddlog
+}
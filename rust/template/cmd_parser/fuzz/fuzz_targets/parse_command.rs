@@ -0,0 +1,32 @@
+//! Parse arbitrary input as a sequence of commands.  Neither parser may
+//! panic, and the untrusted parser must accept exactly the same commands as
+//! the regular one on input that stays within its limits.
+
+#![no_main]
+
+use cmd_parser::{parse_command, parse_command_untrusted, ParseError};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut input = data;
+    while !input.is_empty() {
+        match parse_command_untrusted(input) {
+            Ok((rest, cmd)) => {
+                // The regular parser reads from files, which the untrusted
+                // parser refuses to do, so it only runs on accepted input.
+                let (trusted_rest, trusted_cmd) = parse_command(input).unwrap();
+                assert_eq!(cmd, trusted_cmd);
+                assert_eq!(rest, trusted_rest);
+                if rest.len() == input.len() {
+                    break;
+                }
+                input = rest;
+            }
+            Err(ParseError::Invalid { offset }) => {
+                assert!(offset <= input.len());
+                break;
+            }
+            Err(_) => break,
+        }
+    }
+});
@@ -0,0 +1,53 @@
+//! Convert arbitrary records into Rust values.  Records come from the
+//! command parser (`insert R(...)`) or from their JSON serialization; either
+//! way, converting them into typed values must fail gracefully instead of
+//! panicking.
+
+#![no_main]
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use cmd_parser::{parse_command_untrusted, Command};
+use differential_datalog::record::{FromRecord, IntoRecord, Mutator, Record, UpdCmd};
+use libfuzzer_sys::fuzz_target;
+use num::BigInt;
+
+fn convert<T>(rec: &Record)
+where
+    T: FromRecord + IntoRecord + Clone + Default + PartialEq + std::fmt::Debug,
+    Record: Mutator<T>,
+{
+    if let Ok(v) = T::from_record(rec) {
+        // Converting a value back and forth must not change it.
+        assert_eq!(T::from_record(&v.clone().into_record()), Ok(v));
+    }
+    let mut v = T::default();
+    let _ = rec.mutate(&mut v);
+}
+
+fn check(rec: &Record) {
+    let _ = rec.to_string();
+    convert::<bool>(rec);
+    convert::<u8>(rec);
+    convert::<i32>(rec);
+    convert::<u128>(rec);
+    convert::<BigInt>(rec);
+    convert::<String>(rec);
+    convert::<(u16, String)>(rec);
+    convert::<Vec<i64>>(rec);
+    convert::<BTreeSet<u32>>(rec);
+    convert::<BTreeMap<String, Vec<bool>>>(rec);
+}
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(rec) = serde_json::from_slice::<Record>(data) {
+        check(&rec);
+    }
+
+    let mut cmd = b"insert R(".to_vec();
+    cmd.extend_from_slice(data);
+    cmd.extend_from_slice(b");");
+    if let Ok((_, Command::Update(UpdCmd::Insert(_, rec), _))) = parse_command_untrusted(&cmd) {
+        check(&rec);
+    }
+});
@@ -0,0 +1,193 @@
+//! Parsing commands received from untrusted sources.
+//!
+//! `parse_command()` is written for trusted input, such as `.dat` files
+//! written by the user: string literals of the form `%"file"` are replaced
+//! with the contents of the file, and the nom parsers are recursive, so
+//! deeply nested records or very long runs of whitespace can overflow the
+//! stack.  `parse_command_untrusted()` parses the same syntax, but refuses to
+//! read files, bounds the size of recursive constructs before running the
+//! parser, and reports failures as a `ParseError`.
+
+use std::cell::Cell;
+use std::fmt;
+
+use nom::{Context, Err};
+
+use crate::parse::{parse_command, Command, UNTRUSTED};
+
+/// Maximal nesting depth of parentheses, brackets, and braces in untrusted
+/// input.
+pub const MAX_NESTING_DEPTH: usize = 128;
+
+/// Maximal number of consecutive whitespace characters in untrusted input.
+pub const MAX_WHITESPACE_RUN: usize = 4096;
+
+/// Error returned by `parse_command_untrusted()`.  Offsets are in bytes from
+/// the start of the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The input ends in the middle of a command.
+    Incomplete,
+    /// The input is not a valid command.  Reading files using `%"file"`
+    /// string literals also fails with this error.
+    Invalid { offset: usize },
+    /// Records are nested deeper than `MAX_NESTING_DEPTH`.
+    TooDeep { offset: usize },
+    /// The input contains more than `MAX_WHITESPACE_RUN` consecutive
+    /// whitespace characters.
+    TooMuchWhitespace { offset: usize },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Incomplete => write!(f, "incomplete command"),
+            ParseError::Invalid { offset } => write!(f, "invalid input at offset {}", offset),
+            ParseError::TooDeep { offset } => write!(
+                f,
+                "values nested deeper than {} levels at offset {}",
+                MAX_NESTING_DEPTH, offset
+            ),
+            ParseError::TooMuchWhitespace { offset } => write!(
+                f,
+                "more than {} consecutive whitespace characters at offset {}",
+                MAX_WHITESPACE_RUN, offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Check that `input` does not exceed the limits on recursive constructs.
+/// Brackets inside string literals and comments are not counted.
+fn check_limits(input: &[u8]) -> Result<(), ParseError> {
+    let mut depth = 0usize;
+    let mut whitespace = 0usize;
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut escaped = false;
+
+    for (offset, c) in input.iter().enumerate() {
+        if matches!(c, b' ' | b'\t' | b'\r' | b'\n') {
+            whitespace += 1;
+            if whitespace > MAX_WHITESPACE_RUN {
+                return Err(ParseError::TooMuchWhitespace { offset });
+            }
+        } else {
+            whitespace = 0;
+        }
+
+        if in_comment {
+            in_comment = *c != b'\n';
+        } else if in_string {
+            if escaped {
+                escaped = false;
+            } else if *c == b'\\' {
+                escaped = true;
+            } else if *c == b'"' {
+                in_string = false;
+            }
+        } else {
+            match c {
+                b'"' => in_string = true,
+                b'#' => in_comment = true,
+                b'(' | b'[' | b'{' => {
+                    depth += 1;
+                    if depth > MAX_NESTING_DEPTH {
+                        return Err(ParseError::TooDeep { offset });
+                    }
+                }
+                b')' | b']' | b'}' => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resets the `UNTRUSTED` flag when dropped, even if the parser panics.
+struct UntrustedGuard(bool);
+
+impl UntrustedGuard {
+    fn set(flag: &Cell<bool>) -> Self {
+        UntrustedGuard(flag.replace(true))
+    }
+}
+
+impl Drop for UntrustedGuard {
+    fn drop(&mut self) {
+        let previous = self.0;
+        UNTRUSTED.with(|untrusted| untrusted.set(previous));
+    }
+}
+
+/// Parse one command from input received from an untrusted source.  On
+/// success, returns the rest of the input and the command.
+pub fn parse_command_untrusted(input: &[u8]) -> Result<(&[u8], Command), ParseError> {
+    check_limits(input)?;
+
+    let _guard = UNTRUSTED.with(UntrustedGuard::set);
+    parse_command(input).map_err(|e| match e {
+        Err::Incomplete(_) => ParseError::Incomplete,
+        Err::Error(Context::Code(rest, _)) | Err::Failure(Context::Code(rest, _)) => {
+            ParseError::Invalid {
+                offset: input.len() - rest.len(),
+            }
+        }
+        #[allow(unreachable_patterns)]
+        _ => ParseError::Invalid { offset: 0 },
+    })
+}
+
+#[test]
+fn test_untrusted() {
+    assert_eq!(
+        parse_command_untrusted(br"start;"),
+        Ok((&br""[..], Command::Start))
+    );
+    assert_eq!(
+        parse_command_untrusted(br"insert R(1"),
+        Err(ParseError::Incomplete)
+    );
+    assert_eq!(
+        parse_command_untrusted(br"insert R(1));"),
+        Err(ParseError::Invalid { offset: 0 })
+    );
+    assert_eq!(
+        parse_command_untrusted(br###"insert R(%"/etc/passwd");"###),
+        Err(ParseError::Invalid { offset: 0 })
+    );
+    assert_eq!(
+        parse_command_untrusted(br"log_level 100000000000;"),
+        Err(ParseError::Invalid { offset: 0 })
+    );
+    assert_eq!(
+        parse_command_untrusted(b"echo \xff;"),
+        Err(ParseError::Invalid { offset: 0 })
+    );
+
+    let deep = format!("insert R({}1{});", "(".repeat(1000), ")".repeat(1000));
+    assert_eq!(
+        parse_command_untrusted(deep.as_bytes()),
+        Err(ParseError::TooDeep {
+            offset: 8 + MAX_NESTING_DEPTH
+        })
+    );
+    let spaces = format!("start{};", " ".repeat(100000));
+    assert_eq!(
+        parse_command_untrusted(spaces.as_bytes()),
+        Err(ParseError::TooMuchWhitespace {
+            offset: 5 + MAX_WHITESPACE_RUN
+        })
+    );
+
+    // Brackets in strings and comments do not count.
+    let string = format!("insert R(\"{}\");", "(".repeat(1000));
+    assert!(parse_command_untrusted(string.as_bytes()).is_ok());
+    let comment = format!("#{}\n", "(".repeat(1000));
+    assert_eq!(
+        parse_command_untrusted(comment.as_bytes()),
+        Ok((&b""[..], Command::Comment))
+    );
+}
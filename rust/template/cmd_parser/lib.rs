@@ -1,12 +1,16 @@
 #![warn(missing_debug_implementations)]
 
+mod hardened;
 mod parse;
+mod preprocess;
 
 use std::io;
 use std::io::BufRead;
 use std::io::BufReader;
 
+pub use hardened::*;
 pub use parse::*;
+pub use preprocess::*;
 
 use nom::*;
 use rustyline::error::ReadlineError;
@@ -20,12 +24,14 @@ enum Input {
     Pipe(BufReader<io::Stdin>),
 }
 
-/// Parse commands from stdio.
+/// Parse commands from stdio, after running them through the
+/// `Preprocessor`.
 pub fn interact<F>(cb: F) -> Result<(), String>
 where
     F: Fn(Command, bool) -> (Result<(), String>, bool),
 {
     let mut buf: Vec<u8> = Vec::new();
+    let mut preprocessor = Preprocessor::new();
 
     let istty = unsafe {
         // libc::STDIN_FILENO
@@ -72,7 +78,7 @@ where
                 let res = reader.read_line(&mut line);
                 match res {
                     Ok(0) => {
-                        return Ok(());
+                        return preprocessor.finish();
                     }
                     Ok(_) => {}
                     Err(err) => {
@@ -83,6 +89,17 @@ where
             }
         };
 
+        let line = match preprocessor.process_line(&line) {
+            Ok(line) => line,
+            Err(e) => {
+                let err = format!("Preprocessing failed: {}", e);
+                if !istty {
+                    return Err(err);
+                }
+                eprintln!("{}", err);
+                continue;
+            }
+        };
         buf.extend_from_slice(line.as_bytes());
 
         loop {
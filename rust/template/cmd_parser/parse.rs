@@ -1,5 +1,6 @@
 //! nom-based parser for Datalog values.
 
+use differential_datalog::graph::GraphFormat;
 use differential_datalog::record::*;
 use nom::*;
 use num::bigint::*;
@@ -8,6 +9,13 @@ use num::Num;
 use num::ToPrimitive;
 use ordered_float::OrderedFloat;
 use std::borrow::Cow;
+use std::cell::Cell;
+
+thread_local! {
+    /// Set while parsing input from an untrusted source (see
+    /// `parse_command_untrusted()`), which must not read local files.
+    pub(crate) static UNTRUSTED: Cell<bool> = Cell::new(false);
+}
 
 #[derive(Copy, Debug, PartialEq, Eq, Clone)]
 pub enum ProfileCmd {
@@ -19,19 +27,32 @@ pub enum ProfileCmd {
 pub enum Command {
     Start,
     Commit(bool),
+    CommitWithId(String),
     Comment,
     Rollback,
     Timestamp,
     Profile(Option<ProfileCmd>),
     Dump(Option<String>),
     Clear(String),
+    Truncate(String),
     Exit,
     Echo(String),
     LogLevel(i32),
     Sleep(BigInt),
+    /// Timestamp of the next command in milliseconds since the start of a
+    /// recording (see `CommandRecorder::with_timestamps()`).
+    At(BigInt),
     Update(UpdCmd, bool),
     QueryIndex(String, Record),
     DumpIndex(String),
+    Stats(Option<String>),
+    /// Check that relation has the specified schema fingerprint.
+    CheckFingerprint(String, u64),
+    /// Check that relation contains the specified record.
+    Compare(String, Record),
+    /// Render an edge relation, optionally labeled by a node-label relation,
+    /// as a graph.
+    Graph(GraphFormat, String, Option<String>),
 }
 
 named!(spaces<&[u8], ()>,
@@ -73,6 +94,11 @@ named!(pub parse_command<&[u8], Command>,
     do_parse!(
         spaces >>
         upd: alt!(do_parse!(apply!(sym,"start")     >> apply!(sym,";") >> (Command::Start))     |
+                  do_parse!(apply!(sym,"commit")    >>
+                            apply!(sym,"id")        >>
+                            id: string_literal      >>
+                            apply!(sym,";")         >>
+                            (Command::CommitWithId(id)))                                        |
                   do_parse!(apply!(sym,"commit")    >>
                             delta: opt!(apply!(sym, "dump_changes"))   >>
                             apply!(sym,";")         >>
@@ -94,23 +120,50 @@ named!(pub parse_command<&[u8], Command>,
                             rel: opt!(identifier)   >>
                             apply!(sym,";")         >>
                             (Command::Dump(rel)))                                               |
+                  do_parse!(apply!(sym,"stats")     >>
+                            rel: opt!(identifier)   >>
+                            apply!(sym,";")         >>
+                            (Command::Stats(rel)))                                              |
+                  do_parse!(apply!(sym,"check_fingerprint") >>
+                            rel: identifier         >>
+                            fp: map_opt!(bigint_val, |f: BigInt| f.to_u64()) >>
+                            apply!(sym,";")         >>
+                            (Command::CheckFingerprint(rel, fp)))                               |
                   do_parse!(apply!(sym,"clear")     >>
                             rel: identifier         >>
                             apply!(sym,";")         >>
                             (Command::Clear(rel)))                                              |
+                  do_parse!(apply!(sym,"truncate")  >>
+                            rel: identifier         >>
+                            apply!(sym,";")         >>
+                            (Command::Truncate(rel)))                                           |
+                  do_parse!(apply!(sym,"graph")     >>
+                            format: graph_format    >>
+                            edges: identifier       >>
+                            labels: opt!(identifier) >>
+                            apply!(sym,";")         >>
+                            (Command::Graph(format, edges, labels)))                            |
+                  do_parse!(apply!(sym,"compare")   >>
+                            rec: rel_record         >>
+                            apply!(sym,";")         >>
+                            (Command::Compare(rec.0.into_owned(), rec.1)))                      |
                   do_parse!(apply!(sym,"mssleep")   >>
                             ms: dec_val             >>
                             apply!(sym,";")         >>
                             (Command::Sleep(ms)))                                               |
+                  do_parse!(apply!(sym,"at")        >>
+                            ms: dec_val             >>
+                            apply!(sym,";")         >>
+                            (Command::At(ms)))                                                  |
                   do_parse!(apply!(sym,"exit")      >> apply!(sym,";") >> (Command::Exit))      |
                   do_parse!(apply!(sym,"echo")      >>
-                            txt: take_until!(";")   >>
+                            txt: map_res!(take_until!(";"), std::str::from_utf8) >>
                             apply!(sym,";")         >>
-                            (Command::Echo(String::from_utf8(txt.to_vec()).unwrap())))          |
+                            (Command::Echo(txt.to_string())))                                   |
                   do_parse!(apply!(sym,"log_level") >>
-                            level: bigint_val       >>
+                            level: map_opt!(bigint_val, |l: BigInt| l.to_i32()) >>
                             apply!(sym,";")         >>
-                            (Command::LogLevel(level.to_i32().unwrap())))                       |
+                            (Command::LogLevel(level)))                                         |
                   do_parse!(apply!(sym,"rollback") >> apply!(sym,";") >> (Command::Rollback))   |
                   do_parse!(apply!(sym,"query_index")                         >>
                             idx: identifier                                   >>
@@ -147,6 +200,14 @@ fn test_command() {
         parse_command(br"commit;"),
         Ok((&br""[..], Command::Commit(false)))
     );
+    assert_eq!(
+        parse_command(br#"commit id "batch-1";"#),
+        Ok((&br""[..], Command::CommitWithId("batch-1".to_string())))
+    );
+    assert_eq!(
+        parse_command(br"commit dump_changes;"),
+        Ok((&br""[..], Command::Commit(true)))
+    );
     assert_eq!(
         parse_command(br"timestamp;"),
         Ok((&br""[..], Command::Timestamp))
@@ -167,15 +228,69 @@ fn test_command() {
         parse_command(br"dump;"),
         Ok((&br""[..], Command::Dump(None)))
     );
+    assert_eq!(
+        parse_command(br"check_fingerprint graph::Edge 0x12ab34cd56ef7890;"),
+        Ok((
+            &br""[..],
+            Command::CheckFingerprint("graph::Edge".to_string(), 0x12ab34cd56ef7890)
+        ))
+    );
     assert_eq!(
         parse_command(br"dump Tab;"),
         Ok((&br""[..], Command::Dump(Some("Tab".to_string()))))
     );
+    assert_eq!(
+        parse_command(br"stats;"),
+        Ok((&br""[..], Command::Stats(None)))
+    );
+    assert_eq!(
+        parse_command(br"stats Tab;"),
+        Ok((&br""[..], Command::Stats(Some("Tab".to_string()))))
+    );
     assert_eq!(
         parse_command(br"clear Tab;"),
         Ok((&br""[..], Command::Clear("Tab".to_string())))
     );
+    assert_eq!(
+        parse_command(br"truncate Tab;"),
+        Ok((&br""[..], Command::Truncate("Tab".to_string())))
+    );
+    assert_eq!(
+        parse_command(br"compare Tab(1, true);"),
+        Ok((
+            &br""[..],
+            Command::Compare(
+                "Tab".to_string(),
+                Record::PosStruct(
+                    Cow::from("Tab"),
+                    vec![Record::Int(1.into()), Record::Bool(true)]
+                )
+            )
+        ))
+    );
+    assert_eq!(
+        parse_command(br"graph dot Edge;"),
+        Ok((
+            &br""[..],
+            Command::Graph(GraphFormat::Dot, "Edge".to_string(), None)
+        ))
+    );
+    assert_eq!(
+        parse_command(br"graph graphml Edge Label;"),
+        Ok((
+            &br""[..],
+            Command::Graph(
+                GraphFormat::GraphML,
+                "Edge".to_string(),
+                Some("Label".to_string())
+            )
+        ))
+    );
     assert_eq!(parse_command(br"exit;"), Ok((&br""[..], Command::Exit)));
+    assert_eq!(
+        parse_command(br"at 1500;"),
+        Ok((&br""[..], Command::At(BigInt::from(1500))))
+    );
     assert_eq!(
         parse_command(br"echo test;"),
         Ok((&br""[..], Command::Echo("test".to_string())))
@@ -278,6 +393,23 @@ fn test_command() {
             )
         ))
     );
+    assert_eq!(
+        parse_command(br"modify Rel1 true <- Rel1{.f1.f2 = 5};"),
+        Ok((
+            &br""[..],
+            Command::Update(
+                UpdCmd::Modify(
+                    RelIdentifier::RelName(Cow::from("Rel1")),
+                    Record::Bool(true),
+                    Record::NamedStruct(
+                        Cow::from("Rel1"),
+                        vec![(Cow::from("f1.f2"), Record::Int(5.to_bigint().unwrap()))]
+                    )
+                ),
+                true
+            )
+        ))
+    );
     assert_eq!(
         parse_command(br#"   delete NB::Logical_Router("foo", 0xabcdef1, true) , "#),
         Ok((
@@ -300,6 +432,44 @@ fn test_command() {
     );
 }
 
+named!(graph_format<&[u8], GraphFormat>,
+    alt!(do_parse!(apply!(sym,"dot")     >> (GraphFormat::Dot)) |
+         do_parse!(apply!(sym,"graphml") >> (GraphFormat::GraphML)))
+);
+
+named!(terminated_record<&[u8], Record>,
+    do_parse!(spaces >> rec: record >> apply!(sym,";") >> (rec))
+);
+
+/// Parse a single value written in the `.dat` file syntax or printed by the
+/// `dump` command, e.g., `Edge{.from = 1, .to = 2}`.
+pub fn parse_record(input: &str) -> Result<Record, String> {
+    match terminated_record(format!("{};", input).as_bytes()) {
+        Ok((rest, rec)) if rest.is_empty() => Ok(rec),
+        Ok((rest, _)) => Err(format!(
+            "unexpected input after value: {}",
+            String::from_utf8_lossy(rest)
+        )),
+        Err(e) => Err(format!("invalid value {}: {}", input, crate::err_str(&e))),
+    }
+}
+
+#[test]
+fn test_parse_record() {
+    assert_eq!(
+        parse_record(r#"Edge{.from = 1, .to = "b"}"#),
+        Ok(Record::NamedStruct(
+            Cow::from("Edge"),
+            vec![
+                (Cow::from("from"), Record::Int(1.into())),
+                (Cow::from("to"), Record::String("b".to_string()))
+            ]
+        ))
+    );
+    assert_eq!(parse_record(" 5 "), Ok(Record::Int(5.into())));
+    assert!(parse_record("Edge{.from = 1").is_err());
+}
+
 named!(update<&[u8], UpdCmd>,
     alt!(do_parse!(apply!(sym,"insert")     >> rec: rel_record >> (UpdCmd::Insert(RelIdentifier::RelName(rec.0), rec.1)))               |
          do_parse!(apply!(sym,"insert_or_update") >> rec: rel_record >> (UpdCmd::InsertOrUpdate(RelIdentifier::RelName(rec.0), rec.1))) |
@@ -332,12 +502,21 @@ named!(record<&[u8], Record>,
 
 named!(named_record<&[u8], (Name, Record)>,
     do_parse!(apply!(sym,".") >>
-              fname: identifier >>
+              fname: field_path >>
               apply!(sym,"=") >>
               val: record >>
               (Cow::from(fname), val))
 );
 
+/* Field name, or a path to a nested field, e.g., `f1.f2`, in a modify command. */
+named!(field_path<&[u8], String>,
+    do_parse!(first: identifier >>
+              path: fold_many0!(preceded!(apply!(sym,"."), identifier),
+                                first,
+                                |acc: String, f: String| acc + "." + &f) >>
+              (path))
+);
+
 named!(bool_val<&[u8], Record>,
     alt!(do_parse!(apply!(sym,"true")  >> (Record::Bool(true))) |
          do_parse!(apply!(sym,"false") >> (Record::Bool(false))))
@@ -377,7 +556,9 @@ named!(string_literal<&[u8], String>,
         >>
         spaces
         >>
-        (String::from_utf8(str).unwrap())
+        str: map_res!(value!(str), String::from_utf8)
+        >>
+        (str)
     )
 );
 
@@ -385,12 +566,23 @@ named!(string_from_file<&[u8], String>,
     do_parse!(
         tag!("%")
         >>
-        fname: string_literal
+        str: map_res!(string_literal, read_string_file)
         >>
-        (std::fs::read_to_string(std::path::Path::new(&fname)).map_err(|e|format!("Failed to read string from file {}: {}", fname, e)).unwrap())
+        (str)
     )
 );
 
+fn read_string_file(fname: String) -> Result<String, String> {
+    if UNTRUSTED.with(|untrusted| untrusted.get()) {
+        return Err(format!(
+            "Reading file {} is not allowed in untrusted input",
+            fname
+        ));
+    }
+    std::fs::read_to_string(std::path::Path::new(&fname))
+        .map_err(|e| format!("Failed to read string from file {}: {}", fname, e))
+}
+
 named!(string_inline<&[u8], String>,
     do_parse!(
         str: string_literal
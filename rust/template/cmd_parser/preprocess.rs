@@ -0,0 +1,398 @@
+//! Preprocessor for command files.
+//!
+//! The preprocessor runs on `.dat` files line by line before the commands
+//! are parsed, so that regression suites can share setup code instead of
+//! duplicating it in every file.  Directives occupy a line of their own:
+//!
+//! ```text
+//! #include "common/setup.dat"
+//!
+//! #macro add_link(a, b)
+//! insert Link($a, $b),
+//! insert Link($b, $a),
+//! #endmacro
+//!
+//! start;
+//! #expand add_link(1, 2)
+//! #expand add_link(2, 3)
+//! commit;
+//!
+//! #ifdef DDLOG_SLOW_TESTS
+//! #include "large_topology.dat"
+//! #else
+//! #include "small_topology.dat"
+//! #endif
+//! ```
+//!
+//! * `#include "file"` inserts the preprocessed contents of `file`, relative
+//!   to the directory of the including file (or the working directory for
+//!   standard input).
+//! * `#macro name(params)` ... `#endmacro` defines a macro;
+//!   `#expand name(args)` inserts its body with every `$param` replaced by
+//!   the corresponding argument.  Arguments are separated by commas outside
+//!   of quotes and brackets.
+//! * `#ifdef VAR`, `#ifndef VAR`, `#else`, and `#endif` keep or drop the
+//!   lines between them depending on whether environment variable `VAR` is
+//!   set.
+//!
+//! Any other line starting with `#` is an ordinary comment.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Maximal nesting depth of includes and macro expansions.
+pub const MAX_EXPANSION_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Conditional {
+    /// Whether the lines in the current branch are kept.
+    taken: bool,
+    /// Whether the enclosing section is kept.
+    outer: bool,
+    in_else: bool,
+}
+
+/// Line-by-line command file preprocessor.  Feed it lines with
+/// `process_line()` and pass the output on to the parser; call `finish()`
+/// at the end of the input.
+#[derive(Debug, Default)]
+pub struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    /// Macro being defined: name, parameters, and the body so far.
+    defining: Option<(String, Macro)>,
+    conditionals: Vec<Conditional>,
+    /// Files being included, innermost last.
+    files: Vec<PathBuf>,
+    /// Nesting depth of includes and expansions.
+    depth: usize,
+}
+
+/* Splits a directive line into the directive and its argument. */
+fn directive(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if !line.starts_with('#') {
+        return None;
+    }
+    let line = &line[1..];
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '(')
+        .unwrap_or_else(|| line.len());
+    let name = &line[..end];
+    match name {
+        "include" | "macro" | "endmacro" | "expand" | "ifdef" | "ifndef" | "else" | "endif" => {
+            Some((name, line[end..].trim()))
+        }
+        _ => None,
+    }
+}
+
+/* Splits `name(a, b)` into the name and the list of arguments. */
+fn call(text: &str) -> Result<(String, Vec<String>), String> {
+    let open = text
+        .find('(')
+        .ok_or_else(|| format!("expected '(' in '{}'", text))?;
+    if !text.ends_with(')') {
+        return Err(format!("expected ')' at the end of '{}'", text));
+    }
+    let name = text[..open].trim().to_string();
+    let args = &text[open + 1..text.len() - 1];
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in args.chars() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+        } else {
+            match c {
+                '"' => in_string = true,
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth -= 1,
+                ',' if depth == 0 => {
+                    result.push(current.trim().to_string());
+                    current.clear();
+                    continue;
+                }
+                _ => (),
+            }
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !result.is_empty() {
+        result.push(current.trim().to_string());
+    }
+    Ok((name, result))
+}
+
+/* Replaces `$param` with the corresponding argument; longer parameter names
+ * are substituted first, so that `$ab` is not mistaken for `$a`. */
+fn substitute(line: &str, params: &[String], args: &[String]) -> String {
+    let mut bindings: Vec<(&String, &String)> = params.iter().zip(args.iter()).collect();
+    bindings.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+    let mut line = line.to_string();
+    for (param, arg) in bindings {
+        line = line.replace(&format!("${}", param), arg);
+    }
+    line
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn active(&self) -> bool {
+        self.conditionals
+            .last()
+            .map_or(true, |c| c.taken && c.outer)
+    }
+
+    /// Preprocess one line of input, including the line terminator, if any.
+    /// Returns the text to pass on to the parser, which is empty for
+    /// directives and for lines that are dropped.
+    pub fn process_line(&mut self, line: &str) -> Result<String, String> {
+        let directive = directive(line);
+
+        if let Some((name, mut mac)) = self.defining.take() {
+            match directive {
+                Some(("endmacro", _)) => {
+                    self.macros.insert(name, mac);
+                }
+                Some(("macro", _)) => {
+                    return Err(format!("#macro inside the definition of macro {}", name))
+                }
+                _ => {
+                    mac.body.push(line.trim_end_matches('\n').to_string());
+                    self.defining = Some((name, mac));
+                }
+            }
+            return Ok(String::new());
+        }
+
+        match directive {
+            Some((d, var)) if d == "ifdef" || d == "ifndef" => {
+                if var.is_empty() {
+                    return Err(format!("#{} without a variable name", d));
+                }
+                let defined = env::var_os(var).is_some();
+                let outer = self.active();
+                self.conditionals.push(Conditional {
+                    taken: defined == (d == "ifdef"),
+                    outer,
+                    in_else: false,
+                });
+                Ok(String::new())
+            }
+            Some(("else", _)) => match self.conditionals.last_mut() {
+                Some(cond) if !cond.in_else => {
+                    cond.taken = !cond.taken;
+                    cond.in_else = true;
+                    Ok(String::new())
+                }
+                _ => Err("#else without #ifdef".to_string()),
+            },
+            Some(("endif", _)) => self
+                .conditionals
+                .pop()
+                .map(|_| String::new())
+                .ok_or_else(|| "#endif without #ifdef".to_string()),
+            _ if !self.active() => Ok(String::new()),
+            Some(("include", path)) => self.include(path),
+            Some(("macro", def)) => {
+                let (name, params) = call(def)?;
+                if name.is_empty() {
+                    return Err("#macro without a name".to_string());
+                }
+                self.defining = Some((
+                    name,
+                    Macro {
+                        params,
+                        body: Vec::new(),
+                    },
+                ));
+                Ok(String::new())
+            }
+            Some(("endmacro", _)) => Err("#endmacro without #macro".to_string()),
+            Some(("expand", text)) => self.expand(text),
+            _ => Ok(line.to_string()),
+        }
+    }
+
+    /// Preprocess `text`, e.g., the contents of a file.
+    pub fn process_str(&mut self, text: &str) -> Result<String, String> {
+        let mut output = String::new();
+        for line in text.lines() {
+            output.push_str(&self.process_line(&format!("{}\n", line))?);
+        }
+        Ok(output)
+    }
+
+    /// Check that all macro definitions and conditional sections have been
+    /// closed at the end of the input.
+    pub fn finish(&self) -> Result<(), String> {
+        if let Some((name, _)) = &self.defining {
+            return Err(format!("missing #endmacro for macro {}", name));
+        }
+        if !self.conditionals.is_empty() {
+            return Err("missing #endif".to_string());
+        }
+        Ok(())
+    }
+
+    fn nested<F>(&mut self, f: F) -> Result<String, String>
+    where
+        F: FnOnce(&mut Self) -> Result<String, String>,
+    {
+        if self.depth >= MAX_EXPANSION_DEPTH {
+            return Err(format!(
+                "includes and macro expansions nested deeper than {} levels",
+                MAX_EXPANSION_DEPTH
+            ));
+        }
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn include(&mut self, arg: &str) -> Result<String, String> {
+        if arg.len() < 2 || !arg.starts_with('"') || !arg.ends_with('"') {
+            return Err(format!(
+                "expected a quoted file name after #include: {}",
+                arg
+            ));
+        }
+        let name = Path::new(&arg[1..arg.len() - 1]);
+        let path = match self.files.last().and_then(|file| file.parent()) {
+            Some(dir) if name.is_relative() => dir.join(name),
+            _ => name.to_path_buf(),
+        };
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("failed to include {}: {}", path.display(), e))?;
+        self.nested(|pp| {
+            pp.files.push(path.clone());
+            let conditionals = pp.conditionals.len();
+            let res = pp.process_str(&text).and_then(|output| {
+                if pp.defining.is_some() || pp.conditionals.len() != conditionals {
+                    Err(format!(
+                        "unterminated #macro or #ifdef in {}",
+                        path.display()
+                    ))
+                } else {
+                    Ok(output)
+                }
+            });
+            pp.files.pop();
+            res
+        })
+    }
+
+    fn expand(&mut self, text: &str) -> Result<String, String> {
+        let (name, args) = call(text)?;
+        let mac = self
+            .macros
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| format!("undefined macro {}", name))?;
+        if args.len() != mac.params.len() {
+            return Err(format!(
+                "macro {} expects {} arguments, but {} were given",
+                name,
+                mac.params.len(),
+                args.len()
+            ));
+        }
+        self.nested(|pp| {
+            let mut output = String::new();
+            for line in mac.body.iter() {
+                let line = substitute(line, &mac.params, &args);
+                output.push_str(&pp.process_line(&format!("{}\n", line))?);
+            }
+            Ok(output)
+        })
+    }
+}
+
+/// Preprocess the command file at `path`.
+pub fn preprocess_file<P: AsRef<Path>>(path: P) -> Result<String, String> {
+    let mut pp = Preprocessor::new();
+    let output = pp.include(&format!("\"{}\"", path.as_ref().display()))?;
+    pp.finish()?;
+    Ok(output)
+}
+
+#[test]
+fn test_preprocess() {
+    let mut pp = Preprocessor::new();
+    let output = pp
+        .process_str(
+            r#"# a comment
+#macro link(a, b)
+insert Link($a, $b),
+insert Link($b, $a),
+#endmacro
+start;
+#expand link(1, "x,y")
+#ifdef DDLOG_PREPROCESS_TEST_UNDEFINED
+insert Skipped(1),
+#else
+insert Kept(1);
+#endif
+commit;
+"#,
+        )
+        .unwrap();
+    assert_eq!(
+        output,
+        r#"# a comment
+start;
+insert Link(1, "x,y"),
+insert Link("x,y", 1),
+insert Kept(1);
+commit;
+"#
+    );
+    assert!(pp.finish().is_ok());
+
+    let mut pp = Preprocessor::new();
+    assert!(pp.process_line("#expand undefined()\n").is_err());
+    assert!(pp.process_line("#endif\n").is_err());
+    pp.process_line("#ifndef DDLOG_PREPROCESS_TEST_UNDEFINED\n")
+        .unwrap();
+    assert_eq!(pp.finish(), Err("missing #endif".to_string()));
+
+    let mut pp = Preprocessor::new();
+    pp.process_str("#macro loop()\n#expand loop()\n#endmacro\n")
+        .unwrap();
+    assert!(pp.process_line("#expand loop()\n").is_err());
+}
+
+#[test]
+fn test_include() {
+    let dir = env::temp_dir().join(format!("ddlog_preprocess_test_{}", std::process::id()));
+    fs::create_dir_all(dir.join("common")).unwrap();
+    fs::write(
+        dir.join("common/setup.dat"),
+        "#include \"nodes.dat\"\nstart;\n",
+    )
+    .unwrap();
+    fs::write(dir.join("common/nodes.dat"), "insert Node(1);\n").unwrap();
+    fs::write(
+        dir.join("test.dat"),
+        "#include \"common/setup.dat\"\ncommit;\n",
+    )
+    .unwrap();
+    let output = preprocess_file(dir.join("test.dat"));
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(output, Ok("insert Node(1);\nstart;\ncommit;\n".to_string()));
+}
@@ -189,6 +189,27 @@ extern index_id ddlog_get_index_id(ddlog_prog hprog, const char* iname);
  */
 extern const char* ddlog_get_index_name(ddlog_prog hprog, index_id id);
 
+/*
+ * Metadata embedded into the program by the DDlog compiler: its name, a
+ * hash of its source, and the version of the compiler.
+ *
+ * `ddlog_program_name()` and `ddlog_compiler_version()` return
+ * null-terminated UTF8 strings, or NULL on error, which must be deallocated
+ * using `ddlog_string_free()`.  `ddlog_source_hash()` returns 0 on error.
+ */
+extern char* ddlog_program_name(ddlog_prog hprog);
+extern char* ddlog_compiler_version(ddlog_prog hprog);
+extern uint64_t ddlog_source_hash(ddlog_prog hprog);
+
+/*
+ * Store the fingerprint of the schema of table `id` in `*fingerprint`.  Two
+ * builds of a program agree on the fingerprint of a table if and only if
+ * they agree on the declaration of the table and of all types it uses.
+ *
+ * Returns 0 on success, -1 on error.
+ */
+extern int ddlog_table_fingerprint(ddlog_prog hprog, table_id id, uint64_t *fingerprint);
+
 /*
  * Record commands issued to DDlog via this API in a file.
  *
@@ -351,6 +372,18 @@ ddlog_flatbuf_free(
         size_t size,
         size_t capacity);
 
+/*
+ * Prepare the current transaction for commit, the first phase of committing
+ * a transaction across multiple programs.  Propagates all buffered updates
+ * through the dataflow; following a successful prepare, the transaction can
+ * no longer be modified and `ddlog_transaction_commit()` cannot fail, but
+ * the transaction can still be aborted using `ddlog_transaction_rollback()`.
+ *
+ * On success, returns `0`; on error, returns `-1` and prints error message
+ * (see `print_err_msg` parameter to `ddlog_run()`).
+ */
+extern int ddlog_transaction_prepare(ddlog_prog hprog);
+
 /*
  * Discard all buffered updates and abort the current transaction.
  *
@@ -380,6 +413,25 @@ extern int ddlog_transaction_rollback(ddlog_prog hprog);
  */
 extern int ddlog_apply_updates(ddlog_prog prog, ddlog_cmd **upds, size_t n);
 
+/*
+ * Insert `n` records into input table `table`.
+ *
+ * Equivalent to calling `ddlog_apply_updates()` with an insert command for
+ * each record, but converts all records at once, allocating memory for the
+ * entire batch instead of for each record.  Use it to bulk-load large numbers
+ * of facts.
+ *
+ * On success, returns `0`. On error, returns a negative value and
+ * writes error message (see `print_err_msg` parameter to `ddlog_run()`).
+ * The function fails without modifying the table if `table` is not a valid
+ * input table id or if any of the records does not match its record type.
+ *
+ * Whether the function succeeds or fails, it takes ownership of all records
+ * in the `recs` array (but not the array itself).
+ */
+extern int ddlog_insert_records(ddlog_prog prog, table_id table,
+                                ddlog_record **recs, size_t n);
+
 /*
  * Apply updates, serialized into a FlatBuffer, to DDlog tables.
  *
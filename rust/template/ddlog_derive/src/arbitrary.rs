@@ -0,0 +1,114 @@
+use super::add_trait_bounds;
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{parse_quote, Data, DataEnum, DataStruct, DeriveInput, Error, Fields, Ident, Result};
+
+pub fn arbitrary_inner(input: DeriveInput) -> Result<TokenStream> {
+    // The name of the struct
+    let struct_ident = input.ident;
+
+    // Add the required trait bounds
+    let generics = add_trait_bounds(
+        input.generics,
+        vec![
+            parse_quote!(differential_datalog::proptest::arbitrary::Arbitrary),
+            parse_quote!('static),
+        ],
+    );
+    let (impl_generics, type_generics, where_clause) = generics.split_for_impl();
+
+    let strategy = match input.data {
+        // Derive for structs
+        Data::Struct(derive_struct) => arbitrary_struct(derive_struct),
+
+        // Derive for enums
+        Data::Enum(derive_enum) => arbitrary_enum(&struct_ident, derive_enum)?,
+
+        // Unions can't safely/soundly be automatically implemented over,
+        // the user will have to manually enforce invariants on it
+        Data::Union(union) => {
+            return Err(Error::new_spanned(
+                union.union_token,
+                "`Arbitrary` cannot be automatically implemented on unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        #[automatically_derived]
+        impl #impl_generics differential_datalog::proptest::arbitrary::Arbitrary for #struct_ident #type_generics #where_clause {
+            type Parameters = ();
+            type Strategy = differential_datalog::proptest::strategy::BoxedStrategy<Self>;
+
+            fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+                differential_datalog::proptest::strategy::Strategy::boxed(#strategy)
+            }
+        }
+    })
+}
+
+fn arbitrary_struct(derive_struct: DataStruct) -> TokenStream {
+    constructor_strategy(quote! { Self }, &derive_struct.fields)
+}
+
+/// Pick one of the enum's variants with equal probability
+fn arbitrary_enum(enum_ident: &Ident, derive_enum: DataEnum) -> Result<TokenStream> {
+    if derive_enum.variants.is_empty() {
+        return Err(Error::new_spanned(
+            enum_ident,
+            "`Arbitrary` cannot be automatically implemented on enums without variants",
+        ));
+    }
+
+    let variants = derive_enum.variants.iter().map(|variant| {
+        let variant_ident = &variant.ident;
+        let strategy = constructor_strategy(quote! { Self::#variant_ident }, &variant.fields);
+
+        quote! {
+            differential_datalog::proptest::strategy::Strategy::boxed(#strategy)
+        }
+    });
+
+    Ok(quote! {
+        differential_datalog::proptest::strategy::Union::new(std::vec![#( #variants ),*])
+    })
+}
+
+/// Generate a strategy that builds `constructor` out of arbitrary values of its fields
+///
+/// The fields' strategies are combined into nested pairs, `(field0, (field1, ..., ()))`,
+/// which, unlike flat tuples, are not limited in size.
+fn constructor_strategy(constructor: TokenStream, fields: &Fields) -> TokenStream {
+    let field_idents: Vec<_> = (0..fields.len())
+        .map(|idx| format_ident!("field{}", idx))
+        .collect();
+
+    let (strategy, pattern) = fields.iter().zip(field_idents.iter()).rev().fold(
+        (
+            quote! { differential_datalog::proptest::strategy::Just(()) },
+            quote! { () },
+        ),
+        |(strategy, pattern), (field, ident)| {
+            let field_type = &field.ty;
+            (
+                quote! {
+                    (differential_datalog::proptest::arbitrary::any::<#field_type>(), #strategy)
+                },
+                quote! { (#ident, #pattern) },
+            )
+        },
+    );
+
+    let value = match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            quote! { #constructor { #( #names: #field_idents ),* } }
+        }
+        Fields::Unnamed(_) => quote! { #constructor(#( #field_idents ),*) },
+        Fields::Unit => constructor,
+    };
+
+    quote! {
+        differential_datalog::proptest::strategy::Strategy::prop_map(#strategy, |#pattern| #value)
+    }
+}
@@ -10,6 +10,7 @@ use syn::{
     MetaNameValue, Result, TypeParamBound,
 };
 
+mod arbitrary;
 mod from_record;
 mod into_record;
 mod mutator;
@@ -93,6 +94,32 @@ pub fn derive_mutator(input: proc_macro::TokenStream) -> proc_macro::TokenStream
         .into()
 }
 
+/// Allows deriving `proptest::arbitrary::Arbitrary` for structs and enums
+///
+/// The generated strategy builds values out of arbitrary values of every field, picking
+/// enum variants with equal probability.  The implementation refers to `proptest` through
+/// `differential_datalog::proptest`, which requires differential_datalog's `proptest`
+/// feature to be enabled.  Recursive types are not supported.
+///
+/// ```rust
+/// # use ddlog_derive::Arbitrary;
+///
+/// #[derive(Arbitrary, Debug)]
+/// enum Foo {
+///     Bar { x: u32 },
+///     Baz(String, bool),
+/// }
+/// ```
+///
+#[proc_macro_derive(Arbitrary, attributes(ddlog))]
+pub fn derive_arbitrary(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    arbitrary::arbitrary_inner(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
 /// Add a trait bound to every generic, skipping the addition if the generic
 /// already has the required trait bound
 fn add_trait_bounds(mut generics: Generics, bounds: Vec<TypeParamBound>) -> Generics {
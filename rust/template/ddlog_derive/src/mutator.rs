@@ -172,6 +172,9 @@ fn named_struct_mutator<'a>(
             if let Some(r#__ddlog_generated__field_record) = differential_datalog::record::arg_find(#args, #field_record_name) {
                 <dyn differential_datalog::record::Mutator<#field_ty>>::mutate(r#__ddlog_generated__field_record, #field_ident)?;
             }
+            if let Some(r#__ddlog_generated__field_path) = differential_datalog::record::arg_find_path(#args, #field_record_name) {
+                <dyn differential_datalog::record::Mutator<#field_ty>>::mutate(&r#__ddlog_generated__field_path, #field_ident)?;
+            }
         })
     })
     .collect::<Result<TokenStream>>()?;
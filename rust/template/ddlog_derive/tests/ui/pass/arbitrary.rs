@@ -0,0 +1,57 @@
+use ddlog_derive::Arbitrary;
+use differential_datalog::proptest::{
+    arbitrary::{any, Arbitrary},
+    strategy::{BoxedStrategy, Strategy, ValueTree},
+    test_runner::TestRunner,
+};
+
+fn main() {
+    let mut runner = TestRunner::default();
+    for _ in 0..100 {
+        let value = any::<Enum>().new_tree(&mut runner).unwrap().current();
+        if let Enum::Struct(Struct { small, .. }) = value {
+            assert!(small.0 < 16);
+        }
+        let _ = any::<Generic<Unit, u8>>()
+            .new_tree(&mut runner)
+            .unwrap()
+            .current();
+    }
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq)]
+struct Unit;
+
+#[derive(Arbitrary, Debug, Clone, PartialEq)]
+struct Struct {
+    small: Small,
+    name: String,
+    flags: (bool, bool),
+}
+
+/// A type with a hand-written strategy that generates values below 16.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Small(u8);
+
+impl Arbitrary for Small {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Small>;
+
+    fn arbitrary_with(_args: ()) -> Self::Strategy {
+        (0..16u8).prop_map(Small).boxed()
+    }
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq)]
+enum Enum {
+    Empty,
+    Tuple(u32, i64),
+    Struct(Struct),
+    Named { x: u16, y: Vec<u8> },
+}
+
+#[derive(Arbitrary, Debug, Clone, PartialEq)]
+struct Generic<A, B> {
+    a: A,
+    b: Option<B>,
+}
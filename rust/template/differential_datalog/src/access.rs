@@ -0,0 +1,198 @@
+//! Relation-level access control.
+//!
+//! An `AccessPolicy` is a closure registered with a running program that is
+//! invoked for every update applied to an input relation and every query of
+//! a relation, together with the `CallerContext` of the thread issuing the
+//! request.  Front ends that serve several clients (e.g., an HTTP or RPC
+//! server) set the caller context once per request using
+//! `with_caller_context()`, and the policy decides whether the request is
+//! allowed, e.g., to enforce per-tenant or read-only access.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use crate::program::{RelId, Update};
+
+/// Kind of access to a relation checked by an `AccessPolicy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Insert a record into an input relation (`Update::Insert` or
+    /// `Update::InsertOrUpdate`).
+    Insert,
+    /// Delete a record from an input relation (`Update::DeleteValue` or
+    /// `Update::DeleteKey`).
+    Delete,
+    /// Modify a record of an input relation (`Update::Modify`).
+    Modify,
+    /// Delete all records of an input relation.
+    Clear,
+    /// Read the contents of a relation: query or dump one of its indexes,
+    /// dump the relation, or take a snapshot of it.
+    Query,
+}
+
+impl Operation {
+    /// The operation performed by `update`.
+    pub fn of_update<V>(update: &Update<V>) -> Self {
+        match update {
+            Update::Insert { .. } | Update::InsertOrUpdate { .. } => Operation::Insert,
+            Update::DeleteValue { .. } | Update::DeleteKey { .. } => Operation::Delete,
+            Update::Modify { .. } => Operation::Modify,
+        }
+    }
+
+    /// Returns `true` for operations that do not modify the relation.
+    pub fn is_read_only(self) -> bool {
+        self == Operation::Query
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Operation::Insert => "insert",
+            Operation::Delete => "delete",
+            Operation::Modify => "modify",
+            Operation::Clear => "clear",
+            Operation::Query => "query",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Identity of the client on whose behalf the current thread accesses the
+/// program.  The meaning of the fields is up to the `AccessPolicy`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallerContext {
+    /// The user or service issuing the request; `None` for anonymous
+    /// callers.
+    pub principal: Option<String>,
+    /// The tenant the caller belongs to.
+    pub tenant: Option<String>,
+    /// Other attributes of the caller, e.g., roles or scopes.
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// A request checked by an `AccessPolicy`.
+#[derive(Debug, Clone, Copy)]
+pub struct AccessRequest<'a> {
+    pub relid: RelId,
+    /// Name of the relation.
+    pub relation: &'a str,
+    pub operation: Operation,
+    pub caller: &'a CallerContext,
+}
+
+/// Decides whether a request is allowed.  Returns the reason for denying
+/// the request as an error.
+pub type AccessPolicy = Arc<dyn Fn(&AccessRequest) -> Result<(), String> + Send + Sync>;
+
+thread_local! {
+    static CALLER: RefCell<Option<Arc<CallerContext>>> = RefCell::new(None);
+}
+
+/// Restores the previous caller context when dropped, even if the closure
+/// passed to `with_caller_context()` panics.
+struct CallerGuard(Option<Arc<CallerContext>>);
+
+impl Drop for CallerGuard {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CALLER.with(|caller| *caller.borrow_mut() = previous);
+    }
+}
+
+/// Run `f` on behalf of `caller`: all requests made by the current thread
+/// inside `f` are checked against the access policy with this context.
+/// Calls can be nested; the innermost context applies.
+pub fn with_caller_context<T, F>(caller: CallerContext, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let previous = CALLER.with(|c| c.borrow_mut().replace(Arc::new(caller)));
+    let _guard = CallerGuard(previous);
+    f()
+}
+
+/// The caller context of the current thread.  Outside of
+/// `with_caller_context()`, this is the default (anonymous) context.
+pub fn caller_context() -> Arc<CallerContext> {
+    CALLER.with(|c| c.borrow().clone().unwrap_or_default())
+}
+
+/// The access policy of a program.  Without a policy, all requests are
+/// allowed.
+#[derive(Default)]
+pub struct AccessControl {
+    policy: RwLock<Option<AccessPolicy>>,
+}
+
+impl fmt::Debug for AccessControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AccessControl")
+            .field("enabled", &self.policy.read().unwrap().is_some())
+            .finish()
+    }
+}
+
+impl AccessControl {
+    /// Install `policy`, replacing the previous one, or remove the policy if
+    /// `None`.
+    pub fn set_policy(&self, policy: Option<AccessPolicy>) {
+        *self.policy.write().unwrap() = policy;
+    }
+
+    /// Check whether the current caller may perform `operation` on
+    /// relation `relid` named `relation`.
+    pub fn check(&self, relid: RelId, relation: &str, operation: Operation) -> Result<(), String> {
+        let policy = match &*self.policy.read().unwrap() {
+            None => return Ok(()),
+            Some(policy) => policy.clone(),
+        };
+        let caller = caller_context();
+        policy(&AccessRequest {
+            relid,
+            relation,
+            operation,
+            caller: &caller,
+        })
+        .map_err(|e| format!("{} on relation {} denied: {}", operation, relation, e))
+    }
+}
+
+#[test]
+fn test_access_control() {
+    let access = AccessControl::default();
+    assert_eq!(access.check(1, "R", Operation::Insert), Ok(()));
+
+    // Anonymous callers have read-only access; tenants can only access
+    // relations prefixed with their name.
+    access.set_policy(Some(Arc::new(|req: &AccessRequest| {
+        match &req.caller.tenant {
+            None if req.operation.is_read_only() => Ok(()),
+            None => Err("read-only access".to_string()),
+            Some(tenant) if req.relation.starts_with(tenant.as_str()) => Ok(()),
+            Some(tenant) => Err(format!("not accessible to tenant {}", tenant)),
+        }
+    })));
+    assert_eq!(access.check(1, "acme.R", Operation::Query), Ok(()));
+    assert_eq!(
+        access.check(1, "acme.R", Operation::Insert),
+        Err("insert on relation acme.R denied: read-only access".to_string())
+    );
+
+    let acme = CallerContext {
+        tenant: Some("acme".to_string()),
+        ..CallerContext::default()
+    };
+    with_caller_context(acme, || {
+        assert_eq!(access.check(1, "acme.R", Operation::Clear), Ok(()));
+        assert!(access.check(2, "globex.R", Operation::Query).is_err());
+    });
+    assert!(access.check(1, "acme.R", Operation::Clear).is_err());
+
+    access.set_policy(None);
+    assert_eq!(access.check(1, "acme.R", Operation::Clear), Ok(()));
+}
@@ -0,0 +1,233 @@
+//! Atomic transactions across multiple DDlog programs.
+//!
+//! Deployments that run several cooperating programs, e.g., one per shard or
+//! one per stage of a pipeline, sometimes need a logical transaction to
+//! either apply to all programs or to none of them.  `DistributedTransaction`
+//! implements two-phase commit over a set of participants: it starts a
+//! transaction in each of them, and, when committed, prepares all
+//! participants (`DDlogDynamic::transaction_prepare()`) before committing
+//! any.  If a participant fails to prepare, e.g., because a user function
+//! panicked, the transaction is rolled back everywhere.
+//!
+//! The coordinator keeps its state in memory: it coordinates programs
+//! running in the same process, or remote programs reached through
+//! `DDlogDynamic` implementations that forward requests over the network,
+//! but does not recover in-doubt transactions if the coordinator itself
+//! fails between the two phases.
+
+use crate::ddlog::DDlogDynamic;
+
+/// A transaction spanning multiple programs.  Updates are applied to each
+/// participant directly (see `participant()`).  Dropping the transaction
+/// without committing it rolls it back.
+pub struct DistributedTransaction<'a> {
+    participants: Vec<&'a dyn DDlogDynamic>,
+    finished: bool,
+}
+
+impl<'a> DistributedTransaction<'a> {
+    /// Start a transaction in each of `participants`.  Fails, rolling back
+    /// the transactions already started, if any participant fails to start
+    /// a transaction.
+    pub fn start(participants: Vec<&'a dyn DDlogDynamic>) -> Result<Self, String> {
+        for (i, p) in participants.iter().enumerate() {
+            if let Err(e) = p.transaction_start() {
+                for started in &participants[..i] {
+                    let _ = started.transaction_rollback();
+                }
+                return Err(format!(
+                    "participant {}: failed to start transaction: {}",
+                    i, e
+                ));
+            }
+        }
+        Ok(Self {
+            participants,
+            finished: false,
+        })
+    }
+
+    /// The number of participants in the transaction.
+    pub fn len(&self) -> usize {
+        self.participants.len()
+    }
+
+    /// Returns `true` if the transaction has no participants.
+    pub fn is_empty(&self) -> bool {
+        self.participants.is_empty()
+    }
+
+    /// The `i`th participant, to which updates are applied as part of the
+    /// transaction.
+    pub fn participant(&self, i: usize) -> &'a dyn DDlogDynamic {
+        self.participants[i]
+    }
+
+    /// Commit the transaction in all participants, or in none of them.
+    ///
+    /// Fails if a participant cannot prepare the transaction, in which case
+    /// it is rolled back in all participants.  Once all participants have
+    /// prepared, the transaction is committed in all of them; a failure at
+    /// this point (e.g., a participant that has been stopped) is reported,
+    /// but does not prevent the remaining participants from committing.
+    pub fn commit(mut self) -> Result<(), String> {
+        self.finished = true;
+
+        for (i, p) in self.participants.iter().enumerate() {
+            if let Err(e) = p.transaction_prepare() {
+                let _ = self.rollback_all();
+                return Err(format!(
+                    "participant {}: failed to prepare transaction: {}",
+                    i, e
+                ));
+            }
+        }
+
+        let errors: Vec<String> = self
+            .participants
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| {
+                p.transaction_commit()
+                    .err()
+                    .map(|e| format!("participant {}: {}", i, e))
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "failed to commit prepared transaction: {}",
+                errors.join("; ")
+            ))
+        }
+    }
+
+    /// Roll back the transaction in all participants.
+    pub fn rollback(mut self) -> Result<(), String> {
+        self.finished = true;
+        self.rollback_all()
+    }
+
+    fn rollback_all(&self) -> Result<(), String> {
+        let errors: Vec<String> = self
+            .participants
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| {
+                p.transaction_rollback()
+                    .err()
+                    .map(|e| format!("participant {}: {}", i, e))
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "failed to roll back transaction: {}",
+                errors.join("; ")
+            ))
+        }
+    }
+}
+
+impl<'a> Drop for DistributedTransaction<'a> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.rollback_all();
+        }
+    }
+}
+
+#[test]
+fn test_distributed_transaction() {
+    use crate::program::IdxId;
+    use crate::program::RelId;
+    use crate::record::{Record, UpdCmd};
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    /// Records the transaction commands it receives.
+    #[derive(Default)]
+    struct Participant {
+        log: Mutex<Vec<&'static str>>,
+        fail_prepare: bool,
+    }
+
+    impl Participant {
+        fn log(&self, cmd: &'static str) -> Result<(), String> {
+            self.log.lock().unwrap().push(cmd);
+            Ok(())
+        }
+
+        fn commands(&self) -> Vec<&'static str> {
+            self.log.lock().unwrap().drain(..).collect()
+        }
+    }
+
+    impl DDlogDynamic for Participant {
+        fn transaction_start(&self) -> Result<(), String> {
+            self.log("start")
+        }
+        fn transaction_commit_dump_changes_dynamic(
+            &self,
+        ) -> Result<BTreeMap<RelId, Vec<(Record, isize)>>, String> {
+            self.log("commit").map(|_| BTreeMap::new())
+        }
+        fn transaction_prepare(&self) -> Result<(), String> {
+            if self.fail_prepare {
+                return Err("poisoned".to_string());
+            }
+            self.log("prepare")
+        }
+        fn transaction_commit(&self) -> Result<(), String> {
+            self.log("commit")
+        }
+        fn transaction_rollback(&self) -> Result<(), String> {
+            self.log("rollback")
+        }
+        fn apply_updates_dynamic(
+            &self,
+            _upds: &mut dyn Iterator<Item = UpdCmd>,
+        ) -> Result<(), String> {
+            self.log("update")
+        }
+        fn clear_relation(&self, _table: RelId) -> Result<(), String> {
+            self.log("clear")
+        }
+        fn query_index_dynamic(&self, _index: IdxId, _key: &Record) -> Result<Vec<Record>, String> {
+            Ok(vec![])
+        }
+        fn dump_index_dynamic(&self, _index: IdxId) -> Result<Vec<Record>, String> {
+            Ok(vec![])
+        }
+        fn stop(&self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    let p1 = Participant::default();
+    let p2 = Participant::default();
+    let txn = DistributedTransaction::start(vec![&p1, &p2]).unwrap();
+    txn.participant(1).clear_relation(0).unwrap();
+    txn.commit().unwrap();
+    assert_eq!(p1.commands(), vec!["start", "prepare", "commit"]);
+    assert_eq!(p2.commands(), vec!["start", "clear", "prepare", "commit"]);
+
+    // A participant that fails to prepare aborts the transaction everywhere.
+    let p3 = Participant {
+        fail_prepare: true,
+        ..Participant::default()
+    };
+    let txn = DistributedTransaction::start(vec![&p1, &p3]).unwrap();
+    assert_eq!(
+        txn.commit(),
+        Err("participant 1: failed to prepare transaction: poisoned".to_string())
+    );
+    assert_eq!(p1.commands(), vec!["start", "prepare", "rollback"]);
+    assert_eq!(p3.commands(), vec!["start", "rollback"]);
+
+    // Dropping the transaction rolls it back.
+    drop(DistributedTransaction::start(vec![&p1]).unwrap());
+    assert_eq!(p1.commands(), vec!["start", "rollback"]);
+}
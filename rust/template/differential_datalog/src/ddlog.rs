@@ -114,6 +114,17 @@ pub trait DDlogDynamic {
         &self,
     ) -> Result<BTreeMap<RelId, Vec<(Record, isize)>>, String>;
 
+    /// Prepare the transaction previously started using
+    /// `transaction_start` for commit: after a successful prepare, the
+    /// transaction cannot be modified, and `transaction_commit` cannot fail.
+    /// Used to commit a transaction across multiple programs atomically
+    /// (see `coordinator::DistributedTransaction`).
+    ///
+    /// Implementations that cannot prepare transactions fail.
+    fn transaction_prepare(&self) -> Result<(), String> {
+        Err("transaction_prepare: not supported".to_string())
+    }
+
     /// Commit a transaction previously started using
     /// `transaction_start`.
     fn transaction_commit(&self) -> Result<(), String>;
@@ -0,0 +1,182 @@
+//! Batch allocation of heap-allocated `DDValue`s.
+//!
+//! Converting a large number of values one by one allocates a separate `Arc`
+//! for each of them.  `DDValConvert::into_ddvalue_batch()` instead moves all
+//! values of a batch into a single block of memory allocated at once.  Every
+//! value in the block is reference counted separately and is dropped as soon
+//! as its last `DDValue` goes away; the block itself is released once all
+//! its values have been dropped.  As with slab allocation, `DDVal` points
+//! directly to the value, and batch-allocated values are released by the
+//! `drop` method of a separate vtable (see `DDValConvert::BATCH_VTABLE`).
+
+use std::{
+    cell::UnsafeCell,
+    mem::{align_of, size_of, MaybeUninit},
+    ptr,
+    sync::atomic::{self, AtomicUsize, Ordering},
+};
+
+#[repr(C)]
+struct Slot<T> {
+    refs: AtomicUsize,
+    block: *const Block<T>,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Slot<T> {
+    /// Offset of `value` from the start of the slot.  `Slot` is `repr(C)`,
+    /// so the value follows the two header fields, padded to its alignment.
+    fn value_offset() -> usize {
+        let header = size_of::<AtomicUsize>() + size_of::<*const Block<T>>();
+        let align = align_of::<T>();
+        (header + align - 1) & !(align - 1)
+    }
+
+    /// Recover the slot from a pointer to its value.
+    unsafe fn from_value_ptr<'a>(value: usize) -> &'a Slot<T> {
+        &*((value - Self::value_offset()) as *const Slot<T>)
+    }
+}
+
+/// A batch of values allocated together.
+struct Block<T> {
+    /// The number of slots whose values have not been dropped yet.
+    live: AtomicUsize,
+    slots: Box<[Slot<T>]>,
+}
+
+/// Move `values` into a newly allocated block.  Returns pointers to the
+/// values, in the same order.
+pub(super) fn alloc<T>(values: Vec<T>) -> Vec<usize> {
+    let n = values.len();
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let block = Box::into_raw(Box::new(Block {
+        live: AtomicUsize::new(n),
+        slots: Box::new([]),
+    }));
+    let slots: Box<[Slot<T>]> = values
+        .into_iter()
+        .map(|value| Slot {
+            refs: AtomicUsize::new(1),
+            block,
+            value: UnsafeCell::new(MaybeUninit::new(value)),
+        })
+        .collect();
+
+    // Safety: the block is not shared with anyone yet.
+    let base = unsafe {
+        (*block).slots = slots;
+        (*block).slots.as_ptr() as usize
+    };
+    (0..n)
+        .map(|i| base + i * size_of::<Slot<T>>() + Slot::<T>::value_offset())
+        .collect()
+}
+
+/// Increment the reference count of a batch-allocated value.
+///
+/// # Safety
+///
+/// `value` must be a pointer returned by `alloc::<T>()` whose reference
+/// count has not dropped to zero.
+pub(super) unsafe fn retain<T>(value: usize) {
+    Slot::<T>::from_value_ptr(value)
+        .refs
+        .fetch_add(1, Ordering::Relaxed);
+}
+
+/// Mark the value in `slot` as dropped, releasing the block if this was its
+/// last live value.
+unsafe fn free_slot<T>(slot: &Slot<T>) {
+    let block = slot.block;
+    if (*block).live.fetch_sub(1, Ordering::Release) == 1 {
+        atomic::fence(Ordering::Acquire);
+        drop(Box::from_raw(block as *mut Block<T>));
+    }
+}
+
+/// Decrement the reference count of a batch-allocated value, dropping it
+/// when the count reaches zero.
+///
+/// # Safety
+///
+/// Same as `retain()`.
+pub(super) unsafe fn release<T>(value: usize) {
+    let slot = Slot::<T>::from_value_ptr(value);
+    if slot.refs.fetch_sub(1, Ordering::Release) == 1 {
+        atomic::fence(Ordering::Acquire);
+        ptr::drop_in_place(value as *mut T);
+        free_slot(slot);
+    }
+}
+
+/// Returns a mutable reference to a batch-allocated value if there are no other
+/// references to it.
+///
+/// # Safety
+///
+/// Same as `retain()`.
+pub(super) unsafe fn get_mut<'a, T>(value: usize) -> Option<&'a mut T> {
+    let slot = Slot::<T>::from_value_ptr(value);
+    if slot.refs.load(Ordering::Acquire) == 1 {
+        Some(&mut *(value as *mut T))
+    } else {
+        None
+    }
+}
+
+/// Extract a batch-allocated value, consuming one reference to it.  The
+/// value is moved out of the block if this was the last reference and
+/// cloned otherwise.
+///
+/// # Safety
+///
+/// Same as `retain()`.
+pub(super) unsafe fn take<T: Clone>(value: usize) -> T {
+    let slot = Slot::<T>::from_value_ptr(value);
+    if slot.refs.load(Ordering::Acquire) == 1 {
+        let v = ptr::read(value as *const T);
+        slot.refs.store(0, Ordering::Relaxed);
+        free_slot(slot);
+        v
+    } else {
+        let v = (*(value as *const T)).clone();
+        release::<T>(value);
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn values_share_block() {
+        let vals = alloc(vec![(1u64, 2u64), (3, 4), (5, 6)]);
+        assert_eq!(vals.len(), 3);
+        assert_eq!(vals[1] - vals[0], size_of::<Slot<(u64, u64)>>());
+        unsafe {
+            assert_eq!(*(vals[2] as *const (u64, u64)), (5, 6));
+            retain::<(u64, u64)>(vals[0]);
+            release::<(u64, u64)>(vals[0]);
+            assert_eq!(take::<(u64, u64)>(vals[0]), (1, 2));
+            assert_eq!(take::<(u64, u64)>(vals[1]), (3, 4));
+            release::<(u64, u64)>(vals[2]);
+        }
+    }
+
+    #[test]
+    fn values_are_dropped_individually() {
+        let counter = Arc::new(());
+        let vals = alloc(vec![Arc::clone(&counter), Arc::clone(&counter)]);
+        assert_eq!(Arc::strong_count(&counter), 3);
+        unsafe { release::<Arc<()>>(vals[0]) };
+        assert_eq!(Arc::strong_count(&counter), 2);
+        unsafe { release::<Arc<()>>(vals[1]) };
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+}
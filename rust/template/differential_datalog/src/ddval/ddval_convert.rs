@@ -1,5 +1,5 @@
 use crate::{
-    ddval::{DDVal, DDValMethods, DDValue},
+    ddval::{batch, hashed, intern::intern_arc, slab, DDVal, DDValMethods, DDValue},
     record::{IntoRecord, Mutator, Record},
 };
 use std::{
@@ -7,7 +7,7 @@ use std::{
     cmp::Ordering,
     fmt::{self, Debug, Display, Formatter},
     hash::{Hash, Hasher},
-    mem::{self, align_of, size_of, ManuallyDrop},
+    mem::{self, align_of, size_of, ManuallyDrop, MaybeUninit},
     sync::Arc,
 };
 
@@ -60,6 +60,8 @@ pub trait DDValConvert: Sized {
     /// # Safety
     ///
     /// `value` **must** be the same type as the type the `DDValue` was created with
+    /// and must have been created by `into_ddval()`.  Use `DDValMethods::take` to
+    /// extract values that may be slab-allocated.
     ///
     unsafe fn from_ddval(value: DDVal) -> Self;
 
@@ -74,9 +76,14 @@ pub trait DDValConvert: Sized {
     {
         let value_type = (value.vtable.type_id)(&value.val);
         if value_type == TypeId::of::<Self>() {
+            let take = value.vtable.take;
+            let mut res = MaybeUninit::<Self>::uninit();
             // Safety: The type we're turning the value into is the same as the one
             //         it was created with
-            Some(unsafe { Self::from_ddval(value.into_ddval()) })
+            unsafe {
+                take(value.into_ddval(), res.as_mut_ptr() as *mut u8);
+                Some(res.assume_init())
+            }
         } else {
             None
         }
@@ -97,6 +104,22 @@ pub trait DDValConvert: Sized {
             .expect("attempted to convert a DDValue into the incorrect type")
     }
 
+    /// Converts a vector of `DDValue`s into values of the given type,
+    /// moving them into a single preallocated vector.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the values was created with a different type
+    ///
+    fn from_ddvalue_batch(values: Vec<DDValue>) -> Vec<Self>
+    where
+        Self: 'static,
+    {
+        let mut res = Vec::with_capacity(values.len());
+        res.extend(values.into_iter().map(Self::from_ddvalue));
+        res
+    }
+
     /// Convert a value to a `DDVal`, erasing its original type.
     ///
     /// This is a safe conversion that cannot fail.
@@ -108,8 +131,42 @@ pub trait DDValConvert: Sized {
     /// Converts the current value into a `DDValue`
     fn into_ddvalue(self) -> DDValue;
 
+    /// Converts a vector of values into `DDValue`s.  Values that do not fit
+    /// in a `usize` are moved into a single block of memory instead of
+    /// being allocated one by one, unless their type uses slab allocation
+    /// or hash caching.
+    fn into_ddvalue_batch(values: Vec<Self>) -> Vec<DDValue>;
+
+    /// Converts the current value into a `DDValue` that shares its heap
+    /// allocation with all equal values converted by this method (see
+    /// `ddval::Intern`).  Values small enough to be stored inline are not
+    /// interned.
+    fn into_ddvalue_interned(self) -> DDValue;
+
     /// The vtable containing all `DDValue` methods for the current type
     const VTABLE: DDValMethods;
+
+    /// The vtable of slab-allocated values of the current type (see
+    /// `ddval::set_slab_allocation()`)
+    const SLAB_VTABLE: DDValMethods;
+
+    /// The vtable of values of the current type stored with a precomputed
+    /// hash (see `ddval::set_hash_caching()`)
+    const HASHED_VTABLE: DDValMethods;
+
+    /// The vtable of values of the current type allocated by
+    /// `into_ddvalue_batch()`
+    const BATCH_VTABLE: DDValMethods;
+}
+
+/// Returns `true` if `this` and `other` are heap-allocated values of type `T`
+/// that share the same `Arc` (clones of the same value or interned values),
+/// and are therefore equal without looking at the value.
+fn same_arc<T>(this: &DDVal, other: &DDVal) -> bool {
+    let fits_in_usize =
+        size_of::<T>() <= size_of::<usize>() && align_of::<T>() <= align_of::<usize>();
+
+    !fits_in_usize && this.v == other.v
 }
 
 /// Implement `DDValConvert` for all types that satisfy its type constraints
@@ -176,7 +233,53 @@ where
     }
 
     fn into_ddvalue(self) -> DDValue {
-        DDValue::new(self.into_ddval(), &Self::VTABLE)
+        let fits_in_usize =
+            size_of::<Self>() <= size_of::<usize>() && align_of::<Self>() <= align_of::<usize>();
+
+        if !fits_in_usize && slab::slab_allocation_enabled::<Self>() {
+            let val = DDVal {
+                v: slab::alloc(self),
+            };
+            DDValue::new(val, &Self::SLAB_VTABLE)
+        } else if !fits_in_usize && hashed::hash_caching_enabled::<Self>() {
+            let val = DDVal {
+                v: hashed::alloc(self),
+            };
+            DDValue::new(val, &Self::HASHED_VTABLE)
+        } else {
+            DDValue::new(self.into_ddval(), &Self::VTABLE)
+        }
+    }
+
+    fn into_ddvalue_batch(values: Vec<Self>) -> Vec<DDValue> {
+        let fits_in_usize =
+            size_of::<Self>() <= size_of::<usize>() && align_of::<Self>() <= align_of::<usize>();
+
+        if fits_in_usize
+            || slab::slab_allocation_enabled::<Self>()
+            || hashed::hash_caching_enabled::<Self>()
+        {
+            values.into_iter().map(Self::into_ddvalue).collect()
+        } else {
+            batch::alloc(values)
+                .into_iter()
+                .map(|v| DDValue::new(DDVal { v }, &Self::BATCH_VTABLE))
+                .collect()
+        }
+    }
+
+    fn into_ddvalue_interned(self) -> DDValue {
+        let fits_in_usize =
+            size_of::<Self>() <= size_of::<usize>() && align_of::<Self>() <= align_of::<usize>();
+
+        if fits_in_usize {
+            self.into_ddvalue()
+        } else {
+            let val = DDVal {
+                v: Arc::into_raw(intern_arc(self)) as usize,
+            };
+            DDValue::new(val, &Self::VTABLE)
+        }
     }
 
     const VTABLE: DDValMethods = {
@@ -198,30 +301,72 @@ where
         let into_record =
             |this: DDVal| -> Record { unsafe { <Self>::from_ddval(this) }.into_record() };
 
-        let eq: unsafe fn(&DDVal, &DDVal) -> bool =
-            |this, other| unsafe { <Self>::from_ddval_ref(this).eq(<Self>::from_ddval_ref(other)) };
+        let eq: unsafe fn(&DDVal, &DDVal) -> bool = |this, other| unsafe {
+            same_arc::<Self>(this, other)
+                || <Self>::from_ddval_ref(this).eq(<Self>::from_ddval_ref(other))
+        };
 
         let partial_cmp: unsafe fn(&DDVal, &DDVal) -> Option<Ordering> = |this, other| unsafe {
-            <Self>::from_ddval_ref(this).partial_cmp(<Self>::from_ddval_ref(other))
+            if same_arc::<Self>(this, other) {
+                Some(Ordering::Equal)
+            } else {
+                <Self>::from_ddval_ref(this).partial_cmp(<Self>::from_ddval_ref(other))
+            }
         };
 
         let cmp: unsafe fn(&DDVal, &DDVal) -> Ordering = |this, other| unsafe {
-            <Self>::from_ddval_ref(this).cmp(<Self>::from_ddval_ref(other))
+            if same_arc::<Self>(this, other) {
+                Ordering::Equal
+            } else {
+                <Self>::from_ddval_ref(this).cmp(<Self>::from_ddval_ref(other))
+            }
         };
 
-        let hash = |this: &DDVal, mut state: &mut dyn Hasher| {
-            Hash::hash(unsafe { <Self>::from_ddval_ref(this) }, &mut state);
+        // Values are hashed via `value_hash()` so that the result does not depend
+        // on whether the value's hash is cached (see `HASHED_VTABLE`).
+        let hash = |this: &DDVal, state: &mut dyn Hasher| {
+            state.write_u64(hashed::value_hash(unsafe { <Self>::from_ddval_ref(this) }));
         };
 
+        // Values whose `Arc` is not shared with other `DDValue`s (or with the
+        // interning pool) are modified in place; shared values are copied on
+        // write.  Values stored inline are cheap to copy and are only replaced
+        // if the mutation succeeds.
         let mutate = |this: &mut DDVal, record: &Record| -> Result<(), String> {
-            let mut clone = unsafe { <Self>::from_ddval_ref(this) }.clone();
-            Mutator::mutate(record, &mut clone)?;
-            *this = clone.into_ddval();
+            let fits_in_usize = size_of::<Self>() <= size_of::<usize>()
+                && align_of::<Self>() <= align_of::<usize>();
 
-            Ok(())
+            if fits_in_usize {
+                let mut clone = unsafe { <Self>::from_ddval_ref(this) }.clone();
+                Mutator::mutate(record, &mut clone)?;
+                unsafe { *<*mut usize>::cast::<Self>(&mut this.v) = clone };
+
+                Ok(())
+            } else {
+                let mut arc = unsafe { ManuallyDrop::new(Arc::from_raw(this.v as *const Self)) };
+                if let Some(value) = Arc::get_mut(&mut arc) {
+                    return Mutator::mutate(record, value);
+                }
+
+                let mut clone = (**arc).clone();
+                Mutator::mutate(record, &mut clone)?;
+                mem::drop(ManuallyDrop::into_inner(arc));
+                this.v = Arc::into_raw(Arc::new(clone)) as usize;
+
+                Ok(())
+            }
         };
 
         let fmt_debug = |this: &DDVal, f: &mut Formatter| -> Result<(), fmt::Error> {
+            // `{:#?}` pretty-prints the value across multiple lines.
+            if f.alternate() {
+                return Display::fmt(
+                    &unsafe { <Self>::from_ddval_ref(this) }
+                        .clone()
+                        .into_record(),
+                    f,
+                );
+            }
             Debug::fmt(unsafe { <Self>::from_ddval_ref(this) }, f)
         };
 
@@ -252,6 +397,9 @@ where
 
         let type_id = |_this: &DDVal| -> TypeId { TypeId::of::<Self>() };
 
+        let take: unsafe fn(DDVal, *mut u8) =
+            |this, out| unsafe { out.cast::<Self>().write(<Self>::from_ddval(this)) };
+
         DDValMethods {
             clone,
             into_record,
@@ -265,6 +413,132 @@ where
             drop,
             ddval_serialize,
             type_id,
+            take,
+        }
+    };
+
+    // Slab-allocated values are always larger than `usize` and are accessed
+    // through a pointer, just like values stored in an `Arc`, so only the
+    // methods that copy, consume, or release values differ from `VTABLE`.
+    const SLAB_VTABLE: DDValMethods = {
+        let clone = |this: &DDVal| -> DDVal {
+            unsafe { slab::retain::<Self>(this.v) };
+            DDVal { v: this.v }
+        };
+
+        let into_record =
+            |this: DDVal| -> Record { unsafe { slab::take::<Self>(this.v) }.into_record() };
+
+        let mutate = |this: &mut DDVal, record: &Record| -> Result<(), String> {
+            if let Some(value) = unsafe { slab::get_mut::<Self>(this.v) } {
+                return Mutator::mutate(record, value);
+            }
+
+            let mut clone = unsafe { <Self>::from_ddval_ref(this) }.clone();
+            Mutator::mutate(record, &mut clone)?;
+            unsafe { slab::release::<Self>(this.v) };
+            this.v = slab::alloc(clone);
+
+            Ok(())
+        };
+
+        let drop = |this: &mut DDVal| unsafe { slab::release::<Self>(this.v) };
+
+        let take: unsafe fn(DDVal, *mut u8) =
+            |this, out| unsafe { out.cast::<Self>().write(slab::take::<Self>(this.v)) };
+
+        DDValMethods {
+            clone,
+            into_record,
+            mutate,
+            drop,
+            take,
+            ..Self::VTABLE
+        }
+    };
+
+    // Like `SLAB_VTABLE`, but for values allocated together with their hash.
+    const HASHED_VTABLE: DDValMethods = {
+        let clone = |this: &DDVal| -> DDVal {
+            DDVal {
+                v: unsafe { hashed::retain::<Self>(this.v) },
+            }
+        };
+
+        let into_record =
+            |this: DDVal| -> Record { unsafe { hashed::take::<Self>(this.v) }.into_record() };
+
+        let hash = |this: &DDVal, state: &mut dyn Hasher| {
+            state.write_u64(unsafe { hashed::cached_hash::<Self>(this.v) });
+        };
+
+        let mutate = |this: &mut DDVal, record: &Record| -> Result<(), String> {
+            if let Some(res) = unsafe {
+                hashed::modify_in_place::<Self, _>(this.v, |value| Mutator::mutate(record, value))
+            } {
+                return res;
+            }
+
+            let mut clone = unsafe { <Self>::from_ddval_ref(this) }.clone();
+            Mutator::mutate(record, &mut clone)?;
+            unsafe { hashed::release::<Self>(this.v) };
+            this.v = hashed::alloc(clone);
+
+            Ok(())
+        };
+
+        let drop = |this: &mut DDVal| unsafe { hashed::release::<Self>(this.v) };
+
+        let take: unsafe fn(DDVal, *mut u8) =
+            |this, out| unsafe { out.cast::<Self>().write(hashed::take::<Self>(this.v)) };
+
+        DDValMethods {
+            clone,
+            into_record,
+            hash,
+            mutate,
+            drop,
+            take,
+            ..Self::VTABLE
+        }
+    };
+
+    // Like `SLAB_VTABLE`, but for values allocated by `into_ddvalue_batch()`.
+    // A mutated value is moved to a block of its own.
+    const BATCH_VTABLE: DDValMethods = {
+        let clone = |this: &DDVal| -> DDVal {
+            unsafe { batch::retain::<Self>(this.v) };
+            DDVal { v: this.v }
+        };
+
+        let into_record =
+            |this: DDVal| -> Record { unsafe { batch::take::<Self>(this.v) }.into_record() };
+
+        let mutate = |this: &mut DDVal, record: &Record| -> Result<(), String> {
+            if let Some(value) = unsafe { batch::get_mut::<Self>(this.v) } {
+                return Mutator::mutate(record, value);
+            }
+
+            let mut clone = unsafe { <Self>::from_ddval_ref(this) }.clone();
+            Mutator::mutate(record, &mut clone)?;
+            unsafe { batch::release::<Self>(this.v) };
+            this.v = batch::alloc(vec![clone])[0];
+
+            Ok(())
+        };
+
+        let drop = |this: &mut DDVal| unsafe { batch::release::<Self>(this.v) };
+
+        let take: unsafe fn(DDVal, *mut u8) =
+            |this, out| unsafe { out.cast::<Self>().write(batch::take::<Self>(this.v)) };
+
+        DDValMethods {
+            clone,
+            into_record,
+            mutate,
+            drop,
+            take,
+            ..Self::VTABLE
         }
     };
 }
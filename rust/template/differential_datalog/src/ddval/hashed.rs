@@ -0,0 +1,175 @@
+//! Heap-allocated `DDValue`s with a precomputed hash.
+//!
+//! Differential dataflow hashes values every time it exchanges them between
+//! workers or inserts them into an arrangement.  For large composite values
+//! (e.g., wide structs used as join keys) rehashing every field each time is
+//! a measurable cost.  In hash caching mode, the hash of a value is computed
+//! once when the value is converted into a `DDValue` and stored next to the
+//! value in its `Arc`, at the cost of 8 extra bytes per value.
+//!
+//! To keep the hashes of equal values equal regardless of how they are
+//! stored, `DDValue`s are always hashed by feeding `value_hash()` of the
+//! underlying value to the hasher: the cached hash is simply that number
+//! computed in advance.
+//!
+//! Hash caching is enabled per type using `set_hash_caching()`.
+
+use fnv::{FnvHashSet, FnvHasher};
+use once_cell::sync::Lazy;
+use std::{
+    any::TypeId,
+    hash::{Hash, Hasher},
+    mem::{align_of, size_of, ManuallyDrop},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+};
+
+#[repr(C)]
+struct Hashed<T> {
+    hash: u64,
+    value: T,
+}
+
+/// Offset of `value` inside `Hashed<T>`.  `Hashed` is `repr(C)`, so the
+/// value follows the hash, padded to its alignment.
+fn value_offset<T>() -> usize {
+    let align = align_of::<T>();
+    (size_of::<u64>() + align - 1) & !(align - 1)
+}
+
+/// Types for which hash caching is enabled.
+static HASHED_TYPES: Lazy<RwLock<FnvHashSet<TypeId>>> =
+    Lazy::new(|| RwLock::new(FnvHashSet::default()));
+
+/// Set when hash caching is enabled for at least one type, so that programs
+/// that do not use it do not pay for the `HASHED_TYPES` lookup.
+static HASH_CACHING_IN_USE: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable hash caching for values of type `T`.
+///
+/// Only affects values that do not fit in a machine word and are converted
+/// into `DDValue`s after the call.  Types that use slab allocation (see
+/// `set_slab_allocation()`) are not affected.
+pub fn set_hash_caching<T: 'static>(enabled: bool) {
+    let mut types = HASHED_TYPES.write().unwrap();
+    if enabled {
+        types.insert(TypeId::of::<T>());
+    } else {
+        types.remove(&TypeId::of::<T>());
+    }
+    HASH_CACHING_IN_USE.store(!types.is_empty(), Ordering::Relaxed);
+}
+
+/// Returns `true` if hash caching is enabled for values of type `T`.
+pub fn hash_caching_enabled<T: 'static>() -> bool {
+    HASH_CACHING_IN_USE.load(Ordering::Relaxed)
+        && HASHED_TYPES.read().unwrap().contains(&TypeId::of::<T>())
+}
+
+/// The hash that represents `value` when hashing a `DDValue`.
+pub(super) fn value_hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = FnvHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Move `value` to the heap together with its hash.  Returns a pointer to
+/// the value.
+pub(super) fn alloc<T: Hash>(value: T) -> usize {
+    let hash = value_hash(&value);
+    Arc::into_raw(Arc::new(Hashed { hash, value })) as usize + value_offset::<T>()
+}
+
+/// Recover the `Arc` from a pointer returned by `alloc()`, without taking
+/// ownership of it.
+///
+/// # Safety
+///
+/// `value` must be a pointer returned by `alloc::<T>()` that has not been
+/// released.
+unsafe fn borrow_arc<T>(value: usize) -> ManuallyDrop<Arc<Hashed<T>>> {
+    ManuallyDrop::new(Arc::from_raw(
+        (value - value_offset::<T>()) as *const Hashed<T>,
+    ))
+}
+
+/// Returns the hash stored with a value.
+///
+/// # Safety
+///
+/// Same as `borrow_arc()`.
+pub(super) unsafe fn cached_hash<T>(value: usize) -> u64 {
+    borrow_arc::<T>(value).hash
+}
+
+/// Create another reference to a value.
+///
+/// # Safety
+///
+/// Same as `borrow_arc()`.
+pub(super) unsafe fn retain<T>(value: usize) -> usize {
+    let arc = borrow_arc::<T>(value);
+    Arc::into_raw(Arc::clone(&arc)) as usize + value_offset::<T>()
+}
+
+/// Release a reference to a value.
+///
+/// # Safety
+///
+/// Same as `borrow_arc()`.
+pub(super) unsafe fn release<T>(value: usize) {
+    ManuallyDrop::into_inner(borrow_arc::<T>(value));
+}
+
+/// Apply `f` to a value in place and update its hash, provided there are no
+/// other references to the value.  Returns `None` if the value is shared.
+///
+/// # Safety
+///
+/// Same as `borrow_arc()`.
+pub(super) unsafe fn modify_in_place<T: Hash, R>(
+    value: usize,
+    f: impl FnOnce(&mut T) -> R,
+) -> Option<R> {
+    let mut arc = borrow_arc::<T>(value);
+    let hashed = Arc::get_mut(&mut arc)?;
+    let res = f(&mut hashed.value);
+    hashed.hash = value_hash(&hashed.value);
+    Some(res)
+}
+
+/// Extract a value, consuming a reference to it.  The value is moved out if
+/// this was the last reference and cloned otherwise.
+///
+/// # Safety
+///
+/// Same as `borrow_arc()`.
+pub(super) unsafe fn take<T: Clone>(value: usize) -> T {
+    let arc = ManuallyDrop::into_inner(borrow_arc::<T>(value));
+    match Arc::try_unwrap(arc) {
+        Ok(hashed) => hashed.value,
+        Err(arc) => arc.value.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_stored_with_value() {
+        let value = (1u64, "cached".to_string());
+        let v = alloc(value.clone());
+        unsafe {
+            assert_eq!(&*(v as *const (u64, String)), &value);
+            assert_eq!(cached_hash::<(u64, String)>(v), value_hash(&value));
+
+            let v2 = retain::<(u64, String)>(v);
+            assert_eq!(v, v2);
+            release::<(u64, String)>(v2);
+            assert_eq!(take::<(u64, String)>(v), value);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+//! Interning of values stored in DD collections.
+//!
+//! Relations often contain the same value (a string, an enum constructor, a
+//! composite key) many times over.  Without interning, each copy converted
+//! into a `DDValue` gets its own heap allocation.  The interner keeps one
+//! `Arc` per distinct value in a global pool, so that all copies share the
+//! same allocation.  Since equal interned values are stored at the same
+//! address, the vtable methods of `DDValue` (see `ddval_convert.rs`) can
+//! compare them by pointer without looking at their contents.
+//!
+//! The pool is split into shards, each protected by its own lock, so that
+//! worker threads interning unrelated values rarely contend.  Values that are
+//! only referenced by the pool are released by `purge_interned()` and
+//! automatically whenever a shard doubles in size.
+
+use crate::record::{FromRecord, IntoRecord, Mutator, Record};
+use fnv::{FnvHashMap, FnvHashSet, FnvHasher};
+use once_cell::sync::Lazy;
+use serde::{
+    de::{Deserialize, Deserializer},
+    ser::{Serialize, Serializer},
+};
+use std::{
+    any::{Any, TypeId},
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt::{self, Debug, Display, Formatter},
+    hash::{Hash, Hasher},
+    ops::Deref,
+    sync::{Arc, Mutex, RwLock},
+};
+
+/// Number of independently locked shards in each pool.
+const SHARDS: usize = 16;
+
+/// A shard is not purged before it reaches this size.
+const MIN_PURGE_THRESHOLD: usize = 1024;
+
+/// A handle to an interned value.
+///
+/// Two `Intern`s created from equal values point to the same allocation, so
+/// equality is checked by comparing pointers.  Unlike the `Intern` type in
+/// DDlog's `internment` library, `Ord` and `Hash` are computed from the
+/// underlying value and are therefore deterministic across runs.
+pub struct Intern<T> {
+    arc: Arc<T>,
+}
+
+impl<T> Intern<T>
+where
+    T: Eq + Hash + Send + Sync + 'static,
+{
+    /// Intern `value`, reusing an existing copy from the pool if there is one.
+    pub fn new(value: T) -> Self {
+        Intern {
+            arc: intern_arc(value),
+        }
+    }
+}
+
+impl<T> Intern<T> {
+    /// Returns the shared allocation holding the value.
+    pub fn as_arc(&self) -> &Arc<T> {
+        &self.arc
+    }
+
+    /// Returns `true` if `this` and `other` point to the same value.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(&this.arc, &other.arc)
+    }
+}
+
+impl<T> Clone for Intern<T> {
+    fn clone(&self) -> Self {
+        Intern {
+            arc: Arc::clone(&self.arc),
+        }
+    }
+}
+
+impl<T> Deref for Intern<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.arc
+    }
+}
+
+impl<T> AsRef<T> for Intern<T> {
+    fn as_ref(&self) -> &T {
+        &self.arc
+    }
+}
+
+impl<T> From<T> for Intern<T>
+where
+    T: Eq + Hash + Send + Sync + 'static,
+{
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: PartialEq> PartialEq for Intern<T> {
+    fn eq(&self, other: &Self) -> bool {
+        // Values from the same pool are equal iff they share the allocation;
+        // fall back to comparing values only if the pointers differ, which
+        // is always the case for unequal values.
+        Intern::ptr_eq(self, other) || *self.arc == *other.arc
+    }
+}
+
+impl<T: Eq> Eq for Intern<T> {}
+
+impl<T: PartialOrd> PartialOrd for Intern<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if Intern::ptr_eq(self, other) {
+            Some(Ordering::Equal)
+        } else {
+            (*self.arc).partial_cmp(&*other.arc)
+        }
+    }
+}
+
+impl<T: Ord> Ord for Intern<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if Intern::ptr_eq(self, other) {
+            Ordering::Equal
+        } else {
+            (*self.arc).cmp(&*other.arc)
+        }
+    }
+}
+
+impl<T: Hash> Hash for Intern<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (*self.arc).hash(state)
+    }
+}
+
+impl<T: Debug> Debug for Intern<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Debug::fmt(&*self.arc, f)
+    }
+}
+
+impl<T: Display> Display for Intern<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&*self.arc, f)
+    }
+}
+
+impl<T: Serialize> Serialize for Intern<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        (*self.arc).serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Intern<T>
+where
+    T: Deserialize<'de> + Eq + Hash + Send + Sync + 'static,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Intern::new)
+    }
+}
+
+impl<T> FromRecord for Intern<T>
+where
+    T: FromRecord + Eq + Hash + Send + Sync + 'static,
+{
+    fn from_record(val: &Record) -> Result<Self, String> {
+        T::from_record(val).map(Intern::new)
+    }
+}
+
+impl<T: IntoRecord + Clone> IntoRecord for Intern<T> {
+    fn into_record(self) -> Record {
+        (*self.arc).clone().into_record()
+    }
+}
+
+impl<T> Mutator<Intern<T>> for Record
+where
+    T: Clone + Eq + Hash + Send + Sync + 'static,
+    Record: Mutator<T>,
+{
+    fn mutate(&self, x: &mut Intern<T>) -> Result<(), String> {
+        let mut v = (*x.arc).clone();
+        self.mutate(&mut v)?;
+        *x = Intern::new(v);
+        Ok(())
+    }
+}
+
+/// Pool entry.  Hashes and compares by value, so that the pool can be
+/// searched with a reference to the value being interned.
+struct Entry<T>(Arc<T>);
+
+impl<T: PartialEq> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        *self.0 == *other.0
+    }
+}
+
+impl<T: Eq> Eq for Entry<T> {}
+
+impl<T: Hash> Hash for Entry<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (*self.0).hash(state)
+    }
+}
+
+impl<T> Borrow<T> for Entry<T> {
+    fn borrow(&self) -> &T {
+        &self.0
+    }
+}
+
+struct Shard<T> {
+    entries: FnvHashSet<Entry<T>>,
+    /// Purge the shard when it reaches this size.
+    purge_threshold: usize,
+}
+
+impl<T> Shard<T> {
+    /// Drop values that are only referenced by the pool.  Returns the number
+    /// of values dropped.
+    fn purge(&mut self) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|e| Arc::strong_count(&e.0) > 1);
+        self.purge_threshold = MIN_PURGE_THRESHOLD.max(2 * self.entries.len());
+        before - self.entries.len()
+    }
+}
+
+/// Interning pool for values of type `T`.
+struct Pool<T> {
+    shards: Vec<Mutex<Shard<T>>>,
+}
+
+impl<T> Pool<T>
+where
+    T: Eq + Hash,
+{
+    fn new() -> Self {
+        Pool {
+            shards: (0..SHARDS)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        entries: FnvHashSet::default(),
+                        purge_threshold: MIN_PURGE_THRESHOLD,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    fn intern(&self, value: T) -> Arc<T> {
+        let mut hasher = FnvHasher::default();
+        value.hash(&mut hasher);
+        let mut shard = self.shards[hasher.finish() as usize % SHARDS]
+            .lock()
+            .unwrap();
+
+        if let Some(entry) = shard.entries.get(&value) {
+            return Arc::clone(&entry.0);
+        }
+        if shard.entries.len() >= shard.purge_threshold {
+            shard.purge();
+        }
+        let arc = Arc::new(value);
+        shard.entries.insert(Entry(Arc::clone(&arc)));
+        arc
+    }
+}
+
+/// Type-erased interface to a `Pool<T>`.
+trait AnyPool: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn purge(&self) -> usize;
+    fn size(&self) -> usize;
+}
+
+impl<T> AnyPool for Pool<T>
+where
+    T: Eq + Hash + Send + Sync + 'static,
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn purge(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().purge()).sum()
+    }
+
+    fn size(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.lock().unwrap().entries.len())
+            .sum()
+    }
+}
+
+/// Pools are created on first use and live for the rest of the program.
+static POOLS: Lazy<RwLock<FnvHashMap<TypeId, &'static dyn AnyPool>>> =
+    Lazy::new(|| RwLock::new(FnvHashMap::default()));
+
+fn pool<T>() -> &'static Pool<T>
+where
+    T: Eq + Hash + Send + Sync + 'static,
+{
+    let type_id = TypeId::of::<T>();
+    let existing = POOLS.read().unwrap().get(&type_id).copied();
+    let pool = match existing {
+        Some(pool) => pool,
+        None => *POOLS
+            .write()
+            .unwrap()
+            .entry(type_id)
+            .or_insert_with(|| Box::leak(Box::new(Pool::<T>::new()))),
+    };
+
+    pool.as_any()
+        .downcast_ref::<Pool<T>>()
+        .expect("interning pool registered under the wrong type")
+}
+
+/// Returns a shared allocation holding `value`, reusing an existing
+/// allocation from the pool if an equal value has been interned before.
+pub fn intern_arc<T>(value: T) -> Arc<T>
+where
+    T: Eq + Hash + Send + Sync + 'static,
+{
+    pool::<T>().intern(value)
+}
+
+/// Release interned values that are no longer referenced outside of the
+/// pool.  Returns the number of values released.
+pub fn purge_interned() -> usize {
+    POOLS.read().unwrap().values().map(|p| p.purge()).sum()
+}
+
+/// The number of distinct values currently held by all interning pools.
+pub fn interned_count() -> usize {
+    POOLS.read().unwrap().values().map(|p| p.size()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_values_share_allocation() {
+        let a = Intern::new("equal_values_share_allocation".to_string());
+        let b = Intern::new("equal_values_share_allocation".to_string());
+        let c = Intern::new("something else".to_string());
+
+        assert!(Intern::ptr_eq(&a, &b));
+        assert!(!Intern::ptr_eq(&a, &c));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(
+            a.cmp(&c),
+            "equal_values_share_allocation".cmp("something else")
+        );
+    }
+
+    #[test]
+    fn purge_releases_unused_values() {
+        #[derive(PartialEq, Eq, Hash)]
+        struct Key(u64);
+
+        let kept = Intern::new(Key(1));
+        drop(Intern::new(Key(2)));
+        assert_eq!(pool::<Key>().size(), 2);
+
+        pool::<Key>().purge();
+        assert_eq!(pool::<Key>().size(), 1);
+        assert!(Intern::ptr_eq(&kept, &Intern::new(Key(1))));
+    }
+}
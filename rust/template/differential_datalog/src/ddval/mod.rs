@@ -50,13 +50,35 @@
 //! DD's knowledge of the context where a value is being created to, e.g., allocate blocks of
 //! values when possible.
 //!
+//! Values that occur many times can be interned (see `Intern` and
+//! `DDValConvert::into_ddvalue_interned()`), so that all copies share one heap allocation.
+//! The vtable compares heap-allocated values that share an allocation by pointer.
+//!
+//! Alternatively, values of selected types can be allocated from per-type slabs (see
+//! `set_slab_allocation()`), which replaces one allocation per value with one allocation per
+//! chunk of values.  Slab-allocated values use a separate vtable that knows how to release them.
+//! Similarly, large values used as keys can be stored together with their precomputed hash (see
+//! `set_hash_caching()`), so that DD does not rehash them every time they are exchanged or
+//! arranged.
+//!
+//! Finally, `DDValConvert::into_ddvalue_batch()` converts a vector of values at once, moving all
+//! of them into a single block of memory (see `batch.rs`).  Adapters and FFI functions that ingest
+//! many values at a time use it to avoid one allocation per value.
+//!
 
+mod batch;
 #[macro_use]
 mod ddval_convert;
 mod ddvalue;
+mod hashed;
+mod intern;
+mod slab;
 
 pub use ddval_convert::DDValConvert;
 pub use ddvalue::DDValue;
+pub use hashed::{hash_caching_enabled, set_hash_caching};
+pub use intern::{intern_arc, interned_count, purge_interned, Intern};
+pub use slab::{set_slab_allocation, slab_allocation_enabled};
 
 use crate::record::Record;
 use std::{
@@ -88,10 +110,22 @@ pub struct DDValMethods {
     pub cmp: unsafe fn(this: &DDVal, other: &DDVal) -> Ordering,
 
     pub hash: fn(this: &DDVal, state: &mut dyn Hasher),
+
+    /// Applies `record` to the value (see `record::Mutator`).  A heap-allocated
+    /// value that is not shared with other `DDValue`s is modified in place,
+    /// without copying it, and may be left partially modified if the mutation
+    /// fails.
     pub mutate: fn(this: &mut DDVal, record: &Record) -> Result<(), String>,
+
     pub fmt_debug: fn(this: &DDVal, f: &mut Formatter) -> Result<(), Error>,
     pub fmt_display: fn(this: &DDVal, f: &mut Formatter) -> Result<(), Error>,
     pub drop: fn(this: &mut DDVal),
     pub ddval_serialize: fn(this: &DDVal) -> &dyn erased_serde::Serialize,
     pub type_id: fn(this: &DDVal) -> TypeId,
+
+    /// Moves the value out of `this` into `out`.
+    ///
+    /// Safety: `out` must point to properly aligned, writable memory for a value
+    /// of the type contained in `this`.
+    pub take: unsafe fn(this: DDVal, out: *mut u8),
 }
@@ -0,0 +1,273 @@
+//! Slab allocation of heap-allocated `DDValue`s.
+//!
+//! By default every value larger than a `usize` is stored in its own `Arc`,
+//! so loading millions of facts performs millions of small allocations.  The
+//! slab allocator instead carves values out of large chunks of memory set
+//! aside for one type, and recycles freed slots through a free list.  A slab
+//! allocated value has the same in-memory layout as far as `DDValue` is
+//! concerned (`DDVal` points directly to the value), but is reference counted
+//! by the slab and released by the `drop` method of a separate vtable (see
+//! `DDValConvert::SLAB_VTABLE`).
+//!
+//! Slab allocation is enabled per type using `set_slab_allocation()`.  Memory
+//! reserved for a type's slab is reused for values of the same type, but is
+//! never returned to the system allocator.
+
+use fnv::{FnvHashMap, FnvHashSet};
+use once_cell::sync::Lazy;
+use std::{
+    any::{Any, TypeId},
+    cell::UnsafeCell,
+    mem::{align_of, size_of, MaybeUninit},
+    ptr,
+    sync::{
+        atomic::{self, AtomicBool, AtomicUsize, Ordering},
+        Mutex, RwLock,
+    },
+};
+
+/// Approximate size of a chunk of slots allocated at once.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Minimal number of slots in a chunk.
+const MIN_CHUNK_SLOTS: usize = 16;
+
+#[repr(C)]
+struct Slot<T> {
+    refs: AtomicUsize,
+    slab: *const Slab<T>,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Slot<T> {
+    /// Offset of `value` from the start of the slot.  `Slot` is `repr(C)`,
+    /// so the value follows the two header fields, padded to its alignment.
+    fn value_offset() -> usize {
+        let header = size_of::<AtomicUsize>() + size_of::<*const Slab<T>>();
+        let align = align_of::<T>();
+        (header + align - 1) & !(align - 1)
+    }
+
+    /// Recover the slot from a pointer to its value.
+    unsafe fn from_value_ptr<'a>(value: usize) -> &'a Slot<T> {
+        &*((value - Self::value_offset()) as *const Slot<T>)
+    }
+}
+
+struct SlabState<T> {
+    /// Chunks are never deallocated, so pointers to slots remain valid.
+    chunks: Vec<Box<[Slot<T>]>>,
+    /// Addresses of unused slots.
+    free: Vec<usize>,
+}
+
+/// Slab of values of type `T`.
+struct Slab<T> {
+    state: Mutex<SlabState<T>>,
+}
+
+// Safety: slots are only accessed through `DDValue`s, which require
+// `T: Send + Sync`; the raw back pointer in each slot refers to the slab
+// itself, which is never deallocated.
+unsafe impl<T: Send + Sync> Send for Slab<T> {}
+unsafe impl<T: Send + Sync> Sync for Slab<T> {}
+
+impl<T> Slab<T> {
+    fn new() -> Self {
+        Slab {
+            state: Mutex::new(SlabState {
+                chunks: Vec::new(),
+                free: Vec::new(),
+            }),
+        }
+    }
+
+    fn alloc(&'static self, value: T) -> usize {
+        let slot_addr = {
+            let mut state = self.state.lock().unwrap();
+            if state.free.is_empty() {
+                let nslots = MIN_CHUNK_SLOTS.max(CHUNK_BYTES / size_of::<Slot<T>>().max(1));
+                let mut chunk: Box<[Slot<T>]> = (0..nslots)
+                    .map(|_| Slot {
+                        refs: AtomicUsize::new(0),
+                        slab: self as *const Slab<T>,
+                        value: UnsafeCell::new(MaybeUninit::uninit()),
+                    })
+                    .collect();
+                let base = chunk.as_mut_ptr() as usize;
+                state
+                    .free
+                    .extend((0..nslots).rev().map(|i| base + i * size_of::<Slot<T>>()));
+                state.chunks.push(chunk);
+            }
+            state.free.pop().unwrap()
+        };
+
+        // Safety: the slot is not in use, so nobody else accesses it.
+        unsafe {
+            let slot = slot_addr as *mut Slot<T>;
+            (*slot).refs.store(1, Ordering::Relaxed);
+            (*(*slot).value.get()).as_mut_ptr().write(value);
+        }
+        slot_addr + Slot::<T>::value_offset()
+    }
+
+    fn free(&self, slot: &Slot<T>) {
+        self.state
+            .lock()
+            .unwrap()
+            .free
+            .push(slot as *const Slot<T> as usize);
+    }
+}
+
+/// Slabs are created on first use and live for the rest of the program.
+static SLABS: Lazy<RwLock<FnvHashMap<TypeId, &'static (dyn Any + Send + Sync)>>> =
+    Lazy::new(|| RwLock::new(FnvHashMap::default()));
+
+/// Types for which slab allocation is enabled.
+static SLAB_TYPES: Lazy<RwLock<FnvHashSet<TypeId>>> =
+    Lazy::new(|| RwLock::new(FnvHashSet::default()));
+
+/// Set when slab allocation is enabled for at least one type, so that
+/// programs that do not use slabs do not pay for the `SLAB_TYPES` lookup.
+static SLABS_IN_USE: AtomicBool = AtomicBool::new(false);
+
+fn slab<T: Send + Sync + 'static>() -> &'static Slab<T> {
+    let type_id = TypeId::of::<T>();
+    let existing = SLABS.read().unwrap().get(&type_id).copied();
+    let slab = match existing {
+        Some(slab) => slab,
+        None => *SLABS
+            .write()
+            .unwrap()
+            .entry(type_id)
+            .or_insert_with(|| Box::leak(Box::new(Slab::<T>::new()))),
+    };
+
+    slab.downcast_ref::<Slab<T>>()
+        .expect("slab registered under the wrong type")
+}
+
+/// Enable or disable slab allocation for values of type `T`.
+///
+/// Only affects values converted into `DDValue`s after the call: existing
+/// values keep the representation they were created with.
+pub fn set_slab_allocation<T: 'static>(enabled: bool) {
+    let mut types = SLAB_TYPES.write().unwrap();
+    if enabled {
+        types.insert(TypeId::of::<T>());
+    } else {
+        types.remove(&TypeId::of::<T>());
+    }
+    SLABS_IN_USE.store(!types.is_empty(), Ordering::Relaxed);
+}
+
+/// Returns `true` if slab allocation is enabled for values of type `T`.
+pub fn slab_allocation_enabled<T: 'static>() -> bool {
+    SLABS_IN_USE.load(Ordering::Relaxed) && SLAB_TYPES.read().unwrap().contains(&TypeId::of::<T>())
+}
+
+/// Move `value` into the slab for type `T`.  Returns a pointer to the value.
+pub(super) fn alloc<T: Send + Sync + 'static>(value: T) -> usize {
+    slab::<T>().alloc(value)
+}
+
+/// Increment the reference count of a slab-allocated value.
+///
+/// # Safety
+///
+/// `value` must be a pointer returned by `alloc::<T>()` whose reference
+/// count has not dropped to zero.
+pub(super) unsafe fn retain<T>(value: usize) {
+    Slot::<T>::from_value_ptr(value)
+        .refs
+        .fetch_add(1, Ordering::Relaxed);
+}
+
+/// Decrement the reference count of a slab-allocated value, dropping it
+/// and recycling its slot when the count reaches zero.
+///
+/// # Safety
+///
+/// Same as `retain()`.
+pub(super) unsafe fn release<T>(value: usize) {
+    let slot = Slot::<T>::from_value_ptr(value);
+    if slot.refs.fetch_sub(1, Ordering::Release) == 1 {
+        atomic::fence(Ordering::Acquire);
+        ptr::drop_in_place(value as *mut T);
+        (*slot.slab).free(slot);
+    }
+}
+
+/// Returns a mutable reference to a slab-allocated value if there are no other
+/// references to it.
+///
+/// # Safety
+///
+/// Same as `retain()`.
+pub(super) unsafe fn get_mut<'a, T>(value: usize) -> Option<&'a mut T> {
+    let slot = Slot::<T>::from_value_ptr(value);
+    if slot.refs.load(Ordering::Acquire) == 1 {
+        Some(&mut *(value as *mut T))
+    } else {
+        None
+    }
+}
+
+/// Extract a slab-allocated value, consuming one reference to it.  The
+/// value is moved out of the slab if this was the last reference and
+/// cloned otherwise.
+///
+/// # Safety
+///
+/// Same as `retain()`.
+pub(super) unsafe fn take<T: Clone>(value: usize) -> T {
+    let slot = Slot::<T>::from_value_ptr(value);
+    if slot.refs.load(Ordering::Acquire) == 1 {
+        let v = ptr::read(value as *const T);
+        slot.refs.store(0, Ordering::Relaxed);
+        (*slot.slab).free(slot);
+        v
+    } else {
+        let v = (*(value as *const T)).clone();
+        release::<T>(value);
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn slots_are_recycled() {
+        let v1 = alloc::<(u64, u64)>((1, 2));
+        let v2 = alloc::<(u64, u64)>((3, 4));
+        assert_ne!(v1, v2);
+        unsafe {
+            assert_eq!(*(v1 as *const (u64, u64)), (1, 2));
+            retain::<(u64, u64)>(v1);
+            release::<(u64, u64)>(v1);
+            assert_eq!(*(v1 as *const (u64, u64)), (1, 2));
+            release::<(u64, u64)>(v1);
+            release::<(u64, u64)>(v2);
+        }
+        let v3 = alloc::<(u64, u64)>((5, 6));
+        assert!(v3 == v1 || v3 == v2);
+        unsafe { assert_eq!(take::<(u64, u64)>(v3), (5, 6)) };
+    }
+
+    #[test]
+    fn values_are_dropped() {
+        let counter = Arc::new(());
+        let v = alloc(Arc::clone(&counter));
+        unsafe { retain::<Arc<()>>(v) };
+        assert_eq!(Arc::strong_count(&counter), 2);
+        unsafe { release::<Arc<()>>(v) };
+        assert_eq!(Arc::strong_count(&counter), 2);
+        unsafe { release::<Arc<()>>(v) };
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+}
@@ -0,0 +1,276 @@
+//! Rendering relation contents as graphs.
+//!
+//! Many DDlog programs compute graph-shaped outputs, e.g., reachability or
+//! dependency relations.  `render_graph()` turns the records of an edge
+//! relation, and optionally a node-label relation, into a GraphViz DOT or
+//! GraphML document for quick visualization.
+//!
+//! The first two fields of each edge record are the source and target node;
+//! any remaining fields are printed as the label of the edge.  The first
+//! field of each node-label record is the node and the second field its
+//! label.  Nodes are identified by their value, so the fields of the two
+//! relations must have the same types for labels to apply.  String values are
+//! printed without quotes.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::record::Record;
+
+/// Output format of `render_graph()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// GraphViz DOT.
+    Dot,
+    /// GraphML (XML).
+    GraphML,
+}
+
+struct Graph {
+    /// Node text and label, in the order of first occurrence.
+    nodes: Vec<(String, Option<String>)>,
+    index: HashMap<String, usize>,
+    /// Source, target, and label of each edge.
+    edges: Vec<(usize, usize, Option<String>)>,
+}
+
+impl Graph {
+    fn node(&mut self, text: String) -> usize {
+        if let Some(i) = self.index.get(&text) {
+            return *i;
+        }
+        let i = self.nodes.len();
+        self.index.insert(text.clone(), i);
+        self.nodes.push((text, None));
+        i
+    }
+}
+
+/* Fields of a struct or tuple record. */
+fn fields(rec: &Record) -> Option<Vec<(Option<&str>, &Record)>> {
+    match rec {
+        Record::Tuple(recs) | Record::PosStruct(_, recs) => {
+            Some(recs.iter().map(|r| (None, r)).collect())
+        }
+        Record::NamedStruct(_, fields) => Some(
+            fields
+                .iter()
+                .map(|(name, r)| (Some(name.as_ref()), r))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+fn text(rec: &Record) -> String {
+    match rec {
+        Record::String(s) => s.clone(),
+        rec => rec.to_string(),
+    }
+}
+
+/// Render `edges`, labeled by `labels`, in `format`.  `name` is used as the
+/// name of the graph.  Fails if a record has fewer than two fields.
+pub fn render_graph<'a, E, L>(
+    name: &str,
+    edges: E,
+    labels: L,
+    format: GraphFormat,
+) -> Result<String, String>
+where
+    E: IntoIterator<Item = &'a Record>,
+    L: IntoIterator<Item = &'a Record>,
+{
+    let mut graph = Graph {
+        nodes: Vec::new(),
+        index: HashMap::new(),
+        edges: Vec::new(),
+    };
+    for edge in edges {
+        let fields = fields(edge)
+            .filter(|fields| fields.len() >= 2)
+            .ok_or_else(|| format!("edge {} does not have source and target fields", edge))?;
+        let from = graph.node(text(fields[0].1));
+        let to = graph.node(text(fields[1].1));
+        let label: Vec<String> = fields[2..]
+            .iter()
+            .map(|(name, r)| match name {
+                Some(name) => format!("{}={}", name, text(r)),
+                None => text(r),
+            })
+            .collect();
+        let label = if label.is_empty() {
+            None
+        } else {
+            Some(label.join(", "))
+        };
+        graph.edges.push((from, to, label));
+    }
+    for label in labels {
+        let fields = fields(label)
+            .filter(|fields| fields.len() >= 2)
+            .ok_or_else(|| format!("node label {} does not have node and label fields", label))?;
+        let node = graph.node(text(fields[0].1));
+        graph.nodes[node].1 = Some(text(fields[1].1));
+    }
+
+    Ok(match format {
+        GraphFormat::Dot => to_dot(name, &graph),
+        GraphFormat::GraphML => to_graphml(name, &graph),
+    })
+}
+
+fn dot_quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn to_dot(name: &str, graph: &Graph) -> String {
+    let mut out = format!("digraph {} {{\n", dot_quote(name));
+    for (i, (text, label)) in graph.nodes.iter().enumerate() {
+        let label = label.as_ref().unwrap_or(text);
+        let _ = writeln!(out, "    n{} [label={}];", i, dot_quote(label));
+    }
+    for (from, to, label) in graph.edges.iter() {
+        match label {
+            Some(label) => {
+                let _ = writeln!(
+                    out,
+                    "    n{} -> n{} [label={}];",
+                    from,
+                    to,
+                    dot_quote(label)
+                );
+            }
+            None => {
+                let _ = writeln!(out, "    n{} -> n{};", from, to);
+            }
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn to_graphml(name: &str, graph: &Graph) -> String {
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n  \
+         <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n  \
+         <key id=\"edge_label\" for=\"edge\" attr.name=\"label\" attr.type=\"string\"/>\n",
+    );
+    let _ = writeln!(
+        out,
+        "  <graph id=\"{}\" edgedefault=\"directed\">",
+        xml_escape(name)
+    );
+    for (i, (text, label)) in graph.nodes.iter().enumerate() {
+        let label = label.as_ref().unwrap_or(text);
+        let _ = writeln!(
+            out,
+            "    <node id=\"n{}\"><data key=\"label\">{}</data></node>",
+            i,
+            xml_escape(label)
+        );
+    }
+    for (from, to, label) in graph.edges.iter() {
+        match label {
+            Some(label) => {
+                let _ = writeln!(
+                    out,
+                    "    <edge source=\"n{}\" target=\"n{}\"><data key=\"edge_label\">{}</data></edge>",
+                    from,
+                    to,
+                    xml_escape(label)
+                );
+            }
+            None => {
+                let _ = writeln!(out, "    <edge source=\"n{}\" target=\"n{}\"/>", from, to);
+            }
+        }
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn edge(from: &str, to: &str, weight: Option<u32>) -> Record {
+        let mut fields = vec![
+            (Cow::Borrowed("from"), Record::String(from.to_string())),
+            (Cow::Borrowed("to"), Record::String(to.to_string())),
+        ];
+        if let Some(w) = weight {
+            fields.push((Cow::Borrowed("w"), Record::Int(w.into())));
+        }
+        Record::NamedStruct(Cow::Borrowed("Edge"), fields)
+    }
+
+    fn label(node: &str, label: &str) -> Record {
+        Record::PosStruct(
+            Cow::Borrowed("Label"),
+            vec![
+                Record::String(node.to_string()),
+                Record::String(label.to_string()),
+            ],
+        )
+    }
+
+    #[test]
+    fn dot() {
+        let edges = vec![edge("a", "b", Some(5)), edge("b", "a", None)];
+        let labels = vec![label("a", "node \"a\""), label("c", "isolated")];
+        assert_eq!(
+            render_graph("Edge", &edges, &labels, GraphFormat::Dot).unwrap(),
+            r#"digraph "Edge" {
+    n0 [label="node \"a\""];
+    n1 [label="b"];
+    n2 [label="isolated"];
+    n0 -> n1 [label="w=5"];
+    n1 -> n0;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn graphml() {
+        let edges = vec![edge("a", "b<c", None)];
+        let output = render_graph("Edge", &edges, &[], GraphFormat::GraphML).unwrap();
+        assert!(output.contains(r#"<node id="n1"><data key="label">b&lt;c</data></node>"#));
+        assert!(output.contains(r#"<edge source="n0" target="n1"/>"#));
+    }
+
+    #[test]
+    fn not_an_edge() {
+        let edges = vec![Record::Int(1.into())];
+        assert!(render_graph("Edge", &edges, &[], GraphFormat::Dot).is_err());
+    }
+}
@@ -0,0 +1,1088 @@
+//! Interpreter for rules loaded at runtime.
+//!
+//! Compiled DDlog programs cannot be extended without a recompile cycle.
+//! This module evaluates additional rules written in a subset of DDlog over
+//! the records of existing relations, so that users can run ad-hoc queries
+//! against a running program.  A rule set is a sequence of rules of the form
+//!
+//! ```text
+//! Head(x, y) :- Rel1(x, _, z), not Rel2(z), z != "foo", y = 5, x < 10.
+//! ```
+//!
+//! where each body literal is a positive or negated atom or a comparison
+//! (`==`, `!=`, `<`, `<=`, `>`, `>=`) between terms.  Terms are variables,
+//! `_`, integer, string, and Boolean literals.  Atoms match the fields of a
+//! record positionally: a struct or tuple record with `n` fields matches an
+//! atom with `n` arguments, and any other record matches an atom with one
+//! argument.  Rules define new relations (heads), which other rules of the
+//! same set may use, recursively, as long as the rule set is stratifiable,
+//! i.e., no relation depends on its own negation.  Relations that the rules
+//! use but do not define are _base_ relations, supplied by the caller.
+//!
+//! `Interpreter` re-evaluates a rule set from scratch over the current
+//! contents of its base relations and reports the changes to the derived
+//! relations since the previous evaluation.  This is much slower than
+//! compiled rules, which are evaluated incrementally by differential
+//! dataflow, but does not require recompiling the program.
+//!
+//! `Query` evaluates a single rule body once, e.g., to inspect the current
+//! state of a program while debugging it.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use num::BigInt;
+use ordered_float::OrderedFloat;
+
+use crate::record::Record;
+
+/// A value manipulated by interpreted rules.  Records other than Booleans,
+/// integers, doubles and strings are opaque: they can be bound to variables,
+/// compared for equality and copied to derived relations, but not compared
+/// with `<` and friends.
+#[derive(Debug, Clone)]
+enum Value {
+    Bool(bool),
+    Int(BigInt),
+    Double(OrderedFloat<f64>),
+    String(String),
+    /// Records are ordered and compared by their textual representation.
+    Opaque(String, Record),
+}
+
+impl Value {
+    fn from_record(record: &Record) -> Self {
+        match record {
+            Record::Bool(b) => Value::Bool(*b),
+            Record::Int(i) => Value::Int(i.clone()),
+            Record::Double(d) => Value::Double(*d),
+            Record::String(s) => Value::String(s.clone()),
+            _ => Value::Opaque(record.to_string(), record.clone()),
+        }
+    }
+
+    fn to_record(&self) -> Record {
+        match self {
+            Value::Bool(b) => Record::Bool(*b),
+            Value::Int(i) => Record::Int(i.clone()),
+            Value::Double(d) => Record::Double(*d),
+            Value::String(s) => Record::String(s.clone()),
+            Value::Opaque(_, record) => record.clone(),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Bool(_) => 0,
+            Value::Int(_) => 1,
+            Value::Double(_) => 2,
+            Value::String(_) => 3,
+            Value::Opaque(..) => 4,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Value {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Int(a), Value::Int(b)) => a.cmp(b),
+            (Value::Double(a), Value::Double(b)) => a.cmp(b),
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            (Value::Opaque(a, _), Value::Opaque(b, _)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+type Fact = Vec<Value>;
+
+/// The fields of `record` matched by atoms.
+fn record_fields(record: &Record) -> Fact {
+    match record {
+        Record::Tuple(fields) | Record::PosStruct(_, fields) => {
+            fields.iter().map(Value::from_record).collect()
+        }
+        Record::NamedStruct(_, fields) => fields
+            .iter()
+            .map(|(_, field)| Value::from_record(field))
+            .collect(),
+        _ => vec![Value::from_record(record)],
+    }
+}
+
+fn fact_record(fact: &[Value]) -> Record {
+    match fact {
+        [value] => value.to_record(),
+        _ => Record::Tuple(fact.iter().map(Value::to_record).collect()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Var(usize),
+    Wildcard,
+    Const(Value),
+}
+
+#[derive(Debug, Clone)]
+struct Atom {
+    relation: String,
+    args: Vec<Term>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+#[derive(Debug, Clone)]
+enum Literal {
+    Atom(Atom),
+    Not(Atom),
+    Cmp(Term, CmpOp, Term),
+    /// Binds a variable to a constant; created from `x = <constant>`
+    /// comparisons when `x` does not occur in a positive atom.
+    Bind(usize, Value),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    head: Atom,
+    /// Body literals in evaluation order: every negation and comparison
+    /// follows the atoms that bind its variables.
+    body: Vec<Literal>,
+    num_vars: usize,
+}
+
+/// A parsed set of rules.
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    /// Arity of each relation defined by the rules.
+    heads: BTreeMap<String, usize>,
+    /// Relations used but not defined by the rules.
+    base: BTreeSet<String>,
+    /// Indexes of rules, grouped by stratum in evaluation order.
+    strata: Vec<Vec<usize>>,
+}
+
+impl RuleSet {
+    /// Parse and validate a rule set.
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let rules = Parser::new(src)?.rules()?;
+
+        let mut heads = BTreeMap::new();
+        for rule in rules.iter() {
+            let arity = *heads
+                .entry(rule.head.relation.clone())
+                .or_insert_with(|| rule.head.args.len());
+            if arity != rule.head.args.len() {
+                return Err(format!(
+                    "relation {} is defined with both {} and {} fields",
+                    rule.head.relation,
+                    arity,
+                    rule.head.args.len()
+                ));
+            }
+        }
+        let base = rules
+            .iter()
+            .flat_map(|rule| rule.body.iter())
+            .filter_map(|literal| match literal {
+                Literal::Atom(atom) | Literal::Not(atom) => Some(&atom.relation),
+                Literal::Cmp(..) | Literal::Bind(..) => None,
+            })
+            .filter(|relation| !heads.contains_key(*relation))
+            .cloned()
+            .collect();
+        let strata = stratify(&rules, &heads)?;
+        Ok(Self {
+            rules,
+            heads,
+            base,
+            strata,
+        })
+    }
+
+    /// Relations defined by the rules.
+    pub fn head_relations(&self) -> impl Iterator<Item = &str> {
+        self.heads.keys().map(String::as_str)
+    }
+
+    /// Relations used but not defined by the rules.
+    pub fn base_relations(&self) -> impl Iterator<Item = &str> {
+        self.base.iter().map(String::as_str)
+    }
+
+    /// Evaluate the rules given the records of every base relation.
+    fn evaluate(
+        &self,
+        base: &BTreeMap<String, Vec<Record>>,
+    ) -> Result<BTreeMap<String, BTreeSet<Fact>>, String> {
+        let mut facts = base_facts(&self.base, base)?;
+        for relation in self.heads.keys() {
+            facts.insert(relation.clone(), BTreeSet::new());
+        }
+
+        // Naive fixed point evaluation of each stratum.
+        for stratum in self.strata.iter() {
+            loop {
+                let mut derived = Vec::new();
+                for rule in stratum.iter().map(|i| &self.rules[*i]) {
+                    let mut bindings = vec![None; rule.num_vars];
+                    eval_body(rule, &rule.body, &facts, &mut bindings, &mut derived)?;
+                }
+                let mut changed = false;
+                for (relation, fact) in derived {
+                    changed |= facts.get_mut(relation).unwrap().insert(fact);
+                }
+                if !changed {
+                    break;
+                }
+            }
+        }
+
+        facts.retain(|relation, _| self.heads.contains_key(relation));
+        Ok(facts)
+    }
+}
+
+fn base_facts(
+    relations: &BTreeSet<String>,
+    base: &BTreeMap<String, Vec<Record>>,
+) -> Result<BTreeMap<String, BTreeSet<Fact>>, String> {
+    relations
+        .iter()
+        .map(|relation| {
+            let records = base
+                .get(relation)
+                .ok_or_else(|| format!("missing contents of relation {}", relation))?;
+            Ok((
+                relation.clone(),
+                records.iter().map(record_fields).collect(),
+            ))
+        })
+        .collect()
+}
+
+fn stratify(rules: &[Rule], heads: &BTreeMap<String, usize>) -> Result<Vec<Vec<usize>>, String> {
+    let mut stratum: BTreeMap<&str, usize> = heads.keys().map(|h| (h.as_str(), 0)).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for rule in rules.iter() {
+            for literal in rule.body.iter() {
+                let (atom, offset) = match literal {
+                    Literal::Atom(atom) => (atom, 0),
+                    Literal::Not(atom) => (atom, 1),
+                    Literal::Cmp(..) | Literal::Bind(..) => continue,
+                };
+                let body = match stratum.get(atom.relation.as_str()) {
+                    Some(body) => *body + offset,
+                    None => continue,
+                };
+                let head = stratum.get_mut(rule.head.relation.as_str()).unwrap();
+                if body > *head {
+                    if body > heads.len() {
+                        return Err(format!(
+                            "relation {} depends on its own negation",
+                            rule.head.relation
+                        ));
+                    }
+                    *head = body;
+                    changed = true;
+                }
+            }
+        }
+    }
+    let mut strata = vec![Vec::new(); stratum.values().max().map_or(0, |max| max + 1)];
+    for (i, rule) in rules.iter().enumerate() {
+        strata[stratum[rule.head.relation.as_str()]].push(i);
+    }
+    Ok(strata)
+}
+
+fn term_value<'a>(term: &'a Term, bindings: &'a [Option<Value>]) -> Option<&'a Value> {
+    match term {
+        Term::Var(var) => bindings[*var].as_ref(),
+        Term::Const(value) => Some(value),
+        Term::Wildcard => None,
+    }
+}
+
+/// Match `fact` against `atom`, extending `bindings`.  Returns the variables
+/// bound by the match, or `None` if the fact does not match.
+fn unify(atom: &Atom, fact: &[Value], bindings: &mut [Option<Value>]) -> Option<Vec<usize>> {
+    let mut bound = Vec::new();
+    for (arg, value) in atom.args.iter().zip(fact.iter()) {
+        let matches = match arg {
+            Term::Wildcard => true,
+            Term::Const(c) => c == value,
+            Term::Var(var) => match &bindings[*var] {
+                Some(v) => v == value,
+                None => {
+                    bindings[*var] = Some(value.clone());
+                    bound.push(*var);
+                    true
+                }
+            },
+        };
+        if !matches {
+            for var in bound {
+                bindings[var] = None;
+            }
+            return None;
+        }
+    }
+    Some(bound)
+}
+
+fn relation_facts<'a>(
+    atom: &Atom,
+    facts: &'a BTreeMap<String, BTreeSet<Fact>>,
+) -> Result<&'a BTreeSet<Fact>, String> {
+    let relation = &facts[&atom.relation];
+    match relation.iter().next() {
+        Some(fact) if fact.len() != atom.args.len() => Err(format!(
+            "relation {} has {} fields, but is used with {} arguments",
+            atom.relation,
+            fact.len(),
+            atom.args.len()
+        )),
+        _ => Ok(relation),
+    }
+}
+
+fn eval_body<'a>(
+    rule: &'a Rule,
+    body: &[Literal],
+    facts: &BTreeMap<String, BTreeSet<Fact>>,
+    bindings: &mut [Option<Value>],
+    derived: &mut Vec<(&'a str, Fact)>,
+) -> Result<(), String> {
+    let (literal, rest) = match body.split_first() {
+        None => {
+            let fact = rule
+                .head
+                .args
+                .iter()
+                .map(|arg| term_value(arg, bindings).unwrap().clone())
+                .collect();
+            derived.push((rule.head.relation.as_str(), fact));
+            return Ok(());
+        }
+        Some(split) => split,
+    };
+    match literal {
+        Literal::Atom(atom) => {
+            for fact in relation_facts(atom, facts)?.iter() {
+                if let Some(bound) = unify(atom, fact, bindings) {
+                    eval_body(rule, rest, facts, bindings, derived)?;
+                    for var in bound {
+                        bindings[var] = None;
+                    }
+                }
+            }
+            Ok(())
+        }
+        Literal::Not(atom) => {
+            let mut scratch = bindings.to_vec();
+            let found = relation_facts(atom, facts)?
+                .iter()
+                .any(|fact| unify(atom, fact, &mut scratch).is_some());
+            if !found {
+                eval_body(rule, rest, facts, bindings, derived)?;
+            }
+            Ok(())
+        }
+        Literal::Bind(var, value) => {
+            bindings[*var] = Some(value.clone());
+            eval_body(rule, rest, facts, bindings, derived)?;
+            bindings[*var] = None;
+            Ok(())
+        }
+        Literal::Cmp(left, op, right) => {
+            let (left, right) = (
+                term_value(left, bindings).unwrap(),
+                term_value(right, bindings).unwrap(),
+            );
+            let holds = match op {
+                CmpOp::Eq => left == right,
+                CmpOp::Neq => left != right,
+                _ if left.rank() != right.rank() || matches!(left, Value::Opaque(..)) => false,
+                CmpOp::Lt => left < right,
+                CmpOp::Lte => left <= right,
+                CmpOp::Gt => left > right,
+                CmpOp::Gte => left >= right,
+            };
+            if holds {
+                eval_body(rule, rest, facts, bindings, derived)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Evaluates a rule set and tracks the contents of the relations it
+/// defines.
+#[derive(Debug, Clone)]
+pub struct Interpreter {
+    rules: RuleSet,
+    relations: BTreeMap<String, BTreeSet<Fact>>,
+}
+
+/// Changes to a derived relation: records and their weights.
+pub type DerivedChanges = BTreeMap<String, Vec<(Record, isize)>>;
+
+impl Interpreter {
+    pub fn new(rules: RuleSet) -> Self {
+        let relations = rules
+            .heads
+            .keys()
+            .map(|relation| (relation.clone(), BTreeSet::new()))
+            .collect();
+        Self { rules, relations }
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    /// Re-evaluate the rules over the current records of base relations
+    /// and return the changes to derived relations.
+    pub fn update(
+        &mut self,
+        base: &BTreeMap<String, Vec<Record>>,
+    ) -> Result<DerivedChanges, String> {
+        let relations = self.rules.evaluate(base)?;
+        let mut changes = DerivedChanges::new();
+        for (relation, new) in relations.iter() {
+            let old = &self.relations[relation];
+            let delta: Vec<(Record, isize)> = old
+                .difference(new)
+                .map(|fact| (fact_record(fact), -1))
+                .chain(new.difference(old).map(|fact| (fact_record(fact), 1)))
+                .collect();
+            if !delta.is_empty() {
+                changes.insert(relation.clone(), delta);
+            }
+        }
+        self.relations = relations;
+        Ok(changes)
+    }
+
+    /// Records of derived relation `relation` as of the last update.
+    pub fn relation(&self, relation: &str) -> Option<Vec<Record>> {
+        self.relations
+            .get(relation)
+            .map(|facts| facts.iter().map(|fact| fact_record(fact)).collect())
+    }
+}
+
+/// A one-shot query: a rule body over base relations, e.g.,
+/// `Edge(x, y), not Node(y), x < 10`.  Its results are the distinct
+/// bindings of the variables of the body that satisfy it.
+#[derive(Debug, Clone)]
+pub struct Query {
+    rule: Rule,
+    /// Names of the variables, in order of first occurrence.
+    vars: Vec<String>,
+    base: BTreeSet<String>,
+}
+
+impl Query {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        Parser::new(src)?.query()
+    }
+
+    /// Relations used by the query.
+    pub fn base_relations(&self) -> impl Iterator<Item = &str> {
+        self.base.iter().map(String::as_str)
+    }
+
+    /// Evaluate the query given the records of every base relation.  Each
+    /// result is a `NamedStruct` record with one field per variable.
+    pub fn evaluate(&self, base: &BTreeMap<String, Vec<Record>>) -> Result<Vec<Record>, String> {
+        let facts = base_facts(&self.base, base)?;
+        let mut bindings = vec![None; self.rule.num_vars];
+        let mut derived = Vec::new();
+        eval_body(
+            &self.rule,
+            &self.rule.body,
+            &facts,
+            &mut bindings,
+            &mut derived,
+        )?;
+        let results: BTreeSet<Fact> = derived.into_iter().map(|(_, fact)| fact).collect();
+        Ok(results
+            .into_iter()
+            .map(|fact| {
+                let fields = self
+                    .vars
+                    .iter()
+                    .zip(fact.iter())
+                    .map(|(var, value)| (Cow::from(var.clone()), value.to_record()))
+                    .collect();
+                Record::NamedStruct(Cow::from(""), fields)
+            })
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Relation name, possibly qualified with a module path.
+    Relation(String),
+    Var(String),
+    Wildcard,
+    Int(BigInt),
+    String(String),
+    Bool(bool),
+    Not,
+    Punct(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Relation(name) | Token::Var(name) => write!(f, "'{}'", name),
+            Token::Wildcard => write!(f, "'_'"),
+            Token::Int(i) => write!(f, "'{}'", i),
+            Token::String(s) => write!(f, "{:?}", s),
+            Token::Bool(b) => write!(f, "'{}'", b),
+            Token::Not => write!(f, "'not'"),
+            Token::Punct(p) => write!(f, "'{}'", p),
+        }
+    }
+}
+
+/// Punctuation, longest first.
+const PUNCTUATION: &[&str] = &[
+    ":-", "==", "!=", "<=", ">=", "(", ")", ",", ".", "<", ">", "=",
+];
+
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = src.chars().collect();
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c.is_ascii_digit()
+            || (c == '-' && matches!(chars.get(i + 1), Some(d) if d.is_ascii_digit()))
+        {
+            let start = i;
+            i += 1;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            let value = literal
+                .parse()
+                .map_err(|_| format!("line {}: invalid integer {}", line, literal))?;
+            tokens.push((line, Token::Int(value)));
+        } else if c == '"' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(format!("line {}: unterminated string", line)),
+                    Some('"') => break,
+                    Some('\\') => {
+                        s.push(match chars.get(i + 1) {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some(c @ '"') | Some(c @ '\\') => *c,
+                            _ => return Err(format!("line {}: invalid escape sequence", line)),
+                        });
+                        i += 2;
+                    }
+                    Some(c) => {
+                        s.push(*c);
+                        i += 1;
+                    }
+                }
+            }
+            i += 1;
+            tokens.push((line, Token::String(s)));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut segments = Vec::new();
+            loop {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                segments.push(chars[start..i].iter().collect::<String>());
+                let qualified = chars.get(i) == Some(&':')
+                    && chars.get(i + 1) == Some(&':')
+                    && matches!(chars.get(i + 2), Some(c) if c.is_alphabetic() || *c == '_');
+                if !qualified {
+                    break;
+                }
+                i += 2;
+            }
+            let name = segments.join("::");
+            let local = segments.last().unwrap();
+            let token = match name.as_str() {
+                "_" => Token::Wildcard,
+                "not" => Token::Not,
+                "true" => Token::Bool(true),
+                "false" => Token::Bool(false),
+                _ if local.starts_with(char::is_uppercase) => Token::Relation(name),
+                _ if segments.len() == 1 => Token::Var(name),
+                _ => return Err(format!("line {}: invalid relation name {}", line, name)),
+            };
+            tokens.push((line, token));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let punct = PUNCTUATION
+                .iter()
+                .find(|p| rest.starts_with(**p))
+                .ok_or_else(|| format!("line {}: unexpected character '{}'", line, c))?;
+            i += punct.len();
+            tokens.push((line, Token::Punct(punct)));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Variables of the rule being parsed.
+    vars: Vec<String>,
+}
+
+impl Parser {
+    fn new(src: &str) -> Result<Self, String> {
+        Ok(Self {
+            tokens: tokenize(src)?,
+            pos: 0,
+            vars: Vec::new(),
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |(line, _)| *line)
+    }
+
+    fn error<T>(&self, expected: &str) -> Result<T, String> {
+        match self.peek() {
+            Some(token) => Err(format!(
+                "line {}: expected {}, found {}",
+                self.line(),
+                expected,
+                token
+            )),
+            None => Err(format!(
+                "line {}: expected {}, found end of input",
+                self.line(),
+                expected
+            )),
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn punct(&mut self, punct: &str) -> bool {
+        match self.peek() {
+            Some(Token::Punct(p)) if *p == punct => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if self.punct(punct) {
+            Ok(())
+        } else {
+            self.error(&format!("'{}'", punct))
+        }
+    }
+
+    fn rules(&mut self) -> Result<Vec<Rule>, String> {
+        let mut rules = Vec::new();
+        while self.peek().is_some() {
+            rules.push(self.rule()?);
+        }
+        Ok(rules)
+    }
+
+    fn rule(&mut self) -> Result<Rule, String> {
+        self.vars.clear();
+        let line = self.line();
+        let head = self.atom()?;
+        let body = if self.punct(":-") {
+            self.body()?
+        } else {
+            Vec::new()
+        };
+        self.expect(".")?;
+        let rule =
+            order_body(head, body, self.vars.len()).map_err(|e| format!("line {}: {}", line, e))?;
+        Ok(rule)
+    }
+
+    fn body(&mut self) -> Result<Vec<Literal>, String> {
+        let mut body = vec![self.literal()?];
+        while self.punct(",") {
+            body.push(self.literal()?);
+        }
+        Ok(body)
+    }
+
+    /// A rule body, optionally terminated by '.', whose variables form the
+    /// head of the rule.
+    fn query(&mut self) -> Result<Query, String> {
+        let body = self.body()?;
+        self.punct(".");
+        if self.peek().is_some() {
+            return self.error("',' or end of input");
+        }
+        let head = Atom {
+            relation: String::new(),
+            args: (0..self.vars.len()).map(Term::Var).collect(),
+        };
+        let rule = order_body(head, body, self.vars.len())?;
+        let base = rule
+            .body
+            .iter()
+            .filter_map(|literal| match literal {
+                Literal::Atom(atom) | Literal::Not(atom) => Some(atom.relation.clone()),
+                Literal::Cmp(..) | Literal::Bind(..) => None,
+            })
+            .collect();
+        Ok(Query {
+            rule,
+            vars: self.vars.clone(),
+            base,
+        })
+    }
+
+    fn atom(&mut self) -> Result<Atom, String> {
+        let relation = match self.peek() {
+            Some(Token::Relation(name)) => name.clone(),
+            _ => return self.error("relation name"),
+        };
+        self.pos += 1;
+        self.expect("(")?;
+        let mut args = Vec::new();
+        if !self.punct(")") {
+            loop {
+                args.push(self.term()?);
+                if !self.punct(",") {
+                    break;
+                }
+            }
+            self.expect(")")?;
+        }
+        Ok(Atom { relation, args })
+    }
+
+    fn term(&mut self) -> Result<Term, String> {
+        let term = match self.peek() {
+            Some(Token::Var(name)) => {
+                let var = match self.vars.iter().position(|v| v == name) {
+                    Some(var) => var,
+                    None => {
+                        self.vars.push(name.clone());
+                        self.vars.len() - 1
+                    }
+                };
+                Term::Var(var)
+            }
+            Some(Token::Wildcard) => Term::Wildcard,
+            Some(Token::Int(i)) => Term::Const(Value::Int(i.clone())),
+            Some(Token::String(s)) => Term::Const(Value::String(s.clone())),
+            Some(Token::Bool(b)) => Term::Const(Value::Bool(*b)),
+            _ => return self.error("variable or literal"),
+        };
+        self.pos += 1;
+        Ok(term)
+    }
+
+    fn literal(&mut self) -> Result<Literal, String> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.pos += 1;
+                Ok(Literal::Not(self.atom()?))
+            }
+            Some(Token::Relation(_)) => Ok(Literal::Atom(self.atom()?)),
+            _ => {
+                let left = self.term()?;
+                let op = match self.next() {
+                    Some(Token::Punct("==")) | Some(Token::Punct("=")) => CmpOp::Eq,
+                    Some(Token::Punct("!=")) => CmpOp::Neq,
+                    Some(Token::Punct("<")) => CmpOp::Lt,
+                    Some(Token::Punct("<=")) => CmpOp::Lte,
+                    Some(Token::Punct(">")) => CmpOp::Gt,
+                    Some(Token::Punct(">=")) => CmpOp::Gte,
+                    _ => {
+                        self.pos -= 1;
+                        return self.error("comparison operator");
+                    }
+                };
+                let right = self.term()?;
+                Ok(Literal::Cmp(left, op, right))
+            }
+        }
+    }
+}
+
+fn term_vars(term: &Term) -> Option<usize> {
+    match term {
+        Term::Var(var) => Some(*var),
+        _ => None,
+    }
+}
+
+/// Order the body of a rule so that negations and comparisons are
+/// evaluated as soon as their variables are bound, checking that every
+/// variable is bound by a positive atom.
+fn order_body(head: Atom, body: Vec<Literal>, num_vars: usize) -> Result<Rule, String> {
+    let (atoms, mut filters): (Vec<Literal>, Vec<Literal>) = body
+        .into_iter()
+        .partition(|literal| matches!(literal, Literal::Atom(_)));
+
+    // Comparisons `x = <constant>` bind `x` when it is not bound otherwise.
+    let mut assignments = Vec::new();
+    let mut bound = vec![false; num_vars];
+    for atom in atoms.iter() {
+        if let Literal::Atom(atom) = atom {
+            for var in atom.args.iter().filter_map(term_vars) {
+                bound[var] = true;
+            }
+        }
+    }
+    filters.retain(|literal| match literal {
+        Literal::Cmp(Term::Var(var), CmpOp::Eq, Term::Const(value))
+        | Literal::Cmp(Term::Const(value), CmpOp::Eq, Term::Var(var))
+            if !bound[*var] =>
+        {
+            bound[*var] = true;
+            assignments.push((*var, value.clone()));
+            false
+        }
+        _ => true,
+    });
+
+    let filter_vars = |literal: &Literal| -> Vec<usize> {
+        match literal {
+            Literal::Atom(atom) | Literal::Not(atom) => {
+                atom.args.iter().filter_map(term_vars).collect()
+            }
+            Literal::Cmp(left, _, right) => term_vars(left)
+                .into_iter()
+                .chain(term_vars(right))
+                .collect(),
+            Literal::Bind(var, _) => vec![*var],
+        }
+    };
+    if head.args.contains(&Term::Wildcard) {
+        return Err(format!("'_' in the head of a rule for {}", head.relation));
+    }
+    let wildcard_cmp = filters.iter().any(|literal| {
+        matches!(
+            literal,
+            Literal::Cmp(Term::Wildcard, ..) | Literal::Cmp(_, _, Term::Wildcard)
+        )
+    });
+    if wildcard_cmp {
+        return Err("'_' cannot be compared".to_string());
+    }
+    let unbound = head
+        .args
+        .iter()
+        .filter_map(term_vars)
+        .chain(filters.iter().flat_map(filter_vars))
+        .any(|var| !bound[var]);
+    if unbound {
+        return Err("every variable must occur in a positive atom of the body".to_string());
+    }
+
+    // Bindings come first, then atoms, each followed by the filters whose
+    // variables it binds.
+    let mut is_bound = vec![false; num_vars];
+    let mut ordered: Vec<Literal> = Vec::new();
+    for (var, value) in assignments {
+        is_bound[var] = true;
+        ordered.push(Literal::Bind(var, value));
+    }
+    for atom in atoms.into_iter().map(Some).chain(std::iter::once(None)) {
+        if let Some(Literal::Atom(a)) = &atom {
+            for var in a.args.iter().filter_map(term_vars) {
+                is_bound[var] = true;
+            }
+        }
+        ordered.extend(atom);
+        let (ready, pending): (Vec<Literal>, Vec<Literal>) = filters
+            .into_iter()
+            .partition(|literal| filter_vars(literal).iter().all(|var| is_bound[*var]));
+        ordered.extend(ready);
+        filters = pending;
+    }
+    Ok(Rule {
+        head,
+        body: ordered,
+        num_vars,
+    })
+}
+
+#[cfg(test)]
+fn records(values: &[&[i64]]) -> Vec<Record> {
+    values
+        .iter()
+        .map(|fields| match fields {
+            [field] => Record::Int((*field).into()),
+            _ => Record::Tuple(fields.iter().map(|f| Record::Int((*f).into())).collect()),
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_rules() {
+    let rules = RuleSet::parse(
+        "// Comment.
+         Path(x, y) :- graph::Edge(x, y).
+         Path(x, z) :- Path(x, y), graph::Edge(y, z).
+         Unreachable(x, y) :- Node(x), Node(y), not Path(x, y), x != y.",
+    )
+    .unwrap();
+    assert_eq!(
+        rules.head_relations().collect::<Vec<_>>(),
+        vec!["Path", "Unreachable"]
+    );
+    assert_eq!(
+        rules.base_relations().collect::<Vec<_>>(),
+        vec!["Node", "graph::Edge"]
+    );
+    assert_eq!(rules.strata.len(), 2);
+
+    assert!(RuleSet::parse("R(x) :- S(y).").is_err());
+    assert!(RuleSet::parse("R(x) :- S(x), not T(y).").is_err());
+    assert!(RuleSet::parse("R(x) :- S(x), not R(x).").is_err());
+    assert!(RuleSet::parse("R(x) :- S(x). R(x, y) :- T(x, y).").is_err());
+    assert!(RuleSet::parse("R(x) :- S(x)").is_err());
+}
+
+#[test]
+fn test_interpreter() {
+    let rules = RuleSet::parse(
+        "Path(x, y) :- Edge(x, y).
+         Path(x, z) :- Path(x, y), Edge(y, z).
+         Unreachable(x, y) :- Node(x), Node(y), not Path(x, y), x != y.
+         Flag(x, f) :- Node(x), f = true, x >= 2.",
+    )
+    .unwrap();
+    let mut interpreter = Interpreter::new(rules);
+
+    let mut base = BTreeMap::new();
+    base.insert("Edge".to_string(), records(&[&[1, 2], &[2, 3]]));
+    base.insert("Node".to_string(), records(&[&[1], &[2], &[3]]));
+    let changes = interpreter.update(&base).unwrap();
+    assert_eq!(changes["Path"].len(), 3);
+    assert_eq!(
+        interpreter.relation("Unreachable").unwrap(),
+        records(&[&[2, 1], &[3, 1], &[3, 2]])
+    );
+    assert_eq!(
+        interpreter.relation("Flag").unwrap(),
+        vec![
+            Record::Tuple(vec![Record::Int(2.into()), Record::Bool(true)]),
+            Record::Tuple(vec![Record::Int(3.into()), Record::Bool(true)])
+        ]
+    );
+
+    base.insert("Edge".to_string(), records(&[&[1, 2]]));
+    let changes = interpreter.update(&base).unwrap();
+    let mut path = changes["Path"].clone();
+    path.sort_by_key(|(_, weight)| *weight);
+    assert_eq!(
+        path,
+        vec![
+            (records(&[&[1, 3]])[0].clone(), -1),
+            (records(&[&[2, 3]])[0].clone(), -1)
+        ]
+    );
+    assert_eq!(
+        changes["Unreachable"],
+        vec![
+            (records(&[&[1, 3]])[0].clone(), 1),
+            (records(&[&[2, 3]])[0].clone(), 1)
+        ]
+    );
+    assert!(!changes.contains_key("Flag"));
+
+    base.remove("Node");
+    assert!(interpreter.update(&base).is_err());
+}
+
+#[test]
+fn test_query() {
+    let query = Query::parse("Edge(x, y), not Node(y), x < 3.").unwrap();
+    assert_eq!(
+        query.base_relations().collect::<Vec<_>>(),
+        vec!["Edge", "Node"]
+    );
+
+    let mut base = BTreeMap::new();
+    base.insert("Edge".to_string(), records(&[&[1, 2], &[2, 4], &[3, 5]]));
+    base.insert("Node".to_string(), records(&[&[1], &[2]]));
+    assert_eq!(
+        query.evaluate(&base).unwrap(),
+        vec![Record::NamedStruct(
+            Cow::from(""),
+            vec![
+                (Cow::from("x"), Record::Int(2.into())),
+                (Cow::from("y"), Record::Int(4.into()))
+            ]
+        )]
+    );
+
+    assert!(Query::parse("Edge(x, y). Edge(y, x)").is_err());
+    assert!(Query::parse("Edge(x, _), y > 1").is_err());
+}
@@ -4,13 +4,19 @@
     clippy::type_complexity
 )]
 
+pub mod access;
 mod callback;
+pub mod coordinator;
 mod dataflow;
 mod ddlog;
+pub mod graph;
+pub mod interpreter;
+pub mod metadata;
 mod profile;
 mod profile_statistics;
 mod render;
 pub mod replay;
+pub mod scheduler;
 mod valmap;
 mod variable;
 
@@ -30,4 +36,8 @@ pub use ddlog::{
     D3log, D3logLocationId, DDlog, DDlogDump, DDlogDynamic, DDlogInventory, DDlogProfiling,
 };
 pub use replay::CommandRecorder;
-pub use valmap::DeltaMap;
+pub use valmap::{DeltaMap, FormatOptions};
+
+/// Re-exported for use by code generated by `ddlog_derive::Arbitrary`.
+#[cfg(feature = "proptest")]
+pub use proptest;
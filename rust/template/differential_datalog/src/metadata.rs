@@ -0,0 +1,93 @@
+//! Metadata of a compiled DDlog program.
+//!
+//! The compiler embeds the name of the program, a hash of its source, the
+//! version of the compiler, and a fingerprint of the schema of each
+//! relation into the generated crate (`PROGRAM_METADATA`).  The fingerprint
+//! of a relation is a hash of its declaration and of the definitions of all
+//! types it uses, so that two builds of a program agree on the fingerprint
+//! of a relation if and only if they agree on the layout of its records.
+//!
+//! Data that outlives a running program, such as archives and replay
+//! files, carries the fingerprints of its relations, which are verified
+//! before the data is applied to another program.
+
+use std::fmt;
+
+/// Metadata of one relation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelationMetadata {
+    pub name: &'static str,
+    pub input: bool,
+    /// Hash of the relation's schema.
+    pub fingerprint: u64,
+}
+
+/// Metadata embedded into a compiled program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramMetadata {
+    pub program_name: &'static str,
+    /// Hash of the program, which changes with any change to its source.
+    pub source_hash: u64,
+    /// Version of the DDlog compiler that generated the program.
+    pub compiler_version: &'static str,
+    pub relations: &'static [RelationMetadata],
+}
+
+impl ProgramMetadata {
+    pub fn relation(&self, name: &str) -> Option<&'static RelationMetadata> {
+        self.relations.iter().find(|rel| rel.name == name)
+    }
+
+    pub fn fingerprint(&self, name: &str) -> Option<u64> {
+        self.relation(name).map(|rel| rel.fingerprint)
+    }
+
+    /// Check that data for relation `name` with schema `fingerprint` can be
+    /// applied to this program.
+    pub fn check_fingerprint(&self, name: &str, fingerprint: u64) -> Result<(), String> {
+        match self.fingerprint(name) {
+            None => Err(format!(
+                "relation {} is not defined by program {}",
+                name, self.program_name
+            )),
+            Some(expected) if expected != fingerprint => Err(format!(
+                "schema of relation {} has changed: expected fingerprint {:#018x}, found {:#018x}",
+                name, expected, fingerprint
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ProgramMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (source hash {:#018x}, compiled by DDlog {})",
+            self.program_name, self.source_hash, self.compiler_version
+        )
+    }
+}
+
+#[test]
+fn test_check_fingerprint() {
+    static RELATIONS: [RelationMetadata; 1] = [RelationMetadata {
+        name: "Edge",
+        input: true,
+        fingerprint: 0x1234,
+    }];
+    let metadata = ProgramMetadata {
+        program_name: "graph",
+        source_hash: 0xabcd,
+        compiler_version: "v0.40.2",
+        relations: &RELATIONS,
+    };
+
+    assert_eq!(metadata.check_fingerprint("Edge", 0x1234), Ok(()));
+    assert!(metadata.check_fingerprint("Edge", 0x4321).is_err());
+    assert!(metadata.check_fingerprint("Node", 0x1234).is_err());
+    assert_eq!(
+        metadata.to_string(),
+        "graph (source hash 0x000000000000abcd, compiled by DDlog v0.40.2)"
+    );
+}
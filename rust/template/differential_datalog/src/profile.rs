@@ -42,6 +42,10 @@ pub enum ProfMsg {
     DifferentialMessage(Vec<(Duration, usize, DifferentialEvent)>),
 }
 
+/// CPU time used by each operator at some point in time.
+#[derive(Debug, Clone, Default)]
+pub struct CpuSnapshot(FnvHashMap<usize, Duration>);
+
 #[derive(Debug)]
 pub struct Profile {
     addresses: SequenceTrie<usize, usize>,
@@ -154,6 +158,61 @@ impl Profile {
         Ok(())
     }
 
+    /// The `n` operators with the highest total CPU time, along with the
+    /// time and the number of times they were scheduled.  Only populated
+    /// while CPU profiling is enabled.
+    pub fn hotspots(&self, n: usize) -> Vec<(String, Duration, usize)> {
+        let mut durations: Vec<(usize, (Duration, usize))> =
+            self.durations.iter().map(|(op, d)| (*op, *d)).collect();
+        durations.sort_by(|a, b| (a.1).0.cmp(&(b.1).0).reverse());
+        durations
+            .into_iter()
+            .take(n)
+            .map(|(op, (duration, calls))| (self.op_name(op), duration, calls))
+            .collect()
+    }
+
+    fn op_name(&self, op: usize) -> String {
+        let name = self.names.get(&op).map(AsRef::as_ref).unwrap_or("???");
+        format!("{} {}", name, op)
+    }
+
+    /// CPU time used by each operator so far (see `busy_operators()`).
+    pub fn cpu_snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot(
+            self.durations
+                .iter()
+                .map(|(op, (duration, _))| (*op, *duration))
+                .collect(),
+        )
+    }
+
+    /// Operators that are currently running, i.e., have been scheduled by a
+    /// worker and have not returned yet.  Workers report scheduling events
+    /// in batches, so an operator that has been running for a long time may
+    /// not be known yet; the operator that used the most CPU time since
+    /// `snapshot` is returned in that case.  Only populated while CPU
+    /// profiling is enabled.
+    pub fn busy_operators(&self, snapshot: &CpuSnapshot) -> Vec<String> {
+        let mut running: Vec<usize> = self.starts.keys().map(|(op, _)| *op).collect();
+        running.sort_unstable();
+        running.dedup();
+        if running.is_empty() {
+            running.extend(
+                self.durations
+                    .iter()
+                    .map(|(op, (duration, _))| {
+                        let before = snapshot.0.get(op).cloned().unwrap_or_default();
+                        (*op, *duration - before)
+                    })
+                    .filter(|(_, used)| *used > Duration::new(0, 0))
+                    .max_by_key(|(_, used)| *used)
+                    .map(|(op, _)| op),
+            );
+        }
+        running.into_iter().map(|op| self.op_name(op)).collect()
+    }
+
     pub fn update(&mut self, msg: &ProfMsg) {
         match msg {
             ProfMsg::TimelyMessage(events, profile_cpu, profile_timely) => {
@@ -238,17 +297,13 @@ impl Profile {
                         .durations
                         .entry(*id)
                         .or_insert((Duration::new(0, 0), 0));
-                    let start = self
-                        .starts
-                        .get(&(*id, worker_id))
-                        .cloned()
-                        .unwrap_or_else(|| {
-                            eprintln!(
-                                "TimelyEvent::Stop without a start for operator {}, worker {}",
-                                *id, worker_id
-                            );
-                            Duration::new(0, 0)
-                        });
+                    let start = self.starts.remove(&(*id, worker_id)).unwrap_or_else(|| {
+                        eprintln!(
+                            "TimelyEvent::Stop without a start for operator {}, worker {}",
+                            *id, worker_id
+                        );
+                        Duration::new(0, 0)
+                    });
                     *total += *ts - start;
                     *ncalls += 1;
                 }
@@ -0,0 +1,172 @@
+//! Batching of input updates.
+//!
+//! Two optional mechanisms reduce the overhead of chatty upstream feeds that
+//! submit many small changes:
+//!
+//! * With `Config::coalesce_updates`, updates are buffered until the
+//!   dataflow is flushed instead of being sent to workers right away.  An
+//!   insertion and a deletion of the same value cancel out in the buffer and
+//!   never reach the dataflow.
+//!
+//! * With `Config::commit_latency_budget`, commits that follow a previous
+//!   commit within the budget are _deferred_: the transaction is committed to
+//!   the input relations, but its changes are only propagated through the
+//!   dataflow together with those of the first commit after the budget has
+//!   elapsed, merging a burst of micro-transactions into one.  Outputs of a
+//!   deferred commit are produced by the commit that flushes it, and a panic
+//!   raised while evaluating it fails that commit, or the query or
+//!   `RunningProgram::flush_deferred()` call that flushes it; the deferred
+//!   commit itself cannot be rolled back.  Deferred commits are numbered
+//!   when they are made and recorded for time travel (see `time_travel`)
+//!   when they are flushed.  When a feed goes idle,
+//!   deferred commits are flushed by the next query, by
+//!   `RunningProgram::flush_deferred()`, which clients can call from a
+//!   timer, or when the program stops.
+
+use std::{
+    collections::hash_map::Entry,
+    time::{Duration, Instant},
+};
+
+use fnv::FnvHashMap;
+
+use crate::ddval::DDValue;
+use crate::program::{RelId, Update, Weight};
+
+/// Net changes to input relations that have not been sent to workers.
+#[derive(Debug, Default)]
+pub(crate) struct UpdateBuffer {
+    weights: FnvHashMap<(RelId, DDValue), Weight>,
+}
+
+impl UpdateBuffer {
+    /// Add an `Insert` or `DeleteValue` update to the buffer; other updates
+    /// never reach workers.
+    pub(crate) fn push(&mut self, update: Update<DDValue>) {
+        let (relid, v, w) = match update {
+            Update::Insert { relid, v } => (relid, v, 1),
+            Update::DeleteValue { relid, v } => (relid, v, -1),
+            _ => unreachable!("UpdateBuffer::push: unexpected update {:?}", update),
+        };
+        match self.weights.entry((relid, v)) {
+            Entry::Occupied(mut entry) => {
+                *entry.get_mut() += w;
+                if *entry.get() == 0 {
+                    entry.remove();
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(w);
+            }
+        }
+    }
+
+    /// Remove all buffered changes, returning them as updates.
+    pub(crate) fn drain(&mut self) -> Vec<Update<DDValue>> {
+        let mut updates = Vec::with_capacity(self.weights.len());
+        for ((relid, v), w) in self.weights.drain() {
+            for _ in 0..w.abs() {
+                updates.push(if w > 0 {
+                    Update::Insert {
+                        relid,
+                        v: v.clone(),
+                    }
+                } else {
+                    Update::DeleteValue {
+                        relid,
+                        v: v.clone(),
+                    }
+                });
+            }
+        }
+        updates
+    }
+}
+
+/// Decides which commits to defer under a latency budget.
+#[derive(Debug)]
+pub(crate) struct CommitBatcher {
+    budget: Option<Duration>,
+    /// The time the oldest deferred commit was made.
+    deferred_since: Option<Instant>,
+}
+
+impl CommitBatcher {
+    pub(crate) fn new(budget: Option<Duration>) -> Self {
+        Self {
+            budget,
+            deferred_since: None,
+        }
+    }
+
+    /// Returns `true` if a commit made now should be deferred.
+    pub(crate) fn defer(&mut self) -> bool {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return false,
+        };
+        match self.deferred_since {
+            None => {
+                self.deferred_since = Some(Instant::now());
+                true
+            }
+            Some(since) => since.elapsed() < budget,
+        }
+    }
+
+    pub(crate) fn has_deferred(&self) -> bool {
+        self.deferred_since.is_some()
+    }
+
+    /// Record that all deferred commits have been flushed.
+    pub(crate) fn flushed(&mut self) {
+        self.deferred_since = None;
+    }
+}
+
+#[test]
+fn test_update_buffer() {
+    use crate::ddval::DDValConvert;
+
+    let mut buffer = UpdateBuffer::default();
+    buffer.push(Update::Insert {
+        relid: 1,
+        v: 1u64.into_ddvalue(),
+    });
+    buffer.push(Update::DeleteValue {
+        relid: 1,
+        v: 1u64.into_ddvalue(),
+    });
+    buffer.push(Update::Insert {
+        relid: 1,
+        v: 2u64.into_ddvalue(),
+    });
+    buffer.push(Update::Insert {
+        relid: 1,
+        v: 2u64.into_ddvalue(),
+    });
+
+    let updates = buffer.drain();
+    assert_eq!(updates.len(), 2);
+    assert!(updates
+        .iter()
+        .all(|update| update.is_insert() && update.get_value() == Some(&2u64.into_ddvalue())));
+    assert!(buffer.drain().is_empty());
+}
+
+#[test]
+fn test_commit_batcher() {
+    let mut batcher = CommitBatcher::new(None);
+    assert!(!batcher.defer());
+
+    let mut batcher = CommitBatcher::new(Some(Duration::from_secs(3600)));
+    assert!(batcher.defer());
+    assert!(batcher.defer());
+    assert!(batcher.has_deferred());
+    batcher.flushed();
+    assert!(!batcher.has_deferred());
+
+    let mut batcher = CommitBatcher::new(Some(Duration::from_secs(0)));
+    assert!(batcher.defer());
+    assert!(!batcher.defer());
+}
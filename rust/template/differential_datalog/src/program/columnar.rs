@@ -0,0 +1,393 @@
+//! Columnar storage of flat input relations.
+//!
+//! `RunningProgram` keeps a copy of every input relation, normally as a hash
+//! set of values, each of which is a separate heap allocation.  Input
+//! relations listed in `Program::columnar_rels` (declared with the
+//! `#[columnar]` attribute), whose records are tuples or structs of primitive
+//! fields, are stored as a struct of arrays instead: one vector per field,
+//! holding that field of all records of the relation.  This improves memory
+//! density and makes scans of the relation, e.g., by `dump_input_snapshot()`,
+//! read contiguous memory.
+//!
+//! Records are converted to and from values using their `Record`
+//! representation, which makes individual updates somewhat slower.  Only
+//! `RunningProgram`'s copy of the relation is columnar: arrangements built by
+//! workers are unaffected.
+
+use std::hash::{Hash, Hasher};
+
+use fnv::{FnvHashMap, FnvHasher};
+use num::{BigInt, ToPrimitive};
+use ordered_float::OrderedFloat;
+
+use crate::ddval::DDValue;
+use crate::program::RelId;
+use crate::record::{IntoRecord, Name, Record};
+
+/// Converts the record representation of a value of a columnar relation
+/// back to the value.
+pub type ColumnarDecodeFunc = fn(&Record) -> Result<DDValue, String>;
+
+/// The shape of the records of a relation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Shape {
+    Scalar,
+    Tuple,
+    PosStruct(Name),
+    NamedStruct(Name, Vec<Name>),
+}
+
+/// Split a record into its shape and fields.
+fn split(record: Record) -> Result<(Shape, Vec<Record>), String> {
+    match record {
+        Record::Tuple(fields) => Ok((Shape::Tuple, fields)),
+        Record::PosStruct(name, fields) => Ok((Shape::PosStruct(name), fields)),
+        Record::NamedStruct(name, fields) => {
+            let (names, fields) = fields.into_iter().unzip();
+            Ok((Shape::NamedStruct(name, names), fields))
+        }
+        Record::Bool(_)
+        | Record::Int(_)
+        | Record::Float(_)
+        | Record::Double(_)
+        | Record::String(_) => Ok((Shape::Scalar, vec![record])),
+        _ => Err(format!(
+            "record {} is not a tuple or struct of primitive fields",
+            record
+        )),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Column {
+    Bool(Vec<bool>),
+    /// Integers are stored as `i64` until the column contains a value that
+    /// does not fit.
+    Int(Vec<i64>),
+    BigInt(Vec<BigInt>),
+    Float(Vec<OrderedFloat<f32>>),
+    Double(Vec<OrderedFloat<f64>>),
+    String(Vec<String>),
+}
+
+impl Column {
+    /// An empty column for fields like `field`.
+    fn new(field: &Record) -> Result<Self, String> {
+        match field {
+            Record::Bool(_) => Ok(Column::Bool(Vec::new())),
+            Record::Int(_) => Ok(Column::Int(Vec::new())),
+            Record::Float(_) => Ok(Column::Float(Vec::new())),
+            Record::Double(_) => Ok(Column::Double(Vec::new())),
+            Record::String(_) => Ok(Column::String(Vec::new())),
+            _ => Err(format!("field {} is not a primitive value", field)),
+        }
+    }
+
+    fn accepts(&self, field: &Record) -> bool {
+        matches!(
+            (self, field),
+            (Column::Bool(_), Record::Bool(_))
+                | (Column::Int(_), Record::Int(_))
+                | (Column::BigInt(_), Record::Int(_))
+                | (Column::Float(_), Record::Float(_))
+                | (Column::Double(_), Record::Double(_))
+                | (Column::String(_), Record::String(_))
+        )
+    }
+
+    /// Append `field`, which the column must accept.
+    fn push(&mut self, field: Record) {
+        if let (Column::Int(ints), Record::Int(i)) = (&mut *self, &field) {
+            if i.to_i64().is_none() {
+                let bigints = ints.drain(..).map(BigInt::from).collect();
+                *self = Column::BigInt(bigints);
+            }
+        }
+        match (self, field) {
+            (Column::Bool(column), Record::Bool(b)) => column.push(b),
+            (Column::Int(column), Record::Int(i)) => column.push(i.to_i64().unwrap()),
+            (Column::BigInt(column), Record::Int(i)) => column.push(i),
+            (Column::Float(column), Record::Float(f)) => column.push(f),
+            (Column::Double(column), Record::Double(d)) => column.push(d),
+            (Column::String(column), Record::String(s)) => column.push(s),
+            (column, field) => panic!("Column::push: {:?} does not accept {}", column, field),
+        }
+    }
+
+    fn get(&self, row: usize) -> Record {
+        match self {
+            Column::Bool(column) => Record::Bool(column[row]),
+            Column::Int(column) => Record::Int(BigInt::from(column[row])),
+            Column::BigInt(column) => Record::Int(column[row].clone()),
+            Column::Float(column) => Record::Float(column[row]),
+            Column::Double(column) => Record::Double(column[row]),
+            Column::String(column) => Record::String(column[row].clone()),
+        }
+    }
+
+    fn matches(&self, row: usize, field: &Record) -> bool {
+        match (self, field) {
+            (Column::Bool(column), Record::Bool(b)) => column[row] == *b,
+            (Column::Int(column), Record::Int(i)) => i.to_i64() == Some(column[row]),
+            (Column::BigInt(column), Record::Int(i)) => column[row] == *i,
+            (Column::Float(column), Record::Float(f)) => column[row] == *f,
+            (Column::Double(column), Record::Double(d)) => column[row] == *d,
+            (Column::String(column), Record::String(s)) => column[row] == *s,
+            _ => false,
+        }
+    }
+
+    /// Remove all fields, forgetting that the column contained large integers.
+    fn clear(&mut self) {
+        *self = match self {
+            Column::Bool(_) => Column::Bool(Vec::new()),
+            Column::Int(_) | Column::BigInt(_) => Column::Int(Vec::new()),
+            Column::Float(_) => Column::Float(Vec::new()),
+            Column::Double(_) => Column::Double(Vec::new()),
+            Column::String(_) => Column::String(Vec::new()),
+        };
+    }
+
+    fn swap_remove(&mut self, row: usize) {
+        match self {
+            Column::Bool(column) => {
+                column.swap_remove(row);
+            }
+            Column::Int(column) => {
+                column.swap_remove(row);
+            }
+            Column::BigInt(column) => {
+                column.swap_remove(row);
+            }
+            Column::Float(column) => {
+                column.swap_remove(row);
+            }
+            Column::Double(column) => {
+                column.swap_remove(row);
+            }
+            Column::String(column) => {
+                column.swap_remove(row);
+            }
+        }
+    }
+}
+
+fn hash_value(v: &DDValue) -> u64 {
+    let mut hasher = FnvHasher::default();
+    v.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A set of values of a flat relation stored column by column.
+#[derive(Debug, Clone)]
+pub struct ColumnarSet {
+    relid: RelId,
+    decode: ColumnarDecodeFunc,
+    /// The shape of all records, known once the first record is inserted.
+    shape: Option<Shape>,
+    columns: Vec<Column>,
+    /// Hash of the value in each row.
+    hashes: Vec<u64>,
+    /// Rows by the hash of their value.
+    index: FnvHashMap<u64, Vec<usize>>,
+}
+
+impl ColumnarSet {
+    pub fn new(relid: RelId, decode: ColumnarDecodeFunc) -> Self {
+        Self {
+            relid,
+            decode,
+            shape: None,
+            columns: Vec::new(),
+            hashes: Vec::new(),
+            index: FnvHashMap::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    fn find(&self, hash: u64, shape: &Shape, fields: &[Record]) -> Option<usize> {
+        if self.shape.as_ref() != Some(shape) || fields.len() != self.columns.len() {
+            return None;
+        }
+        self.index.get(&hash)?.iter().copied().find(|row| {
+            self.columns
+                .iter()
+                .zip(fields)
+                .all(|(column, field)| column.matches(*row, field))
+        })
+    }
+
+    pub fn contains(&self, v: &DDValue) -> bool {
+        match split(v.clone().into_record()) {
+            Ok((shape, fields)) => self.find(hash_value(v), &shape, &fields).is_some(),
+            Err(_) => false,
+        }
+    }
+
+    /// Insert `v` in the set.  Returns `false` if the set already contains
+    /// `v`, and an error if `v` does not have the shape of the relation's
+    /// records.
+    pub fn insert(&mut self, v: &DDValue) -> Result<bool, String> {
+        let (shape, fields) = split(v.clone().into_record())
+            .map_err(|e| format!("relation {}: {}", self.relid, e))?;
+        let hash = hash_value(v);
+        if self.find(hash, &shape, &fields).is_some() {
+            return Ok(false);
+        }
+
+        if self.shape.is_none() {
+            self.columns = fields
+                .iter()
+                .map(Column::new)
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("relation {}: {}", self.relid, e))?;
+            self.shape = Some(shape);
+        } else if self.shape.as_ref() != Some(&shape)
+            || fields.len() != self.columns.len()
+            || !self
+                .columns
+                .iter()
+                .zip(fields.iter())
+                .all(|(column, field)| column.accepts(field))
+        {
+            return Err(format!(
+                "relation {}: value {:?} does not match the columns of the relation",
+                self.relid, v
+            ));
+        }
+
+        for (column, field) in self.columns.iter_mut().zip(fields) {
+            column.push(field);
+        }
+        self.index.entry(hash).or_default().push(self.hashes.len());
+        self.hashes.push(hash);
+        Ok(true)
+    }
+
+    /// Remove `v` from the set.  Returns `false` if the set does not contain
+    /// `v`.
+    pub fn remove(&mut self, v: &DDValue) -> bool {
+        let (shape, fields) = match split(v.clone().into_record()) {
+            Ok(split) => split,
+            Err(_) => return false,
+        };
+        let hash = hash_value(v);
+        match self.find(hash, &shape, &fields) {
+            Some(row) => {
+                self.remove_row(row);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_row(&mut self, row: usize) {
+        let hash = self.hashes[row];
+        let rows = self.index.get_mut(&hash).unwrap();
+        rows.retain(|r| *r != row);
+        if rows.is_empty() {
+            self.index.remove(&hash);
+        }
+
+        // The last row moves into the place of the removed row.
+        let last = self.hashes.len() - 1;
+        if row != last {
+            for r in self.index.get_mut(&self.hashes[last]).unwrap().iter_mut() {
+                if *r == last {
+                    *r = row;
+                }
+            }
+        }
+        self.hashes.swap_remove(row);
+        for column in self.columns.iter_mut() {
+            column.swap_remove(row);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for column in self.columns.iter_mut() {
+            column.clear();
+        }
+        self.hashes.clear();
+        self.index.clear();
+    }
+
+    fn get(&self, row: usize) -> DDValue {
+        let mut fields: Vec<Record> = self.columns.iter().map(|column| column.get(row)).collect();
+        let record = match self.shape.as_ref().unwrap() {
+            Shape::Scalar => fields.pop().unwrap(),
+            Shape::Tuple => Record::Tuple(fields),
+            Shape::PosStruct(name) => Record::PosStruct(name.clone(), fields),
+            Shape::NamedStruct(name, names) => {
+                Record::NamedStruct(name.clone(), names.iter().cloned().zip(fields).collect())
+            }
+        };
+        (self.decode)(&record).unwrap_or_else(|e| {
+            panic!(
+                "relation {}: failed to decode stored record {}: {}",
+                self.relid, record, e
+            )
+        })
+    }
+
+    /// The values in the set, decoded on the fly.
+    pub fn iter(&self) -> impl Iterator<Item = DDValue> + '_ {
+        (0..self.len()).map(move |row| self.get(row))
+    }
+}
+
+#[test]
+fn test_columnar_set() {
+    use crate::ddval::DDValConvert;
+    use crate::record::FromRecord;
+
+    fn decode(record: &Record) -> Result<DDValue, String> {
+        <(u64, String)>::from_record(record).map(|v| v.into_ddvalue())
+    }
+
+    let value = |i: u64| (i, format!("v{}", i)).into_ddvalue();
+    let mut set = ColumnarSet::new(1, decode);
+    for i in 0..10 {
+        assert_eq!(set.insert(&value(i)), Ok(true));
+    }
+    assert_eq!(set.insert(&value(3)), Ok(false));
+    assert!(set.insert(&1u64.into_ddvalue()).is_err());
+    assert_eq!(set.len(), 10);
+
+    assert!(set.remove(&value(0)));
+    assert!(!set.remove(&value(0)));
+    assert!(!set.contains(&value(0)));
+    assert!(set.contains(&value(9)));
+
+    let mut values: Vec<DDValue> = set.iter().collect();
+    values.sort();
+    assert_eq!(values, (1..10).map(value).collect::<Vec<_>>());
+
+    set.clear();
+    assert!(set.is_empty());
+    assert_eq!(set.insert(&value(1)), Ok(true));
+}
+
+#[test]
+fn test_columnar_bigint() {
+    use crate::ddval::DDValConvert;
+    use crate::record::FromRecord;
+
+    fn decode(record: &Record) -> Result<DDValue, String> {
+        u128::from_record(record).map(|v| v.into_ddvalue())
+    }
+
+    let mut set = ColumnarSet::new(1, decode);
+    assert_eq!(set.insert(&1u128.into_ddvalue()), Ok(true));
+    assert_eq!(set.insert(&u128::MAX.into_ddvalue()), Ok(true));
+    assert!(set.contains(&1u128.into_ddvalue()));
+    assert!(set.contains(&u128::MAX.into_ddvalue()));
+    assert_eq!(set.iter().count(), 2);
+}
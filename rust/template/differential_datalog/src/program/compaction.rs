@@ -0,0 +1,128 @@
+//! Controlling the compaction of index arrangements.
+//!
+//! Workers keep a trace for each arrangement used by an index, i.e., each
+//! arrangement that can be queried with `RunningProgram::query_arrangement()`.
+//! By default, traces are compacted to the current timestamp after every
+//! transaction, keeping only the current contents of the arrangement.  A
+//! _compaction lag_ makes the trace retain the history of the last `lag`
+//! timestamps instead, at the cost of memory.  A `CompactionPolicy` bounds
+//! this cost by suspending the lag of arrangements that are rarely queried.
+//!
+//! Arrangements internal to the dataflow are compacted by differential
+//! dataflow itself and are not affected.
+
+use fnv::FnvHashMap;
+
+use crate::program::{ArrId, TS};
+
+/// Policy for compacting arrangements that have a compaction lag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionPolicy {
+    /// Arrangements that have not been queried for this many timestamps are
+    /// compacted to the current timestamp; their lag is restored the next
+    /// time they are queried.
+    pub idle_timestamps: TS,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ArrangementCompaction {
+    /// The configured lag.
+    lag: TS,
+    /// The lag currently applied by workers; `0` while suspended by the
+    /// policy.
+    effective_lag: TS,
+    last_query: TS,
+}
+
+/// Compaction settings of a running program.  Changes to the lag applied by
+/// workers are returned to the caller, which forwards them to the workers.
+#[derive(Debug, Default)]
+pub(crate) struct Compaction {
+    arrangements: FnvHashMap<ArrId, ArrangementCompaction>,
+    policy: Option<CompactionPolicy>,
+}
+
+impl Compaction {
+    /// Configure the lag of `arrid`.
+    pub(crate) fn set_lag(&mut self, arrid: ArrId, lag: TS, now: TS) {
+        if lag == 0 {
+            self.arrangements.remove(&arrid);
+        } else {
+            self.arrangements.insert(
+                arrid,
+                ArrangementCompaction {
+                    lag,
+                    effective_lag: lag,
+                    last_query: now,
+                },
+            );
+        }
+    }
+
+    pub(crate) fn lag(&self, arrid: ArrId) -> TS {
+        self.arrangements.get(&arrid).map_or(0, |arr| arr.lag)
+    }
+
+    pub(crate) fn set_policy(&mut self, policy: Option<CompactionPolicy>) {
+        self.policy = policy;
+    }
+
+    /// Record a query of `arrid` at timestamp `now`.  Returns the lag to
+    /// apply if the lag of the arrangement was suspended.
+    pub(crate) fn on_query(&mut self, arrid: ArrId, now: TS) -> Option<TS> {
+        let arr = self.arrangements.get_mut(&arrid)?;
+        arr.last_query = now;
+        if arr.effective_lag == arr.lag {
+            None
+        } else {
+            arr.effective_lag = arr.lag;
+            Some(arr.lag)
+        }
+    }
+
+    /// Apply the policy at timestamp `now`.  Returns the arrangements whose
+    /// lag must be suspended.
+    pub(crate) fn on_advance(&mut self, now: TS) -> Vec<ArrId> {
+        let idle = match self.policy {
+            Some(policy) => policy.idle_timestamps,
+            None => return Vec::new(),
+        };
+        let mut suspended = Vec::new();
+        for (arrid, arr) in self.arrangements.iter_mut() {
+            if arr.effective_lag != 0 && now.saturating_sub(arr.last_query) > idle {
+                arr.effective_lag = 0;
+                suspended.push(*arrid);
+            }
+        }
+        suspended
+    }
+}
+
+#[test]
+fn test_compaction() {
+    let arr1 = (1, 0);
+    let arr2 = (2, 0);
+
+    let mut compaction = Compaction::default();
+    compaction.set_lag(arr1, 10, 1);
+    compaction.set_lag(arr2, 5, 1);
+    assert_eq!(compaction.lag(arr1), 10);
+
+    // Without a policy, lags are never suspended.
+    assert!(compaction.on_advance(100).is_empty());
+
+    compaction.set_policy(Some(CompactionPolicy { idle_timestamps: 3 }));
+    assert_eq!(compaction.on_query(arr1, 100), None);
+    let mut suspended = compaction.on_advance(102);
+    assert_eq!(suspended, vec![arr2]);
+    suspended = compaction.on_advance(104);
+    assert_eq!(suspended, vec![arr1]);
+    assert!(compaction.on_advance(105).is_empty());
+
+    // Querying a suspended arrangement restores its lag.
+    assert_eq!(compaction.on_query(arr1, 105), Some(10));
+    assert_eq!(compaction.on_query(arr1, 106), None);
+
+    compaction.set_lag(arr1, 0, 106);
+    assert_eq!(compaction.lag(arr1), 0);
+}
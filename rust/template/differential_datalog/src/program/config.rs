@@ -2,13 +2,15 @@
 
 use crate::{
     profile::Profile,
-    program::{worker::ProfilingData, Program, PROF_MSG_BUF_SIZE},
+    program::{placement::WorkerPlacement, worker::ProfilingData, Program, PROF_MSG_BUF_SIZE},
 };
 use differential_dataflow::Config as DDFlowConfig;
 use std::{
+    cell::Cell,
     env,
     sync::{atomic::AtomicBool, Arc, Mutex},
     thread::{self, JoinHandle},
+    time::Duration,
 };
 use timely::Config as TimelyConfig;
 
@@ -30,6 +32,65 @@ pub struct Config {
     ///
     /// See [`differential_dataflow::Config`]
     pub differential_idle_merge_effort: Option<isize>,
+    /// Seed for random number functions
+    ///
+    /// Random functions in DDlog are pure functions of this seed and
+    /// their arguments, so running the same inputs with the same seed
+    /// always produces the same outputs
+    pub random_seed: u64,
+    /// Partition relation data among tenants
+    ///
+    /// In multi-tenant mode, updates and queries are issued on behalf of
+    /// a tenant, and facts of different tenants never interact.  See
+    /// [`crate::program::tenant`]
+    pub multi_tenant: bool,
+    /// The number of committed transaction ids to remember
+    ///
+    /// See [`crate::program::RunningProgram::transaction_commit_with_id`]
+    pub txn_id_history: usize,
+    /// Build arrangements that duplicate another arrangement of the same
+    /// relation only once
+    ///
+    /// See [`crate::program::sharing`]
+    pub share_duplicate_arrangements: bool,
+    /// Buffer input updates until they are propagated through the
+    /// dataflow, cancelling out insertions and deletions of the same value
+    ///
+    /// See [`crate::program::batching`]
+    pub coalesce_updates: bool,
+    /// Defer commits made within this time of the first commit that has
+    /// not been propagated through the dataflow yet
+    ///
+    /// See [`crate::program::batching`]
+    pub commit_latency_budget: Option<Duration>,
+    /// Pin timely workers to CPUs or NUMA nodes
+    ///
+    /// See [`crate::program::placement`]
+    pub worker_placement: WorkerPlacement,
+    /// Allocate the memory of each worker from the NUMA node it runs on
+    ///
+    /// Most effective together with [`Config::worker_placement`]. See
+    /// [`crate::program::placement`]
+    pub numa_local_memory: bool,
+    /// Check that the multiplicity of every fact in every relation fits
+    /// into a [`crate::program::Weight`], failing the transaction that
+    /// overflows it
+    ///
+    /// See [`crate::program::overflow`]
+    pub check_weight_overflow: bool,
+    /// Order relation dumps and the changes passed to changelog callbacks
+    /// canonically, so that they do not depend on the number of workers or
+    /// on the order in which values were interned
+    ///
+    /// See [`crate::record::Record::canonical_cmp`]
+    pub canonical_order: bool,
+    /// Count the changes to relations that do not pass them to a change
+    /// callback, e.g., intermediate relations, which adds an operator per
+    /// relation to the dataflow
+    ///
+    /// Changes to relations with a callback are always counted. See
+    /// [`crate::program::stats`]
+    pub relation_stats: bool,
 }
 
 impl Config {
@@ -40,6 +101,17 @@ impl Config {
             enable_debug_regions: false,
             profiling_kind: ProfilingKind::default(),
             differential_idle_merge_effort: None,
+            random_seed: 0,
+            multi_tenant: false,
+            txn_id_history: 1024,
+            share_duplicate_arrangements: false,
+            coalesce_updates: false,
+            commit_latency_budget: None,
+            worker_placement: WorkerPlacement::Unpinned,
+            numa_local_memory: false,
+            check_weight_overflow: false,
+            canonical_order: false,
+            relation_stats: false,
         }
     }
 
@@ -79,6 +151,25 @@ impl Default for Config {
     }
 }
 
+thread_local! {
+    /// The seed of the program whose worker runs on the current thread, see
+    /// [`Config::random_seed`]
+    static RANDOM_SEED: Cell<u64> = Cell::new(0);
+}
+
+/// Returns the random seed of the program evaluated by the current worker
+/// thread
+///
+/// Every program sets the seed of its own workers when they start, so
+/// programs with different seeds can run side by side
+pub fn random_seed() -> u64 {
+    RANDOM_SEED.with(Cell::get)
+}
+
+pub(super) fn set_random_seed(seed: u64) {
+    RANDOM_SEED.with(|s| s.set(seed));
+}
+
 /// The kind of profiling to be enabled for DDlog
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProfilingKind {
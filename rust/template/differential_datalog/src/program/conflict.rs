@@ -0,0 +1,121 @@
+//! Primary-key conflict policies.
+//!
+//! Inserting a record into a relation with a primary key fails by default
+//! if the relation already contains a record with the same key.  A
+//! `ConflictPolicy` registered for the relation (see
+//! `RunningProgram::set_conflict_policy()`) resolves such conflicts
+//! instead: by keeping the existing record, by replacing it with the new
+//! record (last write wins), or by replacing it with a record computed from
+//! both by a user-defined merge function.
+//!
+//! Every conflict, whether resolved or rejected, is recorded as a
+//! `KeyConflict`; the conflicts recorded since the last call to
+//! `RunningProgram::take_key_conflicts()` are returned by it, e.g., to
+//! report them in a diagnostic relation.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::ddval::DDValue;
+use crate::program::RelId;
+
+/// Computes the record that replaces `existing` when `new` with the same
+/// key is inserted.  The result must have the same key.
+pub type MergeFunc = Arc<dyn Fn(&DDValue, &DDValue) -> Result<DDValue, String> + Send + Sync>;
+
+/// How to resolve an insertion of a record whose key is already present in
+/// the relation.
+#[derive(Clone)]
+pub enum ConflictPolicy {
+    /// Fail the insertion (the default).
+    Error,
+    /// Ignore the new record (first write wins).
+    KeepExisting,
+    /// Replace the existing record with the new one (last write wins).
+    Replace,
+    /// Replace the existing record with the result of the merge function.
+    Merge(MergeFunc),
+}
+
+impl fmt::Debug for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConflictPolicy::Error => f.write_str("Error"),
+            ConflictPolicy::KeepExisting => f.write_str("KeepExisting"),
+            ConflictPolicy::Replace => f.write_str("Replace"),
+            ConflictPolicy::Merge(_) => f.write_str("Merge(..)"),
+        }
+    }
+}
+
+/// Outcome of a conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    /// The insertion failed.
+    Rejected,
+    /// The new record was ignored.
+    KeptExisting,
+    /// The existing record was replaced by the new one.
+    Replaced,
+    /// The existing record was replaced by the merge of both.
+    Merged,
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Resolution::Rejected => "rejected",
+            Resolution::KeptExisting => "kept_existing",
+            Resolution::Replaced => "replaced",
+            Resolution::Merged => "merged",
+        };
+        f.write_str(name)
+    }
+}
+
+/// An insertion of a record whose key was already present in the relation.
+#[derive(Debug, Clone)]
+pub struct KeyConflict {
+    pub relid: RelId,
+    pub key: DDValue,
+    pub existing: DDValue,
+    pub new: DDValue,
+    pub resolution: Resolution,
+}
+
+impl ConflictPolicy {
+    /// Resolve the insertion of `new` into a relation that contains
+    /// `existing` with the same key, `key`.  Returns the record to store and
+    /// the outcome, or an error if the insertion must fail.
+    pub(crate) fn resolve<F>(
+        &self,
+        key: &DDValue,
+        existing: &DDValue,
+        new: &DDValue,
+        key_func: F,
+    ) -> Result<(DDValue, Resolution), String>
+    where
+        F: Fn(&DDValue) -> DDValue,
+    {
+        match self {
+            ConflictPolicy::Error => Err(format!(
+                "Insert: duplicate key '{:?}' in value '{:?}'",
+                key, new
+            )),
+            ConflictPolicy::KeepExisting => Ok((existing.clone(), Resolution::KeptExisting)),
+            ConflictPolicy::Replace => Ok((new.clone(), Resolution::Replaced)),
+            ConflictPolicy::Merge(merge) => {
+                let merged = merge(existing, new).map_err(|e| {
+                    format!("Insert: failed to merge values with key '{:?}': {}", key, e)
+                })?;
+                if key_func(&merged) != *key {
+                    return Err(format!(
+                        "Insert: merging values with key '{:?}' produced value '{:?}' with a different key",
+                        key, merged
+                    ));
+                }
+                Ok((merged, Resolution::Merged))
+            }
+        }
+    }
+}
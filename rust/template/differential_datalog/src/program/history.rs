@@ -0,0 +1,182 @@
+//! Retention of deleted facts.
+//!
+//! When history is retained for an input relation with set semantics (see
+//! `RunningProgram::set_history_retention()`), the runtime records when each
+//! of its facts was inserted and, when a transaction that deletes a fact
+//! commits, keeps the fact as a tombstone with its insertion and deletion
+//! times until the retention period has elapsed.  Changes to the history
+//! are reported as `HistoryEvent`s, e.g., to maintain a history relation
+//! against which temporal queries ("what did we know at T?") can be
+//! evaluated: `RunningProgram::transaction_history_events()` returns the
+//! changes that committing the current transaction makes, so that they can
+//! be applied as part of the same transaction, and
+//! `RunningProgram::take_history_events()` those made by starting and
+//! stopping retention between transactions.
+//!
+//! Times are in milliseconds since the UNIX epoch.  The time of a
+//! transaction is taken by the first call to `transaction_history_events()`
+//! or, failing that, when it commits.  Facts that were already present when
+//! retention started have insertion time 0.  Tombstones expire when a
+//! transaction commits after their retention period.
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fnv::FnvHashMap;
+
+use crate::ddval::DDValue;
+use crate::program::{DeltaSet, RelId};
+
+/// A version of a fact in the history of a relation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HistoryFact {
+    pub relid: RelId,
+    pub value: DDValue,
+    pub inserted: u64,
+    /// `None` while the fact is present in the relation.
+    pub deleted: Option<u64>,
+}
+
+/// A change to the history of a relation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryEvent {
+    /// A fact was inserted.
+    Inserted(HistoryFact),
+    /// A fact was deleted and is retained as a tombstone: the fact with
+    /// `deleted` set to `None` is replaced by this one.
+    Deleted(HistoryFact),
+    /// A fact, usually a tombstone, is no longer part of the history, because
+    /// its retention period has elapsed or retention was stopped.
+    Expired(HistoryFact),
+}
+
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// The history of one relation.
+#[derive(Debug)]
+pub(crate) struct RelationHistory {
+    relid: RelId,
+    retention: Duration,
+    /// Insertion times of the facts in the relation.
+    inserted: FnvHashMap<DDValue, u64>,
+    /// Tombstones, in the order of deletion.
+    tombstones: VecDeque<HistoryFact>,
+}
+
+impl RelationHistory {
+    /// Start retaining the history of `relid`, which contains `values`.
+    pub(crate) fn new<I>(
+        relid: RelId,
+        retention: Duration,
+        values: I,
+        events: &mut Vec<HistoryEvent>,
+    ) -> Self
+    where
+        I: Iterator<Item = DDValue>,
+    {
+        let mut inserted = FnvHashMap::default();
+        for value in values {
+            events.push(HistoryEvent::Inserted(HistoryFact {
+                relid,
+                value: value.clone(),
+                inserted: 0,
+                deleted: None,
+            }));
+            inserted.insert(value, 0);
+        }
+        Self {
+            relid,
+            retention,
+            inserted,
+            tombstones: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn set_retention(&mut self, retention: Duration) {
+        self.retention = retention;
+    }
+
+    /// The changes to the history that committing a transaction with the
+    /// changes `delta` to the relation at `now` makes, including the
+    /// expiration of old tombstones.  The history itself is only updated by
+    /// `commit()`.
+    pub(crate) fn changes(&self, delta: &DeltaSet, now: u64, events: &mut Vec<HistoryEvent>) {
+        for (value, weight) in delta.iter() {
+            if *weight > 0 {
+                events.push(HistoryEvent::Inserted(HistoryFact {
+                    relid: self.relid,
+                    value: value.clone(),
+                    inserted: now,
+                    deleted: None,
+                }));
+            } else if *weight < 0 {
+                events.push(HistoryEvent::Deleted(HistoryFact {
+                    relid: self.relid,
+                    value: value.clone(),
+                    inserted: self.inserted.get(value).cloned().unwrap_or(0),
+                    deleted: Some(now),
+                }));
+            }
+        }
+        let cutoff = self.cutoff(now);
+        events.extend(
+            self.tombstones
+                .iter()
+                .take_while(|tombstone| tombstone.deleted.unwrap_or(0) < cutoff)
+                .cloned()
+                .map(HistoryEvent::Expired),
+        );
+    }
+
+    /// Record the changes made to the relation by a transaction committed at
+    /// `now` and expire old tombstones, as reported by `changes()`.
+    pub(crate) fn commit(&mut self, delta: &DeltaSet, now: u64) {
+        for (value, weight) in delta.iter() {
+            if *weight > 0 {
+                self.inserted.insert(value.clone(), now);
+            } else if *weight < 0 {
+                self.tombstones.push_back(HistoryFact {
+                    relid: self.relid,
+                    value: value.clone(),
+                    inserted: self.inserted.remove(value).unwrap_or(0),
+                    deleted: Some(now),
+                });
+            }
+        }
+
+        let cutoff = self.cutoff(now);
+        while let Some(tombstone) = self.tombstones.front() {
+            if tombstone.deleted.unwrap_or(0) >= cutoff {
+                break;
+            }
+            self.tombstones.pop_front();
+        }
+    }
+
+    /// Tombstones deleted before the returned time have expired at `now`.
+    fn cutoff(&self, now: u64) -> u64 {
+        now.saturating_sub(self.retention.as_millis() as u64)
+    }
+
+    /// Stop retaining the history, expiring all of it.
+    pub(crate) fn stop(self, events: &mut Vec<HistoryEvent>) {
+        let relid = self.relid;
+        events.extend(
+            self.inserted
+                .into_iter()
+                .map(|(value, inserted)| HistoryFact {
+                    relid,
+                    value,
+                    inserted,
+                    deleted: None,
+                })
+                .chain(self.tombstones)
+                .map(HistoryEvent::Expired),
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! Lazy relations, computed when queried.
+//!
+//! A derived relation listed in `Program::lazy_rels` is not maintained
+//! incrementally.  Instead, the collections its rules start from are passed
+//! through a _gate_, an input collection that is empty except while the
+//! relation is being queried.  While the gate is closed, the relation and
+//! all intermediate results of its rules are empty and take no memory.  To
+//! query the relation, `RunningProgram` opens its gate (and the gates of the
+//! lazy relations it depends on), queries the relation's arrangement, and
+//! closes the gate again, retracting the relation's contents.
+//!
+//! Rules of lazy relations join with the arrangements of other relations as
+//! usual, so that these arrangements are shared rather than rebuilt on
+//! every query.  The collections a rule starts from are arranged once in
+//! order to be gated, which costs memory proportional to their size.
+//!
+//! Lazy relations must be non-recursive derived relations, and only other
+//! lazy relations may depend on them.  Their change callbacks see the
+//! relation's contents being inserted and retracted around every query.
+
+use differential_dataflow::{
+    hashable::Hashable,
+    operators::{arrange::ArrangeByKey, JoinCore},
+    Collection,
+};
+use fnv::{FnvHashMap, FnvHashSet};
+use timely::dataflow::Scope;
+
+use crate::ddval::DDValue;
+use crate::program::{Dep, ProgNode, Program, RelId, Weight, TS};
+
+/// Gated collections are keyed by the hash of their values modulo the
+/// number of buckets to spread them over workers.  An open gate contains
+/// all buckets.
+pub(crate) const GATE_BUCKETS: u64 = 256;
+
+impl Program {
+    /// Check the lazy relations of the program.  Returns the lazy relations
+    /// whose gates must be opened to query each lazy relation.
+    pub(super) fn lazy_gates(&self) -> Result<FnvHashMap<RelId, Vec<RelId>>, String> {
+        let lazy: FnvHashSet<RelId> = self.lazy_rels.iter().copied().collect();
+        if lazy.is_empty() {
+            return Ok(FnvHashMap::default());
+        }
+
+        // Lazy relations that each relation depends on directly.
+        let mut lazy_deps: FnvHashMap<RelId, Vec<RelId>> = FnvHashMap::default();
+        for node in self.nodes.iter() {
+            for rel in Self::node_relations(node) {
+                if lazy.contains(&rel.id) && (rel.input || !matches!(node, ProgNode::Rel { .. })) {
+                    return Err(format!(
+                        "lazy relation {} must be a non-recursive derived relation",
+                        rel.name
+                    ));
+                }
+
+                let deps: FnvHashSet<RelId> = rel
+                    .rules
+                    .iter()
+                    .flat_map(|rule| rule.dependencies())
+                    .map(|dep: Dep| dep.relid())
+                    .filter(|relid| lazy.contains(relid))
+                    .collect();
+                if let Some(dep) = deps.iter().next() {
+                    if !lazy.contains(&rel.id) {
+                        return Err(format!(
+                            "relation {} depends on lazy relation {}",
+                            rel.name,
+                            self.get_relation(*dep).name
+                        ));
+                    }
+                }
+                lazy_deps.insert(rel.id, deps.into_iter().collect());
+            }
+        }
+        if let Some(drel) = self
+            .delayed_rels
+            .iter()
+            .find(|drel| lazy.contains(&drel.rel_id))
+        {
+            return Err(format!(
+                "lazy relation {} cannot be delayed",
+                self.get_relation(drel.rel_id).name
+            ));
+        }
+
+        let mut gates = FnvHashMap::default();
+        for relid in lazy.iter() {
+            if !lazy_deps.contains_key(relid) {
+                return Err(format!("unknown lazy relation {}", relid));
+            }
+            let mut closure = vec![*relid];
+            let mut i = 0;
+            while i < closure.len() {
+                for dep in lazy_deps[&closure[i]].iter() {
+                    if !closure.contains(dep) {
+                        closure.push(*dep);
+                    }
+                }
+                i += 1;
+            }
+            closure.sort_unstable();
+            gates.insert(*relid, closure);
+        }
+        Ok(gates)
+    }
+}
+
+/// Pass `collection` through gate `gate`: the result contains the contents of
+/// `collection` while the gate is open and is empty otherwise.
+pub(crate) fn gate_collection<S>(
+    collection: &Collection<S, DDValue, Weight>,
+    gate: &Collection<S, u64, Weight>,
+) -> Collection<S, DDValue, Weight>
+where
+    S: Scope<Timestamp = TS>,
+{
+    let gate = gate.map(|bucket| (bucket, ())).arrange_by_key();
+    collection
+        .map(|v| (v.hashed() % GATE_BUCKETS, v))
+        .arrange_by_key()
+        .join_core(&gate, |_, v, _| Some(v.clone()))
+}
@@ -13,12 +13,31 @@
 // TODO: single input relation
 
 pub mod arrange;
+pub mod batching;
+pub mod columnar;
+pub mod compaction;
 pub mod config;
+pub mod conflict;
+pub mod history;
+mod lazy;
+pub mod overflow;
+pub mod placement;
+pub mod plan;
+mod poison;
+pub mod progress;
+pub mod sharing;
+pub mod stats;
+mod stratification;
+pub mod tenant;
+mod time_travel;
 mod timestamp;
+mod txn_ids;
 mod update;
 mod worker;
 
 pub use arrange::diff_distinct;
+pub use poison::DDlogError;
+pub use stratification::{StratificationError, StratificationErrorKind};
 pub use timestamp::{TSNested, TupleTS, TS};
 pub use update::Update;
 
@@ -35,24 +54,41 @@ use crate::{
 use arrange::{
     antijoin_arranged, Arrangement as DataflowArrangement, ArrangementFlavor, Arrangements,
 };
+use batching::{CommitBatcher, UpdateBuffer};
+use columnar::{ColumnarDecodeFunc, ColumnarSet};
+use compaction::{Compaction, CompactionPolicy};
 use config::{Config, SelfProfilingRig};
-use crossbeam_channel::{Receiver, Sender};
+use conflict::{ConflictPolicy, KeyConflict, Resolution};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 use fnv::{FnvHashMap, FnvHashSet};
+use history::{HistoryEvent, RelationHistory};
+use plan::{Plan, PlanFormat};
+pub(crate) use poison::guard;
+use poison::guard_iter;
+use progress::{Frontier, Progress};
+use sharing::DuplicateArrangement;
+use stats::{RelationStats, Stats};
 use std::{
     any::Any,
     borrow::Cow,
     cmp,
-    collections::{hash_map, BTreeSet},
+    collections::{hash_map, BTreeMap, BTreeSet},
     fmt::{self, Debug, Formatter},
     iter::{self, Cycle, Skip},
     ops::Range,
+    rc::Rc,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc, Mutex,
     },
     thread::JoinHandle,
+    time::{Duration, Instant},
 };
+use tenant::{lift_key, TenantId};
+pub(crate) use tenant::{MultiTenant, SingleTenant, TenantMode};
+use time_travel::TimeTravel;
 use timestamp::ToTupleTS;
+use txn_ids::TxnIds;
 use worker::DDlogWorker;
 
 use differential_dataflow::lattice::Lattice;
@@ -85,11 +121,25 @@ type TValEnter<P, T> = TraceEnter<TValAgent<P>, T>;
 type TKeyEnter<P, T> = TraceEnter<TKeyAgent<P>, T>;
 
 /// Diff associated with records in differential dataflow
+#[cfg(not(any(feature = "weight_64", feature = "weight_128")))]
 pub type Weight = i32;
 
+/// Diff associated with records in differential dataflow
+/// Use 64-bit weights for programs with facts that have many derivations
+#[cfg(all(feature = "weight_64", not(feature = "weight_128")))]
+pub type Weight = i64;
+
+/// Diff associated with records in differential dataflow
+/// Use 128-bit weights for programs with facts that have many derivations
+#[cfg(feature = "weight_128")]
+pub type Weight = i128;
+
 /// Message buffer for profiling messages
 const PROF_MSG_BUF_SIZE: usize = 10_000;
 
+/// Number of values sent to a worker at a time by `RunningProgram::bulk_load()`
+const BULK_LOAD_BATCH_SIZE: usize = 100_000;
+
 /// Result type returned by this library
 pub type Response<X> = Result<X, String>;
 
@@ -163,11 +213,20 @@ pub type AggFunc = fn(&DDValue, &[(&DDValue, Weight)]) -> Option<DDValue>;
 /// individual non-recursive relations and strongly connected components
 /// comprised of one or more mutually recursive relations.
 /// * `delayed_rels` - delayed relations used in the program.
+/// * `lazy_rels` - derived relations computed when queried rather than
+///   maintained incrementally (see `lazy`).
+/// * `shared_arrangements` - pairs of arrangements of the same relation,
+///   where the first arrangement is replaced by the second (see `sharing`).
+/// * `columnar_rels` - input relations stored column by column, along with
+///   functions that decode their records (see `columnar`).
 /// * `init_data` - initial relation contents.
 #[derive(Clone)]
 pub struct Program {
     pub nodes: Vec<ProgNode>,
     pub delayed_rels: Vec<DelayedRelation>,
+    pub lazy_rels: Vec<RelId>,
+    pub shared_arrangements: Vec<(ArrId, ArrId)>,
+    pub columnar_rels: Vec<(RelId, ColumnarDecodeFunc)>,
     pub init_data: Vec<(RelId, DDValue)>,
 }
 
@@ -856,6 +915,44 @@ pub type IndexedValSet = FnvHashMap<DDValue, DDValue>;
 /// Relation delta
 pub type DeltaSet = FnvHashMap<DDValue, isize>;
 
+/// Key extractor used to upsert values into a multiset relation (see
+/// `RunningProgram::set_upsert_key()`).
+pub type UpsertKeyFunc = Arc<dyn Fn(&DDValue) -> DDValue + Send + Sync>;
+
+/// Index of a multiset relation by a user-defined key, used to implement
+/// `insert_or_update` for relations without a primary key.
+struct UpsertIndex {
+    key_func: UpsertKeyFunc,
+    /// Distinct values with positive weight in the relation, grouped by key.
+    values: FnvHashMap<DDValue, FnvHashSet<DDValue>>,
+}
+
+impl UpsertIndex {
+    fn new(key_func: UpsertKeyFunc, elements: &ValMSet) -> Self {
+        let mut index = UpsertIndex {
+            key_func,
+            values: FnvHashMap::default(),
+        };
+        for v in elements.keys() {
+            index.update(elements, v);
+        }
+        index
+    }
+
+    /// Update the index after the weight of `v` in `elements` has changed.
+    fn update(&mut self, elements: &ValMSet, v: &DDValue) {
+        let key = lift_key(v, &*self.key_func);
+        if elements.get(v).map_or(false, |w| *w > 0) {
+            self.values.entry(key).or_default().insert(v.clone());
+        } else if let hash_map::Entry::Occupied(mut oe) = self.values.entry(key) {
+            oe.get_mut().remove(v);
+            if oe.get().is_empty() {
+                oe.remove_entry();
+            }
+        }
+    }
+}
+
 /// Runtime representation of a datalog program.
 ///
 /// The program will be automatically stopped when the object goes out
@@ -874,7 +971,65 @@ pub struct RunningProgram {
     relations: FnvHashMap<RelId, RelationInstance>,
     worker_guards: Option<WorkerGuards<Result<(), String>>>,
     transaction_in_progress: bool,
+    /// The current transaction has been prepared (see
+    /// `transaction_prepare()`) and can no longer be modified.
+    prepared: bool,
+    /// Output changes held back by the workers until the current
+    /// transaction is committed (see `transaction_prepare()`).
+    held_outputs: Option<HeldOutputs>,
+    /// Set when a user function panics during the current transaction.  A
+    /// poisoned transaction cannot be committed, only rolled back.
+    poisoned: Option<DDlogError>,
+    /// Relation data is partitioned among tenants (see `Config::multi_tenant`).
+    multi_tenant: bool,
+    /// Tenants that have updated the program; initial data has been added
+    /// for each of them.
+    tenants: FnvHashSet<TenantId>,
+    /// Initial data of the program, added for each new tenant in
+    /// multi-tenant mode.
+    init_data: Vec<(RelId, DDValue)>,
+    /// Progress of the dataflow, reported by workers.
+    progress: Arc<Progress>,
+    /// Ids of recently committed transactions (see
+    /// `transaction_commit_with_id()`).
+    committed_txns: TxnIds,
+    /// Compaction settings of index arrangements.
+    compaction: Compaction,
+    /// Gates to open to query each lazy relation.
+    lazy_gates: FnvHashMap<RelId, Vec<RelId>>,
+    /// Duplicate arrangements of the program.
+    arrangement_report: Vec<DuplicateArrangement>,
+    /// Primary-key conflict policies of input relations (see `conflict`).
+    conflict_policies: FnvHashMap<RelId, ConflictPolicy>,
+    /// Primary-key conflicts since the last `take_key_conflicts()`.
+    key_conflicts: Vec<KeyConflict>,
+    /// Input relations whose history is retained (see `history`).
+    history: FnvHashMap<RelId, RelationHistory>,
+    /// Changes to the history made between transactions since the last
+    /// `take_history_events()`.
+    history_events: Vec<HistoryEvent>,
+    /// Time of the current transaction in the history, once fixed by
+    /// `transaction_history_events()`.
+    history_time: Option<u64>,
+    /// Commit numbers and arrangements that retain past commits (see
+    /// `time_travel`).
+    time_travel: TimeTravel,
+    /// Dataflow plan of the program.
+    plan: Plan,
+    /// Statistics of all relations.
+    stats: Stats,
+    /// No transaction has been started yet, so that values can still be
+    /// bulk-loaded.
+    bulk_load_allowed: bool,
+    /// Updates not sent to workers yet (see `Config::coalesce_updates`).
+    update_buffer: Option<UpdateBuffer>,
+    /// Commits not propagated through the dataflow yet (see
+    /// `Config::commit_latency_budget`).
+    batcher: CommitBatcher,
     need_to_flush: bool,
+    /// Flush acks still expected from each worker after a commit exceeded
+    /// its deadline (see `transaction_commit_with_deadline()`).
+    pending_acks: Vec<usize>,
     timestamp: TS,
     /// CPU profiling enabled (can be expensive).
     profile_cpu: Option<Arc<AtomicBool>>,
@@ -920,6 +1075,9 @@ enum RelationInstance {
         elements: ValMSet,
         /// Changes since start of transaction.
         delta: DeltaSet,
+        /// Index used to upsert values, if a key extractor has been registered
+        /// for the relation.
+        upsert_index: Option<UpsertIndex>,
     },
     Flat {
         /// Set of all elements in the relation. Used to enforce set semantics for input relations
@@ -937,15 +1095,33 @@ enum RelationInstance {
         /// enforce set semantics.
         delta: DeltaSet,
     },
+    Columnar {
+        /// Set of all elements in the relation, stored column by column.
+        elements: ColumnarSet,
+        /// Changes since start of transaction.
+        delta: DeltaSet,
+    },
 }
 
 impl RelationInstance {
+    /// The number of elements in the relation, unless it is a stream.
+    fn len(&self) -> Option<usize> {
+        match self {
+            RelationInstance::Stream { .. } => None,
+            RelationInstance::Multiset { elements, .. } => Some(elements.len()),
+            RelationInstance::Flat { elements, .. } => Some(elements.len()),
+            RelationInstance::Indexed { elements, .. } => Some(elements.len()),
+            RelationInstance::Columnar { elements, .. } => Some(elements.len()),
+        }
+    }
+
     pub fn delta(&self) -> &DeltaSet {
         match self {
             RelationInstance::Stream { delta } => delta,
             RelationInstance::Multiset { delta, .. } => delta,
             RelationInstance::Flat { delta, .. } => delta,
             RelationInstance::Indexed { delta, .. } => delta,
+            RelationInstance::Columnar { delta, .. } => delta,
         }
     }
 
@@ -955,10 +1131,21 @@ impl RelationInstance {
             RelationInstance::Multiset { delta, .. } => delta,
             RelationInstance::Flat { delta, .. } => delta,
             RelationInstance::Indexed { delta, .. } => delta,
+            RelationInstance::Columnar { delta, .. } => delta,
         }
     }
 }
 
+/// Output changes held back while a transaction is prepared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HeldOutputs {
+    /// The changes of the transaction.
+    Transaction,
+    /// The changes of the transaction and of the deferred commits (see
+    /// `Config::commit_latency_budget`) that share its dataflow epoch.
+    WithDeferred,
+}
+
 /// Messages sent to timely worker threads.
 #[derive(Debug, Clone)]
 enum Msg {
@@ -978,6 +1165,35 @@ enum Msg {
     /// all values in the collection; otherwise returns values associated
     /// with the specified key.
     Query(ArrId, Option<DDValue>),
+    /// Query arrangement as of a past timestamp: only values with times
+    /// before the third argument are taken into account.
+    QueryAsOf(ArrId, Option<DDValue>, TS),
+    /// Open or close the gate of a lazy relation.
+    Gate { relid: RelId, open: bool },
+    /// Insert values into an input relation (see
+    /// `RunningProgram::bulk_load()`).
+    BulkLoad {
+        relid: RelId,
+        values: Vec<DDValue>,
+        timestamp: TS,
+    },
+    /// Set the compaction lag of an arrangement.
+    SetCompactionLag(ArrId, TS),
+    /// Compact the trace of an arrangement, or of all arrangements, to the
+    /// current timestamp.
+    CompactNow(Option<ArrId>),
+    /// Hold back output changes at the given timestamp until
+    /// `ReleaseOutputs` or `DiscardOutputs` (see
+    /// `RunningProgram::transaction_prepare()` and
+    /// `RunningProgram::transaction_commit_with_deadline()`).
+    HoldOutputs(TS),
+    /// Reply with the net held back changes to an output relation.
+    HeldOutputs(RelId),
+    /// Deliver the held back output changes and acknowledge.
+    ReleaseOutputs,
+    /// Drop the held back output changes, along with all output changes at
+    /// timestamps up to and including the given one.
+    DiscardOutputs(TS),
     /// Stop worker.
     Stop,
 }
@@ -987,8 +1203,15 @@ enum Msg {
 enum Reply {
     /// Acknowledge flush completion.
     FlushAck,
-    /// Result of a query.
-    QueryRes(Option<BTreeSet<DDValue>>),
+    /// Flush completed, but a user function panicked while processing the
+    /// flushed updates.
+    Panic(DDlogError),
+    /// Result of a query: values in the arrangement and their
+    /// multiplicities.
+    QueryRes(Option<BTreeMap<DDValue, Weight>>),
+    /// The trace queried by `Msg::QueryAsOf` has been compacted past the
+    /// requested time.
+    QueryCompacted,
 }
 
 impl Program {
@@ -1005,6 +1228,26 @@ impl Program {
 
     /// Initialize the program with the given configuration
     pub fn run_with_config(&self, config: Config) -> Result<RunningProgram, String> {
+        // Reject programs that cannot be stratified before starting worker
+        // threads, which would otherwise fail during dataflow construction.
+        self.check_stratification()?;
+        let lazy_gates = self.lazy_gates()?;
+        let shared_arrangements =
+            self.arrangements_to_share(config.share_duplicate_arrangements)?;
+
+        // Transformers are opaque to the runtime and cannot preserve tenant
+        // tags.
+        if config.multi_tenant
+            && self
+                .nodes
+                .iter()
+                .any(|node| matches!(node, ProgNode::Apply { .. }))
+        {
+            return Err(
+                "multi-tenant mode is not supported for programs that use transformers".to_string(),
+            );
+        }
+
         // Setup channels to communicate with the dataflow.
         // We use async channels to avoid deadlocks when workers are parked in
         // `step_or_park`.  This has the downside of introducing an unbounded buffer
@@ -1020,17 +1263,45 @@ impl Program {
             .unzip();
         let reply_send = Arc::from(reply_send);
 
+        let progress = Arc::new(Progress::new(config.num_timely_workers));
+        let worker_progress = progress.clone();
+
         let profiling_rig = SelfProfilingRig::new(&config);
 
         // Clone the program so that it can be moved into the timely computation
-        let program = Arc::new(self.clone());
+        let mut program = self.clone();
+        program.shared_arrangements = shared_arrangements;
+        let arrangement_report = program.arrangement_report();
+        let plan = program.plan();
+        let stats = Stats::new(
+            program
+                .nodes
+                .iter()
+                .flat_map(Self::node_relations)
+                .filter(|rel| config.relation_stats || rel.change_cb.is_some())
+                .map(|rel| rel.id),
+        );
+        let stats_counters = stats.counters();
+        let program = Arc::new(program);
         let timely_config = config.timely_config()?;
+        let placement =
+            placement::assign_workers(config.worker_placement, config.num_timely_workers)?;
         let (worker_config, profiling_data) = (config, profiling_rig.profiling_data.clone());
 
         // Start up timely computation.
         let worker_guards = timely::execute(
             timely_config,
             move |worker: &mut Worker<Allocator>| -> Result<_, String> {
+                // Must be set before the worker starts evaluating random
+                // functions.
+                config::set_random_seed(worker_config.random_seed);
+                placement::place_worker(
+                    placement
+                        .as_ref()
+                        .map(|cpus| cpus[worker.index()].as_slice()),
+                    worker_config.numa_local_memory,
+                )?;
+
                 let worker = DDlogWorker::new(
                     worker,
                     worker_config,
@@ -1038,6 +1309,8 @@ impl Program {
                     profiling_data.clone(),
                     Arc::clone(&request_recv),
                     Arc::clone(&reply_send),
+                    Arc::clone(&worker_progress),
+                    stats_counters.clone(),
                 );
 
                 worker.run()
@@ -1045,6 +1318,19 @@ impl Program {
         )
         .map_err(|err| format!("Failed to start timely computation: {:?}", err))?;
 
+        let columnar: FnvHashMap<RelId, ColumnarDecodeFunc> =
+            self.columnar_rels.iter().copied().collect();
+        for relid in columnar.keys() {
+            let rel = self.get_relation(*relid);
+            if !rel.input || !matches!(rel.caching_mode, CachingMode::Set) || rel.key_func.is_some()
+            {
+                return Err(format!(
+                    "columnar relation {} must be an input relation with set semantics and no primary key",
+                    rel.name
+                ));
+            }
+        }
+
         let mut rels = FnvHashMap::default();
         for relid in self.input_relations() {
             let rel = self.get_relation(relid);
@@ -1064,10 +1350,20 @@ impl Program {
                             RelationInstance::Multiset {
                                 elements: FnvHashMap::default(),
                                 delta: FnvHashMap::default(),
+                                upsert_index: None,
                             },
                         );
                     }
                     CachingMode::Set => match rel.key_func {
+                        None if columnar.contains_key(&relid) => {
+                            rels.insert(
+                                relid,
+                                RelationInstance::Columnar {
+                                    elements: ColumnarSet::new(relid, columnar[&relid]),
+                                    delta: FnvHashMap::default(),
+                                },
+                            );
+                        }
                         None => {
                             rels.insert(
                                 relid,
@@ -1092,13 +1388,40 @@ impl Program {
             }
         }
 
-        let running_program = RunningProgram {
+        let mut running_program = RunningProgram {
             senders: request_send,
             reply_recv,
             relations: rels,
             worker_guards: Some(worker_guards),
             transaction_in_progress: false,
+            prepared: false,
+            held_outputs: None,
+            poisoned: None,
+            multi_tenant: config.multi_tenant,
+            tenants: FnvHashSet::default(),
+            init_data: self.init_data.clone(),
+            progress,
+            committed_txns: TxnIds::new(config.txn_id_history),
+            compaction: Compaction::default(),
+            lazy_gates,
+            arrangement_report,
+            conflict_policies: FnvHashMap::default(),
+            key_conflicts: Vec::new(),
+            history: FnvHashMap::default(),
+            history_events: Vec::new(),
+            history_time: None,
+            time_travel: TimeTravel::default(),
+            plan,
+            stats,
+            bulk_load_allowed: true,
+            update_buffer: if config.coalesce_updates {
+                Some(UpdateBuffer::default())
+            } else {
+                None
+            },
+            batcher: CommitBatcher::new(config.commit_latency_budget),
             need_to_flush: false,
+            pending_acks: vec![0; config.num_timely_workers],
             timestamp: 1,
             profile_cpu: profiling_rig.profile_cpu,
             profile_timely: profiling_rig.profile_timely,
@@ -1107,7 +1430,10 @@ impl Program {
             worker_round_robbin: (0..config.num_timely_workers).cycle().skip(0),
         };
         // Wait for the initial transaction to complete.
+        running_program.progress.submit(1);
         running_program.await_flush_ack()?;
+        running_program.check_poisoned()?;
+        running_program.stats.on_commit();
 
         Ok(running_program)
     }
@@ -1162,10 +1488,13 @@ impl Program {
         panic!("get_relation({}): relation not found", relid)
     }
 
-    /* indices of program nodes that use arrangement */
+    /* indices of program nodes that use arrangement or an arrangement sharing it */
     fn arrangement_used_by_nodes(&self, arrid: ArrId) -> impl Iterator<Item = usize> + '_ {
         self.nodes.iter().enumerate().filter_map(move |(i, n)| {
-            if Self::node_uses_arrangement(n, arrid) {
+            if self
+                .arrangements_sharing(arrid)
+                .any(|arrid| Self::node_uses_arrangement(n, arrid))
+            {
                 Some(i)
             } else {
                 None
@@ -1237,13 +1566,14 @@ impl Program {
     // TODO: Much of this logic would be vastly simplified if we used a
     //       combination of traits and `Vec<XFormCollection>`s (as opposed to
     //       what we do now with a linked list of them)
-    fn xform_collection<'a, S, T, Lookup>(
+    fn xform_collection<'a, M, S, T, Lookup>(
         col: Collection<S, DDValue, Weight>,
         xform: &Option<XFormCollection>,
         arrangements: &Arrangements<'a, S, T>,
         lookup_collection: Lookup,
     ) -> Collection<S, DDValue, Weight>
     where
+        M: TenantMode,
         S: Scope,
         S::Timestamp: Lattice + Refines<T> + ToTupleTS,
         T: Lattice + Timestamp,
@@ -1251,17 +1581,20 @@ impl Program {
     {
         match xform {
             None => col,
-            Some(ref x) => Self::xform_collection_ref(&col, x, arrangements, lookup_collection),
+            Some(ref x) => {
+                Self::xform_collection_ref::<M, _, _, _>(&col, x, arrangements, lookup_collection)
+            }
         }
     }
 
-    fn xform_collection_ref<'a, S, T, Lookup>(
+    fn xform_collection_ref<'a, M, S, T, Lookup>(
         col: &Collection<S, DDValue, Weight>,
         xform: &XFormCollection,
         arrangements: &Arrangements<'a, S, T>,
         lookup_collection: Lookup,
     ) -> Collection<S, DDValue, Weight>
     where
+        M: TenantMode,
         S: Scope,
         S::Timestamp: Lattice + Refines<T> + ToTupleTS,
         T: Lattice + Timestamp,
@@ -1273,8 +1606,17 @@ impl Program {
                 afun,
                 ref next,
             } => {
-                let arr = with_prof_context(&description, || col.flat_map(afun).arrange_by_key());
-                Self::xform_arrangement(&arr, &*next, arrangements, lookup_collection)
+                let rule = description.clone();
+                let arr = with_prof_context(&description, || {
+                    col.flat_map(move |v| guard(&rule, None, || M::arrange(v, afun)))
+                        .arrange_by_key()
+                });
+                Self::xform_arrangement::<M, _, _, _, _>(
+                    &arr,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormCollection::Differentiate {
                 ref description,
@@ -1289,51 +1631,96 @@ impl Program {
                         &col.delay(move |t| one.results_in(t).expect("Integer overflow in Differentiate: maximal number of transactions exceeded")).negate())
                 });
 
-                Self::xform_collection(diff, &*next, arrangements, lookup_collection)
+                Self::xform_collection::<M, _, _, _>(diff, &*next, arrangements, lookup_collection)
             }
             XFormCollection::Map {
                 ref description,
                 mfun,
                 ref next,
             } => {
-                let mapped = with_prof_context(&description, || col.map(mfun));
-                Self::xform_collection(mapped, &*next, arrangements, lookup_collection)
+                let rule = description.clone();
+                let mapped = with_prof_context(&description, || {
+                    // `flat_map` rather than `map`, so that a value whose
+                    // function panics can be dropped.
+                    col.flat_map(move |v| guard(&rule, None, || Some(M::map(v, mfun))))
+                });
+                Self::xform_collection::<M, _, _, _>(
+                    mapped,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormCollection::FlatMap {
                 ref description,
                 fmfun,
                 ref next,
             } => {
+                let rule: Rc<str> = Rc::from(description.as_ref());
                 let flattened = with_prof_context(&description, || {
-                    col.flat_map(move |x| fmfun(x).into_iter().flatten())
+                    col.flat_map(move |x| {
+                        guard(&rule, None, || M::flat_map(x, fmfun))
+                            .map(|iter| guard_iter(&rule, iter))
+                            .into_iter()
+                            .flatten()
+                    })
                 });
-                Self::xform_collection(flattened, &*next, arrangements, lookup_collection)
+                Self::xform_collection::<M, _, _, _>(
+                    flattened,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormCollection::Filter {
                 ref description,
                 ffun,
                 ref next,
             } => {
-                let filtered = with_prof_context(&description, || col.filter(ffun));
-                Self::xform_collection(filtered, &*next, arrangements, lookup_collection)
+                let rule = description.clone();
+                let filtered = with_prof_context(&description, || {
+                    col.filter(move |v| guard(&rule, false, || ffun(M::value(v))))
+                });
+                Self::xform_collection::<M, _, _, _>(
+                    filtered,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormCollection::FilterMap {
                 ref description,
                 fmfun,
                 ref next,
             } => {
-                let flattened = with_prof_context(&description, || col.flat_map(fmfun));
-                Self::xform_collection(flattened, &*next, arrangements, lookup_collection)
+                let rule = description.clone();
+                let flattened = with_prof_context(&description, || {
+                    col.flat_map(move |v| guard(&rule, None, || M::map_opt(v, fmfun)))
+                });
+                Self::xform_collection::<M, _, _, _>(
+                    flattened,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormCollection::Inspect {
                 ref description,
                 ifun,
                 ref next,
             } => {
+                let rule = description.clone();
                 let inspect = with_prof_context(&description, || {
-                    col.inspect(move |(v, ts, w)| ifun(v, ts.to_tuple_ts(), *w))
+                    col.inspect(move |(v, ts, w)| {
+                        guard(&rule, (), || ifun(M::value(v), ts.to_tuple_ts(), *w))
+                    })
                 });
-                Self::xform_collection(inspect, &*next, arrangements, lookup_collection)
+                Self::xform_collection::<M, _, _, _>(
+                    inspect,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormCollection::StreamJoin {
                 ref description,
@@ -1344,7 +1731,9 @@ impl Program {
             } => {
                 let join = with_prof_context(&description, || {
                     // arrange input collection
-                    let collection_with_keys = col.flat_map(afun);
+                    let (arule, jrule) = (description.clone(), description.clone());
+                    let collection_with_keys =
+                        col.flat_map(move |v| guard(&arule, None, || M::arrange(v, afun)));
                     let arr = match arrangements.lookup_arr(arrangement) {
                         ArrangementFlavor::Local(DataflowArrangement::Map(arranged)) => arranged,
                         ArrangementFlavor::Local(DataflowArrangement::Set(_)) => {
@@ -1356,7 +1745,14 @@ impl Program {
                         &collection_with_keys,
                         arr,
                         |(k, _), key| *key = k.clone(),
-                        move |v1, w1, v2, w2| (jfun(&v1.1, v2), w1 * w2),
+                        move |v1, w1, v2, w2| {
+                            (
+                                guard(&jrule, None, || {
+                                    M::map_ref(&v1.1, |v1| jfun(v1, M::value(v2)))
+                                }),
+                                w1 * w2,
+                            )
+                        },
                         ().into_ddvalue(),
                         ().into_ddvalue(),
                         ().into_ddvalue(),
@@ -1366,7 +1762,7 @@ impl Program {
                     // to return `Option`.
                     .flat_map(|v| v)
                 });
-                Self::xform_collection(join, &*next, arrangements, lookup_collection)
+                Self::xform_collection::<M, _, _, _>(join, &*next, arrangements, lookup_collection)
             }
             XFormCollection::StreamSemijoin {
                 ref description,
@@ -1377,7 +1773,9 @@ impl Program {
             } => {
                 let join = with_prof_context(&description, || {
                     // arrange input collection
-                    let collection_with_keys = col.flat_map(afun);
+                    let (arule, jrule) = (description.clone(), description.clone());
+                    let collection_with_keys =
+                        col.flat_map(move |v| guard(&arule, None, || M::arrange(v, afun)));
                     let arr = match arrangements.lookup_arr(arrangement) {
                         ArrangementFlavor::Local(DataflowArrangement::Set(arranged)) => arranged,
                         ArrangementFlavor::Local(DataflowArrangement::Map(_)) => {
@@ -1389,7 +1787,9 @@ impl Program {
                         &collection_with_keys,
                         arr,
                         |(k, _), key| *key = k.clone(),
-                        move |v1, w1, _, w2| (jfun(&v1.1), w1 * w2),
+                        move |v1, w1, _, w2| {
+                            (guard(&jrule, None, || M::map_ref(&v1.1, jfun)), w1 * w2)
+                        },
                         ().into_ddvalue(),
                         ().into_ddvalue(),
                         ().into_ddvalue(),
@@ -1399,7 +1799,7 @@ impl Program {
                     // to return `Option`.
                     .flat_map(|v| v)
                 });
-                Self::xform_collection(join, &*next, arrangements, lookup_collection)
+                Self::xform_collection::<M, _, _, _>(join, &*next, arrangements, lookup_collection)
             }
 
             XFormCollection::StreamXForm {
@@ -1427,6 +1827,7 @@ impl Program {
                             // since the function calls itself (a potentially infinite number of times),
                             // each requiring further nesting of the scopes (and their types)
                             let xformed = Self::streamless_xform_collection::<
+                                M,
                                 Child<S, AltNeu<S::Timestamp>>,
                                 S::Timestamp,
                                 _,
@@ -1445,18 +1846,24 @@ impl Program {
                             )
                         });
 
-                Self::xform_collection(xformed, &*next, arrangements, lookup_collection)
+                Self::xform_collection::<M, _, _, _>(
+                    xformed,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
         }
     }
 
-    fn streamless_xform_collection<'a, S, T, Lookup>(
+    fn streamless_xform_collection<'a, M, S, T, Lookup>(
         col: Collection<S, DDValue, Weight>,
         xform: &Option<XFormCollection>,
         arrangements: &Arrangements<'a, S, T>,
         lookup_collection: Lookup,
     ) -> Collection<S, DDValue, Weight>
     where
+        M: TenantMode,
         S: Scope,
         S::Timestamp: Lattice + Refines<T> + ToTupleTS,
         T: Lattice + Timestamp,
@@ -1464,19 +1871,23 @@ impl Program {
     {
         match xform {
             None => col,
-            Some(ref x) => {
-                Self::streamless_xform_collection_ref(&col, x, arrangements, lookup_collection)
-            }
+            Some(ref x) => Self::streamless_xform_collection_ref::<M, _, _, _>(
+                &col,
+                x,
+                arrangements,
+                lookup_collection,
+            ),
         }
     }
 
-    fn streamless_xform_collection_ref<'a, S, T, Lookup>(
+    fn streamless_xform_collection_ref<'a, M, S, T, Lookup>(
         col: &Collection<S, DDValue, Weight>,
         xform: &XFormCollection,
         arrangements: &Arrangements<'a, S, T>,
         lookup_collection: Lookup,
     ) -> Collection<S, DDValue, Weight>
     where
+        M: TenantMode,
         S: Scope,
         S::Timestamp: Lattice + Refines<T> + ToTupleTS,
         T: Lattice + Timestamp,
@@ -1488,8 +1899,17 @@ impl Program {
                 afun,
                 ref next,
             } => {
-                let arr = with_prof_context(&description, || col.flat_map(afun).arrange_by_key());
-                Self::xform_arrangement(&arr, &*next, arrangements, lookup_collection)
+                let rule = description.clone();
+                let arr = with_prof_context(&description, || {
+                    col.flat_map(move |v| guard(&rule, None, || M::arrange(v, afun)))
+                        .arrange_by_key()
+                });
+                Self::xform_arrangement::<M, _, _, _, _>(
+                    &arr,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormCollection::Differentiate {
                 ref description,
@@ -1504,25 +1924,46 @@ impl Program {
                         &col.delay(move |t| one.results_in(t).expect("Integer overflow in Differentiate: maximal number of transactions exceeded")).negate())
                 });
 
-                Self::streamless_xform_collection(diff, &*next, arrangements, lookup_collection)
+                Self::streamless_xform_collection::<M, _, _, _>(
+                    diff,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormCollection::Map {
                 ref description,
                 mfun,
                 ref next,
             } => {
-                let mapped = with_prof_context(&description, || col.map(mfun));
-                Self::streamless_xform_collection(mapped, &*next, arrangements, lookup_collection)
+                let rule = description.clone();
+                let mapped = with_prof_context(&description, || {
+                    // `flat_map` rather than `map`, so that a value whose
+                    // function panics can be dropped.
+                    col.flat_map(move |v| guard(&rule, None, || Some(M::map(v, mfun))))
+                });
+                Self::streamless_xform_collection::<M, _, _, _>(
+                    mapped,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormCollection::FlatMap {
                 ref description,
                 fmfun,
                 ref next,
             } => {
+                let rule: Rc<str> = Rc::from(description.as_ref());
                 let flattened = with_prof_context(&description, || {
-                    col.flat_map(move |x| fmfun(x).into_iter().flatten())
+                    col.flat_map(move |x| {
+                        guard(&rule, None, || M::flat_map(x, fmfun))
+                            .map(|iter| guard_iter(&rule, iter))
+                            .into_iter()
+                            .flatten()
+                    })
                 });
-                Self::streamless_xform_collection(
+                Self::streamless_xform_collection::<M, _, _, _>(
                     flattened,
                     &*next,
                     arrangements,
@@ -1534,16 +1975,27 @@ impl Program {
                 ffun,
                 ref next,
             } => {
-                let filtered = with_prof_context(&description, || col.filter(ffun));
-                Self::streamless_xform_collection(filtered, &*next, arrangements, lookup_collection)
+                let rule = description.clone();
+                let filtered = with_prof_context(&description, || {
+                    col.filter(move |v| guard(&rule, false, || ffun(M::value(v))))
+                });
+                Self::streamless_xform_collection::<M, _, _, _>(
+                    filtered,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormCollection::FilterMap {
                 ref description,
                 fmfun,
                 ref next,
             } => {
-                let flattened = with_prof_context(&description, || col.flat_map(fmfun));
-                Self::streamless_xform_collection(
+                let rule = description.clone();
+                let flattened = with_prof_context(&description, || {
+                    col.flat_map(move |v| guard(&rule, None, || M::map_opt(v, fmfun)))
+                });
+                Self::streamless_xform_collection::<M, _, _, _>(
                     flattened,
                     &*next,
                     arrangements,
@@ -1555,10 +2007,18 @@ impl Program {
                 ifun,
                 ref next,
             } => {
+                let rule = description.clone();
                 let inspect = with_prof_context(&description, || {
-                    col.inspect(move |(v, ts, w)| ifun(v, ts.to_tuple_ts(), *w))
+                    col.inspect(move |(v, ts, w)| {
+                        guard(&rule, (), || ifun(M::value(v), ts.to_tuple_ts(), *w))
+                    })
                 });
-                Self::streamless_xform_collection(inspect, &*next, arrangements, lookup_collection)
+                Self::streamless_xform_collection::<M, _, _, _>(
+                    inspect,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormCollection::StreamJoin {
                 ref description,
@@ -1569,7 +2029,9 @@ impl Program {
             } => {
                 let join = with_prof_context(&description, || {
                     // arrange input collection
-                    let collection_with_keys = col.flat_map(afun);
+                    let (arule, jrule) = (description.clone(), description.clone());
+                    let collection_with_keys =
+                        col.flat_map(move |v| guard(&arule, None, || M::arrange(v, afun)));
                     let arr = match arrangements.lookup_arr(arrangement) {
                         ArrangementFlavor::Local(DataflowArrangement::Map(arranged)) => arranged,
                         ArrangementFlavor::Local(DataflowArrangement::Set(_)) => {
@@ -1581,7 +2043,14 @@ impl Program {
                         &collection_with_keys,
                         arr,
                         |(k, _), key| *key = k.clone(),
-                        move |v1, w1, v2, w2| (jfun(&v1.1, v2), w1 * w2),
+                        move |v1, w1, v2, w2| {
+                            (
+                                guard(&jrule, None, || {
+                                    M::map_ref(&v1.1, |v1| jfun(v1, M::value(v2)))
+                                }),
+                                w1 * w2,
+                            )
+                        },
                         ().into_ddvalue(),
                         ().into_ddvalue(),
                         ().into_ddvalue(),
@@ -1591,7 +2060,12 @@ impl Program {
                     // to return `Option`.
                     .flat_map(|v| v)
                 });
-                Self::streamless_xform_collection(join, &*next, arrangements, lookup_collection)
+                Self::streamless_xform_collection::<M, _, _, _>(
+                    join,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormCollection::StreamSemijoin {
                 ref description,
@@ -1602,7 +2076,9 @@ impl Program {
             } => {
                 let join = with_prof_context(&description, || {
                     // arrange input collection
-                    let collection_with_keys = col.flat_map(afun);
+                    let (arule, jrule) = (description.clone(), description.clone());
+                    let collection_with_keys =
+                        col.flat_map(move |v| guard(&arule, None, || M::arrange(v, afun)));
                     let arr = match arrangements.lookup_arr(arrangement) {
                         ArrangementFlavor::Local(DataflowArrangement::Set(arranged)) => arranged,
                         ArrangementFlavor::Local(DataflowArrangement::Map(_)) => {
@@ -1614,7 +2090,9 @@ impl Program {
                         &collection_with_keys,
                         arr,
                         |(k, _), key| *key = k.clone(),
-                        move |v1, w1, _, w2| (jfun(&v1.1), w1 * w2),
+                        move |v1, w1, _, w2| {
+                            (guard(&jrule, None, || M::map_ref(&v1.1, jfun)), w1 * w2)
+                        },
                         ().into_ddvalue(),
                         ().into_ddvalue(),
                         ().into_ddvalue(),
@@ -1624,7 +2102,12 @@ impl Program {
                     // to return `Option`.
                     .flat_map(|v| v)
                 });
-                Self::streamless_xform_collection(join, &*next, arrangements, lookup_collection)
+                Self::streamless_xform_collection::<M, _, _, _>(
+                    join,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
 
             XFormCollection::StreamXForm {
@@ -1635,13 +2118,14 @@ impl Program {
         }
     }
 
-    fn xform_arrangement<'a, S, T, TR, LC>(
+    fn xform_arrangement<'a, M, S, T, TR, LC>(
         arr: &Arranged<S, TR>,
         xform: &XFormArrangement,
         arrangements: &Arrangements<'a, S, T>,
         lookup_collection: LC,
     ) -> Collection<S, DDValue, Weight>
     where
+        M: TenantMode,
         S: Scope,
         S::Timestamp: Lattice + Refines<T> + ToTupleTS,
         T: Lattice + Timestamp,
@@ -1658,10 +2142,13 @@ impl Program {
                 fmfun,
                 ref next,
             } => with_prof_context(&description, || {
-                Self::streamless_xform_collection(
-                    arr.flat_map_ref(move |_, v| match fmfun(v.clone()) {
-                        Some(iter) => iter,
-                        None => Box::new(None.into_iter()),
+                let rule: Rc<str> = Rc::from(description.as_ref());
+                Self::streamless_xform_collection::<M, _, _, _>(
+                    arr.flat_map_ref(move |_, v| {
+                        guard(&rule, None, || M::flat_map(v.clone(), fmfun))
+                            .map(|iter| guard_iter(&rule, iter))
+                            .into_iter()
+                            .flatten()
                     }),
                     &*next,
                     arrangements,
@@ -1673,8 +2160,11 @@ impl Program {
                 fmfun,
                 ref next,
             } => with_prof_context(&description, || {
-                Self::streamless_xform_collection(
-                    arr.flat_map_ref(move |_, v| fmfun(v.clone())),
+                let rule = description.clone();
+                Self::streamless_xform_collection::<M, _, _, _>(
+                    arr.flat_map_ref(move |_, v| {
+                        guard(&rule, None, || M::map_opt(v.clone(), fmfun))
+                    }),
                     &*next,
                     arrangements,
                     lookup_collection,
@@ -1687,27 +2177,37 @@ impl Program {
                 ref next,
             } => {
                 let col = with_prof_context(&description, || {
-                    ffun.map_or_else(
-                        || {
-                            arr.reduce(move |key, src, dst| {
-                                if let Some(x) = aggfun(key, src) {
+                    let rule = description.clone();
+                    match ffun {
+                        None => arr
+                            .reduce(move |key, src, dst| {
+                                if let Some(x) =
+                                    guard(&rule, None, || M::aggregate(key, src, aggfun))
+                                {
                                     dst.push((x, 1));
                                 };
                             })
-                            .map(|(_, v)| v)
-                        },
-                        |f| {
-                            arr.filter(move |_, v| f(v))
+                            .map(|(_, v)| v),
+                        Some(f) => {
+                            let frule = description.clone();
+                            arr.filter(move |_, v| guard(&frule, false, || f(M::value(v))))
                                 .reduce(move |key, src, dst| {
-                                    if let Some(x) = aggfun(key, src) {
+                                    if let Some(x) =
+                                        guard(&rule, None, || M::aggregate(key, src, aggfun))
+                                    {
                                         dst.push((x, 1));
                                     };
                                 })
                                 .map(|(_, v)| v)
-                        },
-                    )
+                        }
+                    }
                 });
-                Self::streamless_xform_collection(col, &*next, arrangements, lookup_collection)
+                Self::streamless_xform_collection::<M, _, _, _>(
+                    col,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormArrangement::Join {
                 ref description,
@@ -1718,21 +2218,51 @@ impl Program {
             } => match arrangements.lookup_arr(arrangement) {
                 ArrangementFlavor::Local(DataflowArrangement::Map(arranged)) => {
                     let col = with_prof_context(&description, || {
-                        ffun.map_or_else(
-                            || arr.join_core(&arranged, jfun),
-                            |f| arr.filter(move |_, v| f(v)).join_core(&arranged, jfun),
-                        )
+                        let rule = description.clone();
+                        let jfun = move |k: &DDValue, v1: &DDValue, v2: &DDValue| {
+                            guard(&rule, None, || {
+                                M::map_ref(k, |k| jfun(k, M::value(v1), M::value(v2)))
+                            })
+                        };
+                        match ffun {
+                            None => arr.join_core(&arranged, jfun),
+                            Some(f) => {
+                                let frule = description.clone();
+                                arr.filter(move |_, v| guard(&frule, false, || f(M::value(v))))
+                                    .join_core(&arranged, jfun)
+                            }
+                        }
                     });
-                    Self::streamless_xform_collection(col, &*next, arrangements, lookup_collection)
+                    Self::streamless_xform_collection::<M, _, _, _>(
+                        col,
+                        &*next,
+                        arrangements,
+                        lookup_collection,
+                    )
                 }
                 ArrangementFlavor::Foreign(DataflowArrangement::Map(arranged)) => {
                     let col = with_prof_context(&description, || {
-                        ffun.map_or_else(
-                            || arr.join_core(&arranged, jfun),
-                            |f| arr.filter(move |_, v| f(v)).join_core(&arranged, jfun),
-                        )
+                        let rule = description.clone();
+                        let jfun = move |k: &DDValue, v1: &DDValue, v2: &DDValue| {
+                            guard(&rule, None, || {
+                                M::map_ref(k, |k| jfun(k, M::value(v1), M::value(v2)))
+                            })
+                        };
+                        match ffun {
+                            None => arr.join_core(&arranged, jfun),
+                            Some(f) => {
+                                let frule = description.clone();
+                                arr.filter(move |_, v| guard(&frule, false, || f(M::value(v))))
+                                    .join_core(&arranged, jfun)
+                            }
+                        }
                     });
-                    Self::streamless_xform_collection(col, &*next, arrangements, lookup_collection)
+                    Self::streamless_xform_collection::<M, _, _, _>(
+                        col,
+                        &*next,
+                        arrangements,
+                        lookup_collection,
+                    )
                 }
 
                 _ => panic!("Join: not a map arrangement {:?}", arrangement),
@@ -1746,21 +2276,47 @@ impl Program {
             } => match arrangements.lookup_arr(arrangement) {
                 ArrangementFlavor::Local(DataflowArrangement::Set(arranged)) => {
                     let col = with_prof_context(&description, || {
-                        ffun.map_or_else(
-                            || arr.join_core(&arranged, jfun),
-                            |f| arr.filter(move |_, v| f(v)).join_core(&arranged, jfun),
-                        )
+                        let rule = description.clone();
+                        let jfun = move |k: &DDValue, v1: &DDValue, v2: &()| {
+                            guard(&rule, None, || M::map_ref(k, |k| jfun(k, M::value(v1), v2)))
+                        };
+                        match ffun {
+                            None => arr.join_core(&arranged, jfun),
+                            Some(f) => {
+                                let frule = description.clone();
+                                arr.filter(move |_, v| guard(&frule, false, || f(M::value(v))))
+                                    .join_core(&arranged, jfun)
+                            }
+                        }
                     });
-                    Self::streamless_xform_collection(col, &*next, arrangements, lookup_collection)
+                    Self::streamless_xform_collection::<M, _, _, _>(
+                        col,
+                        &*next,
+                        arrangements,
+                        lookup_collection,
+                    )
                 }
                 ArrangementFlavor::Foreign(DataflowArrangement::Set(arranged)) => {
                     let col = with_prof_context(&description, || {
-                        ffun.map_or_else(
-                            || arr.join_core(&arranged, jfun),
-                            |f| arr.filter(move |_, v| f(v)).join_core(&arranged, jfun),
-                        )
+                        let rule = description.clone();
+                        let jfun = move |k: &DDValue, v1: &DDValue, v2: &()| {
+                            guard(&rule, None, || M::map_ref(k, |k| jfun(k, M::value(v1), v2)))
+                        };
+                        match ffun {
+                            None => arr.join_core(&arranged, jfun),
+                            Some(f) => {
+                                let frule = description.clone();
+                                arr.filter(move |_, v| guard(&frule, false, || f(M::value(v))))
+                                    .join_core(&arranged, jfun)
+                            }
+                        }
                     });
-                    Self::streamless_xform_collection(col, &*next, arrangements, lookup_collection)
+                    Self::streamless_xform_collection::<M, _, _, _>(
+                        col,
+                        &*next,
+                        arrangements,
+                        lookup_collection,
+                    )
                 }
                 _ => panic!("Semijoin: not a set arrangement {:?}", arrangement),
             },
@@ -1772,27 +2328,45 @@ impl Program {
             } => match arrangements.lookup_arr(arrangement) {
                 ArrangementFlavor::Local(DataflowArrangement::Set(arranged)) => {
                     let col = with_prof_context(&description, || {
+                        let rule = description.clone();
                         ffun.map_or_else(
                             || antijoin_arranged(&arr, &arranged).map(|(_, v)| v),
                             |f| {
-                                antijoin_arranged(&arr.filter(move |_, v| f(v)), &arranged)
-                                    .map(|(_, v)| v)
+                                antijoin_arranged(
+                                    &arr.filter(move |_, v| guard(&rule, false, || f(M::value(v)))),
+                                    &arranged,
+                                )
+                                .map(|(_, v)| v)
                             },
                         )
                     });
-                    Self::streamless_xform_collection(col, &*next, arrangements, lookup_collection)
+                    Self::streamless_xform_collection::<M, _, _, _>(
+                        col,
+                        &*next,
+                        arrangements,
+                        lookup_collection,
+                    )
                 }
                 ArrangementFlavor::Foreign(DataflowArrangement::Set(arranged)) => {
                     let col = with_prof_context(&description, || {
+                        let rule = description.clone();
                         ffun.map_or_else(
                             || antijoin_arranged(&arr, &arranged).map(|(_, v)| v),
                             |f| {
-                                antijoin_arranged(&arr.filter(move |_, v| f(v)), &arranged)
-                                    .map(|(_, v)| v)
+                                antijoin_arranged(
+                                    &arr.filter(move |_, v| guard(&rule, false, || f(M::value(v)))),
+                                    &arranged,
+                                )
+                                .map(|(_, v)| v)
                             },
                         )
                     });
-                    Self::streamless_xform_collection(col, &*next, arrangements, lookup_collection)
+                    Self::streamless_xform_collection::<M, _, _, _>(
+                        col,
+                        &*next,
+                        arrangements,
+                        lookup_collection,
+                    )
                 }
                 _ => panic!("Antijoin: not a set arrangement {:?}", arrangement),
             },
@@ -1811,42 +2385,49 @@ impl Program {
                     // this overhead, we need a version of `lookup_map` that
                     // allows key function to return `Option`.
                     let kfun = kfun;
-                    let jfun = jfun;
+                    let (krule, frule) = (description.clone(), description.clone());
+                    let jrule = description.clone();
+                    let jfun = move |v1: &DDValue, v2: &DDValue| {
+                        guard(&jrule, None, || M::map_ref(v1, |v1| jfun(v1, M::value(v2))))
+                    };
                     let collection_with_keys = lookup_collection(rel)
                         .unwrap_or_else(|| panic!("xform_arrangement: unknown relation {:?}", rel))
-                        .flat_map(move |v| kfun(&v).map(|k| (k, v)));
+                        .flat_map(move |v| {
+                            guard(&krule, None, || M::map_ref(&v, kfun)).map(|k| (k, v))
+                        });
                     // Filter the arrangement if `ffun` is supplied.
-                    let join = ffun.map_or_else(
-                        || {
-                            lookup_map(
-                                &collection_with_keys,
-                                arr.clone(),
-                                |(k, _), key| *key = k.clone(),
-                                move |v1, w1, v2, w2| (jfun(v2, &v1.1), w1 * w2),
-                                ().into_ddvalue(),
-                                ().into_ddvalue(),
-                                ().into_ddvalue(),
-                            )
-                        },
-                        |f| {
-                            lookup_map(
-                                &collection_with_keys,
-                                arr.filter(move |_, v| f(v)),
-                                |(k, _), key| *key = k.clone(),
-                                move |v1, w1, v2, w2| (jfun(v2, &v1.1), w1 * w2),
-                                ().into_ddvalue(),
-                                ().into_ddvalue(),
-                                ().into_ddvalue(),
-                            )
-                        },
-                    );
+                    let join = match ffun {
+                        None => lookup_map(
+                            &collection_with_keys,
+                            arr.clone(),
+                            |(k, _), key| *key = k.clone(),
+                            move |v1, w1, v2, w2| (jfun(v2, &v1.1), w1 * w2),
+                            ().into_ddvalue(),
+                            ().into_ddvalue(),
+                            ().into_ddvalue(),
+                        ),
+                        Some(f) => lookup_map(
+                            &collection_with_keys,
+                            arr.filter(move |_, v| guard(&frule, false, || f(M::value(v)))),
+                            |(k, _), key| *key = k.clone(),
+                            move |v1, w1, v2, w2| (jfun(v2, &v1.1), w1 * w2),
+                            ().into_ddvalue(),
+                            ().into_ddvalue(),
+                            ().into_ddvalue(),
+                        ),
+                    };
 
                     // Filter out `None`'s.
                     // FIXME: We wouldn't need this if `lookup_map` allowed `output_func`
                     // to return `Option`.
                     join.flat_map(|v| v)
                 });
-                Self::streamless_xform_collection(col, &*next, arrangements, lookup_collection)
+                Self::streamless_xform_collection::<M, _, _, _>(
+                    col,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
             XFormArrangement::StreamSemijoin {
                 ref description,
@@ -1862,54 +2443,81 @@ impl Program {
                     // this overhead, we need a version of `lookup_map` that
                     // allows key function to return `Option`.
                     let kfun = kfun;
-                    let jfun = jfun;
+                    let (krule, frule) = (description.clone(), description.clone());
+                    let jrule = description.clone();
+                    let jfun = move |v: &DDValue| guard(&jrule, None, || M::map_ref(v, jfun));
                     let collection_keys = lookup_collection(rel)
                         .unwrap_or_else(|| panic!("xform_arrangement: unknown relation {:?}", rel))
-                        .flat_map(move |v| kfun(&v));
+                        .flat_map(move |v| guard(&krule, None, || M::map_ref(&v, kfun)));
                     // Filter the arrangement if `ffun` is supplied.
-                    let join = ffun.map_or_else(
-                        || {
-                            lookup_map(
-                                &collection_keys,
-                                arr.clone(),
-                                |k, key| *key = k.clone(),
-                                move |_, w1, v2, w2| (jfun(v2), w1 * w2),
-                                ().into_ddvalue(),
-                                ().into_ddvalue(),
-                                ().into_ddvalue(),
-                            )
-                        },
-                        |f| {
-                            lookup_map(
-                                &collection_keys,
-                                arr.filter(move |_, v| f(v)),
-                                |k, key| *key = k.clone(),
-                                move |_, w1, v2, w2| (jfun(v2), w1 * w2),
-                                ().into_ddvalue(),
-                                ().into_ddvalue(),
-                                ().into_ddvalue(),
-                            )
-                        },
-                    );
+                    let join = match ffun {
+                        None => lookup_map(
+                            &collection_keys,
+                            arr.clone(),
+                            |k, key| *key = k.clone(),
+                            move |_, w1, v2, w2| (jfun(v2), w1 * w2),
+                            ().into_ddvalue(),
+                            ().into_ddvalue(),
+                            ().into_ddvalue(),
+                        ),
+                        Some(f) => lookup_map(
+                            &collection_keys,
+                            arr.filter(move |_, v| guard(&frule, false, || f(M::value(v)))),
+                            |k, key| *key = k.clone(),
+                            move |_, w1, v2, w2| (jfun(v2), w1 * w2),
+                            ().into_ddvalue(),
+                            ().into_ddvalue(),
+                            ().into_ddvalue(),
+                        ),
+                    };
 
                     // Filter out `None`'s.
                     // FIXME: We wouldn't need this if `lookup_map` allowed `output_func`
                     // to return `Option`.
                     join.flat_map(|v| v)
                 });
-                Self::streamless_xform_collection(col, &*next, arrangements, lookup_collection)
+                Self::streamless_xform_collection::<M, _, _, _>(
+                    col,
+                    &*next,
+                    arrangements,
+                    lookup_collection,
+                )
             }
         }
     }
 
     /// Compile right-hand-side of a rule to a collection
+    ///
+    /// The rule's functions are lifted to tenant-tagged values only in
+    /// multi-tenant programs, see [`TenantMode`].
     fn mk_rule<'a, S, T, F>(
+        &self,
+        multi_tenant: bool,
+        rule: &Rule,
+        lookup_collection: F,
+        arrangements: Arrangements<'a, S, T>,
+    ) -> Collection<S, DDValue, Weight>
+    where
+        S: Scope,
+        S::Timestamp: Lattice + Refines<T> + ToTupleTS,
+        T: Lattice + Timestamp,
+        F: Fn(RelId) -> Option<Collection<S, DDValue, Weight>>,
+    {
+        if multi_tenant {
+            self.mk_tenant_rule::<MultiTenant, _, _, _>(rule, lookup_collection, arrangements)
+        } else {
+            self.mk_tenant_rule::<SingleTenant, _, _, _>(rule, lookup_collection, arrangements)
+        }
+    }
+
+    fn mk_tenant_rule<'a, M, S, T, F>(
         &self,
         rule: &Rule,
         lookup_collection: F,
         arrangements: Arrangements<'a, S, T>,
     ) -> Collection<S, DDValue, Weight>
     where
+        M: TenantMode,
         S: Scope,
         S::Timestamp: Lattice + Refines<T> + ToTupleTS,
         T: Lattice + Timestamp,
@@ -1932,7 +2540,7 @@ impl Program {
                 rel,
                 xform: Some(x),
                 ..
-            } => Self::xform_collection_ref(
+            } => Self::xform_collection_ref::<M, _, _, _>(
                 &lookup_collection(*rel)
                     .unwrap_or_else(|| panic!("mk_rule: unknown relation {:?}", rel)),
                 x,
@@ -1941,10 +2549,20 @@ impl Program {
             ),
             Rule::ArrangementRule { arr, xform, .. } => match arrangements.lookup_arr(*arr) {
                 ArrangementFlavor::Local(DataflowArrangement::Map(arranged)) => {
-                    Self::xform_arrangement(&arranged, xform, &arrangements, &lookup_collection)
+                    Self::xform_arrangement::<M, _, _, _, _>(
+                        &arranged,
+                        xform,
+                        &arrangements,
+                        &lookup_collection,
+                    )
                 }
                 ArrangementFlavor::Foreign(DataflowArrangement::Map(arranged)) => {
-                    Self::xform_arrangement(&arranged, xform, &arrangements, &lookup_collection)
+                    Self::xform_arrangement::<M, _, _, _, _>(
+                        &arranged,
+                        xform,
+                        &arrangements,
+                        &lookup_collection,
+                    )
                 }
                 _ => panic!("Rule starts with a set arrangement {:?}", *arr),
             },
@@ -2006,60 +2624,409 @@ impl RunningProgram {
         }
 
         self.transaction_in_progress = true;
+        self.bulk_load_allowed = false;
+        self.history_time = None;
         Ok(())
     }
 
-    /// Commit a transaction.
-    pub fn transaction_commit(&mut self) -> Response<()> {
+    /// First phase of a two-phase commit: propagate the changes of the
+    /// current transaction through the dataflow and make sure that it can
+    /// be committed.  After a successful prepare, the transaction can no
+    /// longer be modified, and `transaction_commit()` only finalizes it,
+    /// which cannot fail; alternatively, it can still be rolled back.
+    ///
+    /// The workers hold back the output changes of a prepared transaction
+    /// (see `prepared_output_changes()`): output callbacks only see them
+    /// when the transaction is committed, and never if it is rolled back.
+    /// Deferred commits (see `Config::commit_latency_budget`) share the
+    /// dataflow epoch of the transaction and cannot be rolled back, so if
+    /// any are pending, rolling the transaction back delivers its changes
+    /// along with their retractions.
+    pub fn transaction_prepare(&mut self) -> Response<()> {
         if !self.transaction_in_progress {
-            return Err("transaction_commit: no transaction in progress".to_string());
+            return Err("transaction_prepare: no transaction in progress".to_string());
+        }
+        if self.prepared {
+            return Ok(());
         }
 
+        self.await_pending_acks()?;
+        self.broadcast(Msg::HoldOutputs(self.timestamp))?;
+        self.held_outputs = Some(if self.batcher.has_deferred() {
+            HeldOutputs::WithDeferred
+        } else {
+            HeldOutputs::Transaction
+        });
         self.flush()?;
-        self.delta_cleanup();
-        self.transaction_in_progress = false;
+        self.check_poisoned()?;
+        self.prepare_commit()?;
+        self.prepared = true;
         Ok(())
     }
 
-    /// Rollback the transaction, undoing all changes.
-    pub fn transaction_rollback(&mut self) -> Response<()> {
-        if !self.transaction_in_progress {
-            return Err("transaction_rollback: no transaction in progress".to_string());
+    /// Net changes to output relation `relid` made by the prepared
+    /// transaction, which are held back until it is committed.  Empty if
+    /// no transaction has been prepared.
+    pub fn prepared_output_changes(&mut self, relid: RelId) -> Response<BTreeMap<DDValue, Weight>> {
+        if self.held_outputs.is_none() {
+            return Ok(BTreeMap::new());
         }
 
-        self.flush().and_then(|_| self.delta_undo()).map(|_| {
-            self.transaction_in_progress = false;
-        })
+        self.broadcast(Msg::HeldOutputs(relid))?;
+        let mut res: BTreeMap<DDValue, Weight> = BTreeMap::new();
+        for (worker_index, chan) in self.reply_recv.iter().enumerate() {
+            match chan.recv() {
+                Ok(Reply::QueryRes(Some(vals))) => {
+                    for (v, w) in vals {
+                        let weight = res.entry(v.clone()).or_insert(0);
+                        *weight += w;
+                        if *weight == 0 {
+                            res.remove(&v);
+                        }
+                    }
+                }
+                reply => {
+                    return Err(format!(
+                        "prepared_output_changes: unexpected reply from worker {}: {:?}",
+                        worker_index, reply
+                    ))
+                }
+            }
+        }
+        Ok(res)
     }
 
-    /// Insert one record into input relation. Relations have set semantics, i.e.,
-    /// adding an existing record is a no-op.
-    pub fn insert(&mut self, relid: RelId, v: DDValue) -> Response<()> {
-        self.apply_updates(iter::once(Update::Insert { relid, v }), |_| Ok(()))
+    /// Returns `true` if a transaction is in progress.
+    pub fn transaction_in_progress(&self) -> bool {
+        self.transaction_in_progress
     }
 
-    /// Insert one record into input relation or replace existing record with the same key.
-    pub fn insert_or_update(&mut self, relid: RelId, v: DDValue) -> Response<()> {
-        self.apply_updates(iter::once(Update::InsertOrUpdate { relid, v }), |_| Ok(()))
+    /// Returns `true` if the current transaction has been prepared.
+    pub fn transaction_prepared(&self) -> bool {
+        self.prepared
     }
 
-    /// Remove a record if it exists in the relation.
-    pub fn delete_value(&mut self, relid: RelId, v: DDValue) -> Response<()> {
-        self.apply_updates(iter::once(Update::DeleteValue { relid, v }), |_| Ok(()))
-    }
+    /// Commit a transaction.  Fails if a user function panicked while
+    /// evaluating the transaction (see `poisoned()`); the transaction then
+    /// remains in progress and must be rolled back.
+    ///
+    /// With `Config::commit_latency_budget`, the commit may be deferred,
+    /// in which case its outputs are produced by a later commit or by
+    /// `flush_deferred()`, which fail if evaluating it panics (see
+    /// `batching`).  A deferred commit is numbered right away, but can only
+    /// be queried as of once it has been propagated (see `time_travel`).
+    pub fn transaction_commit(&mut self) -> Response<()> {
+        if !self.transaction_in_progress {
+            return Err("transaction_commit: no transaction in progress".to_string());
+        }
 
-    /// Remove a key if it exists in the relation.
-    pub fn delete_key(&mut self, relid: RelId, k: DDValue) -> Response<()> {
-        self.apply_updates(iter::once(Update::DeleteKey { relid, k }), |_| Ok(()))
+        if !self.prepared {
+            // A panic raised by a flush during the transaction fails the
+            // commit, even if it would be deferred.
+            self.check_poisoned()?;
+            if self.need_to_flush && self.batcher.defer() {
+                self.delta_cleanup();
+                // Recorded by time travel once `flush()` propagates it.
+                self.time_travel.on_commit();
+                self.transaction_in_progress = false;
+                return Ok(());
+            }
+            self.flush()?;
+            self.check_poisoned()?;
+            self.prepare_commit()?;
+        }
+        self.release_outputs()?;
+        self.delta_cleanup();
+        self.record_commit();
+        self.stats.on_commit();
+        self.prepared = false;
+        self.transaction_in_progress = false;
+        Ok(())
     }
 
-    /// Modify a key if it exists in the relation.
-    pub fn modify_key(
-        &mut self,
-        relid: RelId,
-        k: DDValue,
-        m: Arc<dyn Mutator<DDValue> + Send + Sync>,
-    ) -> Response<()> {
+    /// Commit a transaction, unless propagating it through the dataflow
+    /// takes longer than `budget`, in which case the transaction is rolled
+    /// back and an error naming the operators that were still busy (if CPU
+    /// profiling is enabled) is returned.
+    ///
+    /// Output callbacks only see the changes of the transaction once it
+    /// has met its deadline: the workers hold them back until then.
+    ///
+    /// The budget bounds how long the caller waits, not the work of the
+    /// workers.  Timely dataflow cannot abandon an epoch whose updates
+    /// have been sent, so on timeout the workers finish processing the
+    /// transaction and then undo it in the background, dropping the output
+    /// changes of both.  A deadline commit made while they are still at it
+    /// counts the wait against its budget, and is rolled back if the
+    /// workers do not finish in time; other operations that need the
+    /// workers wait for them.
+    ///
+    /// Deadline commits are never deferred (see
+    /// `Config::commit_latency_budget`).  Commits deferred earlier share the
+    /// dataflow epoch of the transaction and cannot be rolled back, so if
+    /// any are pending, the transaction is committed without a deadline.
+    pub fn transaction_commit_with_deadline(&mut self, budget: Duration) -> Response<()> {
+        if !self.transaction_in_progress {
+            return Err("transaction_commit_with_deadline: no transaction in progress".to_string());
+        }
+        if self.prepared || !self.need_to_flush {
+            return self.transaction_commit();
+        }
+
+        if self.batcher.has_deferred() {
+            self.flush()?;
+        } else {
+            self.flush_with_deadline(budget)?;
+        }
+        self.check_poisoned()?;
+        self.prepare_commit()?;
+        self.delta_cleanup();
+        self.record_commit();
+        self.stats.on_commit();
+        self.transaction_in_progress = false;
+        Ok(())
+    }
+
+    /// Propagate the current transaction through the dataflow, rolling it
+    /// back if that takes longer than `budget`.  The workers hold back the
+    /// output changes of the transaction until it has met the deadline.
+    fn flush_with_deadline(&mut self, budget: Duration) -> Response<()> {
+        let deadline = Instant::now() + budget;
+        let epoch = self.timestamp;
+        if !self.await_pending_acks_until(deadline)? {
+            // The updates of the transaction have not been flushed, so its
+            // undo updates cancel them out within the epoch.
+            self.broadcast(Msg::DiscardOutputs(epoch))?;
+            self.start_rollback()?;
+            return Err(format!(
+                "transaction_commit_with_deadline: the workers were still undoing an earlier transaction after the budget of {:?}; the transaction has been rolled back",
+                budget
+            ));
+        }
+        let cpu = self
+            .profile
+            .as_ref()
+            .map(|profile| profile.lock().unwrap().cpu_snapshot());
+        self.broadcast(Msg::HoldOutputs(epoch))?;
+        self.start_flush()?;
+        if self.await_flush_ack_until(deadline)? {
+            self.broadcast(Msg::ReleaseOutputs)?;
+            self.await_flush_ack()?;
+            return self.apply_compaction_policy();
+        }
+
+        let busy = match (self.profile.as_ref(), cpu) {
+            (Some(profile), Some(cpu)) => profile.lock().unwrap().busy_operators(&cpu),
+            _ => Vec::new(),
+        };
+        // The undo updates of `start_rollback()` go into the next epoch.
+        self.broadcast(Msg::DiscardOutputs(epoch + 1))?;
+        self.start_rollback()?;
+        Err(format!(
+            "transaction_commit_with_deadline: propagation exceeded the budget of {:?}{}; the transaction has been rolled back",
+            budget,
+            if busy.is_empty() {
+                String::new()
+            } else {
+                format!(" (busy: {})", busy.join(", "))
+            }
+        ))
+    }
+
+    /// Undo the current transaction without waiting for the dataflow to
+    /// process the undo updates.
+    fn start_rollback(&mut self) -> Response<()> {
+        let mut updates = Vec::with_capacity(self.relations.len());
+        for (relid, rel) in &self.relations {
+            Self::delta_undo_updates(*relid, rel.delta(), &mut updates);
+        }
+        self.prepared = false;
+        self.apply_updates(updates.into_iter(), |_| Ok(()))?;
+        self.start_flush()?;
+        for pending in self.pending_acks.iter_mut() {
+            *pending += 1;
+        }
+        self.stats.on_commit();
+        self.poisoned = None;
+        self.transaction_in_progress = false;
+        Ok(())
+    }
+
+    /// Propagate deferred commits (see `Config::commit_latency_budget`)
+    /// through the dataflow.  Clients whose updates may stop arriving
+    /// should call this periodically so that the outputs of the last
+    /// deferred commits are not held back indefinitely.  Fails if a user
+    /// function panicked while evaluating the deferred commits, which
+    /// cannot be rolled back.
+    pub fn flush_deferred(&mut self) -> Response<()> {
+        if self.transaction_in_progress {
+            return Err("flush_deferred: transaction in progress".to_string());
+        }
+        if !self.batcher.has_deferred() {
+            return Ok(());
+        }
+
+        self.flush()?;
+        self.stats.on_commit();
+        self.poisoned
+            .take()
+            .map_or(Ok(()), |e| Err(format!("flush_deferred: {}", e)))
+    }
+
+    /// Commit a transaction identified by `id`, which must be unique among
+    /// all transactions submitted by the caller.  If a transaction with the
+    /// same id has already been committed, the current transaction is
+    /// assumed to be a replay of it and is rolled back instead, so that
+    /// upstream sources that deliver transactions at least once do not apply
+    /// them twice.  Returns `true` if the transaction was committed and
+    /// `false` if it was discarded as a replay.
+    ///
+    /// Only the last `Config::txn_id_history` ids are remembered.
+    pub fn transaction_commit_with_id(&mut self, id: &str) -> Response<bool> {
+        if !self.transaction_in_progress {
+            return Err("transaction_commit_with_id: no transaction in progress".to_string());
+        }
+
+        if self.committed_txns.contains(id) {
+            self.transaction_rollback()?;
+            Ok(false)
+        } else {
+            self.transaction_commit()?;
+            self.committed_txns.insert(id);
+            Ok(true)
+        }
+    }
+
+    /// Ids of recently committed transactions, oldest first.
+    pub fn committed_transaction_ids(&self) -> Vec<String> {
+        self.committed_txns.iter().cloned().collect()
+    }
+
+    /// Remember `ids` as committed, e.g., after restoring the program from a
+    /// snapshot taken by an earlier instance.
+    pub fn restore_transaction_ids<'a, I>(&mut self, ids: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        for id in ids {
+            self.committed_txns.insert(id);
+        }
+    }
+
+    /// Rollback the transaction, undoing all changes.  This also recovers
+    /// from a poisoned transaction.  Panics raised while undoing the changes
+    /// are ignored: the offending values produced no output when they were
+    /// added either.
+    pub fn transaction_rollback(&mut self) -> Response<()> {
+        if !self.transaction_in_progress {
+            return Err("transaction_rollback: no transaction in progress".to_string());
+        }
+
+        self.prepared = false;
+        self.flush()
+            .and_then(|_| self.drop_outputs())
+            .and_then(|_| self.delta_undo())
+            .and_then(|_| self.record_propagated())
+            .map(|_| {
+                self.stats.on_commit();
+                self.poisoned = None;
+                self.transaction_in_progress = false;
+            })
+    }
+
+    /// Deliver the output changes held back since the current transaction
+    /// was prepared.
+    fn release_outputs(&mut self) -> Response<()> {
+        if self.held_outputs.take().is_none() {
+            return Ok(());
+        }
+        self.broadcast(Msg::ReleaseOutputs)?;
+        self.await_flush_ack()
+    }
+
+    /// Drop the output changes held back since the current transaction was
+    /// prepared, along with those of undoing it, unless they include the
+    /// changes of deferred commits.
+    fn drop_outputs(&mut self) -> Response<()> {
+        match self.held_outputs.take() {
+            None => Ok(()),
+            Some(HeldOutputs::WithDeferred) => {
+                self.broadcast(Msg::ReleaseOutputs)?;
+                self.await_flush_ack()
+            }
+            // The undo updates go into the current epoch.
+            Some(HeldOutputs::Transaction) => self.broadcast(Msg::DiscardOutputs(self.timestamp)),
+        }
+    }
+
+    /// Current progress of the dataflow (see `progress::Frontier`).
+    pub fn frontier(&self) -> Frontier {
+        self.progress.frontier()
+    }
+
+    /// Handle to the progress tracker of the program, which can be used to
+    /// monitor the dataflow without access to the `RunningProgram`.
+    pub fn progress(&self) -> Arc<Progress> {
+        self.progress.clone()
+    }
+
+    /// Block until the dataflow has processed all committed inputs or
+    /// `timeout` expires.  Returns `true` if the dataflow is quiescent.
+    pub fn await_quiescence(&self, timeout: Duration) -> bool {
+        self.progress.await_quiescence(timeout).is_quiescent()
+    }
+
+    /// Returns the error (a panic or a weight overflow) that poisoned the
+    /// current transaction, if any.
+    pub fn poisoned(&self) -> Option<&DDlogError> {
+        self.poisoned.as_ref()
+    }
+
+    /// Fails if the current transaction has been prepared.
+    fn check_not_prepared(&self) -> Response<()> {
+        if self.prepared {
+            Err("the current transaction has been prepared and cannot be modified".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Fails if the current transaction is poisoned.
+    fn check_poisoned(&self) -> Response<()> {
+        match &self.poisoned {
+            Some(e) => Err(format!("transaction poisoned: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Insert one record into input relation. Relations have set semantics, i.e.,
+    /// adding an existing record is a no-op.
+    pub fn insert(&mut self, relid: RelId, v: DDValue) -> Response<()> {
+        self.apply_updates(iter::once(Update::Insert { relid, v }), |_| Ok(()))
+    }
+
+    /// Insert one record into input relation or replace existing record with the same key.
+    pub fn insert_or_update(&mut self, relid: RelId, v: DDValue) -> Response<()> {
+        self.apply_updates(iter::once(Update::InsertOrUpdate { relid, v }), |_| Ok(()))
+    }
+
+    /// Remove a record if it exists in the relation.
+    pub fn delete_value(&mut self, relid: RelId, v: DDValue) -> Response<()> {
+        self.apply_updates(iter::once(Update::DeleteValue { relid, v }), |_| Ok(()))
+    }
+
+    /// Remove a key if it exists in the relation.
+    pub fn delete_key(&mut self, relid: RelId, k: DDValue) -> Response<()> {
+        self.apply_updates(iter::once(Update::DeleteKey { relid, k }), |_| Ok(()))
+    }
+
+    /// Modify a key if it exists in the relation.
+    pub fn modify_key(
+        &mut self,
+        relid: RelId,
+        k: DDValue,
+        m: Arc<dyn Mutator<DDValue> + Send + Sync>,
+    ) -> Response<()> {
         self.apply_updates(iter::once(Update::Modify { relid, k, m }), |_| Ok(()))
     }
 
@@ -2078,9 +3045,17 @@ impl RunningProgram {
             RelationInstance::Stream { delta } => {
                 Self::stream_update(delta, update, filtered_updates)
             }
-            RelationInstance::Multiset { elements, delta } => {
-                Self::mset_update(elements, delta, update, filtered_updates)
-            }
+            RelationInstance::Multiset {
+                elements,
+                delta,
+                upsert_index,
+            } => Self::mset_update(
+                elements,
+                delta,
+                upsert_index.as_mut(),
+                update,
+                filtered_updates,
+            ),
             RelationInstance::Flat { elements, delta } => {
                 Self::set_update(elements, delta, update, filtered_updates)
             }
@@ -2088,8 +3063,158 @@ impl RunningProgram {
                 key_func,
                 elements,
                 delta,
-            } => Self::indexed_set_update(*key_func, elements, delta, update, filtered_updates),
+            } => Self::indexed_set_update(
+                *key_func,
+                elements,
+                delta,
+                self.conflict_policies.get(&update.relid()),
+                &mut self.key_conflicts,
+                update,
+                filtered_updates,
+            ),
+            RelationInstance::Columnar { elements, delta } => {
+                Self::columnar_update(elements, delta, update, filtered_updates)
+            }
+        }
+    }
+
+    /// Register a key extractor for multiset input relation `relid`, or remove
+    /// it if `key_func` is `None`.
+    ///
+    /// Multiset relations do not have a primary key, so `insert_or_update`
+    /// normally fails for them.  Once a key extractor is registered,
+    /// `insert_or_update` inserts the new value and deletes all values in the
+    /// relation, along with their duplicates, that have the same key as the
+    /// new value.
+    pub fn set_upsert_key(
+        &mut self,
+        relid: RelId,
+        key_func: Option<UpsertKeyFunc>,
+    ) -> Response<()> {
+        match self.relations.get_mut(&relid) {
+            None => Err(format!("set_upsert_key: unknown input relation {}", relid)),
+            Some(RelationInstance::Multiset {
+                elements,
+                upsert_index,
+                ..
+            }) => {
+                *upsert_index = key_func.map(|f| UpsertIndex::new(f, elements));
+                Ok(())
+            }
+            Some(_) => Err(format!(
+                "set_upsert_key: relation {} is not a multiset",
+                relid
+            )),
+        }
+    }
+
+    /// Set the policy that resolves insertions of records whose primary key
+    /// is already present in input relation `relid` (see `conflict`), or
+    /// restore the default policy, `ConflictPolicy::Error`, if `None`.
+    pub fn set_conflict_policy(
+        &mut self,
+        relid: RelId,
+        policy: Option<ConflictPolicy>,
+    ) -> Response<()> {
+        match self.relations.get(&relid) {
+            None => Err(format!(
+                "set_conflict_policy: unknown input relation {}",
+                relid
+            )),
+            Some(RelationInstance::Indexed { .. }) => {
+                match policy {
+                    Some(policy) => self.conflict_policies.insert(relid, policy),
+                    None => self.conflict_policies.remove(&relid),
+                };
+                Ok(())
+            }
+            Some(_) => Err(format!(
+                "set_conflict_policy: relation {} does not have a primary key",
+                relid
+            )),
+        }
+    }
+
+    /// Returns the primary-key conflicts encountered since the last call,
+    /// including those of transactions that have been rolled back.
+    pub fn take_key_conflicts(&mut self) -> Vec<KeyConflict> {
+        std::mem::take(&mut self.key_conflicts)
+    }
+
+    /// Retain the facts deleted from input relation `relid` as tombstones
+    /// for `retention` (see `history`), or stop retaining its history if
+    /// `None`.  Changing the retention period of a relation whose history is
+    /// already retained keeps its history.  Only relations with set
+    /// semantics are supported; fails if a transaction is in progress.
+    pub fn set_history_retention(
+        &mut self,
+        relid: RelId,
+        retention: Option<Duration>,
+    ) -> Response<()> {
+        if self.transaction_in_progress {
+            return Err("set_history_retention: transaction in progress".to_string());
+        }
+        let rel = self
+            .relations
+            .get(&relid)
+            .ok_or_else(|| format!("set_history_retention: unknown input relation {}", relid))?;
+        let retention = match retention {
+            Some(retention) => retention,
+            None => {
+                if let Some(history) = self.history.remove(&relid) {
+                    history.stop(&mut self.history_events);
+                }
+                return Ok(());
+            }
+        };
+        if let Some(history) = self.history.get_mut(&relid) {
+            history.set_retention(retention);
+            return Ok(());
         }
+        let values: Vec<DDValue> = match rel {
+            RelationInstance::Flat { elements, .. } => elements.iter().cloned().collect(),
+            RelationInstance::Indexed { elements, .. } => elements.values().cloned().collect(),
+            RelationInstance::Columnar { elements, .. } => elements.iter().collect(),
+            _ => {
+                return Err(format!(
+                    "set_history_retention: relation {} does not have set semantics",
+                    relid
+                ))
+            }
+        };
+        let history = RelationHistory::new(
+            relid,
+            retention,
+            values.into_iter(),
+            &mut self.history_events,
+        );
+        self.history.insert(relid, history);
+        Ok(())
+    }
+
+    /// Returns the changes to the history of relations made by
+    /// `set_history_retention()` since the last call.
+    pub fn take_history_events(&mut self) -> Vec<HistoryEvent> {
+        std::mem::take(&mut self.history_events)
+    }
+
+    /// Returns the changes to the history of relations that committing the
+    /// current transaction with the updates applied so far makes.  The
+    /// first call fixes the time of the transaction, so that the history
+    /// recorded when it commits matches the returned changes; later updates
+    /// are recorded at the same time.
+    pub fn transaction_history_events(&mut self) -> Vec<HistoryEvent> {
+        let mut events = Vec::new();
+        if !self.transaction_in_progress || self.history.is_empty() {
+            return events;
+        }
+        let now = *self.history_time.get_or_insert_with(history::now_millis);
+        for (relid, history) in self.history.iter() {
+            if let Some(rel) = self.relations.get(relid) {
+                history.changes(rel.delta(), now, &mut events);
+            }
+        }
+        events
     }
 
     /// Apply multiple insert and delete operations in one batch.
@@ -2098,18 +3223,103 @@ impl RunningProgram {
     where
         I: Iterator<Item = Update<DDValue>>,
         F: Fn(&Update<DDValue>) -> Response<()>,
+    {
+        self.do_apply_updates(updates.map(|update| inspect(&update).map(|_| update)))
+    }
+
+    /// Apply updates on behalf of `tenant` in multi-tenant mode.  `inspect` is
+    /// invoked for each update before its values are tagged with the tenant.
+    pub fn apply_tenant_updates<I, F>(
+        &mut self,
+        tenant: TenantId,
+        updates: I,
+        inspect: F,
+    ) -> Response<()>
+    where
+        I: Iterator<Item = Update<DDValue>>,
+        F: Fn(&Update<DDValue>) -> Response<()>,
+    {
+        if !self.multi_tenant {
+            return Err("apply_tenant_updates: multi-tenant mode is not enabled".to_string());
+        }
+
+        self.do_apply_updates(
+            updates.map(|update| inspect(&update).map(|_| tenant::tag_update(tenant, update))),
+        )
+    }
+
+    fn do_apply_updates<I>(&mut self, updates: I) -> Response<()>
+    where
+        I: Iterator<Item = Response<Update<DDValue>>>,
     {
         if !self.transaction_in_progress {
             return Err("apply_updates: no transaction in progress".to_string());
         }
+        self.check_not_prepared()?;
 
         // Remove no-op updates to maintain set semantics
         let mut filtered_updates = Vec::new();
-        for update in updates {
-            inspect(&update)?;
-            self.apply_update(update, &mut filtered_updates)?;
+        let mut new_tenants = Vec::new();
+        let res = updates.try_for_each(|update| {
+            let update = update?;
+            if self.multi_tenant {
+                self.add_tenant(&update, &mut new_tenants, &mut filtered_updates)?;
+            }
+            self.apply_update(update, &mut filtered_updates)
+        });
+        if let Err(e) = res {
+            for tenant in new_tenants {
+                self.tenants.remove(&tenant);
+            }
+            return Err(e);
+        }
+
+        self.send_updates(filtered_updates)
+    }
+
+    /// In multi-tenant mode, check that `update` is issued on behalf of a
+    /// tenant.  When the tenant is new, add the initial data of the program
+    /// for it to `updates`.
+    fn add_tenant(
+        &mut self,
+        update: &Update<DDValue>,
+        new_tenants: &mut Vec<TenantId>,
+        updates: &mut Vec<Update<DDValue>>,
+    ) -> Response<()> {
+        let tenant = tenant::tenant_of_update(update).ok_or_else(|| {
+            format!(
+                "apply_updates: update to relation {} is not issued on behalf of a tenant in multi-tenant mode",
+                update.relid()
+            )
+        })?;
+        if self.tenants.insert(tenant) {
+            new_tenants.push(tenant);
+            updates.extend(self.init_data.iter().map(|(relid, v)| Update::Insert {
+                relid: *relid,
+                v: tenant::tag(tenant, v.clone()),
+            }));
+        }
+        Ok(())
+    }
+
+    /// Distribute updates that have already been applied to the input relations
+    /// among workers, or buffer them until the next flush.
+    fn send_updates(&mut self, filtered_updates: Vec<Update<DDValue>>) -> Response<()> {
+        if filtered_updates.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(buffer) = self.update_buffer.as_mut() {
+            for update in filtered_updates {
+                buffer.push(update);
+            }
+            self.need_to_flush = true;
+            return Ok(());
         }
+        self.dispatch_updates(filtered_updates)
+    }
 
+    fn dispatch_updates(&mut self, filtered_updates: Vec<Update<DDValue>>) -> Response<()> {
         if filtered_updates.is_empty() {
             return Ok(());
         }
@@ -2139,10 +3349,31 @@ impl RunningProgram {
             return Err("clear_relation: no transaction in progress".to_string());
         }
 
+        let updates = self.clear_relation_updates(relid)?;
+        self.apply_updates(updates.into_iter(), |_| Ok(()))
+    }
+
+    /// Deletes all values of `tenant` in an input table, in multi-tenant mode.
+    pub fn clear_tenant_relation(&mut self, tenant: TenantId, relid: RelId) -> Response<()> {
+        if !self.transaction_in_progress {
+            return Err("clear_tenant_relation: no transaction in progress".to_string());
+        }
+
+        let updates = self.clear_relation_updates(relid)?;
+        self.apply_updates(
+            updates
+                .into_iter()
+                .filter(|update| tenant::tenant_of_update(update) == Some(tenant)),
+            |_| Ok(()),
+        )
+    }
+
+    /// Updates that delete all values in an input table.
+    fn clear_relation_updates(&self, relid: RelId) -> Response<Vec<Update<DDValue>>> {
         let updates = {
             let rel = self
                 .relations
-                .get_mut(&relid)
+                .get(&relid)
                 .ok_or_else(|| format!("clear_relation: unknown input relation {}", relid))?;
 
             match rel {
@@ -2164,46 +3395,458 @@ impl RunningProgram {
                         });
                     }
 
-                    updates
+                    updates
+                }
+                RelationInstance::Indexed { elements, .. } => {
+                    let mut updates: Vec<Update<DDValue>> = Vec::with_capacity(elements.len());
+                    for k in elements.keys() {
+                        updates.push(Update::DeleteKey {
+                            relid,
+                            k: k.clone(),
+                        });
+                    }
+
+                    updates
+                }
+                RelationInstance::Columnar { elements, .. } => elements
+                    .iter()
+                    .map(|v| Update::DeleteValue { relid, v })
+                    .collect(),
+            }
+        };
+
+        Ok(updates)
+    }
+
+    /// Deletes all values in an input table in a single pass.
+    ///
+    /// Unlike `clear_relation()`, which feeds a deletion for every value
+    /// through `apply_updates()`, this retracts the tracked contents of the
+    /// relation all at once, without checking deletions one by one.
+    pub fn clear_relation_bulk(&mut self, relid: RelId) -> Response<()> {
+        if !self.transaction_in_progress {
+            return Err("clear_relation_bulk: no transaction in progress".to_string());
+        }
+        self.check_not_prepared()?;
+
+        let rel = self
+            .relations
+            .get_mut(&relid)
+            .ok_or_else(|| format!("clear_relation_bulk: unknown input relation {}", relid))?;
+
+        let mut updates: Vec<Update<DDValue>> = Vec::new();
+        match rel {
+            RelationInstance::Stream { .. } => {
+                return Err("clear_relation_bulk: operation not supported for streams".to_string())
+            }
+            RelationInstance::Multiset {
+                elements,
+                delta,
+                upsert_index,
+            } => {
+                for (v, w) in elements.iter() {
+                    match delta.entry(v.clone()) {
+                        hash_map::Entry::Occupied(mut oe) => {
+                            *oe.get_mut() -= w;
+                            if *oe.get() == 0 {
+                                oe.remove_entry();
+                            }
+                        }
+                        hash_map::Entry::Vacant(ve) => {
+                            ve.insert(-w);
+                        }
+                    }
+                }
+                Self::delta_undo_updates(relid, elements, &mut updates);
+                elements.clear();
+                if let Some(index) = upsert_index {
+                    index.values.clear();
+                }
+            }
+            RelationInstance::Flat { elements, delta } => {
+                updates.reserve(elements.len());
+                for v in elements.drain() {
+                    Self::delta_dec(delta, &v);
+                    updates.push(Update::DeleteValue { relid, v });
+                }
+            }
+            RelationInstance::Indexed {
+                elements, delta, ..
+            } => {
+                updates.reserve(elements.len());
+                for (_, v) in elements.drain() {
+                    Self::delta_dec(delta, &v);
+                    updates.push(Update::DeleteValue { relid, v });
+                }
+            }
+            RelationInstance::Columnar { elements, delta } => {
+                updates.reserve(elements.len());
+                for v in elements.iter() {
+                    Self::delta_dec(delta, &v);
+                    updates.push(Update::DeleteValue { relid, v });
+                }
+                elements.clear();
+            }
+        }
+
+        self.send_updates(updates)
+    }
+
+    /// Load `values` into input relation `relid` before the first
+    /// transaction is started, e.g., to initialize the program with a large
+    /// number of facts.
+    ///
+    /// Values are sent to workers in large batches, which workers feed into
+    /// the dataflow in parallel.  Unlike `apply_updates()`, this does not
+    /// record the values in the delta sets of a transaction: only set
+    /// semantics and primary keys are enforced, and the load cannot be rolled
+    /// back.  The values are propagated through the dataflow by the first
+    /// transaction.
+    ///
+    /// Fails once a transaction has been started, for streams, and in
+    /// multi-tenant mode.  If a value violates its relation's primary key,
+    /// the values preceding it remain loaded.
+    pub fn bulk_load<I>(&mut self, relid: RelId, values: I) -> Response<()>
+    where
+        I: IntoIterator<Item = DDValue>,
+    {
+        if !self.bulk_load_allowed {
+            return Err("bulk_load: a transaction has already been started".to_string());
+        }
+        if self.multi_tenant {
+            return Err("bulk_load: not supported in multi-tenant mode".to_string());
+        }
+
+        let mut rel = self
+            .relations
+            .remove(&relid)
+            .ok_or_else(|| format!("bulk_load: unknown input relation {}", relid))?;
+        let res = self.bulk_load_into(relid, &mut rel, values.into_iter());
+        self.relations.insert(relid, rel);
+        res
+    }
+
+    fn bulk_load_into<I>(
+        &mut self,
+        relid: RelId,
+        rel: &mut RelationInstance,
+        values: I,
+    ) -> Response<()>
+    where
+        I: Iterator<Item = DDValue>,
+    {
+        if let RelationInstance::Stream { .. } = rel {
+            return Err("bulk_load: operation not supported for streams".to_string());
+        }
+
+        let mut batch = Vec::with_capacity(BULK_LOAD_BATCH_SIZE);
+        for v in values {
+            let new = match rel {
+                RelationInstance::Stream { .. } => unreachable!(),
+                RelationInstance::Multiset {
+                    elements,
+                    upsert_index,
+                    ..
+                } => {
+                    Self::delta_inc(elements, &v);
+                    if let Some(index) = upsert_index {
+                        index.update(elements, &v);
+                    }
+                    true
+                }
+                RelationInstance::Flat { elements, .. } => elements.insert(v.clone()),
+                RelationInstance::Indexed {
+                    key_func, elements, ..
+                } => match elements.entry(lift_key(&v, *key_func)) {
+                    hash_map::Entry::Occupied(oe) => {
+                        let err = format!(
+                            "bulk_load: duplicate key '{:?}' in value '{:?}'",
+                            oe.key(),
+                            v
+                        );
+                        self.send_bulk_load(relid, batch)?;
+                        return Err(err);
+                    }
+                    hash_map::Entry::Vacant(ve) => {
+                        ve.insert(v.clone());
+                        true
+                    }
+                },
+                RelationInstance::Columnar { elements, .. } => match elements.insert(&v) {
+                    Ok(new) => new,
+                    Err(e) => {
+                        self.send_bulk_load(relid, batch)?;
+                        return Err(format!("bulk_load: {}", e));
+                    }
+                },
+            };
+            if new {
+                batch.push(v);
+                if batch.len() == BULK_LOAD_BATCH_SIZE {
+                    let full =
+                        std::mem::replace(&mut batch, Vec::with_capacity(BULK_LOAD_BATCH_SIZE));
+                    self.send_bulk_load(relid, full)?;
+                }
+            }
+        }
+        self.send_bulk_load(relid, batch)
+    }
+
+    /// Send a batch of bulk-loaded values to the next worker.
+    fn send_bulk_load(&mut self, relid: RelId, values: Vec<DDValue>) -> Response<()> {
+        if values.is_empty() {
+            return Ok(());
+        }
+
+        let worker_idx = self.worker_round_robbin.next().unwrap_or(0);
+        self.send(
+            worker_idx,
+            Msg::BulkLoad {
+                relid,
+                values,
+                timestamp: self.timestamp,
+            },
+        )?;
+        self.need_to_flush = true;
+        Ok(())
+    }
+
+    /// Computes the smallest set of updates that turns the current contents of
+    /// input relation `relid` into `desired`.  Deletions precede insertions in
+    /// the result, so that values can be replaced without violating primary
+    /// key constraints.
+    ///
+    /// Duplicate values in `desired` are significant for multiset relations
+    /// and are ignored for all other relations.  The updates are not applied
+    /// to the relation.
+    pub fn relation_sync_updates(
+        &self,
+        relid: RelId,
+        desired: Vec<DDValue>,
+    ) -> Response<Vec<Update<DDValue>>> {
+        let rel = self
+            .relations
+            .get(&relid)
+            .ok_or_else(|| format!("relation_sync_updates: unknown input relation {}", relid))?;
+
+        let mut deletes = Vec::new();
+        let mut inserts = Vec::new();
+        match rel {
+            RelationInstance::Stream { .. } => {
+                return Err("relation_sync_updates: operation not supported for streams".to_string())
+            }
+            RelationInstance::Multiset { elements, .. } => {
+                // Weight of each value in `desired` minus its weight in `elements`.
+                let mut diff: DeltaSet = FnvHashMap::default();
+                for v in desired {
+                    *diff.entry(v).or_insert(0) += 1;
                 }
-                RelationInstance::Indexed { elements, .. } => {
-                    let mut updates: Vec<Update<DDValue>> = Vec::with_capacity(elements.len());
-                    for k in elements.keys() {
-                        updates.push(Update::DeleteKey {
+                for (v, w) in elements.iter() {
+                    *diff.entry(v.clone()).or_insert(0) -= w;
+                }
+                for (v, w) in diff {
+                    if w < 0 {
+                        deletes.extend((0..-w).map(|_| Update::DeleteValue {
                             relid,
-                            k: k.clone(),
-                        });
+                            v: v.clone(),
+                        }));
+                    } else {
+                        inserts.extend((0..w).map(|_| Update::Insert {
+                            relid,
+                            v: v.clone(),
+                        }));
                     }
-
-                    updates
                 }
             }
-        };
+            RelationInstance::Flat { elements, .. } => {
+                let desired: ValSet = desired.into_iter().collect();
+                deletes.extend(elements.difference(&desired).map(|v| Update::DeleteValue {
+                    relid,
+                    v: v.clone(),
+                }));
+                inserts.extend(
+                    desired
+                        .into_iter()
+                        .filter(|v| !elements.contains(v))
+                        .map(|v| Update::Insert { relid, v }),
+                );
+            }
+            RelationInstance::Columnar { elements, .. } => {
+                let desired: ValSet = desired.into_iter().collect();
+                deletes.extend(
+                    elements
+                        .iter()
+                        .filter(|v| !desired.contains(v))
+                        .map(|v| Update::DeleteValue { relid, v }),
+                );
+                inserts.extend(
+                    desired
+                        .into_iter()
+                        .filter(|v| !elements.contains(v))
+                        .map(|v| Update::Insert { relid, v }),
+                );
+            }
+            RelationInstance::Indexed { elements, .. } => {
+                let current: FnvHashSet<&DDValue> = elements.values().collect();
+                let desired: ValSet = desired.into_iter().collect();
+                deletes.extend(current.iter().filter(|v| !desired.contains(**v)).map(|v| {
+                    Update::DeleteValue {
+                        relid,
+                        v: (*v).clone(),
+                    }
+                }));
+                inserts.extend(
+                    desired
+                        .into_iter()
+                        .filter(|v| !current.contains(&v))
+                        .map(|v| Update::Insert { relid, v }),
+                );
+            }
+        }
 
-        self.apply_updates(updates.into_iter(), |_| Ok(()))
+        deletes.append(&mut inserts);
+        Ok(deletes)
     }
 
     /// Returns all values in the arrangement with the specified key.
     pub fn query_arrangement(&mut self, arrid: ArrId, k: DDValue) -> Response<BTreeSet<DDValue>> {
-        self._query_arrangement(arrid, Some(k))
+        Ok(self
+            ._query_arrangement(arrid, Some(k))?
+            .into_iter()
+            .map(|(v, _)| v)
+            .collect())
     }
 
     /// Returns the entire content of an arrangement.
     pub fn dump_arrangement(&mut self, arrid: ArrId) -> Response<BTreeSet<DDValue>> {
+        Ok(self
+            ._query_arrangement(arrid, None)?
+            .into_iter()
+            .map(|(v, _)| v)
+            .collect())
+    }
+
+    /// Returns all values in the arrangement with the specified key along
+    /// with their multiplicities, i.e., the number of times each value has
+    /// been derived (or inserted, for input multisets).
+    pub fn query_arrangement_multiplicities(
+        &mut self,
+        arrid: ArrId,
+        k: DDValue,
+    ) -> Response<BTreeMap<DDValue, Weight>> {
+        self._query_arrangement(arrid, Some(k))
+    }
+
+    /// Returns the entire content of an arrangement along with the
+    /// multiplicities of its values.
+    pub fn dump_arrangement_multiplicities(
+        &mut self,
+        arrid: ArrId,
+    ) -> Response<BTreeMap<DDValue, Weight>> {
         self._query_arrangement(arrid, None)
     }
 
+    /// Returns the values of `tenant` in the arrangement with the specified
+    /// key, in multi-tenant mode.
+    pub fn query_tenant_arrangement(
+        &mut self,
+        tenant: TenantId,
+        arrid: ArrId,
+        k: DDValue,
+    ) -> Response<BTreeSet<DDValue>> {
+        let vals = self._query_arrangement(arrid, Some(tenant::tag(tenant, k)))?;
+        Ok(vals.into_iter().map(|(v, _)| tenant::untag(v).1).collect())
+    }
+
+    /// Returns the values of `tenant` in an arrangement, in multi-tenant mode.
+    pub fn dump_tenant_arrangement(
+        &mut self,
+        tenant: TenantId,
+        arrid: ArrId,
+    ) -> Response<BTreeSet<DDValue>> {
+        let vals = self._query_arrangement(arrid, None)?;
+        Ok(vals
+            .into_iter()
+            .filter_map(|(v, _)| match tenant::untag(v) {
+                (Some(t), v) if t == tenant => Some(v),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Query an arrangement.  Arrangements of lazy relations are computed
+    /// for the duration of the query, which is not allowed while a
+    /// transaction is in progress.
     fn _query_arrangement(
         &mut self,
         arrid: ArrId,
         k: Option<DDValue>,
-    ) -> Response<BTreeSet<DDValue>> {
+    ) -> Response<BTreeMap<DDValue, Weight>> {
+        if !self.transaction_in_progress {
+            self.flush_deferred()
+                .map_err(|e| format!("query_arrangement: {}", e))?;
+        }
+        let gates = match self.lazy_gates.get(&arrid.0) {
+            None => return self.do_query_arrangement(arrid, k, None),
+            Some(gates) => gates.clone(),
+        };
+        if self.transaction_in_progress {
+            return Err(format!(
+                "query_arrangement: cannot query lazy relation {} while a transaction is in progress",
+                arrid.0
+            ));
+        }
+
+        let res = self
+            .set_gates(&gates, true)
+            .and_then(|()| self.do_query_arrangement(arrid, k, None));
+        self.set_gates(&gates, false)?;
+        // Panics while evaluating the relation only affect this query.
+        if let Some(e) = self.poisoned.take() {
+            return Err(format!("query_arrangement: {}", e));
+        }
+        res
+    }
+
+    /// Open or close the gates of lazy relations and wait for the dataflow
+    /// to compute (or retract) their contents.
+    fn set_gates(&mut self, gates: &[RelId], open: bool) -> Response<()> {
+        for relid in gates {
+            self.send(
+                0,
+                Msg::Gate {
+                    relid: *relid,
+                    open,
+                },
+            )?;
+        }
+        self.need_to_flush = true;
+        self.flush()
+    }
+
+    /// Query an arrangement, as of timestamp `before` if specified (see
+    /// `Msg::QueryAsOf`).
+    fn do_query_arrangement(
+        &mut self,
+        arrid: ArrId,
+        k: Option<DDValue>,
+        before: Option<TS>,
+    ) -> Response<BTreeMap<DDValue, Weight>> {
+        if let Some(lag) = self.compaction.on_query(arrid, self.timestamp) {
+            self.broadcast(Msg::SetCompactionLag(arrid, lag))?;
+        }
+
         // Send query and receive replies from all workers. If a key is specified, then at most
         // one worker will send a non-empty reply.
-        self.broadcast(Msg::Query(arrid, k))?;
+        self.await_pending_acks()?;
+        self.broadcast(match before {
+            None => Msg::Query(arrid, k),
+            Some(before) => Msg::QueryAsOf(arrid, k, before),
+        })?;
 
-        let mut res: BTreeSet<DDValue> = BTreeSet::new();
+        let mut res: BTreeMap<DDValue, Weight> = BTreeMap::new();
         let mut unknown = false;
+        let mut compacted = false;
         for (worker_index, chan) in self.reply_recv.iter().enumerate() {
             let reply = chan.recv().map_err(|e| {
                 format!(
@@ -2214,17 +3857,22 @@ impl RunningProgram {
 
             match reply {
                 Reply::QueryRes(Some(mut vals)) => {
-                    if !vals.is_empty() {
-                        if res.is_empty() {
-                            std::mem::swap(&mut res, &mut vals);
-                        } else {
-                            res.append(&mut vals);
+                    if res.is_empty() {
+                        std::mem::swap(&mut res, &mut vals);
+                    } else {
+                        // The same value can be associated with different
+                        // keys, which may live in different workers.
+                        for (v, w) in vals {
+                            *res.entry(v).or_insert(0) += w;
                         }
                     }
                 }
                 Reply::QueryRes(None) => {
                     unknown = true;
                 }
+                Reply::QueryCompacted => {
+                    compacted = true;
+                }
                 repl => {
                     return Err(format!(
                         "query_arrangement: unexpected reply from worker {}: {:?}",
@@ -2236,11 +3884,165 @@ impl RunningProgram {
 
         if unknown {
             Err(format!("query_arrangement: unknown index: {:?}", arrid))
+        } else if compacted {
+            Err(format!(
+                "query_arrangement: the history of index {:?} is no longer retained",
+                arrid
+            ))
         } else {
             Ok(res)
         }
     }
 
+    /// Make the trace of arrangement `arrid` retain the history of the last
+    /// `lag` timestamps (see `compaction`).  A lag of `0`, the default,
+    /// compacts the trace to the current timestamp after every transaction.
+    pub fn set_compaction_lag(&mut self, arrid: ArrId, lag: TS) -> Response<()> {
+        self.compaction.set_lag(arrid, lag, self.timestamp);
+        self.broadcast(Msg::SetCompactionLag(arrid, lag))
+    }
+
+    /// The compaction lag of arrangement `arrid`.
+    pub fn compaction_lag(&self, arrid: ArrId) -> TS {
+        self.compaction.lag(arrid)
+    }
+
+    /// Discard the history retained by the trace of arrangement `arrid`, or
+    /// of all arrangements if `arrid` is `None`.  The trace retains history
+    /// again from this point on, according to its compaction lag.  Memory
+    /// is reclaimed as batches of the trace are merged.
+    pub fn compact_now(&mut self, arrid: Option<ArrId>) -> Response<()> {
+        self.broadcast(Msg::CompactNow(arrid))
+    }
+
+    /// Number of the last committed transaction, `0` before the first
+    /// commit (see `time_travel`).
+    pub fn commit_number(&self) -> u64 {
+        self.time_travel.last_commit()
+    }
+
+    /// Retain the history of arrangement `arrid` for its last `commits`
+    /// commits, so that it can be queried as of any of them (see
+    /// `time_travel`), or stop retaining it if `commits` is 0.  Commits made
+    /// before the call are only retained if another arrangement retained
+    /// them.
+    pub fn set_time_travel(&mut self, arrid: ArrId, commits: usize) -> Response<()> {
+        self.time_travel.set_retained(arrid, commits);
+        let lag = self.time_travel.lag(arrid, self.timestamp).unwrap_or(0);
+        self.set_compaction_lag(arrid, lag)
+    }
+
+    /// Returns all values in the arrangement with the specified key, or all
+    /// values if `k` is `None`, along with their multiplicities, as they
+    /// were right after commit `commit`.  Fails if the history of the
+    /// arrangement after the commit is no longer retained.  Lazy relations
+    /// cannot be queried as of past commits.
+    pub fn query_arrangement_as_of(
+        &mut self,
+        arrid: ArrId,
+        k: Option<DDValue>,
+        commit: u64,
+    ) -> Response<BTreeMap<DDValue, Weight>> {
+        if self.lazy_gates.contains_key(&arrid.0) {
+            return Err(format!(
+                "query_arrangement_as_of: cannot query lazy relation {} as of a past commit",
+                arrid.0
+            ));
+        }
+        if !self.transaction_in_progress {
+            self.flush_deferred()
+                .map_err(|e| format!("query_arrangement_as_of: {}", e))?;
+        }
+        let before = self
+            .time_travel
+            .timestamp_of(commit)
+            .map_err(|e| format!("query_arrangement_as_of: {}", e))?;
+        self.do_query_arrangement(arrid, k, Some(before))
+    }
+
+    /// Record a commit that has been propagated through the dataflow,
+    /// extending the history retained by arrangements with time travel,
+    /// whose compaction lags `prepare_commit()` has adjusted.
+    fn record_commit(&mut self) {
+        self.time_travel.on_commit();
+        self.time_travel.on_propagated(self.timestamp);
+    }
+
+    /// Adjust the compaction lags of arrangements with time travel, so
+    /// that they retain the commit about to be recorded.
+    fn prepare_commit(&mut self) -> Response<()> {
+        for (arrid, lag) in self.time_travel.lags_after(1, self.timestamp) {
+            self.set_compaction_lag(arrid, lag)?;
+        }
+        Ok(())
+    }
+
+    /// Record that the last flush propagated the deferred commits, and no
+    /// changes of a transaction in progress, so that arrangements with time
+    /// travel retain them.
+    fn record_propagated(&mut self) -> Response<()> {
+        if !self.time_travel.has_unpropagated() {
+            return Ok(());
+        }
+        for (arrid, lag) in self.time_travel.lags_after(0, self.timestamp) {
+            self.set_compaction_lag(arrid, lag)?;
+        }
+        self.time_travel.on_propagated(self.timestamp);
+        Ok(())
+    }
+
+    /// Set the policy for compacting arrangements with a compaction lag.
+    /// The policy is applied after every transaction.
+    pub fn set_compaction_policy(&mut self, policy: Option<CompactionPolicy>) {
+        self.compaction.set_policy(policy);
+    }
+
+    /// Duplicate and near-duplicate arrangements of the program, and whether
+    /// they are shared (see `sharing`).
+    pub fn arrangement_report(&self) -> &[DuplicateArrangement] {
+        &self.arrangement_report
+    }
+
+    /// Statistics of relation `relid`, counting changes up to the last
+    /// committed transaction (see `stats`).  `None` if the relation is not
+    /// counted (see `Config::relation_stats`).
+    pub fn relation_stats(&self, relid: RelId) -> Option<RelationStats> {
+        self.stats.get(relid)
+    }
+
+    /// Statistics of all counted relations of the program.
+    pub fn all_relation_stats(&self) -> BTreeMap<RelId, RelationStats> {
+        self.stats
+            .relids()
+            .filter_map(|relid| Some((relid, self.stats.get(relid)?)))
+            .collect()
+    }
+
+    /// Render the dataflow plan of the program, annotated with the current
+    /// size of relations (see `plan`).
+    pub fn explain_plan(&self, format: PlanFormat) -> String {
+        let mut plan = self.plan.clone();
+        for (relid, stats) in self.all_relation_stats() {
+            plan.set_relation_size(relid, stats.size as usize);
+        }
+        // Input relations know their exact size.
+        for (relid, relation) in self.relations.iter() {
+            if let Some(size) = relation.len() {
+                plan.set_relation_size(*relid, size);
+            }
+        }
+        plan.render(format)
+    }
+
+    /// Suspend the compaction lag of arrangements that the compaction policy
+    /// considers idle.
+    fn apply_compaction_policy(&mut self) -> Response<()> {
+        for arrid in self.compaction.on_advance(self.timestamp) {
+            self.broadcast(Msg::SetCompactionLag(arrid, 0))?;
+        }
+        Ok(())
+    }
+
     /// increment the counter associated with value `x` in the delta-set
     /// `delta(x) == false` => remove entry (equivalent to delta(x):=0)
     /// `x not in delta => `delta(x) := true`
@@ -2325,23 +4127,59 @@ impl RunningProgram {
     /// Update value and delta multisets of an input multiset relation before performing an update.
     /// `s` is the current content of the relation.
     /// `ds` is delta since start of transaction.
+    /// `index` is the upsert index of the relation, if any.
     /// `x` is the value being inserted or deleted.
     /// `insert` indicates type of update (`true` for insert, `false` for delete).
     /// Returns `true` if the update modifies the relation, i.e., it's not a no-op.
     fn mset_update(
         s: &mut ValMSet,
         ds: &mut DeltaSet,
+        mut index: Option<&mut UpsertIndex>,
         upd: Update<DDValue>,
         updates: &mut Vec<Update<DDValue>>,
     ) -> Response<()> {
+        if let (Update::InsertOrUpdate { relid, v }, Some(index)) = (&upd, index.as_mut()) {
+            let key = lift_key(v, &*index.key_func);
+
+            // Delete all values with the same key.
+            for old in index.values.remove(&key).unwrap_or_default() {
+                let w = s.get(&old).copied().unwrap_or(0);
+                for _ in 0..w {
+                    Self::delta_dec(s, &old);
+                    Self::delta_dec(ds, &old);
+                    updates.push(Update::DeleteValue {
+                        relid: *relid,
+                        v: old.clone(),
+                    });
+                }
+            }
+
+            // Insert new value.
+            Self::delta_inc(s, v);
+            Self::delta_inc(ds, v);
+            index.update(s, v);
+            updates.push(Update::Insert {
+                relid: *relid,
+                v: v.clone(),
+            });
+
+            return Ok(());
+        }
+
         match &upd {
             Update::Insert { v, .. } => {
                 Self::delta_inc(s, v);
                 Self::delta_inc(ds, v);
+                if let Some(index) = index {
+                    index.update(s, v);
+                }
             }
             Update::DeleteValue { v, .. } => {
                 Self::delta_dec(s, v);
                 Self::delta_dec(ds, v);
+                if let Some(index) = index {
+                    index.update(s, v);
+                }
             }
             Update::InsertOrUpdate { relid, .. } => {
                 return Err(format!(
@@ -2423,9 +4261,63 @@ impl RunningProgram {
         Ok(())
     }
 
+    /// Update a columnar input relation.  Like `set_update()`, but also fails
+    /// if the value does not match the columns of the relation.
+    fn columnar_update(
+        s: &mut ColumnarSet,
+        ds: &mut DeltaSet,
+        upd: Update<DDValue>,
+        updates: &mut Vec<Update<DDValue>>,
+    ) -> Response<()> {
+        let ok = match &upd {
+            Update::Insert { v, .. } => {
+                let new = s.insert(v)?;
+                if new {
+                    Self::delta_inc(ds, v);
+                }
+
+                new
+            }
+            Update::DeleteValue { v, .. } => {
+                let present = s.remove(v);
+                if present {
+                    Self::delta_dec(ds, v);
+                }
+
+                present
+            }
+            Update::InsertOrUpdate { relid, .. } => {
+                return Err(format!(
+                    "Cannot perform insert_or_update operation on relation {} that does not have a primary key",
+                    relid,
+                ));
+            }
+            Update::DeleteKey { relid, .. } => {
+                return Err(format!(
+                    "Cannot delete by key from relation {} that does not have a primary key",
+                    relid,
+                ));
+            }
+            Update::Modify { relid, .. } => {
+                return Err(format!(
+                    "Cannot modify record in relation {} that does not have a primary key",
+                    relid,
+                ));
+            }
+        };
+
+        if ok {
+            updates.push(upd);
+        }
+
+        Ok(())
+    }
+
     /// insert:
-    ///      key exists in `s`:
-    ///          - error
+    ///      key exists in `s` with value `old`:
+    ///          - resolve the conflict according to `policy` (error by
+    ///            default) and record it in `conflicts`
+    ///          - if resolved to `v != old`: replace `old` with `v`
     ///      key not in `s`:
     ///          - s.insert(x)
     ///          - ds(x)++;
@@ -2439,16 +4331,32 @@ impl RunningProgram {
         key_func: fn(&DDValue) -> DDValue,
         s: &mut IndexedValSet,
         ds: &mut DeltaSet,
+        policy: Option<&ConflictPolicy>,
+        conflicts: &mut Vec<KeyConflict>,
         upd: Update<DDValue>,
         updates: &mut Vec<Update<DDValue>>,
     ) -> Response<()> {
+        let key_func = |v: &DDValue| lift_key(v, key_func);
         match upd {
             Update::Insert { relid, v } => match s.entry(key_func(&v)) {
-                hash_map::Entry::Occupied(_) => Err(format!(
-                    "Insert: duplicate key '{:?}' in value '{:?}'",
-                    key_func(&v),
-                    v
-                )),
+                hash_map::Entry::Occupied(mut oe) => {
+                    let policy = policy.unwrap_or(&ConflictPolicy::Error);
+                    let resolved = policy.resolve(oe.key(), oe.get(), &v, key_func);
+                    conflicts.push(KeyConflict {
+                        relid,
+                        key: oe.key().clone(),
+                        existing: oe.get().clone(),
+                        new: v,
+                        resolution: resolved
+                            .as_ref()
+                            .map_or(Resolution::Rejected, |(_, resolution)| *resolution),
+                    });
+                    let (new, _) = resolved?;
+                    if new != *oe.get() {
+                        Self::indexed_replace(ds, relid, oe.get_mut(), new, updates);
+                    }
+                    Ok(())
+                }
                 hash_map::Entry::Vacant(ve) => {
                     ve.insert(v.clone());
                     Self::delta_inc(ds, &v);
@@ -2460,21 +4368,7 @@ impl RunningProgram {
 
             Update::InsertOrUpdate { relid, v } => match s.entry(key_func(&v)) {
                 hash_map::Entry::Occupied(mut oe) => {
-                    // Delete old value.
-                    let old = oe.get().clone();
-                    Self::delta_dec(ds, oe.get());
-                    updates.push(Update::DeleteValue { relid, v: old });
-
-                    // Insert new value.
-                    Self::delta_inc(ds, &v);
-                    updates.push(Update::Insert {
-                        relid,
-                        v: v.clone(),
-                    });
-
-                    // Update store
-                    *oe.get_mut() = v;
-
+                    Self::indexed_replace(ds, relid, oe.get_mut(), v, updates);
                     Ok(())
                 }
                 hash_map::Entry::Vacant(ve) => {
@@ -2533,6 +4427,32 @@ impl RunningProgram {
         }
     }
 
+    /// Replace value `old` of an indexed relation with `new`.
+    fn indexed_replace(
+        ds: &mut DeltaSet,
+        relid: RelId,
+        old: &mut DDValue,
+        new: DDValue,
+        updates: &mut Vec<Update<DDValue>>,
+    ) {
+        // Delete old value.
+        Self::delta_dec(ds, old);
+        updates.push(Update::DeleteValue {
+            relid,
+            v: old.clone(),
+        });
+
+        // Insert new value.
+        Self::delta_inc(ds, &new);
+        updates.push(Update::Insert {
+            relid,
+            v: new.clone(),
+        });
+
+        // Update store
+        *old = new;
+    }
+
     /// Returns a reference to indexed input relation content.
     /// If called in the middle of a transaction, returns state snapshot including changes
     /// made by the current transaction.
@@ -2555,6 +4475,18 @@ impl RunningProgram {
         }
     }
 
+    /// Returns a reference to the content of a columnar input relation (see
+    /// `columnar`).
+    /// If called in the middle of a transaction, returns state snapshot including changes
+    /// made by the current transaction.
+    pub fn get_input_columnar_data(&self, relid: RelId) -> Response<&ColumnarSet> {
+        match self.relations.get(&relid) {
+            None => Err(format!("unknown relation {}", relid)),
+            Some(RelationInstance::Columnar { elements, .. }) => Ok(elements),
+            Some(_) => Err(format!("not a columnar relation {}", relid)),
+        }
+    }
+
     /// Returns a reference to an input multiset content.
     /// If called in the middle of a transaction, returns state snapshot including changes
     /// made by the current transaction.
@@ -2611,8 +4543,17 @@ impl RunningProgram {
         Ok(())
     }
 
-    /// Clear delta sets of all input relations on transaction commit.
+    /// Clear delta sets of all input relations on transaction commit, after
+    /// recording the changes to relations whose history is retained.
     fn delta_cleanup(&mut self) {
+        if !self.history.is_empty() {
+            let now = self.history_time.take().unwrap_or_else(history::now_millis);
+            for (relid, history) in self.history.iter_mut() {
+                if let Some(rel) = self.relations.get(relid) {
+                    history.commit(rel.delta(), now);
+                }
+            }
+        }
         for rel in self.relations.values_mut() {
             rel.delta_mut().clear();
         }
@@ -2669,20 +4610,44 @@ impl RunningProgram {
             return Ok(());
         }
 
+        self.start_flush()
+            .and_then(|()| self.await_flush_ack())
+            .and_then(|()| {
+                // Deferred commits flushed along with a transaction are
+                // recorded once it is committed or undone.
+                if self.transaction_in_progress {
+                    Ok(())
+                } else {
+                    self.record_propagated()
+                }
+            })
+            .and_then(|()| self.apply_compaction_policy())
+    }
+
+    /// Send buffered updates and a `Flush` command to all workers without
+    /// waiting for them to complete it.
+    fn start_flush(&mut self) -> Response<()> {
+        if let Some(buffer) = self.update_buffer.as_mut() {
+            let updates = buffer.drain();
+            self.dispatch_updates(updates)?;
+        }
+        self.batcher.flushed();
         self.broadcast(Msg::Flush {
             advance_to: self.timestamp + 1,
         })
-        .and_then(|()| {
+        .map(|()| {
             self.timestamp += 1;
+            self.progress.submit(self.timestamp);
             self.need_to_flush = false;
-            self.await_flush_ack()
         })
     }
 
     /// Wait for all workers to complete the `Flush` command.  This guarantees
     /// that all outputs have been produced and we have successfully committed
-    /// the current transaction.
-    fn await_flush_ack(&self) -> Response<()> {
+    /// the current transaction.  Poisons the transaction if any of the workers
+    /// reports a panic.
+    fn await_flush_ack(&mut self) -> Response<()> {
+        self.await_pending_acks()?;
         for (worker_index, receiver) in self.reply_recv.iter().enumerate() {
             match receiver.recv() {
                 Err(_) => {
@@ -2692,6 +4657,9 @@ impl RunningProgram {
                     ))
                 }
                 Ok(Reply::FlushAck) => (),
+                Ok(Reply::Panic(e)) => {
+                    self.poisoned.get_or_insert(e);
+                }
                 Ok(msg) => {
                     return Err(format!(
                         "received unexpected reply to flush request from worker {}: {:?}",
@@ -2702,6 +4670,97 @@ impl RunningProgram {
         }
         Ok(())
     }
+
+    /// Like `await_flush_ack()`, but give up at `deadline`, leaving the acks
+    /// that have not arrived in `pending_acks`.  Returns `false` on timeout.
+    fn await_flush_ack_until(&mut self, deadline: Instant) -> Response<bool> {
+        let mut timed_out = false;
+        for (worker_index, receiver) in self.reply_recv.iter().enumerate() {
+            if !timed_out {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                match receiver.recv_timeout(timeout) {
+                    Err(RecvTimeoutError::Timeout) => timed_out = true,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(format!(
+                            "failed to receive flush ack message from worker {}",
+                            worker_index
+                        ))
+                    }
+                    Ok(Reply::FlushAck) => (),
+                    Ok(Reply::Panic(e)) => {
+                        self.poisoned.get_or_insert(e);
+                    }
+                    Ok(msg) => {
+                        return Err(format!(
+                            "received unexpected reply to flush request from worker {}: {:?}",
+                            worker_index, msg,
+                        ))
+                    }
+                }
+            }
+            if timed_out {
+                self.pending_acks[worker_index] += 1;
+            }
+        }
+        Ok(!timed_out)
+    }
+
+    /// Like `await_pending_acks()`, but give up at `deadline`.  Returns
+    /// `false` on timeout.
+    fn await_pending_acks_until(&mut self, deadline: Instant) -> Response<bool> {
+        for (worker_index, receiver) in self.reply_recv.iter().enumerate() {
+            while self.pending_acks[worker_index] > 0 {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                match receiver.recv_timeout(timeout) {
+                    Ok(Reply::FlushAck) | Ok(Reply::Panic(_)) => {
+                        self.pending_acks[worker_index] -= 1
+                    }
+                    Err(RecvTimeoutError::Timeout) => return Ok(false),
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(format!(
+                            "failed to receive flush ack message from worker {}",
+                            worker_index
+                        ))
+                    }
+                    Ok(msg) => {
+                        return Err(format!(
+                            "received unexpected reply to flush request from worker {}: {:?}",
+                            worker_index, msg,
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Wait for the acks of flushes started by commits that exceeded their
+    /// deadline.  Panics they report were raised by transactions that have
+    /// been rolled back and are ignored.
+    fn await_pending_acks(&mut self) -> Response<()> {
+        for (worker_index, receiver) in self.reply_recv.iter().enumerate() {
+            while self.pending_acks[worker_index] > 0 {
+                match receiver.recv() {
+                    Ok(Reply::FlushAck) | Ok(Reply::Panic(_)) => {
+                        self.pending_acks[worker_index] -= 1
+                    }
+                    Err(_) => {
+                        return Err(format!(
+                            "failed to receive flush ack message from worker {}",
+                            worker_index
+                        ))
+                    }
+                    Ok(msg) => {
+                        return Err(format!(
+                            "received unexpected reply to flush request from worker {}: {:?}",
+                            worker_index, msg,
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Drop for RunningProgram {
@@ -0,0 +1,131 @@
+//! Detecting weight overflow.
+//!
+//! Differential dataflow adds and multiplies the weights of facts using
+//! plain `Weight` arithmetic, which silently wraps around when a fact has
+//! more derivations than fit into a `Weight`, e.g., in programs that join
+//! many relations with large multiplicities.  The width of weights can be
+//! increased to 64 or 128 bits with the `weight_64` and `weight_128`
+//! features (`ddlog --weight-width`).
+//!
+//! With `Config::check_weight_overflow`, workers additionally track the
+//! multiplicity of every fact of every relation with 128-bit arithmetic, and
+//! report an error if it leaves the range of `Weight`.  The error poisons
+//! the current transaction (see `poison`), which can then only be rolled
+//! back.  Tracking multiplicities costs about as much memory as a copy of
+//! all relations, so this is meant for debugging.  Overflows are detected
+//! when an overflowing multiplicity reaches a relation; a product of weights
+//! that wraps inside a join and yields an in-range result goes unnoticed.
+//!
+//! Output callbacks receive `isize` weights, which are narrower than
+//! 128-bit `Weight`s.  Whatever the configuration, a change whose weight
+//! does not fit into an `isize` is not passed to the callback, but poisons
+//! the transaction with `DDlogError::OutputWeightOverflow`.
+
+use fnv::FnvHashMap;
+use std::convert::TryFrom;
+
+use crate::{
+    ddval::DDValue,
+    program::{poison, DDlogError, Weight},
+};
+
+/// Tracks the multiplicities of the facts of one relation seen by a worker.
+pub(crate) struct WeightChecker {
+    relation: String,
+    totals: FnvHashMap<DDValue, i128>,
+}
+
+impl WeightChecker {
+    pub(crate) fn new(relation: &str) -> Self {
+        Self {
+            relation: relation.to_string(),
+            totals: FnvHashMap::default(),
+        }
+    }
+
+    /// Add `w` to the multiplicity of `v`, reporting an overflow if the new
+    /// multiplicity does not fit into a `Weight`.
+    pub(crate) fn update(&mut self, v: &DDValue, w: Weight) {
+        let total = self.totals.entry(v.clone()).or_insert(0);
+        let new_total = total.checked_add(w as i128);
+        match new_total {
+            Some(0) => {
+                self.totals.remove(v);
+            }
+            Some(t) if Weight::try_from(t).is_ok() => *total = t,
+            _ => {
+                poison::record(DDlogError::WeightOverflow {
+                    relation: self.relation.clone(),
+                    value: v.to_string(),
+                });
+                // Keep the wrapped-around weight computed by the dataflow,
+                // so that the fact is only reported once.
+                *total = i128::from(total.wrapping_add(w as i128) as Weight);
+            }
+        }
+    }
+}
+
+/// Converts the weight of a change to an output relation into the `isize`
+/// passed to output callbacks, or reports an error if it does not fit.
+pub(crate) fn output_weight(relation: &str, v: &DDValue, w: Weight) -> Option<isize> {
+    match isize::try_from(w) {
+        Ok(w) => Some(w),
+        Err(_) => {
+            poison::record(DDlogError::OutputWeightOverflow {
+                relation: relation.to_string(),
+                value: v.to_string(),
+            });
+            None
+        }
+    }
+}
+
+#[test]
+fn test_weight_checker() {
+    use crate::ddval::DDValConvert;
+    use crate::program::poison::take_panic;
+
+    let mut checker = WeightChecker::new("R");
+    let v = 5u64.into_ddvalue();
+    checker.update(&v, Weight::MAX);
+    assert_eq!(take_panic(), None);
+    checker.update(&v, -1);
+    checker.update(&v, 1);
+    assert_eq!(take_panic(), None);
+
+    checker.update(&v, 1);
+    assert_eq!(
+        take_panic(),
+        Some(DDlogError::WeightOverflow {
+            relation: "R".to_string(),
+            value: v.to_string()
+        })
+    );
+    // The dataflow wrapped around, and so does the checker.
+    checker.update(&v, 1);
+    assert_eq!(take_panic(), None);
+}
+
+#[test]
+fn test_output_weight() {
+    use crate::ddval::DDValConvert;
+    use crate::program::poison::take_panic;
+
+    let v = 5u64.into_ddvalue();
+    assert_eq!(output_weight("R", &v, 1), Some(1));
+    assert_eq!(output_weight("R", &v, -3), Some(-3));
+    assert_eq!(take_panic(), None);
+
+    #[cfg(feature = "weight_128")]
+    {
+        assert_eq!(output_weight("R", &v, Weight::MAX), None);
+        assert_eq!(
+            take_panic(),
+            Some(DDlogError::OutputWeightOverflow {
+                relation: "R".to_string(),
+                value: v.to_string()
+            })
+        );
+    }
+}
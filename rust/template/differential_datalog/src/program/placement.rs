@@ -0,0 +1,252 @@
+//! Placement of worker threads on CPUs and NUMA nodes.
+//!
+//! On large multi-socket servers, workers that migrate between sockets, or
+//! whose arrangements live in the memory of another socket, spend much of
+//! their time on cross-node memory traffic.  `Config::worker_placement` pins
+//! each timely worker to a CPU or to the CPUs of a NUMA node, and
+//! `Config::numa_local_memory` makes the memory allocated by a worker come
+//! from the node it runs on.  The allocator serves each thread from its own
+//! arena, so the arenas of pinned workers, and the arrangements built in
+//! them, end up in local memory.
+//!
+//! Placement is only supported on Linux, where the NUMA topology is read from
+//! `/sys/devices/system/node`.  Only CPUs the process is allowed to run on
+//! are used, so placement composes with `taskset` and cgroup CPU limits.
+
+use std::fs;
+
+/// Placement of timely workers on CPUs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerPlacement {
+    /// Let the operating system schedule workers.
+    Unpinned,
+    /// Pin worker `i` to the `first_cpu + i`-th CPU available to the process,
+    /// wrapping around when there are more workers than CPUs.
+    Cores { first_cpu: usize },
+    /// Assign workers to NUMA nodes round-robin, pinning each worker to the
+    /// CPUs of its node.
+    NumaNodes,
+}
+
+impl WorkerPlacement {
+    pub const fn is_unpinned(&self) -> bool {
+        matches!(self, Self::Unpinned)
+    }
+}
+
+impl Default for WorkerPlacement {
+    fn default() -> Self {
+        Self::Unpinned
+    }
+}
+
+/// The CPUs each worker is pinned to, indexed by worker.
+pub(crate) type CpuAssignment = Vec<Vec<usize>>;
+
+/// Compute the CPUs of `num_workers` workers under `placement`, or `None`
+/// if workers are not pinned.
+pub(crate) fn assign_workers(
+    placement: WorkerPlacement,
+    num_workers: usize,
+) -> Result<Option<CpuAssignment>, String> {
+    if placement.is_unpinned() {
+        return Ok(None);
+    }
+    let allowed = sys::allowed_cpus()?;
+    if allowed.is_empty() {
+        return Err("worker placement: no CPUs available to the process".to_string());
+    }
+    let nodes = match placement {
+        WorkerPlacement::NumaNodes => numa_nodes(&allowed),
+        _ => Vec::new(),
+    };
+    Ok(Some(assign(placement, num_workers, &allowed, &nodes)))
+}
+
+/// Pin the calling worker thread to `cpus`, if any, and, if `local_memory`
+/// is set, make it allocate memory from the NUMA node it runs on.
+pub(crate) fn place_worker(cpus: Option<&[usize]>, local_memory: bool) -> Result<(), String> {
+    if let Some(cpus) = cpus {
+        sys::set_affinity(cpus)?;
+    }
+    if local_memory {
+        sys::set_local_memory_policy()?;
+    }
+    Ok(())
+}
+
+fn assign(
+    placement: WorkerPlacement,
+    num_workers: usize,
+    allowed: &[usize],
+    nodes: &[Vec<usize>],
+) -> CpuAssignment {
+    match placement {
+        WorkerPlacement::Unpinned => vec![allowed.to_vec(); num_workers],
+        WorkerPlacement::Cores { first_cpu } => (0..num_workers)
+            .map(|i| vec![allowed[(first_cpu + i) % allowed.len()]])
+            .collect(),
+        // Without NUMA information, treat the machine as a single node.
+        WorkerPlacement::NumaNodes if nodes.is_empty() => vec![allowed.to_vec(); num_workers],
+        WorkerPlacement::NumaNodes => (0..num_workers)
+            .map(|i| nodes[i % nodes.len()].clone())
+            .collect(),
+    }
+}
+
+/// CPUs of every NUMA node that has CPUs in `allowed`.
+fn numa_nodes(allowed: &[usize]) -> Vec<Vec<usize>> {
+    let entries = match fs::read_dir("/sys/devices/system/node") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut nodes: Vec<(usize, Vec<usize>)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let node: usize = entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("node")?
+                .parse()
+                .ok()?;
+            let cpulist = fs::read_to_string(entry.path().join("cpulist")).ok()?;
+            let cpus: Vec<usize> = parse_cpu_list(&cpulist)
+                .ok()?
+                .into_iter()
+                .filter(|cpu| allowed.contains(cpu))
+                .collect();
+            if cpus.is_empty() {
+                None
+            } else {
+                Some((node, cpus))
+            }
+        })
+        .collect();
+    nodes.sort();
+    nodes.into_iter().map(|(_, cpus)| cpus).collect()
+}
+
+/// Parse a kernel CPU list, e.g., `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> Result<Vec<usize>, String> {
+    let parse = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|_| format!("invalid CPU list '{}'", list.trim()))
+    };
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.find('-') {
+            Some(dash) => {
+                let (from, to) = (parse(&range[..dash])?, parse(&range[dash + 1..])?);
+                cpus.extend(from..=to);
+            }
+            None => cpus.push(parse(range)?),
+        }
+    }
+    Ok(cpus)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::{io, mem, ptr};
+
+    /// `MPOL_LOCAL` from `linux/mempolicy.h`.
+    const MPOL_LOCAL: libc::c_int = 4;
+
+    pub(super) fn allowed_cpus() -> Result<Vec<usize>, String> {
+        unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            if libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+                return Err(format!(
+                    "worker placement: failed to read CPU affinity: {}",
+                    io::Error::last_os_error()
+                ));
+            }
+            Ok((0..libc::CPU_SETSIZE as usize)
+                .filter(|cpu| libc::CPU_ISSET(*cpu, &set))
+                .collect())
+        }
+    }
+
+    pub(super) fn set_affinity(cpus: &[usize]) -> Result<(), String> {
+        unsafe {
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            for cpu in cpus {
+                libc::CPU_SET(*cpu, &mut set);
+            }
+            if libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(format!(
+                    "worker placement: failed to pin worker to CPUs {:?}: {}",
+                    cpus,
+                    io::Error::last_os_error()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn set_local_memory_policy() -> Result<(), String> {
+        // `MPOL_LOCAL` takes an empty node mask.
+        let maxnode: libc::c_ulong = 0;
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_set_mempolicy,
+                MPOL_LOCAL,
+                ptr::null::<libc::c_ulong>(),
+                maxnode,
+            )
+        };
+        if res != 0 {
+            return Err(format!(
+                "worker placement: failed to set local memory policy: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    const UNSUPPORTED: &str = "worker placement is only supported on Linux";
+
+    pub(super) fn allowed_cpus() -> Result<Vec<usize>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    pub(super) fn set_affinity(_cpus: &[usize]) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    pub(super) fn set_local_memory_policy() -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+}
+
+#[test]
+fn test_parse_cpu_list() {
+    assert_eq!(
+        parse_cpu_list("0-3,8,10-11\n"),
+        Ok(vec![0, 1, 2, 3, 8, 10, 11])
+    );
+    assert_eq!(parse_cpu_list("\n"), Ok(vec![]));
+    assert!(parse_cpu_list("0-x").is_err());
+}
+
+#[test]
+fn test_assign_workers() {
+    let allowed = vec![0, 1, 2, 3];
+    let nodes = vec![vec![0, 1], vec![2, 3]];
+
+    assert_eq!(
+        assign(WorkerPlacement::Cores { first_cpu: 1 }, 4, &allowed, &nodes),
+        vec![vec![1], vec![2], vec![3], vec![0]]
+    );
+    assert_eq!(
+        assign(WorkerPlacement::NumaNodes, 3, &allowed, &nodes),
+        vec![vec![0, 1], vec![2, 3], vec![0, 1]]
+    );
+    assert_eq!(
+        assign(WorkerPlacement::NumaNodes, 2, &allowed, &[]),
+        vec![allowed.clone(), allowed]
+    );
+}
@@ -0,0 +1,380 @@
+//! Dataflow plan of a program.
+//!
+//! The plan is a graph of the relations, arrangements and operators that the
+//! program compiles into, i.e., of the dataflow constructed by workers.  Each
+//! rule contributes a chain of operators from the relation or arrangement it
+//! starts from to the relation it defines; joins additionally consume an
+//! arrangement of another relation.  Arrangements are labeled by the pattern
+//! they arrange their relation by, which determines their key function.
+//! Arrangements shared with another arrangement (see `sharing`) are shown
+//! as that arrangement.
+//!
+//! `RunningProgram::explain_plan()` annotates the plan with the current size
+//! of relations and renders it in DOT or JSON format.
+
+use std::fmt::Write;
+
+use fnv::FnvHashMap;
+
+use crate::program::{
+    ArrId, ProgNode, Program, RelId, Relation, Rule, XFormArrangement, XFormCollection,
+};
+
+/// Output format of `Plan::render()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanFormat {
+    /// Graphviz DOT.
+    Dot,
+    /// JSON object with `nodes` and `edges` arrays.
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlanNodeKind {
+    Relation {
+        relid: RelId,
+        input: bool,
+        recursive: bool,
+    },
+    DelayedRelation {
+        relid: RelId,
+    },
+    Arrangement {
+        arrid: ArrId,
+        queryable: bool,
+    },
+    Operator,
+    Transformer,
+}
+
+impl PlanNodeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            PlanNodeKind::Relation { .. } => "relation",
+            PlanNodeKind::DelayedRelation { .. } => "delayed_relation",
+            PlanNodeKind::Arrangement { .. } => "arrangement",
+            PlanNodeKind::Operator => "operator",
+            PlanNodeKind::Transformer => "transformer",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanNode {
+    pub kind: PlanNodeKind,
+    /// Relation or arrangement name, or operator description.
+    pub label: String,
+    /// Number of records in the relation, when known.
+    pub size: Option<usize>,
+}
+
+/// Dataflow graph; edges are pairs of indexes in `nodes`.
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    pub nodes: Vec<PlanNode>,
+    pub edges: Vec<(usize, usize)>,
+}
+
+impl Plan {
+    /// Record the size of relation `relid`.
+    pub fn set_relation_size(&mut self, relid: RelId, size: usize) {
+        for node in self.nodes.iter_mut() {
+            if matches!(node.kind, PlanNodeKind::Relation { relid: id, .. } if id == relid) {
+                node.size = Some(size);
+            }
+        }
+    }
+
+    pub fn render(&self, format: PlanFormat) -> String {
+        match format {
+            PlanFormat::Dot => self.to_dot(),
+            PlanFormat::Json => self.to_json(),
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let mut out = String::from("digraph plan {\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let shape = match node.kind {
+                PlanNodeKind::Relation { input: true, .. } => "box, style=bold",
+                PlanNodeKind::Relation { .. } | PlanNodeKind::DelayedRelation { .. } => "box",
+                PlanNodeKind::Arrangement { .. } => "cylinder",
+                PlanNodeKind::Operator => "ellipse",
+                PlanNodeKind::Transformer => "hexagon",
+            };
+            let label = match node.size {
+                Some(size) => format!("{}\n{} records", node.label, size),
+                None => node.label.clone(),
+            };
+            let _ = writeln!(
+                out,
+                "    n{} [label={}, shape={}];",
+                i,
+                quote(&label),
+                shape
+            );
+        }
+        for (from, to) in self.edges.iter() {
+            let _ = writeln!(out, "    n{} -> n{};", from, to);
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn to_json(&self) -> String {
+        let nodes: Vec<String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let mut fields = vec![
+                    format!("\"id\":{}", i),
+                    format!("\"kind\":\"{}\"", node.kind.name()),
+                    format!("\"label\":{}", quote(&node.label)),
+                ];
+                match node.kind {
+                    PlanNodeKind::Relation {
+                        relid,
+                        input,
+                        recursive,
+                    } => {
+                        fields.push(format!("\"relid\":{}", relid));
+                        fields.push(format!("\"input\":{}", input));
+                        fields.push(format!("\"recursive\":{}", recursive));
+                    }
+                    PlanNodeKind::DelayedRelation { relid } => {
+                        fields.push(format!("\"relid\":{}", relid));
+                    }
+                    PlanNodeKind::Arrangement { arrid, queryable } => {
+                        fields.push(format!("\"arrid\":[{},{}]", arrid.0, arrid.1));
+                        fields.push(format!("\"queryable\":{}", queryable));
+                    }
+                    PlanNodeKind::Operator | PlanNodeKind::Transformer => {}
+                }
+                if let Some(size) = node.size {
+                    fields.push(format!("\"size\":{}", size));
+                }
+                format!("{{{}}}", fields.join(","))
+            })
+            .collect();
+        let edges: Vec<String> = self
+            .edges
+            .iter()
+            .map(|(from, to)| format!("{{\"from\":{},\"to\":{}}}", from, to))
+            .collect();
+        format!(
+            "{{\"nodes\":[{}],\"edges\":[{}]}}",
+            nodes.join(","),
+            edges.join(",")
+        )
+    }
+}
+
+/// Quote `s` as a JSON string, which DOT also accepts as a quoted label.
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+struct PlanBuilder<'a> {
+    program: &'a Program,
+    plan: Plan,
+    relations: FnvHashMap<RelId, usize>,
+    arrangements: FnvHashMap<ArrId, usize>,
+}
+
+impl<'a> PlanBuilder<'a> {
+    fn node(&mut self, kind: PlanNodeKind, label: &str) -> usize {
+        self.plan.nodes.push(PlanNode {
+            kind,
+            label: label.to_string(),
+            size: None,
+        });
+        self.plan.nodes.len() - 1
+    }
+
+    fn edge(&mut self, from: usize, to: usize) {
+        self.plan.edges.push((from, to));
+    }
+
+    fn relation(&mut self, rel: &Relation, recursive: bool) {
+        let node = self.node(
+            PlanNodeKind::Relation {
+                relid: rel.id,
+                input: rel.input,
+                recursive,
+            },
+            rel.name(),
+        );
+        self.relations.insert(rel.id, node);
+        for (i, arr) in rel.arrangements.iter().enumerate() {
+            if self.program.shared_arrangement((rel.id, i)).is_none() {
+                let arr_node = self.node(
+                    PlanNodeKind::Arrangement {
+                        arrid: (rel.id, i),
+                        queryable: self
+                            .program
+                            .arrangements_sharing((rel.id, i))
+                            .any(|arrid| rel.arrangements[arrid.1].queryable()),
+                    },
+                    arr.name(),
+                );
+                self.arrangements.insert((rel.id, i), arr_node);
+                self.edge(node, arr_node);
+            }
+        }
+    }
+
+    fn relation_node(&self, relid: RelId) -> usize {
+        self.relations[&relid]
+    }
+
+    fn arrangement_node(&self, arrid: ArrId) -> usize {
+        self.arrangements[&self.program.shared_arrangement(arrid).unwrap_or(arrid)]
+    }
+
+    fn rules(&mut self, rel: &Relation) {
+        let target = self.relation_node(rel.id);
+        for rule in rel.rules.iter() {
+            let last = match rule {
+                Rule::CollectionRule {
+                    rel: source, xform, ..
+                } => {
+                    let from = self.relation_node(*source);
+                    self.next_collection(from, xform)
+                }
+                Rule::ArrangementRule { arr, xform, .. } => {
+                    let from = self.arrangement_node(*arr);
+                    self.arrangement_xform(from, xform)
+                }
+            };
+            self.edge(last, target);
+        }
+    }
+
+    fn operator(&mut self, from: usize, description: &str) -> usize {
+        let node = self.node(PlanNodeKind::Operator, description);
+        self.edge(from, node);
+        node
+    }
+
+    fn next_collection(&mut self, from: usize, xform: &Option<XFormCollection>) -> usize {
+        match xform {
+            None => from,
+            Some(xform) => self.collection_xform(from, xform),
+        }
+    }
+
+    fn collection_xform(&mut self, from: usize, xform: &XFormCollection) -> usize {
+        let node = self.operator(from, xform.description());
+        match xform {
+            XFormCollection::Arrange { next, .. } => self.arrangement_xform(node, next),
+            XFormCollection::Differentiate { next, .. }
+            | XFormCollection::Map { next, .. }
+            | XFormCollection::FlatMap { next, .. }
+            | XFormCollection::Filter { next, .. }
+            | XFormCollection::FilterMap { next, .. }
+            | XFormCollection::Inspect { next, .. } => self.next_collection(node, next),
+            XFormCollection::StreamJoin {
+                arrangement, next, ..
+            }
+            | XFormCollection::StreamSemijoin {
+                arrangement, next, ..
+            } => {
+                let arr = self.arrangement_node(*arrangement);
+                self.edge(arr, node);
+                self.next_collection(node, next)
+            }
+            XFormCollection::StreamXForm { xform, next, .. } => {
+                let last = self.next_collection(node, xform);
+                self.next_collection(last, next)
+            }
+        }
+    }
+
+    fn arrangement_xform(&mut self, from: usize, xform: &XFormArrangement) -> usize {
+        let node = self.operator(from, xform.description());
+        match xform {
+            XFormArrangement::FlatMap { next, .. }
+            | XFormArrangement::FilterMap { next, .. }
+            | XFormArrangement::Aggregate { next, .. } => self.next_collection(node, next),
+            XFormArrangement::Join {
+                arrangement, next, ..
+            }
+            | XFormArrangement::Semijoin {
+                arrangement, next, ..
+            }
+            | XFormArrangement::Antijoin {
+                arrangement, next, ..
+            } => {
+                let arr = self.arrangement_node(*arrangement);
+                self.edge(arr, node);
+                self.next_collection(node, next)
+            }
+            XFormArrangement::StreamJoin { rel, next, .. }
+            | XFormArrangement::StreamSemijoin { rel, next, .. } => {
+                let rel = self.relation_node(*rel);
+                self.edge(rel, node);
+                self.next_collection(node, next)
+            }
+        }
+    }
+}
+
+impl Program {
+    /// The dataflow plan of the program.
+    pub fn plan(&self) -> Plan {
+        let mut builder = PlanBuilder {
+            program: self,
+            plan: Plan::default(),
+            relations: FnvHashMap::default(),
+            arrangements: FnvHashMap::default(),
+        };
+
+        // Create all relations first, since rules can refer to delayed
+        // relations and relations later in the same SCC.
+        for node in self.nodes.iter() {
+            match node {
+                ProgNode::Rel { rel } => builder.relation(rel, false),
+                ProgNode::Apply { .. } => {
+                    builder.node(PlanNodeKind::Transformer, "transformer");
+                }
+                ProgNode::SCC { rels } => {
+                    for rel in rels.iter() {
+                        builder.relation(&rel.rel, true);
+                    }
+                }
+            }
+        }
+        for drel in self.delayed_rels.iter() {
+            let name = self.get_delayed_relation_name(drel.id).unwrap_or_default();
+            let node = builder.node(PlanNodeKind::DelayedRelation { relid: drel.id }, &name);
+            builder.relations.insert(drel.id, node);
+            let base = builder.relation_node(drel.rel_id);
+            builder.edge(base, node);
+        }
+
+        for rel in self.nodes.iter().flat_map(Self::node_relations) {
+            builder.rules(rel);
+        }
+        builder.plan
+    }
+}
+
+#[test]
+fn test_quote() {
+    assert_eq!(quote("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+}
@@ -0,0 +1,195 @@
+//! Recovering from panics in user functions.
+//!
+//! User functions (`MapFunc`, `JoinFunc`, `AggFunc`, etc.) are invoked by
+//! dataflow operators running inside timely worker threads.  A panic in one
+//! of them used to take down the worker, leaving the program in an undefined
+//! state.  Instead, operators call user functions via `guard()`, which
+//! catches the panic, records it in a per-worker slot, and makes the operator
+//! produce no output for the offending value.  The worker reports the
+//! recorded panic in response to the next `Flush` command, and
+//! `RunningProgram` poisons the current transaction: it cannot be committed,
+//! but it can be rolled back, after which the program can be used normally.
+//! Other errors detected by operators, such as weight overflows (see
+//! `overflow`), are reported the same way.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::rc::Rc;
+
+use crate::program::Weight;
+
+/// Errors reported by the DDlog runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DDlogError {
+    /// A user function panicked while evaluating a rule.
+    EvaluationPanic {
+        /// Description of the rule or operator that invoked the function.
+        rule: String,
+        /// The panic message.
+        message: String,
+    },
+    /// The multiplicity of a fact does not fit into a `Weight`.
+    WeightOverflow {
+        relation: String,
+        /// The fact whose multiplicity overflows.
+        value: String,
+    },
+    /// The weight of a change to an output relation does not fit into the
+    /// `isize` weights passed to output callbacks (only possible with
+    /// 128-bit weights).
+    OutputWeightOverflow {
+        relation: String,
+        /// The changed fact.
+        value: String,
+    },
+}
+
+impl fmt::Display for DDlogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DDlogError::EvaluationPanic { rule, message } => {
+                write!(f, "panic while evaluating '{}': {}", rule, message)
+            }
+            DDlogError::WeightOverflow { relation, value } => write!(
+                f,
+                "multiplicity of {} in relation {} overflows {}-bit weights",
+                value,
+                relation,
+                mem::size_of::<Weight>() * 8
+            ),
+            DDlogError::OutputWeightOverflow { relation, value } => write!(
+                f,
+                "weight of the change to {} in relation {} does not fit into {}-bit output weights",
+                value,
+                relation,
+                mem::size_of::<isize>() * 8
+            ),
+        }
+    }
+}
+
+impl Error for DDlogError {}
+
+impl From<DDlogError> for String {
+    fn from(e: DDlogError) -> Self {
+        e.to_string()
+    }
+}
+
+thread_local! {
+    /// The first panic caught by the current worker since the last call to
+    /// `take_panic()`.
+    static PANIC: RefCell<Option<DDlogError>> = RefCell::new(None);
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// Invoke `f` on behalf of `rule`.  If `f` panics, record the panic and
+/// return `fallback` instead.
+pub(crate) fn guard<R, F>(rule: &str, fallback: R, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => res,
+        Err(payload) => {
+            record(DDlogError::EvaluationPanic {
+                rule: rule.to_string(),
+                message: panic_message(&*payload),
+            });
+            fallback
+        }
+    }
+}
+
+/// Iterator over the outputs of a `FlatMapFunc` invoked on behalf of a rule.
+/// If advancing the iterator panics, the panic is recorded and the
+/// iteration ends.
+pub(crate) struct GuardedIter<I> {
+    rule: Rc<str>,
+    iter: Option<I>,
+}
+
+/// Iterate over `iter` on behalf of `rule`.
+pub(crate) fn guard_iter<I: Iterator>(rule: &Rc<str>, iter: I) -> GuardedIter<I> {
+    GuardedIter {
+        rule: rule.clone(),
+        iter: Some(iter),
+    }
+}
+
+impl<I: Iterator> Iterator for GuardedIter<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let iter = self.iter.as_mut()?;
+        match guard(&self.rule, None, || Some(iter.next())) {
+            Some(next) => next,
+            None => {
+                self.iter = None;
+                None
+            }
+        }
+    }
+}
+
+/// Record an error detected by an operator of the current worker, unless
+/// the worker has already recorded one.
+pub(crate) fn record(e: DDlogError) {
+    PANIC.with(|panic| {
+        panic.borrow_mut().get_or_insert(e);
+    });
+}
+
+/// Returns and clears the panic recorded by the current worker, if any.
+pub(crate) fn take_panic() -> Option<DDlogError> {
+    PANIC.with(|panic| panic.borrow_mut().take())
+}
+
+#[test]
+fn test_guard() {
+    assert_eq!(guard("rule1", 0, || 1), 1);
+    assert_eq!(take_panic(), None);
+
+    assert_eq!(guard("rule1", 0, || panic!("first")), 0);
+    assert_eq!(guard("rule2", 0, || panic!("second {}", 2)), 0);
+    assert_eq!(
+        take_panic(),
+        Some(DDlogError::EvaluationPanic {
+            rule: "rule1".to_string(),
+            message: "first".to_string()
+        })
+    );
+    assert_eq!(take_panic(), None);
+}
+
+#[test]
+fn test_guard_iter() {
+    let rule: Rc<str> = Rc::from("rule1");
+    let values: Vec<u32> = guard_iter(&rule, 1..4).collect();
+    assert_eq!(values, vec![1, 2, 3]);
+    assert_eq!(take_panic(), None);
+
+    let iter = (1..4).map(|x| if x == 2 { panic!("two") } else { x });
+    let values: Vec<u32> = guard_iter(&rule, iter).collect();
+    assert_eq!(values, vec![1]);
+    assert_eq!(
+        take_panic(),
+        Some(DDlogError::EvaluationPanic {
+            rule: "rule1".to_string(),
+            message: "two".to_string()
+        })
+    );
+}
@@ -0,0 +1,146 @@
+//! Tracking how far the dataflow has processed its inputs.
+//!
+//! Every committed transaction closes one epoch (timestamp) of the input
+//! relations.  Workers report the frontier of their dataflow probe, i.e.,
+//! the earliest timestamp they may still produce outputs for, after every
+//! scheduling step.  Once the frontier of all workers has passed the last
+//! closed epoch, the dataflow is quiescent: all outputs reflect all inputs
+//! submitted so far.
+//!
+//! `Progress` is shared by `RunningProgram` and its workers and can be
+//! queried without locking the program, e.g., from an orchestration thread
+//! while a transaction is being committed.
+
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::program::TS;
+
+/// Progress of the dataflow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frontier {
+    /// All input timestamps below this one are closed: no more updates will
+    /// be submitted at these timestamps.
+    pub submitted: TS,
+    /// All workers have finished processing timestamps below this one.
+    pub processed: TS,
+}
+
+impl Frontier {
+    /// Returns `true` if all submitted inputs have been fully processed.
+    pub fn is_quiescent(&self) -> bool {
+        self.processed >= self.submitted
+    }
+}
+
+#[derive(Debug)]
+struct ProgressState {
+    submitted: TS,
+    /// The frontier of each worker's probe.
+    workers: Vec<TS>,
+}
+
+/// Progress tracker shared by a running program and its workers.
+#[derive(Debug)]
+pub struct Progress {
+    state: Mutex<ProgressState>,
+    changed: Condvar,
+}
+
+impl Progress {
+    pub(crate) fn new(workers: usize) -> Self {
+        Self {
+            state: Mutex::new(ProgressState {
+                submitted: 0,
+                workers: vec![0; workers],
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Record that all input timestamps below `ts` are closed.
+    pub(crate) fn submit(&self, ts: TS) {
+        let mut state = self.state.lock().unwrap();
+        if ts > state.submitted {
+            state.submitted = ts;
+            self.changed.notify_all();
+        }
+    }
+
+    /// Record the frontier of worker `worker`; `None` if the worker's
+    /// dataflow has terminated.
+    pub(crate) fn report(&self, worker: usize, frontier: Option<TS>) {
+        let frontier = frontier.unwrap_or(TS::max_value());
+        let mut state = self.state.lock().unwrap();
+        if state.workers[worker] != frontier {
+            state.workers[worker] = frontier;
+            self.changed.notify_all();
+        }
+    }
+
+    fn frontier_of(state: &ProgressState) -> Frontier {
+        Frontier {
+            submitted: state.submitted,
+            processed: state
+                .workers
+                .iter()
+                .copied()
+                .min()
+                .unwrap_or(TS::max_value()),
+        }
+    }
+
+    /// Current progress of the dataflow.
+    pub fn frontier(&self) -> Frontier {
+        Self::frontier_of(&self.state.lock().unwrap())
+    }
+
+    /// Block until all inputs submitted so far have been processed or
+    /// `timeout` expires.  Returns the frontier at that point; use
+    /// `Frontier::is_quiescent()` to distinguish the two cases.
+    pub fn await_quiescence(&self, timeout: Duration) -> Frontier {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let frontier = Self::frontier_of(&state);
+            let now = Instant::now();
+            if frontier.is_quiescent() || now >= deadline {
+                return frontier;
+            }
+            state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+}
+
+#[test]
+fn test_progress() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let progress = Arc::new(Progress::new(2));
+    progress.submit(1);
+    assert_eq!(
+        progress.frontier(),
+        Frontier {
+            submitted: 1,
+            processed: 0
+        }
+    );
+    progress.report(0, Some(1));
+    assert!(!progress.frontier().is_quiescent());
+    assert!(!progress
+        .await_quiescence(Duration::from_millis(10))
+        .is_quiescent());
+
+    let worker = {
+        let progress = progress.clone();
+        thread::spawn(move || progress.report(1, Some(2)))
+    };
+    assert!(progress
+        .await_quiescence(Duration::from_secs(60))
+        .is_quiescent());
+    worker.join().unwrap();
+
+    progress.report(0, None);
+    assert_eq!(progress.frontier().processed, 2);
+}
@@ -0,0 +1,212 @@
+//! Finding and sharing duplicate arrangements.
+//!
+//! Every arrangement of a relation keeps its own indexed copy of the
+//! relation, so arrangements that compute the same thing waste memory.  Two
+//! arrangements of the same relation are _duplicates_ if they are built by
+//! the same function, and _near-duplicates_ if they arrange the relation by
+//! the same pattern using different functions, e.g., because the pattern
+//! occurs in rules compiled separately.
+//!
+//! `Program::arrangement_report()` lists both kinds.  Duplicates can be
+//! shared automatically by enabling `Config::share_duplicate_arrangements`.
+//! Near-duplicates, whose equivalence the runtime cannot check, are only
+//! shared when listed in `Program::shared_arrangements`.  A shared
+//! arrangement is built once and used in place of all arrangements that
+//! share it.
+
+use std::fmt;
+
+use fnv::FnvHashSet;
+
+use crate::program::{ArrId, Arrangement, Program, RelId};
+
+/// A pair of arrangements of the same relation that may be shared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateArrangement {
+    pub relid: RelId,
+    pub relation: String,
+    /// The arrangement that `duplicate` can share.
+    pub arrangement: ArrId,
+    pub duplicate: ArrId,
+    /// The arrangements are built by the same function, as opposed to
+    /// merely arranging the relation by the same pattern.
+    pub exact: bool,
+    /// `duplicate` shares `arrangement` in the running program.
+    pub shared: bool,
+}
+
+impl fmt::Display for DuplicateArrangement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: arrangement {} {} arrangement {}{}",
+            self.relation,
+            self.duplicate.1,
+            if self.exact {
+                "duplicates"
+            } else {
+                "nearly duplicates"
+            },
+            self.arrangement.1,
+            if self.shared { " (shared)" } else { "" }
+        )
+    }
+}
+
+/// Arrangement name without the trailing comment describing its use.
+fn pattern(name: &str) -> &str {
+    match name.rfind("/*") {
+        Some(i) => name[..i].trim_end(),
+        None => name,
+    }
+}
+
+fn same_kind(arr1: &Arrangement, arr2: &Arrangement) -> bool {
+    matches!(
+        (arr1, arr2),
+        (Arrangement::Map { .. }, Arrangement::Map { .. })
+            | (Arrangement::Set { .. }, Arrangement::Set { .. })
+    )
+}
+
+fn same_function(arr1: &Arrangement, arr2: &Arrangement) -> bool {
+    match (arr1, arr2) {
+        (Arrangement::Map { afun: f1, .. }, Arrangement::Map { afun: f2, .. }) => {
+            *f1 as usize == *f2 as usize
+        }
+        (
+            Arrangement::Set {
+                fmfun: f1,
+                distinct: d1,
+                ..
+            },
+            Arrangement::Set {
+                fmfun: f2,
+                distinct: d2,
+                ..
+            },
+        ) => *f1 as usize == *f2 as usize && d1 == d2,
+        _ => false,
+    }
+}
+
+impl Program {
+    /// Duplicate and near-duplicate arrangements of the program.  Each
+    /// arrangement is reported at most once, as a duplicate of the first
+    /// arrangement of its relation it duplicates.
+    pub fn arrangement_report(&self) -> Vec<DuplicateArrangement> {
+        let mut report = Vec::new();
+        for rel in self.nodes.iter().flat_map(Self::node_relations) {
+            for (dup, arr2) in rel.arrangements.iter().enumerate() {
+                let previous = &rel.arrangements[..dup];
+                let found = previous
+                    .iter()
+                    .position(|arr1| same_function(arr1, arr2))
+                    .map(|arr| (arr, true))
+                    .or_else(|| {
+                        previous
+                            .iter()
+                            .position(|arr1| {
+                                same_kind(arr1, arr2)
+                                    && pattern(arr1.name()) == pattern(arr2.name())
+                            })
+                            .map(|arr| (arr, false))
+                    });
+                if let Some((arr, exact)) = found {
+                    let shared = self.shared_arrangement((rel.id, dup));
+                    report.push(DuplicateArrangement {
+                        relid: rel.id,
+                        relation: rel.name.to_string(),
+                        arrangement: shared.unwrap_or((rel.id, arr)),
+                        duplicate: (rel.id, dup),
+                        exact,
+                        shared: shared.is_some(),
+                    });
+                }
+            }
+        }
+        report
+    }
+
+    /// Validate `shared_arrangements` and, if `share_duplicates` is set, add
+    /// the exact duplicates found by `arrangement_report()`.
+    pub(super) fn arrangements_to_share(
+        &self,
+        share_duplicates: bool,
+    ) -> Result<Vec<(ArrId, ArrId)>, String> {
+        let mut shared = self.shared_arrangements.clone();
+        let mut duplicates: FnvHashSet<ArrId> = FnvHashSet::default();
+        for (dup, arr) in shared.iter() {
+            if dup.0 != arr.0 {
+                return Err(format!(
+                    "arrangement {:?} cannot share arrangement {:?} of a different relation",
+                    dup, arr
+                ));
+            }
+            let arrangements = self
+                .nodes
+                .iter()
+                .flat_map(Self::node_relations)
+                .find(|rel| rel.id == dup.0)
+                .map_or(&[][..], |rel| &rel.arrangements[..]);
+            match (arrangements.get(dup.1), arrangements.get(arr.1)) {
+                (Some(arr1), Some(arr2)) if same_kind(arr1, arr2) => {}
+                (Some(_), Some(_)) => {
+                    return Err(format!(
+                        "arrangements {:?} and {:?} are of different kinds",
+                        dup, arr
+                    ))
+                }
+                _ => return Err(format!("unknown arrangement {:?} or {:?}", dup, arr)),
+            }
+            if dup == arr || !duplicates.insert(*dup) {
+                return Err(format!("arrangement {:?} is shared more than once", dup));
+            }
+        }
+        if let Some((_, arr)) = shared.iter().find(|(_, arr)| duplicates.contains(arr)) {
+            return Err(format!(
+                "arrangement {:?} shares another arrangement and cannot be shared",
+                arr
+            ));
+        }
+
+        if share_duplicates {
+            for dup in self.arrangement_report() {
+                if dup.exact
+                    && !dup.shared
+                    && !duplicates.contains(&dup.arrangement)
+                    && !shared.iter().any(|(_, arr)| *arr == dup.duplicate)
+                {
+                    shared.push((dup.duplicate, dup.arrangement));
+                    duplicates.insert(dup.duplicate);
+                }
+            }
+        }
+        Ok(shared)
+    }
+
+    /// The arrangement shared by `arrid`, if any.
+    pub(super) fn shared_arrangement(&self, arrid: ArrId) -> Option<ArrId> {
+        self.shared_arrangements
+            .iter()
+            .find(|(dup, _)| *dup == arrid)
+            .map(|(_, arr)| *arr)
+    }
+
+    /// `arrid` and the arrangements that share it.
+    pub(super) fn arrangements_sharing(&self, arrid: ArrId) -> impl Iterator<Item = ArrId> + '_ {
+        std::iter::once(arrid).chain(
+            self.shared_arrangements
+                .iter()
+                .filter(move |(_, arr)| *arr == arrid)
+                .map(|(dup, _)| *dup),
+        )
+    }
+}
+
+#[test]
+fn test_pattern() {
+    assert_eq!(pattern("(T{.x=_0}: T) /*join*/"), "(T{.x=_0}: T)");
+    assert_eq!(pattern("(T{.x=_0}: T) /*antijoin*/"), "(T{.x=_0}: T)");
+    assert_eq!(pattern("arrange1.0"), "arrange1.0");
+}
@@ -0,0 +1,160 @@
+//! Per-relation statistics.
+//!
+//! Workers count the insertions and deletions produced by the relations of
+//! the program, so that relations that grow unexpectedly can be spotted
+//! without profiling the program.  Counts are weights of the updates as they
+//! leave the relation's dataflow.  Relations with a change callback are
+//! counted where their changes are passed to the callback.  Counting other
+//! relations, including ones that are neither inputs nor outputs, takes an
+//! extra operator per relation and is only done with
+//! `Config::relation_stats`; an update to such a relation may, rarely, be
+//! counted both as an insertion and as a deletion within the same
+//! transaction.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use fnv::FnvHashMap;
+
+use crate::program::{RelId, Weight};
+
+/// Statistics of a relation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelationStats {
+    /// The number of records currently in the relation.
+    pub size: u64,
+    /// The total weight of records inserted into the relation.
+    pub inserts: u64,
+    /// The total weight of records deleted from the relation.
+    pub deletes: u64,
+    /// The number of insertions and deletions made by the last
+    /// transaction.
+    pub last_commit_delta: u64,
+}
+
+impl fmt::Display for RelationStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "size: {}, inserts: {}, deletes: {}, last commit: {}",
+            self.size, self.inserts, self.deletes, self.last_commit_delta
+        )
+    }
+}
+
+/// Counters updated by workers.
+#[derive(Debug, Default)]
+pub(crate) struct RelationCounters {
+    inserts: AtomicU64,
+    deletes: AtomicU64,
+}
+
+impl RelationCounters {
+    pub(crate) fn record(&self, w: Weight) {
+        if w > 0 {
+            self.inserts.fetch_add(w as u64, Ordering::Relaxed);
+        } else {
+            self.deletes
+                .fetch_add((-(w as i64)) as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn load(&self) -> (u64, u64) {
+        (
+            self.inserts.load(Ordering::Relaxed),
+            self.deletes.load(Ordering::Relaxed),
+        )
+    }
+}
+
+pub(crate) type StatsCounters = FnvHashMap<RelId, Arc<RelationCounters>>;
+
+/// Statistics of all relations of a running program.
+#[derive(Debug)]
+pub(crate) struct Stats {
+    counters: StatsCounters,
+    /// Counts at the end of the last two transactions.
+    last_commit: FnvHashMap<RelId, (u64, u64)>,
+    previous_commit: FnvHashMap<RelId, (u64, u64)>,
+}
+
+impl Stats {
+    pub(crate) fn new<I>(relids: I) -> Self
+    where
+        I: IntoIterator<Item = RelId>,
+    {
+        Self {
+            counters: relids
+                .into_iter()
+                .map(|relid| (relid, Arc::new(RelationCounters::default())))
+                .collect(),
+            last_commit: FnvHashMap::default(),
+            previous_commit: FnvHashMap::default(),
+        }
+    }
+
+    /// Counters to hand to workers.
+    pub(crate) fn counters(&self) -> StatsCounters {
+        self.counters.clone()
+    }
+
+    /// Record the end of a transaction.
+    pub(crate) fn on_commit(&mut self) {
+        let counts = self
+            .counters
+            .iter()
+            .map(|(relid, counters)| (*relid, counters.load()))
+            .collect();
+        self.previous_commit = std::mem::replace(&mut self.last_commit, counts);
+    }
+
+    /// Statistics of `relid` as of the end of the last transaction.
+    pub(crate) fn get(&self, relid: RelId) -> Option<RelationStats> {
+        if !self.counters.contains_key(&relid) {
+            return None;
+        }
+        let count = |counts: &FnvHashMap<RelId, (u64, u64)>| {
+            counts.get(&relid).copied().unwrap_or_default()
+        };
+        let (inserts, deletes) = count(&self.last_commit);
+        let (prev_inserts, prev_deletes) = count(&self.previous_commit);
+        Some(RelationStats {
+            size: inserts.saturating_sub(deletes),
+            inserts,
+            deletes,
+            last_commit_delta: (inserts - prev_inserts) + (deletes - prev_deletes),
+        })
+    }
+
+    pub(crate) fn relids(&self) -> impl Iterator<Item = RelId> + '_ {
+        self.counters.keys().copied()
+    }
+}
+
+#[test]
+fn test_stats() {
+    let mut stats = Stats::new(vec![1, 2]);
+    let counters = stats.counters();
+    counters[&1].record(1);
+    counters[&1].record(2);
+    stats.on_commit();
+    counters[&1].record(-1);
+    stats.on_commit();
+
+    assert_eq!(
+        stats.get(1),
+        Some(RelationStats {
+            size: 2,
+            inserts: 3,
+            deletes: 1,
+            last_commit_delta: 1,
+        })
+    );
+    assert_eq!(stats.get(2), Some(RelationStats::default()));
+    assert_eq!(stats.get(3), None);
+}
@@ -0,0 +1,415 @@
+//! Static checks that a `Program` can be turned into a dataflow.
+//!
+//! The DDlog compiler only generates programs that pass these checks; they
+//! exist to report problems in programs constructed or modified by other
+//! means with a structured error instead of a panic inside a timely worker.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use fnv::{FnvHashMap, FnvHashSet};
+
+use crate::program::{
+    Dep, ProgNode, Program, RelId, Relation, Rule, XFormArrangement, XFormCollection,
+};
+
+/// The kind of problem detected by `Program::check_stratification()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StratificationErrorKind {
+    /// A rule refers to a relation that is not declared in the program.
+    UnknownRelation,
+    /// A rule refers to a relation that is computed by a later program node.
+    /// Program nodes must be ordered so that each node only depends on
+    /// nodes that precede it and on itself.
+    UseBeforeDefinition,
+    /// An input relation is part of a recursive component.
+    InputInRecursion,
+    /// A recursive rule negates a relation from its own recursive component.
+    NegationInRecursion,
+    /// A stream operator (`Differentiate` or `StreamXForm`) is used inside a
+    /// recursive component.
+    StreamInRecursion,
+}
+
+impl Display for StratificationErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let descr = match self {
+            StratificationErrorKind::UnknownRelation => "reference to unknown relation",
+            StratificationErrorKind::UseBeforeDefinition => "relation used before it is defined",
+            StratificationErrorKind::InputInRecursion => "input relation in recursive component",
+            StratificationErrorKind::NegationInRecursion => "negation inside recursive component",
+            StratificationErrorKind::StreamInRecursion => {
+                "stream operator inside recursive component"
+            }
+        };
+        f.write_str(descr)
+    }
+}
+
+/// Structured description of a program that cannot be stratified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StratificationError {
+    pub kind: StratificationErrorKind,
+    /// Relation whose definition is invalid.
+    pub relation: String,
+    /// Description of the offending rule, if the problem is caused by a rule.
+    pub rule: Option<String>,
+    /// The relation that `rule` refers to in a way that breaks stratification
+    /// (e.g., the negated relation), if known.
+    pub dependency: Option<String>,
+    /// Dependency cycle that contains `relation`, starting and ending with
+    /// `relation`.  Each relation in the cycle depends on the next one.
+    /// Empty if the problem does not involve a cycle.
+    pub cycle: Vec<String>,
+}
+
+impl Display for StratificationError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} in relation '{}'", self.kind, self.relation)?;
+        if let Some(rule) = &self.rule {
+            write!(f, ", rule '{}'", rule)?;
+        }
+        if let Some(dep) = &self.dependency {
+            write!(f, ", via relation '{}'", dep)?;
+        }
+        if !self.cycle.is_empty() {
+            write!(f, "; dependency cycle: {}", self.cycle.join(" -> "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for StratificationError {}
+
+impl From<StratificationError> for String {
+    fn from(e: StratificationError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Finds the first stratification violation in an arrangement transformation
+/// that is part of a rule in recursive component `scc`.
+fn check_xform_arrangement(
+    xform: &XFormArrangement,
+    scc: &FnvHashSet<RelId>,
+) -> Option<(StratificationErrorKind, Option<RelId>)> {
+    match xform {
+        XFormArrangement::FlatMap { next, .. }
+        | XFormArrangement::FilterMap { next, .. }
+        | XFormArrangement::Aggregate { next, .. }
+        | XFormArrangement::Join { next, .. }
+        | XFormArrangement::Semijoin { next, .. }
+        | XFormArrangement::StreamJoin { next, .. }
+        | XFormArrangement::StreamSemijoin { next, .. } => check_next(next, scc),
+        XFormArrangement::Antijoin {
+            arrangement, next, ..
+        } => {
+            if scc.contains(&arrangement.0) {
+                Some((
+                    StratificationErrorKind::NegationInRecursion,
+                    Some(arrangement.0),
+                ))
+            } else {
+                check_next(next, scc)
+            }
+        }
+    }
+}
+
+fn check_xform_collection(
+    xform: &XFormCollection,
+    scc: &FnvHashSet<RelId>,
+) -> Option<(StratificationErrorKind, Option<RelId>)> {
+    match xform {
+        XFormCollection::Arrange { next, .. } => check_xform_arrangement(next, scc),
+        XFormCollection::Differentiate { .. } | XFormCollection::StreamXForm { .. } => {
+            Some((StratificationErrorKind::StreamInRecursion, None))
+        }
+        XFormCollection::Map { next, .. }
+        | XFormCollection::FlatMap { next, .. }
+        | XFormCollection::Filter { next, .. }
+        | XFormCollection::FilterMap { next, .. }
+        | XFormCollection::Inspect { next, .. }
+        | XFormCollection::StreamJoin { next, .. }
+        | XFormCollection::StreamSemijoin { next, .. } => check_next(next, scc),
+    }
+}
+
+fn check_next(
+    next: &Option<XFormCollection>,
+    scc: &FnvHashSet<RelId>,
+) -> Option<(StratificationErrorKind, Option<RelId>)> {
+    next.as_ref().and_then(|n| check_xform_collection(n, scc))
+}
+
+fn check_recursive_rule(
+    rule: &Rule,
+    scc: &FnvHashSet<RelId>,
+) -> Option<(StratificationErrorKind, Option<RelId>)> {
+    match rule {
+        Rule::CollectionRule { xform, .. } => check_next(xform, scc),
+        Rule::ArrangementRule { xform, .. } => check_xform_arrangement(xform, scc),
+    }
+}
+
+impl Program {
+    /// Check that the program is correctly stratified, i.e., that its
+    /// nodes are listed in dependency order and that recursive components
+    /// do not contain input relations, stream operators, or negation of
+    /// relations from the same component.
+    ///
+    /// `Program::run()` performs this check before constructing the
+    /// dataflow and returns the error as a string.  Clients that construct
+    /// programs at runtime can call this method first to get a structured
+    /// description of the problem.
+    pub fn check_stratification(&self) -> Result<(), StratificationError> {
+        // Program node that defines each relation.
+        let mut rel_nodes: FnvHashMap<RelId, usize> = FnvHashMap::default();
+        let mut rels: FnvHashMap<RelId, &Relation> = FnvHashMap::default();
+        for (i, node) in self.nodes.iter().enumerate() {
+            for rel in Self::node_relations(node) {
+                rel_nodes.insert(rel.id, i);
+                rels.insert(rel.id, rel);
+            }
+        }
+        let delayed: FnvHashSet<RelId> = self.delayed_rels.iter().map(|d| d.id).collect();
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let scc: FnvHashSet<RelId> = match node {
+                ProgNode::SCC { rels } => rels.iter().map(|r| r.rel.id).collect(),
+                _ => FnvHashSet::default(),
+            };
+
+            for rel in Self::node_relations(node) {
+                if rel.input && !scc.is_empty() {
+                    return Err(StratificationError {
+                        kind: StratificationErrorKind::InputInRecursion,
+                        relation: rel.name().to_string(),
+                        rule: None,
+                        dependency: None,
+                        cycle: vec![],
+                    });
+                }
+
+                for rule in rel.rules.iter() {
+                    for dep in rule.dependencies() {
+                        let relid = dep.relid();
+                        if delayed.contains(&relid) {
+                            continue;
+                        }
+                        let kind = match rel_nodes.get(&relid) {
+                            None => StratificationErrorKind::UnknownRelation,
+                            Some(j) if *j > i => StratificationErrorKind::UseBeforeDefinition,
+                            _ => continue,
+                        };
+                        return Err(StratificationError {
+                            kind,
+                            relation: rel.name().to_string(),
+                            rule: Some(rule.description().to_string()),
+                            dependency: Some(
+                                rels.get(&relid)
+                                    .map(|r| r.name().to_string())
+                                    .unwrap_or_else(|| relid.to_string()),
+                            ),
+                            cycle: Self::dependency_cycle(&rels, rel.id, relid, None),
+                        });
+                    }
+
+                    if scc.is_empty() {
+                        continue;
+                    }
+                    if let Some((kind, relid)) = check_recursive_rule(rule, &scc) {
+                        return Err(StratificationError {
+                            kind,
+                            relation: rel.name().to_string(),
+                            rule: Some(rule.description().to_string()),
+                            dependency: relid.map(|relid| rels[&relid].name().to_string()),
+                            cycle: relid
+                                .map(|relid| {
+                                    Self::dependency_cycle(&rels, rel.id, relid, Some(&scc))
+                                })
+                                .unwrap_or_default(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn node_relations(node: &ProgNode) -> Vec<&Relation> {
+        match node {
+            ProgNode::Rel { rel } => vec![rel],
+            ProgNode::Apply { .. } => vec![],
+            ProgNode::SCC { rels } => rels.iter().map(|r| &r.rel).collect(),
+        }
+    }
+
+    /// Find a dependency path from `dep` back to `relid` (optionally
+    /// restricted to relations in `scope`) and return it as a cycle through
+    /// `relid`, or an empty vector if there is no such path.
+    fn dependency_cycle(
+        rels: &FnvHashMap<RelId, &Relation>,
+        relid: RelId,
+        dep: RelId,
+        scope: Option<&FnvHashSet<RelId>>,
+    ) -> Vec<String> {
+        let mut parents: FnvHashMap<RelId, RelId> = FnvHashMap::default();
+        let mut queue: VecDeque<RelId> = VecDeque::new();
+        queue.push_back(dep);
+        parents.insert(dep, dep);
+
+        while let Some(current) = queue.pop_front() {
+            if current == relid {
+                let mut path = vec![relid];
+                let mut n = relid;
+                while n != dep {
+                    n = parents[&n];
+                    path.push(n);
+                }
+                // `path` lists relations from `relid` back to `dep`, in
+                // reverse dependency order.
+                path.reverse();
+                path.insert(0, relid);
+                return path
+                    .into_iter()
+                    .map(|r| rels[&r].name().to_string())
+                    .collect();
+            }
+            let rel = match rels.get(&current) {
+                Some(rel) => rel,
+                None => continue,
+            };
+            for rule in rel.rules.iter() {
+                for next in rule.dependencies().iter().map(Dep::relid) {
+                    if scope.map(|s| s.contains(&next)).unwrap_or(true)
+                        && rels.contains_key(&next)
+                        && !parents.contains_key(&next)
+                    {
+                        parents.insert(next, current);
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::{Arrangement, CachingMode, RecursiveRelation};
+    use std::borrow::Cow;
+
+    fn relation(name: &'static str, id: RelId, input: bool, rules: Vec<Rule>) -> Relation {
+        Relation {
+            name: Cow::Borrowed(name),
+            input,
+            distinct: true,
+            caching_mode: CachingMode::Set,
+            key_func: None,
+            id,
+            rules,
+            arrangements: vec![Arrangement::Set {
+                name: Cow::Borrowed("set"),
+                fmfun: Some,
+                distinct: true,
+            }],
+            change_cb: None,
+        }
+    }
+
+    fn copy_rule(from: RelId) -> Rule {
+        Rule::CollectionRule {
+            description: Cow::Borrowed("copy"),
+            rel: from,
+            xform: None,
+        }
+    }
+
+    fn antijoin_rule(from: RelId, negated: RelId) -> Rule {
+        Rule::CollectionRule {
+            description: Cow::Borrowed("antijoin"),
+            rel: from,
+            xform: Some(XFormCollection::Arrange {
+                description: Cow::Borrowed("arrange"),
+                afun: |v| Some((v.clone(), v)),
+                next: Box::new(XFormArrangement::Antijoin {
+                    description: Cow::Borrowed("antijoin"),
+                    ffun: None,
+                    arrangement: (negated, 0),
+                    next: Box::new(None),
+                }),
+            }),
+        }
+    }
+
+    fn program(nodes: Vec<ProgNode>) -> Program {
+        Program {
+            nodes,
+            delayed_rels: vec![],
+            lazy_rels: vec![],
+            shared_arrangements: vec![],
+            columnar_rels: vec![],
+            init_data: vec![],
+        }
+    }
+
+    #[test]
+    fn stratified() {
+        let prog = program(vec![
+            ProgNode::Rel {
+                rel: relation("Edge", 0, true, vec![]),
+            },
+            ProgNode::Rel {
+                rel: relation("Path", 1, false, vec![antijoin_rule(0, 0)]),
+            },
+        ]);
+        assert_eq!(prog.check_stratification(), Ok(()));
+    }
+
+    #[test]
+    fn negation_in_recursion() {
+        let prog = program(vec![
+            ProgNode::Rel {
+                rel: relation("Edge", 0, true, vec![]),
+            },
+            ProgNode::SCC {
+                rels: vec![
+                    RecursiveRelation {
+                        rel: relation("Path", 1, false, vec![antijoin_rule(0, 2)]),
+                        distinct: true,
+                    },
+                    RecursiveRelation {
+                        rel: relation("Blocked", 2, false, vec![copy_rule(1)]),
+                        distinct: true,
+                    },
+                ],
+            },
+        ]);
+        let err = prog.check_stratification().unwrap_err();
+        assert_eq!(err.kind, StratificationErrorKind::NegationInRecursion);
+        assert_eq!(err.relation, "Path");
+        assert_eq!(err.rule.as_deref(), Some("antijoin"));
+        assert_eq!(err.dependency.as_deref(), Some("Blocked"));
+        assert_eq!(err.cycle, vec!["Path", "Blocked", "Path"]);
+    }
+
+    #[test]
+    fn use_before_definition() {
+        let prog = program(vec![
+            ProgNode::Rel {
+                rel: relation("Path", 0, false, vec![copy_rule(1)]),
+            },
+            ProgNode::Rel {
+                rel: relation("Edge", 1, true, vec![]),
+            },
+        ]);
+        let err = prog.check_stratification().unwrap_err();
+        assert_eq!(err.kind, StratificationErrorKind::UseBeforeDefinition);
+        assert_eq!(err.dependency.as_deref(), Some("Edge"));
+        assert!(err.cycle.is_empty());
+    }
+}
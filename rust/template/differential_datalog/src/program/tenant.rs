@@ -0,0 +1,279 @@
+//! Partitioning relation data among tenants.
+//!
+//! In multi-tenant mode (see `Config::multi_tenant`), every fact is tagged
+//! with the id of the tenant it belongs to: values stored in the dataflow
+//! are `Tenanted` wrappers around the values of the relation's type.
+//! Operators strip the tag before invoking user functions and add it back
+//! to their outputs, and the tag is part of every arrangement key, so facts
+//! of different tenants are never joined, aggregated or deduplicated
+//! together, and one dataflow serves all tenants in isolation.
+//!
+//! Constant facts of the program (`Program::init_data`) are added for each
+//! tenant the first time it updates the program.
+//!
+//! Operators are generic over a `TenantMode`, which is chosen when the
+//! dataflow is built, so that single-tenant programs do not pay for
+//! handling tags: with `SingleTenant`, rule functions are invoked directly
+//! on the values in the dataflow.  `MultiTenant` costs a type check and an
+//! allocation per value for untagging and retagging it; the `operators`
+//! benchmark in `rust/ddlog_benches` compares the two modes.
+
+use std::any::TypeId;
+use std::borrow::Cow;
+
+use serde::Serialize;
+
+use crate::{
+    ddval::{DDValConvert, DDValue},
+    program::{AggFunc, ArrangeFunc, FlatMapFunc, Update, Weight},
+    record::{IntoRecord, Mutator, Record},
+};
+
+/// Identifies a tenant of a multi-tenant program.
+pub type TenantId = u64;
+
+/// A value that belongs to a tenant.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub struct Tenanted {
+    pub tenant: TenantId,
+    pub value: DDValue,
+}
+
+impl IntoRecord for Tenanted {
+    fn into_record(self) -> Record {
+        Record::NamedStruct(
+            Cow::from("Tenanted"),
+            vec![
+                (Cow::from("tenant"), self.tenant.into_record()),
+                (Cow::from("value"), self.value.into_record()),
+            ],
+        )
+    }
+}
+
+/// Mutators modify the value, not the tag.
+impl Mutator<Tenanted> for Record {
+    fn mutate(&self, x: &mut Tenanted) -> Result<(), String> {
+        self.mutate(&mut x.value)
+    }
+}
+
+fn is_tenanted(v: &DDValue) -> bool {
+    v.type_id() == TypeId::of::<Tenanted>()
+}
+
+/// Tag `v` with `tenant`.
+pub fn tag(tenant: TenantId, v: DDValue) -> DDValue {
+    Tenanted { tenant, value: v }.into_ddvalue()
+}
+
+/// The tenant `v` belongs to, if it is tagged.
+pub fn tenant_of(v: &DDValue) -> Option<TenantId> {
+    untag_ref(v).0
+}
+
+/// Split `v` into its tenant and untagged value.
+pub fn untag(v: DDValue) -> (Option<TenantId>, DDValue) {
+    if is_tenanted(&v) {
+        let Tenanted { tenant, value } = Tenanted::from_ddvalue(v);
+        (Some(tenant), value)
+    } else {
+        (None, v)
+    }
+}
+
+/// Like `untag()`, but borrows the value.
+pub fn untag_ref(v: &DDValue) -> (Option<TenantId>, &DDValue) {
+    match Tenanted::try_from_ddvalue_ref(v) {
+        Some(Tenanted { tenant, value }) => (Some(*tenant), value),
+        None => (None, v),
+    }
+}
+
+/// Tag `v` with `tenant`, if any.
+pub fn retag(tenant: Option<TenantId>, v: DDValue) -> DDValue {
+    match tenant {
+        Some(tenant) => tag(tenant, v),
+        None => v,
+    }
+}
+
+/// Tag the values and keys in `update` with `tenant`.
+pub fn tag_update(tenant: TenantId, update: Update<DDValue>) -> Update<DDValue> {
+    match update {
+        Update::Insert { relid, v } => Update::Insert {
+            relid,
+            v: tag(tenant, v),
+        },
+        Update::InsertOrUpdate { relid, v } => Update::InsertOrUpdate {
+            relid,
+            v: tag(tenant, v),
+        },
+        Update::DeleteValue { relid, v } => Update::DeleteValue {
+            relid,
+            v: tag(tenant, v),
+        },
+        Update::DeleteKey { relid, k } => Update::DeleteKey {
+            relid,
+            k: tag(tenant, k),
+        },
+        Update::Modify { relid, k, m } => Update::Modify {
+            relid,
+            k: tag(tenant, k),
+            m,
+        },
+    }
+}
+
+/// The tenant on whose behalf `update` is issued, if its value or key is
+/// tagged.
+pub(crate) fn tenant_of_update(update: &Update<DDValue>) -> Option<TenantId> {
+    match update {
+        Update::Insert { v, .. }
+        | Update::InsertOrUpdate { v, .. }
+        | Update::DeleteValue { v, .. } => tenant_of(v),
+        Update::DeleteKey { k, .. } | Update::Modify { k, .. } => tenant_of(k),
+    }
+}
+
+/// Compute the primary key of a value.  The key is tagged with the tenant of
+/// the value.
+pub(crate) fn lift_key(v: &DDValue, f: impl FnOnce(&DDValue) -> DDValue) -> DDValue {
+    let (tenant, v) = untag_ref(v);
+    retag(tenant, f(v))
+}
+
+/// How dataflow operators pass values to user functions.  The mode is
+/// chosen once, when the dataflow is built: in single-tenant mode operators
+/// invoke user functions directly, and only in multi-tenant mode do they
+/// strip and restore tenant tags.
+pub(crate) trait TenantMode: 'static {
+    /// The value passed to user functions for `v`.
+    fn value(v: &DDValue) -> &DDValue;
+
+    /// Apply a function that maps a value to a value.
+    fn map(v: DDValue, f: impl FnOnce(DDValue) -> DDValue) -> DDValue;
+
+    /// Apply a function that maps a value to an optional value.
+    fn map_opt(v: DDValue, f: impl FnOnce(DDValue) -> Option<DDValue>) -> Option<DDValue>;
+
+    /// Apply a function that computes an optional value out of a borrowed
+    /// value.
+    fn map_ref(v: &DDValue, f: impl FnOnce(&DDValue) -> Option<DDValue>) -> Option<DDValue>;
+
+    /// Apply a `FlatMapFunc`.
+    fn flat_map(v: DDValue, f: FlatMapFunc) -> Option<Box<dyn Iterator<Item = DDValue>>>;
+
+    /// Apply an `ArrangeFunc`.
+    fn arrange(v: DDValue, f: ArrangeFunc) -> Option<(DDValue, DDValue)>;
+
+    /// Apply an `AggFunc`.
+    fn aggregate(key: &DDValue, src: &[(&DDValue, Weight)], f: AggFunc) -> Option<DDValue>;
+}
+
+/// Values are not tagged.
+pub(crate) struct SingleTenant;
+
+impl TenantMode for SingleTenant {
+    #[inline]
+    fn value(v: &DDValue) -> &DDValue {
+        v
+    }
+
+    #[inline]
+    fn map(v: DDValue, f: impl FnOnce(DDValue) -> DDValue) -> DDValue {
+        f(v)
+    }
+
+    #[inline]
+    fn map_opt(v: DDValue, f: impl FnOnce(DDValue) -> Option<DDValue>) -> Option<DDValue> {
+        f(v)
+    }
+
+    #[inline]
+    fn map_ref(v: &DDValue, f: impl FnOnce(&DDValue) -> Option<DDValue>) -> Option<DDValue> {
+        f(v)
+    }
+
+    #[inline]
+    fn flat_map(v: DDValue, f: FlatMapFunc) -> Option<Box<dyn Iterator<Item = DDValue>>> {
+        f(v)
+    }
+
+    #[inline]
+    fn arrange(v: DDValue, f: ArrangeFunc) -> Option<(DDValue, DDValue)> {
+        f(v)
+    }
+
+    #[inline]
+    fn aggregate(key: &DDValue, src: &[(&DDValue, Weight)], f: AggFunc) -> Option<DDValue> {
+        f(key, src)
+    }
+}
+
+/// Values are `Tenanted`; user functions see the untagged values, and
+/// their outputs are tagged with the tenant of their inputs.
+pub(crate) struct MultiTenant;
+
+impl TenantMode for MultiTenant {
+    fn value(v: &DDValue) -> &DDValue {
+        untag_ref(v).1
+    }
+
+    fn map(v: DDValue, f: impl FnOnce(DDValue) -> DDValue) -> DDValue {
+        let (tenant, v) = untag(v);
+        retag(tenant, f(v))
+    }
+
+    fn map_opt(v: DDValue, f: impl FnOnce(DDValue) -> Option<DDValue>) -> Option<DDValue> {
+        let (tenant, v) = untag(v);
+        f(v).map(|v| retag(tenant, v))
+    }
+
+    fn map_ref(v: &DDValue, f: impl FnOnce(&DDValue) -> Option<DDValue>) -> Option<DDValue> {
+        let (tenant, v) = untag_ref(v);
+        f(v).map(|v| retag(tenant, v))
+    }
+
+    fn flat_map(v: DDValue, f: FlatMapFunc) -> Option<Box<dyn Iterator<Item = DDValue>>> {
+        let (tenant, v) = untag(v);
+        f(v).map(|iter| {
+            Box::new(iter.map(move |v| retag(tenant, v))) as Box<dyn Iterator<Item = DDValue>>
+        })
+    }
+
+    /// Both the key and the value are tagged.
+    fn arrange(v: DDValue, f: ArrangeFunc) -> Option<(DDValue, DDValue)> {
+        let (tenant, v) = untag(v);
+        f(v).map(|(k, v)| (retag(tenant, k), retag(tenant, v)))
+    }
+
+    /// The key and values of a group belong to the same tenant.
+    fn aggregate(key: &DDValue, src: &[(&DDValue, Weight)], f: AggFunc) -> Option<DDValue> {
+        match untag_ref(key) {
+            (None, _) => f(key, src),
+            (tenant, key) => {
+                let src: Vec<(&DDValue, Weight)> =
+                    src.iter().map(|(v, w)| (untag_ref(v).1, *w)).collect();
+                f(key, &src).map(|v| retag(tenant, v))
+            }
+        }
+    }
+}
+
+#[test]
+fn test_tenanted() {
+    let v = 5u64.into_ddvalue();
+    assert_eq!(untag(v.clone()), (None, v.clone()));
+    assert_eq!(tenant_of(&v), None);
+
+    let t = tag(7, v.clone());
+    assert_eq!(tenant_of(&t), Some(7));
+    assert_eq!(untag_ref(&t), (Some(7), &v));
+    assert_ne!(t, tag(8, v.clone()));
+
+    let double = |v: DDValue| (u64::from_ddvalue(v) * 2).into_ddvalue();
+    assert_eq!(MultiTenant::map(t, double), tag(7, 10u64.into_ddvalue()));
+    assert_eq!(MultiTenant::map(v.clone(), double), 10u64.into_ddvalue());
+    assert_eq!(SingleTenant::map(v, double), 10u64.into_ddvalue());
+}
@@ -0,0 +1,196 @@
+//! Querying index arrangements as of past commits.
+//!
+//! Committed transactions are numbered from 1 (see
+//! `RunningProgram::commit_number()`).  An arrangement with time travel
+//! enabled (see `RunningProgram::set_time_travel()`) retains the history of
+//! its last `commits` commits and can be queried as it was right after any
+//! of them with `RunningProgram::query_arrangement_as_of()`, e.g., to find
+//! out what an output relation contained a few commits ago while chasing a
+//! regression.
+//!
+//! History is retained by a compaction lag (see `compaction`) that the
+//! runtime adjusts after every commit to span the retained commits; it
+//! replaces any lag configured for the arrangement with
+//! `set_compaction_lag()`.  History discarded by `compact_now()`, or by a
+//! compaction policy while the arrangement is not queried, can no longer be
+//! queried.
+//!
+//! Commits are numbered when they are made, but deferred commits (see
+//! `Config::commit_latency_budget`) only reach arrangements once a later
+//! flush propagates them through the dataflow, in the same epoch as the
+//! commits flushed with them.  Time travel therefore keeps the commit
+//! numbers apart from the epochs: a query as of a deferred commit, which
+//! flushes it first, returns the state after all commits propagated in its
+//! epoch.
+
+use std::collections::VecDeque;
+
+use fnv::FnvHashMap;
+
+use crate::program::{ArrId, TS};
+
+#[derive(Debug, Default)]
+pub(crate) struct TimeTravel {
+    /// Number of the last commit, `0` before the first commit.
+    last_commit: u64,
+    /// Number of the last commit propagated through the dataflow.
+    last_propagated: u64,
+    /// The epochs the most recent commits were propagated in, oldest first:
+    /// the number of the first commit propagated in the epoch and the
+    /// timestamp of the program right after it; values with earlier
+    /// timestamps make up the state after the last commit of the epoch.
+    /// Enough epochs are kept for the arrangement that retains the most
+    /// commits.
+    epochs: VecDeque<(u64, TS)>,
+    /// Number of commits retained by each arrangement with time travel.
+    retained: FnvHashMap<ArrId, usize>,
+}
+
+impl TimeTravel {
+    pub(crate) fn last_commit(&self) -> u64 {
+        self.last_commit
+    }
+
+    /// Returns `true` if commits have been made since the dataflow was last
+    /// flushed.
+    pub(crate) fn has_unpropagated(&self) -> bool {
+        self.last_propagated < self.last_commit
+    }
+
+    /// Retain the last `commits` commits of `arrid`, or stop retaining its
+    /// history if `commits` is 0.
+    pub(crate) fn set_retained(&mut self, arrid: ArrId, commits: usize) {
+        if commits == 0 {
+            self.retained.remove(&arrid);
+        } else {
+            self.retained.insert(arrid, commits);
+        }
+    }
+
+    /// The compaction lag `arrid` needs at timestamp `now` to retain its
+    /// commits, or `None` if time travel is not enabled for it.
+    pub(crate) fn lag(&self, arrid: ArrId, now: TS) -> Option<TS> {
+        let commits = *self.retained.get(&arrid)?;
+        Some(self.lag_after(commits, 0, now))
+    }
+
+    /// The lag each arrangement with time travel needs to retain its
+    /// commits once `new` more commits have been made and all commits have
+    /// been propagated by a flush after which the program is at timestamp
+    /// `now`.
+    pub(crate) fn lags_after(&self, new: u64, now: TS) -> Vec<(ArrId, TS)> {
+        self.retained
+            .iter()
+            .map(|(arrid, commits)| (*arrid, self.lag_after(*commits, new, now)))
+            .collect()
+    }
+
+    fn lag_after(&self, commits: usize, new: u64, now: TS) -> TS {
+        // The commit whose state the oldest retained commit started from.
+        // Before `commits` commits have been made, all history is retained.
+        let start = match (self.last_commit + new).checked_sub(commits as u64) {
+            Some(start) if start > 0 => start,
+            _ => return now,
+        };
+        // Commits that have not been propagated yet will be propagated at
+        // `now`.
+        if start > self.last_propagated {
+            return 1;
+        }
+        match self.epoch_of(start) {
+            Some(ts) => now.saturating_sub(ts) + 1,
+            None => now,
+        }
+    }
+
+    /// Record a commit.  It is not retained until it has been propagated
+    /// (see `on_propagated()`).
+    pub(crate) fn on_commit(&mut self) {
+        self.last_commit += 1;
+    }
+
+    /// Record that all commits made so far have been propagated by a flush
+    /// after which the program is at timestamp `now`.
+    pub(crate) fn on_propagated(&mut self, now: TS) {
+        if !self.has_unpropagated() {
+            return;
+        }
+        match self.epochs.back() {
+            Some((_, ts)) if *ts == now => (),
+            _ => self.epochs.push_back((self.last_propagated + 1, now)),
+        }
+        self.last_propagated = self.last_commit;
+        // Keep the epoch of the commit that the oldest retained commit
+        // started from, and always the last epoch.
+        let retained = self.retained.values().max().copied().unwrap_or(0);
+        let start = self.last_commit.saturating_sub(retained as u64);
+        while self.epochs.len() > 1 && self.epochs[1].0 <= start {
+            self.epochs.pop_front();
+        }
+    }
+
+    /// The timestamp right after the epoch commit `commit` was propagated
+    /// in, if it is still retained.
+    fn epoch_of(&self, commit: u64) -> Option<TS> {
+        self.epochs
+            .iter()
+            .rev()
+            .find(|(first, _)| *first <= commit)
+            .map(|(_, ts)| *ts)
+    }
+
+    /// The timestamp right after commit `commit` was propagated: the state
+    /// of an arrangement after the commit consists of its values with
+    /// earlier timestamps.
+    pub(crate) fn timestamp_of(&self, commit: u64) -> Result<TS, String> {
+        if commit == 0 || commit > self.last_commit {
+            return Err(format!(
+                "commit {} has not been made (last commit: {})",
+                commit, self.last_commit
+            ));
+        }
+        if commit > self.last_propagated {
+            return Err(format!(
+                "commit {} has not been propagated through the dataflow yet",
+                commit
+            ));
+        }
+        self.epoch_of(commit)
+            .ok_or_else(|| format!("commit {} is no longer retained", commit))
+    }
+}
+
+#[test]
+fn test_deferred_commits() {
+    let mut time_travel = TimeTravel::default();
+    time_travel.set_retained((1, 0), 2);
+
+    // Commit 1 is propagated on its own, commits 2 and 3 together.
+    time_travel.on_commit();
+    time_travel.on_propagated(1);
+    time_travel.on_commit();
+    assert_eq!(time_travel.last_commit(), 2);
+    assert!(time_travel
+        .timestamp_of(2)
+        .unwrap_err()
+        .contains("has not been propagated"));
+    assert_eq!(time_travel.lags_after(1, 2), vec![((1, 0), 2)]);
+    time_travel.on_commit();
+    time_travel.on_propagated(2);
+    assert!(!time_travel.has_unpropagated());
+    assert_eq!(time_travel.timestamp_of(1), Ok(1));
+    assert_eq!(time_travel.timestamp_of(2), Ok(2));
+    assert_eq!(time_travel.timestamp_of(3), Ok(2));
+
+    // Retaining commits 3 and 4 takes the state after commit 2, which was
+    // propagated with commit 3.
+    time_travel.on_commit();
+    time_travel.on_propagated(3);
+    assert_eq!(time_travel.lag((1, 0), 3), Some(2));
+    assert!(time_travel
+        .timestamp_of(1)
+        .unwrap_err()
+        .contains("no longer retained"));
+    assert_eq!(time_travel.timestamp_of(2), Ok(2));
+    assert_eq!(time_travel.timestamp_of(4), Ok(3));
+}
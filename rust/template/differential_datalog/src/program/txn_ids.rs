@@ -0,0 +1,73 @@
+//! Remembering the ids of recently committed transactions.
+//!
+//! Upstream sources with at-least-once delivery may submit the same
+//! transaction more than once, e.g., after reconnecting to the program.
+//! Such sources attach a unique id to every transaction they commit (see
+//! `RunningProgram::transaction_commit_with_id()`).  The program remembers
+//! the most recent ids and turns a repeated commit into a no-op.
+
+use std::collections::VecDeque;
+
+use fnv::FnvHashSet;
+
+/// Bounded set of transaction ids; once full, the oldest id is forgotten
+/// to make room for a new one.
+#[derive(Debug, Clone)]
+pub(crate) struct TxnIds {
+    capacity: usize,
+    /// Ids in the order they were committed, oldest first.
+    order: VecDeque<String>,
+    ids: FnvHashSet<String>,
+}
+
+impl TxnIds {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            order: VecDeque::with_capacity(capacity),
+            ids: FnvHashSet::default(),
+        }
+    }
+
+    pub(crate) fn contains(&self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+
+    /// Remember `id`, forgetting the oldest id if the set is full.
+    pub(crate) fn insert(&mut self, id: &str) {
+        if self.capacity == 0 || self.ids.contains(id) {
+            return;
+        }
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.order.push_back(id.to_string());
+        self.ids.insert(id.to_string());
+    }
+
+    /// Remembered ids, oldest first.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &String> {
+        self.order.iter()
+    }
+}
+
+#[test]
+fn test_txn_ids() {
+    let mut ids = TxnIds::new(2);
+    ids.insert("a");
+    ids.insert("a");
+    ids.insert("b");
+    assert!(ids.contains("a"));
+
+    // Adding a third id evicts the oldest one.
+    ids.insert("c");
+    assert!(!ids.contains("a"));
+    assert_eq!(ids.iter().collect::<Vec<_>>(), vec!["b", "c"]);
+
+    // With zero capacity, nothing is remembered.
+    let mut ids = TxnIds::new(0);
+    ids.insert("a");
+    assert!(!ids.contains("a"));
+}
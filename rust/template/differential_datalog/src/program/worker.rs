@@ -8,7 +8,12 @@ use crate::{
     program::{
         arrange::{Arrangement, Arrangements},
         config::{Config, ProfilingKind},
-        ArrId, Dep, Msg, ProgNode, Program, Reply, Update, TS,
+        lazy::{gate_collection, GATE_BUCKETS},
+        overflow::{output_weight, WeightChecker},
+        poison::take_panic,
+        progress::Progress,
+        stats::StatsCounters,
+        ArrId, Dep, Msg, ProgNode, Program, Reply, Rule, Update, TS,
     },
     render::RenderContext,
     variable::Variable,
@@ -31,7 +36,8 @@ use differential_dataflow::{
 use dogsdogsdogs::operators::lookup_map;
 use fnv::{FnvBuildHasher, FnvHashMap};
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap},
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
     mem,
     net::TcpStream,
     ops::Deref,
@@ -57,13 +63,63 @@ struct SessionData {
     // Input session for the special `Enabled` relation (see detailed comment in
     // `session_dataflow()`).
     enabled_session: InputSession<TS, (), Weight>,
+    // Input sessions for the gates of lazy relations (see `lazy`).
+    gates: FnvHashMap<RelId, InputSession<TS, u64, Weight>>,
     // Traces for arrangements used in indexes.
     traces: BTreeMap<
         ArrId,
         TraceAgent<
-            Spine<DDValue, DDValue, u32, i32, Rc<OrdValBatch<DDValue, DDValue, u32, i32, u32>>>,
+            Spine<
+                DDValue,
+                DDValue,
+                u32,
+                Weight,
+                Rc<OrdValBatch<DDValue, DDValue, u32, Weight, u32>>,
+            >,
         >,
     >,
+    // Compaction lags of traces (see `compaction`).  Traces without a lag are
+    // compacted to the current timestamp.
+    compaction_lags: FnvHashMap<ArrId, TS>,
+    // Compaction frontier of each trace, which must never move backward.
+    compaction_frontiers: FnvHashMap<ArrId, TS>,
+}
+
+/// Output changes held back by a worker while a transaction is prepared or
+/// during a deadline commit (see `RunningProgram::transaction_prepare()` and
+/// `RunningProgram::transaction_commit_with_deadline()`).
+#[derive(Default)]
+struct OutputGate {
+    /// The timestamp whose output changes are held back.
+    held: Option<TS>,
+    /// Output changes at timestamps up to and including this one are dropped.
+    discard_through: Option<TS>,
+    /// The held back changes.
+    changes: Vec<(RelId, DDValue, Weight)>,
+}
+
+impl OutputGate {
+    /// Returns `true` if the output change `v` at `time` should be delivered
+    /// right away; otherwise holds it back or drops it.
+    fn admit(&mut self, relid: RelId, v: &DDValue, time: TS, w: Weight) -> bool {
+        if self
+            .discard_through
+            .map_or(false, |through| time <= through)
+        {
+            false
+        } else if self.held == Some(time) {
+            self.changes.push((relid, v.clone(), w));
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Stop holding back changes, returning the ones held back so far.
+    fn release(&mut self) -> Vec<(RelId, DDValue, Weight)> {
+        self.held = None;
+        mem::take(&mut self.changes)
+    }
 }
 
 type DelayedVarMap<S> = FnvHashMap<
@@ -90,6 +146,13 @@ pub struct DDlogWorker<'a> {
     request_receiver: Receiver<Msg>,
     /// The current worker's sender for sending messages
     reply_sender: Sender<Reply>,
+    /// Progress tracker to report the frontier of this worker to
+    progress: Arc<Progress>,
+    /// Counters of relation statistics
+    stats: StatsCounters,
+    /// Output changes held back while a transaction is prepared or during a
+    /// deadline commit
+    outputs: Rc<RefCell<OutputGate>>,
 }
 
 impl<'a> DDlogWorker<'a> {
@@ -102,6 +165,8 @@ impl<'a> DDlogWorker<'a> {
         profiling: Option<ProfilingData>,
         request_receivers: Arc<[Receiver<Msg>]>,
         reply_senders: Arc<[Sender<Reply>]>,
+        progress: Arc<Progress>,
+        stats: StatsCounters,
     ) -> Self {
         let worker_index = worker.index();
 
@@ -112,6 +177,9 @@ impl<'a> DDlogWorker<'a> {
             profiling,
             request_receiver: request_receivers[worker_index].clone(),
             reply_sender: reply_senders[worker_index].clone(),
+            progress,
+            stats,
+            outputs: Rc::default(),
         }
     }
 
@@ -134,11 +202,17 @@ impl<'a> DDlogWorker<'a> {
 
         self.ingest_initial_data(&mut session_data, &probe)?;
 
-        // Close session handles for non-input sessions.
+        // Close session handles for non-input sessions.  In multi-tenant mode,
+        // initial data is added for each new tenant, so sessions of relations
+        // that contain initial data must stay open.
+        let (program, multi_tenant) = (&self.program, self.config.multi_tenant);
         session_data.sessions = session_data
             .sessions
             .drain()
-            .filter(|&(relid, _)| self.program.get_relation(relid).input)
+            .filter(|&(relid, _)| {
+                program.get_relation(relid).input
+                    || (multi_tenant && program.init_data.iter().any(|(r, _)| *r == relid))
+            })
             .collect();
 
         // Keeps track of the last timestamp we've seen in a `Flush` message.
@@ -160,16 +234,113 @@ impl<'a> DDlogWorker<'a> {
                     Msg::Flush { advance_to } => {
                         self.advance(&mut session_data, advance_to);
                         self.flush(&mut session_data, &probe);
+                        self.report_progress(&probe);
                         timestamp = advance_to;
 
                         self.reply_sender
-                            .send(Reply::FlushAck)
+                            .send(Self::flush_reply())
                             .map_err(|e| format!("failed to send ACK: {}", e))?;
                     }
 
                     // Handle queries
                     Msg::Query(arrid, key) => {
-                        self.handle_query(&mut session_data.traces, arrid, key)?
+                        self.handle_query(&mut session_data.traces, arrid, key, None)?
+                    }
+
+                    // Values with times before `before` are only accurate if
+                    // the trace has not been compacted past them.
+                    Msg::QueryAsOf(arrid, key, before) => {
+                        let frontier = session_data
+                            .compaction_frontiers
+                            .get(&arrid)
+                            .copied()
+                            .unwrap_or(0);
+                        if before <= frontier {
+                            self.reply_sender.send(Reply::QueryCompacted).map_err(|e| {
+                                format!("handle_query: failed to send error response: {}", e)
+                            })?;
+                        } else {
+                            self.handle_query(&mut session_data.traces, arrid, key, Some(before))?
+                        }
+                    }
+
+                    Msg::Gate { relid, open } => {
+                        let session = session_data
+                            .gates
+                            .get_mut(&relid)
+                            .ok_or_else(|| format!("no gate found for relation ID {}", relid))?;
+                        for bucket in 0..GATE_BUCKETS {
+                            session.update_at(bucket, timestamp, if open { 1 } else { -1 });
+                        }
+                    }
+
+                    Msg::BulkLoad {
+                        relid,
+                        values,
+                        timestamp,
+                    } => {
+                        let session = session_data
+                            .sessions
+                            .get_mut(&relid)
+                            .ok_or_else(|| format!("no session found for relation ID {}", relid))?;
+                        for v in values {
+                            session.update_at(v, timestamp, 1);
+                        }
+                    }
+
+                    Msg::SetCompactionLag(arrid, lag) => {
+                        if lag == 0 {
+                            session_data.compaction_lags.remove(&arrid);
+                        } else {
+                            session_data.compaction_lags.insert(arrid, lag);
+                        }
+                        Self::compact_traces(&mut session_data, timestamp);
+                    }
+
+                    // Discard the history retained by traces.
+                    Msg::CompactNow(arrid) => {
+                        for (id, frontier) in session_data.compaction_frontiers.iter_mut() {
+                            if arrid.map_or(true, |a| a == *id) {
+                                *frontier = timestamp;
+                            }
+                        }
+                        Self::compact_traces(&mut session_data, timestamp);
+                    }
+
+                    Msg::HoldOutputs(timestamp) => self.outputs.borrow_mut().held = Some(timestamp),
+
+                    Msg::HeldOutputs(relid) => {
+                        let mut changes = BTreeMap::new();
+                        for (_, v, w) in self
+                            .outputs
+                            .borrow()
+                            .changes
+                            .iter()
+                            .filter(|(r, _, _)| *r == relid)
+                        {
+                            *changes.entry(v.clone()).or_insert(0) += *w;
+                        }
+                        self.reply_sender
+                            .send(Reply::QueryRes(Some(changes)))
+                            .map_err(|e| format!("failed to send held outputs: {}", e))?;
+                    }
+
+                    Msg::ReleaseOutputs => {
+                        let changes = self.outputs.borrow_mut().release();
+                        for (relid, v, w) in changes {
+                            if let Some(cb) = &self.program.get_relation(relid).change_cb {
+                                cb(relid, &v, w);
+                            }
+                        }
+                        self.reply_sender
+                            .send(Reply::FlushAck)
+                            .map_err(|e| format!("failed to send ACK: {}", e))?;
+                    }
+
+                    Msg::DiscardOutputs(through) => {
+                        let mut outputs = self.outputs.borrow_mut();
+                        outputs.release();
+                        outputs.discard_through = Some(through);
                     }
 
                     // On either the stop message or a channel disconnection we can shut down
@@ -185,8 +356,11 @@ impl<'a> DDlogWorker<'a> {
             // After each batch of commands received we step, if there's no timely work to be
             // done, our thread will be parked until it's re-awoken by a command.
             self.worker.step_or_park(None);
+            self.report_progress(&probe);
         }
 
+        self.report_progress(&probe);
+
         Ok(())
     }
 
@@ -243,7 +417,14 @@ impl<'a> DDlogWorker<'a> {
 
         // Only the leader introduces data into the input sessions
         if self.is_leader() {
-            for (relid, v) in self.program.init_data.iter() {
+            // In multi-tenant mode, initial data is tagged and added by
+            // `RunningProgram` for each tenant.
+            let init_data = if self.config.multi_tenant {
+                &[][..]
+            } else {
+                &self.program.init_data[..]
+            };
+            for (relid, v) in init_data.iter() {
                 session_data
                     .sessions
                     .get_mut(relid)
@@ -257,14 +438,30 @@ impl<'a> DDlogWorker<'a> {
         // All workers advance to timestamp 1 and flush their inputs
         self.advance(session_data, timestamp);
         self.flush(session_data, probe);
+        self.report_progress(probe);
 
         self.reply_sender
-            .send(Reply::FlushAck)
+            .send(Self::flush_reply())
             .map_err(|e| format!("failed to send ACK: {}", e))?;
 
         Ok(())
     }
 
+    /// Publish the frontier of the dataflow probe.
+    fn report_progress(&self, probe: &ProbeHandle<TS>) {
+        let frontier = probe.with_frontier(|frontier| frontier.first().copied());
+        self.progress.report(self.worker_index(), frontier);
+    }
+
+    /// Reply to a `Flush` command, reporting the first panic caught by this
+    /// worker's operators since the previous flush.
+    fn flush_reply() -> Reply {
+        match take_panic() {
+            Some(e) => Reply::Panic(e),
+            None => Reply::FlushAck,
+        }
+    }
+
     /// Empty the Enabled relation to help the dataflow terminate.
     fn disable(&mut self, session_data: &mut SessionData, timestamp: TS, probe: &ProbeHandle<TS>) {
         if self.is_leader() {
@@ -282,9 +479,28 @@ impl<'a> DDlogWorker<'a> {
             session_input.advance_to(timestamp);
         }
         session_data.enabled_session.advance_to(timestamp);
+        for gate in session_data.gates.values_mut() {
+            gate.advance_to(timestamp);
+        }
 
-        for trace in session_data.traces.values_mut() {
-            let e = [timestamp];
+        Self::compact_traces(session_data, timestamp);
+    }
+
+    /// Compact traces to the current timestamp minus their compaction lag.
+    fn compact_traces(session_data: &mut SessionData, timestamp: TS) {
+        let SessionData {
+            traces,
+            compaction_lags,
+            compaction_frontiers,
+            ..
+        } = session_data;
+
+        for (arrid, trace) in traces.iter_mut() {
+            let lag = compaction_lags.get(arrid).copied().unwrap_or(0);
+            let frontier = compaction_frontiers.entry(*arrid).or_insert(0);
+            *frontier = (*frontier).max(timestamp.saturating_sub(lag));
+
+            let e = [*frontier];
             let ac = AntichainRef::new(&e);
             trace.set_physical_compaction(ac);
             trace.set_logical_compaction(ac);
@@ -297,6 +513,9 @@ impl<'a> DDlogWorker<'a> {
             relation_input.flush();
         }
         session_data.enabled_session.flush();
+        for gate in session_data.gates.values_mut() {
+            gate.flush();
+        }
 
         if let Some(session) = session_data.sessions.values_mut().next() {
             while probe.less_than(session.time()) {
@@ -305,12 +524,14 @@ impl<'a> DDlogWorker<'a> {
         }
     }
 
-    /// Handle a query
+    /// Handle a query.  If `before` is specified, only values with earlier
+    /// times are taken into account.
     fn handle_query<Trace>(
         &self,
         traces: &mut BTreeMap<ArrId, Trace>,
         arrid: ArrId,
         key: Option<DDValue>,
+        before: Option<TS>,
     ) -> Result<(), String>
     where
         Trace: TraceReader<Key = DDValue, Val = DDValue, Time = TS, R = Weight>,
@@ -337,16 +558,18 @@ impl<'a> DDlogWorker<'a> {
         cursor.rewind_keys(&storage);
         cursor.rewind_vals(&storage);
 
-        let values = match key {
+        let mut values = BTreeMap::new();
+        match key {
             Some(k) => {
                 cursor.seek_key(&storage, &k);
-                if !cursor.key_valid(&storage) {
-                    BTreeSet::new()
-                } else {
-                    let mut values = BTreeSet::new();
+                if cursor.key_valid(&storage) {
                     while cursor.val_valid(&storage) && *cursor.key(&storage) == k {
                         let mut weight = 0;
-                        cursor.map_times(&storage, |_, &diff| weight += diff);
+                        cursor.map_times(&storage, |t, &diff| {
+                            if before.map_or(true, |before| *t < before) {
+                                weight += diff
+                            }
+                        });
 
                         //assert!(weight >= 0);
                         // FIXME: this will add the value to the set even if `weight < 0`,
@@ -354,26 +577,27 @@ impl<'a> DDlogWorker<'a> {
                         // A negative wait should only be possible if there are values with
                         // negative weights in one of the input multisets.
                         if weight != 0 {
-                            values.insert(cursor.val(&storage).clone());
+                            values.insert(cursor.val(&storage).clone(), weight);
                         }
 
                         cursor.step_val(&storage);
                     }
-
-                    values
                 }
             }
 
             None => {
-                let mut values = BTreeSet::new();
                 while cursor.key_valid(&storage) {
                     while cursor.val_valid(&storage) {
                         let mut weight = 0;
-                        cursor.map_times(&storage, |_, &diff| weight += diff);
+                        cursor.map_times(&storage, |t, &diff| {
+                            if before.map_or(true, |before| *t < before) {
+                                weight += diff
+                            }
+                        });
 
                         //assert!(weight >= 0);
                         if weight != 0 {
-                            values.insert(cursor.val(&storage).clone());
+                            *values.entry(cursor.val(&storage).clone()).or_insert(0) += weight;
                         }
 
                         cursor.step_val(&storage);
@@ -381,10 +605,8 @@ impl<'a> DDlogWorker<'a> {
 
                     cursor.step_key(&storage);
                 }
-
-                values
             }
-        };
+        }
 
         self.reply_sender
             .send(Reply::QueryRes(Some(values)))
@@ -476,12 +698,17 @@ impl<'a> DDlogWorker<'a> {
 
     fn session_dataflow(&mut self, mut probe: ProbeHandle<TS>) -> Result<SessionData, String> {
         let program = self.program.clone();
+        let stats = self.stats.clone();
         let render_context = RenderContext::new(self.config);
+        let check_weight_overflow = self.config.check_weight_overflow;
+        let outputs = self.outputs.clone();
 
         self.worker.dataflow::<TS, _, _>(
             |outer: &mut Child<Worker<Allocator>, TS>| -> Result<_, String> {
                 let mut sessions: FnvHashMap<RelId, InputSession<TS, DDValue, Weight>> =
                     FnvHashMap::default();
+                let mut gates: FnvHashMap<RelId, InputSession<TS, u64, Weight>> =
+                    FnvHashMap::default();
                 let mut collections: FnvHashMap<
                     RelId,
                     Collection<Child<Worker<Allocator>, TS>, DDValue, Weight>,
@@ -542,6 +769,7 @@ impl<'a> DDlogWorker<'a> {
                             &*program,
                             &render_context,
                             &mut sessions,
+                            &mut gates,
                             &mut collections,
                             &mut arrangements,
                             &delayed_vars,
@@ -580,9 +808,27 @@ impl<'a> DDlogWorker<'a> {
                 }
 
                 for (relid, collection) in collections {
+                    let counters = stats.get(&relid).cloned();
+
+                    if check_weight_overflow {
+                        let mut checker = WeightChecker::new(&program.get_relation(relid).name);
+                        let inspected =
+                            with_prof_context(&format!("check weights {}", relid), || {
+                                collection
+                                    .consolidate()
+                                    .inspect(move |x| checker.update(&x.0, x.2))
+                            });
+
+                        with_prof_context(&format!("probe {}", relid), || {
+                            inspected.probe_with(&mut probe)
+                        });
+                    }
+
                     // notify client about changes
                     if let Some(relation_callback) = &program.get_relation(relid).change_cb {
                         let relation_callback = relation_callback.clone();
+                        let relation_name = program.get_relation(relid).name.clone();
+                        let outputs = outputs.clone();
 
                         let consolidated =
                             with_prof_context(&format!("consolidate {}", relid), || {
@@ -592,10 +838,27 @@ impl<'a> DDlogWorker<'a> {
                         let inspected = with_prof_context(&format!("inspect {}", relid), || {
                             consolidated.inspect(move |x| {
                                 // assert!(x.2 == 1 || x.2 == -1, "x: {:?}", x);
-                                (relation_callback)(relid, &x.0, x.2)
+                                if let Some(counters) = &counters {
+                                    counters.record(x.2);
+                                }
+                                // Changes that output callbacks cannot represent
+                                // poison the transaction instead.
+                                if output_weight(&relation_name, &x.0, x.2).is_some()
+                                    && outputs.borrow_mut().admit(relid, &x.0, x.1, x.2)
+                                {
+                                    (relation_callback)(relid, &x.0, x.2)
+                                }
                             })
                         });
 
+                        with_prof_context(&format!("probe {}", relid), || {
+                            inspected.probe_with(&mut probe)
+                        });
+                    } else if let Some(counters) = counters {
+                        let inspected = with_prof_context(&format!("stats {}", relid), || {
+                            collection.inspect(move |x| counters.record(x.2))
+                        });
+
                         with_prof_context(&format!("probe {}", relid), || {
                             inspected.probe_with(&mut probe)
                         });
@@ -620,7 +883,10 @@ impl<'a> DDlogWorker<'a> {
                 Ok(SessionData {
                     sessions,
                     enabled_session,
+                    gates,
                     traces,
+                    compaction_lags: FnvHashMap::default(),
+                    compaction_frontiers: FnvHashMap::default(),
                 })
             },
         )
@@ -636,6 +902,7 @@ fn render_relation<S>(
     program: &Program,
     render_context: &RenderContext,
     sessions: &mut FnvHashMap<RelId, InputSession<TS, DDValue, Weight>>,
+    gates: &mut FnvHashMap<RelId, InputSession<TS, u64, Weight>>,
     collections: &mut FnvHashMap<RelId, Collection<S, DDValue, Weight>>,
     arrangements: &mut FnvHashMap<ArrId, Arrangement<S, Weight, TValAgent<TS>, TKeyAgent<TS>>>,
     delayed_vars: &DelayedVarMap<S>,
@@ -653,25 +920,58 @@ fn render_relation<S>(
         collection
     };
 
-    let entered_arrangements: FnvHashMap<_, ArrangementFlavor<_, TS>> = arrangements
+    let mut entered_arrangements: FnvHashMap<_, ArrangementFlavor<_, TS>> = arrangements
         .iter()
         .map(|(&arr_id, arr)| (arr_id, ArrangementFlavor::Local(arr.clone())))
         .collect();
 
+    // Rules of a lazy relation only see the collections and arrangements they
+    // start from while the relation's gate is open.
+    let gate = if program.lazy_rels.contains(&relation.id) {
+        let (session, gate) = scope.new_collection::<u64, Weight>();
+        gates.insert(relation.id, session);
+
+        for rule in relation.rules.iter() {
+            if let Rule::ArrangementRule { arr, .. } = rule {
+                let source = collections
+                    .get(&arr.0)
+                    .unwrap_or_else(|| panic!("render_relation: unknown relation {:?}", arr.0));
+                let gated = with_prof_context(&format!("gate {}", relation.name), || {
+                    gate_collection(source, &gate)
+                });
+                let arrangement = program.get_relation(arr.0).arrangements[arr.1]
+                    .build_arrangement_root(&render_context, &gated);
+                entered_arrangements.insert(*arr, ArrangementFlavor::Local(arrangement));
+            }
+        }
+        Some(gate)
+    } else {
+        None
+    };
+
     // apply rules
     // TODO: Regions for rules
     let rule_collections = relation.rules.iter().map(|rule| {
         let get_rule_collection = |relation_id| {
-            if let Some(collection) = collections.get(&relation_id) {
+            let collection = if let Some(collection) = collections.get(&relation_id) {
                 Some(collection.clone())
             } else {
                 delayed_vars
                     .get(&relation_id)
                     .map(|(_, _, collection)| collection.clone())
+            };
+            match &gate {
+                Some(gate) => collection.map(|collection| {
+                    with_prof_context(&format!("gate {}", relation.name), || {
+                        gate_collection(&collection, gate)
+                    })
+                }),
+                None => collection,
             }
         };
 
         program.mk_rule(
+            render_context.config.multi_tenant,
             rule,
             get_rule_collection,
             Arrangements {
@@ -696,13 +996,16 @@ fn render_relation<S>(
     // create arrangements
     // TODO: Arrangements have their own shebang, region them off too
     for (arr_id, arrangement) in relation.arrangements.iter().enumerate() {
-        with_prof_context(arrangement.name(), || {
-            arrangements.insert(
-                (relation.id, arr_id),
-                arrangement.build_arrangement_root(&render_context, &collection),
-            )
-        });
+        if program.shared_arrangement((relation.id, arr_id)).is_none() {
+            with_prof_context(arrangement.name(), || {
+                arrangements.insert(
+                    (relation.id, arr_id),
+                    arrangement.build_arrangement_root(&render_context, &collection),
+                )
+            });
+        }
     }
+    share_arrangements(program, arrangements);
 
     collections.insert(relation.id, collection);
 }
@@ -771,9 +1074,10 @@ fn render_scc<'a>(
         for rel in rels {
             for (i, arr) in rel.rel.arrangements.iter().enumerate() {
                 // check if arrangement is actually used inside this node
-                if program
-                    .arrangement_used_by_nodes((rel.rel.id, i))
-                    .any(|n| n == node_id)
+                if program.shared_arrangement((rel.rel.id, i)).is_none()
+                    && program
+                        .arrangement_used_by_nodes((rel.rel.id, i))
+                        .any(|n| n == node_id)
                 {
                     with_prof_context(&format!("local {}", arr.name()), || {
                         local_arrangements.insert(
@@ -784,6 +1088,7 @@ fn render_scc<'a>(
                 }
             }
         }
+        share_arrangements(program, &mut local_arrangements);
 
         let dependencies = Program::dependencies(rels.iter().map(|relation| &relation.rel));
 
@@ -829,6 +1134,7 @@ fn render_scc<'a>(
         for rel in rels {
             for rule in &rel.rel.rules {
                 let c = program.mk_rule(
+                    render_context.config.multi_tenant,
                     rule,
                     |rid| {
                         vars.get(&rid)
@@ -876,10 +1182,13 @@ fn render_scc<'a>(
     for rel in rels {
         for (i, arr) in rel.rel.arrangements.iter().enumerate() {
             // only if the arrangement is used outside of this node
-            if arr.queryable()
-                || program
-                    .arrangement_used_by_nodes((rel.rel.id, i))
-                    .any(|n| n != node_id)
+            if program.shared_arrangement((rel.rel.id, i)).is_none()
+                && (program
+                    .arrangements_sharing((rel.rel.id, i))
+                    .any(|arrid| rel.rel.arrangements[arrid.1].queryable())
+                    || program
+                        .arrangement_used_by_nodes((rel.rel.id, i))
+                        .any(|n| n != node_id))
             {
                 with_prof_context(
                     &format!("global {}", arr.name()),
@@ -897,10 +1206,23 @@ fn render_scc<'a>(
             }
         }
     }
+    share_arrangements(program, arrangements);
 
     Ok(())
 }
 
+/// Make arrangements that share another arrangement (see `sharing`) refer to
+/// it, once it has been built.
+fn share_arrangements<A: Clone>(program: &Program, arrangements: &mut FnvHashMap<ArrId, A>) {
+    for (dup, arr) in program.shared_arrangements.iter() {
+        if !arrangements.contains_key(dup) {
+            if let Some(arrangement) = arrangements.get(arr).cloned() {
+                arrangements.insert(*dup, arrangement);
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ProfilingData {
     /// Whether CPU profiling is enabled
@@ -0,0 +1,169 @@
+//! Canonical ordering of `Record`s.
+//!
+//! Relation dumps and callback batches are ordered by the `Ord`
+//! implementation of the value type, which is not always stable across runs:
+//! interned values, for instance, are ordered by their addresses.  The
+//! canonical ordering only depends on the structure of the value: constructors
+//! are ordered by name, strings lexicographically, numbers numerically, and
+//! compound values lexicographically by their components.  Sets and maps are
+//! compared as sorted sequences of elements (keys), so the order in which the
+//! type stores them does not matter.
+
+use super::{CollectionKind, Record};
+use std::cmp::Ordering;
+
+impl Record {
+    /// Compare two records in the canonical order.
+    pub fn canonical_cmp(&self, other: &Record) -> Ordering {
+        match (self, other) {
+            (Record::Bool(x), Record::Bool(y)) => x.cmp(y),
+            (Record::Int(x), Record::Int(y)) => x.cmp(y),
+            (Record::Float(x), Record::Float(y)) => x.cmp(y),
+            (Record::Double(x), Record::Double(y)) => x.cmp(y),
+            (Record::String(x), Record::String(y)) => x.cmp(y),
+            (Record::Serialized(f1, x), Record::Serialized(f2, y)) => {
+                f1.cmp(f2).then_with(|| x.cmp(y))
+            }
+            (Record::Tuple(xs), Record::Tuple(ys)) => cmp_seq(xs.iter(), ys.iter()),
+            (Record::Array(k1, xs), Record::Array(k2, ys)) => {
+                if is_unordered(*k1) || is_unordered(*k2) {
+                    cmp_seq(sorted(xs).into_iter(), sorted(ys).into_iter())
+                } else {
+                    cmp_seq(xs.iter(), ys.iter())
+                }
+            }
+            (x, y) if rank(x) == STRUCT_RANK && rank(y) == STRUCT_RANK => {
+                let (n1, xs) = struct_fields(x);
+                let (n2, ys) = struct_fields(y);
+                n1.cmp(n2)
+                    .then_with(|| cmp_seq(xs.into_iter(), ys.into_iter()))
+            }
+            _ => rank(self).cmp(&rank(other)),
+        }
+    }
+
+    /// Sort the elements of all sets and maps nested in the record in the
+    /// canonical order, so that printing the record produces the same output
+    /// regardless of how the value type orders them.
+    pub fn canonicalize(&mut self) {
+        match self {
+            Record::Tuple(xs) | Record::PosStruct(_, xs) => {
+                xs.iter_mut().for_each(Record::canonicalize)
+            }
+            Record::NamedStruct(_, fields) => fields.iter_mut().for_each(|(_, x)| x.canonicalize()),
+            Record::Array(kind, xs) => {
+                xs.iter_mut().for_each(Record::canonicalize);
+                if is_unordered(*kind) {
+                    xs.sort_by(Record::canonical_cmp);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/* The element order of sets and maps is determined by the `Ord`
+ * implementation of the element (key) type. */
+fn is_unordered(kind: CollectionKind) -> bool {
+    matches!(kind, CollectionKind::Set | CollectionKind::Map)
+}
+
+fn sorted(xs: &[Record]) -> Vec<&Record> {
+    let mut xs: Vec<&Record> = xs.iter().collect();
+    xs.sort_by(|x, y| x.canonical_cmp(y));
+    xs
+}
+
+fn cmp_seq<'a>(
+    mut xs: impl Iterator<Item = &'a Record>,
+    mut ys: impl Iterator<Item = &'a Record>,
+) -> Ordering {
+    loop {
+        match (xs.next(), ys.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => match x.canonical_cmp(y) {
+                Ordering::Equal => (),
+                ord => return ord,
+            },
+        }
+    }
+}
+
+fn struct_fields(rec: &Record) -> (&str, Vec<&Record>) {
+    match rec {
+        Record::PosStruct(n, xs) => (n, xs.iter().collect()),
+        Record::NamedStruct(n, fields) => (n, fields.iter().map(|(_, x)| x).collect()),
+        _ => unreachable!("struct_fields: not a struct"),
+    }
+}
+
+const STRUCT_RANK: u8 = 8;
+
+/* Order of records of different kinds. */
+fn rank(rec: &Record) -> u8 {
+    match rec {
+        Record::Bool(_) => 0,
+        Record::Int(_) => 1,
+        Record::Float(_) => 2,
+        Record::Double(_) => 3,
+        Record::String(_) => 4,
+        Record::Serialized(..) => 5,
+        Record::Tuple(_) => 6,
+        Record::Array(..) => 7,
+        Record::PosStruct(..) | Record::NamedStruct(..) => STRUCT_RANK,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn int(i: i32) -> Record {
+        Record::Int(i.into())
+    }
+
+    fn cons(name: &'static str, args: Vec<Record>) -> Record {
+        Record::PosStruct(Cow::Borrowed(name), args)
+    }
+
+    #[test]
+    fn constructors_by_name() {
+        let none = cons("ddlog_std::None", vec![]);
+        let some = cons("ddlog_std::Some", vec![int(0)]);
+        assert_eq!(none.canonical_cmp(&some), Ordering::Less);
+        assert_eq!(
+            cons("ddlog_std::Some", vec![int(2)])
+                .canonical_cmp(&cons("ddlog_std::Some", vec![int(10)])),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn sets_ignore_element_order() {
+        let s1 = Record::Array(CollectionKind::Set, vec![int(2), int(1)]);
+        let s2 = Record::Array(CollectionKind::Set, vec![int(1), int(2)]);
+        let v1 = Record::Array(CollectionKind::Vector, vec![int(2), int(1)]);
+        let v2 = Record::Array(CollectionKind::Vector, vec![int(1), int(2)]);
+        assert_eq!(s1.canonical_cmp(&s2), Ordering::Equal);
+        assert_eq!(v1.canonical_cmp(&v2), Ordering::Greater);
+    }
+
+    #[test]
+    fn canonicalize_nested_sets() {
+        let mut rec = cons(
+            "R",
+            vec![Record::Array(
+                CollectionKind::Set,
+                vec![
+                    Record::String("b".to_string()),
+                    Record::String("a".to_string()),
+                ],
+            )],
+        );
+        rec.canonicalize();
+        assert_eq!(rec.to_string(), r#"R{["a", "b"]}"#);
+    }
+}
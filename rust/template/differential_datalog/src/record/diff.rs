@@ -0,0 +1,323 @@
+//! Structural diff of `Record`s.
+//!
+//! `Record::diff()` compares two values field by field and reports the
+//! differences along with their paths, e.g., `.routes[1].next_hop`, so that
+//! test failures involving large values point at the fields that actually
+//! differ instead of printing both values in full.
+
+use super::{CollectionKind, Name, Record};
+use std::fmt;
+
+/// A single difference between two records, found by `Record::diff()`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PathDelta {
+    /// Path to the differing value relative to the root of the record: a
+    /// sequence of struct field names or positions (`.field`, `.0`), vector
+    /// indices (`[2]`), map keys (`[key]`), and set elements (`{elem}`).
+    /// Empty if the records differ at the top level.
+    pub path: String,
+    /// The value in the first record, or `None` if the value only exists in
+    /// the second record (e.g., an extra vector element or map entry).
+    pub expected: Option<Record>,
+    /// The value in the second record, or `None` if the value only exists in
+    /// the first record.
+    pub actual: Option<Record>,
+}
+
+impl fmt::Display for PathDelta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "<value>"
+        } else {
+            self.path.as_str()
+        };
+        write!(f, "{}: ", path)?;
+        match &self.expected {
+            Some(rec) => write!(f, "{}", rec)?,
+            None => write!(f, "<missing>")?,
+        }
+        write!(f, " -> ")?;
+        match &self.actual {
+            Some(rec) => write!(f, "{}", rec),
+            None => write!(f, "<missing>"),
+        }
+    }
+}
+
+impl Record {
+    /// Structural diff between `self` (the expected value) and `other` (the
+    /// actual value).  Returns an empty vector if the records are equal.
+    ///
+    /// Structs with the same constructor are compared field by field (by name
+    /// if both use named fields, by position otherwise), vectors element by
+    /// element, maps by key, and sets by membership.  Values that cannot be
+    /// compared structurally, e.g., structs with different constructors,
+    /// produce a single delta for the whole value.
+    pub fn diff(&self, other: &Record) -> Vec<PathDelta> {
+        let mut deltas = Vec::new();
+        diff_at(&mut String::new(), self, other, &mut deltas);
+        deltas
+    }
+}
+
+fn push_delta(
+    path: &str,
+    expected: Option<&Record>,
+    actual: Option<&Record>,
+    deltas: &mut Vec<PathDelta>,
+) {
+    deltas.push(PathDelta {
+        path: path.to_string(),
+        expected: expected.cloned(),
+        actual: actual.cloned(),
+    });
+}
+
+/* Compares `expected` and `actual` at `path`.  Path components are appended
+ * to `path` while descending into nested values and removed on the way
+ * back. */
+fn diff_at(path: &mut String, expected: &Record, actual: &Record, deltas: &mut Vec<PathDelta>) {
+    if expected == actual {
+        return;
+    }
+    match (expected, actual) {
+        (Record::Tuple(xs), Record::Tuple(ys)) if xs.len() == ys.len() => {
+            diff_positional(path, xs, ys, deltas)
+        }
+        (Record::PosStruct(n1, xs), Record::PosStruct(n2, ys))
+            if n1 == n2 && xs.len() == ys.len() =>
+        {
+            diff_positional(path, xs, ys, deltas)
+        }
+        (Record::NamedStruct(n1, xs), Record::NamedStruct(n2, ys)) if n1 == n2 => {
+            diff_named(path, xs, ys, deltas)
+        }
+        (Record::NamedStruct(n1, xs), Record::PosStruct(n2, ys))
+            if n1 == n2 && xs.len() == ys.len() =>
+        {
+            let xs: Vec<Record> = xs.iter().map(|(_, x)| x.clone()).collect();
+            diff_positional(path, &xs, ys, deltas)
+        }
+        (Record::PosStruct(n1, xs), Record::NamedStruct(n2, ys))
+            if n1 == n2 && xs.len() == ys.len() =>
+        {
+            let ys: Vec<Record> = ys.iter().map(|(_, y)| y.clone()).collect();
+            diff_positional(path, xs, &ys, deltas)
+        }
+        (Record::Array(CollectionKind::Set, xs), Record::Array(CollectionKind::Set, ys)) => {
+            diff_sets(path, xs, ys, deltas)
+        }
+        (Record::Array(CollectionKind::Map, xs), Record::Array(CollectionKind::Map, ys))
+            if is_map(xs) && is_map(ys) =>
+        {
+            diff_maps(path, xs, ys, deltas)
+        }
+        (Record::Array(k1, xs), Record::Array(k2, ys)) if k1 == k2 => {
+            diff_vectors(path, xs, ys, deltas)
+        }
+        _ => push_delta(path, Some(expected), Some(actual), deltas),
+    }
+}
+
+fn with_component<F>(path: &mut String, component: fmt::Arguments, f: F)
+where
+    F: FnOnce(&mut String),
+{
+    let len = path.len();
+    let _ = fmt::Write::write_fmt(path, component);
+    f(path);
+    path.truncate(len);
+}
+
+fn diff_positional(path: &mut String, xs: &[Record], ys: &[Record], deltas: &mut Vec<PathDelta>) {
+    for (i, (x, y)) in xs.iter().zip(ys.iter()).enumerate() {
+        with_component(path, format_args!(".{}", i), |path| {
+            diff_at(path, x, y, deltas)
+        });
+    }
+}
+
+fn diff_named(
+    path: &mut String,
+    xs: &[(Name, Record)],
+    ys: &[(Name, Record)],
+    deltas: &mut Vec<PathDelta>,
+) {
+    for (fname, x) in xs.iter() {
+        let y = ys.iter().find(|(n, _)| n == fname).map(|(_, y)| y);
+        with_component(path, format_args!(".{}", fname), |path| match y {
+            Some(y) => diff_at(path, x, y, deltas),
+            None => push_delta(path, Some(x), None, deltas),
+        });
+    }
+    for (fname, y) in ys.iter() {
+        if !xs.iter().any(|(n, _)| n == fname) {
+            with_component(path, format_args!(".{}", fname), |path| {
+                push_delta(path, None, Some(y), deltas)
+            });
+        }
+    }
+}
+
+fn diff_vectors(path: &mut String, xs: &[Record], ys: &[Record], deltas: &mut Vec<PathDelta>) {
+    for i in 0..xs.len().max(ys.len()) {
+        with_component(path, format_args!("[{}]", i), |path| {
+            match (xs.get(i), ys.get(i)) {
+                (Some(x), Some(y)) => diff_at(path, x, y, deltas),
+                (x, y) => push_delta(path, x, y, deltas),
+            }
+        });
+    }
+}
+
+fn diff_sets(path: &mut String, xs: &[Record], ys: &[Record], deltas: &mut Vec<PathDelta>) {
+    for x in xs.iter().filter(|x| !ys.contains(x)) {
+        with_component(path, format_args!("{{{}}}", x), |path| {
+            push_delta(path, Some(x), None, deltas)
+        });
+    }
+    for y in ys.iter().filter(|y| !xs.contains(y)) {
+        with_component(path, format_args!("{{{}}}", y), |path| {
+            push_delta(path, None, Some(y), deltas)
+        });
+    }
+}
+
+/* Maps are represented as arrays of key-value tuples. */
+fn is_map(entries: &[Record]) -> bool {
+    entries
+        .iter()
+        .all(|e| matches!(e, Record::Tuple(kv) if kv.len() == 2))
+}
+
+fn map_entry(entry: &Record) -> (&Record, &Record) {
+    match entry {
+        Record::Tuple(kv) => (&kv[0], &kv[1]),
+        _ => unreachable!("map_entry: not a key-value pair"),
+    }
+}
+
+fn diff_maps(path: &mut String, xs: &[Record], ys: &[Record], deltas: &mut Vec<PathDelta>) {
+    for (k, x) in xs.iter().map(map_entry) {
+        let y = ys
+            .iter()
+            .map(map_entry)
+            .find(|(k2, _)| *k2 == k)
+            .map(|(_, y)| y);
+        with_component(path, format_args!("[{}]", k), |path| match y {
+            Some(y) => diff_at(path, x, y, deltas),
+            None => push_delta(path, Some(x), None, deltas),
+        });
+    }
+    for (k, y) in ys.iter().map(map_entry) {
+        if !xs.iter().map(map_entry).any(|(k2, _)| k2 == k) {
+            with_component(path, format_args!("[{}]", k), |path| {
+                push_delta(path, None, Some(y), deltas)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn route(prefix: &str, next_hop: &str) -> Record {
+        Record::NamedStruct(
+            Cow::Borrowed("Route"),
+            vec![
+                (Cow::Borrowed("prefix"), Record::String(prefix.to_string())),
+                (
+                    Cow::Borrowed("next_hop"),
+                    Record::String(next_hop.to_string()),
+                ),
+            ],
+        )
+    }
+
+    fn routes(routes: Vec<Record>) -> Record {
+        Record::NamedStruct(
+            Cow::Borrowed("Config"),
+            vec![(
+                Cow::Borrowed("routes"),
+                Record::Array(CollectionKind::Vector, routes),
+            )],
+        )
+    }
+
+    #[test]
+    fn equal_records() {
+        let rec = route("10.0.0.0/8", "10.0.0.1");
+        assert_eq!(rec.diff(&rec.clone()), vec![]);
+    }
+
+    #[test]
+    fn nested_fields() {
+        let expected = routes(vec![
+            route("10.0.0.0/8", "10.0.0.1"),
+            route("0.0.0.0/0", "192.168.1.1"),
+        ]);
+        let actual = routes(vec![
+            route("10.0.0.0/8", "10.0.0.1"),
+            route("0.0.0.0/0", "192.168.1.254"),
+            route("10.1.0.0/16", "10.1.0.1"),
+        ]);
+        let deltas: Vec<String> = expected
+            .diff(&actual)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            deltas,
+            vec![
+                r#".routes[1].next_hop: "192.168.1.1" -> "192.168.1.254""#.to_string(),
+                r#".routes[2]: <missing> -> Route{.prefix = "10.1.0.0/16", .next_hop = "10.1.0.1"}"#
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn maps_and_sets() {
+        let int = |i: i32| Record::Int(i.into());
+        let kv = |k: i32, v: i32| Record::Tuple(vec![int(k), int(v)]);
+        let expected = Record::Tuple(vec![
+            Record::Array(CollectionKind::Map, vec![kv(1, 10), kv(2, 20)]),
+            Record::Array(CollectionKind::Set, vec![int(1), int(2)]),
+        ]);
+        let actual = Record::Tuple(vec![
+            Record::Array(CollectionKind::Map, vec![kv(2, 21), kv(3, 30)]),
+            Record::Array(CollectionKind::Set, vec![int(2), int(3)]),
+        ]);
+        let deltas: Vec<String> = expected
+            .diff(&actual)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            deltas,
+            vec![
+                ".0[1]: 10 -> <missing>",
+                ".0[2]: 20 -> 21",
+                ".0[3]: <missing> -> 30",
+                ".1{1}: 1 -> <missing>",
+                ".1{3}: <missing> -> 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn different_constructors() {
+        let expected = Record::PosStruct(Cow::Borrowed("Some"), vec![Record::Bool(true)]);
+        let actual = Record::PosStruct(Cow::Borrowed("None"), vec![]);
+        assert_eq!(
+            expected.diff(&actual),
+            vec![PathDelta {
+                path: String::new(),
+                expected: Some(expected.clone()),
+                actual: Some(actual.clone()),
+            }]
+        );
+    }
+}
@@ -1,11 +1,17 @@
 //! An untyped representation of DDlog values and database update commands.
 
 mod arrays;
+mod canonical;
+mod diff;
+mod pretty;
 mod tuples;
 
+pub use diff::PathDelta;
 use num::{BigInt, BigUint, ToPrimitive};
 use ordered_float::OrderedFloat;
+pub use pretty::{Pretty, PrettyConfig};
 use serde::{Deserialize, Serialize};
+
 use std::{
     borrow::Cow,
     collections::{btree_map, BTreeMap, BTreeSet},
@@ -204,6 +210,9 @@ impl Record {
 
 impl fmt::Display for Record {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            return write!(f, "{}", self.display_pretty(&PrettyConfig::default()));
+        }
         match self {
             Record::Bool(true) => write!(f, "true"),
             Record::Bool(false) => write!(f, "false"),
@@ -749,9 +758,30 @@ impl<T: IntoRecord> IntoRecord for vec::Vec<T> {
     }
 }
 
-impl<T: FromRecord> Mutator<vec::Vec<T>> for Record {
+/// Vector update semantics: replace the entire vector, unless the update is
+/// an element update (see `UPDATE_ELEMENTS`), in which case the elements with
+/// the specified indexes are mutated in place.
+impl<T: FromRecord> Mutator<vec::Vec<T>> for Record
+where
+    Record: Mutator<T>,
+{
     fn mutate(&self, v: &mut vec::Vec<T>) -> Result<(), String> {
-        *v = <vec::Vec<T>>::from_record(self)?;
+        match element_updates(self) {
+            Some(upds) => {
+                for (idx, m) in upds? {
+                    let len = v.len();
+                    let elem = idx
+                        .as_int()
+                        .and_then(ToPrimitive::to_usize)
+                        .and_then(|i| v.get_mut(i))
+                        .ok_or_else(|| {
+                            format!("invalid index {} in vector of length {}", idx, len)
+                        })?;
+                    m.mutate(elem)?;
+                }
+            }
+            None => *v = <vec::Vec<T>>::from_record(self)?,
+        }
         Ok(())
     }
 }
@@ -772,9 +802,23 @@ impl<K: IntoRecord + Ord, V: IntoRecord> IntoRecord for BTreeMap<K, V> {
 }
 
 /// Map update semantics is that the update contains keys that are in one of the maps but not the
-/// other, plus keys that are in both maps but with different values.
-impl<K: FromRecord + Ord, V: FromRecord + PartialEq> Mutator<BTreeMap<K, V>> for Record {
+/// other, plus keys that are in both maps but with different values.  Alternatively, an element
+/// update (see `UPDATE_ELEMENTS`) mutates the values of existing keys in place.
+impl<K: FromRecord + Ord, V: FromRecord + PartialEq> Mutator<BTreeMap<K, V>> for Record
+where
+    Record: Mutator<V>,
+{
     fn mutate(&self, map: &mut BTreeMap<K, V>) -> Result<(), String> {
+        if let Some(upds) = element_updates(self) {
+            for (key, m) in upds? {
+                let elem = map
+                    .get_mut(&K::from_record(key)?)
+                    .ok_or_else(|| format!("key {} not found in map", key))?;
+                m.mutate(elem)?;
+            }
+            return Ok(());
+        }
+
         let upd = <BTreeMap<K, V>>::from_record(self)?;
         for (k, v) in upd.into_iter() {
             match map.entry(k) {
@@ -839,6 +883,84 @@ pub fn arg_find<'a>(args: &'a [(Name, Record)], argname: &str) -> Option<&'a Rec
     args.iter().find(|(n, _)| *n == argname).map(|(_, v)| v)
 }
 
+/// Constructor name of a mutator record that updates individual elements of
+/// a vector or map: `__update_elements{(idx_or_key1, mutator1), ...}`.  Each
+/// element, identified by its index in a vector or by its key in a map, is
+/// updated by applying the corresponding mutator to it.
+pub const UPDATE_ELEMENTS: &str = "__update_elements";
+
+/// Returns the `(index or key, mutator)` pairs of an element update (see
+/// `UPDATE_ELEMENTS`), or `None` if `rec` is not an element update.
+fn element_updates(rec: &Record) -> Option<Result<vec::Vec<(&Record, &Record)>, String>> {
+    match rec {
+        Record::PosStruct(cons, elems) if cons == UPDATE_ELEMENTS => Some(
+            elems
+                .iter()
+                .map(|elem| match elem.as_tuple() {
+                    Some([key, m]) => Ok((key, m)),
+                    _ => Err(format!("invalid element update {}", elem)),
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// One step along the path to a nested part of a value.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PathElem {
+    /// Struct field.
+    Field(Name),
+    /// Vector element.
+    Index(usize),
+    /// The value associated with a key in a map.
+    Key(Record),
+}
+
+/// Builds a mutator record that applies `value` to the part of a value
+/// identified by `path`, leaving the rest of the value unchanged.  `value` is
+/// itself applied as a mutator: scalars and vectors are replaced, while
+/// structs, sets, and maps are updated according to their `Mutator` semantics.
+///
+/// For example, the path `[Field("items"), Index(2), Field("price")]` updates
+/// the `price` field of the third element of the `items` vector.
+pub fn mutator_for_path(path: &[PathElem], value: Record) -> Record {
+    path.iter().rev().fold(value, |m, elem| match elem {
+        PathElem::Field(name) => Record::NamedStruct(Cow::from(""), vec![(name.clone(), m)]),
+        PathElem::Index(idx) => Record::PosStruct(
+            Cow::from(UPDATE_ELEMENTS),
+            vec![Record::Tuple(vec![Record::Int(BigInt::from(*idx)), m])],
+        ),
+        PathElem::Key(key) => Record::PosStruct(
+            Cow::from(UPDATE_ELEMENTS),
+            vec![Record::Tuple(vec![key.clone(), m])],
+        ),
+    })
+}
+
+/// Collects arguments that refer to nested fields of `argname` by path,
+/// e.g., `argname.f1.f2`, into a named struct that can be used to mutate the
+/// `argname` field (`{.f1.f2 = ...}`).  Returns `None` if there are no such
+/// arguments.
+///
+/// This allows a modify command to update a nested field of a large struct
+/// without specifying the rest of the struct.
+pub fn arg_find_path(args: &[(Name, Record)], argname: &str) -> Option<Record> {
+    let fields: Vec<(Name, Record)> = args
+        .iter()
+        .filter_map(|(n, v)| {
+            let path = n.strip_prefix(argname)?.strip_prefix('.')?;
+            Some((Cow::from(path.to_string()), v.clone()))
+        })
+        .collect();
+
+    if fields.is_empty() {
+        None
+    } else {
+        Some(Record::NamedStruct(Cow::from(""), fields))
+    }
+}
+
 // C API to Record and UpdCmd
 // These functions should live in a separate model, but that
 // does not work (the functions do not get exported by the
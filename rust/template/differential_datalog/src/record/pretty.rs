@@ -0,0 +1,337 @@
+//! Multi-line pretty-printer for `Record`s.
+//!
+//! `Record`'s `Display` implementation prints the entire value on one line,
+//! which is hard to read for deeply nested values.  The pretty-printer prints
+//! a value on one line if it fits within the configured line width and
+//! otherwise breaks it into one line per field or collection element,
+//! indented according to nesting depth:
+//!
+//! ```text
+//! Config{
+//!     .name = "eth0",
+//!     .routes = [
+//!         Route{.prefix = "10.0.0.0/8", .next_hop = "10.0.0.1"},
+//!         Route{.prefix = "0.0.0.0/0", .next_hop = "192.168.1.1"}
+//!     ]
+//! }
+//! ```
+//!
+//! The pretty-printer is also used when a `Record` or a `DDValue` is formatted
+//! with the alternate flag (`{:#}` or `{:#?}`).
+
+use super::{format_ddlog_str, Record};
+use std::fmt::{self, Write};
+
+const ANSI_RESET: &str = "\x1b[0m";
+const ANSI_CONSTRUCTOR: &str = "\x1b[1;34m";
+const ANSI_FIELD: &str = "\x1b[36m";
+const ANSI_STRING: &str = "\x1b[32m";
+const ANSI_LITERAL: &str = "\x1b[33m";
+
+/// Pretty-printer settings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrettyConfig {
+    /// Number of spaces per indentation level.
+    pub indent: usize,
+    /// Maximal line width.  Values that do not fit on the current line are
+    /// split across multiple lines.
+    pub width: usize,
+    /// Print field names of structs with named fields (`.field = value`).
+    /// When `false`, all structs are printed using positional syntax.
+    pub field_names: bool,
+    /// Highlight constructors, field names, and literals using ANSI terminal
+    /// colors.
+    pub color: bool,
+}
+
+impl Default for PrettyConfig {
+    fn default() -> Self {
+        Self {
+            indent: 4,
+            width: 80,
+            field_names: true,
+            color: false,
+        }
+    }
+}
+
+/// `Display` adapter that prints a `Record` using the pretty-printer.
+pub struct Pretty<'a> {
+    record: &'a Record,
+    config: &'a PrettyConfig,
+}
+
+impl fmt::Display for Pretty<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Printer {
+            config: self.config,
+        }
+        .print(self.record, 0, 0, f)
+    }
+}
+
+impl Record {
+    /// Returns an object that formats the record using the pretty-printer.
+    pub fn display_pretty<'a>(&'a self, config: &'a PrettyConfig) -> Pretty<'a> {
+        Pretty {
+            record: self,
+            config,
+        }
+    }
+
+    /// Pretty-prints the record into a string.
+    pub fn pretty(&self, config: &PrettyConfig) -> String {
+        self.display_pretty(config).to_string()
+    }
+}
+
+struct DDlogStr<'a>(&'a str);
+
+impl fmt::Display for DDlogStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        format_ddlog_str(self.0, f)
+    }
+}
+
+struct Printer<'a> {
+    config: &'a PrettyConfig,
+}
+
+impl Printer<'_> {
+    fn colored(&self, color: &str, text: fmt::Arguments, w: &mut dyn Write) -> fmt::Result {
+        if self.config.color {
+            write!(w, "{}{}{}", color, text, ANSI_RESET)
+        } else {
+            w.write_fmt(text)
+        }
+    }
+
+    /* Width of the single-line representation of `rec`, not counting
+     * color escape sequences. */
+    fn flat_width(&self, rec: &Record) -> usize {
+        let config = PrettyConfig {
+            color: false,
+            ..self.config.clone()
+        };
+        let mut flat = String::new();
+        let _ = Printer { config: &config }.print_flat(rec, &mut flat);
+        flat.chars().count()
+    }
+
+    fn print_seq<'r>(
+        &self,
+        open: &str,
+        elems: impl Iterator<Item = (Option<&'r str>, &'r Record)>,
+        close: &str,
+        w: &mut dyn Write,
+    ) -> fmt::Result {
+        w.write_str(open)?;
+        for (i, (fname, r)) in elems.enumerate() {
+            if i > 0 {
+                w.write_str(", ")?;
+            }
+            if let Some(fname) = fname {
+                self.colored(ANSI_FIELD, format_args!(".{}", fname), w)?;
+                w.write_str(" = ")?;
+            }
+            self.print_flat(r, w)?;
+        }
+        w.write_str(close)
+    }
+
+    fn print_flat(&self, rec: &Record, w: &mut dyn Write) -> fmt::Result {
+        match rec {
+            Record::Bool(b) => self.colored(ANSI_LITERAL, format_args!("{}", b), w),
+            Record::Int(i) => self.colored(ANSI_LITERAL, format_args!("{}", i), w),
+            Record::Float(d) => self.colored(ANSI_LITERAL, format_args!("{}", d), w),
+            Record::Double(d) => self.colored(ANSI_LITERAL, format_args!("{}", d), w),
+            Record::String(s) => self.colored(ANSI_STRING, format_args!("{}", DDlogStr(s)), w),
+            Record::Serialized(n, s) => {
+                self.colored(ANSI_STRING, format_args!("#{}{}", n, DDlogStr(s)), w)
+            }
+            Record::Tuple(recs) => self.print_seq("(", recs.iter().map(|r| (None, r)), ")", w),
+            Record::Array(_, recs) => self.print_seq("[", recs.iter().map(|r| (None, r)), "]", w),
+            Record::PosStruct(n, recs) => {
+                self.colored(ANSI_CONSTRUCTOR, format_args!("{}", n), w)?;
+                self.print_seq("{", recs.iter().map(|r| (None, r)), "}", w)
+            }
+            Record::NamedStruct(n, fields) => {
+                self.colored(ANSI_CONSTRUCTOR, format_args!("{}", n), w)?;
+                let field_names = self.config.field_names;
+                self.print_seq(
+                    "{",
+                    fields
+                        .iter()
+                        .map(|(fname, r)| (Some(fname.as_ref()).filter(|_| field_names), r)),
+                    "}",
+                    w,
+                )
+            }
+        }
+    }
+
+    fn newline(&self, level: usize, w: &mut dyn Write) -> fmt::Result {
+        writeln!(w)?;
+        write!(w, "{:1$}", "", level * self.config.indent)
+    }
+
+    /* Print a sequence of elements one per line, indented one level deeper
+     * than `level`. */
+    fn print_broken<'r>(
+        &self,
+        open: &str,
+        elems: impl Iterator<Item = (Option<&'r str>, &'r Record)>,
+        close: &str,
+        level: usize,
+        w: &mut dyn Write,
+    ) -> fmt::Result {
+        w.write_str(open)?;
+        let inner = level + 1;
+        for (i, (fname, r)) in elems.enumerate() {
+            if i > 0 {
+                w.write_str(",")?;
+            }
+            self.newline(inner, w)?;
+            let mut column = inner * self.config.indent;
+            if let Some(fname) = fname {
+                self.colored(ANSI_FIELD, format_args!(".{}", fname), w)?;
+                w.write_str(" = ")?;
+                column += fname.chars().count() + 4;
+            }
+            self.print(r, inner, column, w)?;
+        }
+        self.newline(level, w)?;
+        w.write_str(close)
+    }
+
+    /* Print `rec` at nesting depth `level`, starting at `column`. */
+    fn print(&self, rec: &Record, level: usize, column: usize, w: &mut dyn Write) -> fmt::Result {
+        if column + self.flat_width(rec) <= self.config.width {
+            return self.print_flat(rec, w);
+        }
+        match rec {
+            Record::Tuple(recs) if !recs.is_empty() => {
+                self.print_broken("(", recs.iter().map(|r| (None, r)), ")", level, w)
+            }
+            Record::Array(_, recs) if !recs.is_empty() => {
+                self.print_broken("[", recs.iter().map(|r| (None, r)), "]", level, w)
+            }
+            Record::PosStruct(n, recs) if !recs.is_empty() => {
+                self.colored(ANSI_CONSTRUCTOR, format_args!("{}", n), w)?;
+                self.print_broken("{", recs.iter().map(|r| (None, r)), "}", level, w)
+            }
+            Record::NamedStruct(n, fields) if !fields.is_empty() => {
+                self.colored(ANSI_CONSTRUCTOR, format_args!("{}", n), w)?;
+                let field_names = self.config.field_names;
+                self.print_broken(
+                    "{",
+                    fields
+                        .iter()
+                        .map(|(fname, r)| (Some(fname.as_ref()).filter(|_| field_names), r)),
+                    "}",
+                    level,
+                    w,
+                )
+            }
+            _ => self.print_flat(rec, w),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::CollectionKind;
+    use std::borrow::Cow;
+
+    fn route(prefix: &str, next_hop: &str) -> Record {
+        Record::NamedStruct(
+            Cow::Borrowed("Route"),
+            vec![
+                (Cow::Borrowed("prefix"), Record::String(prefix.to_string())),
+                (
+                    Cow::Borrowed("next_hop"),
+                    Record::String(next_hop.to_string()),
+                ),
+            ],
+        )
+    }
+
+    fn sample() -> Record {
+        Record::NamedStruct(
+            Cow::Borrowed("Config"),
+            vec![
+                (Cow::Borrowed("name"), Record::String("eth0".to_string())),
+                (
+                    Cow::Borrowed("routes"),
+                    Record::Array(
+                        CollectionKind::Vector,
+                        vec![
+                            route("10.0.0.0/8", "10.0.0.1"),
+                            route("0.0.0.0/0", "192.168.1.1"),
+                        ],
+                    ),
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn short_values_stay_on_one_line() {
+        let rec = route("10.0.0.0/8", "10.0.0.1");
+        assert_eq!(rec.pretty(&PrettyConfig::default()), rec.to_string());
+    }
+
+    #[test]
+    fn long_values_are_broken() {
+        let expected = r#"Config{
+    .name = "eth0",
+    .routes = [
+        Route{.prefix = "10.0.0.0/8", .next_hop = "10.0.0.1"},
+        Route{.prefix = "0.0.0.0/0", .next_hop = "192.168.1.1"}
+    ]
+}"#;
+        assert_eq!(sample().pretty(&PrettyConfig::default()), expected);
+        assert_eq!(format!("{:#}", sample()), expected);
+    }
+
+    #[test]
+    fn positional_fields_and_indentation() {
+        let config = PrettyConfig {
+            indent: 2,
+            width: 40,
+            field_names: false,
+            color: false,
+        };
+        let expected = r#"Config{
+  "eth0",
+  [
+    Route{"10.0.0.0/8", "10.0.0.1"},
+    Route{"0.0.0.0/0", "192.168.1.1"}
+  ]
+}"#;
+        assert_eq!(sample().pretty(&config), expected);
+    }
+
+    #[test]
+    fn colors_do_not_affect_layout() {
+        let config = PrettyConfig {
+            color: true,
+            ..PrettyConfig::default()
+        };
+        let colored = sample().pretty(&config);
+        assert!(colored.contains(ANSI_RESET));
+        let stripped = colored
+            .split('\x1b')
+            .enumerate()
+            .map(|(i, s)| {
+                if i == 0 {
+                    s
+                } else {
+                    &s[s.find('m').unwrap() + 1..]
+                }
+            })
+            .collect::<String>();
+        assert_eq!(stripped, sample().pretty(&PrettyConfig::default()));
+    }
+}
@@ -1,7 +1,7 @@
 use crate::{
     dataflow::{diff_distinct, FilterMap, MapExt},
     ddval::DDValue,
-    program::arrange::Arrangement,
+    program::{arrange::Arrangement, guard, MultiTenant, SingleTenant, TenantMode},
     render::{Offset, RenderContext, Str, TraceKey, TraceValue},
 };
 use differential_dataflow::{
@@ -54,7 +54,7 @@ type Arranged<S, R> =
 impl<'a> ArrangeBy<'a> {
     pub fn render<S, R>(
         &self,
-        _context: &RenderContext,
+        context: &RenderContext,
         collection: &Collection<S, DDValue, R>,
     ) -> Arranged<S, R>
     where
@@ -83,7 +83,8 @@ impl<'a> ArrangeBy<'a> {
                 };
 
                 // The keyed relation
-                let keyed = match self.key_collection(
+                let keyed = match self.dispatch_key_collection(
+                    context,
                     collection,
                     key_function,
                     distinct,
@@ -105,14 +106,26 @@ impl<'a> ArrangeBy<'a> {
             }
 
             ArrangementKind::Map { value_function } => {
-                self.render_map(&collection, value_function, &arrangement_name)
+                if context.config.multi_tenant {
+                    self.render_map::<MultiTenant, _, _>(
+                        collection,
+                        value_function,
+                        &arrangement_name,
+                    )
+                } else {
+                    self.render_map::<SingleTenant, _, _>(
+                        collection,
+                        value_function,
+                        &arrangement_name,
+                    )
+                }
             }
         }
     }
 
     pub fn render_root<S, R>(
         &self,
-        _context: &RenderContext,
+        context: &RenderContext,
         collection: &Collection<S, DDValue, R>,
     ) -> Arrangement<S, R, TraceAgent<TraceValue<S, R, Offset>>, TraceAgent<TraceKey<S, R, Offset>>>
     where
@@ -143,7 +156,8 @@ impl<'a> ArrangeBy<'a> {
                 };
 
                 // The keyed relation
-                let mut keyed = match self.key_collection(
+                let mut keyed = match self.dispatch_key_collection(
+                    context,
                     collection,
                     key_function,
                     distinct,
@@ -185,37 +199,83 @@ impl<'a> ArrangeBy<'a> {
             }
 
             ArrangementKind::Map { value_function } => {
-                self.render_map(&collection, value_function, &arrangement_name)
+                if context.config.multi_tenant {
+                    self.render_map::<MultiTenant, _, _>(
+                        collection,
+                        value_function,
+                        &arrangement_name,
+                    )
+                } else {
+                    self.render_map::<SingleTenant, _, _>(
+                        collection,
+                        value_function,
+                        &arrangement_name,
+                    )
+                }
             }
         }
     }
 
-    fn render_map<S, R>(
+    fn render_map<M, S, R>(
         &self,
         collection: &Collection<S, DDValue, R>,
         value_function: ValueFunc,
         arrangement_name: &str,
     ) -> Arranged<S, R>
     where
+        M: TenantMode,
         S: Scope,
         S::Timestamp: Lattice,
         R: Abelian + ExchangeData + Add<Output = R> + From<i8>,
     {
         // Extract the relation's key and value tuple before arranging it
+        let name = format!(
+            "FilterMap: Extract key and value for {}",
+            self.target_relation,
+        );
+        let rule = name.clone();
         let arranged = collection
-            .filter_map_named(
-                &format!(
-                    "FilterMap: Extract key and value for {}",
-                    self.target_relation,
-                ),
-                value_function,
-            )
+            .filter_map_named(&name, move |value| {
+                guard(&rule, None, || M::arrange(value, value_function))
+            })
             .arrange_named(arrangement_name);
 
         Arrangement::Map(arranged)
     }
 
-    fn key_collection<S, R>(
+    /// Keys `collection`, lifting the key function to tagged values if the
+    /// program is multi-tenant
+    fn dispatch_key_collection<S, R>(
+        &self,
+        context: &RenderContext,
+        collection: &Collection<S, DDValue, R>,
+        key_function: Option<KeyFunc>,
+        distinct: bool,
+        arrangement_name: &str,
+    ) -> Result<Collection<S, DDValue, R>, Arranged<S, R>>
+    where
+        S: Scope,
+        S::Timestamp: Lattice,
+        R: Abelian + ExchangeData + Add<Output = R> + From<i8>,
+    {
+        if context.config.multi_tenant {
+            self.key_collection::<MultiTenant, _, _>(
+                collection,
+                key_function,
+                distinct,
+                arrangement_name,
+            )
+        } else {
+            self.key_collection::<SingleTenant, _, _>(
+                collection,
+                key_function,
+                distinct,
+                arrangement_name,
+            )
+        }
+    }
+
+    fn key_collection<M, S, R>(
         &self,
         collection: &Collection<S, DDValue, R>,
         key_function: Option<KeyFunc>,
@@ -223,6 +283,7 @@ impl<'a> ArrangeBy<'a> {
         arrangement_name: &str,
     ) -> Result<Collection<S, DDValue, R>, Arranged<S, R>>
     where
+        M: TenantMode,
         S: Scope,
         S::Timestamp: Lattice,
         R: Abelian + ExchangeData + Add<Output = R> + From<i8>,
@@ -230,15 +291,18 @@ impl<'a> ArrangeBy<'a> {
         if let Some(key_function) = key_function {
             // The name for extracting the set's key out of the relation
             let keyed_name = format!("FilterMap: Extract key for {}", self.target_relation);
+            let rule = keyed_name.clone();
 
             if distinct {
-                Ok(collection.filter_map_named(&keyed_name, key_function))
+                Ok(collection.filter_map_named(&keyed_name, move |value| {
+                    guard(&rule, None, || M::map_opt(value, key_function))
+                }))
 
             // If our set is filtered and is not distinct we can skip a redundant map
             // operation by mapping into a `(key, ())` within the filter itself
             } else {
                 let keyed = collection.filter_map_named(&keyed_name, move |value| {
-                    key_function(value).map(|key| (key, ()))
+                    guard(&rule, None, || M::map_opt(value, key_function)).map(|key| (key, ()))
                 });
 
                 let arranged =
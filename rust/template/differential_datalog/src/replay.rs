@@ -6,10 +6,12 @@ use std::io::Write;
 use std::iter::Peekable;
 use std::ops::Deref;
 use std::string::ToString;
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
 
 use crate::ddlog::{DDlog, DDlogDump, DDlogDynamic, DDlogInventory, DDlogProfiling};
 use crate::ddval::DDValue;
+use crate::metadata::ProgramMetadata;
 use crate::program::IdxId;
 use crate::program::RelId;
 use crate::program::Update;
@@ -66,6 +68,8 @@ pub struct CommandRecorder<W, I> {
     // Typically, `I` is `Box<dyn DDlogInventory + Send + Sync>` or
     // `Arc<dyn DDlogInventory + Send + Sync>`
     inventory: I,
+    // Start of the recording, if commands are timestamped.
+    start: Option<Instant>,
 }
 
 impl<W, B> Debug for CommandRecorder<W, B> {
@@ -79,9 +83,19 @@ impl<W, B> CommandRecorder<W, B> {
         CommandRecorder {
             writer: Mutex::new(writer),
             inventory,
+            start: None,
         }
     }
 
+    /// Precede each recorded command with an `at <ms>;` command holding the
+    /// number of milliseconds since the recording started, so that the
+    /// recording can be replayed with its original pacing (see the
+    /// `--replay-speed` option of the CLI).
+    pub fn with_timestamps(mut self) -> Self {
+        self.start = Some(Instant::now());
+        self
+    }
+
     pub fn release_writer(self) -> W {
         self.writer.into_inner().unwrap()
     }
@@ -124,13 +138,50 @@ where
     W: Write,
     I: Deref<Target = dyn DDlogInventory + Send + Sync>,
 {
+    /// Lock the writer for recording a command, timestamping the command
+    /// if enabled.
+    fn lock_writer(&self) -> Result<MutexGuard<'_, W>, String> {
+        let mut writer = self.writer.lock().unwrap();
+        if let Some(start) = self.start {
+            writeln!(&mut writer, "at {};", start.elapsed().as_millis())
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(writer)
+    }
+
+    /// Record a commit with transaction id `id` (see
+    /// `RunningProgram::transaction_commit_with_id()`).  Replaying the
+    /// recording restores the set of committed ids along with the data.
+    pub fn transaction_commit_with_id(&self, id: &str) -> Result<(), String> {
+        let mut writer = self.lock_writer()?;
+        writeln!(&mut writer, "commit id {};", Record::String(id.to_string()))
+            .map_err(|e| e.to_string())
+    }
+
+    /// Record the schema fingerprints of the input relations of the program
+    /// (see `crate::metadata`), so that replaying the recording into a build
+    /// of the program with a different schema fails before applying any
+    /// updates to the changed relations.
+    pub fn record_fingerprints(&self, metadata: &ProgramMetadata) -> Result<(), String> {
+        let mut writer = self.writer.lock().unwrap();
+        for rel in metadata.relations.iter().filter(|rel| rel.input) {
+            writeln!(
+                &mut writer,
+                "check_fingerprint {} {:#018x};",
+                rel.name, rel.fingerprint
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
     fn do_record_updates<It, U, F>(&self, updates: It, mut record: F) -> Result<(), String>
     where
         W: Write,
         It: Iterator<Item = U>,
         F: FnMut(&dyn DDlogInventory, &mut W, &U) -> IOResult<()>,
     {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         let inventory = &*self.inventory;
         Peeking::new(updates)
             .try_for_each(move |(upd, last)| {
@@ -223,26 +274,32 @@ where
     I: Deref<Target = dyn DDlogInventory + Send + Sync>,
 {
     fn transaction_start(&self) -> Result<(), String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(&mut writer, "start;").map_err(|e| e.to_string())
     }
 
+    /// Prepare is not recorded: replaying the subsequent commit has the
+    /// same effect.
+    fn transaction_prepare(&self) -> Result<(), String> {
+        Ok(())
+    }
+
     fn transaction_commit(&self) -> Result<(), String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(&mut writer, "commit;").map_err(|e| e.to_string())
     }
 
     fn transaction_commit_dump_changes_dynamic(
         &self,
     ) -> Result<BTreeMap<RelId, Vec<(Record, isize)>>, String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(&mut writer, "commit dump_changes;")
             .map(|_| BTreeMap::new())
             .map_err(|e| e.to_string())
     }
 
     fn transaction_rollback(&self) -> Result<(), String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(&mut writer, "rollback;").map_err(|e| e.to_string())
     }
 
@@ -251,7 +308,7 @@ where
     }
 
     fn clear_relation(&self, rid: RelId) -> Result<(), String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(
             &mut writer,
             "clear {};",
@@ -261,7 +318,7 @@ where
     }
 
     fn query_index_dynamic(&self, iid: IdxId, key: &Record) -> Result<Vec<Record>, String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(
             &mut writer,
             "query_index {}({});",
@@ -273,7 +330,7 @@ where
     }
 
     fn dump_index_dynamic(&self, iid: IdxId) -> Result<Vec<Record>, String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(
             &mut writer,
             "dump_index {};",
@@ -294,7 +351,7 @@ where
     I: Deref<Target = dyn DDlogInventory + Send + Sync>,
 {
     fn transaction_commit_dump_changes(&self) -> Result<DeltaMap<DDValue>, String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(&mut writer, "commit dump_changes;")
             .map(|_| DeltaMap::new())
             .map_err(|e| e.to_string())
@@ -305,7 +362,7 @@ where
     }
 
     fn query_index(&self, iid: IdxId, key: DDValue) -> Result<BTreeSet<DDValue>, String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(
             &mut writer,
             "query_index {}({});",
@@ -317,7 +374,7 @@ where
     }
 
     fn dump_index(&self, iid: IdxId) -> Result<BTreeSet<DDValue>, String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(
             &mut writer,
             "dump_index {};",
@@ -338,7 +395,7 @@ where
         rid: RelId,
         _cb: Option<&dyn Fn(&Record, isize) -> bool>,
     ) -> Result<(), String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(
             &mut writer,
             "dump {};",
@@ -358,7 +415,7 @@ where
     I: Deref<Target = dyn DDlogInventory + Send + Sync>,
 {
     fn enable_cpu_profiling(&self, enable: bool) -> Result<(), String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(
             &mut writer,
             "profile cpu {};",
@@ -368,7 +425,7 @@ where
     }
 
     fn enable_timely_profiling(&self, enable: bool) -> Result<(), String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(
             &mut writer,
             "profile timely {};",
@@ -378,7 +435,7 @@ where
     }
 
     fn profile(&self) -> Result<String, String> {
-        let mut writer = self.writer.lock().unwrap();
+        let mut writer = self.lock_writer()?;
         writeln!(&mut writer, "profile;")
             .map_err(|e| e.to_string())
             .map(|_| "".to_string())
@@ -388,6 +445,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::metadata::RelationMetadata;
     #[cfg(feature = "c_api")]
     use std::ffi::CStr;
 
@@ -452,4 +510,60 @@ update 10;
 "#;
         test(updates, expected);
     }
+
+    #[test]
+    fn fingerprint_recording() {
+        static RELATIONS: [RelationMetadata; 2] = [
+            RelationMetadata {
+                name: "Edge",
+                input: true,
+                fingerprint: 0xabcd,
+            },
+            RelationMetadata {
+                name: "Path",
+                input: false,
+                fingerprint: 0x1234,
+            },
+        ];
+        let metadata = ProgramMetadata {
+            program_name: "graph",
+            source_hash: 0,
+            compiler_version: "",
+            relations: &RELATIONS,
+        };
+
+        let mut buf = Vec::new();
+        let recorder = CommandRecorder::new(
+            &mut buf,
+            Box::new(DummyInventory) as Box<dyn DDlogInventory + Send + Sync>,
+        );
+        recorder.record_fingerprints(&metadata).unwrap();
+        assert_eq!(
+            buf.as_slice(),
+            &b"check_fingerprint Edge 0x000000000000abcd;\n"[..]
+        );
+    }
+
+    #[test]
+    fn timestamp_recording() {
+        let mut buf = Vec::new();
+        let recorder = CommandRecorder::new(
+            &mut buf,
+            Box::new(DummyInventory) as Box<dyn DDlogInventory + Send + Sync>,
+        )
+        .with_timestamps();
+        recorder.transaction_start().unwrap();
+        recorder
+            .do_record_updates(vec![1].iter(), |_, w, r| write!(w, "update {}", r))
+            .unwrap();
+        recorder.transaction_commit().unwrap();
+
+        let recording = String::from_utf8(buf).unwrap();
+        let lines: Vec<&str> = recording.lines().collect();
+        assert_eq!(lines.len(), 6);
+        for (i, cmd) in ["start;", "update 1;", "commit;"].iter().enumerate() {
+            assert!(lines[2 * i].starts_with("at "));
+            assert_eq!(lines[2 * i + 1], *cmd);
+        }
+    }
 }
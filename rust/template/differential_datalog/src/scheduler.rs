@@ -0,0 +1,231 @@
+//! Fair scheduling of transactions submitted by multiple clients.
+//!
+//! A running program executes one transaction at a time.  When several
+//! threads share a program, `TransactionScheduler` decides which of them
+//! starts the next transaction instead of letting them race for it.  Clients
+//! waiting for a turn are served by start-time fair queuing: every client
+//! receives a share of the turns proportional to its priority, so a busy
+//! client cannot starve the others, and a client that has been idle does not
+//! accumulate credit that would let it monopolize the program later.
+//! Waiting clients with the same share are served in the order they arrived.
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Identifies a client of the scheduler, e.g., a connection or a thread.
+pub type ClientId = u64;
+
+/// Priority of clients whose priority has not been set.
+pub const DEFAULT_PRIORITY: u32 = 1;
+
+/// Virtual time it takes a client with priority 1 to use one turn.
+const TURN_COST: u64 = 1 << 20;
+
+#[derive(Debug, Clone, Copy)]
+struct Client {
+    priority: u32,
+    /// Virtual time at which the last turn of the client finishes.
+    finish: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Waiter {
+    ticket: u64,
+    client: ClientId,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The client whose turn it is.
+    owner: Option<ClientId>,
+    waiting: Vec<Waiter>,
+    clients: HashMap<ClientId, Client>,
+    /// Virtual time at which the last turn started.
+    now: u64,
+    next_ticket: u64,
+}
+
+impl State {
+    fn client(&self, client: ClientId) -> Client {
+        self.clients.get(&client).cloned().unwrap_or(Client {
+            priority: DEFAULT_PRIORITY,
+            finish: 0,
+        })
+    }
+
+    /* Virtual time at which the next turn of `client` would start. */
+    fn start_time(&self, client: ClientId) -> u64 {
+        self.client(client).finish.max(self.now)
+    }
+
+    /* The waiter whose turn is next. */
+    fn next(&self) -> Option<u64> {
+        self.waiting
+            .iter()
+            .min_by_key(|w| (self.start_time(w.client), w.ticket))
+            .map(|w| w.ticket)
+    }
+
+    fn grant(&mut self, ticket: u64) {
+        let pos = self
+            .waiting
+            .iter()
+            .position(|w| w.ticket == ticket)
+            .unwrap();
+        let waiter = self.waiting.remove(pos);
+        let start = self.start_time(waiter.client);
+        let mut client = self.client(waiter.client);
+        client.finish = start + TURN_COST / u64::from(client.priority);
+        self.clients.insert(waiter.client, client);
+        self.now = start;
+        self.owner = Some(waiter.client);
+    }
+}
+
+/// Serializes turns of multiple clients; see the module documentation.
+#[derive(Debug, Default)]
+pub struct TransactionScheduler {
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+/// The right to run a transaction.  The next client gets its turn when the
+/// `Turn` is dropped.
+#[derive(Debug)]
+pub struct Turn<'a> {
+    scheduler: &'a TransactionScheduler,
+    client: ClientId,
+}
+
+impl Turn<'_> {
+    /// The client whose turn it is.
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        self.scheduler.state.lock().unwrap().owner = None;
+        self.scheduler.released.notify_all();
+    }
+}
+
+impl TransactionScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the priority of `client`: a client with priority 2 gets twice as
+    /// many turns as a client with priority 1 when both are waiting.
+    /// Priority 0 is treated as 1.
+    pub fn set_priority(&self, client: ClientId, priority: u32) {
+        let mut state = self.state.lock().unwrap();
+        let mut entry = state.client(client);
+        entry.priority = priority.max(1);
+        state.clients.insert(client, entry);
+    }
+
+    /// Forget the priority and history of `client`, e.g., when it
+    /// disconnects.
+    pub fn remove_client(&self, client: ClientId) {
+        self.state.lock().unwrap().clients.remove(&client);
+    }
+
+    /// Number of clients waiting for a turn.
+    pub fn waiting(&self) -> usize {
+        self.state.lock().unwrap().waiting.len()
+    }
+
+    /// Wait for the turn of `client`, giving up after `timeout`, if
+    /// specified.
+    pub fn acquire(&self, client: ClientId, timeout: Option<Duration>) -> Result<Turn<'_>, String> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push(Waiter { ticket, client });
+        loop {
+            if state.owner.is_none() && state.next() == Some(ticket) {
+                state.grant(ticket);
+                return Ok(Turn {
+                    scheduler: self,
+                    client,
+                });
+            }
+            state = match deadline {
+                None => self.released.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        state.waiting.retain(|w| w.ticket != ticket);
+                        // Giving up may make another waiter next in line.
+                        self.released.notify_all();
+                        return Err(format!(
+                            "client {} timed out after {:?} waiting for a transaction",
+                            client,
+                            timeout.unwrap()
+                        ));
+                    }
+                    self.released.wait_timeout(state, deadline - now).unwrap().0
+                }
+            };
+        }
+    }
+}
+
+#[test]
+fn test_scheduler_fairness() {
+    let mut state = State::default();
+    let mut order = Vec::new();
+    let enqueue = |state: &mut State, client| {
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push(Waiter { ticket, client });
+    };
+    state.clients.insert(
+        2,
+        Client {
+            priority: 2,
+            finish: 0,
+        },
+    );
+    // Client 1 arrives first with many requests; client 2 has twice the
+    // priority.
+    for _ in 0..6 {
+        enqueue(&mut state, 1);
+    }
+    for _ in 0..6 {
+        enqueue(&mut state, 2);
+    }
+    while let Some(ticket) = state.next() {
+        state.grant(ticket);
+        order.push(state.owner.take().unwrap());
+    }
+    assert_eq!(order, vec![1, 2, 2, 1, 2, 2, 1, 2, 2, 1, 1, 1]);
+}
+
+#[test]
+fn test_scheduler_timeout() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let scheduler = Arc::new(TransactionScheduler::new());
+    let turn = scheduler.acquire(1, None).unwrap();
+    assert!(scheduler
+        .acquire(2, Some(Duration::from_millis(10)))
+        .is_err());
+    assert_eq!(scheduler.waiting(), 0);
+
+    let waiter = {
+        let scheduler = scheduler.clone();
+        thread::spawn(move || {
+            scheduler
+                .acquire(2, Some(Duration::from_secs(10)))
+                .map(|turn| turn.client())
+        })
+    };
+    drop(turn);
+    assert_eq!(waiter.join().unwrap(), Ok(2));
+}
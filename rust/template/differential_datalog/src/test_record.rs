@@ -1,5 +1,6 @@
 //! Tests for functions and macros in `record.rs`
 
+use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet};
 use std::iter::FromIterator;
 use std::vec;
@@ -532,3 +533,85 @@ fn test_set() {
     .unwrap();
     assert_eq!(v, BTreeSet::from_iter(vec![1, 2]));
 }
+
+#[test]
+fn test_arg_find_path() {
+    let args = vec![
+        (Cow::from("f1"), Record::Bool(true)),
+        (Cow::from("f2.x"), Record::Int(BigInt::from(1))),
+        (Cow::from("f2.y.z"), Record::Int(BigInt::from(2))),
+        (Cow::from("f22.x"), Record::Int(BigInt::from(3))),
+    ];
+
+    assert_eq!(arg_find_path(&args, "f1"), None);
+    assert_eq!(
+        arg_find_path(&args, "f2"),
+        Some(Record::NamedStruct(
+            Cow::from(""),
+            vec![
+                (Cow::from("x"), Record::Int(BigInt::from(1))),
+                (Cow::from("y.z"), Record::Int(BigInt::from(2))),
+            ]
+        ))
+    );
+}
+
+#[test]
+fn test_update_elements() {
+    let mut v: vec::Vec<vec::Vec<u32>> = vec![vec![1, 2], vec![3, 4]];
+    mutator_for_path(
+        &[PathElem::Index(1), PathElem::Index(0)],
+        Record::Int(BigInt::from(30)),
+    )
+    .mutate(&mut v)
+    .unwrap();
+    assert_eq!(v, vec![vec![1, 2], vec![30, 4]]);
+    assert_eq!(
+        mutator_for_path(
+            &[PathElem::Index(2)],
+            Record::Array(CollectionKind::Vector, vec![])
+        )
+        .mutate(&mut v),
+        Err("invalid index 2 in vector of length 2".to_string())
+    );
+
+    let mut m: BTreeMap<u32, u32> = BTreeMap::from_iter(vec![(0, 10), (1, 10)]);
+    mutator_for_path(
+        &[PathElem::Key(Record::Int(BigInt::from(1)))],
+        Record::Int(BigInt::from(20)),
+    )
+    .mutate(&mut m)
+    .unwrap();
+    assert_eq!(m, BTreeMap::from_iter(vec![(0, 10), (1, 20)]));
+    assert_eq!(
+        mutator_for_path(
+            &[PathElem::Key(Record::Int(BigInt::from(5)))],
+            Record::Int(BigInt::from(20)),
+        )
+        .mutate(&mut m),
+        Err("key 5 not found in map".to_string())
+    );
+}
+
+#[test]
+fn test_mutator_for_path() {
+    assert_eq!(
+        mutator_for_path(
+            &[PathElem::Field(Cow::from("items")), PathElem::Index(2)],
+            Record::Bool(true)
+        ),
+        Record::NamedStruct(
+            Cow::from(""),
+            vec![(
+                Cow::from("items"),
+                Record::PosStruct(
+                    Cow::from(UPDATE_ELEMENTS),
+                    vec![Record::Tuple(vec![
+                        Record::Int(BigInt::from(2)),
+                        Record::Bool(true)
+                    ])]
+                )
+            )]
+        )
+    );
+}
@@ -11,6 +11,7 @@ use std::io;
 
 use crate::ddlog::DDlogInventory;
 use crate::program::RelId;
+use crate::record::{IntoRecord, PrettyConfig, Record};
 
 /* Stores a set of changes to output tables.
  */
@@ -19,6 +20,41 @@ pub struct DeltaMap<V> {
     map: BTreeMap<RelId, BTreeMap<V, isize>>,
 }
 
+/// How `DeltaMap::format_as_sets_with()` and `format_rel_as_set_with()`
+/// print relation contents.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FormatOptions {
+    /// Print values using the record pretty-printer.
+    pub pretty: Option<PrettyConfig>,
+    /// Print values in the canonical order (see `Record::canonical_cmp()`)
+    /// rather than in the order defined by their type, which may differ
+    /// between runs, e.g., for interned values.  Sets and maps nested in
+    /// values are also printed in the canonical order.
+    pub canonical_order: bool,
+}
+
+impl FormatOptions {
+    /// Convert values with their weights to records, in the order specified
+    /// by `canonical_order`.
+    pub fn records<'a, V, I>(&self, values: I) -> Vec<(Record, isize)>
+    where
+        V: IntoRecord + Clone + 'a,
+        I: IntoIterator<Item = (&'a V, isize)>,
+    {
+        let mut records: Vec<(Record, isize)> = values
+            .into_iter()
+            .map(|(v, w)| (v.clone().into_record(), w))
+            .collect();
+        if self.canonical_order {
+            for (rec, _) in records.iter_mut() {
+                rec.canonicalize();
+            }
+            records.sort_by(|(r1, w1), (r2, w2)| r1.canonical_cmp(r2).then(w1.cmp(w2)));
+        }
+        records
+    }
+}
+
 impl<V> AsMut<BTreeMap<RelId, BTreeMap<V, isize>>> for DeltaMap<V> {
     fn as_mut(&mut self) -> &mut BTreeMap<RelId, BTreeMap<V, isize>> {
         &mut self.map
@@ -119,6 +155,64 @@ impl<V: Display + Ord + Clone> DeltaMap<V> {
         Ok(())
     }
 
+    /// Like `format_as_sets`, but formats values as specified by `options`.
+    pub fn format_as_sets_with(
+        &self,
+        w: &mut dyn io::Write,
+        inventory: &dyn DDlogInventory,
+        options: &FormatOptions,
+    ) -> io::Result<()>
+    where
+        V: IntoRecord,
+    {
+        for (relid, map) in &self.map {
+            w.write_fmt(format_args!(
+                "{}:\n",
+                inventory.get_table_name(*relid).unwrap()
+            ))?;
+            Self::format_set_with(map, w, options)?;
+            w.write_fmt(format_args!("\n"))?;
+        }
+        Ok(())
+    }
+
+    /// Like `format_rel_as_set`, but formats values as specified by
+    /// `options`.
+    pub fn format_rel_as_set_with(
+        &mut self,
+        relid: RelId,
+        w: &mut dyn io::Write,
+        options: &FormatOptions,
+    ) -> io::Result<()>
+    where
+        V: IntoRecord,
+    {
+        let map = self.get_rel(relid);
+        Self::format_set_with(map, w, options)
+    }
+
+    fn format_set_with(
+        map: &BTreeMap<V, isize>,
+        w: &mut dyn io::Write,
+        options: &FormatOptions,
+    ) -> io::Result<()>
+    where
+        V: IntoRecord,
+    {
+        for (rec, weight) in options.records(map.iter().map(|(v, w)| (v, *w))) {
+            let rec = match &options.pretty {
+                Some(config) => rec.pretty(config),
+                None => rec.to_string(),
+            };
+            if weight == 1 {
+                w.write_fmt(format_args!("{}\n", rec))?;
+            } else {
+                w.write_fmt(format_args!("{} {:+}\n", rec, weight))?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_rel(&mut self, relid: RelId) -> &BTreeMap<V, isize> {
         self.map.entry(relid).or_insert_with(BTreeMap::default)
     }
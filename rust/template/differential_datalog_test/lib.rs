@@ -92,6 +92,9 @@ fn test_multiple_stops() {
     let prog: Program = Program {
         nodes: Vec::new(),
         delayed_rels: Vec::new(),
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
         init_data: Vec::new(),
     };
 
@@ -100,6 +103,44 @@ fn test_multiple_stops() {
     running.stop().unwrap();
 }
 
+/// Test that the frontier advances with every commit.
+#[test]
+fn test_frontier() {
+    let rel = Relation {
+        name: Cow::from("T1"),
+        input: true,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 1,
+        rules: Vec::new(),
+        arrangements: Vec::new(),
+        change_cb: None,
+    };
+    let prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel }],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    let mut running = prog.run(2).unwrap();
+    assert!(running.await_quiescence(std::time::Duration::from_secs(60)));
+    let before = running.frontier();
+
+    running.transaction_start().unwrap();
+    running.insert(1, U64(42).into_ddvalue()).unwrap();
+    running.transaction_commit().unwrap();
+    assert!(running.await_quiescence(std::time::Duration::from_secs(60)));
+    let after = running.frontier();
+    assert_eq!(after.submitted, before.submitted + 1);
+    assert!(after.processed >= after.submitted);
+
+    running.stop().unwrap();
+}
+
 /// Test that we can attempt to insert a value into a non-existent
 /// relation and fail gracefully.
 #[test]
@@ -107,6 +148,9 @@ fn test_insert_non_existent_relation() {
     let prog: Program = Program {
         nodes: vec![],
         delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
         init_data: vec![],
     };
 
@@ -158,6 +202,9 @@ fn test_input_relation_nested() {
             },
         ],
         delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
         init_data: vec![],
     };
 
@@ -187,6 +234,9 @@ fn test_one_relation(nthreads: usize) {
     let prog: Program = Program {
         nodes: vec![ProgNode::Rel { rel }],
         delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
         init_data: vec![],
     };
 
@@ -259,6 +309,149 @@ fn test_one_relation_multi() {
     test_one_relation(16)
 }*/
 
+/* Test computing the updates that bring a relation to the desired state.
+ */
+#[test]
+fn test_relation_sync_updates() {
+    let relset: Arc<Mutex<Delta<U64>>> = Arc::new(Mutex::new(BTreeMap::default()));
+    let rel = {
+        let relset1 = relset.clone();
+        Relation {
+            name: Cow::from("T1"),
+            input: true,
+            distinct: true,
+            caching_mode: CachingMode::Set,
+            key_func: None,
+            id: 1,
+            rules: Vec::new(),
+            arrangements: Vec::new(),
+            change_cb: Some(Arc::new(move |_, v, w| set_update("T1", &relset1, v, w))),
+        }
+    };
+
+    let prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel }],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    let mut running = prog.run(1).unwrap();
+
+    running.transaction_start().unwrap();
+    for x in 0..10 {
+        running.insert(1, U64(x).into_ddvalue()).unwrap();
+    }
+    running.transaction_commit().unwrap();
+
+    let desired: Vec<DDValue> = (5..15).map(|x| U64(x).into_ddvalue()).collect();
+    let updates = running.relation_sync_updates(1, desired).unwrap();
+    assert_eq!(updates.len(), 10);
+    assert!(updates[..5].iter().all(|u| !u.is_insert()));
+    assert!(updates[5..].iter().all(|u| u.is_insert()));
+
+    running.transaction_start().unwrap();
+    running
+        .apply_updates(updates.into_iter(), |_| Ok(()))
+        .unwrap();
+    running.transaction_commit().unwrap();
+
+    let expected: BTreeMap<_, _> = (5..15).map(|x| (U64(x), 1)).collect();
+    assert_eq!(*relset.lock().unwrap(), expected);
+
+    running.stop().unwrap();
+}
+
+/* Test `insert_or_update` on a multiset relation with a user-defined key.
+ */
+#[test]
+fn test_multiset_upsert() {
+    let relset: Arc<Mutex<Delta<Tuple2<U64>>>> = Arc::new(Mutex::new(BTreeMap::default()));
+    let rel = {
+        let relset1 = relset.clone();
+        Relation {
+            name: Cow::from("T1"),
+            input: true,
+            distinct: false,
+            caching_mode: CachingMode::Multiset,
+            key_func: None,
+            id: 1,
+            rules: Vec::new(),
+            arrangements: Vec::new(),
+            change_cb: Some(Arc::new(move |_, v, w| set_update("T1", &relset1, v, w))),
+        }
+    };
+
+    let prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel }],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    let mut running = prog.run(1).unwrap();
+    let tuple = |k, v| Tuple2(Box::new(U64(k)), Box::new(U64(v)));
+
+    /* 1. Upserts fail until a key extractor is registered */
+    running.transaction_start().unwrap();
+    running.insert(1, tuple(1, 10).into_ddvalue()).unwrap();
+    running.insert(1, tuple(1, 10).into_ddvalue()).unwrap();
+    running.insert(1, tuple(1, 11).into_ddvalue()).unwrap();
+    running.insert(1, tuple(2, 20).into_ddvalue()).unwrap();
+    assert!(running
+        .insert_or_update(1, tuple(1, 12).into_ddvalue())
+        .is_err());
+    running.transaction_commit().unwrap();
+
+    /* 2. Upsert replaces all values with the same key, including duplicates */
+    running
+        .set_upsert_key(
+            1,
+            Some(Arc::new(|v: &DDValue| {
+                (*Tuple2::<U64>::from_ddvalue_ref(v).0)
+                    .clone()
+                    .into_ddvalue()
+            })),
+        )
+        .unwrap();
+    running.transaction_start().unwrap();
+    running
+        .insert_or_update(1, tuple(1, 12).into_ddvalue())
+        .unwrap();
+    running
+        .insert_or_update(1, tuple(3, 30).into_ddvalue())
+        .unwrap();
+    running.transaction_commit().unwrap();
+
+    let expected: BTreeMap<_, _> = vec![(tuple(1, 12), 1), (tuple(2, 20), 1), (tuple(3, 30), 1)]
+        .into_iter()
+        .collect();
+    assert_eq!(*relset.lock().unwrap(), expected);
+
+    /* 3. Rollback restores the replaced values */
+    running.transaction_start().unwrap();
+    running
+        .insert_or_update(1, tuple(2, 21).into_ddvalue())
+        .unwrap();
+    running.transaction_rollback().unwrap();
+    running.transaction_start().unwrap();
+    running
+        .insert_or_update(1, tuple(2, 22).into_ddvalue())
+        .unwrap();
+    running.transaction_commit().unwrap();
+
+    let expected: BTreeMap<_, _> = vec![(tuple(1, 12), 1), (tuple(2, 22), 1), (tuple(3, 30), 1)]
+        .into_iter()
+        .collect();
+    assert_eq!(*relset.lock().unwrap(), expected);
+
+    running.stop().unwrap();
+}
+
 /* Two tables + 1 rule that keeps the two synchronized
  */
 fn test_two_relations(nthreads: usize) {
@@ -306,6 +499,9 @@ fn test_two_relations(nthreads: usize) {
     let prog: Program = Program {
         nodes: vec![ProgNode::Rel { rel: rel1 }, ProgNode::Rel { rel: rel2 }],
         delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
         init_data: vec![],
     };
 
@@ -454,6 +650,9 @@ fn test_semijoin(nthreads: usize) {
             ProgNode::Rel { rel: rel3 },
         ],
         delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
         init_data: vec![],
     };
 
@@ -615,6 +814,9 @@ fn test_join(nthreads: usize) {
             ProgNode::Rel { rel: rel4 },
         ],
         delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
         init_data: vec![],
     };
 
@@ -641,6 +843,10 @@ fn test_join(nthreads: usize) {
         rel2dump,
         vals.iter().map(|x| U64(*x).into_ddvalue()).collect()
     );
+    assert_eq!(
+        running.dump_arrangement_multiplicities((2, 0)).unwrap(),
+        vals.iter().map(|x| (U64(*x).into_ddvalue(), 1)).collect()
+    );
 
     for key in vals.iter() {
         let vals = running
@@ -886,6 +1092,9 @@ fn test_streamjoin(nthreads: usize) {
             ProgNode::Rel { rel: rel6 },
         ],
         delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
         init_data: vec![],
     };
 
@@ -1031,6 +1240,9 @@ fn test_antijoin(nthreads: usize) {
             ProgNode::Rel { rel: rel3 },
         ],
         delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
         init_data: vec![],
     };
 
@@ -1243,6 +1455,9 @@ fn test_map(nthreads: usize) {
             ProgNode::Rel { rel: rel4 },
         ],
         delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
         init_data: vec![],
     };
 
@@ -1288,6 +1503,1176 @@ fn test_map_multi() {
     test_map(16)
 }
 
+/* Random functions see the seed of the program that evaluates them, even
+ * while other programs with different seeds are running.
+ */
+fn test_random_seed(nthreads: usize) {
+    use differential_datalog::program::config::{self, Config};
+
+    fn mfun(v: DDValue) -> DDValue {
+        let &U64(uv) = U64::from_ddvalue_ref(&v);
+        U64(uv.wrapping_add(config::random_seed())).into_ddvalue()
+    }
+
+    let start = |seed: u64| {
+        let relset2: Arc<Mutex<Delta<U64>>> = Arc::new(Mutex::new(BTreeMap::default()));
+        let rel1 = Relation {
+            name: Cow::from("T1"),
+            input: true,
+            distinct: true,
+            caching_mode: CachingMode::Set,
+            key_func: None,
+            id: 1,
+            rules: Vec::new(),
+            arrangements: Vec::new(),
+            change_cb: None,
+        };
+        let rel2 = {
+            let relset2 = relset2.clone();
+            Relation {
+                name: Cow::from("T2"),
+                input: false,
+                distinct: true,
+                caching_mode: CachingMode::Set,
+                key_func: None,
+                id: 2,
+                rules: vec![Rule::CollectionRule {
+                    description: Cow::from("T2.R1"),
+                    rel: 1,
+                    xform: Some(XFormCollection::Map {
+                        description: Cow::from("map +seed"),
+                        mfun: mfun as MapFunc,
+                        next: Box::new(None),
+                    }),
+                }],
+                arrangements: Vec::new(),
+                change_cb: Some(Arc::new(move |_, v, w| set_update("T2", &relset2, v, w))),
+            }
+        };
+        let prog: Program = Program {
+            nodes: vec![ProgNode::Rel { rel: rel1 }, ProgNode::Rel { rel: rel2 }],
+            delayed_rels: vec![],
+            lazy_rels: vec![],
+            shared_arrangements: vec![],
+            columnar_rels: vec![],
+            init_data: vec![],
+        };
+        let config = Config {
+            num_timely_workers: nthreads,
+            random_seed: seed,
+            ..Config::new()
+        };
+        (prog.run_with_config(config).unwrap(), relset2)
+    };
+    let update = |running: &mut RunningProgram, v: u64, insert: bool| {
+        running.transaction_start().unwrap();
+        if insert {
+            running.insert(1, U64(v).into_ddvalue()).unwrap();
+        } else {
+            running.delete_value(1, U64(v).into_ddvalue()).unwrap();
+        }
+        running.transaction_commit().unwrap();
+    };
+
+    let (mut running1, relset1) = start(100);
+    update(&mut running1, 1, true);
+    let (mut running2, relset2) = start(200);
+    update(&mut running2, 1, true);
+    update(&mut running1, 2, true);
+
+    let expected1: Delta<U64> = [(U64(101), 1), (U64(102), 1)].iter().cloned().collect();
+    let expected2: Delta<U64> = [(U64(201), 1)].iter().cloned().collect();
+    assert_eq!(*relset1.lock().unwrap(), expected1);
+    assert_eq!(*relset2.lock().unwrap(), expected2);
+
+    /* Deleting an input retracts exactly the value derived from it. */
+    update(&mut running1, 1, false);
+    let expected1: Delta<U64> = [(U64(102), 1)].iter().cloned().collect();
+    assert_eq!(*relset1.lock().unwrap(), expected1);
+
+    running1.stop().unwrap();
+    running2.stop().unwrap();
+}
+
+#[test]
+fn test_random_seed_1() {
+    test_random_seed(1)
+}
+
+#[test]
+fn test_random_seed_multi() {
+    test_random_seed(16)
+}
+
+/* Panics in user functions poison the transaction.
+ */
+fn test_evaluation_panic(nthreads: usize) {
+    let rel1 = Relation {
+        name: Cow::from("T1"),
+        input: true,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 1,
+        rules: Vec::new(),
+        arrangements: Vec::new(),
+        change_cb: None,
+    };
+
+    fn mfun(v: DDValue) -> DDValue {
+        let &U64(uv) = U64::from_ddvalue_ref(&v);
+        if uv == 13 {
+            panic!("unlucky number");
+        }
+        U64(uv * 2).into_ddvalue()
+    }
+
+    let relset2: Arc<Mutex<Delta<U64>>> = Arc::new(Mutex::new(BTreeMap::default()));
+    let rel2 = {
+        let relset2 = relset2.clone();
+        Relation {
+            name: Cow::from("T2"),
+            input: false,
+            distinct: true,
+            caching_mode: CachingMode::Set,
+            key_func: None,
+            id: 2,
+            rules: vec![Rule::CollectionRule {
+                description: Cow::from("T2.R1"),
+                rel: 1,
+                xform: Some(XFormCollection::Map {
+                    description: Cow::from("map x2"),
+                    mfun: mfun as MapFunc,
+                    next: Box::new(None),
+                }),
+            }],
+            arrangements: Vec::new(),
+            change_cb: Some(Arc::new(move |_, v, w| set_update("T2", &relset2, v, w))),
+        }
+    };
+
+    let prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel: rel1 }, ProgNode::Rel { rel: rel2 }],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    let mut running = prog.run(nthreads).unwrap();
+
+    running.transaction_start().unwrap();
+    running.insert(1, U64(1).into_ddvalue()).unwrap();
+    running.insert(1, U64(2).into_ddvalue()).unwrap();
+    running.transaction_commit().unwrap();
+    let before = relset2.lock().unwrap().clone();
+
+    /* The panic poisons the transaction, which can only be rolled back. */
+    running.transaction_start().unwrap();
+    running.insert(1, U64(3).into_ddvalue()).unwrap();
+    running.insert(1, U64(13).into_ddvalue()).unwrap();
+    assert!(running.transaction_commit().is_err());
+    assert_eq!(
+        running.poisoned(),
+        Some(&DDlogError::EvaluationPanic {
+            rule: "map x2".to_string(),
+            message: "unlucky number".to_string(),
+        })
+    );
+    assert!(running.transaction_commit().is_err());
+    running.transaction_rollback().unwrap();
+    assert_eq!(running.poisoned(), None);
+    assert_eq!(*relset2.lock().unwrap(), before);
+
+    /* The program keeps working after the rollback. */
+    running.transaction_start().unwrap();
+    running.insert(1, U64(3).into_ddvalue()).unwrap();
+    running.transaction_commit().unwrap();
+    let expected: BTreeMap<_, _> = [2, 4, 6].iter().map(|x| (U64(*x), 1)).collect();
+    assert_eq!(*relset2.lock().unwrap(), expected);
+
+    running.stop().unwrap();
+}
+
+#[test]
+fn test_evaluation_panic_1() {
+    test_evaluation_panic(1)
+}
+
+#[test]
+fn test_evaluation_panic_multi() {
+    test_evaluation_panic(16)
+}
+
+/* Prepared transactions can be committed or rolled back, but not modified.
+ * Their outputs are only delivered when they are committed.
+ */
+#[test]
+fn test_transaction_prepare() {
+    let relset1: Arc<Mutex<Delta<U64>>> = Arc::new(Mutex::new(BTreeMap::default()));
+    let rel1 = {
+        let relset1 = relset1.clone();
+        Relation {
+            name: Cow::from("T1"),
+            input: true,
+            distinct: true,
+            caching_mode: CachingMode::Set,
+            key_func: None,
+            id: 1,
+            rules: Vec::new(),
+            arrangements: Vec::new(),
+            change_cb: Some(Arc::new(move |_, v, w| set_update("T1", &relset1, v, w))),
+        }
+    };
+    let prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel: rel1 }],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    let mut running = prog.run(2).unwrap();
+    let expected: Delta<U64> = [(U64(1), 1)].iter().cloned().collect();
+
+    /* Output changes of a prepared transaction are held back until it is
+     * committed. */
+    running.transaction_start().unwrap();
+    running.insert(1, U64(1).into_ddvalue()).unwrap();
+    running.transaction_prepare().unwrap();
+    assert!(relset1.lock().unwrap().is_empty());
+    assert_eq!(
+        running.prepared_output_changes(1).unwrap(),
+        [(U64(1).into_ddvalue(), 1)].iter().cloned().collect()
+    );
+    assert!(running.insert(1, U64(2).into_ddvalue()).is_err());
+    running.transaction_commit().unwrap();
+    assert_eq!(*relset1.lock().unwrap(), expected);
+    assert!(running.prepared_output_changes(1).unwrap().is_empty());
+
+    /* Rolling back a prepared transaction drops its outputs. */
+    running.transaction_start().unwrap();
+    running.insert(1, U64(3).into_ddvalue()).unwrap();
+    running.transaction_prepare().unwrap();
+    running.transaction_rollback().unwrap();
+    assert_eq!(*relset1.lock().unwrap(), expected);
+
+    /* Later transactions are delivered as usual. */
+    running.transaction_start().unwrap();
+    running.insert(1, U64(4).into_ddvalue()).unwrap();
+    running.transaction_commit().unwrap();
+    let expected: Delta<U64> = [(U64(1), 1), (U64(4), 1)].iter().cloned().collect();
+    assert_eq!(*relset1.lock().unwrap(), expected);
+
+    running.stop().unwrap();
+}
+
+/* A transaction committed with the id of an earlier transaction is discarded.
+ */
+#[test]
+fn test_transaction_commit_with_id() {
+    use differential_datalog::program::config::Config;
+
+    let relset1: Arc<Mutex<Delta<U64>>> = Arc::new(Mutex::new(BTreeMap::default()));
+    let rel1 = {
+        let relset1 = relset1.clone();
+        Relation {
+            name: Cow::from("T1"),
+            input: true,
+            distinct: false,
+            caching_mode: CachingMode::Multiset,
+            key_func: None,
+            id: 1,
+            rules: Vec::new(),
+            arrangements: Vec::new(),
+            change_cb: Some(Arc::new(move |_, v, w| set_update("T1", &relset1, v, w))),
+        }
+    };
+    let prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel: rel1 }],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    let config = Config {
+        txn_id_history: 2,
+        ..Config::new()
+    };
+    let mut running = prog.run_with_config(config).unwrap();
+    let expected: Delta<U64> = [(U64(1), 1), (U64(2), 1)].iter().cloned().collect();
+
+    for (id, v) in [("a", 1), ("b", 2)].iter() {
+        running.transaction_start().unwrap();
+        running.insert(1, U64(*v).into_ddvalue()).unwrap();
+        assert!(running.transaction_commit_with_id(id).unwrap());
+    }
+    assert_eq!(*relset1.lock().unwrap(), expected);
+
+    /* Replaying transaction "a" has no effect. */
+    running.transaction_start().unwrap();
+    running.insert(1, U64(1).into_ddvalue()).unwrap();
+    assert!(!running.transaction_commit_with_id("a").unwrap());
+    assert_eq!(*relset1.lock().unwrap(), expected);
+
+    /* Only the last two ids are remembered. */
+    running.transaction_start().unwrap();
+    assert!(running.transaction_commit_with_id("c").unwrap());
+    assert_eq!(
+        running.committed_transaction_ids(),
+        vec!["b".to_string(), "c".to_string()]
+    );
+
+    running.stop().unwrap();
+}
+
+/* Queries return the current contents of an arrangement regardless of its
+ * compaction settings.
+ */
+#[test]
+fn test_compaction_lag() {
+    use differential_datalog::program::compaction::CompactionPolicy;
+
+    fn afun(v: DDValue) -> Option<(DDValue, DDValue)> {
+        Some((v.clone(), v))
+    }
+    let rel1 = Relation {
+        name: Cow::from("T1"),
+        input: true,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 1,
+        rules: Vec::new(),
+        arrangements: vec![Arrangement::Map {
+            name: Cow::from("arrange1.0"),
+            afun: afun as ArrangeFunc,
+            queryable: true,
+        }],
+        change_cb: None,
+    };
+    let prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel: rel1 }],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    let mut running = prog.run(2).unwrap();
+    running.set_compaction_lag((1, 0), 5).unwrap();
+    assert_eq!(running.compaction_lag((1, 0)), 5);
+    running.set_compaction_policy(Some(CompactionPolicy { idle_timestamps: 2 }));
+
+    fn expected(vals: &[u64]) -> BTreeSet<DDValue> {
+        vals.iter().map(|v| U64(*v).into_ddvalue()).collect()
+    }
+    for i in 0..10 {
+        running.transaction_start().unwrap();
+        running.insert(1, U64(i).into_ddvalue()).unwrap();
+        if i > 0 {
+            running.delete_value(1, U64(i - 1).into_ddvalue()).unwrap();
+        }
+        running.transaction_commit().unwrap();
+        if i % 4 == 0 {
+            assert_eq!(running.dump_arrangement((1, 0)).unwrap(), expected(&[i]));
+        }
+    }
+    assert_eq!(
+        running
+            .query_arrangement((1, 0), U64(9).into_ddvalue())
+            .unwrap(),
+        expected(&[9])
+    );
+
+    running.compact_now(Some((1, 0))).unwrap();
+    running.compact_now(None).unwrap();
+    assert_eq!(running.dump_arrangement((1, 0)).unwrap(), expected(&[9]));
+
+    running.stop().unwrap();
+}
+
+/* Lazy relations are only computed while being queried.
+ */
+#[test]
+fn test_lazy_relation() {
+    let rel1 = Relation {
+        name: Cow::from("T1"),
+        input: true,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 1,
+        rules: Vec::new(),
+        arrangements: Vec::new(),
+        change_cb: None,
+    };
+
+    fn mfun(v: DDValue) -> DDValue {
+        let &U64(uv) = U64::from_ddvalue_ref(&v);
+        U64(uv * 2).into_ddvalue()
+    }
+    fn afun(v: DDValue) -> Option<(DDValue, DDValue)> {
+        Some((v.clone(), v))
+    }
+
+    let relset2: Arc<Mutex<Delta<U64>>> = Arc::new(Mutex::new(BTreeMap::default()));
+    let rel2 = {
+        let relset2 = relset2.clone();
+        Relation {
+            name: Cow::from("T2"),
+            input: false,
+            distinct: true,
+            caching_mode: CachingMode::Set,
+            key_func: None,
+            id: 2,
+            rules: vec![Rule::CollectionRule {
+                description: Cow::from("T2.R1"),
+                rel: 1,
+                xform: Some(XFormCollection::Map {
+                    description: Cow::from("map x2"),
+                    mfun: mfun as MapFunc,
+                    next: Box::new(None),
+                }),
+            }],
+            arrangements: vec![Arrangement::Map {
+                name: Cow::from("arrange2.0"),
+                afun: afun as ArrangeFunc,
+                queryable: true,
+            }],
+            change_cb: Some(Arc::new(move |_, v, w| set_update("T2", &relset2, v, w))),
+        }
+    };
+    let prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel: rel1 }, ProgNode::Rel { rel: rel2 }],
+        delayed_rels: vec![],
+        lazy_rels: vec![2],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    let mut running = prog.run(2).unwrap();
+    running.transaction_start().unwrap();
+    for i in 0..5 {
+        running.insert(1, U64(i).into_ddvalue()).unwrap();
+    }
+    // Lazy relations cannot be queried in the middle of a transaction.
+    assert!(running.dump_arrangement((2, 0)).is_err());
+    running.transaction_commit().unwrap();
+
+    // The relation is not computed until it is queried.
+    assert_eq!(relset2.lock().unwrap().len(), 0);
+    let expected: BTreeSet<DDValue> = (0..5).map(|i| U64(i * 2).into_ddvalue()).collect();
+    assert_eq!(running.dump_arrangement((2, 0)).unwrap(), expected);
+    assert_eq!(
+        running
+            .query_arrangement((2, 0), U64(4).into_ddvalue())
+            .unwrap(),
+        vec![U64(4).into_ddvalue()].into_iter().collect()
+    );
+    // ... and its contents are retracted after the query.
+    assert_eq!(relset2.lock().unwrap().len(), 0);
+
+    running.stop().unwrap();
+}
+
+/* Duplicate arrangements are reported and can be shared.
+ */
+#[test]
+fn test_shared_arrangements() {
+    use differential_datalog::program::config::Config;
+
+    fn afun1(v: DDValue) -> Option<(DDValue, DDValue)> {
+        Some((v.clone(), v))
+    }
+    fn afun2(v: DDValue) -> Option<(DDValue, DDValue)> {
+        Some((v.clone(), v))
+    }
+    let rel1 = Relation {
+        name: Cow::from("T1"),
+        input: true,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 1,
+        rules: Vec::new(),
+        arrangements: vec![
+            Arrangement::Map {
+                name: Cow::from("(T1{_0}: T1) /*join*/"),
+                afun: afun1 as ArrangeFunc,
+                queryable: true,
+            },
+            Arrangement::Map {
+                name: Cow::from("(T1{_0}: T1) /*join*/"),
+                afun: afun1 as ArrangeFunc,
+                queryable: true,
+            },
+            Arrangement::Map {
+                name: Cow::from("(T1{_0}: T1) /*join*/"),
+                afun: afun2 as ArrangeFunc,
+                queryable: true,
+            },
+        ],
+        change_cb: None,
+    };
+    let mut prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel: rel1 }],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    let report = prog.arrangement_report();
+    assert_eq!(report.len(), 2);
+    assert_eq!(
+        (report[0].duplicate, report[0].arrangement),
+        ((1, 1), (1, 0))
+    );
+    assert!(report[0].exact);
+    assert_eq!(
+        (report[1].duplicate, report[1].arrangement),
+        ((1, 2), (1, 0))
+    );
+    assert!(!report[1].exact);
+
+    // Arrangements can only share arrangements of the same relation.
+    prog.shared_arrangements = vec![((1, 2), (2, 0))];
+    assert!(prog.run(1).is_err());
+
+    // Share the exact duplicate automatically and force sharing the near
+    // duplicate.
+    prog.shared_arrangements = vec![((1, 2), (1, 0))];
+    let config = Config {
+        share_duplicate_arrangements: true,
+        ..Config::new()
+    };
+    let mut running = prog.run_with_config(config).unwrap();
+    assert!(running.arrangement_report().iter().all(|dup| dup.shared));
+
+    running.transaction_start().unwrap();
+    running.insert(1, U64(1).into_ddvalue()).unwrap();
+    running.transaction_commit().unwrap();
+    let expected: BTreeSet<DDValue> = vec![U64(1).into_ddvalue()].into_iter().collect();
+    for arr in 0..3 {
+        assert_eq!(running.dump_arrangement((1, arr)).unwrap(), expected);
+    }
+
+    running.stop().unwrap();
+}
+
+/* The plan of a program shows relations, arrangements and operators.
+ */
+#[test]
+fn test_explain_plan() {
+    use differential_datalog::program::plan::{PlanFormat, PlanNodeKind};
+
+    fn afun(v: DDValue) -> Option<(DDValue, DDValue)> {
+        Some((v.clone(), v))
+    }
+    fn jfun(_key: &DDValue, v1: &DDValue, _v2: &DDValue) -> Option<DDValue> {
+        Some(v1.clone())
+    }
+    let rel1 = Relation {
+        name: Cow::from("T1"),
+        input: true,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 1,
+        rules: Vec::new(),
+        arrangements: vec![Arrangement::Map {
+            name: Cow::from("(T1{_0}: T1) /*join*/"),
+            afun: afun as ArrangeFunc,
+            queryable: false,
+        }],
+        change_cb: None,
+    };
+    let rel2 = Relation {
+        name: Cow::from("T2"),
+        input: true,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 2,
+        rules: Vec::new(),
+        arrangements: Vec::new(),
+        change_cb: None,
+    };
+    let rel3 = Relation {
+        name: Cow::from("T3"),
+        input: false,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 3,
+        rules: vec![Rule::CollectionRule {
+            description: Cow::from("T3.R1"),
+            rel: 2,
+            xform: Some(XFormCollection::Arrange {
+                description: Cow::from("arrange T2 by self"),
+                afun: afun as ArrangeFunc,
+                next: Box::new(XFormArrangement::Join {
+                    description: Cow::from("T2 join T1"),
+                    ffun: None,
+                    arrangement: (1, 0),
+                    jfun: jfun as JoinFunc,
+                    next: Box::new(None),
+                }),
+            }),
+        }],
+        arrangements: Vec::new(),
+        change_cb: None,
+    };
+    let prog: Program = Program {
+        nodes: vec![
+            ProgNode::Rel { rel: rel1 },
+            ProgNode::Rel { rel: rel2 },
+            ProgNode::Rel { rel: rel3 },
+        ],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    let plan = prog.plan();
+    let labels: Vec<&str> = plan.nodes.iter().map(|node| node.label.as_str()).collect();
+    assert_eq!(
+        labels,
+        vec![
+            "T1",
+            "(T1{_0}: T1) /*join*/",
+            "T2",
+            "T3",
+            "arrange T2 by self",
+            "T2 join T1"
+        ]
+    );
+    assert_eq!(
+        plan.nodes[1].kind,
+        PlanNodeKind::Arrangement {
+            arrid: (1, 0),
+            queryable: false
+        }
+    );
+    assert_eq!(plan.edges, vec![(0, 1), (2, 4), (4, 5), (1, 5), (5, 3)]);
+
+    let mut running = prog.run(1).unwrap();
+    running.transaction_start().unwrap();
+    for i in 0..3 {
+        running.insert(1, U64(i).into_ddvalue()).unwrap();
+    }
+    running.transaction_commit().unwrap();
+
+    let json = running.explain_plan(PlanFormat::Json);
+    assert!(json.contains(
+        r#"{"id":0,"kind":"relation","label":"T1","relid":1,"input":true,"recursive":false,"size":3}"#
+    ));
+    let dot = running.explain_plan(PlanFormat::Dot);
+    assert!(dot.starts_with("digraph plan {"));
+    assert!(dot.contains("n1 -> n5;"));
+
+    running.stop().unwrap();
+}
+
+/* Statistics count the changes to input and derived relations.
+ */
+#[test]
+fn test_relation_stats() {
+    use differential_datalog::program::config::Config;
+    use differential_datalog::program::stats::RelationStats;
+
+    let rel1 = Relation {
+        name: Cow::from("T1"),
+        input: true,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 1,
+        rules: Vec::new(),
+        arrangements: Vec::new(),
+        change_cb: None,
+    };
+    fn mfun(v: DDValue) -> DDValue {
+        let &U64(uv) = U64::from_ddvalue_ref(&v);
+        U64(uv / 2).into_ddvalue()
+    }
+    let rel2 = Relation {
+        name: Cow::from("T2"),
+        input: false,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 2,
+        rules: vec![Rule::CollectionRule {
+            description: Cow::from("T2.R1"),
+            rel: 1,
+            xform: Some(XFormCollection::Map {
+                description: Cow::from("map /2"),
+                mfun: mfun as MapFunc,
+                next: Box::new(None),
+            }),
+        }],
+        arrangements: Vec::new(),
+        change_cb: None,
+    };
+    let prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel: rel1 }, ProgNode::Rel { rel: rel2 }],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    // Neither relation has a change callback, so neither is counted by
+    // default.
+    let mut running = prog.run(2).unwrap();
+    running.transaction_start().unwrap();
+    running.insert(1, U64(0).into_ddvalue()).unwrap();
+    running.transaction_commit().unwrap();
+    assert!(running.all_relation_stats().is_empty());
+    running.stop().unwrap();
+
+    let config = Config {
+        num_timely_workers: 2,
+        relation_stats: true,
+        ..Default::default()
+    };
+    let mut running = prog.run_with_config(config).unwrap();
+    running.transaction_start().unwrap();
+    for i in 0..4 {
+        running.insert(1, U64(i).into_ddvalue()).unwrap();
+    }
+    running.transaction_commit().unwrap();
+    assert_eq!(
+        running.relation_stats(2),
+        Some(RelationStats {
+            size: 2,
+            inserts: 2,
+            deletes: 0,
+            last_commit_delta: 2,
+        })
+    );
+
+    running.transaction_start().unwrap();
+    running.delete_value(1, U64(0).into_ddvalue()).unwrap();
+    running.delete_value(1, U64(1).into_ddvalue()).unwrap();
+    running.transaction_commit().unwrap();
+    assert_eq!(
+        running.relation_stats(1),
+        Some(RelationStats {
+            size: 2,
+            inserts: 4,
+            deletes: 2,
+            last_commit_delta: 2,
+        })
+    );
+    assert_eq!(
+        running.relation_stats(2),
+        Some(RelationStats {
+            size: 1,
+            inserts: 2,
+            deletes: 1,
+            last_commit_delta: 1,
+        })
+    );
+    assert_eq!(running.all_relation_stats().len(), 2);
+    assert_eq!(running.relation_stats(3), None);
+
+    running.stop().unwrap();
+}
+
+/* Commits within the latency budget are deferred, and coalesced updates
+ * that cancel out never reach the dataflow.
+ */
+#[test]
+fn test_commit_batching() {
+    use differential_datalog::program::config::Config;
+    use std::time::Duration;
+
+    let rel1 = Relation {
+        name: Cow::from("T1"),
+        input: true,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 1,
+        rules: Vec::new(),
+        arrangements: Vec::new(),
+        change_cb: None,
+    };
+    let set2: Arc<Mutex<Delta<U64>>> = Arc::new(Mutex::new(Delta::new()));
+    let rel2 = {
+        let set2 = set2.clone();
+        Relation {
+            name: Cow::from("T2"),
+            input: false,
+            distinct: true,
+            caching_mode: CachingMode::Set,
+            key_func: None,
+            id: 2,
+            rules: vec![Rule::CollectionRule {
+                description: Cow::from("T2.R1"),
+                rel: 1,
+                xform: None,
+            }],
+            arrangements: Vec::new(),
+            change_cb: Some(Arc::new(move |_, v, w| set_update("T2", &set2, v, w))),
+        }
+    };
+    let prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel: rel1 }, ProgNode::Rel { rel: rel2 }],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    let config = Config {
+        num_timely_workers: 2,
+        coalesce_updates: true,
+        commit_latency_budget: Some(Duration::from_secs(3600)),
+        relation_stats: true,
+        ..Default::default()
+    };
+    let mut running = prog.run_with_config(config).unwrap();
+
+    running.transaction_start().unwrap();
+    running.insert(1, U64(1).into_ddvalue()).unwrap();
+    running.insert(1, U64(2).into_ddvalue()).unwrap();
+    running.transaction_commit().unwrap();
+
+    running.transaction_start().unwrap();
+    running.delete_value(1, U64(1).into_ddvalue()).unwrap();
+    running.transaction_commit().unwrap();
+
+    // Both commits are deferred.
+    assert!(set2.lock().unwrap().is_empty());
+    running.flush_deferred().unwrap();
+    assert_eq!(
+        *set2.lock().unwrap(),
+        vec![(U64(2), 1)].into_iter().collect::<Delta<U64>>()
+    );
+    // The insertion and deletion of `1` cancelled out.
+    assert_eq!(running.relation_stats(1).unwrap().inserts, 1);
+
+    running.stop().unwrap();
+}
+
+/* Bulk-loaded values are propagated by the first transaction.
+ */
+#[test]
+fn test_bulk_load() {
+    let rel1 = Relation {
+        name: Cow::from("T1"),
+        input: true,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 1,
+        rules: Vec::new(),
+        arrangements: Vec::new(),
+        change_cb: None,
+    };
+    let set2: Arc<Mutex<Delta<U64>>> = Arc::new(Mutex::new(Delta::new()));
+    let rel2 = {
+        let set2 = set2.clone();
+        Relation {
+            name: Cow::from("T2"),
+            input: false,
+            distinct: true,
+            caching_mode: CachingMode::Set,
+            key_func: None,
+            id: 2,
+            rules: vec![Rule::CollectionRule {
+                description: Cow::from("T2.R1"),
+                rel: 1,
+                xform: None,
+            }],
+            arrangements: Vec::new(),
+            change_cb: Some(Arc::new(move |_, v, w| set_update("T2", &set2, v, w))),
+        }
+    };
+    let prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel: rel1 }, ProgNode::Rel { rel: rel2 }],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![],
+    };
+
+    let mut running = prog.run(2).unwrap();
+    running
+        .bulk_load(1, (0..1000).map(|i| U64(i).into_ddvalue()))
+        .unwrap();
+    // Duplicates are ignored.
+    running
+        .bulk_load(1, (0..10).map(|i| U64(i).into_ddvalue()))
+        .unwrap();
+    assert!(running.bulk_load(2, vec![]).is_err());
+
+    running.transaction_start().unwrap();
+    running.delete_value(1, U64(0).into_ddvalue()).unwrap();
+    running.transaction_commit().unwrap();
+    assert_eq!(
+        *set2.lock().unwrap(),
+        (1..1000).map(|i| (U64(i), 1)).collect::<Delta<U64>>()
+    );
+
+    assert!(running.bulk_load(1, vec![U64(0).into_ddvalue()]).is_err());
+
+    running.stop().unwrap();
+}
+
+/* Columnar input relations behave like other input relations.
+ */
+#[test]
+fn test_columnar_relation() {
+    use differential_datalog::program::columnar::ColumnarDecodeFunc;
+    use differential_datalog::record::{FromRecord, Record};
+
+    fn decode(record: &Record) -> Result<DDValue, String> {
+        U64::from_record(record).map(|v| v.into_ddvalue())
+    }
+
+    let rel1 = Relation {
+        name: Cow::from("T1"),
+        input: true,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 1,
+        rules: Vec::new(),
+        arrangements: Vec::new(),
+        change_cb: None,
+    };
+    let set2: Arc<Mutex<Delta<U64>>> = Arc::new(Mutex::new(Delta::new()));
+    let rel2 = {
+        let set2 = set2.clone();
+        Relation {
+            name: Cow::from("T2"),
+            input: false,
+            distinct: true,
+            caching_mode: CachingMode::Set,
+            key_func: None,
+            id: 2,
+            rules: vec![Rule::CollectionRule {
+                description: Cow::from("T2.R1"),
+                rel: 1,
+                xform: None,
+            }],
+            arrangements: Vec::new(),
+            change_cb: Some(Arc::new(move |_, v, w| set_update("T2", &set2, v, w))),
+        }
+    };
+    let mut prog: Program = Program {
+        nodes: vec![ProgNode::Rel { rel: rel1 }, ProgNode::Rel { rel: rel2 }],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![(2, decode as ColumnarDecodeFunc)],
+        init_data: vec![],
+    };
+    assert!(prog.run(1).is_err());
+
+    prog.columnar_rels = vec![(1, decode as ColumnarDecodeFunc)];
+    let mut running = prog.run(2).unwrap();
+    running.transaction_start().unwrap();
+    for i in 0..10 {
+        running.insert(1, U64(i).into_ddvalue()).unwrap();
+    }
+    // Set semantics are preserved.
+    running.insert(1, U64(0).into_ddvalue()).unwrap();
+    running.delete_value(1, U64(3).into_ddvalue()).unwrap();
+    running.transaction_commit().unwrap();
+
+    let expected: Delta<U64> = (0..10).filter(|i| *i != 3).map(|i| (U64(i), 1)).collect();
+    assert_eq!(*set2.lock().unwrap(), expected);
+    let mut stored: Vec<DDValue> = running.get_input_columnar_data(1).unwrap().iter().collect();
+    stored.sort();
+    assert_eq!(
+        stored,
+        expected
+            .keys()
+            .map(|v| v.clone().into_ddvalue())
+            .collect::<Vec<_>>()
+    );
+
+    running.transaction_start().unwrap();
+    running.clear_relation(1).unwrap();
+    running.transaction_commit().unwrap();
+    assert!(set2.lock().unwrap().is_empty());
+    assert!(running.get_input_columnar_data(1).unwrap().is_empty());
+
+    running.stop().unwrap();
+}
+
+/* Multi-tenant mode: facts of different tenants do not interact.
+ */
+fn test_multi_tenant(nthreads: usize) {
+    use differential_datalog::program::config::Config;
+    use differential_datalog::program::tenant::Tenanted;
+
+    let rel1 = Relation {
+        name: Cow::from("T1"),
+        input: true,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 1,
+        rules: Vec::new(),
+        arrangements: Vec::new(),
+        change_cb: None,
+    };
+
+    fn mfun(v: DDValue) -> DDValue {
+        let &U64(uv) = U64::from_ddvalue_ref(&v);
+        U64(uv * 2).into_ddvalue()
+    }
+
+    let rel3 = Relation {
+        name: Cow::from("T3"),
+        input: false,
+        distinct: true,
+        caching_mode: CachingMode::Set,
+        key_func: None,
+        id: 3,
+        rules: Vec::new(),
+        arrangements: Vec::new(),
+        change_cb: None,
+    };
+
+    let relset2: Arc<Mutex<Delta<Tenanted>>> = Arc::new(Mutex::new(BTreeMap::default()));
+    let rel2 = {
+        let relset2 = relset2.clone();
+        Relation {
+            name: Cow::from("T2"),
+            input: false,
+            distinct: true,
+            caching_mode: CachingMode::Set,
+            key_func: None,
+            id: 2,
+            rules: vec![
+                Rule::CollectionRule {
+                    description: Cow::from("T2.R1"),
+                    rel: 1,
+                    xform: Some(XFormCollection::Map {
+                        description: Cow::from("map x2"),
+                        mfun: mfun as MapFunc,
+                        next: Box::new(None),
+                    }),
+                },
+                Rule::CollectionRule {
+                    description: Cow::from("T2.R2"),
+                    rel: 3,
+                    xform: None,
+                },
+            ],
+            arrangements: Vec::new(),
+            change_cb: Some(Arc::new(move |_, v, w| set_update("T2", &relset2, v, w))),
+        }
+    };
+
+    let prog: Program = Program {
+        nodes: vec![
+            ProgNode::Rel { rel: rel1 },
+            ProgNode::Rel { rel: rel3 },
+            ProgNode::Rel { rel: rel2 },
+        ],
+        delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
+        init_data: vec![(3, U64(100).into_ddvalue())],
+    };
+
+    let config = Config {
+        num_timely_workers: nthreads,
+        multi_tenant: true,
+        ..Default::default()
+    };
+    let mut running = prog.run_with_config(config).unwrap();
+    let expected = |facts: &[(u64, u64)]| -> Delta<Tenanted> {
+        facts
+            .iter()
+            .map(|(tenant, x)| {
+                let value = U64(*x).into_ddvalue();
+                (
+                    Tenanted {
+                        tenant: *tenant,
+                        value,
+                    },
+                    1,
+                )
+            })
+            .collect()
+    };
+
+    /* Initial data is added for each tenant once it updates the program. */
+    assert_eq!(*relset2.lock().unwrap(), expected(&[]));
+    running.transaction_start().unwrap();
+    for tenant in 1..=2 {
+        let updates = vec![Update::Insert {
+            relid: 1,
+            v: U64(1).into_ddvalue(),
+        }];
+        running
+            .apply_tenant_updates(tenant, updates.into_iter(), |_| Ok(()))
+            .unwrap();
+    }
+    running.transaction_commit().unwrap();
+    assert_eq!(
+        *relset2.lock().unwrap(),
+        expected(&[(1, 2), (1, 100), (2, 2), (2, 100)])
+    );
+
+    /* Deleting a fact of one tenant does not affect the other. */
+    running.transaction_start().unwrap();
+    let updates = vec![Update::DeleteValue {
+        relid: 1,
+        v: U64(1).into_ddvalue(),
+    }];
+    running
+        .apply_tenant_updates(1, updates.into_iter(), |_| Ok(()))
+        .unwrap();
+    running.transaction_commit().unwrap();
+    assert_eq!(
+        *relset2.lock().unwrap(),
+        expected(&[(1, 100), (2, 2), (2, 100)])
+    );
+
+    /* Updates must be issued on behalf of a tenant. */
+    running.transaction_start().unwrap();
+    assert!(running.insert(1, U64(5).into_ddvalue()).is_err());
+    running.clear_tenant_relation(2, 1).unwrap();
+    running.transaction_commit().unwrap();
+    assert_eq!(*relset2.lock().unwrap(), expected(&[(1, 100), (2, 100)]));
+
+    running.stop().unwrap();
+}
+
+#[test]
+fn test_multi_tenant_1() {
+    test_multi_tenant(1)
+}
+
+#[test]
+fn test_multi_tenant_multi() {
+    test_multi_tenant(16)
+}
+
 /* Delayed relations.
  */
 fn test_delayed(nthreads: usize) {
@@ -1429,6 +2814,9 @@ fn test_delayed(nthreads: usize) {
             rel_id: 1,
             delay: 1,
         }],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
         init_data: vec![],
     };
 
@@ -1660,6 +3048,9 @@ fn test_recursion(nthreads: usize) {
             },
         ],
         delayed_rels: vec![],
+        lazy_rels: vec![],
+        shared_arrangements: vec![],
+        columnar_rels: vec![],
         init_data: vec![],
     };
 
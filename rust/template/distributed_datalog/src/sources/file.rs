@@ -52,7 +52,7 @@ fn handle<C>(
                 .on_start()
                 .map_err(|e| error!("observer failed on_start: {:?}", e));
         }
-        Command::Commit(_) => {
+        Command::Commit(_) | Command::CommitWithId(_) => {
             let _ = observer
                 .on_commit()
                 .map_err(|e| error!("observer failed on_commit: {:?}", e));
@@ -0,0 +1,694 @@
+//! Deliver alerts derived by the program as webhooks or email.
+//!
+//! `AlertSink` watches a designated output relation, e.g.,
+//!
+//! ```text
+//! output relation Alert(severity: string, host: string, summary: string)
+//! ```
+//!
+//! and turns every record inserted into it into a notification sent to each
+//! configured `AlertChannel`: an HTTP POST to a webhook, a message to a Slack
+//! incoming webhook, or an email sent through an SMTP relay.  When
+//! `AlertSinkConfig::notify_resolved` is set, records deleted from the
+//! relation are delivered as well, as resolved alerts.
+//!
+//! Messages are rendered from Jinja2-style templates
+//! (https://docs.rs/minijinja) with the following variables:
+//!
+//! * `relation`: the name of the relation.
+//! * `alert`: the record, serialized to JSON; fields of structs are
+//!   available as `alert.<field>`.
+//! * `text`: the text form of the record.
+//! * `resolved`: `true` if the record was deleted from the relation.
+//! * `commit`: the sequence number of the commit (see `ChangelogBatch`).
+//! * `timestamp`: the time of the commit, in seconds since the Unix epoch.
+//!
+//! Alerts are queued by the commit and delivered by a background thread,
+//! so commits never wait for the network.  A delivery that fails is retried
+//! `AlertSinkConfig::retries` times with exponential backoff, and
+//! `AlertSinkConfig::rate_limit` caps the number of alerts delivered per
+//! time window, so that a burst of alerts does not flood the receivers;
+//! alerts over the limit, or that do not fit in the queue, are dropped and
+//! counted in `AlertStats`.  Requires the `alerts` feature.
+//!
+//! ```ignore
+//! let sink = AlertSink::attach(
+//!     &hddlog,
+//!     "Alert",
+//!     AlertSinkConfig {
+//!         channels: vec![AlertChannel::Slack {
+//!             webhook_url: "https://hooks.slack.com/services/...".to_string(),
+//!             template: "[{{ alert.severity }}] {{ alert.host }}: {{ alert.summary }}".to_string(),
+//!         }],
+//!         ..AlertSinkConfig::default()
+//!     },
+//! )?;
+//! ```
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use minijinja::{Environment, UndefinedBehavior};
+use serde_json::{json, Value};
+
+use differential_datalog::program::RelId;
+use differential_datalog::record::IntoRecord;
+
+use crate::api::{CommitSubscriptionId, HDDlog};
+use crate::update_handler::ChangelogBatch;
+use crate::Relations;
+
+/// Email delivery through an SMTP relay.
+#[derive(Debug, Clone)]
+pub struct EmailConfig {
+    /// Host name of the SMTP relay.  Connections use TLS.
+    pub relay: String,
+    /// Port of the relay; by default, the submission port (465).
+    pub port: Option<u16>,
+    /// User name and password to authenticate with.
+    pub credentials: Option<(String, String)>,
+    /// Sender, e.g., `DDlog <alerts@example.com>`.
+    pub from: String,
+    /// Recipients.
+    pub to: Vec<String>,
+    /// Template of the subject.
+    pub subject: String,
+    /// Template of the plain-text body.
+    pub body: String,
+}
+
+/// Destination of alerts.
+#[derive(Debug, Clone)]
+pub enum AlertChannel {
+    /// POST the alert to `url`.  The body is rendered from `template`, or is
+    /// a JSON object with the template variables if `template` is `None`.
+    Webhook {
+        url: String,
+        /// Extra HTTP headers, e.g., for authentication.
+        headers: Vec<(String, String)>,
+        /// Value of the `Content-Type` header.
+        content_type: String,
+        template: Option<String>,
+    },
+    /// Post a message rendered from `template` to a Slack incoming webhook.
+    Slack {
+        webhook_url: String,
+        template: String,
+    },
+    Email(EmailConfig),
+}
+
+/// Configuration of an alert sink.
+#[derive(Debug, Clone)]
+pub struct AlertSinkConfig {
+    pub channels: Vec<AlertChannel>,
+    /// Also deliver records deleted from the relation, with `resolved` set.
+    pub notify_resolved: bool,
+    /// Number of times a failed delivery is retried.
+    pub retries: u32,
+    /// Delay before the first retry; doubles with every retry.
+    pub retry_delay: Duration,
+    /// Timeout of HTTP requests.
+    pub timeout: Duration,
+    /// Deliver at most this many alerts per time window.
+    pub rate_limit: Option<(usize, Duration)>,
+    /// Maximal number of alerts waiting to be delivered.
+    pub queue_capacity: usize,
+}
+
+impl Default for AlertSinkConfig {
+    fn default() -> Self {
+        Self {
+            channels: Vec::new(),
+            notify_resolved: false,
+            retries: 3,
+            retry_delay: Duration::from_secs(1),
+            timeout: Duration::from_secs(10),
+            rate_limit: None,
+            queue_capacity: 10000,
+        }
+    }
+}
+
+/// Delivery statistics of an alert sink.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AlertStats {
+    /// Alerts delivered to all channels.
+    pub delivered: u64,
+    /// Alerts that could not be delivered to at least one channel.
+    pub failed: u64,
+    /// Alerts dropped because of the rate limit.
+    pub rate_limited: u64,
+    /// Alerts dropped because the queue was full.
+    pub overflowed: u64,
+}
+
+#[derive(Default)]
+struct Queue {
+    /// Template variables of alerts waiting to be delivered.
+    alerts: VecDeque<Value>,
+    stats: AlertStats,
+    /// The last delivery error.
+    error: Option<String>,
+    stop: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    wakeup: Condvar,
+}
+
+/* A channel ready for delivery, with the names of its templates. */
+enum Channel {
+    Webhook {
+        url: String,
+        headers: Vec<(String, String)>,
+        content_type: String,
+        template: Option<String>,
+    },
+    Slack {
+        url: String,
+        template: String,
+    },
+    Email {
+        transport: SmtpTransport,
+        from: Mailbox,
+        to: Vec<Mailbox>,
+        subject: String,
+        body: String,
+    },
+}
+
+/* Delivers alerts; owned by the background thread. */
+struct Deliverer {
+    channels: Vec<Channel>,
+    templates: Environment<'static>,
+    agent: ureq::Agent,
+    config: AlertSinkConfig,
+    /// Delivery times within the current rate-limit window.
+    recent: VecDeque<Instant>,
+}
+
+/// An alert sink attached to a running program.  Dropping it delivers the
+/// alerts that are already queued and detaches the sink.
+pub struct AlertSink<'a> {
+    hddlog: &'a HDDlog,
+    subscription: CommitSubscriptionId,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/* Adds `template` to `env` under `name` and returns the name. */
+fn add_template(
+    env: &mut Environment<'static>,
+    name: String,
+    template: &str,
+) -> Result<String, String> {
+    env.add_template_owned(name.clone(), template.to_string())
+        .map_err(|e| format!("invalid alert template: {}", e))?;
+    Ok(name)
+}
+
+fn parse_mailbox(address: &str) -> Result<Mailbox, String> {
+    address
+        .parse()
+        .map_err(|e| format!("invalid email address '{}': {}", address, e))
+}
+
+impl Channel {
+    /* Compiles the templates of the `index`th channel into `env`. */
+    fn new(
+        index: usize,
+        channel: &AlertChannel,
+        env: &mut Environment<'static>,
+    ) -> Result<Self, String> {
+        let name = |part: &str| format!("{}.{}", index, part);
+        Ok(match channel {
+            AlertChannel::Webhook {
+                url,
+                headers,
+                content_type,
+                template,
+            } => Channel::Webhook {
+                url: url.clone(),
+                headers: headers.clone(),
+                content_type: content_type.clone(),
+                template: template
+                    .as_deref()
+                    .map(|t| add_template(env, name("body"), t))
+                    .transpose()?,
+            },
+            AlertChannel::Slack {
+                webhook_url,
+                template,
+            } => Channel::Slack {
+                url: webhook_url.clone(),
+                template: add_template(env, name("text"), template)?,
+            },
+            AlertChannel::Email(email) => {
+                let mut transport = SmtpTransport::relay(&email.relay)
+                    .map_err(|e| format!("invalid SMTP relay '{}': {}", email.relay, e))?;
+                if let Some(port) = email.port {
+                    transport = transport.port(port);
+                }
+                if let Some((user, password)) = &email.credentials {
+                    transport =
+                        transport.credentials(Credentials::new(user.clone(), password.clone()));
+                }
+                Channel::Email {
+                    transport: transport.build(),
+                    from: parse_mailbox(&email.from)?,
+                    to: email
+                        .to
+                        .iter()
+                        .map(String::as_str)
+                        .map(parse_mailbox)
+                        .collect::<Result<_, _>>()?,
+                    subject: add_template(env, name("subject"), &email.subject)?,
+                    body: add_template(env, name("body"), &email.body)?,
+                }
+            }
+        })
+    }
+}
+
+impl Deliverer {
+    fn render(&self, template: &str, vars: &Value) -> Result<String, String> {
+        self.templates
+            .get_template(template)
+            .and_then(|t| t.render(vars))
+            .map_err(|e| format!("failed to render alert: {}", e))
+    }
+
+    fn post(
+        &self,
+        url: &str,
+        headers: &[(String, String)],
+        content_type: &str,
+        body: &str,
+    ) -> Result<(), String> {
+        let mut request = self.agent.post(url).set("Content-Type", content_type);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        request
+            .send_string(body)
+            .map(|_| ())
+            .map_err(|e| format!("POST {} failed: {}", url, e))
+    }
+
+    fn send(&self, channel: &Channel, vars: &Value) -> Result<(), String> {
+        match channel {
+            Channel::Webhook {
+                url,
+                headers,
+                content_type,
+                template,
+            } => {
+                let body = match template {
+                    Some(template) => self.render(template, vars)?,
+                    None => vars.to_string(),
+                };
+                self.post(url, headers, content_type, &body)
+            }
+            Channel::Slack { url, template } => {
+                let text = self.render(template, vars)?;
+                self.post(
+                    url,
+                    &[],
+                    "application/json",
+                    &json!({ "text": text }).to_string(),
+                )
+            }
+            Channel::Email {
+                transport,
+                from,
+                to,
+                subject,
+                body,
+            } => {
+                let mut message = Message::builder()
+                    .from(from.clone())
+                    .subject(self.render(subject, vars)?);
+                for to in to.iter() {
+                    message = message.to(to.clone());
+                }
+                let message = message
+                    .body(self.render(body, vars)?)
+                    .map_err(|e| format!("failed to build email: {}", e))?;
+                transport
+                    .send(&message)
+                    .map(|_| ())
+                    .map_err(|e| format!("failed to send email: {}", e))
+            }
+        }
+    }
+
+    fn send_with_retries(&self, channel: &Channel, vars: &Value) -> Result<(), String> {
+        let mut delay = self.config.retry_delay;
+        let mut attempt = 0;
+        loop {
+            match self.send(channel, vars) {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt == self.config.retries => {
+                    return Err(format!("{} (after {} attempts)", e, attempt + 1))
+                }
+                Err(_) => {
+                    attempt += 1;
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+            }
+        }
+    }
+
+    /* Returns `false` if delivering an alert now would exceed the rate
+     * limit. */
+    fn admit(&mut self) -> bool {
+        let (limit, window) = match self.config.rate_limit {
+            Some(rate_limit) => rate_limit,
+            None => return true,
+        };
+        let now = Instant::now();
+        while let Some(t) = self.recent.front() {
+            if now.duration_since(*t) < window {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= limit {
+            return false;
+        }
+        self.recent.push_back(now);
+        true
+    }
+
+    fn deliver(&mut self, vars: &Value, shared: &Shared) {
+        if !self.admit() {
+            shared.queue.lock().unwrap().stats.rate_limited += 1;
+            return;
+        }
+        let mut error = None;
+        for channel in self.channels.iter() {
+            if let Err(e) = self.send_with_retries(channel, vars) {
+                error = Some(e);
+            }
+        }
+        let mut queue = shared.queue.lock().unwrap();
+        match error {
+            None => queue.stats.delivered += 1,
+            Some(e) => {
+                queue.stats.failed += 1;
+                queue.error = Some(e);
+            }
+        }
+    }
+
+    fn run(mut self, shared: Arc<Shared>) {
+        loop {
+            let vars = {
+                let mut queue = shared.queue.lock().unwrap();
+                while queue.alerts.is_empty() && !queue.stop {
+                    queue = shared.wakeup.wait(queue).unwrap();
+                }
+                match queue.alerts.pop_front() {
+                    Some(vars) => vars,
+                    None => return,
+                }
+            };
+            self.deliver(&vars, &shared);
+        }
+    }
+}
+
+/* Template variables of the changes in `batch` that are delivered. */
+fn alerts<'b>(
+    relation: &'b str,
+    batch: &'b ChangelogBatch,
+    notify_resolved: bool,
+) -> impl Iterator<Item = Value> + 'b {
+    let timestamp = batch
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    batch
+        .changes
+        .iter()
+        .filter(move |(_, weight)| *weight > 0 || (notify_resolved && *weight < 0))
+        .map(move |(value, weight)| {
+            json!({
+                "relation": relation,
+                "alert": serde_json::to_value(value).unwrap_or(Value::Null),
+                "text": value.clone().into_record().to_string(),
+                "resolved": *weight < 0,
+                "commit": batch.commit,
+                "timestamp": timestamp,
+            })
+        })
+}
+
+impl<'a> AlertSink<'a> {
+    /// Deliver the records inserted into output relation `relation` of
+    /// `hddlog` to the channels in `config`.
+    pub fn attach(
+        hddlog: &'a HDDlog,
+        relation: &str,
+        config: AlertSinkConfig,
+    ) -> Result<Self, String> {
+        let relid = match Relations::try_from(relation) {
+            Ok(rel) if rel.is_output() => rel as RelId,
+            _ => return Err(format!("unknown output relation {}", relation)),
+        };
+        if config.channels.is_empty() {
+            return Err("no alert channels configured".to_string());
+        }
+
+        let mut templates = Environment::new();
+        templates.set_undefined_behavior(UndefinedBehavior::Strict);
+        let channels = config
+            .channels
+            .iter()
+            .enumerate()
+            .map(|(index, channel)| Channel::new(index, channel, &mut templates))
+            .collect::<Result<Vec<_>, _>>()?;
+        let deliverer = Deliverer {
+            channels,
+            templates,
+            agent: ureq::AgentBuilder::new().timeout(config.timeout).build(),
+            config: config.clone(),
+            recent: VecDeque::new(),
+        };
+
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::default()),
+            wakeup: Condvar::new(),
+        });
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || deliverer.run(shared))
+        };
+
+        let subscription = {
+            let shared = shared.clone();
+            let relation = relation.to_string();
+            hddlog.subscribe_commits(
+                &[relid],
+                Arc::new(move |batches: &[ChangelogBatch]| {
+                    let mut queue = shared.queue.lock().unwrap();
+                    for batch in batches {
+                        for vars in alerts(&relation, batch, config.notify_resolved) {
+                            if queue.alerts.len() >= config.queue_capacity {
+                                queue.stats.overflowed += 1;
+                            } else {
+                                queue.alerts.push_back(vars);
+                            }
+                        }
+                    }
+                    shared.wakeup.notify_all();
+                }),
+            )
+        };
+        let subscription = match subscription {
+            Ok(subscription) => subscription,
+            Err(e) => {
+                shared.queue.lock().unwrap().stop = true;
+                shared.wakeup.notify_all();
+                let _ = thread.join();
+                return Err(e);
+            }
+        };
+        Ok(Self {
+            hddlog,
+            subscription,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Delivery statistics so far.
+    pub fn stats(&self) -> AlertStats {
+        self.shared.queue.lock().unwrap().stats.clone()
+    }
+
+    /// The last error encountered while delivering an alert, if any.
+    pub fn last_error(&self) -> Option<String> {
+        self.shared.queue.lock().unwrap().error.clone()
+    }
+
+    /// Number of alerts waiting to be delivered.
+    pub fn pending(&self) -> usize {
+        self.shared.queue.lock().unwrap().alerts.len()
+    }
+}
+
+impl Drop for AlertSink<'_> {
+    fn drop(&mut self) {
+        self.hddlog.unsubscribe_commits(self.subscription);
+        self.shared.queue.lock().unwrap().stop = true;
+        self.shared.wakeup.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deliverer(config: AlertSinkConfig) -> Deliverer {
+        let mut templates = Environment::new();
+        templates.set_undefined_behavior(UndefinedBehavior::Strict);
+        Deliverer {
+            channels: Vec::new(),
+            templates,
+            agent: ureq::AgentBuilder::new().build(),
+            config,
+            recent: VecDeque::new(),
+        }
+    }
+
+    fn email(from: &str, to: &str, subject: &str) -> AlertChannel {
+        AlertChannel::Email(EmailConfig {
+            relay: "smtp.example.com".to_string(),
+            port: None,
+            credentials: None,
+            from: from.to_string(),
+            to: vec![to.to_string()],
+            subject: subject.to_string(),
+            body: "{{ text }}".to_string(),
+        })
+    }
+
+    #[test]
+    fn compile_channels() {
+        let mut env = Environment::new();
+        let webhook = AlertChannel::Webhook {
+            url: "http://localhost/".to_string(),
+            headers: Vec::new(),
+            content_type: "application/json".to_string(),
+            template: None,
+        };
+        assert!(Channel::new(0, &webhook, &mut env).is_ok());
+        let slack = AlertChannel::Slack {
+            webhook_url: "http://localhost/".to_string(),
+            template: "{{ text }}".to_string(),
+        };
+        match Channel::new(1, &slack, &mut env).unwrap() {
+            Channel::Slack { template, .. } => assert_eq!(template, "1.text"),
+            _ => panic!("expected a Slack channel"),
+        }
+        match Channel::new(
+            2,
+            &email("a@example.com", "b@example.com", "alert"),
+            &mut env,
+        )
+        .unwrap()
+        {
+            Channel::Email { subject, body, .. } => {
+                assert_eq!(subject, "2.subject");
+                assert_eq!(body, "2.body");
+            }
+            _ => panic!("expected an email channel"),
+        }
+    }
+
+    #[test]
+    fn channel_errors() {
+        let mut env = Environment::new();
+        let slack = AlertChannel::Slack {
+            webhook_url: "http://localhost/".to_string(),
+            template: "{{ text".to_string(),
+        };
+        let err = Channel::new(0, &slack, &mut env).err().unwrap();
+        assert!(err.starts_with("invalid alert template: "), "{}", err);
+        let err = Channel::new(
+            0,
+            &email("a@example.com", "b@example.com", "{% if %}"),
+            &mut env,
+        )
+        .err()
+        .unwrap();
+        assert!(err.starts_with("invalid alert template: "), "{}", err);
+        for (from, to) in &[("nobody", "b@example.com"), ("a@example.com", "b@")] {
+            let err = Channel::new(0, &email(from, to, "alert"), &mut env)
+                .err()
+                .unwrap();
+            let address = if *from == "nobody" { from } else { to };
+            assert!(
+                err.starts_with(&format!("invalid email address '{}': ", address)),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn render_templates() {
+        let mut deliverer = deliverer(AlertSinkConfig::default());
+        add_template(
+            &mut deliverer.templates,
+            "t".to_string(),
+            "[{{ alert.severity }}] {{ text }}{% if resolved %} (resolved){% endif %}",
+        )
+        .unwrap();
+        let vars = json!({
+            "alert": {"severity": "high"},
+            "text": "disk full",
+            "resolved": true,
+        });
+        assert_eq!(
+            deliverer.render("t", &vars).unwrap(),
+            "[high] disk full (resolved)"
+        );
+        // Missing variables and fields are errors.
+        let err = deliverer
+            .render("t", &json!({"alert": {}, "text": "", "resolved": false}))
+            .unwrap_err();
+        assert!(err.starts_with("failed to render alert: "), "{}", err);
+    }
+
+    #[test]
+    fn rate_limit() {
+        let window = Duration::from_millis(100);
+        let mut limited = deliverer(AlertSinkConfig {
+            rate_limit: Some((2, window)),
+            ..AlertSinkConfig::default()
+        });
+        assert!(limited.admit());
+        assert!(limited.admit());
+        assert!(!limited.admit());
+        thread::sleep(window);
+        assert!(limited.admit());
+
+        let mut unlimited = deliverer(AlertSinkConfig::default());
+        assert!((0..100).all(|_| unlimited.admit()));
+    }
+}
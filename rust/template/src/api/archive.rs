@@ -0,0 +1,255 @@
+//! Portable snapshots of the contents of a DDlog program.
+//!
+//! `HDDlog::export()` writes the contents of all input relations, and
+//! optionally all output relations, to a versioned archive file, which
+//! `HDDlog::import()` loads back into another instance of the same program.
+//! Facts are stored as `Record`s together with the name and DDlog type of
+//! their relation, so that archives do not depend on the in-memory layout of
+//! values and can be moved between machines and builds of the program.
+//! Each relation also carries the fingerprint of its schema (see
+//! `differential_datalog::metadata`), and is only restored into a build of
+//! the program whose schema for the relation is the same.
+//!
+//! Archives can be gzip-compressed (see `compression`).
+
+use super::*;
+
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the archive format written by `HDDlog::export()`.  Archives
+/// with a different version are rejected by `HDDlog::import()`.
+pub const ARCHIVE_VERSION: u32 = 2;
+
+/// Encoding of an archive file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// Human-readable JSON.
+    Json,
+    /// Compact binary encoding (`bincode`).
+    Binary,
+}
+
+/// Snapshot of the contents of a DDlog program.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Archive {
+    pub version: u32,
+    /// Name and source hash of the program the archive was taken from.
+    pub program_name: String,
+    pub source_hash: u64,
+    pub relations: Vec<ArchivedRelation>,
+}
+
+/// Contents of one relation in an `Archive`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchivedRelation {
+    pub name: String,
+    /// `true` for input relations, `false` for output relations.
+    pub input: bool,
+    /// DDlog type of the relation's records.
+    pub type_name: String,
+    /// Fingerprint of the relation's schema.
+    pub fingerprint: u64,
+    /// Facts of the relation and their weights.
+    pub facts: Vec<(Record, isize)>,
+}
+
+impl Archive {
+    /// Write the archive to `path`, replacing the file if it already exists.
+    /// The archive is compressed if `path` ends in `.gz`.
+    pub fn write(&self, path: &Path, format: ArchiveFormat) -> Result<(), String> {
+        let file = fs::File::create(path)
+            .map_err(|e| format!("failed to create archive {}: {}", path.display(), e))?;
+        let writer = BufWriter::new(file);
+        if compression::compressed_path(path) {
+            compression::write_gzip(writer, |w| self.write_to(w, format))
+        } else {
+            self.write_to(writer, format)
+        }
+        .map_err(|e| format!("failed to write archive {}: {}", path.display(), e))
+    }
+
+    fn write_to<W: Write>(&self, writer: W, format: ArchiveFormat) -> Result<(), String> {
+        match format {
+            ArchiveFormat::Json => serde_json::to_writer(writer, self).map_err(|e| e.to_string()),
+            ArchiveFormat::Binary => {
+                bincode::serialize_into(writer, self).map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    /// Read an archive from `path`, decompressing it if it is compressed.
+    pub fn read(path: &Path, format: ArchiveFormat) -> Result<Self, String> {
+        let file = fs::File::open(path)
+            .map_err(|e| format!("failed to open archive {}: {}", path.display(), e))?;
+        let mut reader = BufReader::new(file);
+        let archive = match compression::is_gzip(&mut reader) {
+            Ok(true) => compression::read_gzip(reader, |r| Self::read_from(r, format)),
+            Ok(false) => Self::read_from(reader, format),
+            Err(e) => Err(e.to_string()),
+        }
+        .map_err(|e| format!("failed to read archive {}: {}", path.display(), e))?;
+
+        if archive.version != ARCHIVE_VERSION {
+            return Err(format!(
+                "unsupported archive version {} (expected {})",
+                archive.version, ARCHIVE_VERSION
+            ));
+        }
+        Ok(archive)
+    }
+
+    fn read_from<R: Read>(reader: R, format: ArchiveFormat) -> Result<Self, String> {
+        match format {
+            ArchiveFormat::Json => serde_json::from_reader(reader).map_err(|e| e.to_string()),
+            ArchiveFormat::Binary => bincode::deserialize_from(reader).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+fn archived_relation<'a, I>(rel: Relations, facts: I) -> Result<ArchivedRelation, String>
+where
+    I: Iterator<Item = (&'a DDValue, isize)>,
+{
+    let relid = rel as RelId;
+    let name = Inventory.get_table_name(relid)?;
+    Ok(ArchivedRelation {
+        name: name.to_string(),
+        input: rel.is_input(),
+        type_name: relid2type(relid).unwrap_or_default().to_string(),
+        fingerprint: PROGRAM_METADATA.fingerprint(name).unwrap_or_default(),
+        facts: facts.map(|(v, w)| (v.clone().into_record(), w)).collect(),
+    })
+}
+
+impl HDDlog {
+    /// Take a snapshot of the program's input relations.  Output relations
+    /// are included if `include_outputs` is `true`, which requires the
+    /// program to have been started with `do_store` enabled.  Input streams
+    /// are never included, as their contents are not tracked.
+    pub fn archive(&self, include_outputs: bool) -> Result<Archive, String> {
+        let mut rels: Vec<Relations> = INPUT_RELIDMAP.keys().copied().collect();
+        rels.sort_by_key(|rel| *rel as RelId);
+
+        let mut relations = Vec::with_capacity(rels.len());
+        {
+            let prog = self.prog.lock().unwrap();
+            for rel in rels {
+                let relid = rel as RelId;
+                self.check_access(relid, Operation::Query)?;
+                let archived = if let Ok(valset) = prog.get_input_relation_data(relid) {
+                    archived_relation(rel, valset.iter().map(|v| (v, 1)))?
+                } else if let Ok(ivalset) = prog.get_input_relation_index(relid) {
+                    archived_relation(rel, ivalset.values().map(|v| (v, 1)))?
+                } else if let Ok(ivalmset) = prog.get_input_multiset_data(relid) {
+                    archived_relation(rel, ivalmset.iter().map(|(v, w)| (v, *w)))?
+                } else if let Ok(columnar) = prog.get_input_columnar_data(relid) {
+                    let values: Vec<DDValue> = columnar.iter().collect();
+                    archived_relation(rel, values.iter().map(|v| (v, 1)))?
+                } else {
+                    continue;
+                };
+                relations.push(archived);
+            }
+        }
+
+        if include_outputs {
+            let db = self
+                .db
+                .as_ref()
+                .ok_or_else(|| "cannot archive output relations: do_store is disabled".to_string())?
+                .lock()
+                .unwrap();
+            let mut rels: Vec<Relations> = OUTPUT_RELIDMAP.keys().copied().collect();
+            rels.sort_by_key(|rel| *rel as RelId);
+            for rel in rels {
+                self.check_access(rel as RelId, Operation::Query)?;
+                let facts = db.try_get_rel(rel as RelId).into_iter().flatten();
+                relations.push(archived_relation(rel, facts.map(|(v, w)| (v, *w)))?);
+            }
+        }
+
+        Ok(Archive {
+            version: ARCHIVE_VERSION,
+            program_name: PROGRAM_METADATA.program_name.to_string(),
+            source_hash: PROGRAM_METADATA.source_hash,
+            relations,
+        })
+    }
+
+    /// Write a snapshot of the program (see `archive()`) to file `path`.
+    pub fn export(
+        &self,
+        path: &Path,
+        format: ArchiveFormat,
+        include_outputs: bool,
+    ) -> Result<(), String> {
+        self.archive(include_outputs)?.write(path, format)
+    }
+
+    /// Replace the contents of the input relations in `archive` with the
+    /// facts stored in the archive, in a single transaction.  Input
+    /// relations that do not occur in the archive and output relations
+    /// stored in the archive are left alone.
+    ///
+    /// Fails without modifying the program if the archive refers to unknown
+    /// relations or relations whose type or schema fingerprint differs from
+    /// the archived one.
+    pub fn restore(&self, archive: &Archive) -> Result<(), String> {
+        let mut contents = Vec::new();
+        for archived in archive.relations.iter().filter(|r| r.input) {
+            let rel = Relations::try_from(archived.name.as_str())
+                .map_err(|()| format!("unknown relation {}", archived.name))?;
+            if !rel.is_input() {
+                return Err(format!("{} is not an input relation", archived.name));
+            }
+            let type_name = relid2type(rel as RelId).unwrap_or_default();
+            if archived.type_name != type_name {
+                return Err(format!(
+                    "relation {} has type {}, but the archive contains values of type {}",
+                    archived.name, type_name, archived.type_name
+                ));
+            }
+            PROGRAM_METADATA
+                .check_fingerprint(&archived.name, archived.fingerprint)
+                .map_err(|e| {
+                    format!("cannot restore archive of {}: {}", archive.program_name, e)
+                })?;
+
+            let mut records = Vec::with_capacity(archived.facts.len());
+            for (rec, w) in archived.facts.iter() {
+                if *w < 0 {
+                    return Err(format!(
+                        "negative weight {} of {} in relation {}",
+                        w, rec, archived.name
+                    ));
+                }
+                records.extend(iter::repeat(rec.clone()).take(*w as usize));
+            }
+            contents.push((rel as RelId, relvals_from_records(rel, &records)?));
+        }
+
+        self.transaction_start()?;
+        let res = contents.into_iter().try_for_each(|(relid, desired)| {
+            let updates = self
+                .prog
+                .lock()
+                .unwrap()
+                .relation_sync_updates(relid, desired)?;
+            self.apply_updates(&mut updates.into_iter())
+        });
+        let res = res.and_then(|()| self.transaction_commit());
+        if res.is_err() {
+            let _ = self.transaction_rollback();
+        }
+        res
+    }
+
+    /// Load a snapshot written by `export()` from file `path` (see
+    /// `restore()`).
+    pub fn import(&self, path: &Path, format: ArchiveFormat) -> Result<(), String> {
+        self.restore(&Archive::read(path, format)?)
+    }
+}
@@ -0,0 +1,202 @@
+//! Audit log of API calls.
+//!
+//! Programs that import the `audit` library get an `audit::AuditLog` input
+//! relation recording the accesses to relations made through the API: who
+//! made them (the principal and tenant of the caller context, see
+//! `differential_datalog::access`), when, which operation on which relation,
+//! how many times, and whether the access policy allowed them.  Rules can
+//! analyze the program's own usage like any other input.
+//!
+//! Accesses are recorded when they are checked against the access policy
+//! and inserted into `AuditLog` by the next commit or prepare.  They are
+//! only forgotten once that transaction has been committed: if it fails or
+//! is rolled back, they are inserted again by the following commit.
+//! Repeated accesses with the same caller, operation,
+//! relation and outcome between two commits, e.g., the updates of one
+//! `apply_updates()` call, are recorded as one entry with a count.  Audit
+//! entries bypass the access policy and are not recorded by the command
+//! recorder.
+
+use super::*;
+
+use differential_datalog::access::caller_context;
+use fnv::FnvHashMap;
+use std::borrow::Cow;
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the relation declared by the `audit` library.
+const AUDIT_RELATION: &str = "audit::AuditLog";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct AuditKey {
+    principal: String,
+    tenant: String,
+    operation: Operation,
+    relid: RelId,
+    allowed: bool,
+}
+
+#[derive(Debug)]
+struct AuditEntry {
+    key: AuditKey,
+    /// Time of the first access, in milliseconds since the UNIX epoch.
+    timestamp: u64,
+    count: u64,
+}
+
+#[derive(Debug, Default)]
+struct PendingEntries {
+    /// Sequence number of the first entry in `entries`.
+    seq: u64,
+    entries: Vec<AuditEntry>,
+    /// The number of entries at the start of `entries` that have been
+    /// inserted into the current transaction.  These entries no longer
+    /// change, so that inserting them again after a rollback produces the
+    /// same values.
+    inserted: usize,
+    /// Positions of the entries that have not been inserted yet, by key.
+    index: FnvHashMap<AuditKey, usize>,
+}
+
+/// Records accesses and inserts them into the audit log relation.
+#[derive(Debug)]
+pub struct Auditor {
+    /// The audit log relation, if the program declares one.
+    relid: Option<RelId>,
+    pending: Mutex<PendingEntries>,
+}
+
+impl Auditor {
+    pub fn new() -> Self {
+        Self {
+            relid: Relations::try_from(AUDIT_RELATION)
+                .ok()
+                .map(|rel| rel as RelId),
+            pending: Mutex::new(PendingEntries::default()),
+        }
+    }
+
+    /// An auditor that records nothing, even if the program declares an
+    /// audit log relation.
+    pub fn disabled() -> Self {
+        Self {
+            relid: None,
+            pending: Mutex::new(PendingEntries::default()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.relid.is_some()
+    }
+
+    /// Record an access by the current caller.
+    pub fn record(&self, relid: RelId, operation: Operation, allowed: bool) {
+        if !self.enabled() {
+            return;
+        }
+        let caller = caller_context();
+        let key = AuditKey {
+            principal: caller.principal.clone().unwrap_or_default(),
+            tenant: caller.tenant.clone().unwrap_or_default(),
+            operation,
+            relid,
+            allowed,
+        };
+        let mut pending = self.pending.lock().unwrap();
+        match pending.index.get(&key) {
+            Some(&i) => pending.entries[i].count += 1,
+            None => {
+                let i = pending.entries.len();
+                pending.index.insert(key.clone(), i);
+                pending.entries.push(AuditEntry {
+                    key,
+                    timestamp: now_millis(),
+                    count: 1,
+                });
+            }
+        }
+    }
+
+    /// Insert the accesses recorded since the last commit into the audit
+    /// log as part of the current transaction.  Entries inserted by an
+    /// earlier flush are inserted again, which does nothing unless that
+    /// transaction has been rolled back.  Does nothing if the transaction
+    /// has been prepared; its accesses are then inserted by the next
+    /// transaction.  Call `committed()` once the transaction has been
+    /// committed.
+    pub fn flush(&self, prog: &mut RunningProgram) -> Result<(), String> {
+        let relid = match self.relid {
+            Some(relid) => relid,
+            None => return Ok(()),
+        };
+        let mut pending = self.pending.lock().unwrap();
+        if pending.entries.is_empty() || prog.transaction_prepared() {
+            return Ok(());
+        }
+        let rel = Relations::try_from(relid).map_err(|()| format!("unknown relation {}", relid))?;
+        let updates = pending
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let record = entry_record(pending.seq + i as u64, entry);
+                relval_from_record(rel, &record).map(|v| Update::Insert { relid, v })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        prog.apply_updates(updates.into_iter(), |_| Ok(()))?;
+
+        pending.inserted = pending.entries.len();
+        pending.index.clear();
+        Ok(())
+    }
+
+    /// Forget the entries inserted by the last flush, which are now part of
+    /// the committed contents of the audit log.
+    pub fn committed(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        let inserted = mem::take(&mut pending.inserted);
+        pending.entries.drain(..inserted);
+        pending.seq += inserted as u64;
+        for i in pending.index.values_mut() {
+            *i -= inserted;
+        }
+    }
+}
+
+impl Default for Auditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn entry_record(seq: u64, entry: &AuditEntry) -> Record {
+    let string = |s: &str| Record::String(s.to_string());
+    let int = |i: u64| Record::Int(i.into());
+    Record::NamedStruct(
+        Cow::from(AUDIT_RELATION),
+        vec![
+            (Cow::from("seq"), int(seq)),
+            (Cow::from("timestamp"), int(entry.timestamp)),
+            (Cow::from("principal"), string(&entry.key.principal)),
+            (Cow::from("tenant"), string(&entry.key.tenant)),
+            (
+                Cow::from("operation"),
+                string(&entry.key.operation.to_string()),
+            ),
+            (
+                Cow::from("relation"),
+                string(relid2name(entry.key.relid).unwrap_or_default()),
+            ),
+            (Cow::from("count"), int(entry.count)),
+            (Cow::from("allowed"), Record::Bool(entry.key.allowed)),
+        ],
+    )
+}
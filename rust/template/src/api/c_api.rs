@@ -10,7 +10,7 @@ use differential_datalog::{
     ddval::DDValue,
     program::{IdxId, RelId},
     record::IntoRecord,
-    DDlog, DDlogDump, DDlogDynamic, DDlogInventory, DDlogProfiling, DeltaMap,
+    DDlog, DDlogDump, DDlogDynamic, DDlogInventory, DDlogProfiling, DeltaMap, FormatOptions,
 };
 use std::{
     collections::BTreeMap,
@@ -109,6 +109,63 @@ pub unsafe extern "C" fn ddlog_get_index_name(
     }
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn ddlog_program_name(prog: *const HDDlog) -> *mut raw::c_char {
+    if prog.is_null() {
+        return ptr::null_mut();
+    }
+    let prog = &*prog;
+
+    CString::new(prog.metadata().program_name)
+        .map(CString::into_raw)
+        .unwrap_or_else(|_| ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ddlog_compiler_version(prog: *const HDDlog) -> *mut raw::c_char {
+    if prog.is_null() {
+        return ptr::null_mut();
+    }
+    let prog = &*prog;
+
+    CString::new(prog.metadata().compiler_version)
+        .map(CString::into_raw)
+        .unwrap_or_else(|_| ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ddlog_source_hash(prog: *const HDDlog) -> u64 {
+    if prog.is_null() {
+        return 0;
+    }
+    let prog = &*prog;
+
+    prog.metadata().source_hash
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ddlog_table_fingerprint(
+    prog: *const HDDlog,
+    tid: libc::size_t,
+    fingerprint: *mut u64,
+) -> raw::c_int {
+    if prog.is_null() || fingerprint.is_null() {
+        return -1;
+    }
+    let prog = &*prog;
+
+    match prog.relation_fingerprint(tid) {
+        Ok(fp) => {
+            *fingerprint = fp;
+            0
+        }
+        Err(e) => {
+            prog.eprintln(&format!("ddlog_table_fingerprint(): error: {}", e));
+            -1
+        }
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn ddlog_run(
     workers: raw::c_uint,
@@ -345,11 +402,23 @@ unsafe fn do_transaction_commit_dump_changes_as_array(
     *num_changes = size;
     // Make sure that vector's capacity will be equal to its length.
     let mut change_vec = Vec::with_capacity(size);
+    let options = FormatOptions {
+        canonical_order: prog.config.canonical_order,
+        ..FormatOptions::default()
+    };
     for (rel, delta) in updates.into_iter() {
-        for (val, w) in delta.into_iter() {
+        let records: Vec<(Record, isize)> = if options.canonical_order {
+            options.records(delta.iter().map(|(v, w)| (v, *w)))
+        } else {
+            delta
+                .into_iter()
+                .map(|(val, w)| (val.into_record(), w))
+                .collect()
+        };
+        for (rec, w) in records {
             change_vec.push(ddlog_record_update {
                 table: rel,
-                rec: Box::into_raw(Box::new(val.into_record())),
+                rec: Box::into_raw(Box::new(rec)),
                 w,
             });
         }
@@ -625,6 +694,19 @@ pub unsafe extern "C" fn ddlog_transaction_commit(prog: *const HDDlog) -> raw::c
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn ddlog_transaction_prepare(prog: *const HDDlog) -> raw::c_int {
+    if prog.is_null() {
+        return -1;
+    }
+    let prog = &*prog;
+
+    prog.transaction_prepare().map(|_| 0).unwrap_or_else(|e| {
+        prog.eprintln(&format!("ddlog_transaction_prepare(): error: {}", e));
+        -1
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn ddlog_transaction_rollback(prog: *const HDDlog) -> raw::c_int {
     if prog.is_null() {
@@ -657,6 +739,27 @@ pub unsafe extern "C" fn ddlog_apply_updates(
         })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn ddlog_insert_records(
+    prog: *const HDDlog,
+    table: libc::size_t,
+    recs: *const *mut Record,
+    n: libc::size_t,
+) -> raw::c_int {
+    if prog.is_null() || recs.is_null() {
+        return -1;
+    }
+    let prog = &*prog;
+
+    let recs: Vec<Record> = (0..n).map(|i| *Box::from_raw(*recs.add(i))).collect();
+    prog.insert_records(table, &recs)
+        .map(|_| 0)
+        .unwrap_or_else(|e| {
+            prog.eprintln(&format!("ddlog_insert_records(): error: {}", e));
+            -1
+        })
+}
+
 #[cfg(feature = "flatbuf")]
 #[no_mangle]
 pub unsafe extern "C" fn ddlog_apply_updates_from_flatbuf(
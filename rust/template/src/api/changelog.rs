@@ -0,0 +1,116 @@
+//! Changelogs of output relations.
+//!
+//! An output relation with a changelog subscriber exposes every change made
+//! to it as a stream of `ChangelogBatch`es, one per commit, annotated with a
+//! monotonically increasing commit number and the wall-clock time of the
+//! commit.  Changelogs are meant for audit trails and for feeding changes to
+//! downstream systems (change data capture).
+//!
+//! Output changes are also produced when a prepared or poisoned transaction
+//! is rolled back (retracting the changes it made) and while the initial
+//! transaction runs, before any changelog can be subscribed to.  Callbacks
+//! are invoked from the update handler thread before the commit returns and
+//! must not call back into the program.
+//!
+//! A slow subscriber therefore stalls commits.  Subscribers that cannot keep
+//! up can instead be served through a bounded delivery queue
+//! (`subscribe_changelog_queued()`), drained by a dedicated thread, with an
+//! `OverflowPolicy` deciding whether a full queue blocks the commit, drops
+//! the batch, or coalesces it with the last queued batch.  If the
+//! subscriber panics, its delivery thread stops; the queue then reports the
+//! error (`ChangelogQueueStats::error()`) and discards further batches
+//! instead of blocking commits forever.
+//!
+//! Commit subscribers (`subscribe_commits()`) receive the batches of all
+//! relations they subscribed to that were changed by a commit at once,
+//! which lets sinks apply each commit to an external store atomically.
+//! They can be served through a delivery queue as well
+//! (`subscribe_commits_queued()`).
+
+use super::*;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Identifies a commit subscription (see `HDDlog::subscribe_commits()`).
+pub type CommitSubscriptionId = u64;
+
+static NEXT_COMMIT_SUBSCRIPTION: AtomicU64 = AtomicU64::new(1);
+
+impl HDDlog {
+    /// Invoke `cb` with the changes to output relation `table` made by each
+    /// subsequent commit, replacing the relation's previous changelog
+    /// callback, if any.
+    pub fn subscribe_changelog(&self, table: RelId, cb: ChangelogCallback) -> Result<(), String> {
+        self.check_changelog_access(table)?;
+        self.changelog_callbacks.write().unwrap().insert(table, cb);
+        Ok(())
+    }
+
+    fn check_changelog_access(&self, table: RelId) -> Result<(), String> {
+        let rel = Relations::try_from(table).map_err(|()| format!("unknown relation {}", table))?;
+        if !rel.is_output() {
+            return Err(format!(
+                "{} is not an output relation",
+                Inventory.get_table_name(table)?
+            ));
+        }
+        self.check_access(table, Operation::Query)
+    }
+
+    /// Like `subscribe_changelog()`, but deliver batches to `cb` from a
+    /// separate thread through a bounded queue configured by `config`.
+    /// Returns a handle reporting the state of the queue.
+    pub fn subscribe_changelog_queued(
+        &self,
+        table: RelId,
+        cb: ChangelogCallback,
+        config: DeliveryConfig,
+    ) -> Result<ChangelogQueueStats, String> {
+        let (cb, stats) = queued_changelog_callback(table, cb, config)?;
+        self.subscribe_changelog(table, cb)?;
+        Ok(stats)
+    }
+
+    /// Stop delivering the changelog of `table`.
+    pub fn unsubscribe_changelog(&self, table: RelId) {
+        self.changelog_callbacks.write().unwrap().remove(&table);
+    }
+
+    /// Invoke `cb` once per commit that changes any of the output relations
+    /// in `tables`, with one batch per changed relation.  Commit
+    /// subscriptions are independent of changelog callbacks.
+    pub fn subscribe_commits(
+        &self,
+        tables: &[RelId],
+        cb: CommitCallback,
+    ) -> Result<CommitSubscriptionId, String> {
+        for table in tables {
+            self.check_changelog_access(*table)?;
+        }
+        let id = NEXT_COMMIT_SUBSCRIPTION.fetch_add(1, Ordering::Relaxed);
+        self.commit_callbacks
+            .write()
+            .unwrap()
+            .insert(id, (tables.iter().cloned().collect(), cb));
+        Ok(id)
+    }
+
+    /// Like `subscribe_commits()`, but deliver the batches of each commit to
+    /// `cb` from a separate thread through a bounded queue configured by
+    /// `config`.  Returns the subscription id and a handle reporting the
+    /// state of the queue.
+    pub fn subscribe_commits_queued(
+        &self,
+        tables: &[RelId],
+        cb: CommitCallback,
+        config: DeliveryConfig,
+    ) -> Result<(CommitSubscriptionId, ChangelogQueueStats), String> {
+        let (cb, stats) = queued_commit_callback(cb, config)?;
+        Ok((self.subscribe_commits(tables, cb)?, stats))
+    }
+
+    /// Cancel the commit subscription `id`.
+    pub fn unsubscribe_commits(&self, id: CommitSubscriptionId) {
+        self.commit_callbacks.write().unwrap().remove(&id);
+    }
+}
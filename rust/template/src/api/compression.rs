@@ -0,0 +1,118 @@
+//! Transparent compression of archives and command recordings.
+//!
+//! With the `compression` feature, archives written by `HDDlog::export()` to
+//! a path ending in `.gz` and command recordings started with
+//! `HDDlog::record_compressed_commands()` are gzip-compressed.
+//! `HDDlog::import()` recognizes compressed archives by their contents,
+//! whatever their name; compressed recordings can be replayed by
+//! decompressing them into the CLI, e.g., `zcat commands.dat.gz | prog_cli`.
+
+use super::*;
+
+use std::io::{BufRead, Read, Write};
+use std::path::Path;
+
+#[cfg(feature = "compression")]
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+/// First bytes of a gzip stream (RFC 1952).
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Returns `true` if a file written to `path` must be compressed.
+pub(super) fn compressed_path(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "gz")
+}
+
+/// Returns `true` if `reader` starts with a gzip stream.
+pub(super) fn is_gzip<R: BufRead>(reader: &mut R) -> io::Result<bool> {
+    Ok(reader.fill_buf()?.starts_with(&GZIP_MAGIC))
+}
+
+/// Write to `writer` through a gzip encoder, finishing the stream once
+/// `write` returns.
+#[cfg(feature = "compression")]
+pub(super) fn write_gzip<W, F>(writer: W, write: F) -> Result<(), String>
+where
+    W: Write,
+    F: FnOnce(&mut GzEncoder<W>) -> Result<(), String>,
+{
+    let mut encoder = GzEncoder::new(writer, Compression::default());
+    write(&mut encoder)?;
+    encoder.finish().map(|_| ()).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "compression"))]
+pub(super) fn write_gzip<W, F>(_writer: W, _write: F) -> Result<(), String>
+where
+    W: Write,
+    F: FnOnce(&mut W) -> Result<(), String>,
+{
+    Err(NO_COMPRESSION.to_string())
+}
+
+/// Read from `reader` through a gzip decoder.
+#[cfg(feature = "compression")]
+pub(super) fn read_gzip<R, T, F>(reader: R, read: F) -> Result<T, String>
+where
+    R: Read,
+    F: FnOnce(GzDecoder<R>) -> Result<T, String>,
+{
+    read(GzDecoder::new(reader))
+}
+
+#[cfg(not(feature = "compression"))]
+pub(super) fn read_gzip<R, T, F>(_reader: R, _read: F) -> Result<T, String>
+where
+    R: Read,
+    F: FnOnce(R) -> Result<T, String>,
+{
+    Err(NO_COMPRESSION.to_string())
+}
+
+#[cfg(not(feature = "compression"))]
+const NO_COMPRESSION: &str = "compressed files require the `compression` feature";
+
+/// File that recorded commands are written to.
+pub enum RecordingFile {
+    Plain(fs::File),
+    #[cfg(feature = "compression")]
+    Gzip(GzEncoder<fs::File>),
+}
+
+impl RecordingFile {
+    /// Finish writing the recording, which completes the gzip stream of a
+    /// compressed recording, and return the file.
+    pub fn finish(self) -> io::Result<fs::File> {
+        match self {
+            RecordingFile::Plain(file) => Ok(file),
+            #[cfg(feature = "compression")]
+            RecordingFile::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl Write for RecordingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            RecordingFile::Plain(file) => file.write(buf),
+            #[cfg(feature = "compression")]
+            RecordingFile::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            RecordingFile::Plain(file) => file.flush(),
+            #[cfg(feature = "compression")]
+            RecordingFile::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+impl HDDlog {
+    /// Like `record_commands()`, but compresses the recording with gzip.
+    pub fn record_compressed_commands(&mut self, file: &mut Option<fs::File>) {
+        self.set_command_recorder(file, false, true)
+    }
+}
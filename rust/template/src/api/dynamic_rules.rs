@@ -0,0 +1,224 @@
+//! Rules loaded at runtime.
+//!
+//! `load_rules()` adds a set of rules, written in the subset of DDlog
+//! accepted by `differential_datalog::interpreter`, over the input and
+//! output relations of the program, without recompiling it.  The rules
+//! define new relations, which are evaluated by the interpreter after every
+//! commit, and whose changes are delivered to the callback of the rule set.
+//! Rule sets can also be refreshed explicitly, e.g., after a commit made by
+//! replaying recorded commands.
+//!
+//! The interpreter re-evaluates each rule set over the full contents of the
+//! relations it uses, so rules loaded at runtime are much slower than
+//! compiled rules and are best suited for ad-hoc queries over relations of
+//! moderate size.  Rules can only use output relations if the program runs
+//! with `do_store` enabled.  Reading relations is checked against the access
+//! policy of the caller that loads or refreshes the rules.
+//!
+//! `query_once()` evaluates a single rule body over the current contents of
+//! the program's relations, without loading any rules.
+
+use super::*;
+
+use differential_datalog::interpreter::{DerivedChanges, Interpreter, Query, RuleSet};
+
+/// Identifies a set of rules loaded with `load_rules()`.
+pub type RuleSetId = u64;
+
+/// Callback invoked with the changes to the relations derived by a rule set.
+/// Runs while rule sets are locked, so it must not load or unload rules.
+pub type RulesCallback = Arc<dyn Fn(RuleSetId, &DerivedChanges) + Send + Sync>;
+
+struct LoadedRules {
+    interpreter: Interpreter,
+    /// Relations used by the rules, by name.
+    base: Vec<(String, Relations)>,
+    callback: Option<RulesCallback>,
+}
+
+/// Rule sets loaded into a program.
+#[derive(Default)]
+pub struct DynamicRules {
+    next_id: RuleSetId,
+    loaded: BTreeMap<RuleSetId, LoadedRules>,
+}
+
+impl std::fmt::Debug for DynamicRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynamicRules")
+            .field("loaded", &self.loaded.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl HDDlog {
+    /// Parse rules from `src` and evaluate them over the current contents of
+    /// the program's relations.  `cb`, if any, receives the initial contents
+    /// of the derived relations, as insertions, and their changes after
+    /// every subsequent commit.
+    ///
+    /// Fails if the rules do not parse, use relations that are neither
+    /// input nor output relations of the program, or define relations whose
+    /// names clash with relations of the program.
+    pub fn load_rules(&self, src: &str, cb: Option<RulesCallback>) -> Result<RuleSetId, String> {
+        let rules = RuleSet::parse(src)?;
+        if let Some(head) = rules
+            .head_relations()
+            .find(|head| Relations::try_from(*head).is_ok())
+        {
+            return Err(format!(
+                "rules define relation {}, which is already defined by the program",
+                head
+            ));
+        }
+        let base = rules
+            .base_relations()
+            .map(|name| match Relations::try_from(name) {
+                Ok(rel) => Ok((name.to_string(), rel)),
+                Err(()) => Err(format!("unknown relation {}", name)),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let mut loaded = LoadedRules {
+            interpreter: Interpreter::new(rules),
+            base,
+            callback: cb,
+        };
+        let mut dynamic_rules = self.dynamic_rules.lock().unwrap();
+        let id = dynamic_rules.next_id;
+        self.evaluate_rules(id, &mut loaded)?;
+        dynamic_rules.next_id += 1;
+        dynamic_rules.loaded.insert(id, loaded);
+        Ok(id)
+    }
+
+    /// Evaluate `query`, a rule body over input and output relations, e.g.,
+    /// `Edge(x, y), not Node(y), x < 10`, against their current contents.
+    /// Returns one `NamedStruct` record per distinct binding of the
+    /// variables of the query, with one field per variable.
+    pub fn query_once(&self, query: &str) -> Result<Vec<Record>, String> {
+        let query = Query::parse(query)?;
+        let mut base = BTreeMap::new();
+        for name in query.base_relations() {
+            let rel =
+                Relations::try_from(name).map_err(|()| format!("unknown relation {}", name))?;
+            base.insert(name.to_string(), self.relation_records(name, rel)?);
+        }
+        query.evaluate(&base)
+    }
+
+    /// Remove a rule set loaded with `load_rules()`.
+    pub fn unload_rules(&self, id: RuleSetId) -> Result<(), String> {
+        self.dynamic_rules
+            .lock()
+            .unwrap()
+            .loaded
+            .remove(&id)
+            .map(|_| ())
+            .ok_or_else(|| format!("unknown rule set {}", id))
+    }
+
+    /// Contents of relation `relation` derived by rule set `id`.
+    pub fn dump_dynamic_relation(
+        &self,
+        id: RuleSetId,
+        relation: &str,
+    ) -> Result<Vec<Record>, String> {
+        let dynamic_rules = self.dynamic_rules.lock().unwrap();
+        let loaded = dynamic_rules
+            .loaded
+            .get(&id)
+            .ok_or_else(|| format!("unknown rule set {}", id))?;
+        loaded
+            .interpreter
+            .relation(relation)
+            .ok_or_else(|| format!("rule set {} does not define relation {}", id, relation))
+    }
+
+    /// Re-evaluate all loaded rule sets over the current contents of the
+    /// program's relations.  Rule sets are refreshed after every commit;
+    /// this is only needed when relations change otherwise.  Refreshes every
+    /// rule set even if some of them fail, and returns the first error.
+    pub fn refresh_rules(&self) -> Result<(), String> {
+        let mut dynamic_rules = self.dynamic_rules.lock().unwrap();
+        let mut res = Ok(());
+        for (id, loaded) in dynamic_rules.loaded.iter_mut() {
+            let refreshed = self
+                .evaluate_rules(*id, loaded)
+                .map_err(|e| format!("failed to evaluate rule set {}: {}", id, e));
+            res = res.and(refreshed);
+        }
+        res
+    }
+
+    /// Refresh rule sets after a successful commit.  The commit has already
+    /// happened, so errors are reported with `print_err` rather than
+    /// returned.
+    pub(super) fn refresh_rules_after_commit(&self) {
+        if self.dynamic_rules.lock().unwrap().loaded.is_empty() {
+            return;
+        }
+        if let Err(e) = self.refresh_rules() {
+            self.eprintln(&e);
+        }
+    }
+
+    fn evaluate_rules(&self, id: RuleSetId, loaded: &mut LoadedRules) -> Result<(), String> {
+        let mut base = BTreeMap::new();
+        for (name, rel) in loaded.base.iter() {
+            base.insert(name.clone(), self.relation_records(name, *rel)?);
+        }
+        let changes = loaded.interpreter.update(&base)?;
+        if let Some(cb) = &loaded.callback {
+            if !changes.is_empty() {
+                cb(id, &changes);
+            }
+        }
+        Ok(())
+    }
+
+    /// Current contents of input or output relation `rel`.
+    fn relation_records(&self, name: &str, rel: Relations) -> Result<Vec<Record>, String> {
+        let relid = rel as RelId;
+        self.check_access(relid, Operation::Query)?;
+        if INPUT_RELIDMAP.contains_key(&rel) {
+            let prog = self.prog.lock().unwrap();
+            let records = if let Ok(valset) = prog.get_input_relation_data(relid) {
+                valset.iter().map(|v| v.clone().into_record()).collect()
+            } else if let Ok(ivalset) = prog.get_input_relation_index(relid) {
+                ivalset.values().map(|v| v.clone().into_record()).collect()
+            } else if let Ok(ivalmset) = prog.get_input_multiset_data(relid) {
+                ivalmset
+                    .iter()
+                    .filter(|(_, w)| **w > 0)
+                    .map(|(v, _)| v.clone().into_record())
+                    .collect()
+            } else if let Ok(columnar) = prog.get_input_columnar_data(relid) {
+                columnar.iter().map(|v| v.into_record()).collect()
+            } else {
+                return Err(format!("cannot read input stream {}", name));
+            };
+            Ok(records)
+        } else if OUTPUT_RELIDMAP.contains_key(&rel) {
+            let db = self
+                .db
+                .as_ref()
+                .ok_or_else(|| {
+                    format!("cannot read output relation {}: do_store is disabled", name)
+                })?
+                .lock()
+                .unwrap();
+            Ok(db
+                .try_get_rel(relid)
+                .into_iter()
+                .flatten()
+                .map(|(v, _)| v.clone().into_record())
+                .collect())
+        } else {
+            Err(format!(
+                "relation {} is neither an input nor an output relation",
+                name
+            ))
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! History relation of input relations.
+//!
+//! Programs that import the `history` library get a `history::Fact` input
+//! relation holding the history of the input relations whose history is
+//! retained (see `differential_datalog::program::history` and
+//! `HDDlog::set_history_retention()`): a row for each fact currently in
+//! such a relation, with its insertion time, and a tombstone for each fact
+//! deleted within the retention period, with its insertion and deletion
+//! times.  Rules can then evaluate temporal queries such as "what did we
+//! know at T?" against it.
+//!
+//! The changes that a transaction makes to the history are applied to the
+//! relation as part of the same transaction, when it is committed or
+//! prepared, so that rules see the history up to and including the
+//! transaction.  Like audit log entries, the changes made by starting or
+//! stopping retention between transactions are applied by the next commit
+//! or prepare and only forgotten once that transaction has been committed.
+//! History rows bypass the access policy and are not recorded by the
+//! command recorder.
+
+use super::*;
+
+use differential_datalog::program::history::{HistoryEvent, HistoryFact};
+use std::borrow::Cow;
+use std::mem;
+
+/// Name of the relation declared by the `history` library.
+const HISTORY_RELATION: &str = "history::Fact";
+
+#[derive(Debug, Default)]
+struct PendingEvents {
+    events: Vec<HistoryEvent>,
+    /// The number of events at the start of `events` that have been applied
+    /// to the current transaction.
+    applied: usize,
+}
+
+/// Applies changes to the history of input relations to the history
+/// relation.
+#[derive(Debug)]
+pub struct HistoryLog {
+    /// The history relation, if the program declares one.
+    relid: Option<RelId>,
+    /// Changes made by starting or stopping retention between transactions.
+    pending: Mutex<PendingEvents>,
+}
+
+impl HistoryLog {
+    pub fn new() -> Self {
+        Self {
+            relid: Relations::try_from(HISTORY_RELATION)
+                .ok()
+                .map(|rel| rel as RelId),
+            pending: Mutex::new(PendingEvents::default()),
+        }
+    }
+
+    /// Apply the changes to the history made by starting or stopping
+    /// retention since the last commit, followed by the changes that
+    /// committing the current transaction makes, to the history relation
+    /// as part of the current transaction.  Changes applied by an earlier
+    /// flush are applied again, which does nothing unless that transaction
+    /// has been rolled back.  Does nothing if the transaction has been
+    /// prepared, which applied its changes already.  Call `committed()` once
+    /// the transaction has been committed.
+    pub fn flush(&self, prog: &mut RunningProgram) -> Result<(), String> {
+        if prog.transaction_prepared() {
+            return Ok(());
+        }
+        // Changes are collected whether or not they are recorded.
+        let events = prog.take_history_events();
+        let relid = match self.relid {
+            Some(relid) => relid,
+            None => return Ok(()),
+        };
+        let mut pending = self.pending.lock().unwrap();
+        pending.events.extend(events);
+        let transaction_events = prog.transaction_history_events();
+        if pending.events.is_empty() && transaction_events.is_empty() {
+            return Ok(());
+        }
+        let rel = Relations::try_from(relid).map_err(|()| format!("unknown relation {}", relid))?;
+        let value = |fact: &HistoryFact| relval_from_record(rel, &fact_record(fact));
+        let mut updates = Vec::with_capacity(pending.events.len() + transaction_events.len());
+        for event in pending.events.iter().chain(transaction_events.iter()) {
+            match event {
+                HistoryEvent::Inserted(fact) => updates.push(Update::Insert {
+                    relid,
+                    v: value(fact)?,
+                }),
+                HistoryEvent::Deleted(tombstone) => {
+                    let fact = HistoryFact {
+                        deleted: None,
+                        ..tombstone.clone()
+                    };
+                    updates.push(Update::DeleteValue {
+                        relid,
+                        v: value(&fact)?,
+                    });
+                    updates.push(Update::Insert {
+                        relid,
+                        v: value(tombstone)?,
+                    });
+                }
+                HistoryEvent::Expired(fact) => updates.push(Update::DeleteValue {
+                    relid,
+                    v: value(fact)?,
+                }),
+            }
+        }
+        prog.apply_updates(updates.into_iter(), |_| Ok(()))?;
+
+        pending.applied = pending.events.len();
+        Ok(())
+    }
+
+    /// Forget the changes applied by the last flush, which are now part of
+    /// the committed contents of the history relation.
+    pub fn committed(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        let applied = mem::take(&mut pending.applied);
+        pending.events.drain(..applied);
+    }
+}
+
+impl Default for HistoryLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn fact_record(fact: &HistoryFact) -> Record {
+    let int = |i: u64| Record::Int(i.into());
+    let deleted = match fact.deleted {
+        Some(deleted) => Record::NamedStruct(
+            Cow::from("ddlog_std::Some"),
+            vec![(Cow::from("x"), int(deleted))],
+        ),
+        None => Record::NamedStruct(Cow::from("ddlog_std::None"), Vec::new()),
+    };
+    Record::NamedStruct(
+        Cow::from(HISTORY_RELATION),
+        vec![
+            (
+                Cow::from("relation"),
+                Record::String(relid2name(fact.relid).unwrap_or_default().to_string()),
+            ),
+            (
+                Cow::from("record"),
+                Record::String(fact.value.clone().into_record().to_string()),
+            ),
+            (Cow::from("inserted"), int(fact.inserted)),
+            (Cow::from("deleted"), deleted),
+        ],
+    )
+}
+
+impl HDDlog {
+    /// Retain the facts deleted from input relation `table` as tombstones in
+    /// the `history::Fact` relation for `retention`, or stop retaining its
+    /// history if `None` (see `RunningProgram::set_history_retention()`).
+    /// The history is only recorded if the program imports the `history`
+    /// library.  Retaining the history of a relation exposes its past
+    /// contents, so it requires query access to the relation.
+    pub fn set_history_retention(
+        &self,
+        table: RelId,
+        retention: Option<Duration>,
+    ) -> Result<(), String> {
+        self.check_access(table, Operation::Query)?;
+        if Some(table) == self.history_log.relid {
+            return Err(format!("cannot retain the history of {}", HISTORY_RELATION));
+        }
+        self.prog
+            .lock()
+            .unwrap()
+            .set_history_retention(table, retention)
+    }
+}
@@ -0,0 +1,207 @@
+//! Integrity constraints.
+//!
+//! Programs that import the `integrity` library declare constraints, such as
+//! foreign keys and uniqueness of non-primary keys, as rules that derive
+//! their violations into the `integrity::Violation` output relation.  Being
+//! ordinary rules, constraints are checked incrementally by every commit.
+//!
+//! By default (`ConstraintMode::Report`), violations are only reported in
+//! the relation.  A constraint switched to `ConstraintMode::Reject` with
+//! `HDDlog::set_constraint_mode()` instead makes the commit of a transaction
+//! that introduces a violation of it fail: the transaction is prepared,
+//! and rolled back if its output changes, which are held back until it is
+//! committed, insert a violation of a rejecting constraint.  Violations that
+//! already existed when the constraint was switched to `Reject` do not fail
+//! later commits.
+//!
+//! While any constraint rejects violations, commits are never deferred (see
+//! `Config::commit_latency_budget`).  `transaction_commit_with_deadline()`
+//! does not check constraints, since checking propagates the transaction
+//! without a time limit.
+
+use super::*;
+
+use std::collections::HashMap;
+
+/// Name of the relation declared by the `integrity` library.
+const VIOLATION_RELATION: &str = "integrity::Violation";
+
+/// Id of the commit subscription that collects violations; ids returned by
+/// `HDDlog::subscribe_commits()` start at 1.
+const INTEGRITY_SUBSCRIPTION: CommitSubscriptionId = 0;
+
+/// What a commit does when it introduces a violation of a constraint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintMode {
+    /// Commit the transaction; the violation is reported in the violation
+    /// relation (the default).
+    Report,
+    /// Roll the transaction back and fail the commit.
+    Reject,
+}
+
+/// Tracks the violations of integrity constraints made by each commit.
+#[derive(Debug)]
+pub struct IntegrityChecker {
+    /// The violation relation, if the program declares one.
+    relid: Option<RelId>,
+    /// Modes of constraints that are not in the default mode.
+    modes: Arc<Mutex<HashMap<String, ConstraintMode>>>,
+    /// Net changes to the violation relation since the last check.
+    changes: Arc<Mutex<BTreeMap<DDValue, isize>>>,
+}
+
+impl IntegrityChecker {
+    /// Collect the changes to the violation relation, if the program declares
+    /// one, through `commit_callbacks`.
+    pub fn new(commit_callbacks: &CommitCallbacks) -> Self {
+        let relid = Relations::try_from(VIOLATION_RELATION)
+            .ok()
+            .map(|rel| rel as RelId);
+        let modes: Arc<Mutex<HashMap<String, ConstraintMode>>> = Arc::default();
+        let changes: Arc<Mutex<BTreeMap<DDValue, isize>>> = Arc::default();
+        if let Some(relid) = relid {
+            let modes = modes.clone();
+            let changes = changes.clone();
+            let cb: CommitCallback = Arc::new(move |batches: &[ChangelogBatch]| {
+                // Only rejecting constraints are checked.
+                if modes.lock().unwrap().is_empty() {
+                    return;
+                }
+                let mut changes = changes.lock().unwrap();
+                for (value, weight) in batches.iter().flat_map(|b| b.changes.iter()) {
+                    let w = changes.entry(value.clone()).or_insert(0);
+                    *w += weight;
+                    if *w == 0 {
+                        changes.remove(value);
+                    }
+                }
+            });
+            commit_callbacks
+                .write()
+                .unwrap()
+                .insert(INTEGRITY_SUBSCRIPTION, (iter::once(relid).collect(), cb));
+        }
+        Self {
+            relid,
+            modes,
+            changes,
+        }
+    }
+
+    /// Returns `true` if commits must be checked against the constraints.
+    fn rejects_any(&self) -> bool {
+        self.relid.is_some() && !self.modes.lock().unwrap().is_empty()
+    }
+
+    /// Violations of rejecting constraints inserted since the last call,
+    /// including `held` changes that have not been delivered yet, as
+    /// `(constraint, kind, relation, record)` tuples.
+    fn take_rejected(
+        &self,
+        held: BTreeMap<DDValue, isize>,
+    ) -> Vec<(String, String, String, String)> {
+        let mut changes = mem::take(&mut *self.changes.lock().unwrap());
+        for (value, weight) in held {
+            *changes.entry(value).or_insert(0) += weight;
+        }
+        let modes = self.modes.lock().unwrap();
+        changes
+            .into_iter()
+            .filter(|(_, w)| *w > 0)
+            .filter_map(|(v, _)| violation_fields(v.into_record()))
+            .filter(|(constraint, _, _, _)| modes.get(constraint) == Some(&ConstraintMode::Reject))
+            .collect()
+    }
+}
+
+fn violation_fields(record: Record) -> Option<(String, String, String, String)> {
+    let fields = match record {
+        Record::NamedStruct(_, fields) => fields,
+        _ => return None,
+    };
+    let field = |name: &str| {
+        fields.iter().find_map(|(n, v)| match v {
+            Record::String(s) if n == name => Some(s.clone()),
+            _ => None,
+        })
+    };
+    Some((
+        field("constraint")?,
+        field("kind")?,
+        field("relation")?,
+        field("record")?,
+    ))
+}
+
+impl HDDlog {
+    /// Set the mode of integrity constraint `constraint`, i.e., whether
+    /// commits that violate it fail.
+    pub fn set_constraint_mode(&self, constraint: &str, mode: ConstraintMode) {
+        let mut modes = self.integrity.modes.lock().unwrap();
+        if mode == ConstraintMode::Report {
+            modes.remove(constraint);
+        } else {
+            modes.insert(constraint.to_string(), mode);
+        }
+    }
+
+    /// The mode of integrity constraint `constraint`.
+    pub fn constraint_mode(&self, constraint: &str) -> ConstraintMode {
+        self.integrity
+            .modes
+            .lock()
+            .unwrap()
+            .get(constraint)
+            .cloned()
+            .unwrap_or(ConstraintMode::Report)
+    }
+
+    /// Forget the violations produced since the last check, before preparing
+    /// a transaction.
+    pub(super) fn start_constraint_check(&self) {
+        self.integrity.changes.lock().unwrap().clear();
+    }
+
+    /// Roll back the just prepared transaction if it violates a rejecting
+    /// constraint.
+    pub(super) fn finish_constraint_check(&self) -> Result<(), String> {
+        let held = match self.integrity.relid {
+            Some(relid) if self.integrity.rejects_any() => {
+                self.prog.lock().unwrap().prepared_output_changes(relid)?
+            }
+            _ => BTreeMap::new(),
+        };
+        let rejected = self.integrity.take_rejected(held);
+        if rejected.is_empty() {
+            return Ok(());
+        }
+        let _ = self.transaction_rollback();
+        Err(format!(
+            "transaction violates integrity constraints and has been rolled back: {}",
+            rejected
+                .iter()
+                .map(|(constraint, kind, relation, record)| format!(
+                    "{} ({} in {}: {})",
+                    constraint, kind, relation, record
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+
+    /// Prepare the current transaction, which checks it against the
+    /// integrity constraints, unless no constraint rejects violations or the
+    /// transaction has already been prepared.
+    pub(super) fn check_constraints(&self) -> Result<(), String> {
+        let needs_check = self.integrity.rejects_any() && {
+            let prog = self.prog.lock().unwrap();
+            prog.transaction_in_progress() && !prog.transaction_prepared()
+        };
+        if needs_check {
+            self.transaction_prepare()
+        } else {
+            Ok(())
+        }
+    }
+}
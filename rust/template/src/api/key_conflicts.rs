@@ -0,0 +1,162 @@
+//! Diagnostic relation of primary-key conflicts.
+//!
+//! Programs that import the `key_conflicts` library get a
+//! `key_conflicts::KeyConflict` input relation recording every insertion of
+//! a record whose primary key was already present in its relation, along
+//! with how the conflict was resolved by the relation's conflict policy (see
+//! `differential_datalog::program::conflict` and
+//! `HDDlog::set_conflict_policy()`).
+//!
+//! Like audit log entries, conflicts are inserted into the relation by the
+//! next commit or prepare and only forgotten once that transaction has been
+//! committed, so the conflicts of a transaction that fails or is rolled
+//! back, including the rejected insertions that may have caused the
+//! rollback, are recorded by the following commit.  Conflict entries bypass
+//! the access policy and are not recorded by the command recorder.
+
+use super::*;
+
+use differential_datalog::program::conflict::KeyConflict;
+use std::borrow::Cow;
+use std::mem;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the relation declared by the `key_conflicts` library.
+const CONFLICT_RELATION: &str = "key_conflicts::KeyConflict";
+
+#[derive(Debug)]
+struct ConflictEntry {
+    /// Time the conflict was collected, in milliseconds since the UNIX
+    /// epoch.
+    timestamp: u64,
+    conflict: KeyConflict,
+}
+
+#[derive(Debug, Default)]
+struct PendingConflicts {
+    /// Sequence number of the first entry in `entries`.
+    seq: u64,
+    entries: Vec<ConflictEntry>,
+    /// The number of entries at the start of `entries` that have been
+    /// inserted into the current transaction.
+    inserted: usize,
+}
+
+/// Inserts primary-key conflicts into the conflict relation.
+#[derive(Debug)]
+pub struct ConflictLog {
+    /// The conflict relation, if the program declares one.
+    relid: Option<RelId>,
+    pending: Mutex<PendingConflicts>,
+}
+
+impl ConflictLog {
+    pub fn new() -> Self {
+        Self {
+            relid: Relations::try_from(CONFLICT_RELATION)
+                .ok()
+                .map(|rel| rel as RelId),
+            pending: Mutex::new(PendingConflicts::default()),
+        }
+    }
+
+    /// A log that records nothing, even if the program declares a conflict
+    /// relation.
+    pub fn disabled() -> Self {
+        Self {
+            relid: None,
+            pending: Mutex::new(PendingConflicts::default()),
+        }
+    }
+
+    /// Insert the conflicts encountered since the last commit into the
+    /// conflict relation as part of the current transaction.  Entries
+    /// inserted by an earlier flush are inserted again, which does nothing
+    /// unless that transaction has been rolled back.  Does nothing if the
+    /// transaction has been prepared; its conflicts are then inserted by the
+    /// next transaction.  Call `committed()` once the transaction has been
+    /// committed.
+    pub fn flush(&self, prog: &mut RunningProgram) -> Result<(), String> {
+        if prog.transaction_prepared() {
+            return Ok(());
+        }
+        // Conflicts are collected whether or not they are recorded.
+        let conflicts = prog.take_key_conflicts();
+        let relid = match self.relid {
+            Some(relid) => relid,
+            None => return Ok(()),
+        };
+        let mut pending = self.pending.lock().unwrap();
+        let timestamp = now_millis();
+        pending
+            .entries
+            .extend(conflicts.into_iter().map(|conflict| ConflictEntry {
+                timestamp,
+                conflict,
+            }));
+        if pending.entries.is_empty() {
+            return Ok(());
+        }
+        let rel = Relations::try_from(relid).map_err(|()| format!("unknown relation {}", relid))?;
+        let updates = pending
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let record = conflict_record(pending.seq + i as u64, entry);
+                relval_from_record(rel, &record).map(|v| Update::Insert { relid, v })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        prog.apply_updates(updates.into_iter(), |_| Ok(()))?;
+
+        pending.inserted = pending.entries.len();
+        Ok(())
+    }
+
+    /// Forget the entries inserted by the last flush, which are now part of
+    /// the committed contents of the conflict relation.
+    pub fn committed(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        let inserted = mem::take(&mut pending.inserted);
+        pending.entries.drain(..inserted);
+        pending.seq += inserted as u64;
+    }
+}
+
+impl Default for ConflictLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn conflict_record(seq: u64, entry: &ConflictEntry) -> Record {
+    let conflict = &entry.conflict;
+    let string = |s: String| Record::String(s);
+    let int = |i: u64| Record::Int(i.into());
+    let text = |v: &DDValue| string(v.clone().into_record().to_string());
+    Record::NamedStruct(
+        Cow::from(CONFLICT_RELATION),
+        vec![
+            (Cow::from("seq"), int(seq)),
+            (Cow::from("timestamp"), int(entry.timestamp)),
+            (
+                Cow::from("relation"),
+                string(relid2name(conflict.relid).unwrap_or_default().to_string()),
+            ),
+            (Cow::from("key"), text(&conflict.key)),
+            (Cow::from("existing"), text(&conflict.existing)),
+            (Cow::from("new"), text(&conflict.new)),
+            (
+                Cow::from("resolution"),
+                string(conflict.resolution.to_string()),
+            ),
+        ],
+    )
+}
@@ -1,5 +1,20 @@
+mod archive;
+mod audit_log;
 mod c_api;
-
+mod changelog;
+mod compression;
+mod dynamic_rules;
+mod history;
+mod integrity;
+mod key_conflicts;
+mod scheduler;
+mod self_check;
+mod session;
+mod settings_file;
+mod tenant;
+mod view_cache;
+
+pub use archive::*;
 #[cfg(feature = "c_api")]
 pub use c_api::*;
 
@@ -18,14 +33,27 @@ use std::os::windows::io::{FromRawHandle, IntoRawHandle, RawHandle};
 use std::ptr;
 use std::slice;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use differential_datalog::access::{AccessControl, AccessPolicy, Operation};
 use differential_datalog::ddval::*;
+use differential_datalog::graph::{render_graph, GraphFormat};
+use differential_datalog::metadata::ProgramMetadata;
+use differential_datalog::program::compaction::CompactionPolicy;
+use differential_datalog::program::config::{Config, ProfilingKind};
+use differential_datalog::program::conflict::ConflictPolicy;
+use differential_datalog::program::plan::PlanFormat;
+use differential_datalog::program::progress::{Frontier, Progress};
+use differential_datalog::program::sharing::DuplicateArrangement;
+use differential_datalog::program::stats::RelationStats;
 use differential_datalog::program::*;
-use differential_datalog::record::{IntoRecord, Record};
+use differential_datalog::record::{mutator_for_path, IntoRecord, PathElem, Record};
 use differential_datalog::replay;
+use differential_datalog::scheduler::TransactionScheduler;
 use differential_datalog::Callback;
 use differential_datalog::CommandRecorder;
 use differential_datalog::DeltaMap;
+use differential_datalog::FormatOptions;
 use differential_datalog::{
     D3log, D3logLocationId, DDlog, DDlogDump, DDlogDynamic, DDlogInventory, DDlogProfiling,
 };
@@ -34,6 +62,22 @@ use std::collections::BTreeSet;
 
 use super::update_handler::*;
 use super::*;
+use audit_log::Auditor;
+pub use changelog::CommitSubscriptionId;
+pub use compression::RecordingFile;
+use dynamic_rules::DynamicRules;
+pub use dynamic_rules::{RuleSetId, RulesCallback};
+use history::HistoryLog;
+pub use integrity::ConstraintMode;
+use integrity::IntegrityChecker;
+use key_conflicts::ConflictLog;
+pub use scheduler::ScheduledTransaction;
+pub use self_check::{Divergence, SelfCheck, SelfCheckCallback};
+pub use session::{Session, SessionToken};
+pub use settings_file::{
+    parse_settings, SettingValue, Settings, SettingsCallback, SettingsChange, SettingsWatcher,
+};
+pub use view_cache::{InvalidationCallback, InvalidationCallbackId, MaterializedView, ViewKeyFunc};
 
 /* FlatBuffers bindings generated by `ddlog` */
 #[cfg(feature = "flatbuf")]
@@ -43,7 +87,6 @@ use super::flatbuf;
 use super::flatbuf::FromFlatBuffer;
 
 // TODO: Move HDDlog into the differential_datalog crate.
-#[derive(Debug)]
 pub struct HDDlog {
     pub prog: Mutex<RunningProgram>,
     pub update_handler: Box<dyn IMTUpdateHandler>,
@@ -52,7 +95,71 @@ pub struct HDDlog {
     pub print_err: Option<extern "C" fn(msg: *const raw::c_char)>,
     /// When set, all commands sent to the program are recorded in
     /// the specified `.dat` file so that they can be replayed later.
-    pub command_recorder: Option<CommandRecorder<fs::File, Box<dyn DDlogInventory + Send + Sync>>>,
+    pub command_recorder:
+        Option<CommandRecorder<RecordingFile, Box<dyn DDlogInventory + Send + Sync>>>,
+    /// Access policy consulted for every update and query.
+    pub access_control: AccessControl,
+    /// Records accesses in the audit log relation, if the program has one.
+    auditor: Auditor,
+    /// Records primary-key conflicts in the conflict relation, if the
+    /// program has one.
+    conflict_log: ConflictLog,
+    /// Maintains the history relation, if the program has one.
+    history_log: HistoryLog,
+    /// Checks commits against integrity constraints.
+    integrity: IntegrityChecker,
+    /// Settings last loaded into the settings relation.
+    settings: Mutex<Settings>,
+    /// Rules loaded at runtime.
+    dynamic_rules: Mutex<DynamicRules>,
+    /// Configuration and `do_store` flag the program was started with.
+    config: Config,
+    do_store: bool,
+    /// Callbacks subscribed to by tenants in multi-tenant mode.
+    pub tenant_callbacks: TenantCallbacks,
+    /// Changelog callbacks of output relations.
+    pub changelog_callbacks: ChangelogCallbacks,
+    /// Callbacks invoked with all changes made by a commit.
+    pub commit_callbacks: CommitCallbacks,
+    /// Progress of the dataflow, which can be queried without locking
+    /// `prog`.
+    pub progress: Arc<Progress>,
+    /// Decides which client starts the next scheduled transaction.
+    scheduler: TransactionScheduler,
+}
+
+// Callbacks are not `Debug`; only report how many there are.
+impl std::fmt::Debug for HDDlog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HDDlog")
+            .field("prog", &self.prog)
+            .field("update_handler", &self.update_handler)
+            .field("db", &self.db)
+            .field("deltadb", &self.deltadb)
+            .field("print_err", &self.print_err)
+            .field("command_recorder", &self.command_recorder)
+            .field("access_control", &self.access_control)
+            .field("auditor", &self.auditor)
+            .field("conflict_log", &self.conflict_log)
+            .field("history_log", &self.history_log)
+            .field("integrity", &self.integrity)
+            .field("settings", &self.settings)
+            .field("dynamic_rules", &self.dynamic_rules)
+            .field("config", &self.config)
+            .field("do_store", &self.do_store)
+            .field("tenants", &self.tenant_callbacks.read().unwrap().len())
+            .field(
+                "changelogs",
+                &self.changelog_callbacks.read().unwrap().len(),
+            )
+            .field(
+                "commit_subscribers",
+                &self.commit_callbacks.read().unwrap().len(),
+            )
+            .field("progress", &self.progress)
+            .field("scheduler", &self.scheduler)
+            .finish()
+    }
 }
 
 impl HDDlog {
@@ -63,6 +170,23 @@ impl HDDlog {
         Self::do_run(workers, do_store, None)
     }
 
+    /// Like `run`, but starts the program with the given configuration,
+    /// e.g., to set the seed used by random number functions.
+    pub fn run_with_config(
+        config: Config,
+        do_store: bool,
+    ) -> Result<(Self, DeltaMap<DDValue>), String>
+    where
+        Self: Sized,
+    {
+        Self::do_run_with_config(config, do_store, None)
+    }
+
+    /// The configuration the program was started with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
     pub fn print_err(f: Option<extern "C" fn(msg: *const raw::c_char)>, msg: &str) {
         match f {
             None => eprintln!("{}", msg),
@@ -75,15 +199,531 @@ impl HDDlog {
     }
 
     pub fn record_commands(&mut self, file: &mut Option<fs::File>) {
+        self.set_command_recorder(file, false, false)
+    }
+
+    /// Like `record_commands()`, but precedes each recorded command with its
+    /// time since the recording started (an `at <ms>;` command), so that the
+    /// CLI can replay the recording with realistic pacing (see its
+    /// `--replay-speed` option).
+    pub fn record_timestamped_commands(&mut self, file: &mut Option<fs::File>) {
+        self.set_command_recorder(file, true, false)
+    }
+
+    fn set_command_recorder(
+        &mut self,
+        file: &mut Option<fs::File>,
+        timestamps: bool,
+        compress: bool,
+    ) {
         let mut old_recorder = None;
         mem::swap(&mut self.command_recorder, &mut old_recorder);
-        let mut old_file = old_recorder.map(|r| r.release_writer());
+        let mut old_file = old_recorder.and_then(|r| match r.release_writer().finish() {
+            Ok(f) => Some(f),
+            Err(e) => {
+                self.eprintln(&format!("failed to finish replay file: {}", e));
+                None
+            }
+        });
         mem::swap(file, &mut old_file);
 
         match old_file {
             None => self.command_recorder = None,
-            Some(f) => self.command_recorder = Some(CommandRecorder::new(f, Box::new(Inventory))),
+            Some(f) => {
+                let writer = match compress {
+                    #[cfg(feature = "compression")]
+                    true => RecordingFile::Gzip(flate2::write::GzEncoder::new(
+                        f,
+                        flate2::Compression::default(),
+                    )),
+                    _ => RecordingFile::Plain(f),
+                };
+                let mut recorder: CommandRecorder<_, Box<dyn DDlogInventory + Send + Sync>> =
+                    CommandRecorder::new(writer, Box::new(Inventory));
+                if timestamps {
+                    recorder = recorder.with_timestamps();
+                }
+                // Replaying the recording into a program whose input
+                // relations have a different schema fails early.
+                if let Err(e) = recorder.record_fingerprints(&PROGRAM_METADATA) {
+                    self.eprintln(&format!(
+                        "failed to record fingerprints in replay file: {}",
+                        e
+                    ));
+                }
+                self.command_recorder = Some(recorder);
+            }
+        }
+    }
+
+    /// Insert a batch of records into input relation `table`.
+    ///
+    /// All records are converted to values at once (see
+    /// `DDValConvert::into_ddvalue_batch()`), which avoids one memory
+    /// allocation per value when bulk-loading large numbers of facts.
+    /// Fails without applying any updates if one of the records does not
+    /// match the type of the relation.
+    pub fn insert_records(&self, table: RelId, records: &[Record]) -> Result<(), String> {
+        let rel = Relations::try_from(table).map_err(|()| format!("unknown relation {}", table))?;
+        let vals = relvals_from_records(rel, records)?;
+        self.apply_updates(&mut vals.into_iter().map(|v| Update::Insert { relid: table, v }))
+    }
+
+    /// Update part of the record with primary key `key` in input relation
+    /// `table`, without deleting and reinserting the entire record.
+    ///
+    /// `path` identifies the part of the record to update: a struct field,
+    /// possibly nested, or an element of a vector or map inside it.  `value`
+    /// is applied to it as a mutator (see `record::mutator_for_path()`).
+    pub fn modify(
+        &self,
+        table: RelId,
+        key: &Record,
+        path: &[PathElem],
+        value: Record,
+    ) -> Result<(), String> {
+        let rel = Relations::try_from(table).map_err(|()| format!("unknown relation {}", table))?;
+        let k = relkey_from_record(rel, key)?;
+        let m = mutator_for_path(path, value);
+        self.apply_updates(&mut iter::once(Update::Modify {
+            relid: table,
+            k,
+            m: Arc::new(m),
+        }))
+    }
+
+    /// Retract all facts of input relation `table` in a single pass (see
+    /// `RunningProgram::clear_relation_bulk()`).
+    pub fn clear_relation_bulk(&self, table: RelId) -> Result<(), String> {
+        self.check_access(table, Operation::Clear)?;
+        self.record_command(|r| r.clear_relation(table));
+        self.prog.lock().unwrap().clear_relation_bulk(table)
+    }
+
+    /// Load `values` into input relation `table` before the first
+    /// transaction is started (see `RunningProgram::bulk_load()`).  Bulk
+    /// loads are not recorded by the command recorder.
+    pub fn bulk_load(&self, table: RelId, values: Vec<DDValue>) -> Result<(), String> {
+        self.check_access(table, Operation::Insert)?;
+        let rel = Relations::try_from(table).map_err(|()| format!("unknown relation {}", table))?;
+        for v in values.iter() {
+            relval_validate(rel, v)?;
+        }
+        self.prog.lock().unwrap().bulk_load(table, values)
+    }
+
+    /// Bring the contents of input relation `table` in line with `desired`.
+    ///
+    /// Computes the minimal set of insertions and deletions between the
+    /// current and the desired contents of the relation (see
+    /// `RunningProgram::relation_sync_updates()`) and applies it in a new
+    /// transaction.  The transaction is rolled back if any of the updates
+    /// fails or the transaction is poisoned by a panic.
+    pub fn sync_relation(&self, table: RelId, desired: Vec<DDValue>) -> Result<(), String> {
+        self.transaction_start()?;
+        let updates = self
+            .prog
+            .lock()
+            .unwrap()
+            .relation_sync_updates(table, desired);
+        let res = updates
+            .and_then(|upds| self.apply_updates(&mut upds.into_iter()))
+            .and_then(|()| self.transaction_commit());
+        if res.is_err() {
+            let _ = self.transaction_rollback();
+        }
+        res
+    }
+
+    /// Register a key extractor that enables `insert_or_update` for multiset
+    /// input relation `table` (see `RunningProgram::set_upsert_key()`).
+    pub fn set_upsert_key(
+        &self,
+        table: RelId,
+        key_func: Option<UpsertKeyFunc>,
+    ) -> Result<(), String> {
+        self.prog.lock().unwrap().set_upsert_key(table, key_func)
+    }
+
+    /// Set the policy for inserting a record whose key is already present
+    /// in input relation `table` with a primary key, or restore the default
+    /// of failing the insertion if `None` (see
+    /// `RunningProgram::set_conflict_policy()`).  Conflicts are recorded in
+    /// the `key_conflicts::KeyConflict` relation if the program imports the
+    /// `key_conflicts` library.
+    pub fn set_conflict_policy(
+        &self,
+        table: RelId,
+        policy: Option<ConflictPolicy>,
+    ) -> Result<(), String> {
+        self.prog.lock().unwrap().set_conflict_policy(table, policy)
+    }
+
+    /// Install a policy that decides whether the caller of each update and
+    /// query is allowed to access the relation (see
+    /// `differential_datalog::access`), or remove the policy if `None`.
+    /// Rolling back a transaction is always allowed.
+    pub fn set_access_policy(&self, policy: Option<AccessPolicy>) {
+        self.access_control.set_policy(policy)
+    }
+
+    /// Metadata embedded into the program by the compiler: its name, source
+    /// hash, compiler version, and relation schema fingerprints (see
+    /// `differential_datalog::metadata`).
+    pub fn metadata(&self) -> &'static ProgramMetadata {
+        &PROGRAM_METADATA
+    }
+
+    /// Schema fingerprint of relation `table`.
+    pub fn relation_fingerprint(&self, table: RelId) -> Result<u64, String> {
+        relid2name(table)
+            .and_then(|name| PROGRAM_METADATA.fingerprint(name))
+            .ok_or_else(|| format!("unknown relation {}", table))
+    }
+
+    /// Current progress of the dataflow: the last closed input epoch and how
+    /// far all workers have processed it (see
+    /// `differential_datalog::program::progress`).  Does not block while
+    /// another thread commits a transaction.
+    pub fn frontier(&self) -> Frontier {
+        self.progress.frontier()
+    }
+
+    /// Block until all outputs reflect all committed inputs or `timeout`
+    /// expires.  Returns `true` if the dataflow is quiescent.
+    pub fn await_quiescence(&self, timeout: Duration) -> bool {
+        self.progress.await_quiescence(timeout).is_quiescent()
+    }
+
+    /// Make the arrangement of `index` retain the history of the last `lag`
+    /// transactions (see `differential_datalog::program::compaction`).
+    pub fn set_compaction_lag(&self, index: IdxId, lag: TS) -> Result<(), String> {
+        let idx = Indexes::try_from(index).map_err(|()| format!("unknown index {}", index))?;
+        self.prog
+            .lock()
+            .unwrap()
+            .set_compaction_lag(indexes2arrid(idx), lag)
+    }
+
+    /// Discard the history retained by the arrangement of `index`, or of all
+    /// indexes if `index` is `None`.
+    pub fn compact_now(&self, index: Option<IdxId>) -> Result<(), String> {
+        let arrid = match index {
+            Some(index) => Some(indexes2arrid(
+                Indexes::try_from(index).map_err(|()| format!("unknown index {}", index))?,
+            )),
+            None => None,
+        };
+        self.prog.lock().unwrap().compact_now(arrid)
+    }
+
+    /// Set the policy for compacting rarely queried indexes that have a
+    /// compaction lag.
+    pub fn set_compaction_policy(&self, policy: Option<CompactionPolicy>) {
+        self.prog.lock().unwrap().set_compaction_policy(policy)
+    }
+
+    /// Duplicate and near-duplicate arrangements of the program.  Exact
+    /// duplicates are shared when the program runs with
+    /// `Config::share_duplicate_arrangements`.
+    pub fn arrangement_report(&self) -> Vec<DuplicateArrangement> {
+        self.prog.lock().unwrap().arrangement_report().to_vec()
+    }
+
+    /// Statistics of relation `table` (see `RunningProgram::relation_stats()`).
+    pub fn relation_stats(&self, table: RelId) -> Result<RelationStats, String> {
+        self.check_access(table, Operation::Query)?;
+        self.prog
+            .lock()
+            .unwrap()
+            .relation_stats(table)
+            .ok_or_else(|| format!("relation {} is unknown or not counted", table))
+    }
+
+    /// Statistics of all counted relations the caller is allowed to query.
+    pub fn all_relation_stats(&self) -> BTreeMap<RelId, RelationStats> {
+        let stats = self.prog.lock().unwrap().all_relation_stats();
+        stats
+            .into_iter()
+            .filter(|(relid, _)| self.check_access(*relid, Operation::Query).is_ok())
+            .collect()
+    }
+
+    /// Render the dataflow graph the program compiles into: its relations,
+    /// arrangements and operators, annotated with the current size of
+    /// relations.
+    pub fn explain_plan(&self, format: PlanFormat) -> String {
+        self.prog.lock().unwrap().explain_plan(format)
+    }
+
+    /// Propagate commits deferred under `Config::commit_latency_budget`
+    /// through the dataflow, delivering their outputs to the update handler
+    /// (see `RunningProgram::flush_deferred()`).
+    pub fn flush_deferred(&self) -> Result<(), String> {
+        self.update_handler.before_commit();
+
+        let res = self.prog.lock().unwrap().flush_deferred();
+        self.update_handler.after_commit(res.is_ok());
+        if res.is_ok() {
+            self.refresh_rules_after_commit();
+        }
+        res
+    }
+
+    /// Commit the current transaction, or roll it back if propagating it
+    /// through the dataflow takes longer than `budget` (see
+    /// `RunningProgram::transaction_commit_with_deadline()`).  The recording
+    /// of commands shows the outcome.
+    pub fn transaction_commit_with_deadline(&self, budget: Duration) -> Result<(), String> {
+        self.update_handler.before_commit();
+        let mut in_progress = true;
+        let res = self.commit_audited(|prog| {
+            let res = prog.transaction_commit_with_deadline(budget);
+            in_progress = prog.transaction_in_progress();
+            res
+        });
+        self.update_handler.after_commit(res.is_ok());
+        match res {
+            Ok(()) => {
+                self.record_command(|r| r.transaction_commit());
+                self.refresh_rules_after_commit();
+            }
+            Err(_) if !in_progress => self.record_command(|r| r.transaction_rollback()),
+            Err(_) => (),
+        }
+        res
+    }
+
+    /// Commit the current transaction, unless a transaction with the same
+    /// `id` has already been committed, in which case it is rolled back
+    /// (see `RunningProgram::transaction_commit_with_id()`).  Returns `true`
+    /// if the transaction was committed.
+    ///
+    /// Commits are recorded along with their ids, so that replaying the
+    /// recorded commands into a new instance of the program also restores
+    /// the set of committed ids.
+    pub fn transaction_commit_with_id(&self, id: &str) -> Result<bool, String> {
+        self.check_constraints()?;
+        self.record_command(|r| r.transaction_commit_with_id(id));
+        self.update_handler.before_commit();
+        let res = self.commit_audited(|prog| prog.transaction_commit_with_id(id));
+        self.update_handler.after_commit(res.is_ok());
+        if let Ok(true) = res {
+            self.refresh_rules_after_commit();
+        }
+        res
+    }
+
+    /// Ids of recently committed transactions, oldest first.
+    pub fn committed_transaction_ids(&self) -> Vec<String> {
+        self.prog.lock().unwrap().committed_transaction_ids()
+    }
+
+    /// Returns the error that poisoned the current transaction, if any (see
+    /// `RunningProgram::poisoned()`).
+    pub fn poisoned(&self) -> Option<DDlogError> {
+        self.prog.lock().unwrap().poisoned().cloned()
+    }
+
+    /// Like `query_index()`, but also returns the multiplicity of each
+    /// value, i.e., the number of its derivations.
+    pub fn query_index_multiplicities(
+        &self,
+        index: IdxId,
+        key: DDValue,
+    ) -> Result<BTreeMap<DDValue, Weight>, String> {
+        let idx = Indexes::try_from(index).map_err(|()| format!("unknown index {}", index))?;
+        let arrid = indexes2arrid(idx);
+        self.check_access(arrid.0, Operation::Query)?;
+        self.prog
+            .lock()
+            .unwrap()
+            .query_arrangement_multiplicities(arrid, key)
+    }
+
+    /// Like `dump_index()`, but also returns the multiplicity of each value.
+    pub fn dump_index_multiplicities(
+        &self,
+        index: IdxId,
+    ) -> Result<BTreeMap<DDValue, Weight>, String> {
+        let idx = Indexes::try_from(index).map_err(|()| format!("unknown index {}", index))?;
+        let arrid = indexes2arrid(idx);
+        self.check_access(arrid.0, Operation::Query)?;
+        self.prog
+            .lock()
+            .unwrap()
+            .dump_arrangement_multiplicities(arrid)
+    }
+
+    /// Number of the last committed transaction, `0` before the first
+    /// commit.  Commits are numbered from 1.
+    pub fn commit_number(&self) -> u64 {
+        self.prog.lock().unwrap().commit_number()
+    }
+
+    /// Make the arrangement of `index` retain its history for the last
+    /// `commits` commits, so that it can be queried as of any of them with
+    /// `query_index_asof()`, or stop retaining it if `commits` is 0 (see
+    /// `differential_datalog::program::time_travel`).  Replaces the
+    /// compaction lag of the index.
+    pub fn set_index_time_travel(&self, index: IdxId, commits: usize) -> Result<(), String> {
+        let idx = Indexes::try_from(index).map_err(|()| format!("unknown index {}", index))?;
+        self.prog
+            .lock()
+            .unwrap()
+            .set_time_travel(indexes2arrid(idx), commits)
+    }
+
+    /// Like `query_index()`, but returns the values as they were right after
+    /// commit `commit_no`, e.g., to debug a regression in an output relation.
+    /// Fails if the commit is not retained by the index (see
+    /// `set_index_time_travel()`).  Indexes of relations derived from the
+    /// history relation (see `set_history_retention()`) include the changes
+    /// that commit `commit_no` made to the history.
+    pub fn query_index_asof(
+        &self,
+        index: IdxId,
+        key: DDValue,
+        commit_no: u64,
+    ) -> Result<BTreeSet<DDValue>, String> {
+        self.query_index_as_of(index, Some(key), commit_no)
+    }
+
+    /// Like `dump_index()`, but returns the values as they were right after
+    /// commit `commit_no`.
+    pub fn dump_index_asof(
+        &self,
+        index: IdxId,
+        commit_no: u64,
+    ) -> Result<BTreeSet<DDValue>, String> {
+        self.query_index_as_of(index, None, commit_no)
+    }
+
+    fn query_index_as_of(
+        &self,
+        index: IdxId,
+        key: Option<DDValue>,
+        commit_no: u64,
+    ) -> Result<BTreeSet<DDValue>, String> {
+        let idx = Indexes::try_from(index).map_err(|()| format!("unknown index {}", index))?;
+        let arrid = indexes2arrid(idx);
+        self.check_access(arrid.0, Operation::Query)?;
+        Ok(self
+            .prog
+            .lock()
+            .unwrap()
+            .query_arrangement_as_of(arrid, key, commit_no)?
+            .into_iter()
+            .map(|(v, _)| v)
+            .collect())
+    }
+
+    /// Returns the contents of output relations `relids` as of the same
+    /// commit.  Calling `dump_table()` for each relation in turn may observe
+    /// different commits if another thread commits transactions in between;
+    /// `dump_all()` captures all relations while holding the lock that
+    /// commits hold while updating stored relations, so the snapshot
+    /// reflects the same set of transactions for every relation.
+    ///
+    /// Values are returned in the order of their type, or in the canonical
+    /// order if the program was started with `Config::canonical_order`.
+    /// Requires the program to have been started with `do_store` enabled.
+    pub fn dump_all(
+        &self,
+        relids: &[RelId],
+    ) -> Result<BTreeMap<RelId, Vec<(DDValue, Weight)>>, String> {
+        self.dump_stored(relids)?
+            .into_iter()
+            .map(|(relid, facts)| {
+                let facts = facts
+                    .into_iter()
+                    .map(|(v, w)| {
+                        Weight::try_from(w)
+                            .map_err(|_| {
+                                format!("weight {} of {} does not fit into a Weight", w, v)
+                            })
+                            .map(|w| (v, w))
+                    })
+                    .collect::<Result<Vec<(DDValue, Weight)>, String>>()?;
+                Ok((relid, facts))
+            })
+            .collect()
+    }
+
+    /// Like `dump_all()`, but returns the `isize` weights that `do_store`
+    /// keeps, which are also the weights of changelog batches.  Used by
+    /// sinks that start from a snapshot and then follow changelogs.
+    pub(crate) fn dump_stored(
+        &self,
+        relids: &[RelId],
+    ) -> Result<BTreeMap<RelId, Vec<(DDValue, isize)>>, String> {
+        for relid in relids {
+            match Relations::try_from(*relid) {
+                Ok(rel) if rel.is_output() => (),
+                _ => return Err(format!("unknown output relation {}", relid)),
+            }
+            self.check_access(*relid, Operation::Query)?;
+        }
+        let db = self
+            .db
+            .as_ref()
+            .ok_or_else(|| "cannot dump tables: do_store is disabled".to_string())?
+            .lock()
+            .unwrap();
+        let mut snapshot = BTreeMap::new();
+        for relid in relids {
+            self.record_command(|r| r.dump_table(*relid, None));
+            let mut facts: Vec<(DDValue, isize)> = db
+                .try_get_rel(*relid)
+                .into_iter()
+                .flatten()
+                .map(|(v, w)| (v.clone(), *w))
+                .collect();
+            if self.config.canonical_order {
+                sort_canonically(&mut facts);
+            }
+            snapshot.insert(*relid, facts);
         }
+        Ok(snapshot)
+    }
+
+    /// The `n` dataflow operators that used the most CPU time (see
+    /// `Profile::hotspots()`).  Empty unless CPU profiling is enabled.
+    pub fn cpu_hotspots(&self, n: usize) -> Vec<(String, Duration, usize)> {
+        self.prog
+            .lock()
+            .unwrap()
+            .profile
+            .as_ref()
+            .map(|profile| profile.lock().unwrap().hotspots(n))
+            .unwrap_or_default()
+    }
+
+    /// Render output relation `edges`, optionally labeled by output relation
+    /// `labels`, as a graph in `format` (see `differential_datalog::graph`).
+    /// Both relations are read from the same snapshot (see `dump_all()`).
+    pub fn export_graph(
+        &self,
+        edges: RelId,
+        labels: Option<RelId>,
+        format: GraphFormat,
+    ) -> Result<String, String> {
+        let relids: Vec<RelId> = iter::once(edges).chain(labels).collect();
+        let snapshot = self.dump_all(&relids)?;
+        let records = |relid: Option<RelId>| -> Vec<Record> {
+            relid
+                .and_then(|relid| snapshot.get(&relid))
+                .into_iter()
+                .flatten()
+                .map(|(v, _)| v.clone().into_record())
+                .collect()
+        };
+        render_graph(
+            relid2name(edges).unwrap_or("graph"),
+            &records(Some(edges)),
+            &records(labels),
+            format,
+        )
     }
 
     /// Apply a set of updates directly from the flatbuffer
@@ -169,6 +809,8 @@ impl DDlogInventory for HDDlog {
 impl DDlogDump for HDDlog {
     fn dump_input_snapshot(&self, w: &mut dyn io::Write) -> io::Result<()> {
         for (rel, relname) in INPUT_RELIDMAP.iter() {
+            self.check_access(*rel as RelId, Operation::Query)
+                .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e))?;
             let prog = self.prog.lock().unwrap();
             match prog.get_input_relation_data(*rel as RelId) {
                 Ok(valset) => {
@@ -200,9 +842,17 @@ impl DDlogDump for HDDlog {
                                 }
                             }
                         }
-                        _ => {
-                            panic!("Unknown input relation {:?} in dump_input_snapshot", rel);
-                        }
+                        _ => match prog.get_input_columnar_data(*rel as RelId) {
+                            Ok(columnar) => {
+                                for v in columnar.iter() {
+                                    replay::record_insert(w, relname, &v)?;
+                                    writeln!(w, ",")?;
+                                }
+                            }
+                            _ => {
+                                panic!("Unknown input relation {:?} in dump_input_snapshot", rel);
+                            }
+                        },
                     },
                 },
             }
@@ -215,9 +865,15 @@ impl DDlogDump for HDDlog {
         table: RelId,
         cb: Option<&dyn Fn(&record::Record, isize) -> bool>,
     ) -> Result<(), String> {
+        self.check_access(table, Operation::Query)?;
         self.record_command(|r| r.dump_table(table, None));
         if let Some(ref db) = self.db {
-            HDDlog::db_dump_table(&mut db.lock().unwrap(), table, cb);
+            HDDlog::db_dump_table(
+                &mut db.lock().unwrap(),
+                table,
+                self.config.canonical_order,
+                cb,
+            );
             Ok(())
         } else {
             Err(
@@ -260,13 +916,22 @@ impl DDlogDynamic for HDDlog {
         self.prog.lock().unwrap().transaction_start()
     }
 
+    fn transaction_prepare(&self) -> Result<(), String> {
+        // Output changes are held back until the transaction is committed.
+        self.start_constraint_check();
+        self.commit_audited(|prog| prog.transaction_prepare())?;
+        self.finish_constraint_check()
+    }
+
     fn transaction_commit(&self) -> Result<(), String> {
+        self.check_constraints()?;
         self.record_command(|r| r.transaction_commit());
         self.update_handler.before_commit();
 
-        match (self.prog.lock().unwrap().transaction_commit()) {
+        match (self.commit_audited(|prog| prog.transaction_commit())) {
             Ok(()) => {
                 self.update_handler.after_commit(true);
+                self.refresh_rules_after_commit();
                 Ok(())
             }
             Err(e) => {
@@ -295,10 +960,16 @@ impl DDlogDynamic for HDDlog {
 
     fn transaction_rollback(&self) -> Result<(), String> {
         self.record_command(|r| r.transaction_rollback());
-        self.prog.lock().unwrap().transaction_rollback()
+        // Rolling back a prepared or poisoned transaction retracts the output
+        // changes it produced.
+        self.update_handler.before_commit();
+        let res = self.prog.lock().unwrap().transaction_rollback();
+        self.update_handler.after_commit(res.is_ok());
+        res
     }
 
     fn clear_relation(&self, table: RelId) -> Result<(), String> {
+        self.check_access(table, Operation::Clear)?;
         self.record_command(|r| r.clear_relation(table));
         self.prog.lock().unwrap().clear_relation(table)
     }
@@ -371,13 +1042,18 @@ impl DDlogDynamic for HDDlog {
 
 impl DDlog for HDDlog {
     fn transaction_commit_dump_changes(&self) -> Result<DeltaMap<DDValue>, String> {
-        self.record_command(|r| r.transaction_commit_dump_changes());
         *self.deltadb.lock().unwrap() = Some(DeltaMap::new());
+        if let Err(e) = self.check_constraints() {
+            *self.deltadb.lock().unwrap() = None;
+            return Err(e);
+        }
+        self.record_command(|r| r.transaction_commit_dump_changes());
 
         self.update_handler.before_commit();
-        match (self.prog.lock().unwrap().transaction_commit()) {
+        match (self.commit_audited(|prog| prog.transaction_commit())) {
             Ok(()) => {
                 self.update_handler.after_commit(true);
+                self.refresh_rules_after_commit();
                 let mut delta = self.deltadb.lock().unwrap();
                 Ok(delta.take().unwrap())
             }
@@ -389,20 +1065,7 @@ impl DDlog for HDDlog {
     }
 
     fn apply_updates(&self, upds: &mut dyn Iterator<Item = Update<DDValue>>) -> Result<(), String> {
-        // Make sure that the updates being inserted have the correct value types for their
-        // relation
-        let inspect_update: fn(&Update<DDValue>) -> Result<(), String> = |update| {
-            let relation = Relations::try_from(update.relid())
-                .map_err(|_| format!("unknown relation id {}", update.relid()))?;
-
-            if let Some(value) = update.get_value() {
-                if relation.type_id() != value.type_id() {
-                    return Err(format!("attempted to insert the incorrect type {:?} into relation {:?} whose value type is {:?}", value.type_id(), relation, relation.type_id()));
-                }
-            }
-
-            Ok(())
-        };
+        let inspect_update = |update: &Update<DDValue>| self.inspect_update(update);
 
         if self.command_recorder.is_some() {
             let update_vec: Vec<_> = upds.collect();
@@ -424,6 +1087,7 @@ impl DDlog for HDDlog {
         self.record_command(|r| r.query_index(index, key.clone()));
         let idx = Indexes::try_from(index).map_err(|()| format!("unknown index {}", index))?;
         let arrid = indexes2arrid(idx);
+        self.check_access(arrid.0, Operation::Query)?;
         self.prog.lock().unwrap().query_arrangement(arrid, key)
     }
 
@@ -431,6 +1095,7 @@ impl DDlog for HDDlog {
         self.record_command(|r| r.dump_index(index));
         let idx = Indexes::try_from(index).map_err(|()| format!("unknown index {}", index))?;
         let arrid = indexes2arrid(idx);
+        self.check_access(arrid.0, Operation::Query)?;
         self.prog.lock().unwrap().dump_arrangement(arrid)
     }
 }
@@ -452,29 +1117,56 @@ impl HDDlog {
         do_store: bool,
         print_err: Option<extern "C" fn(msg: *const raw::c_char)>,
     ) -> Result<(Self, DeltaMap<DDValue>), String> {
-        let workers = if workers == 0 { 1 } else { workers };
+        let config = Config {
+            num_timely_workers: if workers == 0 { 1 } else { workers },
+            profiling_kind: ProfilingKind::SelfProfiling,
+            ..Default::default()
+        };
+        Self::do_run_with_config(config, do_store, print_err)
+    }
 
+    fn do_run_with_config(
+        config: Config,
+        do_store: bool,
+        print_err: Option<extern "C" fn(msg: *const raw::c_char)>,
+    ) -> Result<(Self, DeltaMap<DDValue>), String> {
         let db: Arc<Mutex<DeltaMap<DDValue>>> = Arc::new(Mutex::new(DeltaMap::new()));
         let db2 = db.clone();
 
         let deltadb: Arc<Mutex<Option<DeltaMap<_>>>> = Arc::new(Mutex::new(Some(DeltaMap::new())));
         let deltadb2 = deltadb.clone();
 
+        let tenant_callbacks = TenantCallbacks::default();
+        let tenant_callbacks2 = tenant_callbacks.clone();
+
+        let changelog_callbacks = ChangelogCallbacks::default();
+        let changelog_callbacks2 = changelog_callbacks.clone();
+        let commit_callbacks = CommitCallbacks::default();
+        let commit_callbacks2 = commit_callbacks.clone();
+
         let handler: Box<dyn IMTUpdateHandler> = {
             let handler_generator = move || {
                 /* Always use delta handler, which costs nothing unless it is
                  * actually used. */
                 let delta_handler = DeltaUpdateHandler::new(deltadb2);
 
+                /* Likewise, the changelog handler only records changes to
+                 * relations with a changelog or commit subscriber. */
+                let changelog_handler = ChangelogUpdateHandler::new(
+                    changelog_callbacks2,
+                    commit_callbacks2,
+                    config.canonical_order,
+                );
+
+                let mut handlers: Vec<Box<dyn UpdateHandler>> =
+                    vec![Box::new(delta_handler), Box::new(changelog_handler)];
                 if do_store {
-                    let handlers: Vec<Box<dyn UpdateHandler>> = vec![
-                        Box::new(delta_handler),
-                        Box::new(ValMapUpdateHandler::new(db2)),
-                    ];
-                    Box::new(ChainedUpdateHandler::new(handlers)) as Box<dyn UpdateHandler>
-                } else {
-                    Box::new(delta_handler) as Box<dyn UpdateHandler>
+                    handlers.push(Box::new(ValMapUpdateHandler::new(db2)));
                 }
+                if config.multi_tenant {
+                    handlers.push(Box::new(TenantUpdateHandler::new(tenant_callbacks2)));
+                }
+                Box::new(ChainedUpdateHandler::new(handlers)) as Box<dyn UpdateHandler>
             };
             Box::new(ThreadUpdateHandler::new(handler_generator))
         };
@@ -483,7 +1175,7 @@ impl HDDlog {
 
         /* Notify handler about initial transaction */
         handler.before_commit();
-        let prog = program.run(workers as usize)?;
+        let prog = program.run_with_config(config)?;
         handler.after_commit(true);
 
         /* Extract state after initial transaction. */
@@ -491,22 +1183,53 @@ impl HDDlog {
 
         Ok((
             HDDlog {
+                progress: prog.progress(),
                 prog: Mutex::new(prog),
                 update_handler: handler,
                 db: Some(db),
                 deltadb,
                 print_err,
                 command_recorder: None,
+                access_control: AccessControl::default(),
+                auditor: Auditor::new(),
+                conflict_log: ConflictLog::new(),
+                history_log: HistoryLog::new(),
+                integrity: IntegrityChecker::new(&commit_callbacks),
+                settings: Mutex::new(Settings::new()),
+                dynamic_rules: Mutex::new(DynamicRules::default()),
+                config,
+                do_store,
+                tenant_callbacks,
+                changelog_callbacks,
+                commit_callbacks,
+                scheduler: TransactionScheduler::new(),
             },
             init_state,
         ))
     }
 
-    fn db_dump_table<F>(db: &mut DeltaMap<DDValue>, table: usize, cb: Option<F>)
-    where
+    fn db_dump_table<F>(
+        db: &mut DeltaMap<DDValue>,
+        table: usize,
+        canonical_order: bool,
+        cb: Option<F>,
+    ) where
         F: Fn(&record::Record, isize) -> bool,
     {
         if let Some(f) = cb {
+            if canonical_order {
+                let options = FormatOptions {
+                    canonical_order,
+                    ..FormatOptions::default()
+                };
+                let records = options.records(db.get_rel(table).iter().map(|(v, w)| (v, *w)));
+                for (rec, w) in records.iter() {
+                    if !f(rec, *w) {
+                        break;
+                    }
+                }
+                return;
+            }
             for (val, w) in db.get_rel(table) {
                 //assert!(*w == 1);
                 if !f(&val.clone().into_record(), *w) {
@@ -516,6 +1239,65 @@ impl HDDlog {
         };
     }
 
+    /// Make sure that the update being inserted has the correct value type for
+    /// its relation and conforms to the relation's JSON schema, if any, and
+    /// that the caller is allowed to modify the relation.
+    fn inspect_update(&self, update: &Update<DDValue>) -> Result<(), String> {
+        let relation = Relations::try_from(update.relid())
+            .map_err(|_| format!("unknown relation id {}", update.relid()))?;
+
+        if let Some(value) = update.get_value() {
+            if relation.type_id() != value.type_id() {
+                return Err(format!("attempted to insert the incorrect type {:?} into relation {:?} whose value type is {:?}", value.type_id(), relation, relation.type_id()));
+            }
+        }
+
+        match update {
+            Update::Insert { v, .. } | Update::InsertOrUpdate { v, .. } => {
+                relval_validate(relation, v)?
+            }
+            _ => (),
+        }
+
+        self.check_access(update.relid(), Operation::of_update(update))
+    }
+
+    /// Check the access policy for `operation` on relation `relid`, and
+    /// record the access in the audit log.
+    fn check_access(&self, relid: RelId, operation: Operation) -> Result<(), String> {
+        let res =
+            self.access_control
+                .check(relid, relid2name(relid).unwrap_or_default(), operation);
+        self.auditor.record(relid, operation, res.is_ok());
+        res
+    }
+
+    /// Commit the current transaction of `prog` after inserting the
+    /// accesses and key conflicts recorded since the last commit and the
+    /// changes to the history made since then, including those of the
+    /// transaction, into the audit log, conflict relation, and history
+    /// relation.
+    /// The recorded entries are kept until `commit` actually commits the
+    /// transaction, so that a failed or rolled back commit does not lose
+    /// them.
+    fn commit_audited<T, F>(&self, commit: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut RunningProgram) -> Result<T, String>,
+    {
+        let mut prog = self.prog.lock().unwrap();
+        let commit_number = prog.commit_number();
+        self.auditor.flush(&mut prog)?;
+        self.conflict_log.flush(&mut prog)?;
+        self.history_log.flush(&mut prog)?;
+        let res = commit(&mut prog);
+        if prog.commit_number() != commit_number {
+            self.auditor.committed();
+            self.conflict_log.committed();
+            self.history_log.committed();
+        }
+        res
+    }
+
     fn record_command<T, F>(&self, cmd: F)
     where
         F: FnOnce(
@@ -0,0 +1,97 @@
+//! Transactions scheduled fairly among multiple clients.
+//!
+//! `HDDlog::transaction_start()` fails while another transaction is in
+//! progress, leaving it to the clients sharing a program to retry.  Clients
+//! that start transactions with `HDDlog::try_start_transaction()` instead
+//! wait for their turn, which is granted in proportion to their priority
+//! (see `differential_datalog::scheduler`).  The scheduler only orders
+//! scheduled transactions: clients that call `transaction_start()` directly
+//! still compete with them first-come, first-served.
+
+use super::*;
+
+use differential_datalog::scheduler::{ClientId, Turn};
+
+impl HDDlog {
+    /// Set the share of transactions granted to `client` when several
+    /// clients are waiting; the default priority is 1.
+    pub fn set_client_priority(&self, client: ClientId, priority: u32) {
+        self.scheduler.set_priority(client, priority)
+    }
+
+    /// Forget the priority of `client`, e.g., when it disconnects.
+    pub fn remove_client(&self, client: ClientId) {
+        self.scheduler.remove_client(client)
+    }
+
+    /// Wait for the turn of `client` and start a transaction.  Fails if the
+    /// turn does not come within `timeout`, if specified, or if the
+    /// transaction cannot be started.  The next client gets its turn once
+    /// the transaction is committed or rolled back.
+    pub fn try_start_transaction(
+        &self,
+        client: ClientId,
+        timeout: Option<Duration>,
+    ) -> Result<ScheduledTransaction<'_>, String> {
+        let turn = self.scheduler.acquire(client, timeout)?;
+        self.transaction_start()?;
+        Ok(ScheduledTransaction {
+            hddlog: self,
+            turn,
+            open: true,
+        })
+    }
+}
+
+/// A transaction started by `HDDlog::try_start_transaction()`.  The
+/// transaction is rolled back if it is dropped without being committed.
+#[derive(Debug)]
+pub struct ScheduledTransaction<'a> {
+    hddlog: &'a HDDlog,
+    turn: Turn<'a>,
+    open: bool,
+}
+
+impl ScheduledTransaction<'_> {
+    /// The client that started the transaction.
+    pub fn client(&self) -> ClientId {
+        self.turn.client()
+    }
+
+    pub fn apply_updates(
+        &self,
+        upds: &mut dyn Iterator<Item = Update<DDValue>>,
+    ) -> Result<(), String> {
+        self.hddlog.apply_updates(upds)
+    }
+
+    pub fn apply_updates_dynamic(
+        &self,
+        upds: &mut dyn Iterator<Item = UpdCmd>,
+    ) -> Result<(), String> {
+        self.hddlog.apply_updates_dynamic(upds)
+    }
+
+    pub fn commit(mut self) -> Result<(), String> {
+        self.open = false;
+        self.hddlog.transaction_commit()
+    }
+
+    pub fn commit_dump_changes(mut self) -> Result<DeltaMap<DDValue>, String> {
+        self.open = false;
+        self.hddlog.transaction_commit_dump_changes()
+    }
+
+    pub fn rollback(mut self) -> Result<(), String> {
+        self.open = false;
+        self.hddlog.transaction_rollback()
+    }
+}
+
+impl Drop for ScheduledTransaction<'_> {
+    fn drop(&mut self) {
+        if self.open {
+            let _ = self.hddlog.transaction_rollback();
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! Differential consistency self-check.
+//!
+//! `self_check()` recomputes output relations from scratch and compares
+//! them with the incrementally maintained versions.  It starts a shadow
+//! instance of the program, loads a snapshot of the current inputs into it
+//! in a single transaction, and diffs the outputs of the shadow against the
+//! outputs stored by the program, reporting the offending facts of every
+//! relation that diverges.  Since the shadow computes its outputs without
+//! any history, a divergence points at an operator that does not maintain
+//! its output correctly under incremental updates, such as a custom
+//! aggregate or an extern function that is not pure.
+//!
+//! `start_self_check()` runs the check periodically on a background
+//! thread.  Checks are expensive, as they copy all inputs and recompute the
+//! program from scratch, and are meant for debugging and testing.  The
+//! program must run with `do_store` enabled, and a check must not overlap
+//! with a commit, which would make the snapshot of inputs and outputs
+//! inconsistent.
+
+use super::*;
+
+use crossbeam_channel::RecvTimeoutError;
+use differential_datalog::program::placement::WorkerPlacement;
+use std::sync::Weak;
+use std::thread::{self, JoinHandle};
+
+/// Facts on which the incrementally maintained and the recomputed contents
+/// of an output relation disagree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub relation: String,
+    /// Facts, and their weights, computed from scratch that the program
+    /// does not contain with the same weight.
+    pub missing: Vec<(Record, isize)>,
+    /// Facts, and their weights, contained by the program that were not
+    /// computed from scratch with the same weight.
+    pub unexpected: Vec<(Record, isize)>,
+}
+
+/// Callback invoked by the periodic self-check with the divergences found
+/// by every check that found some or failed.
+pub type SelfCheckCallback = Arc<dyn Fn(Result<&[Divergence], &str>) + Send + Sync>;
+
+/// Stops the periodic self-check when dropped.
+#[derive(Debug)]
+pub struct SelfCheck {
+    stop: Option<crossbeam_channel::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for SelfCheck {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes up the checker thread.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl HDDlog {
+    /// Recompute output relations `tables`, or all output relations if
+    /// `tables` is empty, from scratch and compare them with their current
+    /// contents.  Returns the relations that diverge.
+    pub fn self_check(&self, tables: &[RelId]) -> Result<Vec<Divergence>, String> {
+        if !self.do_store {
+            return Err("self-check requires do_store to be enabled".to_string());
+        }
+        for table in tables.iter() {
+            match Relations::try_from(*table) {
+                Ok(rel) if rel.is_output() => {}
+                _ => return Err(format!("unknown output relation {}", table)),
+            }
+        }
+        let snapshot = self.archive(true)?;
+
+        // The shadow runs on unpinned workers, and commits immediately.
+        let config = Config {
+            worker_placement: WorkerPlacement::Unpinned,
+            numa_local_memory: false,
+            commit_latency_budget: None,
+            ..self.config
+        };
+        let (mut shadow, _) = HDDlog::run_with_config(config, true)?;
+        // Accesses made and conflicts encountered by the shadow must not
+        // show up in its audit log and conflict relation, which are restored
+        // from the program's.
+        shadow.auditor = Auditor::disabled();
+        shadow.conflict_log = ConflictLog::disabled();
+        let res = shadow
+            .restore(&snapshot)
+            .and_then(|()| shadow.archive(true));
+        let _ = shadow.stop();
+        let recomputed = res?;
+
+        let selected = |relation: &ArchivedRelation| {
+            !relation.input
+                && (tables.is_empty()
+                    || Relations::try_from(relation.name.as_str())
+                        .map_or(false, |rel| tables.contains(&(rel as RelId))))
+        };
+        let current: BTreeMap<&str, &ArchivedRelation> = snapshot
+            .relations
+            .iter()
+            .filter(|relation| selected(*relation))
+            .map(|relation| (relation.name.as_str(), relation))
+            .collect();
+        Ok(recomputed
+            .relations
+            .iter()
+            .filter(|relation| selected(*relation))
+            .filter_map(|relation| {
+                let facts = current
+                    .get(relation.name.as_str())
+                    .map_or(&[][..], |r| r.facts.as_slice());
+                diverge(&relation.name, &relation.facts, facts)
+            })
+            .collect())
+    }
+
+    /// Run `self_check(tables)` every `interval`, invoking `cb` with the
+    /// outcome of every check that found divergences or failed.  Checking
+    /// stops when the returned handle or the program is dropped.
+    pub fn start_self_check(
+        this: &Arc<Self>,
+        tables: Vec<RelId>,
+        interval: Duration,
+        cb: SelfCheckCallback,
+    ) -> Result<SelfCheck, String> {
+        let hddlog: Weak<HDDlog> = Arc::downgrade(this);
+        let (stop, stopped) = crossbeam_channel::bounded::<()>(0);
+        let thread = thread::Builder::new()
+            .name("ddlog-self-check".to_string())
+            .spawn(move || loop {
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
+                let hddlog = match hddlog.upgrade() {
+                    Some(hddlog) => hddlog,
+                    None => return,
+                };
+                match hddlog.self_check(&tables) {
+                    Ok(divergences) if divergences.is_empty() => {}
+                    Ok(divergences) => cb(Ok(&divergences)),
+                    Err(e) => cb(Err(&e)),
+                }
+            })
+            .map_err(|e| format!("failed to start self-check: {}", e))?;
+
+        Ok(SelfCheck {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Facts of a relation and their total weights, by the textual form of the
+/// facts, as records are not ordered.
+type Facts = BTreeMap<String, (Record, isize)>;
+
+fn facts_by_text(facts: &[(Record, isize)]) -> Facts {
+    let mut map = Facts::new();
+    for (record, weight) in facts.iter() {
+        map.entry(record.to_string())
+            .or_insert_with(|| (record.clone(), 0))
+            .1 += *weight;
+    }
+    map
+}
+
+/// Facts of `a` that `b` does not contain with the same weight.
+fn difference(a: &Facts, b: &Facts) -> Vec<(Record, isize)> {
+    a.iter()
+        .filter(|(text, (_, weight))| b.get(*text).map(|(_, w)| w) != Some(weight))
+        .map(|(_, fact)| fact.clone())
+        .collect()
+}
+
+/// Compare the recomputed `expected` facts of `relation` with its `actual`
+/// facts.
+fn diverge(
+    relation: &str,
+    expected: &[(Record, isize)],
+    actual: &[(Record, isize)],
+) -> Option<Divergence> {
+    let (expected, actual) = (facts_by_text(expected), facts_by_text(actual));
+    let missing = difference(&expected, &actual);
+    let unexpected = difference(&actual, &expected);
+    if missing.is_empty() && unexpected.is_empty() {
+        None
+    } else {
+        Some(Divergence {
+            relation: relation.to_string(),
+            missing,
+            unexpected,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    fn fact(i: u64) -> Record {
+        Record::PosStruct(Cow::from("R"), vec![Record::Int(i.into())])
+    }
+
+    #[test]
+    fn no_divergence() {
+        // Weights of repeated facts add up.
+        assert_eq!(
+            diverge(
+                "R",
+                &[(fact(1), 2), (fact(2), 1)],
+                &[(fact(2), 1), (fact(1), 1), (fact(1), 1)]
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn missing_and_unexpected_facts() {
+        let divergence = diverge(
+            "R",
+            &[(fact(1), 1), (fact(2), 1), (fact(3), 2)],
+            &[(fact(1), 1), (fact(3), 1), (fact(4), 1)],
+        )
+        .unwrap();
+        assert_eq!(divergence.relation, "R");
+        assert_eq!(divergence.missing, vec![(fact(2), 1), (fact(3), 2)]);
+        assert_eq!(divergence.unexpected, vec![(fact(3), 1), (fact(4), 1)]);
+    }
+}
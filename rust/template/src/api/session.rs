@@ -0,0 +1,95 @@
+//! Read-your-writes sessions.
+//!
+//! Changelog batches delivered through a queue (see
+//! `HDDlog::subscribe_changelog_queued()`) may still be in flight when a
+//! commit returns, so a service that answers requests from the state
+//! maintained by its subscribers could miss the effect of the commit it just
+//! made.  A `Session` groups the queued subscriptions of a client;
+//! `HDDlog::transaction_commit_in_session()` commits and then waits until
+//! every batch produced by the commit has been delivered to those
+//! subscriptions.  `Session::token()` and `Session::wait_for()` split the
+//! wait from the commit, e.g., to let another thread wait for it.
+
+use super::*;
+
+use std::time::Instant;
+
+/// Queued changelog subscriptions of a client.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    queues: Vec<ChangelogQueueStats>,
+}
+
+/// Position of a session's subscriptions at some point in time: the number
+/// of batches queued for each of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionToken {
+    positions: Vec<u64>,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a subscription created by `HDDlog::subscribe_changelog_queued()`
+    /// to the session.
+    pub fn add(&mut self, queue: ChangelogQueueStats) {
+        self.queues.push(queue);
+    }
+
+    /// The current position of the session's subscriptions.  Batches
+    /// produced by a commit have been queued by the time the commit
+    /// returns, so a token taken after a commit covers its changes.
+    pub fn token(&self) -> SessionToken {
+        SessionToken {
+            positions: self.queues.iter().map(|queue| queue.queued()).collect(),
+        }
+    }
+
+    /// Wait until all batches queued before `token` was taken have been
+    /// delivered, giving up after `timeout`, if specified.
+    pub fn wait_for(&self, token: &SessionToken, timeout: Option<Duration>) -> Result<(), String> {
+        if token.positions.len() > self.queues.len() {
+            return Err("session token does not belong to this session".to_string());
+        }
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        for (queue, position) in self.queues.iter().zip(token.positions.iter()) {
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            queue
+                .wait_delivered(*position, remaining)
+                .map_err(|e| format!("session subscribers: {}", e))?;
+        }
+        Ok(())
+    }
+}
+
+impl HDDlog {
+    /// Subscribe to the changelog of `table` through a delivery queue (see
+    /// `subscribe_changelog_queued()`) as part of `session`.
+    pub fn subscribe_changelog_in_session(
+        &self,
+        session: &mut Session,
+        table: RelId,
+        cb: ChangelogCallback,
+        config: DeliveryConfig,
+    ) -> Result<ChangelogQueueStats, String> {
+        let queue = self.subscribe_changelog_queued(table, cb, config)?;
+        session.add(queue.clone());
+        Ok(queue)
+    }
+
+    /// Commit the current transaction and wait until its changes have been
+    /// delivered to the subscribers of `session`.  A timeout error means
+    /// that the transaction has been committed, but some of its changes are
+    /// still being delivered.
+    pub fn transaction_commit_in_session(
+        &self,
+        session: &Session,
+        timeout: Option<Duration>,
+    ) -> Result<(), String> {
+        self.transaction_commit()?;
+        session.wait_for(&session.token(), timeout)
+    }
+}
@@ -0,0 +1,388 @@
+//! Hot-reloadable settings.
+//!
+//! Programs that import the `settings` library get a `settings::Setting`
+//! input relation mapping setting names to values, which rules join with to
+//! read tunable constants such as thresholds (see `lib/settings.dl` for the
+//! recommended pattern).  The host loads settings from a TOML or JSON file
+//! with `load_settings()`, or watches the file with `watch_settings()` and
+//! reloads it whenever it changes.  Nested tables or objects are flattened
+//! into dotted names, e.g., `limits.max_latency`.
+//!
+//! Each reload replaces all settings in a single transaction, so rules never
+//! observe a mix of old and new values, and only updates the settings that
+//! changed.  Reloads go through the regular update API: they are checked
+//! against the access policy and recorded by the command recorder, and fail
+//! if the host has a transaction in progress, in which case the watcher
+//! retries at the next poll.
+
+use super::*;
+
+use crossbeam_channel::RecvTimeoutError;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::Weak;
+use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
+
+/// Name of the relation declared by the `settings` library.
+const SETTING_RELATION: &str = "settings::Setting";
+
+/// Value of a setting.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl SettingValue {
+    fn to_record(&self) -> Record {
+        let (constructor, field, value) = match self {
+            SettingValue::Bool(b) => ("settings::SettingBool", "b", Record::Bool(*b)),
+            SettingValue::Int(i) => ("settings::SettingInt", "i", Record::Int((*i).into())),
+            SettingValue::Float(f) => (
+                "settings::SettingFloat",
+                "f",
+                Record::Double(OrderedFloat(*f)),
+            ),
+            SettingValue::String(s) => ("settings::SettingString", "s", Record::String(s.clone())),
+        };
+        Record::NamedStruct(Cow::from(constructor), vec![(Cow::from(field), value)])
+    }
+}
+
+/// Settings by name.
+pub type Settings = BTreeMap<String, SettingValue>;
+
+/// Settings changed by a reload.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SettingsChange {
+    /// Names of settings that were added or whose value changed.
+    pub updated: Vec<String>,
+    /// Names of settings that were removed.
+    pub removed: Vec<String>,
+}
+
+impl SettingsChange {
+    pub fn is_empty(&self) -> bool {
+        self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Callback invoked by a settings watcher after every reload that changed
+/// settings or failed.
+pub type SettingsCallback = Arc<dyn Fn(Result<&SettingsChange, &str>) + Send + Sync>;
+
+/// Stops watching the settings file when dropped.
+#[derive(Debug)]
+pub struct SettingsWatcher {
+    stop: Option<crossbeam_channel::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for SettingsWatcher {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes up the watcher thread.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl HDDlog {
+    /// Replace all settings with `settings` in one transaction.
+    pub fn apply_settings(&self, settings: Settings) -> Result<SettingsChange, String> {
+        let rel = Relations::try_from(SETTING_RELATION)
+            .map_err(|()| "the program does not import the settings library".to_string())?;
+        let mut current = self.settings.lock().unwrap();
+
+        let mut change = SettingsChange::default();
+        let mut updates = Vec::new();
+        for (name, value) in settings.iter() {
+            if current.get(name) != Some(value) {
+                let record = Record::NamedStruct(
+                    Cow::from(SETTING_RELATION),
+                    vec![
+                        (Cow::from("name"), Record::String(name.clone())),
+                        (Cow::from("value"), value.to_record()),
+                    ],
+                );
+                updates.push(Update::InsertOrUpdate {
+                    relid: rel as RelId,
+                    v: relval_from_record(rel, &record)?,
+                });
+                change.updated.push(name.clone());
+            }
+        }
+        for name in current.keys().filter(|name| !settings.contains_key(*name)) {
+            updates.push(Update::DeleteKey {
+                relid: rel as RelId,
+                k: relkey_from_record(rel, &Record::String(name.clone()))?,
+            });
+            change.removed.push(name.clone());
+        }
+        if change.is_empty() {
+            return Ok(change);
+        }
+
+        self.transaction_start()?;
+        let res = self
+            .apply_updates(&mut updates.into_iter())
+            .and_then(|()| self.transaction_commit());
+        if let Err(e) = res {
+            let _ = self.transaction_rollback();
+            return Err(e);
+        }
+        *current = settings;
+        Ok(change)
+    }
+
+    /// Load settings from a TOML or JSON file, depending on its extension,
+    /// replacing all current settings.
+    pub fn load_settings<P: AsRef<Path>>(&self, path: P) -> Result<SettingsChange, String> {
+        self.apply_settings(parse_settings(path.as_ref())?)
+    }
+
+    /// Load settings from `path`, and reload them whenever the file's
+    /// modification time changes, checking every `poll_interval`.  `cb` is
+    /// invoked with the outcome of every reload that changed settings or
+    /// failed.  Watching stops when the returned watcher or the program is
+    /// dropped.
+    pub fn watch_settings<P: AsRef<Path>>(
+        this: &Arc<Self>,
+        path: P,
+        poll_interval: Duration,
+        cb: SettingsCallback,
+    ) -> Result<SettingsWatcher, String> {
+        let path = path.as_ref().to_path_buf();
+        let mut mtime = modification_time(&path)?;
+        this.load_settings(&path)?;
+
+        let hddlog = Arc::downgrade(this);
+        let (stop, stopped) = crossbeam_channel::bounded::<()>(0);
+        let thread = thread::Builder::new()
+            .name("ddlog-settings".to_string())
+            .spawn(move || loop {
+                match stopped.recv_timeout(poll_interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
+                if !reload_if_modified(&hddlog, &path, &mut mtime, &cb) {
+                    return;
+                }
+            })
+            .map_err(|e| format!("failed to start settings watcher: {}", e))?;
+
+        Ok(SettingsWatcher {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Reload settings if the file was modified since `mtime`.  Returns `false`
+/// if the program no longer exists.
+fn reload_if_modified(
+    hddlog: &Weak<HDDlog>,
+    path: &Path,
+    mtime: &mut SystemTime,
+    cb: &SettingsCallback,
+) -> bool {
+    let hddlog = match hddlog.upgrade() {
+        Some(hddlog) => hddlog,
+        None => return false,
+    };
+    let modified = match modification_time(path) {
+        Ok(modified) if modified == *mtime => return true,
+        Ok(modified) => modified,
+        Err(e) => {
+            cb(Err(&e));
+            return true;
+        }
+    };
+    // A file that fails to parse is not reloaded until it changes again,
+    // but an update that fails, e.g., because a transaction is in
+    // progress, is retried.
+    let settings = parse_settings(path);
+    if settings.is_err() {
+        *mtime = modified;
+    }
+    match settings.and_then(|settings| hddlog.apply_settings(settings)) {
+        Ok(change) => {
+            *mtime = modified;
+            if !change.is_empty() {
+                cb(Ok(&change));
+            }
+        }
+        Err(e) => cb(Err(&e)),
+    }
+    true
+}
+
+fn modification_time(path: &Path) -> Result<SystemTime, String> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(|e| format!("failed to stat settings file {}: {}", path.display(), e))
+}
+
+/// Parse a TOML or JSON settings file.
+pub fn parse_settings(path: &Path) -> Result<Settings, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read settings file {}: {}", path.display(), e))?;
+    let mut settings = Settings::new();
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => {
+            let value: toml::Value = toml::from_str(&text)
+                .map_err(|e| format!("invalid settings file {}: {}", path.display(), e))?;
+            flatten_toml("", &value, &mut settings)?;
+        }
+        Some("json") => {
+            let value: serde_json::Value = serde_json::from_str(&text)
+                .map_err(|e| format!("invalid settings file {}: {}", path.display(), e))?;
+            flatten_json("", &value, &mut settings)?;
+        }
+        _ => {
+            return Err(format!(
+                "settings file {} must have a .toml or .json extension",
+                path.display()
+            ))
+        }
+    }
+    Ok(settings)
+}
+
+fn setting_name(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+fn flatten_toml(prefix: &str, value: &toml::Value, settings: &mut Settings) -> Result<(), String> {
+    let value = match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter() {
+                flatten_toml(&setting_name(prefix, key), value, settings)?;
+            }
+            return Ok(());
+        }
+        toml::Value::Boolean(b) => SettingValue::Bool(*b),
+        toml::Value::Integer(i) => SettingValue::Int(*i),
+        toml::Value::Float(f) => SettingValue::Float(*f),
+        toml::Value::String(s) => SettingValue::String(s.clone()),
+        toml::Value::Datetime(d) => SettingValue::String(d.to_string()),
+        toml::Value::Array(_) => {
+            return Err(format!("setting {}: arrays are not supported", prefix))
+        }
+    };
+    settings.insert(prefix.to_string(), value);
+    Ok(())
+}
+
+fn flatten_json(
+    prefix: &str,
+    value: &serde_json::Value,
+    settings: &mut Settings,
+) -> Result<(), String> {
+    let value = match value {
+        serde_json::Value::Object(object) => {
+            for (key, value) in object.iter() {
+                flatten_json(&setting_name(prefix, key), value, settings)?;
+            }
+            return Ok(());
+        }
+        serde_json::Value::Bool(b) => SettingValue::Bool(*b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => SettingValue::Int(i),
+            None => SettingValue::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(s) => SettingValue::String(s.clone()),
+        serde_json::Value::Null | serde_json::Value::Array(_) => {
+            return Err(format!(
+                "setting {}: only Booleans, numbers, and strings are supported",
+                prefix
+            ))
+        }
+    };
+    settings.insert(prefix.to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flatten_nested_tables() {
+        let value: toml::Value = toml::from_str(
+            r#"
+            debug = true
+            [limits]
+            max_latency = 250
+            ratio = 0.5
+            [limits.names]
+            default = "x"
+            "#,
+        )
+        .unwrap();
+        let mut settings = Settings::new();
+        flatten_toml("", &value, &mut settings).unwrap();
+        assert_eq!(
+            settings.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("debug".to_string(), SettingValue::Bool(true)),
+                ("limits.max_latency".to_string(), SettingValue::Int(250)),
+                (
+                    "limits.names.default".to_string(),
+                    SettingValue::String("x".to_string())
+                ),
+                ("limits.ratio".to_string(), SettingValue::Float(0.5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn flatten_json_objects() {
+        let value: serde_json::Value =
+            serde_json::from_str(r#"{"a": {"b": 1, "c": 1.5}, "d": "s"}"#).unwrap();
+        let mut settings = Settings::new();
+        flatten_json("", &value, &mut settings).unwrap();
+        assert_eq!(settings["a.b"], SettingValue::Int(1));
+        assert_eq!(settings["a.c"], SettingValue::Float(1.5));
+        assert_eq!(settings["d"], SettingValue::String("s".to_string()));
+    }
+
+    #[test]
+    fn unsupported_values() {
+        let value: toml::Value = toml::from_str("a = [1, 2]").unwrap();
+        assert!(flatten_toml("", &value, &mut Settings::new())
+            .unwrap_err()
+            .contains("arrays are not supported"));
+        let value: serde_json::Value = serde_json::from_str(r#"{"a": null}"#).unwrap();
+        assert!(flatten_json("", &value, &mut Settings::new())
+            .unwrap_err()
+            .contains("setting a"));
+    }
+
+    #[test]
+    fn file_formats() {
+        let dir = std::env::temp_dir();
+        let toml_path = dir.join(format!("ddlog_settings_{}.toml", std::process::id()));
+        let yaml_path = dir.join(format!("ddlog_settings_{}.yaml", std::process::id()));
+        fs::write(&toml_path, "x = 1").unwrap();
+        fs::write(&yaml_path, "x: 1").unwrap();
+        let settings = parse_settings(&toml_path);
+        let err = parse_settings(&yaml_path).unwrap_err();
+        let _ = fs::remove_file(&toml_path);
+        let _ = fs::remove_file(&yaml_path);
+
+        assert_eq!(settings.unwrap()["x"], SettingValue::Int(1));
+        assert!(err.contains("must have a .toml or .json extension"));
+        assert!(parse_settings(&dir.join("no_such_settings_file.json"))
+            .unwrap_err()
+            .contains("failed to read settings file"));
+    }
+}
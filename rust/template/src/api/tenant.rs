@@ -0,0 +1,85 @@
+//! Serving multiple tenants with one program.
+//!
+//! When the program runs with `Config::multi_tenant` set, every fact
+//! belongs to a tenant and facts of different tenants never interact (see
+//! `differential_datalog::program::tenant`).  The methods below update and
+//! query the program on behalf of a tenant and deliver each tenant's output
+//! changes to its own callback.
+//!
+//! Tenant operations are checked against the access policy like other
+//! operations, but are not recorded by the command recorder.  Outputs
+//! stored by the program (`do_store`) and `transaction_commit_dump_changes()`
+//! contain tagged values of all tenants.
+
+use super::*;
+
+use differential_datalog::program::tenant::TenantId;
+
+impl HDDlog {
+    /// Apply updates to input relations on behalf of `tenant`.  Values and
+    /// keys in `upds` are not tagged; they are tagged with `tenant` before
+    /// they are applied.
+    pub fn apply_tenant_updates(
+        &self,
+        tenant: TenantId,
+        upds: &mut dyn Iterator<Item = Update<DDValue>>,
+    ) -> Result<(), String> {
+        self.prog
+            .lock()
+            .unwrap()
+            .apply_tenant_updates(tenant, upds, |update| self.inspect_update(update))
+    }
+
+    /// Delete all facts of `tenant` in input relation `table`.
+    pub fn clear_tenant_relation(&self, tenant: TenantId, table: RelId) -> Result<(), String> {
+        self.check_access(table, Operation::Clear)?;
+        self.prog
+            .lock()
+            .unwrap()
+            .clear_tenant_relation(tenant, table)
+    }
+
+    /// Returns the values of `tenant` in `index` that match `key`.
+    pub fn query_tenant_index(
+        &self,
+        tenant: TenantId,
+        index: IdxId,
+        key: DDValue,
+    ) -> Result<BTreeSet<DDValue>, String> {
+        let idx = Indexes::try_from(index).map_err(|()| format!("unknown index {}", index))?;
+        let arrid = indexes2arrid(idx);
+        self.check_access(arrid.0, Operation::Query)?;
+        self.prog
+            .lock()
+            .unwrap()
+            .query_tenant_arrangement(tenant, arrid, key)
+    }
+
+    /// Returns all values of `tenant` in `index`.
+    pub fn dump_tenant_index(
+        &self,
+        tenant: TenantId,
+        index: IdxId,
+    ) -> Result<BTreeSet<DDValue>, String> {
+        let idx = Indexes::try_from(index).map_err(|()| format!("unknown index {}", index))?;
+        let arrid = indexes2arrid(idx);
+        self.check_access(arrid.0, Operation::Query)?;
+        self.prog
+            .lock()
+            .unwrap()
+            .dump_tenant_arrangement(tenant, arrid)
+    }
+
+    /// Invoke `cb` for every change to an output relation of `tenant`,
+    /// replacing the callback previously subscribed to by the tenant.  Like
+    /// other update handlers, callbacks are invoked while transactions
+    /// commit.
+    pub fn subscribe_tenant(&self, tenant: TenantId, cb: TenantCallback) {
+        self.tenant_callbacks.write().unwrap().insert(tenant, cb);
+    }
+
+    /// Stop delivering changes to the callback of `tenant`.
+    pub fn unsubscribe_tenant(&self, tenant: TenantId) {
+        self.tenant_callbacks.write().unwrap().remove(&tenant);
+    }
+}
@@ -0,0 +1,234 @@
+//! Materialized views of output relations.
+//!
+//! A `MaterializedView` is an in-process map from keys, computed from the
+//! records of an output relation by a key function, to the records with
+//! that key.  The view subscribes to the commits of the relation (see
+//! `HDDlog::subscribe_commits()`) and applies the changes made by each
+//! commit before the commit returns, so it always reflects the last
+//! committed state of the relation.  This replaces the map that embedders
+//! otherwise maintain by hand in a changelog callback.
+//!
+//! Invalidation callbacks registered for a key, or for all keys, are
+//! invoked once per commit that changes the records with that key, with the
+//! new records (an empty slice if the key is no longer present).  Callbacks
+//! run on the update handler thread after the view has been updated; they
+//! may read the view, but must not call back into the program.
+//!
+//! When the program stores the contents of output relations (`do_store`),
+//! the view is initialized with the current contents of the relation and
+//! should be created while no transaction is in progress; otherwise it
+//! starts empty.
+
+use super::*;
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+/// Computes the key of a record of a materialized view.
+pub type ViewKeyFunc<K> = Arc<dyn Fn(&DDValue) -> K + Send + Sync>;
+
+/// Invoked with a key and its new records when a commit changes them.
+pub type InvalidationCallback<K> = Arc<dyn Fn(&K, &[DDValue]) + Send + Sync>;
+
+/// Identifies an invalidation callback (see
+/// `MaterializedView::on_invalidate()`).
+pub type InvalidationCallbackId = u64;
+
+static NEXT_INVALIDATION_CALLBACK: AtomicU64 = AtomicU64::new(1);
+
+struct ViewState<K> {
+    /// Records with positive weight, by key.
+    entries: HashMap<K, BTreeMap<DDValue, isize>>,
+    key_callbacks: HashMap<K, Vec<(InvalidationCallbackId, InvalidationCallback<K>)>>,
+    callbacks: Vec<(InvalidationCallbackId, InvalidationCallback<K>)>,
+}
+
+impl<K: Eq + Hash + Clone> ViewState<K> {
+    fn add(&mut self, key: K, value: &DDValue, weight: isize) {
+        let records = self.entries.entry(key.clone()).or_default();
+        let w = records.entry(value.clone()).or_insert(0);
+        *w += weight;
+        if *w <= 0 {
+            records.remove(value);
+        }
+        if records.is_empty() {
+            self.entries.remove(&key);
+        }
+    }
+
+    fn records(&self, key: &K) -> Vec<DDValue> {
+        self.entries
+            .get(key)
+            .map(|records| records.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /* Callbacks to invoke when `key` changes. */
+    fn callbacks_for(&self, key: &K) -> Vec<InvalidationCallback<K>> {
+        let specific = self.key_callbacks.get(key).into_iter().flatten();
+        self.callbacks
+            .iter()
+            .chain(specific)
+            .map(|(_, cb)| cb.clone())
+            .collect()
+    }
+}
+
+/// A map from keys to the records of an output relation, kept up to date by
+/// the program.  Dropping the view stops updating it.
+pub struct MaterializedView<'a, K> {
+    hddlog: &'a HDDlog,
+    subscription: CommitSubscriptionId,
+    state: Arc<RwLock<ViewState<K>>>,
+}
+
+impl HDDlog {
+    /// Create a materialized view of output relation `table`, with records
+    /// keyed by `key`.
+    pub fn materialize_view<K, F>(
+        &self,
+        table: RelId,
+        key: F,
+    ) -> Result<MaterializedView<'_, K>, String>
+    where
+        K: Eq + Hash + Clone + Send + Sync + 'static,
+        F: Fn(&DDValue) -> K + Send + Sync + 'static,
+    {
+        let key: ViewKeyFunc<K> = Arc::new(key);
+        let mut state = ViewState {
+            entries: HashMap::new(),
+            key_callbacks: HashMap::new(),
+            callbacks: Vec::new(),
+        };
+        if self.db.is_some() {
+            for (value, weight) in self
+                .dump_stored(&[table])?
+                .remove(&table)
+                .unwrap_or_default()
+            {
+                state.add(key(&value), &value, weight);
+            }
+        }
+
+        let state = Arc::new(RwLock::new(state));
+        let subscription = {
+            let state = state.clone();
+            self.subscribe_commits(
+                &[table],
+                Arc::new(move |batches: &[ChangelogBatch]| {
+                    // Keys changed by the commit, in the order they were first
+                    // changed.
+                    let mut changed = Vec::new();
+                    {
+                        let mut state = state.write().unwrap();
+                        let mut seen = HashSet::new();
+                        for (value, weight) in batches.iter().flat_map(|b| b.changes.iter()) {
+                            let k = key(value);
+                            state.add(k.clone(), value, *weight);
+                            if seen.insert(k.clone()) {
+                                changed.push(k);
+                            }
+                        }
+                    }
+                    for k in changed {
+                        let (callbacks, records) = {
+                            let state = state.read().unwrap();
+                            (state.callbacks_for(&k), state.records(&k))
+                        };
+                        for cb in callbacks {
+                            cb(&k, &records);
+                        }
+                    }
+                }),
+            )?
+        };
+        Ok(MaterializedView {
+            hddlog: self,
+            subscription,
+            state,
+        })
+    }
+}
+
+impl<K: Eq + Hash + Clone> MaterializedView<'_, K> {
+    /// The records with key `key`, in the order of their type.
+    pub fn get(&self, key: &K) -> Vec<DDValue> {
+        self.state.read().unwrap().records(key)
+    }
+
+    /// The first record with key `key`, for views where keys are unique.
+    pub fn get_one(&self, key: &K) -> Option<DDValue> {
+        let state = self.state.read().unwrap();
+        state
+            .entries
+            .get(key)
+            .and_then(|records| records.keys().next().cloned())
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.state.read().unwrap().entries.contains_key(key)
+    }
+
+    /// Number of keys in the view.
+    pub fn len(&self) -> usize {
+        self.state.read().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn keys(&self) -> Vec<K> {
+        self.state.read().unwrap().entries.keys().cloned().collect()
+    }
+
+    /// A copy of the entire view.
+    pub fn to_map(&self) -> HashMap<K, Vec<DDValue>> {
+        let state = self.state.read().unwrap();
+        state
+            .entries
+            .iter()
+            .map(|(k, records)| (k.clone(), records.keys().cloned().collect()))
+            .collect()
+    }
+
+    /// Invoke `cb` whenever a commit changes the records with key `key`.
+    pub fn on_invalidate(&self, key: K, cb: InvalidationCallback<K>) -> InvalidationCallbackId {
+        let id = NEXT_INVALIDATION_CALLBACK.fetch_add(1, Ordering::Relaxed);
+        self.state
+            .write()
+            .unwrap()
+            .key_callbacks
+            .entry(key)
+            .or_default()
+            .push((id, cb));
+        id
+    }
+
+    /// Invoke `cb` for every key changed by a commit.
+    pub fn on_any_invalidate(&self, cb: InvalidationCallback<K>) -> InvalidationCallbackId {
+        let id = NEXT_INVALIDATION_CALLBACK.fetch_add(1, Ordering::Relaxed);
+        self.state.write().unwrap().callbacks.push((id, cb));
+        id
+    }
+
+    /// Remove the invalidation callback `id`.
+    pub fn remove_callback(&self, id: InvalidationCallbackId) {
+        let mut state = self.state.write().unwrap();
+        state.callbacks.retain(|(cb_id, _)| *cb_id != id);
+        for callbacks in state.key_callbacks.values_mut() {
+            callbacks.retain(|(cb_id, _)| *cb_id != id);
+        }
+        state
+            .key_callbacks
+            .retain(|_, callbacks| !callbacks.is_empty());
+    }
+}
+
+impl<K> Drop for MaterializedView<'_, K> {
+    fn drop(&mut self) {
+        self.hddlog.unsubscribe_commits(self.subscription);
+    }
+}
@@ -0,0 +1,1308 @@
+//! BGP feed adapter.
+//!
+//! Feeds BGP routing information into the `net::bgp::Peer` and
+//! `net::bgp::Route` input relations of a program that imports the
+//! `net::bgp` library (see `lib/net/bgp.dl`) from one of two sources:
+//!
+//! * `BmpCollector` accepts BMP sessions (RFC 7854) from routers and applies
+//!   peer up, peer down, and route monitoring messages as they arrive.  When
+//!   a router disconnects, the peers it reported go down.
+//! * `MrtLoader` applies MRT files (RFC 6396): `TABLE_DUMP_V2` RIB dumps and
+//!   `BGP4MP` update and state-change files, e.g., those published by route
+//!   collectors.  Load a RIB dump first, then the update files in order.
+//!   Compressed files must be decompressed by the caller.
+//!
+//! Only unicast IPv4 and IPv6 routes are decoded.  Routes received over
+//! ADD-PATH sessions replace each other, and 2-byte AS paths are used as
+//! received, without merging `AS4_PATH` attributes.  Requires the `bgp`
+//! feature.
+
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::io::{ErrorKind, Read};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use differential_datalog::record::{CollectionKind, Record, RelIdentifier, UpdCmd};
+use differential_datalog::scheduler::ClientId;
+
+use crate::api::HDDlog;
+use crate::Relations;
+
+/// Names of the relations declared by the `net::bgp` library.
+const PEER_RELATION: &str = "net::bgp::Peer";
+const ROUTE_RELATION: &str = "net::bgp::Route";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Prefix {
+    pub addr: IpAddr,
+    pub len: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsPathSegment {
+    Sequence(Vec<u32>),
+    Set(Vec<u32>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    Igp,
+    Egp,
+    Incomplete,
+}
+
+/// Path attributes of a BGP UPDATE message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attributes {
+    pub next_hop: Option<IpAddr>,
+    pub as_path: Vec<AsPathSegment>,
+    pub origin: Origin,
+    pub local_pref: Option<u32>,
+    pub med: Option<u32>,
+    pub communities: Vec<(u16, u16)>,
+    pub large_communities: Vec<(u32, u32, u32)>,
+}
+
+impl Default for Attributes {
+    fn default() -> Self {
+        Self {
+            next_hop: None,
+            as_path: Vec::new(),
+            origin: Origin::Incomplete,
+            local_pref: None,
+            med: None,
+            communities: Vec::new(),
+            large_communities: Vec::new(),
+        }
+    }
+}
+
+/// A decoded BGP UPDATE message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Update {
+    pub withdrawn: Vec<Prefix>,
+    pub announced: Vec<Prefix>,
+    /// Next hop of the announced IPv6 routes, from `MP_REACH_NLRI`.
+    pub mp_next_hop: Option<IpAddr>,
+    pub attributes: Attributes,
+}
+
+/// A change to the routing information of a BGP speaker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RibUpdate {
+    PeerUp { peer: IpAddr, asn: u32, bgp_id: u32 },
+    PeerDown { peer: IpAddr },
+    Update { peer: IpAddr, update: Update },
+}
+
+/* Big-endian reader over a message. */
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < n {
+            return Err("truncated message".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn asn(&mut self, as4: bool) -> Result<u32, String> {
+        if as4 {
+            self.u32()
+        } else {
+            self.u16().map(u32::from)
+        }
+    }
+
+    fn ipv4(&mut self) -> Result<IpAddr, String> {
+        Ok(IpAddr::from(<[u8; 4]>::try_from(self.bytes(4)?).unwrap()))
+    }
+
+    fn ipv6(&mut self) -> Result<IpAddr, String> {
+        Ok(IpAddr::from(<[u8; 16]>::try_from(self.bytes(16)?).unwrap()))
+    }
+
+    /* An IPv4 address in the last 4 bytes of a 16-byte field, as in BMP and
+     * MRT headers, or an IPv6 address. */
+    fn padded_ip(&mut self, ipv6: bool) -> Result<IpAddr, String> {
+        if ipv6 {
+            self.ipv6()
+        } else {
+            self.bytes(12)?;
+            self.ipv4()
+        }
+    }
+
+    fn sub(&mut self, n: usize) -> Result<Reader<'a>, String> {
+        self.bytes(n).map(Reader::new)
+    }
+
+    /* A prefix in NLRI encoding: length in bits, then the significant bytes
+     * of the address. */
+    fn prefix(&mut self, afi: u16) -> Result<Prefix, String> {
+        let len = self.u8()?;
+        let max_len = if afi == AFI_IPV6 { 128 } else { 32 };
+        if len > max_len {
+            return Err(format!("invalid prefix length {}", len));
+        }
+        let bytes = self.bytes((len as usize + 7) / 8)?;
+        let addr = if afi == AFI_IPV6 {
+            let mut octets = [0u8; 16];
+            octets[..bytes.len()].copy_from_slice(bytes);
+            IpAddr::from(octets)
+        } else {
+            let mut octets = [0u8; 4];
+            octets[..bytes.len()].copy_from_slice(bytes);
+            IpAddr::from(octets)
+        };
+        Ok(Prefix { addr, len })
+    }
+
+    fn prefixes(&mut self, afi: u16) -> Result<Vec<Prefix>, String> {
+        let mut prefixes = Vec::new();
+        while !self.is_empty() {
+            prefixes.push(self.prefix(afi)?);
+        }
+        Ok(prefixes)
+    }
+}
+
+const AFI_IPV4: u16 = 1;
+const AFI_IPV6: u16 = 2;
+const SAFI_UNICAST: u8 = 1;
+
+/* BGP message types. */
+const BGP_UPDATE: u8 = 2;
+
+/// Maximal length of a BGP message, with the extended messages of RFC 8654.
+pub const MAX_BGP_MESSAGE_LEN: usize = 65535;
+
+/// Decode a BGP message, including its 19-byte header.  Returns `None` for
+/// messages other than UPDATE.  `as4` tells whether AS numbers in the AS path
+/// take 4 bytes, i.e., whether both speakers support 4-byte AS numbers.
+pub fn parse_bgp_message(data: &[u8], as4: bool) -> Result<Option<Update>, String> {
+    let mut r = Reader::new(data);
+    r.bytes(16)?; // marker
+    let length = r.u16()? as usize;
+    if length < 19 {
+        return Err(format!("invalid BGP message length {}", length));
+    }
+    if r.u8()? != BGP_UPDATE {
+        return Ok(None);
+    }
+    let mut body = r.sub(length - 19)?;
+    parse_update(&mut body, as4, false).map(Some)
+}
+
+/* The body of an UPDATE message.  `abbreviated_mp_reach` is set for MRT RIB
+ * entries, whose `MP_REACH_NLRI` attribute only holds the next hop. */
+fn parse_update(r: &mut Reader, as4: bool, abbreviated_mp_reach: bool) -> Result<Update, String> {
+    let mut update = Update::default();
+    let withdrawn_len = r.u16()? as usize;
+    update.withdrawn = r.sub(withdrawn_len)?.prefixes(AFI_IPV4)?;
+    let attributes_len = r.u16()? as usize;
+    let mut attributes = r.sub(attributes_len)?;
+    while !attributes.is_empty() {
+        let flags = attributes.u8()?;
+        let type_code = attributes.u8()?;
+        let len = if flags & 0x10 != 0 {
+            attributes.u16()? as usize
+        } else {
+            attributes.u8()? as usize
+        };
+        let mut value = attributes.sub(len)?;
+        parse_attribute(
+            &mut update,
+            type_code,
+            &mut value,
+            as4,
+            abbreviated_mp_reach,
+        )?;
+    }
+    update.announced.extend(r.prefixes(AFI_IPV4)?);
+    Ok(update)
+}
+
+fn parse_attribute(
+    update: &mut Update,
+    type_code: u8,
+    r: &mut Reader,
+    as4: bool,
+    abbreviated_mp_reach: bool,
+) -> Result<(), String> {
+    let attrs = &mut update.attributes;
+    match type_code {
+        1 => {
+            attrs.origin = match r.u8()? {
+                0 => Origin::Igp,
+                1 => Origin::Egp,
+                _ => Origin::Incomplete,
+            }
+        }
+        2 => {
+            while !r.is_empty() {
+                let segment_type = r.u8()?;
+                let count = r.u8()?;
+                let mut asns = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    asns.push(r.asn(as4)?);
+                }
+                match segment_type {
+                    1 => attrs.as_path.push(AsPathSegment::Set(asns)),
+                    2 => attrs.as_path.push(AsPathSegment::Sequence(asns)),
+                    // Confederation segments do not count towards the path.
+                    _ => (),
+                }
+            }
+        }
+        3 => attrs.next_hop = Some(r.ipv4()?),
+        4 => attrs.med = Some(r.u32()?),
+        5 => attrs.local_pref = Some(r.u32()?),
+        8 => {
+            while !r.is_empty() {
+                attrs.communities.push((r.u16()?, r.u16()?));
+            }
+        }
+        14 if abbreviated_mp_reach => {
+            let len = r.u8()? as usize;
+            update.mp_next_hop = mp_next_hop(&mut r.sub(len)?, len)?;
+        }
+        14 => {
+            let afi = r.u16()?;
+            let safi = r.u8()?;
+            let len = r.u8()? as usize;
+            let next_hop = mp_next_hop(&mut r.sub(len)?, len)?;
+            r.u8()?; // reserved
+            if safi == SAFI_UNICAST && (afi == AFI_IPV4 || afi == AFI_IPV6) {
+                update.mp_next_hop = next_hop;
+                update.announced.extend(r.prefixes(afi)?);
+            }
+        }
+        15 => {
+            let afi = r.u16()?;
+            let safi = r.u8()?;
+            if safi == SAFI_UNICAST && (afi == AFI_IPV4 || afi == AFI_IPV6) {
+                update.withdrawn.extend(r.prefixes(afi)?);
+            }
+        }
+        32 => {
+            while !r.is_empty() {
+                attrs.large_communities.push((r.u32()?, r.u32()?, r.u32()?));
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/* The global next hop of `MP_REACH_NLRI`; an IPv6 link-local next hop may
+ * follow it. */
+fn mp_next_hop(r: &mut Reader, len: usize) -> Result<Option<IpAddr>, String> {
+    Ok(match len {
+        4 => Some(r.ipv4()?),
+        16 | 32 => Some(r.ipv6()?),
+        _ => None,
+    })
+}
+
+/* BMP message types. */
+const BMP_ROUTE_MONITORING: u8 = 0;
+const BMP_PEER_DOWN: u8 = 2;
+const BMP_PEER_UP: u8 = 3;
+
+/// Maximal length of a BMP message: a route monitoring message carries one
+/// BGP message after 48 bytes of headers.  Peer up messages carry two OPEN
+/// messages, which may not exceed 4096 bytes.
+pub const MAX_BMP_MESSAGE_LEN: usize = 48 + MAX_BGP_MESSAGE_LEN;
+
+/// Decode a BMP message, including its 6-byte common header.  Statistics,
+/// initiation, and termination messages produce no updates.
+pub fn parse_bmp_message(data: &[u8]) -> Result<Option<RibUpdate>, String> {
+    let mut r = Reader::new(data);
+    let version = r.u8()?;
+    if version != 3 {
+        return Err(format!("unsupported BMP version {}", version));
+    }
+    r.u32()?; // length
+    let message_type = r.u8()?;
+    if message_type != BMP_ROUTE_MONITORING
+        && message_type != BMP_PEER_DOWN
+        && message_type != BMP_PEER_UP
+    {
+        return Ok(None);
+    }
+
+    // Per-peer header.
+    r.u8()?; // peer type
+    let flags = r.u8()?;
+    r.bytes(8)?; // peer distinguisher
+    let peer = r.padded_ip(flags & 0x80 != 0)?;
+    let asn = r.u32()?;
+    let bgp_id = r.u32()?;
+    r.bytes(8)?; // timestamp
+
+    Ok(match message_type {
+        BMP_ROUTE_MONITORING => {
+            // The A flag marks sessions that use 2-byte AS paths.
+            parse_bgp_message(r.data, flags & 0x20 == 0)?
+                .map(|update| RibUpdate::Update { peer, update })
+        }
+        BMP_PEER_DOWN => Some(RibUpdate::PeerDown { peer }),
+        _ => Some(RibUpdate::PeerUp { peer, asn, bgp_id }),
+    })
+}
+
+/// Read one BMP message from a stream.  Returns `None` at the end of the
+/// stream.  Messages longer than `MAX_BMP_MESSAGE_LEN` are rejected before
+/// they are read.
+pub fn read_bmp_message<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>, String> {
+    let mut header = [0u8; 6];
+    if !read_header(reader, &mut header)? {
+        return Ok(None);
+    }
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if length < header.len() || length > MAX_BMP_MESSAGE_LEN {
+        return Err(format!("invalid BMP message length {}", length));
+    }
+    let mut message = header.to_vec();
+    message.resize(length, 0);
+    read_body(reader, &mut message[header.len()..])?;
+    Ok(Some(message))
+}
+
+/* Fill `buf` with the header of the next message.  Returns false at the end
+ * of the stream, but fails if the stream ends within the header. */
+fn read_header<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool, String> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(false),
+            Ok(0) => return Err("truncated message".to_string()),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(true)
+}
+
+fn read_body<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<(), String> {
+    reader.read_exact(buf).map_err(|e| {
+        if e.kind() == ErrorKind::UnexpectedEof {
+            "truncated message".to_string()
+        } else {
+            e.to_string()
+        }
+    })
+}
+
+fn ip_record(addr: &IpAddr) -> Record {
+    match addr {
+        IpAddr::V4(addr) => Record::NamedStruct(
+            Cow::from("net::ipaddr::IpAddrV4"),
+            vec![(Cow::from("addr4"), Record::Int(u32::from(*addr).into()))],
+        ),
+        IpAddr::V6(addr) => Record::NamedStruct(
+            Cow::from("net::ipaddr::IpAddrV6"),
+            vec![(Cow::from("addr6"), Record::Int(u128::from(*addr).into()))],
+        ),
+    }
+}
+
+fn prefix_record(prefix: &Prefix) -> Record {
+    Record::NamedStruct(
+        Cow::from("net::bgp::Prefix"),
+        vec![
+            (Cow::from("addr"), ip_record(&prefix.addr)),
+            (Cow::from("len"), Record::Int(prefix.len.into())),
+        ],
+    )
+}
+
+fn option_record(x: Option<u32>) -> Record {
+    match x {
+        Some(x) => Record::NamedStruct(
+            Cow::from("ddlog_std::Some"),
+            vec![(Cow::from("x"), Record::Int(x.into()))],
+        ),
+        None => Record::NamedStruct(Cow::from("ddlog_std::None"), Vec::new()),
+    }
+}
+
+fn vec_record(records: Vec<Record>) -> Record {
+    Record::Array(CollectionKind::Vector, records)
+}
+
+fn route_record(peer: &IpAddr, prefix: &Prefix, next_hop: &IpAddr, attrs: &Attributes) -> Record {
+    let int = |i: u32| Record::Int(i.into());
+    let as_path = attrs
+        .as_path
+        .iter()
+        .map(|segment| {
+            let (constructor, asns) = match segment {
+                AsPathSegment::Sequence(asns) => ("net::bgp::AsSequence", asns),
+                AsPathSegment::Set(asns) => ("net::bgp::AsSet", asns),
+            };
+            Record::NamedStruct(
+                Cow::from(constructor),
+                vec![(
+                    Cow::from("asns"),
+                    vec_record(asns.iter().map(|asn| int(*asn)).collect()),
+                )],
+            )
+        })
+        .collect();
+    let origin = match attrs.origin {
+        Origin::Igp => "net::bgp::OriginIgp",
+        Origin::Egp => "net::bgp::OriginEgp",
+        Origin::Incomplete => "net::bgp::OriginIncomplete",
+    };
+    let communities = attrs
+        .communities
+        .iter()
+        .map(|(asn, value)| {
+            Record::NamedStruct(
+                Cow::from("net::bgp::Community"),
+                vec![
+                    (Cow::from("asn"), int((*asn).into())),
+                    (Cow::from("value"), int((*value).into())),
+                ],
+            )
+        })
+        .collect();
+    let large_communities = attrs
+        .large_communities
+        .iter()
+        .map(|(global_admin, local_data1, local_data2)| {
+            Record::NamedStruct(
+                Cow::from("net::bgp::LargeCommunity"),
+                vec![
+                    (Cow::from("global_admin"), int(*global_admin)),
+                    (Cow::from("local_data1"), int(*local_data1)),
+                    (Cow::from("local_data2"), int(*local_data2)),
+                ],
+            )
+        })
+        .collect();
+    Record::NamedStruct(
+        Cow::from(ROUTE_RELATION),
+        vec![
+            (Cow::from("peer"), ip_record(peer)),
+            (Cow::from("prefix"), prefix_record(prefix)),
+            (Cow::from("next_hop"), ip_record(next_hop)),
+            (Cow::from("as_path"), vec_record(as_path)),
+            (
+                Cow::from("origin"),
+                Record::NamedStruct(Cow::from(origin), Vec::new()),
+            ),
+            (Cow::from("local_pref"), option_record(attrs.local_pref)),
+            (Cow::from("med"), option_record(attrs.med)),
+            (Cow::from("communities"), vec_record(communities)),
+            (
+                Cow::from("large_communities"),
+                vec_record(large_communities),
+            ),
+        ],
+    )
+}
+
+/// The routes fed into the program, by peer.  Converts `RibUpdate`s into
+/// relation updates, deleting the routes of peers that go down and ignoring
+/// withdrawals of unknown routes.
+#[derive(Debug, Default)]
+pub struct Rib {
+    routes: HashMap<IpAddr, BTreeSet<Prefix>>,
+}
+
+impl Rib {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of routes.
+    pub fn len(&self) -> usize {
+        self.routes.values().map(BTreeSet::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append the relation updates for `update` to `commands`.
+    pub fn apply(&mut self, update: RibUpdate, commands: &mut Vec<UpdCmd>) {
+        let peer_rel = || RelIdentifier::RelName(Cow::from(PEER_RELATION));
+        let route_rel = || RelIdentifier::RelName(Cow::from(ROUTE_RELATION));
+        match update {
+            RibUpdate::PeerUp { peer, asn, bgp_id } => {
+                self.routes.entry(peer).or_default();
+                commands.push(UpdCmd::InsertOrUpdate(
+                    peer_rel(),
+                    Record::NamedStruct(
+                        Cow::from(PEER_RELATION),
+                        vec![
+                            (Cow::from("addr"), ip_record(&peer)),
+                            (Cow::from("asn"), Record::Int(asn.into())),
+                            (Cow::from("bgp_id"), Record::Int(bgp_id.into())),
+                        ],
+                    ),
+                ));
+            }
+            RibUpdate::PeerDown { peer } => {
+                if let Some(prefixes) = self.routes.remove(&peer) {
+                    for prefix in prefixes {
+                        commands.push(UpdCmd::DeleteKey(
+                            route_rel(),
+                            Record::Tuple(vec![ip_record(&peer), prefix_record(&prefix)]),
+                        ));
+                    }
+                    commands.push(UpdCmd::DeleteKey(peer_rel(), ip_record(&peer)));
+                }
+            }
+            RibUpdate::Update { peer, update } => {
+                let routes = self.routes.entry(peer).or_default();
+                for prefix in update.withdrawn.iter() {
+                    if routes.remove(prefix) {
+                        commands.push(UpdCmd::DeleteKey(
+                            route_rel(),
+                            Record::Tuple(vec![ip_record(&peer), prefix_record(prefix)]),
+                        ));
+                    }
+                }
+                for prefix in update.announced.iter() {
+                    let next_hop = match prefix.addr {
+                        IpAddr::V4(_) => update.attributes.next_hop.or(update.mp_next_hop),
+                        IpAddr::V6(_) => update.mp_next_hop,
+                    };
+                    let next_hop = next_hop.unwrap_or_else(|| IpAddr::from([0, 0, 0, 0]));
+                    routes.insert(*prefix);
+                    commands.push(UpdCmd::InsertOrUpdate(
+                        route_rel(),
+                        route_record(&peer, prefix, &next_hop, &update.attributes),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+fn check_relations() -> Result<(), String> {
+    if Relations::try_from(PEER_RELATION).is_err() || Relations::try_from(ROUTE_RELATION).is_err() {
+        return Err("the program does not import the net::bgp library".to_string());
+    }
+    Ok(())
+}
+
+fn commit(hddlog: &HDDlog, client: ClientId, commands: &mut Vec<UpdCmd>) -> Result<(), String> {
+    if commands.is_empty() {
+        return Ok(());
+    }
+    let txn = hddlog.try_start_transaction(client, None)?;
+    txn.apply_updates_dynamic(&mut commands.drain(..))?;
+    txn.commit()
+}
+
+/* MRT types and subtypes. */
+const MRT_TABLE_DUMP_V2: u16 = 13;
+const MRT_BGP4MP: u16 = 16;
+const MRT_BGP4MP_ET: u16 = 17;
+const PEER_INDEX_TABLE: u16 = 1;
+const RIB_IPV4_UNICAST: u16 = 2;
+const RIB_IPV6_UNICAST: u16 = 4;
+const BGP4MP_STATE_CHANGE: u16 = 0;
+const BGP4MP_MESSAGE: u16 = 1;
+const BGP4MP_MESSAGE_AS4: u16 = 4;
+const BGP4MP_STATE_CHANGE_AS4: u16 = 5;
+const BGP_ESTABLISHED: u16 = 6;
+
+/// Default maximal length of an MRT record.  BGP4MP records hold one BGP
+/// message, but a `TABLE_DUMP_V2` RIB record holds the routes of all peers
+/// to a prefix, and route collectors have hundreds of peers.
+pub const DEFAULT_MAX_MRT_RECORD_LEN: usize = 16 << 20;
+
+/// Applies MRT files to a program.  The loader remembers the routes of
+/// previously loaded files, so that update files can withdraw routes loaded
+/// from a RIB dump.
+pub struct MrtLoader {
+    client: ClientId,
+    rib: Rib,
+    /// Peers of the last `PEER_INDEX_TABLE` record.
+    peer_index: Vec<IpAddr>,
+    max_record_len: usize,
+}
+
+impl MrtLoader {
+    pub fn new(client: ClientId) -> Result<Self, String> {
+        check_relations()?;
+        Ok(Self {
+            client,
+            rib: Rib::new(),
+            peer_index: Vec::new(),
+            max_record_len: DEFAULT_MAX_MRT_RECORD_LEN,
+        })
+    }
+
+    pub fn rib(&self) -> &Rib {
+        &self.rib
+    }
+
+    /// Reject records longer than `len` bytes, instead of
+    /// `DEFAULT_MAX_MRT_RECORD_LEN`.
+    pub fn set_max_record_len(&mut self, len: usize) {
+        self.max_record_len = len;
+    }
+
+    /// Apply an MRT file in one transaction.  Returns the number of MRT
+    /// records read; records of other types are skipped.  Nothing is applied
+    /// if the file is invalid, truncated, or holds a record longer than the
+    /// maximum set with `set_max_record_len()`.
+    pub fn load<R: Read>(&mut self, hddlog: &HDDlog, mut reader: R) -> Result<usize, String> {
+        let mut commands = Vec::new();
+        let mut records = 0;
+        loop {
+            let mut header = [0u8; 12];
+            let invalid = |e: String| format!("invalid MRT record {}: {}", records + 1, e);
+            if !read_header(&mut reader, &mut header).map_err(invalid)? {
+                break;
+            }
+            let mut r = Reader::new(&header[4..]);
+            let record_type = r.u16()?;
+            let subtype = r.u16()?;
+            let length = r.u32()? as usize;
+            if length > self.max_record_len {
+                return Err(invalid(format!(
+                    "length {} exceeds the maximum of {}",
+                    length, self.max_record_len
+                )));
+            }
+            let mut body = vec![0u8; length];
+            read_body(&mut reader, &mut body).map_err(invalid)?;
+            records += 1;
+            let mut body = Reader::new(&body);
+            if record_type == MRT_BGP4MP_ET {
+                body.u32()?; // microseconds
+            }
+            let updates = match record_type {
+                MRT_TABLE_DUMP_V2 => self.table_dump(subtype, &mut body),
+                MRT_BGP4MP | MRT_BGP4MP_ET => bgp4mp(subtype, &mut body),
+                _ => Ok(Vec::new()),
+            }
+            .map_err(|e| format!("invalid MRT record {}: {}", records, e))?;
+            for update in updates {
+                self.rib.apply(update, &mut commands);
+            }
+        }
+        commit(hddlog, self.client, &mut commands)?;
+        Ok(records)
+    }
+
+    fn table_dump(&mut self, subtype: u16, r: &mut Reader) -> Result<Vec<RibUpdate>, String> {
+        let afi = match subtype {
+            PEER_INDEX_TABLE => {
+                r.u32()?; // collector BGP id
+                let view_len = r.u16()? as usize;
+                r.bytes(view_len)?;
+                let count = r.u16()?;
+                let mut updates = Vec::with_capacity(count as usize);
+                self.peer_index.clear();
+                for _ in 0..count {
+                    let peer_type = r.u8()?;
+                    let bgp_id = r.u32()?;
+                    let peer = if peer_type & 1 != 0 {
+                        r.ipv6()?
+                    } else {
+                        r.ipv4()?
+                    };
+                    let asn = r.asn(peer_type & 2 != 0)?;
+                    self.peer_index.push(peer);
+                    updates.push(RibUpdate::PeerUp { peer, asn, bgp_id });
+                }
+                return Ok(updates);
+            }
+            RIB_IPV4_UNICAST => AFI_IPV4,
+            RIB_IPV6_UNICAST => AFI_IPV6,
+            _ => return Ok(Vec::new()),
+        };
+        r.u32()?; // sequence number
+        let prefix = r.prefix(afi)?;
+        let count = r.u16()?;
+        let mut updates = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let index = r.u16()? as usize;
+            r.u32()?; // originated time
+            let attributes_len = r.u16()?;
+            let peer = *self
+                .peer_index
+                .get(index)
+                .ok_or_else(|| format!("unknown peer index {}", index))?;
+            // RIB entries hold path attributes only, with 4-byte AS paths.
+            let mut entry = Vec::with_capacity(attributes_len as usize + 4);
+            entry.extend_from_slice(&[0, 0]);
+            entry.extend_from_slice(&attributes_len.to_be_bytes());
+            entry.extend_from_slice(r.bytes(attributes_len as usize)?);
+            let mut update = parse_update(&mut Reader::new(&entry), true, true)?;
+            update.announced.push(prefix);
+            updates.push(RibUpdate::Update { peer, update });
+        }
+        Ok(updates)
+    }
+}
+
+fn bgp4mp(subtype: u16, r: &mut Reader) -> Result<Vec<RibUpdate>, String> {
+    let as4 = match subtype {
+        BGP4MP_STATE_CHANGE | BGP4MP_MESSAGE => false,
+        BGP4MP_STATE_CHANGE_AS4 | BGP4MP_MESSAGE_AS4 => true,
+        _ => return Ok(Vec::new()),
+    };
+    let asn = r.asn(as4)?;
+    r.asn(as4)?; // local AS
+    r.u16()?; // interface index
+    let afi = r.u16()?;
+    let (peer, _local) = if afi == AFI_IPV6 {
+        (r.ipv6()?, r.ipv6()?)
+    } else {
+        (r.ipv4()?, r.ipv4()?)
+    };
+    Ok(match subtype {
+        BGP4MP_STATE_CHANGE | BGP4MP_STATE_CHANGE_AS4 => {
+            let old_state = r.u16()?;
+            let new_state = r.u16()?;
+            if new_state == BGP_ESTABLISHED {
+                // The BGP identifier is only known from the OPEN message.
+                vec![RibUpdate::PeerUp {
+                    peer,
+                    asn,
+                    bgp_id: 0,
+                }]
+            } else if old_state == BGP_ESTABLISHED {
+                vec![RibUpdate::PeerDown { peer }]
+            } else {
+                Vec::new()
+            }
+        }
+        _ => parse_bgp_message(r.data, as4)?
+            .map(|update| RibUpdate::Update { peer, update })
+            .into_iter()
+            .collect(),
+    })
+}
+
+/// Configuration of a `BmpCollector`.
+#[derive(Debug, Clone)]
+pub struct BmpConfig {
+    /// Maximal time updates are buffered before they are applied.
+    pub flush_interval: Duration,
+    /// Client id used to schedule the collector's transactions.
+    pub client: ClientId,
+}
+
+impl Default for BmpConfig {
+    fn default() -> Self {
+        Self {
+            flush_interval: Duration::from_millis(100),
+            client: ClientId::max_value() - 3,
+        }
+    }
+}
+
+/// A BMP collector.  Routers connect to it and stream their routes.
+/// Dropping the collector closes all sessions.
+pub struct BmpCollector {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    sessions: Arc<Mutex<Vec<TcpStream>>>,
+    threads: Vec<JoinHandle<()>>,
+    rib_len: Arc<Mutex<usize>>,
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+/* Reads the messages of one router, forwarding their updates.  When the
+ * router disconnects, the peers it reported go down. */
+fn bmp_session(
+    mut stream: TcpStream,
+    updates: mpsc::Sender<RibUpdate>,
+    last_error: Arc<Mutex<Option<String>>>,
+) {
+    let router = stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+    let mut peers = BTreeSet::new();
+    loop {
+        let message = match read_bmp_message(&mut stream) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                *last_error.lock().unwrap() = Some(format!("BMP session with {}: {}", router, e));
+                break;
+            }
+        };
+        match parse_bmp_message(&message) {
+            Ok(Some(update)) => {
+                match &update {
+                    RibUpdate::PeerUp { peer, .. } | RibUpdate::Update { peer, .. } => {
+                        peers.insert(*peer);
+                    }
+                    RibUpdate::PeerDown { peer } => {
+                        peers.remove(peer);
+                    }
+                }
+                if updates.send(update).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => (),
+            Err(e) => {
+                *last_error.lock().unwrap() =
+                    Some(format!("invalid BMP message from {}: {}", router, e));
+            }
+        }
+    }
+    for peer in peers {
+        let _ = updates.send(RibUpdate::PeerDown { peer });
+    }
+}
+
+impl BmpCollector {
+    /// Accept BMP sessions for `hddlog` on `addr`, e.g., `0.0.0.0:11019`.
+    pub fn start(hddlog: Arc<HDDlog>, addr: &str, config: BmpConfig) -> Result<Self, String> {
+        check_relations()?;
+        let listener =
+            TcpListener::bind(addr).map_err(|e| format!("failed to bind {}: {}", addr, e))?;
+        let addr = listener.local_addr().map_err(|e| e.to_string())?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let sessions = Arc::new(Mutex::new(Vec::new()));
+        let rib_len = Arc::new(Mutex::new(0));
+        let last_error = Arc::new(Mutex::new(None));
+        let (sender, receiver) = mpsc::channel();
+
+        let acceptor = {
+            let stop = stop.clone();
+            let sessions = sessions.clone();
+            let last_error = last_error.clone();
+            thread::Builder::new()
+                .name("ddlog-bmp".to_string())
+                .spawn(move || {
+                    while !stop.load(Ordering::Acquire) {
+                        match listener.accept() {
+                            Ok((stream, _)) => {
+                                let _ = stream.set_nonblocking(false);
+                                if let Ok(clone) = stream.try_clone() {
+                                    sessions.lock().unwrap().push(clone);
+                                }
+                                let sender = sender.clone();
+                                let last_error = last_error.clone();
+                                let _ = thread::Builder::new()
+                                    .name("ddlog-bmp-session".to_string())
+                                    .spawn(move || bmp_session(stream, sender, last_error));
+                            }
+                            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                                thread::sleep(Duration::from_millis(50));
+                            }
+                            Err(e) => {
+                                *last_error.lock().unwrap() =
+                                    Some(format!("failed to accept BMP session: {}", e));
+                            }
+                        }
+                    }
+                })
+                .map_err(|e| format!("failed to start BMP collector: {}", e))?
+        };
+
+        let applier = {
+            let stop = stop.clone();
+            let rib_len = rib_len.clone();
+            let last_error = last_error.clone();
+            thread::Builder::new()
+                .name("ddlog-bmp-apply".to_string())
+                .spawn(move || {
+                    let mut rib = Rib::new();
+                    let mut commands = Vec::new();
+                    let mut last_flush = Instant::now();
+                    while !stop.load(Ordering::Acquire) {
+                        match receiver.recv_timeout(config.flush_interval) {
+                            Ok(update) => rib.apply(update, &mut commands),
+                            Err(RecvTimeoutError::Timeout) => (),
+                            Err(RecvTimeoutError::Disconnected) => break,
+                        }
+                        if last_flush.elapsed() >= config.flush_interval {
+                            last_flush = Instant::now();
+                            if let Err(e) = commit(&hddlog, config.client, &mut commands) {
+                                commands.clear();
+                                *last_error.lock().unwrap() = Some(e);
+                            }
+                            *rib_len.lock().unwrap() = rib.len();
+                        }
+                    }
+                    let _ = commit(&hddlog, config.client, &mut commands);
+                })
+                .map_err(|e| format!("failed to start BMP collector: {}", e))?
+        };
+
+        Ok(Self {
+            addr,
+            stop,
+            sessions,
+            threads: vec![acceptor, applier],
+            rib_len,
+            last_error,
+        })
+    }
+
+    /// The address the collector listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The number of routes in the program, as of the last transaction.
+    pub fn routes(&self) -> usize {
+        *self.rib_len.lock().unwrap()
+    }
+
+    /// The last error encountered, if any.
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+}
+
+impl Drop for BmpCollector {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        for session in self.sessions.lock().unwrap().drain(..) {
+            let _ = session.shutdown(Shutdown::Both);
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute(type_code: u8, value: &[u8]) -> Vec<u8> {
+        let mut attr = vec![0x40, type_code, value.len() as u8];
+        attr.extend_from_slice(value);
+        attr
+    }
+
+    /// An AS_SEQUENCE segment of 4-byte AS numbers.
+    fn as_sequence(asns: &[u32]) -> Vec<u8> {
+        let mut segment = vec![2, asns.len() as u8];
+        for asn in asns {
+            segment.extend_from_slice(&asn.to_be_bytes());
+        }
+        segment
+    }
+
+    fn bgp_update(withdrawn: &[u8], attributes: &[u8], nlri: &[u8]) -> Vec<u8> {
+        let mut message = vec![0xff; 16];
+        let length = 23 + withdrawn.len() + attributes.len() + nlri.len();
+        message.extend_from_slice(&(length as u16).to_be_bytes());
+        message.push(BGP_UPDATE);
+        message.extend_from_slice(&(withdrawn.len() as u16).to_be_bytes());
+        message.extend_from_slice(withdrawn);
+        message.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+        message.extend_from_slice(attributes);
+        message.extend_from_slice(nlri);
+        message
+    }
+
+    /// A BMP message with a per-peer header for peer 192.0.2.1, AS 65001.
+    fn bmp_message(message_type: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![3];
+        message.extend_from_slice(&((6 + 42 + body.len()) as u32).to_be_bytes());
+        message.push(message_type);
+        message.extend_from_slice(&[0, flags]);
+        message.extend_from_slice(&[0; 8]); // peer distinguisher
+        message.extend_from_slice(&[0; 12]);
+        message.extend_from_slice(&[192, 0, 2, 1]);
+        message.extend_from_slice(&65001u32.to_be_bytes());
+        message.extend_from_slice(&[10, 0, 0, 1]); // BGP id
+        message.extend_from_slice(&[0; 8]); // timestamp
+        message.extend_from_slice(body);
+        message
+    }
+
+    fn peer() -> IpAddr {
+        IpAddr::from([192, 0, 2, 1])
+    }
+
+    fn prefix(addr: [u8; 4], len: u8) -> Prefix {
+        Prefix {
+            addr: IpAddr::from(addr),
+            len,
+        }
+    }
+
+    #[test]
+    fn parse_updates() {
+        let mut attributes = attribute(1, &[0]);
+        attributes.extend(attribute(2, &as_sequence(&[65001, 4200000000])));
+        attributes.extend(attribute(3, &[192, 0, 2, 1]));
+        attributes.extend(attribute(4, &10u32.to_be_bytes()));
+        attributes.extend(attribute(5, &100u32.to_be_bytes()));
+        attributes.extend(attribute(8, &[0xfd, 0xe9, 0, 1]));
+        let mut large = Vec::new();
+        for n in &[65001u32, 1, 2] {
+            large.extend_from_slice(&n.to_be_bytes());
+        }
+        attributes.extend(attribute(32, &large));
+        // MP_REACH_NLRI with an IPv6 next hop and 2001:db8::/32.
+        let mut mp_reach = vec![0, 2, 1, 16];
+        mp_reach.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        mp_reach.extend_from_slice(&[0; 11]);
+        mp_reach.push(1);
+        mp_reach.push(0); // reserved
+        mp_reach.extend_from_slice(&[32, 0x20, 0x01, 0x0d, 0xb8]);
+        attributes.extend(attribute(14, &mp_reach));
+
+        let message = bgp_update(&[8, 10], &attributes, &[24, 198, 51, 100, 0]);
+        let update = parse_bgp_message(&message, true).unwrap().unwrap();
+        assert_eq!(update.withdrawn, vec![prefix([10, 0, 0, 0], 8)]);
+        assert_eq!(
+            update.announced,
+            vec![
+                Prefix {
+                    addr: "2001:db8::".parse().unwrap(),
+                    len: 32
+                },
+                prefix([198, 51, 100, 0], 24),
+            ]
+        );
+        assert_eq!(update.mp_next_hop, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(
+            update.attributes,
+            Attributes {
+                next_hop: Some(peer()),
+                as_path: vec![AsPathSegment::Sequence(vec![65001, 4200000000])],
+                origin: Origin::Igp,
+                local_pref: Some(100),
+                med: Some(10),
+                communities: vec![(65001, 1)],
+                large_communities: vec![(65001, 1, 2)],
+            }
+        );
+
+        // 2-byte AS paths.
+        let message = bgp_update(&[], &attribute(2, &[1, 2, 0xfd, 0xe9, 0xfd, 0xea]), &[]);
+        assert_eq!(
+            parse_bgp_message(&message, false)
+                .unwrap()
+                .unwrap()
+                .attributes
+                .as_path,
+            vec![AsPathSegment::Set(vec![65001, 65002])]
+        );
+
+        // Keepalives.
+        let mut keepalive = vec![0xff; 16];
+        keepalive.extend_from_slice(&[0, 19, 4]);
+        assert_eq!(parse_bgp_message(&keepalive, true).unwrap(), None);
+    }
+
+    #[test]
+    fn parse_invalid_updates() {
+        let message = bgp_update(&[], &attribute(1, &[0]), &[24, 198, 51, 100]);
+        assert_eq!(
+            parse_bgp_message(&message[..message.len() - 1], true).unwrap_err(),
+            "truncated message"
+        );
+        assert_eq!(
+            parse_bgp_message(&bgp_update(&[], &[], &[33, 10, 0, 0, 0, 0]), true).unwrap_err(),
+            "invalid prefix length 33"
+        );
+        let mut message = bgp_update(&[], &[], &[]);
+        message[17] = 18;
+        assert_eq!(
+            parse_bgp_message(&message, true).unwrap_err(),
+            "invalid BGP message length 18"
+        );
+        // Attributes that overrun the attribute section.
+        let mut attributes = attribute(3, &[192, 0, 2, 1]);
+        attributes[2] = 8;
+        assert_eq!(
+            parse_bgp_message(&bgp_update(&[], &attributes, &[]), true).unwrap_err(),
+            "truncated message"
+        );
+    }
+
+    #[test]
+    fn parse_bmp_messages() {
+        assert_eq!(
+            parse_bmp_message(&bmp_message(BMP_PEER_UP, 0, &[0; 20])).unwrap(),
+            Some(RibUpdate::PeerUp {
+                peer: peer(),
+                asn: 65001,
+                bgp_id: 0x0a00_0001,
+            })
+        );
+        assert_eq!(
+            parse_bmp_message(&bmp_message(BMP_PEER_DOWN, 0, &[1])).unwrap(),
+            Some(RibUpdate::PeerDown { peer: peer() })
+        );
+        // The A flag selects 2-byte AS paths.
+        let update = bgp_update(&[], &attribute(2, &[2, 1, 0xfd, 0xe9]), &[]);
+        match parse_bmp_message(&bmp_message(BMP_ROUTE_MONITORING, 0x20, &update)).unwrap() {
+            Some(RibUpdate::Update { peer: p, update }) => {
+                assert_eq!(p, peer());
+                assert_eq!(
+                    update.attributes.as_path,
+                    vec![AsPathSegment::Sequence(vec![65001])]
+                );
+            }
+            update => panic!("unexpected update {:?}", update),
+        }
+        // Statistics reports.
+        assert_eq!(
+            parse_bmp_message(&bmp_message(1, 0, &[0; 4])).unwrap(),
+            None
+        );
+        let mut message = bmp_message(BMP_PEER_UP, 0, &[]);
+        message[0] = 2;
+        assert_eq!(
+            parse_bmp_message(&message).unwrap_err(),
+            "unsupported BMP version 2"
+        );
+        assert_eq!(
+            parse_bmp_message(&bmp_message(BMP_PEER_UP, 0, &[])[..30]).unwrap_err(),
+            "truncated message"
+        );
+    }
+
+    #[test]
+    fn read_bmp_messages() {
+        let up = bmp_message(BMP_PEER_UP, 0, &[0; 20]);
+        let down = bmp_message(BMP_PEER_DOWN, 0, &[1]);
+        let mut stream = up.clone();
+        stream.extend_from_slice(&down);
+        let mut reader = &stream[..];
+        assert_eq!(read_bmp_message(&mut reader).unwrap(), Some(up.clone()));
+        assert_eq!(read_bmp_message(&mut reader).unwrap(), Some(down));
+        assert_eq!(read_bmp_message(&mut reader).unwrap(), None);
+
+        assert_eq!(
+            read_bmp_message(&mut &up[..3]).unwrap_err(),
+            "truncated message"
+        );
+        assert_eq!(
+            read_bmp_message(&mut &up[..up.len() - 1]).unwrap_err(),
+            "truncated message"
+        );
+        // Lengths are checked before the message is read.
+        for length in &[5, MAX_BMP_MESSAGE_LEN as u32 + 1, u32::max_value()] {
+            let mut header = vec![3];
+            header.extend_from_slice(&length.to_be_bytes());
+            header.push(BMP_PEER_UP);
+            assert_eq!(
+                read_bmp_message(&mut &header[..]).unwrap_err(),
+                format!("invalid BMP message length {}", length)
+            );
+        }
+    }
+
+    #[test]
+    fn rib_updates() {
+        let mut rib = Rib::new();
+        let mut commands = Vec::new();
+        rib.apply(
+            RibUpdate::PeerUp {
+                peer: peer(),
+                asn: 65001,
+                bgp_id: 1,
+            },
+            &mut commands,
+        );
+        let update = Update {
+            announced: vec![prefix([198, 51, 100, 0], 24), prefix([203, 0, 113, 0], 24)],
+            attributes: Attributes {
+                next_hop: Some(peer()),
+                ..Attributes::default()
+            },
+            ..Update::default()
+        };
+        rib.apply(
+            RibUpdate::Update {
+                peer: peer(),
+                update,
+            },
+            &mut commands,
+        );
+        assert_eq!(rib.len(), 2);
+        assert_eq!(commands.len(), 3);
+
+        // Withdrawals of unknown routes are ignored.
+        commands.clear();
+        let update = Update {
+            withdrawn: vec![prefix([198, 51, 100, 0], 24), prefix([10, 0, 0, 0], 8)],
+            ..Update::default()
+        };
+        rib.apply(
+            RibUpdate::Update {
+                peer: peer(),
+                update,
+            },
+            &mut commands,
+        );
+        assert_eq!(rib.len(), 1);
+        let route_key = |p: Prefix| {
+            UpdCmd::DeleteKey(
+                RelIdentifier::RelName(Cow::from(ROUTE_RELATION)),
+                Record::Tuple(vec![ip_record(&peer()), prefix_record(&p)]),
+            )
+        };
+        assert_eq!(commands, vec![route_key(prefix([198, 51, 100, 0], 24))]);
+
+        // Peers that go down take their routes with them.
+        commands.clear();
+        rib.apply(RibUpdate::PeerDown { peer: peer() }, &mut commands);
+        assert!(rib.is_empty());
+        assert_eq!(
+            commands,
+            vec![
+                route_key(prefix([203, 0, 113, 0], 24)),
+                UpdCmd::DeleteKey(
+                    RelIdentifier::RelName(Cow::from(PEER_RELATION)),
+                    ip_record(&peer())
+                ),
+            ]
+        );
+        commands.clear();
+        rib.apply(RibUpdate::PeerDown { peer: peer() }, &mut commands);
+        assert!(commands.is_empty());
+    }
+}
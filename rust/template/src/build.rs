@@ -27,9 +27,35 @@ fn libtool() {
     println!("cargo:rerun-if-changed=src/lib.rs");
     println!("cargo:rerun-if-changed=src/main.rs");
     println!("cargo:rerun-if-changed=src/api/mod.rs");
+    println!("cargo:rerun-if-changed=src/api/archive.rs");
     println!("cargo:rerun-if-changed=src/api/c_api.rs");
+    println!("cargo:rerun-if-changed=src/api/changelog.rs");
+    println!("cargo:rerun-if-changed=src/api/compression.rs");
+    println!("cargo:rerun-if-changed=src/api/history.rs");
+    println!("cargo:rerun-if-changed=src/api/integrity.rs");
+    println!("cargo:rerun-if-changed=src/api/key_conflicts.rs");
+    println!("cargo:rerun-if-changed=src/api/scheduler.rs");
+    println!("cargo:rerun-if-changed=src/api/session.rs");
+    println!("cargo:rerun-if-changed=src/api/tenant.rs");
+    println!("cargo:rerun-if-changed=src/api/view_cache.rs");
+    println!("cargo:rerun-if-changed=src/alert_sink.rs");
+    println!("cargo:rerun-if-changed=src/bgp_feed.rs");
+    println!("cargo:rerun-if-changed=src/dashboard.rs");
+    println!("cargo:rerun-if-changed=src/ddlog_testing.rs");
+    println!("cargo:rerun-if-changed=src/flow_collector.rs");
+    println!("cargo:rerun-if-changed=src/k8s_controller.rs");
+    println!("cargo:rerun-if-changed=src/notebook.rs");
+    println!("cargo:rerun-if-changed=src/otlp.rs");
     println!("cargo:rerun-if-changed=src/ovsdb_api.rs");
+    println!("cargo:rerun-if-changed=src/packet_source.rs");
+    println!("cargo:rerun-if-changed=src/postgres_sink.rs");
+    println!("cargo:rerun-if-changed=src/security_logs.rs");
+    println!("cargo:rerun-if-changed=src/sigma.rs");
+    println!("cargo:rerun-if-changed=src/snapshot_publisher.rs");
+    println!("cargo:rerun-if-changed=src/sqlite_sink.rs");
     println!("cargo:rerun-if-changed=src/update_handler.rs");
+    println!("cargo:rerun-if-changed=src/web_ui.rs");
+    println!("cargo:rerun-if-changed=src/web_ui.html");
 
     let lib = "libdatalog_example_ddlog";
 
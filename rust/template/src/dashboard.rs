@@ -0,0 +1,415 @@
+//! Terminal dashboard for a running program.
+//!
+//! The dashboard shows the size of every counted relation (see
+//! `Config::relation_stats`) and how far the dataflow has processed its
+//! inputs, the relations changed by recent commits, the latency of recent
+//! commits, and the dataflow operators that used the most CPU time.  It is drawn by a background thread on the
+//! terminal attached to stderr, so that the regular output of the program can
+//! be redirected, e.g.:
+//!
+//! ```text
+//! datalog_example_cli --dashboard < commands.dat > output.txt
+//! ```
+//!
+//! Press `q` to close the dashboard; the program keeps running.  Requires the
+//! `dashboard` feature.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Stderr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, Row, Sparkline, Table};
+use ratatui::{Frame, Terminal};
+
+use differential_datalog::program::progress::Frontier;
+use differential_datalog::program::stats::RelationStats;
+use differential_datalog::program::RelId;
+use differential_datalog::DDlogProfiling;
+
+use crate::api::HDDlog;
+use crate::relid2name;
+
+/// Number of commits whose latency and changes are remembered.
+const HISTORY: usize = 100;
+/// Number of operators listed as CPU hotspots.
+const HOTSPOTS: usize = 10;
+
+type Term = Terminal<CrosstermBackend<Stderr>>;
+
+struct Commit {
+    /// Sequence number of the commit since the dashboard was attached.
+    number: usize,
+    latency: Duration,
+    /// Relations changed by the commit and the number of changes.
+    changes: Vec<(RelId, u64)>,
+}
+
+#[derive(Default)]
+struct History {
+    commits: VecDeque<Commit>,
+    total: usize,
+}
+
+impl History {
+    /// Record a commit that took `latency`, taking the relations it changed
+    /// from the relation statistics right after it.
+    fn push(&mut self, latency: Duration, stats: &BTreeMap<RelId, RelationStats>) {
+        let changes = stats
+            .iter()
+            .filter(|(_, stats)| stats.last_commit_delta > 0)
+            .map(|(relid, stats)| (*relid, stats.last_commit_delta))
+            .collect();
+        self.total += 1;
+        if self.commits.len() == HISTORY {
+            self.commits.pop_front();
+        }
+        self.commits.push_back(Commit {
+            number: self.total,
+            latency,
+            changes,
+        });
+    }
+}
+
+/// A dashboard attached to a running program.
+pub struct Dashboard {
+    hddlog: Arc<HDDlog>,
+    history: Arc<Mutex<History>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Dashboard {
+    /// Start drawing the dashboard for `hddlog`, refreshing it every
+    /// `refresh`.  Enables CPU profiling, which is needed to find hotspots.
+    pub fn attach(hddlog: Arc<HDDlog>, refresh: Duration) -> io::Result<Self> {
+        let _ = hddlog.enable_cpu_profiling(true);
+        let mut terminal = open_terminal()?;
+        let history = Arc::new(Mutex::new(History::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let hddlog = hddlog.clone();
+            let history = history.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let res = run(&mut terminal, &hddlog, &history, &stop, refresh);
+                close_terminal(&mut terminal).and(res)
+            })
+        };
+        Ok(Self {
+            hddlog,
+            history,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Record a commit that took `latency`.  The relations it changed are
+    /// taken from the relation statistics of the program, so this must be
+    /// called after the commit and before the next one.
+    pub fn record_commit(&self, latency: Duration) {
+        let stats = self.hddlog.all_relation_stats();
+        self.history.lock().unwrap().push(latency, &stats);
+    }
+
+    /// Wait for the user to close the dashboard.
+    pub fn wait(mut self) -> io::Result<()> {
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or_else(|_| {
+                Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "dashboard thread panicked",
+                ))
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn open_terminal() -> io::Result<Term> {
+    enable_raw_mode()?;
+    let mut stderr = io::stderr();
+    execute!(stderr, EnterAlternateScreen)?;
+    Terminal::new(CrosstermBackend::new(stderr))
+}
+
+fn close_terminal(terminal: &mut Term) -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()
+}
+
+fn run(
+    terminal: &mut Term,
+    hddlog: &HDDlog,
+    history: &Mutex<History>,
+    stop: &AtomicBool,
+    refresh: Duration,
+) -> io::Result<()> {
+    while !stop.load(Ordering::Relaxed) {
+        let stats = hddlog.all_relation_stats();
+        let frontier = hddlog.frontier();
+        let hotspots = hddlog.cpu_hotspots(HOTSPOTS);
+        {
+            let history = history.lock().unwrap();
+            terminal.draw(|f| draw(f, &stats, frontier, &history, &hotspots))?;
+        }
+        if event::poll(refresh)? {
+            if let Event::Key(key) = event::read()? {
+                if key.code == KeyCode::Char('q') || key.code == KeyCode::Esc {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn block(title: String) -> Block<'static> {
+    Block::default().borders(Borders::ALL).title(title)
+}
+
+fn header(cells: &[&'static str]) -> Row<'static> {
+    Row::new(cells.to_vec()).style(Style::default().add_modifier(Modifier::BOLD))
+}
+
+fn name(relid: RelId) -> String {
+    relid2name(relid).unwrap_or("?").to_string()
+}
+
+fn draw<B: Backend>(
+    f: &mut Frame<B>,
+    stats: &BTreeMap<RelId, RelationStats>,
+    frontier: Frontier,
+    history: &History,
+    hotspots: &[(String, Duration, usize)],
+) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(f.size());
+    let halves = |area| {
+        Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(area)
+    };
+    let top = halves(rows[0]);
+    let bottom = halves(rows[1]);
+
+    /* Relation sizes, largest first, and the progress of the dataflow. */
+    let mut by_size: Vec<(&RelId, &RelationStats)> = stats.iter().collect();
+    by_size.sort_by(|a, b| a.1.size.cmp(&b.1.size).reverse());
+    let sizes = Table::new(by_size.into_iter().map(|(relid, stats)| {
+        Row::new(vec![
+            name(*relid),
+            stats.size.to_string(),
+            stats.inserts.to_string(),
+            stats.deletes.to_string(),
+        ])
+    }))
+    .header(header(&["Relation", "Size", "Inserts", "Deletes"]))
+    .block(block(if frontier.is_quiescent() {
+        format!("Relations (epoch {})", frontier.submitted)
+    } else {
+        format!(
+            "Relations (epoch {}, processed {})",
+            frontier.submitted, frontier.processed
+        )
+    }))
+    .widths(&[
+        Constraint::Percentage(55),
+        Constraint::Percentage(15),
+        Constraint::Percentage(15),
+        Constraint::Percentage(15),
+    ]);
+    f.render_widget(sizes, top[0]);
+
+    /* Changes made by recent commits, most recent first. */
+    let deltas = Table::new(history.commits.iter().rev().flat_map(|commit| {
+        commit.changes.iter().map(move |(relid, changes)| {
+            Row::new(vec![
+                format!("#{}", commit.number),
+                name(*relid),
+                changes.to_string(),
+            ])
+        })
+    }))
+    .header(header(&["Commit", "Relation", "Changes"]))
+    .block(block("Recent changes".to_string()))
+    .widths(&[
+        Constraint::Percentage(15),
+        Constraint::Percentage(65),
+        Constraint::Percentage(20),
+    ]);
+    f.render_widget(deltas, top[1]);
+
+    /* Commit latency in microseconds. */
+    let latencies: Vec<u64> = history
+        .commits
+        .iter()
+        .map(|commit| commit.latency.as_micros() as u64)
+        .collect();
+    let title = match history.commits.back() {
+        Some(last) => format!(
+            "Commit latency (last: {:?}, max: {:?})",
+            last.latency,
+            history
+                .commits
+                .iter()
+                .map(|commit| commit.latency)
+                .max()
+                .unwrap_or_default()
+        ),
+        None => "Commit latency".to_string(),
+    };
+    let latency = Sparkline::default().block(block(title)).data(&latencies);
+    f.render_widget(latency, bottom[0]);
+
+    /* Operators that used the most CPU time. */
+    let cpu = Table::new(hotspots.iter().map(|(op, duration, calls)| {
+        Row::new(vec![
+            op.clone(),
+            format!("{:?}", duration),
+            calls.to_string(),
+        ])
+    }))
+    .header(header(&["Operator", "CPU time", "Calls"]))
+    .block(block("CPU hotspots".to_string()))
+    .widths(&[
+        Constraint::Percentage(60),
+        Constraint::Percentage(25),
+        Constraint::Percentage(15),
+    ]);
+    f.render_widget(cpu, bottom[1]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::borrow::Cow;
+
+    use differential_datalog::ddval::DDValConvert;
+    use differential_datalog::program::config::Config;
+    use differential_datalog::program::{CachingMode, ProgNode, Program, Relation};
+    use ratatui::backend::TestBackend;
+
+    /// The rendered dashboard, one string per line.
+    fn render(
+        stats: &BTreeMap<RelId, RelationStats>,
+        frontier: Frontier,
+        history: &History,
+        hotspots: &[(String, Duration, usize)],
+    ) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(160, 24)).unwrap();
+        terminal
+            .draw(|f| draw(f, stats, frontier, history, hotspots))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer.get(x, y).symbol.as_str())
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn line_with<'a>(lines: &'a [String], text: &str) -> &'a str {
+        lines
+            .iter()
+            .find(|line| line.contains(text))
+            .unwrap_or_else(|| panic!("'{}' not found in\n{}", text, lines.join("\n")))
+    }
+
+    #[test]
+    fn render_program() {
+        let prog = Program {
+            nodes: vec![ProgNode::Rel {
+                rel: Relation {
+                    name: Cow::from("T"),
+                    input: true,
+                    distinct: true,
+                    caching_mode: CachingMode::Set,
+                    key_func: None,
+                    id: 1,
+                    rules: Vec::new(),
+                    arrangements: Vec::new(),
+                    change_cb: None,
+                },
+            }],
+            delayed_rels: vec![],
+            lazy_rels: vec![],
+            shared_arrangements: vec![],
+            columnar_rels: vec![],
+            init_data: vec![],
+        };
+        let config = Config {
+            relation_stats: true,
+            ..Default::default()
+        };
+        let mut running = prog.run_with_config(config).unwrap();
+        let mut history = History::default();
+
+        running.transaction_start().unwrap();
+        for i in 0..4u64 {
+            running.insert(1, i.into_ddvalue()).unwrap();
+        }
+        running.transaction_commit().unwrap();
+        history.push(Duration::from_millis(5), &running.all_relation_stats());
+        running.transaction_start().unwrap();
+        running.delete_value(1, 3u64.into_ddvalue()).unwrap();
+        running.transaction_commit().unwrap();
+        history.push(Duration::from_millis(2), &running.all_relation_stats());
+
+        let stats = running.all_relation_stats();
+        let frontier = running.frontier();
+        let hotspots = vec![("Map: T".to_string(), Duration::from_millis(3), 7)];
+        let lines = render(&stats, frontier, &history, &hotspots);
+
+        let title = format!("Relations (epoch {}", frontier.submitted);
+        assert!(line_with(&lines, &title).contains("Recent changes"));
+        // Size, inserts and deletes of the relation.
+        let row = line_with(&lines, &format!("│{}", name(1)));
+        assert_eq!(
+            row.split('│')
+                .nth(1)
+                .unwrap()
+                .split_whitespace()
+                .collect::<Vec<_>>()[1..],
+            ["3", "4", "1"]
+        );
+        // Changes of both commits, most recent first.
+        let first = lines.iter().position(|line| line.contains("#1")).unwrap();
+        let second = lines.iter().position(|line| line.contains("#2")).unwrap();
+        assert!(second < first);
+        assert!(line_with(&lines, "Commit latency (last: 2ms, max: 5ms)").contains("CPU hotspots"));
+        assert!(line_with(&lines, "Map: T").contains("3ms"));
+
+        running.stop().unwrap();
+        let idle = running.frontier();
+        let lines = render(&stats, idle, &history, &hotspots);
+        line_with(&lines, &format!("Relations (epoch {})", idle.submitted));
+    }
+}
@@ -0,0 +1,541 @@
+//! Helpers for testing DDlog programs from Rust.
+//!
+//! These functions make it possible to write ordinary `#[test]` functions
+//! against the generated crate: start the program with `start()`, feed it
+//! transactions written in the same syntax as `.dat` files
+//! (`transaction()`), and check the contents of output relations against
+//! the expected facts (`assert_relation()`) or against a dump stored in a
+//! golden file (`assert_golden()`).  Assertions panic with a diff between the
+//! expected and the actual contents, followed by the fields that differ
+//! between each missing fact and the most similar unexpected fact (see
+//! `Record::diff()`).
+//!
+//! `check_consistency()` checks that the outputs that the program computes
+//! incrementally, as transactions are applied one by one, are the same as
+//! the outputs computed from scratch from the final contents of the input
+//! relations.  With the `proptest` feature enabled, `transactions()`
+//! generates random transactions to feed it with.
+//!
+//! ```ignore
+//! let hddlog = ddlog_testing::start(1).unwrap();
+//! ddlog_testing::transaction(&hddlog, "insert Edge(1, 2), insert Edge(2, 3);").unwrap();
+//! ddlog_testing::assert_relation(&hddlog, "Path", &["Path(1, 2)", "Path(1, 3)", "Path(2, 3)"]);
+//! ddlog_testing::assert_golden(&hddlog, "tests/golden/path.dump");
+//! ```
+
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use cmd_parser::{err_str, parse_command, parse_record, Command};
+
+use differential_datalog::ddval::DDValue;
+use differential_datalog::program::{RelId, Update};
+use differential_datalog::record::{PathDelta, Record, RelIdentifier, UpdCmd};
+use differential_datalog::{DDlog, DDlogDynamic, FormatOptions};
+
+use crate::api::HDDlog;
+use crate::{relval_from_record, Relations};
+
+/// When this environment variable is set, `assert_golden()` overwrites golden
+/// files with the actual dump instead of comparing against them.
+pub const UPDATE_GOLDEN_VAR: &str = "DDLOG_UPDATE_GOLDEN";
+
+/// Start the program with `workers` worker threads.  The contents of output
+/// relations are stored, so that they can be checked by the assertions in
+/// this module.
+pub fn start(workers: usize) -> Result<HDDlog, String> {
+    HDDlog::run(workers, true).map(|(hddlog, _)| hddlog)
+}
+
+/// Parse a sequence of updates written in the `.dat` file syntax, e.g.,
+/// `insert R(1), delete S("foo");`.  Comments are allowed; any other commands
+/// (`start`, `commit`, `dump`, ...) are rejected.
+pub fn parse_updates(text: &str) -> Result<Vec<UpdCmd>, String> {
+    let mut updates = Vec::new();
+    let mut input = text.as_bytes();
+    loop {
+        let start = input
+            .iter()
+            .position(|c| !c.is_ascii_whitespace())
+            .unwrap_or(input.len());
+        input = &input[start..];
+        if input.is_empty() {
+            return Ok(updates);
+        }
+
+        match parse_command(input) {
+            Ok((rest, Command::Update(upd, _))) => {
+                updates.push(upd);
+                input = rest;
+            }
+            Ok((rest, Command::Comment)) => input = rest,
+            Ok((_, cmd)) => return Err(format!("unexpected command {:?}", cmd)),
+            Err(e) => {
+                // `err_str()` returns an empty string if the input ends in
+                // the middle of a command.
+                let msg = err_str(&e);
+                return Err(if msg.is_empty() {
+                    format!("incomplete command: {}", String::from_utf8_lossy(input))
+                } else {
+                    format!("invalid input: {}", msg)
+                });
+            }
+        }
+    }
+}
+
+/// Apply the updates in `text` (see `parse_updates()`) in a new transaction.
+/// The transaction is rolled back if any of the updates fails.
+pub fn transaction(hddlog: &HDDlog, text: &str) -> Result<(), String> {
+    let updates = parse_updates(text)?;
+    hddlog.transaction_start()?;
+    match hddlog.apply_updates_dynamic(&mut updates.into_iter()) {
+        Ok(()) => hddlog.transaction_commit(),
+        Err(e) => {
+            let _ = hddlog.transaction_rollback();
+            Err(e)
+        }
+    }
+}
+
+fn output_relation(relation: &str) -> Result<Relations, String> {
+    match Relations::try_from(relation) {
+        Ok(rel) if rel.is_output() => Ok(rel),
+        _ => Err(format!("unknown output relation {}", relation)),
+    }
+}
+
+/// Format a fact the same way as relation dumps, with its weight if it is
+/// not 1.
+fn format_fact(v: &impl std::fmt::Display, weight: isize) -> String {
+    if weight == 1 {
+        v.to_string()
+    } else {
+        format!("{} {:+}", v, weight)
+    }
+}
+
+/// Returns the current contents of output relation `relation`, one fact per
+/// line in the dump format, in sorted order.
+pub fn relation_contents(hddlog: &HDDlog, relation: &str) -> Result<Vec<String>, String> {
+    let rel = output_relation(relation)?;
+    let db = hddlog
+        .db
+        .as_ref()
+        .ok_or_else(|| "the contents of output relations are not stored".to_string())?
+        .lock()
+        .unwrap();
+    let mut facts: Vec<String> = db
+        .try_get_rel(rel as RelId)
+        .into_iter()
+        .flatten()
+        .map(|(v, w)| format_fact(v, *w))
+        .collect();
+    facts.sort();
+    Ok(facts)
+}
+
+/// Convert facts written in the `.dat` file syntax, e.g., `R(1, "foo")`, to
+/// the dump format, in sorted order.
+fn normalize_facts(relation: &str, facts: &[&str]) -> Result<Vec<String>, String> {
+    let rel = output_relation(relation)?;
+    let mut normalized = Vec::with_capacity(facts.len());
+    for fact in facts {
+        let (relname, rec) = match parse_updates(&format!("insert {};", fact))?.pop() {
+            Some(UpdCmd::Insert(RelIdentifier::RelName(relname), rec)) => (relname, rec),
+            _ => return Err(format!("invalid fact {}", fact)),
+        };
+        if relname != relation {
+            return Err(format!(
+                "fact {} does not belong to relation {}",
+                fact, relation
+            ));
+        }
+        normalized.push(format_fact(&relval_from_record(rel, &rec)?, 1));
+    }
+    normalized.sort();
+    Ok(normalized)
+}
+
+/// Assert that output relation `relation` contains exactly the facts in
+/// `expected`, written in the `.dat` file syntax (e.g., `R(1, "foo")`).
+///
+/// # Panics
+///
+/// Panics with a diff between the expected and the actual contents of the
+/// relation if they differ.
+pub fn assert_relation(hddlog: &HDDlog, relation: &str, expected: &[&str]) {
+    let expected = normalize_facts(relation, expected).unwrap();
+    let actual = relation_contents(hddlog, relation).unwrap();
+    if expected != actual {
+        panic!(
+            "unexpected contents of relation {} (-expected, +actual):\n{}",
+            relation,
+            report_diff(&expected, &actual)
+        );
+    }
+}
+
+/// Returns a dump of all output relations, in the same format as the `dump`
+/// CLI command.  Relation contents are dumped in the canonical order if the
+/// program was started with `Config::canonical_order`.
+pub fn dump_outputs(hddlog: &HDDlog) -> Result<String, String> {
+    dump_outputs_with(
+        hddlog,
+        &FormatOptions {
+            canonical_order: hddlog.config().canonical_order,
+            ..FormatOptions::default()
+        },
+    )
+}
+
+fn dump_outputs_with(hddlog: &HDDlog, options: &FormatOptions) -> Result<String, String> {
+    let mut dump = Vec::new();
+    if let Some(db) = hddlog.db.as_ref() {
+        db.lock()
+            .unwrap()
+            .format_as_sets_with(&mut dump, hddlog, options)
+            .map_err(|e| e.to_string())?;
+    }
+    String::from_utf8(dump).map_err(|e| e.to_string())
+}
+
+/// Assert that the dump of all output relations (see `dump_outputs()`)
+/// matches the contents of golden file `path`.  If the `DDLOG_UPDATE_GOLDEN`
+/// environment variable is set, the golden file is overwritten with the
+/// dump instead.
+///
+/// # Panics
+///
+/// Panics with a diff between the golden file and the dump if they differ,
+/// or if the golden file cannot be read or written.
+pub fn assert_golden<P: AsRef<Path>>(hddlog: &HDDlog, path: P) {
+    let path = path.as_ref();
+    let actual = dump_outputs(hddlog).unwrap();
+
+    if env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(path, &actual)
+            .unwrap_or_else(|e| panic!("failed to write {}: {}", path.display(), e));
+        return;
+    }
+
+    let expected = fs::read_to_string(path).unwrap_or_else(|e| {
+        panic!(
+            "failed to read {}: {} (set {} to create it)",
+            path.display(),
+            e,
+            UPDATE_GOLDEN_VAR
+        )
+    });
+    if expected != actual {
+        let expected: Vec<&str> = expected.lines().collect();
+        let actual: Vec<&str> = actual.lines().collect();
+        panic!(
+            "dump differs from {} (-expected, +actual):\n{}",
+            path.display(),
+            report_diff(&expected, &actual)
+        );
+    }
+}
+
+/// Apply `transactions` to a new instance of the program one by one, then
+/// start another instance with the same input relations in a single
+/// transaction (see `HDDlog::restore()`) and compare the outputs of the two
+/// instances.  Transactions that fail (e.g., because they insert duplicate
+/// keys) are rolled back and skipped.
+///
+/// Returns an error with a diff between the two dumps (see
+/// `dump_outputs()`) if the outputs differ.  The dumps are compared in the
+/// canonical order, since the two instances may order interned values
+/// differently.
+pub fn check_consistency(
+    workers: usize,
+    transactions: &[Vec<Update<DDValue>>],
+) -> Result<(), String> {
+    let incremental = start(workers)?;
+    for updates in transactions {
+        incremental.transaction_start()?;
+        match incremental.apply_updates(&mut updates.iter().cloned()) {
+            Ok(()) => incremental.transaction_commit()?,
+            Err(_) => incremental.transaction_rollback()?,
+        }
+    }
+    let canonical = FormatOptions {
+        canonical_order: true,
+        ..FormatOptions::default()
+    };
+    let archive = incremental.archive(false)?;
+    let incremental_dump = dump_outputs_with(&incremental, &canonical)?;
+    incremental.stop()?;
+
+    let from_scratch = start(workers)?;
+    from_scratch.restore(&archive)?;
+    let from_scratch_dump = dump_outputs_with(&from_scratch, &canonical)?;
+    from_scratch.stop()?;
+
+    if incremental_dump != from_scratch_dump {
+        let incremental_dump: Vec<&str> = incremental_dump.lines().collect();
+        let from_scratch_dump: Vec<&str> = from_scratch_dump.lines().collect();
+        return Err(format!(
+            "incremental and from-scratch outputs differ (-incremental, +from scratch):\n{}",
+            report_diff(&incremental_dump, &from_scratch_dump)
+        ));
+    }
+    Ok(())
+}
+
+/// Strategy that generates up to `max_transactions` transactions of up to
+/// `max_updates` random insertions and deletions each in input relation
+/// `relation` with records of type `T`.
+///
+/// Values are drawn from a small pool, so that deletions and repeated
+/// insertions hit existing facts.  Deletions of values that are not in the
+/// relation at that point are left out, which keeps the weights in multiset
+/// relations non-negative.
+///
+/// ```ignore
+/// proptest! {
+///     #[test]
+///     fn edges_are_consistent(txns in ddlog_testing::transactions::<Edge>(Relations::Edge, 5, 10)) {
+///         ddlog_testing::check_consistency(2, &txns).unwrap();
+///     }
+/// }
+/// ```
+#[cfg(feature = "proptest")]
+pub fn transactions<T>(
+    relation: Relations,
+    max_transactions: usize,
+    max_updates: usize,
+) -> impl differential_datalog::proptest::strategy::Strategy<Value = Vec<Vec<Update<DDValue>>>>
+where
+    T: differential_datalog::proptest::arbitrary::Arbitrary
+        + differential_datalog::ddval::DDValConvert
+        + Clone,
+{
+    use differential_datalog::proptest::{arbitrary::any, collection, sample, strategy::Strategy};
+
+    /// Number of distinct values updates are drawn from.
+    const POOL_SIZE: usize = 8;
+
+    let relid = relation as RelId;
+    let update = (any::<bool>(), any::<sample::Index>());
+    let transaction = collection::vec(update, 0..=max_updates);
+    (
+        collection::vec(any::<T>(), 1..=POOL_SIZE),
+        collection::vec(transaction, 1..=max_transactions),
+    )
+        .prop_map(move |(pool, transactions)| {
+            let mut weights = vec![0usize; pool.len()];
+            transactions
+                .into_iter()
+                .map(|updates| {
+                    updates
+                        .into_iter()
+                        .filter_map(|(insert, idx)| {
+                            let i = idx.index(pool.len());
+                            let v = pool[i].clone().into_ddvalue();
+                            if insert {
+                                weights[i] += 1;
+                                Some(Update::Insert { relid, v })
+                            } else if weights[i] > 0 {
+                                weights[i] -= 1;
+                                Some(Update::DeleteValue { relid, v })
+                            } else {
+                                None
+                            }
+                        })
+                        .collect()
+                })
+                .collect()
+        })
+}
+
+/// Among `candidates`, find the record with the fewest differences from
+/// `expected` (see `Record::diff()`).  Records that cannot be compared with
+/// `expected` field by field, e.g., because they were built by a different
+/// constructor, are skipped.  Returns the closest record along with its
+/// differences from `expected`, or `None` if there is no such record.
+pub fn closest_match<'a, I>(
+    expected: &Record,
+    candidates: I,
+) -> Option<(&'a Record, Vec<PathDelta>)>
+where
+    I: IntoIterator<Item = &'a Record>,
+{
+    candidates
+        .into_iter()
+        .map(|candidate| (candidate, expected.diff(candidate)))
+        .filter(|(_, deltas)| deltas.iter().all(|d| !d.path.is_empty()))
+        .min_by_key(|(_, deltas)| deltas.len())
+}
+
+/// Strip the weight from a fact in the dump format, e.g., `R(1) +2`.
+fn strip_weight(line: &str) -> &str {
+    match line.rfind(' ') {
+        Some(i)
+            if line[i + 1..].starts_with(|c: char| c == '+' || c == '-')
+                && line[i + 2..].parse::<usize>().is_ok() =>
+        {
+            &line[..i]
+        }
+        _ => line,
+    }
+}
+
+/// Field-level differences between the facts that are only in `expected` and
+/// the facts that are only in `actual`, given as lines in the dump format.
+/// Each fact only in `expected` is paired with the most similar fact only in
+/// `actual` (see `closest_match()`), followed by the paths of the fields that
+/// differ.  Lines that do not parse as facts, e.g., relation names, are
+/// ignored.
+pub fn field_diffs<S: AsRef<str>>(expected: &[S], actual: &[S]) -> String {
+    fn only_in<S: AsRef<str>>(lines: &[S], other: &[S]) -> Vec<Record> {
+        lines
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|l| !other.iter().any(|o| o.as_ref() == *l))
+            .filter_map(|l| parse_record(strip_weight(l)).ok())
+            .collect()
+    }
+    let missing = only_in(expected, actual);
+    let unexpected = only_in(actual, expected);
+
+    let mut diffs = String::new();
+    for rec in missing.iter() {
+        if let Some((closest, deltas)) = closest_match(rec, &unexpected) {
+            diffs.push_str(&format!("- {}\n+ {}\n", rec, closest));
+            for delta in deltas {
+                diffs.push_str(&format!("    {}\n", delta));
+            }
+        }
+    }
+    diffs
+}
+
+/* `line_diff()` of `expected` and `actual`, followed by their `field_diffs()`,
+ * if any. */
+fn report_diff<S: AsRef<str>>(expected: &[S], actual: &[S]) -> String {
+    let mut report = line_diff(expected, actual);
+    let fields = field_diffs(expected, actual);
+    if !fields.is_empty() {
+        report.push_str("\nfields that differ (-expected, +actual):\n");
+        report.push_str(&fields);
+    }
+    report
+}
+
+/// Line-by-line diff of `expected` and `actual`, based on their longest
+/// common subsequence.  Lines only in `expected` are prefixed with `-`,
+/// lines only in `actual` with `+`, and common lines with a space.
+pub fn line_diff<S: AsRef<str>>(expected: &[S], actual: &[S]) -> String {
+    let (n, m) = (expected.len(), actual.len());
+    // lcs[i][j] is the length of the LCS of `expected[i..]` and `actual[j..]`.
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if expected[i].as_ref() == actual[j].as_ref() {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i].as_ref() == actual[j].as_ref() {
+            diff.push_str(&format!("  {}\n", expected[i].as_ref()));
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            diff.push_str(&format!("+ {}\n", actual[j].as_ref()));
+            j += 1;
+        } else {
+            diff.push_str(&format!("- {}\n", expected[i].as_ref()));
+            i += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use differential_datalog::record::IntoRecord;
+
+    #[test]
+    fn parse_updates_accepts_updates_and_comments() {
+        let updates = parse_updates(
+            r#"
+            # comment
+            insert R(1, "foo"),
+            delete S(2);
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            updates,
+            vec![
+                UpdCmd::Insert(
+                    RelIdentifier::RelName("R".into()),
+                    Record::PosStruct(
+                        "R".into(),
+                        vec![1u32.into_record(), "foo".to_string().into_record()]
+                    )
+                ),
+                UpdCmd::Delete(
+                    RelIdentifier::RelName("S".into()),
+                    Record::PosStruct("S".into(), vec![2u32.into_record()])
+                ),
+            ]
+        );
+        assert_eq!(parse_updates("   ").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn parse_updates_rejects_other_commands() {
+        assert!(parse_updates("start;")
+            .unwrap_err()
+            .starts_with("unexpected command"));
+        assert!(parse_updates("insert R(1)")
+            .unwrap_err()
+            .starts_with("incomplete command"));
+        assert!(parse_updates("insert R(1);\nfrobnicate;")
+            .unwrap_err()
+            .starts_with("invalid input"));
+    }
+
+    #[test]
+    fn line_diff_marks_changed_lines() {
+        assert_eq!(
+            line_diff(&["a", "b", "c"], &["a", "x", "c", "d"]),
+            "  a\n+ x\n- b\n  c\n+ d\n"
+        );
+        assert_eq!(line_diff::<&str>(&[], &[]), "");
+    }
+
+    #[test]
+    fn strip_weight_removes_only_weights() {
+        assert_eq!(strip_weight("R(1) +2"), "R(1)");
+        assert_eq!(strip_weight("R(1) -1"), "R(1)");
+        assert_eq!(strip_weight("R(1)"), "R(1)");
+        assert_eq!(strip_weight(r#"R("a +b")"#), r#"R("a +b")"#);
+    }
+
+    #[test]
+    fn field_diffs_pairs_closest_facts() {
+        let expected = ["R{.x = 1, .y = \"foo\"}", "R{.x = 3, .y = \"bar\"}"];
+        let actual = ["R{.x = 1, .y = \"baz\"} +2", "R{.x = 3, .y = \"bar\"}"];
+        let diffs = field_diffs(&expected, &actual);
+        assert!(diffs.starts_with("- R{.x = 1, .y = \"foo\"}\n+ R{.x = 1, .y = \"baz\"}\n"));
+        assert!(diffs.ends_with("    .y: \"foo\" -> \"baz\"\n"));
+        assert_eq!(field_diffs(&expected, &expected), "");
+    }
+}
@@ -0,0 +1,1049 @@
+//! NetFlow and sFlow collector.
+//!
+//! `FlowCollector` receives NetFlow v5, NetFlow v9, and sFlow v5 datagrams
+//! on a UDP socket, decodes them into flow records, and inserts them into
+//! the `net::flow::Flow` input stream of a program that imports the
+//! `net::flow` library (see `lib/net/flow.dl`).
+//!
+//! * NetFlow v9 data records are decoded with the templates previously
+//!   received from the same exporter and source id.  Records that arrive
+//!   before their template are counted in `FlowStats::no_template` and
+//!   dropped.  Options templates and options data are ignored.
+//! * sFlow flow samples are decoded from sampled IPv4 and IPv6 records or
+//!   from raw Ethernet packet headers; counter samples are ignored.
+//!
+//! Flows received within `FlowCollectorConfig::flush_interval` are inserted
+//! in one transaction, started with `HDDlog::try_start_transaction()` so that
+//! the collector shares the program fairly with other clients.  Requires the
+//! `flows` feature.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use differential_datalog::record::{Record, RelIdentifier, UpdCmd};
+use differential_datalog::scheduler::ClientId;
+
+use crate::api::HDDlog;
+use crate::Relations;
+
+/// Name of the stream declared by the `net::flow` library.
+const FLOW_RELATION: &str = "net::flow::Flow";
+
+/// Configuration of a `FlowCollector`.
+#[derive(Debug, Clone)]
+pub struct FlowCollectorConfig {
+    /// Maximal time flows are buffered before they are inserted.
+    pub flush_interval: Duration,
+    /// Client id used to schedule the collector's transactions.
+    pub client: ClientId,
+}
+
+impl Default for FlowCollectorConfig {
+    fn default() -> Self {
+        Self {
+            flush_interval: Duration::from_millis(100),
+            client: ClientId::max_value() - 2,
+        }
+    }
+}
+
+/// A decoded flow record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowRecord {
+    pub exporter: IpAddr,
+    pub protocol: FlowExportProtocol,
+    pub observation_domain: u32,
+    pub src_addr: IpAddr,
+    pub dst_addr: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub ip_proto: u8,
+    pub tcp_flags: u8,
+    pub tos: u8,
+    pub input_if: u32,
+    pub output_if: u32,
+    pub packets: u64,
+    pub bytes: u64,
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub sampling_rate: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowExportProtocol {
+    NetFlowV5,
+    NetFlowV9,
+    SFlowV5,
+}
+
+impl FlowRecord {
+    fn new(exporter: IpAddr, protocol: FlowExportProtocol, observation_domain: u32) -> Self {
+        let unspecified = IpAddr::from([0, 0, 0, 0]);
+        Self {
+            exporter,
+            protocol,
+            observation_domain,
+            src_addr: unspecified,
+            dst_addr: unspecified,
+            src_port: 0,
+            dst_port: 0,
+            ip_proto: 0,
+            tcp_flags: 0,
+            tos: 0,
+            input_if: 0,
+            output_if: 0,
+            packets: 0,
+            bytes: 0,
+            start_ms: 0,
+            end_ms: 0,
+            sampling_rate: 1,
+        }
+    }
+
+    fn ip_record(addr: &IpAddr) -> Record {
+        match addr {
+            IpAddr::V4(addr) => Record::NamedStruct(
+                Cow::from("net::ipaddr::IpAddrV4"),
+                vec![(Cow::from("addr4"), Record::Int(u32::from(*addr).into()))],
+            ),
+            IpAddr::V6(addr) => Record::NamedStruct(
+                Cow::from("net::ipaddr::IpAddrV6"),
+                vec![(Cow::from("addr6"), Record::Int(u128::from(*addr).into()))],
+            ),
+        }
+    }
+
+    /// The record of the flow in `net::flow::Flow`.
+    pub fn to_record(&self) -> Record {
+        let protocol = match self.protocol {
+            FlowExportProtocol::NetFlowV5 => "net::flow::NetFlowV5",
+            FlowExportProtocol::NetFlowV9 => "net::flow::NetFlowV9",
+            FlowExportProtocol::SFlowV5 => "net::flow::SFlowV5",
+        };
+        let int = |i: u64| Record::Int(i.into());
+        Record::NamedStruct(
+            Cow::from(FLOW_RELATION),
+            vec![
+                (Cow::from("exporter"), Self::ip_record(&self.exporter)),
+                (
+                    Cow::from("protocol"),
+                    Record::NamedStruct(Cow::from(protocol), Vec::new()),
+                ),
+                (
+                    Cow::from("observation_domain"),
+                    int(self.observation_domain.into()),
+                ),
+                (Cow::from("src_addr"), Self::ip_record(&self.src_addr)),
+                (Cow::from("dst_addr"), Self::ip_record(&self.dst_addr)),
+                (Cow::from("src_port"), int(self.src_port.into())),
+                (Cow::from("dst_port"), int(self.dst_port.into())),
+                (Cow::from("ip_proto"), int(self.ip_proto.into())),
+                (Cow::from("tcp_flags"), int(self.tcp_flags.into())),
+                (Cow::from("tos"), int(self.tos.into())),
+                (Cow::from("input_if"), int(self.input_if.into())),
+                (Cow::from("output_if"), int(self.output_if.into())),
+                (Cow::from("packets"), int(self.packets)),
+                (Cow::from("bytes"), int(self.bytes)),
+                (Cow::from("start_ms"), int(self.start_ms)),
+                (Cow::from("end_ms"), int(self.end_ms)),
+                (Cow::from("sampling_rate"), int(self.sampling_rate.into())),
+            ],
+        )
+    }
+}
+
+/* Big-endian reader over a datagram. */
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < n {
+            return Err("truncated datagram".to_string());
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(uint(self.bytes(2)?) as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(uint(self.bytes(4)?) as u32)
+    }
+
+    fn ip(&mut self, len: usize) -> Result<IpAddr, String> {
+        let bytes = self.bytes(len)?;
+        Ok(match len {
+            4 => IpAddr::from(<[u8; 4]>::try_from(bytes).unwrap()),
+            _ => IpAddr::from(<[u8; 16]>::try_from(bytes).unwrap()),
+        })
+    }
+
+    /* A sub-reader over the next `n` bytes. */
+    fn sub(&mut self, n: usize) -> Result<Reader<'a>, String> {
+        self.bytes(n).map(Reader::new)
+    }
+}
+
+/* Big-endian unsigned integer of up to 8 bytes; longer fields keep their
+ * low-order bytes. */
+fn uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |n, b| (n << 8) | u64::from(*b))
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A NetFlow v9 template: field types and lengths.
+type Template = Vec<(u16, u16)>;
+
+/// Decodes datagrams, keeping the NetFlow v9 templates of all exporters.
+#[derive(Debug, Default)]
+pub struct FlowDecoder {
+    /// Templates by exporter, source id, and template id.
+    templates: HashMap<(IpAddr, u32, u16), Template>,
+    /// Data records dropped because their template was unknown.
+    no_template: u64,
+}
+
+impl FlowDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of NetFlow v9 data records dropped so far because their
+    /// template had not been received.
+    pub fn no_template(&self) -> u64 {
+        self.no_template
+    }
+
+    /// Decode a datagram received from `exporter`.
+    pub fn decode(&mut self, exporter: IpAddr, data: &[u8]) -> Result<Vec<FlowRecord>, String> {
+        if data.len() >= 4 && uint(&data[..4]) == 5 {
+            return decode_sflow(data);
+        }
+        match data.get(..2).map(uint) {
+            Some(5) => decode_netflow_v5(exporter, data),
+            Some(9) => self.decode_netflow_v9(exporter, data),
+            Some(version) => Err(format!("unsupported flow export version {}", version)),
+            None => Err("truncated datagram".to_string()),
+        }
+    }
+
+    fn decode_netflow_v9(
+        &mut self,
+        exporter: IpAddr,
+        data: &[u8],
+    ) -> Result<Vec<FlowRecord>, String> {
+        let mut r = Reader::new(data);
+        r.bytes(4)?; // version and count
+        let uptime = u64::from(r.u32()?);
+        let unix_secs = u64::from(r.u32()?);
+        r.u32()?; // sequence
+        let source_id = r.u32()?;
+        let boot_ms = (unix_secs * 1000).saturating_sub(uptime);
+
+        let mut flows = Vec::new();
+        while r.remaining() >= 4 {
+            let flowset_id = r.u16()?;
+            let length = r.u16()? as usize;
+            if length < 4 {
+                return Err(format!("invalid flowset length {}", length));
+            }
+            let mut set = r.sub(length - 4)?;
+            match flowset_id {
+                0 => {
+                    while set.remaining() >= 4 {
+                        let template_id = set.u16()?;
+                        let field_count = set.u16()?;
+                        let mut template = Template::new();
+                        for _ in 0..field_count {
+                            template.push((set.u16()?, set.u16()?));
+                        }
+                        self.templates
+                            .insert((exporter, source_id, template_id), template);
+                    }
+                }
+                // Options templates.
+                1..=255 => (),
+                template_id => {
+                    let template = match self.templates.get(&(exporter, source_id, template_id)) {
+                        Some(template) => template,
+                        None => {
+                            self.no_template += 1;
+                            continue;
+                        }
+                    };
+                    let record_len: usize = template.iter().map(|(_, len)| *len as usize).sum();
+                    if record_len == 0 {
+                        continue;
+                    }
+                    // Records are followed by padding shorter than a record.
+                    while set.remaining() >= record_len {
+                        let mut flow =
+                            FlowRecord::new(exporter, FlowExportProtocol::NetFlowV9, source_id);
+                        for (field_type, len) in template.iter() {
+                            let value = set.bytes(*len as usize)?;
+                            v9_field(&mut flow, *field_type, value, boot_ms);
+                        }
+                        flows.push(flow);
+                    }
+                }
+            }
+        }
+        Ok(flows)
+    }
+}
+
+fn v9_field(flow: &mut FlowRecord, field_type: u16, value: &[u8], boot_ms: u64) {
+    let n = uint(value);
+    match (field_type, value.len()) {
+        (1, _) => flow.bytes = n,
+        (2, _) => flow.packets = n,
+        (4, _) => flow.ip_proto = n as u8,
+        (5, _) => flow.tos = n as u8,
+        (6, _) => flow.tcp_flags = n as u8,
+        (7, _) => flow.src_port = n as u16,
+        (8, 4) | (27, 16) => flow.src_addr = Reader::new(value).ip(value.len()).unwrap(),
+        (10, _) => flow.input_if = n as u32,
+        (11, _) => flow.dst_port = n as u16,
+        (12, 4) | (28, 16) => flow.dst_addr = Reader::new(value).ip(value.len()).unwrap(),
+        (14, _) => flow.output_if = n as u32,
+        (21, _) => flow.end_ms = boot_ms + n,
+        (22, _) => flow.start_ms = boot_ms + n,
+        (34, _) if n > 0 => flow.sampling_rate = n as u32,
+        _ => (),
+    }
+}
+
+fn decode_netflow_v5(exporter: IpAddr, data: &[u8]) -> Result<Vec<FlowRecord>, String> {
+    let mut r = Reader::new(data);
+    r.u16()?; // version
+    let count = r.u16()?;
+    let uptime = u64::from(r.u32()?);
+    let unix_secs = u64::from(r.u32()?);
+    let unix_nsecs = u64::from(r.u32()?);
+    r.u32()?; // sequence
+    let engine = u32::from(r.u16()?);
+    // The two high bits are the sampling mode.
+    let sampling_rate = u32::from(r.u16()? & 0x3fff).max(1);
+    let boot_ms = (unix_secs * 1000 + unix_nsecs / 1_000_000).saturating_sub(uptime);
+
+    let mut flows = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let mut flow = FlowRecord::new(exporter, FlowExportProtocol::NetFlowV5, engine);
+        flow.src_addr = r.ip(4)?;
+        flow.dst_addr = r.ip(4)?;
+        r.u32()?; // next hop
+        flow.input_if = u32::from(r.u16()?);
+        flow.output_if = u32::from(r.u16()?);
+        flow.packets = u64::from(r.u32()?);
+        flow.bytes = u64::from(r.u32()?);
+        flow.start_ms = boot_ms + u64::from(r.u32()?);
+        flow.end_ms = boot_ms + u64::from(r.u32()?);
+        flow.src_port = r.u16()?;
+        flow.dst_port = r.u16()?;
+        r.u8()?; // padding
+        flow.tcp_flags = r.u8()?;
+        flow.ip_proto = r.u8()?;
+        flow.tos = r.u8()?;
+        r.bytes(8)?; // AS numbers, masks, and padding
+        flow.sampling_rate = sampling_rate;
+        flows.push(flow);
+    }
+    Ok(flows)
+}
+
+fn decode_sflow(data: &[u8]) -> Result<Vec<FlowRecord>, String> {
+    let mut r = Reader::new(data);
+    r.u32()?; // version
+    let agent = match r.u32()? {
+        1 => r.ip(4)?,
+        2 => r.ip(16)?,
+        t => return Err(format!("invalid sFlow agent address type {}", t)),
+    };
+    r.bytes(12)?; // sub-agent id, sequence, and uptime
+    let samples = r.u32()?;
+    let now = now_ms();
+
+    let mut flows = Vec::new();
+    for _ in 0..samples {
+        let format = r.u32()?;
+        let length = r.u32()? as usize;
+        let mut sample = r.sub(length)?;
+        let (source, sampling_rate, input_if, output_if) = match format {
+            // Flow sample.
+            1 => {
+                sample.u32()?; // sequence
+                let source = sample.u32()?;
+                let rate = sample.u32()?;
+                sample.bytes(8)?; // sample pool and drops
+                let input = sample.u32()? & 0x3fff_ffff;
+                let output = sample.u32()? & 0x3fff_ffff;
+                (source, rate, input, output)
+            }
+            // Expanded flow sample.
+            3 => {
+                sample.u32()?; // sequence
+                let source = (sample.u32()? << 24) | (sample.u32()? & 0x00ff_ffff);
+                let rate = sample.u32()?;
+                sample.bytes(12)?; // sample pool, drops, and input format
+                let input = sample.u32()?;
+                sample.u32()?; // output format
+                let output = sample.u32()?;
+                (source, rate, input, output)
+            }
+            // Counter samples and unknown formats.
+            _ => continue,
+        };
+        let mut flow = FlowRecord::new(agent, FlowExportProtocol::SFlowV5, source);
+        flow.sampling_rate = sampling_rate.max(1);
+        flow.input_if = input_if;
+        flow.output_if = output_if;
+        flow.packets = 1;
+        flow.start_ms = now;
+        flow.end_ms = now;
+        let mut decoded = false;
+        let records = sample.u32()?;
+        for _ in 0..records {
+            let format = sample.u32()?;
+            let length = sample.u32()? as usize;
+            let mut record = sample.sub(length)?;
+            decoded |= match format {
+                1 => sflow_raw_header(&mut flow, &mut record)?,
+                3 => sflow_sampled_ip(&mut flow, &mut record, 4)?,
+                4 => sflow_sampled_ip(&mut flow, &mut record, 16)?,
+                _ => false,
+            };
+        }
+        if decoded {
+            flows.push(flow);
+        }
+    }
+    Ok(flows)
+}
+
+/* Sampled IPv4 or IPv6 record. */
+fn sflow_sampled_ip(
+    flow: &mut FlowRecord,
+    r: &mut Reader,
+    addr_len: usize,
+) -> Result<bool, String> {
+    flow.bytes = u64::from(r.u32()?);
+    flow.ip_proto = r.u32()? as u8;
+    flow.src_addr = r.ip(addr_len)?;
+    flow.dst_addr = r.ip(addr_len)?;
+    flow.src_port = r.u32()? as u16;
+    flow.dst_port = r.u32()? as u16;
+    flow.tcp_flags = r.u32()? as u8;
+    flow.tos = r.u32()? as u8;
+    Ok(true)
+}
+
+/* Raw packet header record; only Ethernet headers are decoded. */
+fn sflow_raw_header(flow: &mut FlowRecord, r: &mut Reader) -> Result<bool, String> {
+    let protocol = r.u32()?;
+    let frame_length = r.u32()?;
+    r.u32()?; // stripped
+    let header_length = r.u32()? as usize;
+    let header = r.bytes(header_length)?;
+    if protocol != 1 {
+        return Ok(false);
+    }
+    flow.bytes = u64::from(frame_length);
+    Ok(decode_ethernet(flow, header).is_ok())
+}
+
+fn decode_ethernet(flow: &mut FlowRecord, frame: &[u8]) -> Result<(), String> {
+    let mut r = Reader::new(frame);
+    r.bytes(12)?; // MAC addresses
+    let mut ethertype = r.u16()?;
+    while ethertype == 0x8100 || ethertype == 0x88a8 {
+        r.u16()?; // VLAN tag
+        ethertype = r.u16()?;
+    }
+    let transport = match ethertype {
+        0x0800 => {
+            let version_ihl = r.u8()?;
+            flow.tos = r.u8()?;
+            r.bytes(7)?; // length, id, fragment, and TTL
+            flow.ip_proto = r.u8()?;
+            r.u16()?; // checksum
+            flow.src_addr = r.ip(4)?;
+            flow.dst_addr = r.ip(4)?;
+            let options = ((version_ihl & 0x0f) as usize * 4).saturating_sub(20);
+            r.bytes(options)?;
+            flow.ip_proto
+        }
+        0x86dd => {
+            let first = r.u32()?;
+            flow.tos = ((first >> 20) & 0xff) as u8;
+            r.u16()?; // payload length
+            flow.ip_proto = r.u8()?;
+            r.u8()?; // hop limit
+            flow.src_addr = r.ip(16)?;
+            flow.dst_addr = r.ip(16)?;
+            flow.ip_proto
+        }
+        t => return Err(format!("unsupported ethertype {:#x}", t)),
+    };
+    // TCP and UDP ports; the header may be truncated after the IP header.
+    if transport == 6 || transport == 17 {
+        if let (Ok(src), Ok(dst)) = (r.u16(), r.u16()) {
+            flow.src_port = src;
+            flow.dst_port = dst;
+        }
+        if transport == 6 {
+            // Sequence and acknowledgment numbers and data offset.
+            if let Ok(flags) = r.bytes(9).and_then(|_| r.u8()) {
+                flow.tcp_flags = flags;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Counters of a running collector.
+#[derive(Debug, Default)]
+pub struct FlowStats {
+    pub datagrams: AtomicU64,
+    pub flows: AtomicU64,
+    /// Datagrams that could not be decoded.
+    pub errors: AtomicU64,
+    /// NetFlow v9 data records dropped because their template was unknown.
+    pub no_template: AtomicU64,
+}
+
+/// A running flow collector.  Dropping it closes the socket.
+pub struct FlowCollector {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    stats: Arc<FlowStats>,
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+fn insert(hddlog: &HDDlog, client: ClientId, flows: &mut Vec<UpdCmd>) -> Result<(), String> {
+    if flows.is_empty() {
+        return Ok(());
+    }
+    let txn = hddlog.try_start_transaction(client, None)?;
+    txn.apply_updates_dynamic(&mut flows.drain(..))?;
+    txn.commit()
+}
+
+impl FlowCollector {
+    /// Receive flows for `hddlog` on `addr`, e.g., `0.0.0.0:2055`.
+    pub fn start(
+        hddlog: Arc<HDDlog>,
+        addr: &str,
+        config: FlowCollectorConfig,
+    ) -> Result<Self, String> {
+        Relations::try_from(FLOW_RELATION)
+            .map_err(|()| "the program does not import the net::flow library".to_string())?;
+        let socket =
+            UdpSocket::bind(addr).map_err(|e| format!("failed to bind {}: {}", addr, e))?;
+        let addr = socket.local_addr().map_err(|e| e.to_string())?;
+        socket
+            .set_read_timeout(Some(config.flush_interval))
+            .map_err(|e| e.to_string())?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stats = Arc::new(FlowStats::default());
+        let last_error = Arc::new(Mutex::new(None));
+        let thread = {
+            let stop = stop.clone();
+            let stats = stats.clone();
+            let last_error = last_error.clone();
+            thread::Builder::new()
+                .name("ddlog-flows".to_string())
+                .spawn(move || {
+                    let mut decoder = FlowDecoder::new();
+                    let mut buf = vec![0u8; 65536];
+                    let mut pending = Vec::new();
+                    let mut last_flush = Instant::now();
+                    let relation = RelIdentifier::RelName(Cow::from(FLOW_RELATION));
+                    while !stop.load(Ordering::Acquire) {
+                        match socket.recv_from(&mut buf) {
+                            Ok((len, from)) => {
+                                stats.datagrams.fetch_add(1, Ordering::Relaxed);
+                                match decoder.decode(from.ip(), &buf[..len]) {
+                                    Ok(flows) => {
+                                        stats
+                                            .flows
+                                            .fetch_add(flows.len() as u64, Ordering::Relaxed);
+                                        pending.extend(flows.iter().map(|flow| {
+                                            UpdCmd::Insert(relation.clone(), flow.to_record())
+                                        }));
+                                    }
+                                    Err(e) => {
+                                        stats.errors.fetch_add(1, Ordering::Relaxed);
+                                        *last_error.lock().unwrap() =
+                                            Some(format!("invalid datagram from {}: {}", from, e));
+                                    }
+                                }
+                                stats
+                                    .no_template
+                                    .store(decoder.no_template(), Ordering::Relaxed);
+                            }
+                            Err(e)
+                                if e.kind() == ErrorKind::WouldBlock
+                                    || e.kind() == ErrorKind::TimedOut => {}
+                            Err(e) => {
+                                *last_error.lock().unwrap() =
+                                    Some(format!("failed to receive datagram: {}", e));
+                            }
+                        }
+                        if last_flush.elapsed() >= config.flush_interval {
+                            last_flush = Instant::now();
+                            if let Err(e) = insert(&hddlog, config.client, &mut pending) {
+                                pending.clear();
+                                *last_error.lock().unwrap() = Some(e);
+                            }
+                        }
+                    }
+                    let _ = insert(&hddlog, config.client, &mut pending);
+                })
+                .map_err(|e| format!("failed to start flow collector: {}", e))?
+        };
+        Ok(Self {
+            addr,
+            stop,
+            thread: Some(thread),
+            stats,
+            last_error,
+        })
+    }
+
+    /// The address the collector listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn stats(&self) -> &FlowStats {
+        &self.stats
+    }
+
+    /// The last error encountered, if any.
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+}
+
+impl Drop for FlowCollector {
+    fn drop(&mut self) {
+        // The thread notices within one flush interval.
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORTER: [u8; 4] = [192, 0, 2, 1];
+
+    fn put16(buf: &mut Vec<u8>, n: u16) {
+        buf.extend_from_slice(&n.to_be_bytes());
+    }
+
+    fn put32(buf: &mut Vec<u8>, n: u32) {
+        buf.extend_from_slice(&n.to_be_bytes());
+    }
+
+    fn exporter() -> IpAddr {
+        IpAddr::from(EXPORTER)
+    }
+
+    /// A NetFlow v5 datagram announcing `count` records, of which it
+    /// contains `records`.
+    fn netflow_v5(count: u16, records: usize) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put16(&mut buf, 5);
+        put16(&mut buf, count);
+        put32(&mut buf, 1000); // uptime
+        put32(&mut buf, 10); // seconds
+        put32(&mut buf, 0); // nanoseconds
+        put32(&mut buf, 7); // sequence
+        buf.extend_from_slice(&[1, 2]); // engine type and id
+        put16(&mut buf, 0x4000 | 10); // sampling mode and interval
+        for i in 0..records {
+            buf.extend_from_slice(&[10, 0, 0, 1]);
+            buf.extend_from_slice(&[10, 0, 0, 2]);
+            buf.extend_from_slice(&[0; 4]); // next hop
+            put16(&mut buf, 3);
+            put16(&mut buf, 4);
+            put32(&mut buf, 5); // packets
+            put32(&mut buf, 1500 + i as u32); // bytes
+            put32(&mut buf, 100); // first
+            put32(&mut buf, 600); // last
+            put16(&mut buf, 40000);
+            put16(&mut buf, 443);
+            buf.extend_from_slice(&[0, 0x12, 6, 0x20]);
+            buf.extend_from_slice(&[0; 8]);
+        }
+        buf
+    }
+
+    fn netflow_v5_flow(bytes: u64) -> FlowRecord {
+        let mut flow = FlowRecord::new(exporter(), FlowExportProtocol::NetFlowV5, 0x0102);
+        flow.src_addr = IpAddr::from([10, 0, 0, 1]);
+        flow.dst_addr = IpAddr::from([10, 0, 0, 2]);
+        flow.src_port = 40000;
+        flow.dst_port = 443;
+        flow.ip_proto = 6;
+        flow.tcp_flags = 0x12;
+        flow.tos = 0x20;
+        flow.input_if = 3;
+        flow.output_if = 4;
+        flow.packets = 5;
+        flow.bytes = bytes;
+        // Booted 1s before the export at 10s.
+        flow.start_ms = 9100;
+        flow.end_ms = 9600;
+        flow.sampling_rate = 10;
+        flow
+    }
+
+    /// A NetFlow v9 datagram with source id 42 and the given flowsets.
+    fn netflow_v9(flowsets: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put16(&mut buf, 9);
+        put16(&mut buf, flowsets.len() as u16);
+        put32(&mut buf, 1000); // uptime
+        put32(&mut buf, 10); // seconds
+        put32(&mut buf, 7); // sequence
+        put32(&mut buf, 42); // source id
+        for (id, contents) in flowsets {
+            put16(&mut buf, *id);
+            put16(&mut buf, contents.len() as u16 + 4);
+            buf.extend_from_slice(contents);
+        }
+        buf
+    }
+
+    const V9_FIELDS: &[(u16, u16)] = &[
+        (8, 4),  // source address
+        (12, 4), // destination address
+        (7, 2),  // source port
+        (11, 2), // destination port
+        (1, 4),  // bytes
+        (2, 4),  // packets
+        (4, 1),  // protocol
+        (22, 4), // first switched
+        (21, 4), // last switched
+    ];
+
+    fn v9_template(id: u16) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put16(&mut buf, id);
+        put16(&mut buf, V9_FIELDS.len() as u16);
+        for (field_type, len) in V9_FIELDS {
+            put16(&mut buf, *field_type);
+            put16(&mut buf, *len);
+        }
+        buf
+    }
+
+    /// Data records of the template in `v9_template()`, padded to a multiple
+    /// of 4 bytes.
+    fn v9_data(bytes: &[u32]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for bytes in bytes {
+            buf.extend_from_slice(&[10, 0, 0, 1]);
+            buf.extend_from_slice(&[10, 0, 0, 2]);
+            put16(&mut buf, 53);
+            put16(&mut buf, 5353);
+            put32(&mut buf, *bytes);
+            put32(&mut buf, 1);
+            buf.push(17);
+            put32(&mut buf, 200);
+            put32(&mut buf, 300);
+        }
+        while buf.len() % 4 != 0 {
+            buf.push(0);
+        }
+        buf
+    }
+
+    fn netflow_v9_flow(bytes: u64) -> FlowRecord {
+        let mut flow = FlowRecord::new(exporter(), FlowExportProtocol::NetFlowV9, 42);
+        flow.src_addr = IpAddr::from([10, 0, 0, 1]);
+        flow.dst_addr = IpAddr::from([10, 0, 0, 2]);
+        flow.src_port = 53;
+        flow.dst_port = 5353;
+        flow.ip_proto = 17;
+        flow.packets = 1;
+        flow.bytes = bytes;
+        flow.start_ms = 9200;
+        flow.end_ms = 9300;
+        flow
+    }
+
+    /// An sFlow v5 datagram from agent 198.51.100.1 with the given samples.
+    fn sflow(agent_type: u32, samples: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put32(&mut buf, 5);
+        put32(&mut buf, agent_type);
+        buf.extend_from_slice(&[198, 51, 100, 1]);
+        put32(&mut buf, 0); // sub-agent id
+        put32(&mut buf, 7); // sequence
+        put32(&mut buf, 1000); // uptime
+        put32(&mut buf, samples.len() as u32);
+        for (format, contents) in samples {
+            put32(&mut buf, *format);
+            put32(&mut buf, contents.len() as u32);
+            buf.extend_from_slice(contents);
+        }
+        buf
+    }
+
+    /// A flow sample from source 7, sampled one out of 100, with one record.
+    fn sflow_flow_sample(format: u32, record: Vec<u8>) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put32(&mut buf, 1); // sequence
+        put32(&mut buf, 7); // source
+        put32(&mut buf, 100); // sampling rate
+        put32(&mut buf, 1000); // sample pool
+        put32(&mut buf, 0); // drops
+        put32(&mut buf, 3); // input
+        put32(&mut buf, 4); // output
+        put32(&mut buf, 1);
+        put32(&mut buf, format);
+        put32(&mut buf, record.len() as u32);
+        buf.extend(record);
+        buf
+    }
+
+    fn sampled_ipv4() -> Vec<u8> {
+        let mut buf = Vec::new();
+        put32(&mut buf, 1500); // length
+        put32(&mut buf, 6); // protocol
+        buf.extend_from_slice(&[10, 0, 0, 1]);
+        buf.extend_from_slice(&[10, 0, 0, 2]);
+        put32(&mut buf, 40000);
+        put32(&mut buf, 443);
+        put32(&mut buf, 0x12); // TCP flags
+        put32(&mut buf, 0x20); // ToS
+        buf
+    }
+
+    /// Raw header record of a VLAN-tagged Ethernet frame with a TCP segment.
+    fn raw_ethernet_header() -> Vec<u8> {
+        let mut frame = vec![0; 12]; // MAC addresses
+        put16(&mut frame, 0x8100);
+        put16(&mut frame, 100); // VLAN tag
+        put16(&mut frame, 0x0800);
+        frame.extend_from_slice(&[0x45, 0x20]); // version, IHL, and ToS
+        frame.extend_from_slice(&[0; 7]); // length, id, fragment, and TTL
+        frame.push(6);
+        put16(&mut frame, 0); // checksum
+        frame.extend_from_slice(&[10, 0, 0, 1]);
+        frame.extend_from_slice(&[10, 0, 0, 2]);
+        put16(&mut frame, 40000);
+        put16(&mut frame, 443);
+        frame.extend_from_slice(&[0; 8]); // sequence and acknowledgment
+        frame.extend_from_slice(&[0x50, 0x12]); // data offset and flags
+        put16(&mut frame, 65535); // window
+
+        let mut buf = Vec::new();
+        put32(&mut buf, 1); // Ethernet
+        put32(&mut buf, 1514); // frame length
+        put32(&mut buf, 4); // stripped
+        put32(&mut buf, frame.len() as u32);
+        buf.extend(frame);
+        buf
+    }
+
+    fn sflow_flow() -> FlowRecord {
+        let mut flow = FlowRecord::new(
+            IpAddr::from([198, 51, 100, 1]),
+            FlowExportProtocol::SFlowV5,
+            7,
+        );
+        flow.src_addr = IpAddr::from([10, 0, 0, 1]);
+        flow.dst_addr = IpAddr::from([10, 0, 0, 2]);
+        flow.src_port = 40000;
+        flow.dst_port = 443;
+        flow.ip_proto = 6;
+        flow.tcp_flags = 0x12;
+        flow.tos = 0x20;
+        flow.input_if = 3;
+        flow.output_if = 4;
+        flow.packets = 1;
+        flow.sampling_rate = 100;
+        flow
+    }
+
+    #[test]
+    fn decode_netflow_v5_records() {
+        let mut decoder = FlowDecoder::new();
+        assert_eq!(
+            decoder.decode(exporter(), &netflow_v5(2, 2)).unwrap(),
+            vec![netflow_v5_flow(1500), netflow_v5_flow(1501)]
+        );
+        assert_eq!(
+            decoder.decode(exporter(), &netflow_v5(2, 1)).unwrap_err(),
+            "truncated datagram"
+        );
+    }
+
+    #[test]
+    fn decode_netflow_v9_records() {
+        let mut decoder = FlowDecoder::new();
+        // Data records that precede their template are dropped.
+        assert!(decoder
+            .decode(exporter(), &netflow_v9(&[(256, v9_data(&[100]))]))
+            .unwrap()
+            .is_empty());
+        assert_eq!(decoder.no_template(), 1);
+
+        let datagram = netflow_v9(&[
+            (0, v9_template(256)),
+            // Options templates are ignored.
+            (1, vec![0; 8]),
+            (256, v9_data(&[100, 200])),
+        ]);
+        assert_eq!(
+            decoder.decode(exporter(), &datagram).unwrap(),
+            vec![netflow_v9_flow(100), netflow_v9_flow(200)]
+        );
+        assert_eq!(
+            decoder
+                .decode(exporter(), &netflow_v9(&[(256, v9_data(&[300]))]))
+                .unwrap(),
+            vec![netflow_v9_flow(300)]
+        );
+
+        // Templates are per exporter.
+        assert!(decoder
+            .decode(
+                IpAddr::from([192, 0, 2, 2]),
+                &netflow_v9(&[(256, v9_data(&[100]))])
+            )
+            .unwrap()
+            .is_empty());
+        assert_eq!(decoder.no_template(), 2);
+    }
+
+    #[test]
+    fn decode_sflow_samples() {
+        let mut decoder = FlowDecoder::new();
+        let datagram = sflow(
+            1,
+            &[
+                (1, sflow_flow_sample(3, sampled_ipv4())),
+                // Counter samples are ignored.
+                (2, vec![0; 8]),
+                (1, sflow_flow_sample(1, raw_ethernet_header())),
+            ],
+        );
+        let flows = decoder.decode(exporter(), &datagram).unwrap();
+        assert_eq!(flows.len(), 2);
+        for (flow, bytes) in flows.iter().zip(&[1500, 1514]) {
+            // Samples are timestamped on arrival.
+            assert_eq!(flow.start_ms, flow.end_ms);
+            assert_eq!(
+                flow,
+                &FlowRecord {
+                    bytes: *bytes,
+                    start_ms: flow.start_ms,
+                    end_ms: flow.end_ms,
+                    ..sflow_flow()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn decode_errors() {
+        let mut decoder = FlowDecoder::new();
+        assert_eq!(
+            decoder.decode(exporter(), &[0]).unwrap_err(),
+            "truncated datagram"
+        );
+        assert_eq!(
+            decoder.decode(exporter(), &[0, 10, 0, 0]).unwrap_err(),
+            "unsupported flow export version 10"
+        );
+        assert_eq!(
+            decoder
+                .decode(exporter(), &netflow_v5(1, 1)[..40])
+                .unwrap_err(),
+            "truncated datagram"
+        );
+
+        let mut datagram = netflow_v9(&[]);
+        put16(&mut datagram, 256);
+        put16(&mut datagram, 2);
+        assert_eq!(
+            decoder.decode(exporter(), &datagram).unwrap_err(),
+            "invalid flowset length 2"
+        );
+        let mut datagram = netflow_v9(&[(0, v9_template(256))]);
+        datagram.truncate(datagram.len() - 2);
+        assert_eq!(
+            decoder.decode(exporter(), &datagram).unwrap_err(),
+            "truncated datagram"
+        );
+
+        assert_eq!(
+            decoder.decode(exporter(), &sflow(3, &[])).unwrap_err(),
+            "invalid sFlow agent address type 3"
+        );
+        let mut datagram = sflow(1, &[(1, sflow_flow_sample(3, sampled_ipv4()))]);
+        datagram.truncate(datagram.len() - 4);
+        assert_eq!(
+            decoder.decode(exporter(), &datagram).unwrap_err(),
+            "truncated datagram"
+        );
+    }
+
+    #[test]
+    fn flow_records() {
+        let record = netflow_v5_flow(1500).to_record().to_string();
+        assert!(
+            record.starts_with(
+                "net::flow::Flow{.exporter = net::ipaddr::IpAddrV4{.addr4 = 3221225985}, \
+                 .protocol = net::flow::NetFlowV5{}, .observation_domain = 258, "
+            ),
+            "{}",
+            record
+        );
+        assert!(
+            record.ends_with(".packets = 5, .bytes = 1500, .start_ms = 9100, .end_ms = 9600, .sampling_rate = 10}"),
+            "{}",
+            record
+        );
+    }
+}
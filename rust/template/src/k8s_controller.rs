@@ -0,0 +1,615 @@
+//! Kubernetes controllers driven by DDlog rules.
+//!
+//! `Controller` turns a program that imports the `k8s` library (see
+//! `lib/k8s.dl`) into the rules engine of a Kubernetes operator:
+//!
+//! * The resources selected by `ControllerConfig::watches` are watched with
+//!   kube-rs and mirrored into the `k8s::Object` input relation.  Events are
+//!   batched into transactions, which are started with
+//!   `HDDlog::try_start_transaction()` so that the controller shares the
+//!   program fairly with other clients.  When a watch is restarted, objects
+//!   that no longer exist are removed.
+//! * The changes to the `k8s::DesiredObject` output relation made by each
+//!   commit are applied to the cluster with server-side apply, using
+//!   `ControllerConfig::field_manager` as the field manager.  When
+//!   `ControllerConfig::prune` is set, resources whose desired object is
+//!   removed are deleted.
+//!
+//! Resource kinds are resolved through API discovery, so any built-in or
+//! custom resource can be watched or applied.  The controller runs the
+//! watches and patches on its own Tokio runtime and never blocks commits on
+//! the API server.  Errors are retried with backoff by the watches and
+//! reported by `Controller::last_error()`; failed patches are not retried
+//! until the desired object changes again.  Requires the `kubernetes`
+//! feature.
+//!
+//! ```ignore
+//! let hddlog = Arc::new(HDDlog::run(1, false)?.0);
+//! let controller = Controller::start(
+//!     hddlog.clone(),
+//!     ControllerConfig {
+//!         watches: vec![WatchSpec::new("example.com/v1", "App")],
+//!         ..ControllerConfig::default()
+//!     },
+//! )?;
+//! ```
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crossbeam_channel::{Receiver, Sender};
+use futures::StreamExt;
+use kube::api::{Api, DeleteParams, DynamicObject, Patch, PatchParams};
+use kube::core::GroupVersionKind;
+use kube::discovery::{self, ApiCapabilities, ApiResource, Scope};
+use kube::runtime::watcher::{self, Event};
+use kube::runtime::WatchStreamExt;
+use kube::Client;
+use serde_json::Value;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+
+use differential_datalog::program::RelId;
+use differential_datalog::record::{IntoRecord, Record, RelIdentifier, UpdCmd};
+use differential_datalog::scheduler::ClientId;
+
+use crate::api::{CommitSubscriptionId, HDDlog};
+use crate::update_handler::ChangelogBatch;
+use crate::Relations;
+
+/// Names of the relations declared by the `k8s` library.
+const OBJECT_RELATION: &str = "k8s::Object";
+const DESIRED_RELATION: &str = "k8s::DesiredObject";
+
+/// A set of resources mirrored into `k8s::Object`.
+#[derive(Debug, Clone)]
+pub struct WatchSpec {
+    /// API group and version, e.g., `apps/v1` or `v1`.
+    pub api_version: String,
+    pub kind: String,
+    /// Watch only this namespace; by default, all namespaces.
+    pub namespace: Option<String>,
+    /// Watch only resources matching this label selector.
+    pub label_selector: Option<String>,
+}
+
+impl WatchSpec {
+    /// Watch all resources of `kind`.
+    pub fn new(api_version: &str, kind: &str) -> Self {
+        Self {
+            api_version: api_version.to_string(),
+            kind: kind.to_string(),
+            namespace: None,
+            label_selector: None,
+        }
+    }
+}
+
+/// Configuration of a `Controller`.
+#[derive(Debug, Clone)]
+pub struct ControllerConfig {
+    pub watches: Vec<WatchSpec>,
+    /// Field manager of server-side apply patches.
+    pub field_manager: String,
+    /// Delete resources whose desired object is removed.
+    pub prune: bool,
+    /// Client id used to schedule the controller's transactions.
+    pub client: ClientId,
+}
+
+impl Default for ControllerConfig {
+    fn default() -> Self {
+        Self {
+            watches: Vec::new(),
+            field_manager: "ddlog".to_string(),
+            prune: false,
+            client: ClientId::max_value(),
+        }
+    }
+}
+
+/// Identifies a resource: API version, kind, namespace, and name.
+type ObjectKey = (String, String, String, String);
+
+enum WatchEvent {
+    Applied(usize, ObjectKey, String),
+    Deleted(usize, ObjectKey),
+    /// The watch was restarted with the current list of resources.
+    Restarted(usize, Vec<(ObjectKey, String)>),
+}
+
+/// A running controller.  Dropping it stops the watches and detaches the
+/// controller from the program.
+pub struct Controller {
+    hddlog: Arc<HDDlog>,
+    subscription: CommitSubscriptionId,
+    runtime: Option<Runtime>,
+    applier: Option<JoinHandle<()>>,
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+fn split_api_version(api_version: &str) -> (&str, &str) {
+    match api_version.find('/') {
+        Some(i) => (&api_version[..i], &api_version[i + 1..]),
+        None => ("", api_version),
+    }
+}
+
+async fn resolve(
+    client: &Client,
+    api_version: &str,
+    kind: &str,
+) -> Result<(ApiResource, ApiCapabilities), String> {
+    let (group, version) = split_api_version(api_version);
+    discovery::pinned_kind(client, &GroupVersionKind::gvk(group, version, kind))
+        .await
+        .map_err(|e| format!("failed to discover {} {}: {}", api_version, kind, e))
+}
+
+fn object_key(object: &DynamicObject, resource: &ApiResource) -> ObjectKey {
+    (
+        resource.api_version.clone(),
+        resource.kind.clone(),
+        object.metadata.namespace.clone().unwrap_or_default(),
+        object.metadata.name.clone().unwrap_or_default(),
+    )
+}
+
+/* The JSON text of an object without the field management metadata, which
+ * changes with every update and is of no interest to rules. */
+fn object_json(object: &DynamicObject) -> String {
+    let mut object = object.clone();
+    object.metadata.managed_fields = None;
+    serde_json::to_string(&object).unwrap_or_default()
+}
+
+fn string(s: &str) -> Record {
+    Record::String(s.to_string())
+}
+
+fn object_update(key: &ObjectKey, json: Option<&str>) -> UpdCmd {
+    let relation = RelIdentifier::RelName(Cow::from(OBJECT_RELATION));
+    match json {
+        Some(json) => UpdCmd::InsertOrUpdate(
+            relation,
+            Record::NamedStruct(
+                Cow::from(OBJECT_RELATION),
+                vec![
+                    (Cow::from("api_version"), string(&key.0)),
+                    (Cow::from("kind"), string(&key.1)),
+                    (Cow::from("namespace"), string(&key.2)),
+                    (Cow::from("name"), string(&key.3)),
+                    (Cow::from("object"), string(json)),
+                ],
+            ),
+        ),
+        None => UpdCmd::DeleteKey(
+            relation,
+            Record::Tuple(vec![
+                string(&key.0),
+                string(&key.1),
+                string(&key.2),
+                string(&key.3),
+            ]),
+        ),
+    }
+}
+
+async fn watch(
+    client: Client,
+    index: usize,
+    spec: WatchSpec,
+    events: Sender<WatchEvent>,
+    last_error: Arc<Mutex<Option<String>>>,
+) {
+    let (resource, caps) = match resolve(&client, &spec.api_version, &spec.kind).await {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            *last_error.lock().unwrap() = Some(e);
+            return;
+        }
+    };
+    let api: Api<DynamicObject> = match (&spec.namespace, &caps.scope) {
+        (Some(namespace), Scope::Namespaced) => Api::namespaced_with(client, namespace, &resource),
+        _ => Api::all_with(client, &resource),
+    };
+    let mut config = watcher::Config::default();
+    if let Some(selector) = &spec.label_selector {
+        config = config.labels(selector);
+    }
+    let mut stream = watcher(api, config).default_backoff().boxed();
+    while let Some(event) = stream.next().await {
+        let event = match event {
+            Ok(Event::Applied(object)) => {
+                WatchEvent::Applied(index, object_key(&object, &resource), object_json(&object))
+            }
+            Ok(Event::Deleted(object)) => {
+                WatchEvent::Deleted(index, object_key(&object, &resource))
+            }
+            Ok(Event::Restarted(objects)) => WatchEvent::Restarted(
+                index,
+                objects
+                    .iter()
+                    .map(|object| (object_key(object, &resource), object_json(object)))
+                    .collect(),
+            ),
+            Err(e) => {
+                *last_error.lock().unwrap() = Some(format!("watch of {} failed: {}", spec.kind, e));
+                continue;
+            }
+        };
+        if events.send(event).is_err() {
+            return;
+        }
+    }
+}
+
+/* Applies watch events to `k8s::Object`, batching all events that are
+ * available into one transaction. */
+fn apply_events(
+    hddlog: &HDDlog,
+    client: ClientId,
+    events: Receiver<WatchEvent>,
+    last_error: &Mutex<Option<String>>,
+) {
+    // Keys of the objects seen by each watch.
+    let mut known: HashMap<usize, BTreeSet<ObjectKey>> = HashMap::new();
+    while let Ok(event) = events.recv() {
+        let mut updates = Vec::new();
+        for event in std::iter::once(event).chain(events.try_iter()) {
+            match event {
+                WatchEvent::Applied(index, key, json) => {
+                    updates.push(object_update(&key, Some(&json)));
+                    known.entry(index).or_default().insert(key);
+                }
+                WatchEvent::Deleted(index, key) => {
+                    updates.push(object_update(&key, None));
+                    known.entry(index).or_default().remove(&key);
+                }
+                WatchEvent::Restarted(index, objects) => {
+                    let keys: BTreeSet<ObjectKey> =
+                        objects.iter().map(|(key, _)| key.clone()).collect();
+                    for key in known.entry(index).or_default().difference(&keys) {
+                        updates.push(object_update(key, None));
+                    }
+                    for (key, json) in objects.iter() {
+                        updates.push(object_update(key, Some(json)));
+                    }
+                    known.insert(index, keys);
+                }
+            }
+        }
+        let res = hddlog.try_start_transaction(client, None).and_then(|txn| {
+            txn.apply_updates_dynamic(&mut updates.into_iter())?;
+            txn.commit()
+        });
+        if let Err(e) = res {
+            *last_error.lock().unwrap() =
+                Some(format!("failed to update {}: {}", OBJECT_RELATION, e));
+        }
+    }
+}
+
+fn string_field(record: &Record, field: &str) -> Result<String, String> {
+    if let Record::NamedStruct(_, fields) = record {
+        for (name, value) in fields.iter() {
+            if name == field {
+                if let Record::String(s) = value {
+                    return Ok(s.clone());
+                }
+            }
+        }
+    }
+    Err(format!("{} has no string field {}", record, field))
+}
+
+/* Desired objects changed by a commit, given the records of the changes to
+ * `k8s::DesiredObject`: the manifest of objects to apply, or `None` for
+ * objects to delete. */
+fn desired_changes<I>(records: I) -> Result<BTreeMap<ObjectKey, Option<String>>, String>
+where
+    I: IntoIterator<Item = (Record, isize)>,
+{
+    let mut changes = BTreeMap::new();
+    for (record, weight) in records {
+        let key = (
+            string_field(&record, "api_version")?,
+            string_field(&record, "kind")?,
+            string_field(&record, "namespace")?,
+            string_field(&record, "name")?,
+        );
+        if weight > 0 {
+            changes.insert(key, Some(string_field(&record, "manifest")?));
+        } else {
+            // A deletion and an insertion of the same object in one commit
+            // is an update.
+            changes.entry(key).or_insert(None);
+        }
+    }
+    Ok(changes)
+}
+
+struct Applier {
+    client: Client,
+    field_manager: String,
+    prune: bool,
+    resources: HashMap<(String, String), (ApiResource, ApiCapabilities)>,
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+impl Applier {
+    async fn api(&mut self, key: &ObjectKey) -> Result<Api<DynamicObject>, String> {
+        let kind = (key.0.clone(), key.1.clone());
+        if !self.resources.contains_key(&kind) {
+            let resolved = resolve(&self.client, &key.0, &key.1).await?;
+            self.resources.insert(kind.clone(), resolved);
+        }
+        let (resource, caps) = &self.resources[&kind];
+        Ok(match caps.scope {
+            Scope::Namespaced => Api::namespaced_with(self.client.clone(), &key.2, resource),
+            Scope::Cluster => Api::all_with(self.client.clone(), resource),
+        })
+    }
+
+    async fn apply(&mut self, key: &ObjectKey, manifest: Option<&str>) -> Result<(), String> {
+        let api = self.api(key).await?;
+        match manifest {
+            Some(manifest) => {
+                let manifest: Value = serde_json::from_str(manifest)
+                    .map_err(|e| format!("invalid manifest of {}: {}", key.3, e))?;
+                let params = PatchParams::apply(&self.field_manager).force();
+                api.patch(&key.3, &params, &Patch::Apply(&manifest))
+                    .await
+                    .map_err(|e| format!("failed to apply {} {}: {}", key.1, key.3, e))?;
+            }
+            None if self.prune => {
+                api.delete(&key.3, &DeleteParams::default())
+                    .await
+                    .map_err(|e| format!("failed to delete {} {}: {}", key.1, key.3, e))?;
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    async fn run(mut self, mut batches: mpsc::UnboundedReceiver<ChangelogBatch>) {
+        while let Some(batch) = batches.recv().await {
+            let records = batch
+                .changes
+                .iter()
+                .map(|(value, weight)| (value.clone().into_record(), *weight));
+            let changes = match desired_changes(records) {
+                Ok(changes) => changes,
+                Err(e) => {
+                    *self.last_error.lock().unwrap() = Some(e);
+                    continue;
+                }
+            };
+            for (key, manifest) in changes.iter() {
+                if let Err(e) = self.apply(key, manifest.as_deref()).await {
+                    *self.last_error.lock().unwrap() = Some(e);
+                }
+            }
+        }
+    }
+}
+
+impl Controller {
+    /// Start a controller for `hddlog` with the configuration of the
+    /// Kubernetes client taken from the environment (the in-cluster service
+    /// account or the local kubeconfig).
+    pub fn start(hddlog: Arc<HDDlog>, config: ControllerConfig) -> Result<Self, String> {
+        let desired = match Relations::try_from(DESIRED_RELATION) {
+            Ok(rel) if Relations::try_from(OBJECT_RELATION).is_ok() => rel as RelId,
+            _ => return Err("the program does not import the k8s library".to_string()),
+        };
+        let runtime = Runtime::new().map_err(|e| format!("failed to start runtime: {}", e))?;
+        let client = runtime
+            .block_on(Client::try_default())
+            .map_err(|e| format!("failed to configure Kubernetes client: {}", e))?;
+        let last_error = Arc::new(Mutex::new(None));
+
+        let (events_tx, events_rx) = crossbeam_channel::unbounded();
+        for (index, spec) in config.watches.iter().enumerate() {
+            runtime.spawn(watch(
+                client.clone(),
+                index,
+                spec.clone(),
+                events_tx.clone(),
+                last_error.clone(),
+            ));
+        }
+        // The applier stops once all watches have stopped.
+        drop(events_tx);
+        let applier = {
+            let hddlog = hddlog.clone();
+            let last_error = last_error.clone();
+            let client_id = config.client;
+            thread::Builder::new()
+                .name("ddlog-k8s".to_string())
+                .spawn(move || apply_events(&hddlog, client_id, events_rx, &last_error))
+                .map_err(|e| format!("failed to start controller: {}", e))?
+        };
+
+        let (batches_tx, batches_rx) = mpsc::unbounded_channel();
+        runtime.spawn(
+            Applier {
+                client,
+                field_manager: config.field_manager.clone(),
+                prune: config.prune,
+                resources: HashMap::new(),
+                last_error: last_error.clone(),
+            }
+            .run(batches_rx),
+        );
+        let subscription = hddlog.subscribe_commits(
+            &[desired],
+            Arc::new(move |batches: &[ChangelogBatch]| {
+                for batch in batches {
+                    let _ = batches_tx.send(batch.clone());
+                }
+            }),
+        );
+        let subscription = match subscription {
+            Ok(subscription) => subscription,
+            Err(e) => {
+                runtime.shutdown_background();
+                let _ = applier.join();
+                return Err(e);
+            }
+        };
+        Ok(Self {
+            hddlog,
+            subscription,
+            runtime: Some(runtime),
+            applier: Some(applier),
+            last_error,
+        })
+    }
+
+    /// The last error encountered by a watch or patch, if any.
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+}
+
+impl Drop for Controller {
+    fn drop(&mut self) {
+        self.hddlog.unsubscribe_commits(self.subscription);
+        // Shutting down the runtime cancels the watches, which disconnects
+        // the event channel and stops the applier thread.
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+        if let Some(applier) = self.applier.take() {
+            let _ = applier.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desired(name: &str, manifest: Option<&str>) -> Record {
+        let mut fields = vec![
+            (Cow::from("api_version"), string("apps/v1")),
+            (Cow::from("kind"), string("Deployment")),
+            (Cow::from("namespace"), string("default")),
+            (Cow::from("name"), string(name)),
+        ];
+        if let Some(manifest) = manifest {
+            fields.push((Cow::from("manifest"), string(manifest)));
+        }
+        Record::NamedStruct(Cow::from(DESIRED_RELATION), fields)
+    }
+
+    fn key(name: &str) -> ObjectKey {
+        (
+            "apps/v1".to_string(),
+            "Deployment".to_string(),
+            "default".to_string(),
+            name.to_string(),
+        )
+    }
+
+    #[test]
+    fn api_versions() {
+        assert_eq!(split_api_version("apps/v1"), ("apps", "v1"));
+        assert_eq!(split_api_version("v1"), ("", "v1"));
+        assert_eq!(
+            split_api_version("example.com/v1beta1"),
+            ("example.com", "v1beta1")
+        );
+    }
+
+    #[test]
+    fn object_updates() {
+        let relation = RelIdentifier::RelName(Cow::from(OBJECT_RELATION));
+        assert_eq!(
+            object_update(&key("web"), Some("{}")),
+            UpdCmd::InsertOrUpdate(
+                relation.clone(),
+                Record::NamedStruct(
+                    Cow::from(OBJECT_RELATION),
+                    vec![
+                        (Cow::from("api_version"), string("apps/v1")),
+                        (Cow::from("kind"), string("Deployment")),
+                        (Cow::from("namespace"), string("default")),
+                        (Cow::from("name"), string("web")),
+                        (Cow::from("object"), string("{}")),
+                    ],
+                ),
+            )
+        );
+        // Objects are deleted by their primary key.
+        assert_eq!(
+            object_update(&key("web"), None),
+            UpdCmd::DeleteKey(
+                relation,
+                Record::Tuple(vec![
+                    string("apps/v1"),
+                    string("Deployment"),
+                    string("default"),
+                    string("web"),
+                ]),
+            )
+        );
+    }
+
+    #[test]
+    fn object_metadata() {
+        let resource = ApiResource {
+            group: "apps".to_string(),
+            version: "v1".to_string(),
+            api_version: "apps/v1".to_string(),
+            kind: "Deployment".to_string(),
+            plural: "deployments".to_string(),
+        };
+        let object: DynamicObject = serde_json::from_value(serde_json::json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": {
+                "name": "web",
+                "namespace": "default",
+                "managedFields": [{"manager": "kubectl"}],
+            },
+            "spec": {"replicas": 2},
+        }))
+        .unwrap();
+        assert_eq!(object_key(&object, &resource), key("web"));
+        let json: Value = serde_json::from_str(&object_json(&object)).unwrap();
+        assert!(json["metadata"].get("managedFields").is_none());
+        assert_eq!(json["spec"]["replicas"], 2);
+    }
+
+    #[test]
+    fn desired_object_changes() {
+        let changes = desired_changes(vec![
+            (desired("a", Some("{\"v\": 1}")), -1),
+            (desired("a", Some("{\"v\": 2}")), 1),
+            (desired("b", Some("{}")), -1),
+        ])
+        .unwrap();
+        assert_eq!(
+            changes.into_iter().collect::<Vec<_>>(),
+            vec![(key("a"), Some("{\"v\": 2}".to_string())), (key("b"), None),]
+        );
+    }
+
+    #[test]
+    fn malformed_desired_objects() {
+        assert_eq!(
+            desired_changes(vec![(desired("a", None), 1)]).unwrap_err(),
+            format!("{} has no string field manifest", desired("a", None))
+        );
+        // Deletions do not need a manifest.
+        assert!(desired_changes(vec![(desired("a", None), -1)]).is_ok());
+        assert!(string_field(&string("x"), "name")
+            .unwrap_err()
+            .ends_with("has no string field name"));
+    }
+}
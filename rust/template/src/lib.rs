@@ -50,9 +50,39 @@ use once_cell::sync::Lazy;
 
 use fnv::FnvHashMap;
 
+#[cfg(feature = "alerts")]
+pub mod alert_sink;
 pub mod api;
+#[cfg(feature = "bgp")]
+pub mod bgp_feed;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+#[cfg(feature = "command-line")]
+pub mod ddlog_testing;
+#[cfg(feature = "flows")]
+pub mod flow_collector;
+#[cfg(feature = "kubernetes")]
+pub mod k8s_controller;
+#[cfg(feature = "command-line")]
+pub mod notebook;
+#[cfg(feature = "otlp")]
+pub mod otlp;
 pub mod ovsdb_api;
+#[cfg(feature = "packet_capture")]
+pub mod packet_source;
+#[cfg(feature = "postgresql")]
+pub mod postgres_sink;
+#[cfg(feature = "security_logs")]
+pub mod security_logs;
+#[cfg(feature = "sigma")]
+pub mod sigma;
+#[cfg(feature = "snapshots")]
+pub mod snapshot_publisher;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_sink;
 pub mod update_handler;
+#[cfg(feature = "web_ui")]
+pub mod web_ui;
 
 use crate::api::updcmd2upd;
 
@@ -317,6 +347,13 @@ pub fn relval_from_record(
     panic!("relval_from_record not implemented")
 }
 
+pub fn relvals_from_records(
+    _rel: Relations,
+    _recs: &[record::Record],
+) -> ::std::result::Result<Vec<DDValue>, String> {
+    panic!("relvals_from_records not implemented")
+}
+
 pub fn relkey_from_record(
     _rel: Relations,
     _rec: &record::Record,
@@ -324,6 +361,10 @@ pub fn relkey_from_record(
     panic!("relkey_from_record not implemented")
 }
 
+pub fn relval_validate(_rel: Relations, _val: &DDValue) -> ::std::result::Result<(), String> {
+    panic!("relval_validate not implemented")
+}
+
 pub fn idxkey_from_record(
     idx: Indexes,
     _rec: &record::Record,
@@ -335,6 +376,10 @@ pub fn relid2name(_rid: program::RelId) -> Option<&'static str> {
     panic!("relid2name not implemented")
 }
 
+pub fn relid2type(_rid: program::RelId) -> Option<&'static str> {
+    panic!("relid2type not implemented")
+}
+
 pub fn relid2cname(_rid: program::RelId) -> Option<&'static ::std::ffi::CStr> {
     panic!("relid2cname not implemented")
 }
@@ -359,4 +404,12 @@ pub fn indexes2arrid(idx: Indexes) -> program::ArrId {
 
 pub static IDXIDMAP: Lazy<FnvHashMap<Indexes, &'static str>> = Lazy::new(FnvHashMap::default);
 
+pub static PROGRAM_METADATA: differential_datalog::metadata::ProgramMetadata =
+    differential_datalog::metadata::ProgramMetadata {
+        program_name: "datalog_example",
+        source_hash: 0,
+        compiler_version: "",
+        relations: &[],
+    };
+
 impl_trait_d3log!();
@@ -7,9 +7,11 @@
 use std::convert::TryFrom;
 use std::io::stdout;
 use std::io::Write;
+use std::iter;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread::sleep;
+use std::time::Duration;
 use time::Instant;
 
 use api::{updcmd2upd, HDDlog};
@@ -17,21 +19,68 @@ use cmd_parser::*;
 use datalog_example_ddlog::*;
 use ddlog_log::log_set_default_callback;
 use differential_datalog::ddval::*;
+use differential_datalog::program::config::{Config, ProfilingKind};
+use differential_datalog::program::placement::WorkerPlacement;
 use differential_datalog::program::*;
 use differential_datalog::record::*;
-use differential_datalog::DeltaMap;
 use differential_datalog::{DDlog, DDlogDynamic, DDlogProfiling};
+use differential_datalog::{DeltaMap, FormatOptions};
 use num_traits::cast::ToPrimitive;
 use rustop::opts;
 
 #[cfg(feature = "profile")]
 use cpuprofiler::PROFILER;
 
+#[cfg(feature = "dashboard")]
+use datalog_example_ddlog::dashboard::Dashboard;
+#[cfg(feature = "web_ui")]
+use datalog_example_ddlog::web_ui::WebUi;
+
+/// How often the dashboard is redrawn.
+const DASHBOARD_REFRESH: Duration = Duration::from_millis(250);
+
+// Stand-in for the dashboard when the CLI is built without it.
+#[cfg(not(feature = "dashboard"))]
+struct Dashboard;
+
+#[cfg(not(feature = "dashboard"))]
+impl Dashboard {
+    fn attach(_hddlog: Arc<HDDlog>, _refresh: Duration) -> std::io::Result<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "the CLI was built without the `dashboard` feature",
+        ))
+    }
+
+    fn record_commit(&self, _latency: Duration) {}
+
+    fn wait(self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Stand-in for the web UI when the CLI is built without it.
+#[cfg(not(feature = "web_ui"))]
+struct WebUi;
+
+#[cfg(not(feature = "web_ui"))]
+impl WebUi {
+    fn start(_hddlog: Arc<HDDlog>, _addr: &str) -> Result<Self, String> {
+        Err("the CLI was built without the `web_ui` feature".to_string())
+    }
+
+    fn addr(&self) -> &str {
+        ""
+    }
+}
+
 #[allow(clippy::let_and_return)]
 fn handle_cmd(
     start_time: Instant,
+    replay_speed: f64,
     hddlog: &HDDlog,
     print_deltas: bool,
+    format: &FormatOptions,
     interactive: bool,
     upds: &mut Vec<Update<DDValue>>,
     cmd: Command,
@@ -65,7 +114,7 @@ fn handle_cmd(
             let res = if record_delta {
                 hddlog.transaction_commit_dump_changes().map(|changes| {
                     if print_deltas {
-                        dump_delta(&changes)
+                        dump_delta(&changes, format)
                     }
                 })
             } else {
@@ -82,6 +131,11 @@ fn handle_cmd(
             }
             res
         }
+        Command::CommitWithId(id) => hddlog.transaction_commit_with_id(&id).map(|committed| {
+            if !committed {
+                println!("Transaction {} has already been committed", id);
+            }
+        }),
         Command::Comment => Ok(()),
         Command::Rollback => hddlog.transaction_rollback(),
         Command::Timestamp => {
@@ -97,10 +151,11 @@ fn handle_cmd(
         }
 
         Command::Dump(None) => {
-            let _ = hddlog
-                .db
-                .as_ref()
-                .map(|db| db.lock().unwrap().format_as_sets(&mut stdout(), hddlog));
+            let _ = hddlog.db.as_ref().map(|db| {
+                db.lock()
+                    .unwrap()
+                    .format_as_sets_with(&mut stdout(), hddlog, format)
+            });
             Ok(())
         }
         Command::Dump(Some(rname)) => {
@@ -114,12 +169,26 @@ fn handle_cmd(
                     return (Err(err), interactive);
                 }
             };
-            let _ = hddlog
-                .db
-                .as_ref()
-                .map(|db| db.lock().unwrap().format_rel_as_set(relid, &mut stdout()));
+            let _ = hddlog.db.as_ref().map(|db| {
+                db.lock()
+                    .unwrap()
+                    .format_rel_as_set_with(relid, &mut stdout(), format)
+            });
             Ok(())
         }
+        Command::Stats(None) => {
+            for (relid, stats) in hddlog.all_relation_stats() {
+                println!("{}: {}", relid2name(relid).unwrap_or("?"), stats);
+            }
+            Ok(())
+        }
+        Command::Stats(Some(rname)) => Relations::try_from(rname.as_str())
+            .map_err(|_| format!("Unknown relation {}", rname))
+            .and_then(|rid| hddlog.relation_stats(rid as RelId))
+            .map(|stats| println!("{}: {}", rname, stats)),
+        Command::CheckFingerprint(rname, fingerprint) => {
+            hddlog.metadata().check_fingerprint(&rname, fingerprint)
+        }
         Command::Clear(rname) => {
             let relid = match Relations::try_from(rname.as_str()) {
                 Ok(rid) if rid.is_input() => rid as RelId,
@@ -133,6 +202,50 @@ fn handle_cmd(
             };
             hddlog.clear_relation(relid)
         }
+        Command::Truncate(rname) => {
+            let relid = match Relations::try_from(rname.as_str()) {
+                Ok(rid) if rid.is_input() => rid as RelId,
+                _ => {
+                    let err = format!("Unknown input relation {}", rname);
+                    if interactive {
+                        eprintln!("Error: {}", err);
+                    }
+                    return (Err(err), interactive);
+                }
+            };
+            hddlog.clear_relation_bulk(relid)
+        }
+        Command::Compare(rname, rec) => {
+            let rel = match Relations::try_from(rname.as_str()) {
+                Ok(rid) if rid.is_output() => rid,
+                _ => {
+                    let err = format!("Unknown output relation {}", rname);
+                    if interactive {
+                        eprintln!("Error: {}", err);
+                    }
+                    return (Err(err), interactive);
+                }
+            };
+            relval_from_record(rel, &rec).and_then(|val| compare(hddlog, &rname, rel, val))
+        }
+        Command::Graph(format, edges, labels) => {
+            let mut relids = Vec::new();
+            for rname in iter::once(&edges).chain(labels.iter()) {
+                match Relations::try_from(rname.as_str()) {
+                    Ok(rid) if rid.is_output() => relids.push(rid as RelId),
+                    _ => {
+                        let err = format!("Unknown output relation {}", rname);
+                        if interactive {
+                            eprintln!("Error: {}", err);
+                        }
+                        return (Err(err), interactive);
+                    }
+                }
+            }
+            hddlog
+                .export_graph(relids[0], relids.get(1).cloned(), format)
+                .map(|graph| print!("{}", graph))
+        }
         Command::Exit => {
             return (Ok(()), false);
         }
@@ -144,6 +257,18 @@ fn handle_cmd(
             sleep(std::time::Duration::from_millis(ms.to_u64().unwrap()));
             Ok(())
         }
+        Command::At(ms) => {
+            // Wait until `ms` milliseconds (scaled by the replay speed) have
+            // passed since the start of the replay.
+            if replay_speed > 0.0 {
+                let target = ms.to_f64().unwrap_or(0.0) * 1_000_000.0 / replay_speed;
+                let elapsed = start_time.elapsed().whole_nanoseconds() as f64;
+                if target > elapsed {
+                    sleep(Duration::from_nanos((target - elapsed) as u64));
+                }
+            }
+            Ok(())
+        }
         Command::Update(upd, last) => {
             match updcmd2upd(&upd) {
                 Ok(u) => upds.push(u),
@@ -168,16 +293,16 @@ fn handle_cmd(
                     .and_then(|keyval| hddlog.query_index(idxid as IdxId, keyval))
             })
             .map(|vals| {
-                for val in vals.into_iter() {
-                    let _ = writeln!(stdout(), "{}", val.clone().into_record());
+                for (rec, _) in format.records(vals.iter().map(|v| (v, 1))) {
+                    let _ = writeln!(stdout(), "{}", format_record(&rec, format));
                 }
             }),
         Command::DumpIndex(idx) => Indexes::try_from(idx.as_str())
             .map_err(|_| format!("Unknown index {}", idx))
             .and_then(|idxid| hddlog.dump_index(idxid as IdxId))
             .map(|vals| {
-                for val in vals.into_iter() {
-                    let _ = writeln!(stdout(), "{}", val.clone().into_record());
+                for (rec, _) in format.records(vals.iter().map(|v| (v, 1))) {
+                    let _ = writeln!(stdout(), "{}", format_record(&rec, format));
                 }
             }),
     });
@@ -192,12 +317,46 @@ fn handle_cmd(
     }
 }
 
-fn dump_delta(delta: &DeltaMap<DDValue>) {
+/// Check that output relation `rel` contains `val`.  Otherwise, print the
+/// fields that differ between `val` and the most similar fact in the relation.
+fn compare(hddlog: &HDDlog, rname: &str, rel: Relations, val: DDValue) -> Result<(), String> {
+    let db = hddlog
+        .db
+        .as_ref()
+        .ok_or_else(|| "The contents of output relations are not stored".to_string())?
+        .lock()
+        .unwrap();
+    let facts: Vec<Record> = match db.try_get_rel(rel as RelId) {
+        Some(facts) if facts.contains_key(&val) => return Ok(()),
+        Some(facts) => facts.keys().map(|v| v.clone().into_record()).collect(),
+        None => Vec::new(),
+    };
+    let expected = val.into_record();
+    match ddlog_testing::closest_match(&expected, &facts) {
+        Some((closest, deltas)) => {
+            println!("{} not found; closest match: {}", expected, closest);
+            for delta in deltas {
+                println!("    {}", delta);
+            }
+        }
+        None => println!("{} not found", expected),
+    }
+    Err(format!("Relation {} does not contain {}", rname, expected))
+}
+
+fn format_record(rec: &Record, format: &FormatOptions) -> String {
+    match &format.pretty {
+        Some(config) => rec.pretty(config),
+        None => rec.to_string(),
+    }
+}
+
+fn dump_delta(delta: &DeltaMap<DDValue>, format: &FormatOptions) {
     for (table_id, table_data) in delta.iter() {
         let _ = writeln!(stdout(), "{}:", relid2name(*table_id).unwrap());
-        for (val, weight) in table_data.iter() {
-            //debug_assert!(*weight == 1 || *weight == -1);
-            let _ = writeln!(stdout(), "{}: {:+}", val.clone().into_record(), *weight);
+        for (rec, weight) in format.records(table_data.iter().map(|(v, w)| (v, *w))) {
+            //debug_assert!(weight == 1 || weight == -1);
+            let _ = writeln!(stdout(), "{}: {:+}", format_record(&rec, format), weight);
         }
     }
 }
@@ -217,20 +376,63 @@ fn is_upd_cmd(c: &Command) -> bool {
     }
 }
 
-fn run(hddlog: HDDlog, print_deltas: bool) -> Result<(), String> {
+fn run(
+    hddlog: HDDlog,
+    print_deltas: bool,
+    format: FormatOptions,
+    dashboard: bool,
+    web_ui: Option<String>,
+    replay_speed: f64,
+) -> Result<(), String> {
+    let hddlog = Arc::new(hddlog);
+    let _web_ui = match web_ui {
+        Some(addr) => {
+            let web_ui = WebUi::start(hddlog.clone(), &addr)
+                .map_err(|e| format!("Failed to start web UI: {}", e))?;
+            eprintln!("Web UI listening on http://{}/", web_ui.addr());
+            Some(web_ui)
+        }
+        None => None,
+    };
+    let dashboard = if dashboard {
+        Some(
+            Dashboard::attach(hddlog.clone(), DASHBOARD_REFRESH)
+                .map_err(|e| format!("Failed to start dashboard: {}", e))?,
+        )
+    } else {
+        None
+    };
     let upds = Arc::new(Mutex::new(Vec::new()));
     let start_time = Instant::now();
     interact(|cmd, interactive| {
-        handle_cmd(
+        let is_commit = match cmd {
+            Command::Commit(_) | Command::CommitWithId(_) => true,
+            _ => false,
+        };
+        let commit_start = std::time::Instant::now();
+        let res = handle_cmd(
             start_time,
+            replay_speed,
             &hddlog,
             print_deltas,
+            &format,
             interactive,
             &mut upds.lock().unwrap(),
             cmd,
-        )
+        );
+        if let (true, Some(dashboard)) = (is_commit, &dashboard) {
+            dashboard.record_commit(commit_start.elapsed());
+        }
+        res
     })?;
 
+    // Keep showing the final state of the program until the user closes
+    // the dashboard.
+    if let Some(dashboard) = dashboard {
+        dashboard
+            .wait()
+            .map_err(|e| format!("Dashboard failed: {}", e))?;
+    }
     hddlog.stop()
 }
 
@@ -244,10 +446,22 @@ fn main() -> Result<(), String> {
         opt init_snapshot:bool=true, desc:"Do not dump initial output snapshot.";                                                   // --no-init-snapshot
         opt print:bool=true, desc:"Backwards compatibility. The value of this flag is ignored.";                                    // --no-print
         opt workers:usize=1, short:'w', desc:"The number of worker threads. Default is 1.";                                         // --workers or -w
+        opt seed:u64=0, desc:"Seed for random number functions. Default is 0.";                                                     // --seed
+        opt numa:bool=false, desc:"Pin worker threads to NUMA nodes and allocate their memory from local memory.";                  // --numa
+        opt pretty:bool=false, desc:"Pretty-print records in dumps, deltas, and index queries across multiple lines.";              // --pretty
+        opt width:usize=80, desc:"Maximal line width for --pretty. Default is 80.";                                                 // --width
+        opt indent:usize=4, desc:"Indentation step for --pretty. Default is 4.";                                                    // --indent
+        opt no_field_names:bool=false, desc:"Print structs using positional syntax with --pretty.";                                 // --no-field-names
+        opt color:bool=false, desc:"Highlight --pretty output using ANSI terminal colors.";                                         // --color
+        opt canonical_order:bool=false, desc:"Print relation contents and changes in canonical order, independent of the number of workers."; // --canonical-order
+        opt web_ui:Option<String>, desc:"Serve the web introspection UI on the given address, e.g., 127.0.0.1:8080.";                // --web-ui
+        opt relation_stats:bool=false, desc:"Count the changes to all relations for 'stats' and the dashboard, not only to relations with output callbacks."; // --relation-stats
+        opt dashboard:bool=false, desc:"Show a live dashboard of relation sizes, changes, commit latency, and CPU hotspots on stderr.";  // --dashboard
+        opt replay_speed:f64=0.0, desc:"Honor 'at' timestamps in the input, replaying it at the given multiple of the recorded speed (e.g., 1 for real time, 2 for twice as fast). Default is 0, which ignores timestamps."; // --replay-speed
     };
     let (args, rest) = parser.parse_or_exit();
 
-    if !rest.is_empty() || args.workers == 0 {
+    if !rest.is_empty() || args.workers == 0 || args.replay_speed < 0.0 {
         return Err("Invalid command line arguments; try -h for help".to_string());
     }
 
@@ -262,12 +476,48 @@ fn main() -> Result<(), String> {
     }
     fn no_op(_table: usize, _rec: &Record, _w: isize) {}
 
-    match HDDlog::run(args.workers, args.store) {
+    let config = Config {
+        num_timely_workers: args.workers,
+        profiling_kind: ProfilingKind::SelfProfiling,
+        random_seed: args.seed,
+        worker_placement: if args.numa {
+            WorkerPlacement::NumaNodes
+        } else {
+            WorkerPlacement::Unpinned
+        },
+        numa_local_memory: args.numa,
+        canonical_order: args.canonical_order,
+        relation_stats: args.relation_stats,
+        ..Default::default()
+    };
+
+    let format = FormatOptions {
+        pretty: if args.pretty {
+            Some(PrettyConfig {
+                indent: args.indent,
+                width: args.width,
+                field_names: !args.no_field_names,
+                color: args.color,
+            })
+        } else {
+            None
+        },
+        canonical_order: args.canonical_order,
+    };
+
+    match HDDlog::run_with_config(config, args.store) {
         Ok((hddlog, init_output)) => {
             if args.init_snapshot {
-                dump_delta(&init_output);
+                dump_delta(&init_output, &format);
             }
-            run(hddlog, args.delta)
+            run(
+                hddlog,
+                args.delta,
+                format,
+                args.dashboard,
+                args.web_ui,
+                args.replay_speed,
+            )
         }
         Err(err) => Err(format!("Failed to run differential datalog: {}", err)),
     }
@@ -0,0 +1,325 @@
+//! Helpers for driving DDlog programs from Rust notebooks.
+//!
+//! These functions make the generated crate convenient to explore
+//! interactively, e.g., from Jupyter with the evcxr kernel.  Results are
+//! returned as `Table`s and `Transcript`s, which evcxr renders as HTML tables
+//! (see `evcxr_display()`) and which can also be converted to HTML or plain
+//! text for other front ends.
+//!
+//! * `relation()` shows the contents of an output relation.
+//! * `run_script()` and `load_script()` execute commands in the `.dat` file
+//!   syntax, like the `%run` magic of IPython.
+//! * `Transaction` groups updates into a transaction that is committed with
+//!   `Transaction::commit()` and rolled back if it is dropped without being
+//!   committed, much like a Python context manager.
+//!
+//! ```ignore
+//! let hddlog = notebook::start(1)?;
+//! notebook::load_script(&hddlog, "graph.dat")?;
+//! let mut txn = notebook::Transaction::start(&hddlog)?;
+//! txn.run("insert Edge(3, 4);")?;
+//! txn.commit()?          // displays the changes to output relations
+//! notebook::relation(&hddlog, "Path")?
+//! ```
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::Path;
+
+use cmd_parser::{err_str, parse_command, preprocess_file, Command};
+
+use differential_datalog::ddval::DDValue;
+use differential_datalog::program::RelId;
+use differential_datalog::record::{IntoRecord, UpdCmd};
+use differential_datalog::{DDlog, DDlogDynamic, DeltaMap};
+
+use crate::api::HDDlog;
+use crate::ddlog_testing::parse_updates;
+use crate::{relid2name, Relations};
+
+/// Maximal number of rows shown by `Table`s, unless overridden with
+/// `Table::limit()`.
+pub const DEFAULT_ROW_LIMIT: usize = 1000;
+
+/// Start the program with `workers` worker threads, storing the contents of
+/// output relations so that they can be displayed.
+pub fn start(workers: usize) -> Result<HDDlog, String> {
+    HDDlog::run(workers, true).map(|(hddlog, _)| hddlog)
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A table of facts and their weights, e.g., the contents of a relation or
+/// the changes made by a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub title: String,
+    pub rows: Vec<(String, isize)>,
+    limit: usize,
+}
+
+impl Table {
+    fn new<'a, I>(title: String, facts: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a DDValue, isize)>,
+    {
+        Self {
+            title,
+            rows: facts
+                .into_iter()
+                .map(|(v, w)| (v.clone().into_record().to_string(), w))
+                .collect(),
+            limit: DEFAULT_ROW_LIMIT,
+        }
+    }
+
+    /// Show at most `limit` rows.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Render the table as an HTML fragment.
+    pub fn to_html(&self) -> String {
+        let mut html = format!(
+            "<table><caption>{} ({} {})</caption><tr><th>Fact</th><th>Weight</th></tr>",
+            html_escape(&self.title),
+            self.rows.len(),
+            if self.rows.len() == 1 { "row" } else { "rows" }
+        );
+        for (fact, weight) in self.rows.iter().take(self.limit) {
+            html.push_str(&format!(
+                "<tr><td><code>{}</code></td><td>{:+}</td></tr>",
+                html_escape(fact),
+                weight
+            ));
+        }
+        if self.rows.len() > self.limit {
+            html.push_str(&format!(
+                "<tr><td colspan=\"2\">... {} more</td></tr>",
+                self.rows.len() - self.limit
+            ));
+        }
+        html.push_str("</table>");
+        html
+    }
+
+    /// Display the table in an evcxr notebook.
+    pub fn evcxr_display(&self) {
+        evcxr_html(&self.to_html());
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}:", self.title)?;
+        for (fact, weight) in self.rows.iter().take(self.limit) {
+            writeln!(f, "{}: {:+}", fact, weight)?;
+        }
+        if self.rows.len() > self.limit {
+            writeln!(f, "... {} more", self.rows.len() - self.limit)?;
+        }
+        Ok(())
+    }
+}
+
+fn evcxr_html(html: &str) {
+    println!("EVCXR_BEGIN_CONTENT text/html\n{}\nEVCXR_END_CONTENT", html);
+}
+
+/// Output produced by a script (see `run_script()`) or a commit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub items: Vec<TranscriptItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranscriptItem {
+    /// Output of an `echo` command.
+    Text(String),
+    /// A relation dump or the changes to a relation made by a commit.
+    Table(Table),
+}
+
+impl Transcript {
+    fn changes(&mut self, delta: &DeltaMap<DDValue>) {
+        for (relid, changes) in delta.iter() {
+            self.items.push(TranscriptItem::Table(Table::new(
+                format!("{} (changes)", relid2name(*relid).unwrap_or("?")),
+                changes.iter().map(|(v, w)| (v, *w)),
+            )));
+        }
+    }
+
+    /// Render the transcript as an HTML fragment.
+    pub fn to_html(&self) -> String {
+        self.items
+            .iter()
+            .map(|item| match item {
+                TranscriptItem::Text(text) => format!("<pre>{}</pre>", html_escape(text)),
+                TranscriptItem::Table(table) => table.to_html(),
+            })
+            .collect()
+    }
+
+    /// Display the transcript in an evcxr notebook.
+    pub fn evcxr_display(&self) {
+        evcxr_html(&self.to_html());
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for item in self.items.iter() {
+            match item {
+                TranscriptItem::Text(text) => writeln!(f, "{}", text)?,
+                TranscriptItem::Table(table) => write!(f, "{}", table)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn output_relation(relation: &str) -> Result<RelId, String> {
+    match Relations::try_from(relation) {
+        Ok(rel) if rel.is_output() => Ok(rel as RelId),
+        _ => Err(format!("unknown output relation {}", relation)),
+    }
+}
+
+/// The current contents of output relation `relation`.
+pub fn relation(hddlog: &HDDlog, relation: &str) -> Result<Table, String> {
+    let relid = output_relation(relation)?;
+    let snapshot = hddlog.dump_stored(&[relid])?;
+    Ok(Table::new(
+        relation.to_string(),
+        snapshot
+            .get(&relid)
+            .into_iter()
+            .flatten()
+            .map(|(v, w)| (v, *w)),
+    ))
+}
+
+fn apply_pending(hddlog: &HDDlog, pending: &mut Vec<UpdCmd>) -> Result<(), String> {
+    if pending.is_empty() {
+        return Ok(());
+    }
+    hddlog.apply_updates_dynamic(&mut pending.drain(..))
+}
+
+/// Execute commands in the `.dat` file syntax and collect their output.
+/// Supports transactions (`start`, `commit`, `commit dump_changes`,
+/// `rollback`), updates, `dump`, `echo`, and comments.  Stops at the first
+/// command that fails; a transaction started by the script is then left
+/// open, as in the CLI.
+pub fn run_script(hddlog: &HDDlog, script: &str) -> Result<Transcript, String> {
+    let mut transcript = Transcript::default();
+    let mut pending = Vec::new();
+    let mut input = script.as_bytes();
+    loop {
+        let start = input
+            .iter()
+            .position(|c| !c.is_ascii_whitespace())
+            .unwrap_or(input.len());
+        input = &input[start..];
+        if input.is_empty() {
+            apply_pending(hddlog, &mut pending)?;
+            return Ok(transcript);
+        }
+
+        let (rest, cmd) = parse_command(input).map_err(|e| {
+            let msg = err_str(&e);
+            if msg.is_empty() {
+                format!("incomplete command: {}", String::from_utf8_lossy(input))
+            } else {
+                format!("invalid input: {}", msg)
+            }
+        })?;
+        input = rest;
+        if let Command::Update(upd, _) = cmd {
+            pending.push(upd);
+            continue;
+        }
+        apply_pending(hddlog, &mut pending)?;
+        match cmd {
+            Command::Start => hddlog.transaction_start()?,
+            Command::Commit(false) => hddlog.transaction_commit()?,
+            Command::Commit(true) => {
+                transcript.changes(&hddlog.transaction_commit_dump_changes()?);
+            }
+            Command::Rollback => hddlog.transaction_rollback()?,
+            Command::Comment | Command::At(_) => (),
+            Command::Echo(text) => transcript.items.push(TranscriptItem::Text(text)),
+            Command::Dump(Some(rname)) => transcript
+                .items
+                .push(TranscriptItem::Table(relation(hddlog, &rname)?)),
+            Command::Dump(None) => {
+                for rel in hddlog.metadata().relations.iter() {
+                    if output_relation(rel.name).is_ok() {
+                        transcript
+                            .items
+                            .push(TranscriptItem::Table(relation(hddlog, rel.name)?));
+                    }
+                }
+            }
+            cmd => return Err(format!("command not supported in notebooks: {:?}", cmd)),
+        }
+    }
+}
+
+/// Execute the `.dat` file at `path` (see `run_script()`), expanding
+/// includes, macros, and conditional sections (see `cmd_parser::Preprocessor`).
+pub fn load_script<P: AsRef<Path>>(hddlog: &HDDlog, path: P) -> Result<Transcript, String> {
+    run_script(hddlog, &preprocess_file(path)?)
+}
+
+/// A transaction that is rolled back unless committed.
+pub struct Transaction<'a> {
+    hddlog: &'a HDDlog,
+    open: bool,
+}
+
+impl<'a> Transaction<'a> {
+    /// Start a transaction.
+    pub fn start(hddlog: &'a HDDlog) -> Result<Self, String> {
+        hddlog.transaction_start()?;
+        Ok(Self { hddlog, open: true })
+    }
+
+    /// Apply updates written in the `.dat` file syntax, e.g.,
+    /// `insert R(1), delete S("foo");`.
+    pub fn run(&mut self, updates: &str) -> Result<(), String> {
+        let updates = parse_updates(updates)?;
+        self.hddlog.apply_updates_dynamic(&mut updates.into_iter())
+    }
+
+    /// Commit the transaction and return the changes it made to output
+    /// relations.
+    pub fn commit(mut self) -> Result<Transcript, String> {
+        self.open = false;
+        let delta = self.hddlog.transaction_commit_dump_changes()?;
+        let mut transcript = Transcript::default();
+        transcript.changes(&delta);
+        Ok(transcript)
+    }
+
+    /// Roll back the transaction.
+    pub fn rollback(mut self) -> Result<(), String> {
+        self.open = false;
+        self.hddlog.transaction_rollback()
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if self.open {
+            let _ = self.hddlog.transaction_rollback();
+        }
+    }
+}
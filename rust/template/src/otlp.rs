@@ -0,0 +1,581 @@
+//! OpenTelemetry span ingestion.
+//!
+//! `OtlpReceiver` accepts trace exports over OTLP/HTTP with the JSON
+//! encoding (`POST /v1/traces` with `Content-Type: application/json`) and
+//! inserts their spans into the `otel::Span` input relation of a program
+//! that imports the `otel` library (see `lib/otel.dl`).  Each export is
+//! applied in one transaction, started with `HDDlog::try_start_transaction()`
+//! so that the receiver shares the program fairly with other clients.
+//! Exporters must be configured for the JSON encoding; protobuf requests
+//! are rejected with status 415.  `ingest_otlp_json()` inserts spans
+//! received through other transports, e.g., read from files.
+//!
+//! Traces are expired `OtlpConfig::retention` after their last span was
+//! received, which bounds the size of the relation and of the trace analyses
+//! derived from it.  Requires the `otlp` feature.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use differential_datalog::record::{CollectionKind, Record, RelIdentifier, UpdCmd};
+use differential_datalog::scheduler::ClientId;
+
+use crate::api::HDDlog;
+use crate::Relations;
+
+/// Name of the relation declared by the `otel` library.
+const SPAN_RELATION: &str = "otel::Span";
+
+/// How often expired traces are removed.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Configuration of an `OtlpReceiver`.
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    /// Time after the last span of a trace was received at which the trace
+    /// is removed; `None` keeps traces forever.
+    pub retention: Option<Duration>,
+    /// Client id used to schedule the receiver's transactions.
+    pub client: ClientId,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            retention: Some(Duration::from_secs(600)),
+            client: ClientId::max_value() - 1,
+        }
+    }
+}
+
+/// A span parsed from an OTLP export.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub trace_id: String,
+    pub span_id: String,
+    pub record: Record,
+}
+
+fn constructor(name: &str) -> Record {
+    Record::NamedStruct(Cow::from(format!("otel::{}", name)), Vec::new())
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> &'a Value {
+    value.get(name).unwrap_or(&Value::Null)
+}
+
+fn array<'a>(value: &'a Value, name: &str) -> &'a [Value] {
+    field(value, name)
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or(&[])
+}
+
+fn string(value: &Value, name: &str) -> String {
+    field(value, name)
+        .as_str()
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+/* 64-bit integers are encoded as decimal strings, but some exporters send
+ * numbers. */
+fn uint64(value: &Value, name: &str) -> Result<u64, String> {
+    match field(value, name) {
+        Value::Null => Ok(0),
+        Value::String(s) => s
+            .parse()
+            .map_err(|e| format!("invalid {} '{}': {}", name, s, e)),
+        Value::Number(n) => n.as_u64().ok_or_else(|| format!("invalid {} {}", name, n)),
+        v => Err(format!("invalid {} {}", name, v)),
+    }
+}
+
+/* Converts an `AnyValue` to a string: strings are taken as is, other values
+ * are stored in their JSON representation. */
+fn any_value(value: &Value) -> String {
+    match value.as_object().and_then(|o| o.iter().next()) {
+        Some((kind, Value::String(s))) if kind == "stringValue" => s.clone(),
+        Some((_, v)) => v.to_string(),
+        None => String::new(),
+    }
+}
+
+fn attributes(value: &Value) -> BTreeMap<String, String> {
+    array(value, "attributes")
+        .iter()
+        .filter_map(|attr| {
+            let key = attr.get("key")?.as_str()?;
+            Some((key.to_string(), any_value(field(attr, "value"))))
+        })
+        .collect()
+}
+
+fn span_kind(kind: u64) -> Record {
+    constructor(match kind {
+        1 => "SpanKindInternal",
+        2 => "SpanKindServer",
+        3 => "SpanKindClient",
+        4 => "SpanKindProducer",
+        5 => "SpanKindConsumer",
+        _ => "SpanKindUnspecified",
+    })
+}
+
+fn status_code(code: u64) -> Record {
+    constructor(match code {
+        1 => "StatusOk",
+        2 => "StatusError",
+        _ => "StatusUnset",
+    })
+}
+
+fn parse_span(span: &Value, service: &str) -> Result<Span, String> {
+    let trace_id = string(span, "traceId");
+    let span_id = string(span, "spanId");
+    if trace_id.is_empty() || span_id.is_empty() {
+        return Err("span without a trace or span id".to_string());
+    }
+    let status = field(span, "status");
+    let attributes = attributes(span)
+        .into_iter()
+        .map(|(k, v)| Record::Tuple(vec![Record::String(k), Record::String(v)]))
+        .collect();
+    let fields = vec![
+        ("trace_id", Record::String(trace_id.clone())),
+        ("span_id", Record::String(span_id.clone())),
+        (
+            "parent_span_id",
+            Record::String(string(span, "parentSpanId")),
+        ),
+        ("service", Record::String(service.to_string())),
+        (
+            "name",
+            Record::String(field(span, "name").as_str().unwrap_or_default().to_string()),
+        ),
+        ("kind", span_kind(uint64(span, "kind")?)),
+        (
+            "start_ns",
+            Record::Int(uint64(span, "startTimeUnixNano")?.into()),
+        ),
+        (
+            "end_ns",
+            Record::Int(uint64(span, "endTimeUnixNano")?.into()),
+        ),
+        ("status", status_code(uint64(status, "code")?)),
+        (
+            "status_message",
+            Record::String(
+                field(status, "message")
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+            ),
+        ),
+        ("attributes", Record::Array(CollectionKind::Map, attributes)),
+    ];
+    Ok(Span {
+        trace_id,
+        span_id,
+        record: Record::NamedStruct(
+            Cow::from(SPAN_RELATION),
+            fields
+                .into_iter()
+                .map(|(name, value)| (Cow::from(name), value))
+                .collect(),
+        ),
+    })
+}
+
+/// Parse an `ExportTraceServiceRequest` in the OTLP JSON encoding.
+pub fn parse_otlp_json(text: &str) -> Result<Vec<Span>, String> {
+    let request: Value =
+        serde_json::from_str(text).map_err(|e| format!("invalid OTLP request: {}", e))?;
+    let mut spans = Vec::new();
+    for resource_spans in array(&request, "resourceSpans") {
+        let service = attributes(field(resource_spans, "resource"))
+            .remove("service.name")
+            .unwrap_or_default();
+        // `instrumentationLibrarySpans` is the name used before OTLP 0.15.
+        let scopes = array(resource_spans, "scopeSpans")
+            .iter()
+            .chain(array(resource_spans, "instrumentationLibrarySpans"));
+        for scope_spans in scopes {
+            for span in array(scope_spans, "spans") {
+                spans.push(parse_span(span, &service)?);
+            }
+        }
+    }
+    Ok(spans)
+}
+
+fn span_relation() -> Result<(), String> {
+    Relations::try_from(SPAN_RELATION)
+        .map(|_| ())
+        .map_err(|()| "the program does not import the otel library".to_string())
+}
+
+fn span_key(trace_id: &str, span_id: &str) -> Record {
+    Record::Tuple(vec![
+        Record::String(trace_id.to_string()),
+        Record::String(span_id.to_string()),
+    ])
+}
+
+fn apply(hddlog: &HDDlog, client: ClientId, updates: Vec<UpdCmd>) -> Result<(), String> {
+    if updates.is_empty() {
+        return Ok(());
+    }
+    let txn = hddlog.try_start_transaction(client, None)?;
+    txn.apply_updates_dynamic(&mut updates.into_iter())?;
+    txn.commit()
+}
+
+/// Insert the spans of an OTLP JSON export into `otel::Span` in one
+/// transaction, replacing spans with the same ids.  Returns the number of
+/// spans.
+pub fn ingest_otlp_json(hddlog: &HDDlog, client: ClientId, text: &str) -> Result<usize, String> {
+    span_relation()?;
+    let spans = parse_otlp_json(text)?;
+    let count = spans.len();
+    apply(hddlog, client, insertions(spans))?;
+    Ok(count)
+}
+
+fn insertions(spans: Vec<Span>) -> Vec<UpdCmd> {
+    spans
+        .into_iter()
+        .map(|span| {
+            UpdCmd::InsertOrUpdate(
+                RelIdentifier::RelName(Cow::from(SPAN_RELATION)),
+                span.record,
+            )
+        })
+        .collect()
+}
+
+/* Spans of each trace and the time its last span was received. */
+#[derive(Default)]
+struct Traces {
+    traces: HashMap<String, (Instant, Vec<String>)>,
+}
+
+impl Traces {
+    fn add(&mut self, spans: &[(String, String)]) {
+        let now = Instant::now();
+        for (trace_id, span_id) in spans {
+            let trace = self
+                .traces
+                .entry(trace_id.clone())
+                .or_insert_with(|| (now, Vec::new()));
+            trace.0 = now;
+            trace.1.push(span_id.clone());
+        }
+    }
+
+    fn expire(&mut self, retention: Duration) -> Vec<UpdCmd> {
+        let now = Instant::now();
+        let mut updates = Vec::new();
+        self.traces.retain(|trace_id, (last, spans)| {
+            if now.duration_since(*last) < retention {
+                return true;
+            }
+            spans.sort();
+            spans.dedup();
+            for span_id in spans.iter() {
+                updates.push(UpdCmd::DeleteKey(
+                    RelIdentifier::RelName(Cow::from(SPAN_RELATION)),
+                    span_key(trace_id, span_id),
+                ));
+            }
+            false
+        });
+        updates
+    }
+}
+
+/// A running OTLP/HTTP receiver.  Dropping it stops the server; spans that
+/// have been received are left in the relation.
+pub struct OtlpReceiver {
+    server: Arc<Server>,
+    addr: String,
+    thread: Option<JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+fn respond(request: Request, status: u16, body: String) {
+    let response = Response::from_string(body)
+        .with_status_code(status)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap());
+    let _ = request.respond(response);
+}
+
+fn handle(
+    hddlog: &HDDlog,
+    config: &OtlpConfig,
+    traces: &mut Traces,
+    mut request: Request,
+) -> Result<(), String> {
+    if *request.method() != Method::Post || request.url() != "/v1/traces" {
+        respond(request, 404, "{}".to_string());
+        return Ok(());
+    }
+    let json = request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Content-Type") && h.value.as_str().starts_with("application/json"));
+    if !json {
+        respond(
+            request,
+            415,
+            "{\"message\": \"only the JSON encoding is supported\"}".to_string(),
+        );
+        return Ok(());
+    }
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        respond(request, 400, "{}".to_string());
+        return Err(format!("failed to read OTLP request: {}", e));
+    }
+    let res = parse_otlp_json(&body).and_then(|spans| {
+        // Only spans that made it into the relation can be expired.
+        let ids: Vec<(String, String)> = spans
+            .iter()
+            .map(|span| (span.trace_id.clone(), span.span_id.clone()))
+            .collect();
+        apply(hddlog, config.client, insertions(spans))?;
+        if config.retention.is_some() {
+            traces.add(&ids);
+        }
+        Ok(())
+    });
+    match res {
+        Ok(()) => {
+            respond(request, 200, "{}".to_string());
+            Ok(())
+        }
+        Err(e) => {
+            respond(
+                request,
+                400,
+                serde_json::json!({ "message": e }).to_string(),
+            );
+            Err(e)
+        }
+    }
+}
+
+impl OtlpReceiver {
+    /// Receive spans for `hddlog` on `addr`, e.g., `0.0.0.0:4318`.
+    pub fn start(hddlog: Arc<HDDlog>, addr: &str, config: OtlpConfig) -> Result<Self, String> {
+        span_relation()?;
+        let server =
+            Arc::new(Server::http(addr).map_err(|e| format!("failed to bind {}: {}", addr, e))?);
+        let addr = server.server_addr().to_string();
+        let last_error = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let server = server.clone();
+            let last_error = last_error.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut traces = Traces::default();
+                let mut last_expiry = Instant::now();
+                while !stop.load(Ordering::Acquire) {
+                    let mut res = match server.recv_timeout(EXPIRY_INTERVAL) {
+                        Ok(Some(request)) => handle(&hddlog, &config, &mut traces, request),
+                        Ok(None) => Ok(()),
+                        Err(e) => Err(format!("failed to receive OTLP request: {}", e)),
+                    };
+                    if let Some(retention) = config.retention {
+                        if last_expiry.elapsed() >= EXPIRY_INTERVAL {
+                            last_expiry = Instant::now();
+                            res = res.and(apply(&hddlog, config.client, traces.expire(retention)));
+                        }
+                    }
+                    if let Err(e) = res {
+                        *last_error.lock().unwrap() = Some(e);
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            server,
+            addr,
+            thread: Some(thread),
+            stop,
+            last_error,
+        })
+    }
+
+    /// The address the receiver listens on.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// The last error encountered while handling a request, if any.
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+}
+
+impl Drop for OtlpReceiver {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(spans: Value) -> String {
+        serde_json::json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [
+                        {"key": "service.name", "value": {"stringValue": "checkout"}}
+                    ]
+                },
+                "scopeSpans": [{"spans": spans}]
+            }]
+        })
+        .to_string()
+    }
+
+    fn span_field<'a>(span: &'a Span, name: &str) -> &'a Record {
+        match &span.record {
+            Record::NamedStruct(_, fields) => &fields.iter().find(|(n, _)| n == name).unwrap().1,
+            record => panic!("unexpected span record {}", record),
+        }
+    }
+
+    #[test]
+    fn parse_spans() {
+        let spans = parse_otlp_json(&export(serde_json::json!([{
+            "traceId": "5B8EFFF798038103D269B633813FC60C",
+            "spanId": "EEE19B7EC3C1B174",
+            "parentSpanId": "",
+            "name": "GET /cart",
+            "kind": 2,
+            "startTimeUnixNano": "1544712660000000000",
+            "endTimeUnixNano": 1544712661000000000u64,
+            "status": {"code": 2, "message": "timeout"},
+            "attributes": [
+                {"key": "http.method", "value": {"stringValue": "GET"}},
+                {"key": "http.status_code", "value": {"intValue": "504"}},
+                {"key": "retry", "value": {"boolValue": true}}
+            ]
+        }])))
+        .unwrap();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        // Ids are normalized to lower case.
+        assert_eq!(span.trace_id, "5b8efff798038103d269b633813fc60c");
+        assert_eq!(span.span_id, "eee19b7ec3c1b174");
+        assert_eq!(
+            span_field(span, "service"),
+            &Record::String("checkout".to_string())
+        );
+        assert_eq!(span_field(span, "kind"), &constructor("SpanKindServer"));
+        assert_eq!(span_field(span, "status"), &constructor("StatusError"));
+        assert_eq!(
+            span_field(span, "start_ns"),
+            &Record::Int(1544712660000000000u64.into())
+        );
+        assert_eq!(
+            span_field(span, "end_ns"),
+            &Record::Int(1544712661000000000u64.into())
+        );
+        assert_eq!(
+            span_field(span, "attributes").to_string(),
+            r#"[("http.method", "GET"), ("http.status_code", "\"504\""), ("retry", "true")]"#
+        );
+    }
+
+    #[test]
+    fn parse_defaults_and_legacy_exports() {
+        let text = serde_json::json!({
+            "resourceSpans": [{
+                "instrumentationLibrarySpans": [{
+                    "spans": [{"traceId": "01", "spanId": "02", "kind": 9}]
+                }]
+            }]
+        })
+        .to_string();
+        let spans = parse_otlp_json(&text).unwrap();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(span_field(span, "service"), &Record::String(String::new()));
+        assert_eq!(
+            span_field(span, "kind"),
+            &constructor("SpanKindUnspecified")
+        );
+        assert_eq!(span_field(span, "status"), &constructor("StatusUnset"));
+        assert_eq!(span_field(span, "start_ns"), &Record::Int(0.into()));
+        assert!(parse_otlp_json("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse_otlp_json("not json")
+            .unwrap_err()
+            .starts_with("invalid OTLP request: "));
+        assert_eq!(
+            parse_otlp_json(&export(serde_json::json!([{"spanId": "02"}]))).unwrap_err(),
+            "span without a trace or span id"
+        );
+        assert!(parse_otlp_json(&export(serde_json::json!([{
+            "traceId": "01",
+            "spanId": "02",
+            "startTimeUnixNano": "soon"
+        }])))
+        .unwrap_err()
+        .starts_with("invalid startTimeUnixNano 'soon': "));
+        assert_eq!(
+            parse_otlp_json(&export(serde_json::json!([{
+                "traceId": "01",
+                "spanId": "02",
+                "kind": -1
+            }])))
+            .unwrap_err(),
+            "invalid kind -1"
+        );
+    }
+
+    #[test]
+    fn expire_traces() {
+        let mut traces = Traces::default();
+        traces.add(&[
+            ("t1".to_string(), "s2".to_string()),
+            ("t1".to_string(), "s1".to_string()),
+            ("t1".to_string(), "s1".to_string()),
+        ]);
+        assert!(traces.expire(Duration::from_secs(3600)).is_empty());
+        let key = |span_id: &str| {
+            UpdCmd::DeleteKey(
+                RelIdentifier::RelName(Cow::from(SPAN_RELATION)),
+                span_key("t1", span_id),
+            )
+        };
+        assert_eq!(
+            traces.expire(Duration::from_secs(0)),
+            vec![key("s1"), key("s2")]
+        );
+        assert!(traces.traces.is_empty());
+    }
+}
@@ -0,0 +1,777 @@
+//! Packet capture source.
+//!
+//! Captures packets with libpcap, from a live network interface or a PCAP
+//! file, decodes their Ethernet, IP, and TCP/UDP/ICMP headers, and inserts
+//! them into the `net::packet::Packet` input stream of a program that imports
+//! the `net::packet` library (see `lib/net/packet.dl`).
+//!
+//! * `PacketSource::live()` captures from an interface in a background
+//!   thread, inserting the packets captured within each flush interval in one
+//!   transaction.
+//! * `load_pcap_file()` inserts the packets of a file, in transactions of at
+//!   most `PacketSourceConfig::batch_size` packets.
+//!
+//! Both accept a BPF filter in `tcpdump` syntax, applied by libpcap before
+//! packets are copied to the program.  Requires the `packet_capture`
+//! feature, which links libpcap.
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use differential_datalog::record::{Record, RelIdentifier, UpdCmd};
+use differential_datalog::scheduler::ClientId;
+use pcap::{Activated, Capture, Linktype};
+
+use crate::api::HDDlog;
+use crate::Relations;
+
+/// Name of the stream declared by the `net::packet` library.
+const PACKET_RELATION: &str = "net::packet::Packet";
+
+/// Configuration of a packet source.
+#[derive(Debug, Clone)]
+pub struct PacketSourceConfig {
+    /// BPF filter, e.g., `tcp port 443`.
+    pub filter: Option<String>,
+    /// Maximal number of bytes captured per packet.  Headers are decoded
+    /// from the captured bytes only.
+    pub snaplen: i32,
+    /// Put the interface in promiscuous mode.
+    pub promiscuous: bool,
+    /// Maximal time live packets are buffered before they are inserted.
+    pub flush_interval: Duration,
+    /// Maximal number of packets inserted in one transaction.
+    pub batch_size: usize,
+    /// Client id used to schedule the source's transactions.
+    pub client: ClientId,
+}
+
+impl Default for PacketSourceConfig {
+    fn default() -> Self {
+        Self {
+            filter: None,
+            snaplen: 256,
+            promiscuous: false,
+            flush_interval: Duration::from_millis(100),
+            batch_size: 10000,
+            client: ClientId::max_value() - 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpHeader {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub proto: u8,
+    pub ttl: u8,
+    pub tos: u8,
+    pub len: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Tcp {
+        src_port: u16,
+        dst_port: u16,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        window: u16,
+    },
+    Udp {
+        src_port: u16,
+        dst_port: u16,
+        len: u16,
+    },
+    Icmp {
+        icmp_type: u8,
+        code: u8,
+    },
+    Other,
+}
+
+/// Decoded packet headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketHeaders {
+    pub ts_ns: u64,
+    pub wire_len: u32,
+    pub src_mac: u64,
+    pub dst_mac: u64,
+    pub vlan: Option<u16>,
+    pub ethertype: u16,
+    pub ip: Option<IpHeader>,
+    pub transport: Transport,
+}
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+/* Link types, from http://www.tcpdump.org/linktypes.html. */
+const LINKTYPE_NULL: i32 = 0;
+const LINKTYPE_ETHERNET: i32 = 1;
+const LINKTYPE_RAW: i32 = 101;
+const LINKTYPE_LOOP: i32 = 108;
+const LINKTYPE_LINUX_SLL: i32 = 113;
+
+fn be16(data: &[u8], at: usize) -> Option<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn mac(data: &[u8]) -> u64 {
+    data.iter().fold(0, |n, b| (n << 8) | u64::from(*b))
+}
+
+/* The ethertype of a raw IP packet, from its version. */
+fn ip_ethertype(data: &[u8]) -> u16 {
+    match data.first().map(|b| b >> 4) {
+        Some(4) => ETHERTYPE_IPV4,
+        Some(6) => ETHERTYPE_IPV6,
+        _ => 0,
+    }
+}
+
+/// Decode the headers of a packet captured with the given link type.
+/// Headers truncated by the capture length are left out.
+pub fn decode_packet(linktype: i32, ts_ns: u64, wire_len: u32, data: &[u8]) -> PacketHeaders {
+    let mut packet = PacketHeaders {
+        ts_ns,
+        wire_len,
+        src_mac: 0,
+        dst_mac: 0,
+        vlan: None,
+        ethertype: 0,
+        ip: None,
+        transport: Transport::Other,
+    };
+    let payload = match linktype {
+        LINKTYPE_ETHERNET if data.len() >= 14 => {
+            packet.dst_mac = mac(&data[0..6]);
+            packet.src_mac = mac(&data[6..12]);
+            let mut at = 12;
+            let mut ethertype = be16(data, at).unwrap();
+            // 802.1Q and 802.1ad tags; the outer VLAN id is reported.
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                match (be16(data, at + 2), be16(data, at + 4)) {
+                    (Some(tci), Some(inner)) => {
+                        packet.vlan.get_or_insert(tci & 0x0fff);
+                        ethertype = inner;
+                        at += 4;
+                    }
+                    _ => break,
+                }
+            }
+            packet.ethertype = ethertype;
+            &data[(at + 2).min(data.len())..]
+        }
+        LINKTYPE_LINUX_SLL if data.len() >= 16 => {
+            packet.ethertype = be16(data, 14).unwrap();
+            &data[16..]
+        }
+        LINKTYPE_NULL | LINKTYPE_LOOP if data.len() >= 4 => {
+            packet.ethertype = ip_ethertype(&data[4..]);
+            &data[4..]
+        }
+        LINKTYPE_RAW => {
+            packet.ethertype = ip_ethertype(data);
+            data
+        }
+        _ => return packet,
+    };
+    let (ip, transport) = match packet.ethertype {
+        ETHERTYPE_IPV4 => match decode_ipv4(payload) {
+            Some(decoded) => decoded,
+            None => return packet,
+        },
+        ETHERTYPE_IPV6 => match decode_ipv6(payload) {
+            Some(decoded) => decoded,
+            None => return packet,
+        },
+        _ => return packet,
+    };
+    packet.ip = Some(ip);
+    packet.transport = transport
+        .and_then(|t| decode_transport(ip.proto, t))
+        .unwrap_or(Transport::Other);
+    packet
+}
+
+/* The IPv4 header and the transport header, unless the packet is a
+ * non-first fragment. */
+fn decode_ipv4(data: &[u8]) -> Option<(IpHeader, Option<&[u8]>)> {
+    if data.len() < 20 {
+        return None;
+    }
+    let header_len = (data[0] & 0x0f) as usize * 4;
+    let fragment_offset = be16(data, 6)? & 0x1fff;
+    let ip = IpHeader {
+        src: IpAddr::from(<[u8; 4]>::try_from(&data[12..16]).unwrap()),
+        dst: IpAddr::from(<[u8; 4]>::try_from(&data[16..20]).unwrap()),
+        proto: data[9],
+        ttl: data[8],
+        tos: data[1],
+        len: be16(data, 2)?,
+    };
+    let transport = if fragment_offset == 0 {
+        data.get(header_len..)
+    } else {
+        None
+    };
+    Some((ip, transport))
+}
+
+/* The IPv6 header and the transport header.  Hop-by-hop, routing, and
+ * destination options headers are skipped; `proto` is the last next header
+ * value. */
+fn decode_ipv6(data: &[u8]) -> Option<(IpHeader, Option<&[u8]>)> {
+    if data.len() < 40 {
+        return None;
+    }
+    let mut ip = IpHeader {
+        src: IpAddr::from(<[u8; 16]>::try_from(&data[8..24]).unwrap()),
+        dst: IpAddr::from(<[u8; 16]>::try_from(&data[24..40]).unwrap()),
+        proto: data[6],
+        ttl: data[7],
+        tos: ((be16(data, 0)? >> 4) & 0xff) as u8,
+        len: be16(data, 4)?.saturating_add(40),
+    };
+    let mut at = 40;
+    let mut transport = None;
+    loop {
+        match ip.proto {
+            0 | 43 | 60 => match (data.get(at), data.get(at + 1)) {
+                (Some(next), Some(len)) => {
+                    ip.proto = *next;
+                    at += (*len as usize + 1) * 8;
+                }
+                _ => break,
+            },
+            // Fragment header: only the first fragment has a transport header.
+            44 => match (data.get(at), be16(data, at + 2)) {
+                (Some(next), Some(offset)) => {
+                    ip.proto = *next;
+                    at += 8;
+                    if offset >> 3 != 0 {
+                        break;
+                    }
+                }
+                _ => break,
+            },
+            _ => {
+                transport = data.get(at..);
+                break;
+            }
+        }
+    }
+    Some((ip, transport))
+}
+
+/* The transport header, unless it is truncated. */
+fn decode_transport(proto: u8, data: &[u8]) -> Option<Transport> {
+    Some(match proto {
+        6 => Transport::Tcp {
+            src_port: be16(data, 0)?,
+            dst_port: be16(data, 2)?,
+            seq: be32(data, 4)?,
+            ack: be32(data, 8)?,
+            flags: *data.get(13)?,
+            window: be16(data, 14)?,
+        },
+        17 => Transport::Udp {
+            src_port: be16(data, 0)?,
+            dst_port: be16(data, 2)?,
+            len: be16(data, 4)?,
+        },
+        1 | 58 => Transport::Icmp {
+            icmp_type: *data.first()?,
+            code: *data.get(1)?,
+        },
+        _ => Transport::Other,
+    })
+}
+
+fn ip_record(addr: &IpAddr) -> Record {
+    match addr {
+        IpAddr::V4(addr) => Record::NamedStruct(
+            Cow::from("net::ipaddr::IpAddrV4"),
+            vec![(Cow::from("addr4"), Record::Int(u32::from(*addr).into()))],
+        ),
+        IpAddr::V6(addr) => Record::NamedStruct(
+            Cow::from("net::ipaddr::IpAddrV6"),
+            vec![(Cow::from("addr6"), Record::Int(u128::from(*addr).into()))],
+        ),
+    }
+}
+
+fn option_record(x: Option<Record>) -> Record {
+    match x {
+        Some(x) => Record::NamedStruct(Cow::from("ddlog_std::Some"), vec![(Cow::from("x"), x)]),
+        None => Record::NamedStruct(Cow::from("ddlog_std::None"), Vec::new()),
+    }
+}
+
+impl PacketHeaders {
+    /// The record of the packet in `net::packet::Packet`.
+    pub fn to_record(&self, source: &str) -> Record {
+        let int = |i: u64| Record::Int(i.into());
+        let ip = self.ip.map(|ip| {
+            Record::NamedStruct(
+                Cow::from("net::packet::IpHeader"),
+                vec![
+                    (Cow::from("src"), ip_record(&ip.src)),
+                    (Cow::from("dst"), ip_record(&ip.dst)),
+                    (Cow::from("proto"), int(ip.proto.into())),
+                    (Cow::from("ttl"), int(ip.ttl.into())),
+                    (Cow::from("tos"), int(ip.tos.into())),
+                    (Cow::from("len"), int(ip.len.into())),
+                ],
+            )
+        });
+        let (constructor, fields) = match self.transport {
+            Transport::Tcp {
+                src_port,
+                dst_port,
+                seq,
+                ack,
+                flags,
+                window,
+            } => (
+                "net::packet::Tcp",
+                vec![
+                    ("src_port", int(src_port.into())),
+                    ("dst_port", int(dst_port.into())),
+                    ("seq", int(seq.into())),
+                    ("ack", int(ack.into())),
+                    ("flags", int(flags.into())),
+                    ("window", int(window.into())),
+                ],
+            ),
+            Transport::Udp {
+                src_port,
+                dst_port,
+                len,
+            } => (
+                "net::packet::Udp",
+                vec![
+                    ("src_port", int(src_port.into())),
+                    ("dst_port", int(dst_port.into())),
+                    ("len", int(len.into())),
+                ],
+            ),
+            Transport::Icmp { icmp_type, code } => (
+                "net::packet::Icmp",
+                vec![
+                    ("icmp_type", int(icmp_type.into())),
+                    ("code", int(code.into())),
+                ],
+            ),
+            Transport::Other => ("net::packet::OtherTransport", Vec::new()),
+        };
+        let transport = Record::NamedStruct(
+            Cow::from(constructor),
+            fields
+                .into_iter()
+                .map(|(name, value)| (Cow::from(name), value))
+                .collect(),
+        );
+        Record::NamedStruct(
+            Cow::from(PACKET_RELATION),
+            vec![
+                (Cow::from("source"), Record::String(source.to_string())),
+                (Cow::from("ts_ns"), int(self.ts_ns)),
+                (Cow::from("wire_len"), int(self.wire_len.into())),
+                (Cow::from("src_mac"), int(self.src_mac)),
+                (Cow::from("dst_mac"), int(self.dst_mac)),
+                (
+                    Cow::from("vlan"),
+                    option_record(self.vlan.map(|vlan| int(vlan.into()))),
+                ),
+                (Cow::from("ethertype"), int(self.ethertype.into())),
+                (Cow::from("ip"), option_record(ip)),
+                (Cow::from("transport"), transport),
+            ],
+        )
+    }
+}
+
+fn check_relation() -> Result<(), String> {
+    Relations::try_from(PACKET_RELATION)
+        .map(|_| ())
+        .map_err(|()| "the program does not import the net::packet library".to_string())
+}
+
+fn insert(hddlog: &HDDlog, client: ClientId, packets: &mut Vec<UpdCmd>) -> Result<(), String> {
+    if packets.is_empty() {
+        return Ok(());
+    }
+    let txn = hddlog.try_start_transaction(client, None)?;
+    txn.apply_updates_dynamic(&mut packets.drain(..))?;
+    txn.commit()
+}
+
+/* Reads the next packet into `packets`.  Returns `Ok(false)` at the end of
+ * a file; read timeouts of live captures are not errors. */
+fn capture_next<T: Activated + ?Sized>(
+    capture: &mut Capture<T>,
+    linktype: Linktype,
+    source: &str,
+    packets: &mut Vec<UpdCmd>,
+) -> Result<bool, String> {
+    match capture.next_packet() {
+        Ok(packet) => {
+            let ts = packet.header.ts;
+            let ts_ns = (ts.tv_sec as u64)
+                .saturating_mul(1_000_000_000)
+                .saturating_add(ts.tv_usec as u64 * 1000);
+            let headers = decode_packet(linktype.0, ts_ns, packet.header.len, packet.data);
+            packets.push(UpdCmd::Insert(
+                RelIdentifier::RelName(Cow::from(PACKET_RELATION)),
+                headers.to_record(source),
+            ));
+            Ok(true)
+        }
+        Err(pcap::Error::TimeoutExpired) => Ok(true),
+        Err(pcap::Error::NoMorePackets) => Ok(false),
+        Err(e) => Err(format!("failed to capture from {}: {}", source, e)),
+    }
+}
+
+fn set_filter<T: Activated + ?Sized>(
+    capture: &mut Capture<T>,
+    config: &PacketSourceConfig,
+) -> Result<(), String> {
+    if let Some(filter) = &config.filter {
+        capture
+            .filter(filter, true)
+            .map_err(|e| format!("invalid filter '{}': {}", filter, e))?;
+    }
+    Ok(())
+}
+
+/// Insert the packets of a PCAP file into `hddlog`.  Returns the number of
+/// packets inserted.
+pub fn load_pcap_file(
+    hddlog: &HDDlog,
+    path: &str,
+    config: &PacketSourceConfig,
+) -> Result<usize, String> {
+    check_relation()?;
+    let mut capture =
+        Capture::from_file(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+    set_filter(&mut capture, config)?;
+    let linktype = capture.get_datalink();
+    let mut packets = Vec::new();
+    let mut total = 0;
+    while capture_next(&mut capture, linktype, path, &mut packets)? {
+        if packets.len() >= config.batch_size {
+            total += packets.len();
+            insert(hddlog, config.client, &mut packets)?;
+        }
+    }
+    total += packets.len();
+    insert(hddlog, config.client, &mut packets)?;
+    Ok(total)
+}
+
+/// A live capture.  Dropping it stops the capture.
+pub struct PacketSource {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    packets: Arc<AtomicU64>,
+    last_error: Arc<Mutex<Option<String>>>,
+}
+
+impl PacketSource {
+    /// Capture packets for `hddlog` from `device`, e.g., `eth0`.  Capturing
+    /// usually requires elevated privileges.
+    pub fn live(
+        hddlog: Arc<HDDlog>,
+        device: &str,
+        config: PacketSourceConfig,
+    ) -> Result<Self, String> {
+        check_relation()?;
+        let timeout = config.flush_interval.as_millis().max(1) as i32;
+        let mut capture = Capture::from_device(device)
+            .and_then(|c| {
+                c.promisc(config.promiscuous)
+                    .snaplen(config.snaplen)
+                    .timeout(timeout)
+                    .open()
+            })
+            .map_err(|e| format!("failed to capture from {}: {}", device, e))?;
+        set_filter(&mut capture, &config)?;
+        let linktype = capture.get_datalink();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let packets = Arc::new(AtomicU64::new(0));
+        let last_error = Arc::new(Mutex::new(None));
+        let thread = {
+            let source = device.to_string();
+            let stop = stop.clone();
+            let count = packets.clone();
+            let last_error = last_error.clone();
+            thread::Builder::new()
+                .name("ddlog-capture".to_string())
+                .spawn(move || {
+                    let mut pending = Vec::new();
+                    let mut last_flush = Instant::now();
+                    while !stop.load(Ordering::Acquire) {
+                        if let Err(e) = capture_next(&mut capture, linktype, &source, &mut pending)
+                        {
+                            *last_error.lock().unwrap() = Some(e);
+                            break;
+                        }
+                        if last_flush.elapsed() >= config.flush_interval
+                            || pending.len() >= config.batch_size
+                        {
+                            last_flush = Instant::now();
+                            count.fetch_add(pending.len() as u64, Ordering::Relaxed);
+                            if let Err(e) = insert(&hddlog, config.client, &mut pending) {
+                                pending.clear();
+                                *last_error.lock().unwrap() = Some(e);
+                            }
+                        }
+                    }
+                    count.fetch_add(pending.len() as u64, Ordering::Relaxed);
+                    let _ = insert(&hddlog, config.client, &mut pending);
+                })
+                .map_err(|e| format!("failed to start packet capture: {}", e))?
+        };
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+            packets,
+            last_error,
+        })
+    }
+
+    /// The number of packets captured so far.
+    pub fn packets(&self) -> u64 {
+        self.packets.load(Ordering::Relaxed)
+    }
+
+    /// The last error encountered, if any.  The capture stops on errors
+    /// other than failed transactions.
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+}
+
+impl Drop for PacketSource {
+    fn drop(&mut self) {
+        // The thread notices within one read timeout.
+        self.stop.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+    const DST_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+
+    fn ethernet(tags: &[u16], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = DST_MAC.to_vec();
+        frame.extend_from_slice(&SRC_MAC);
+        for tag in tags {
+            frame.extend_from_slice(&0x8100u16.to_be_bytes());
+            frame.extend_from_slice(&tag.to_be_bytes());
+        }
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// An IPv4 packet from 10.0.0.1 to 10.0.0.2 with TTL 64.
+    fn ipv4(proto: u8, fragment_offset: u16, transport: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0x10];
+        packet.extend_from_slice(&(20 + transport.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 1]); // id
+        packet.extend_from_slice(&fragment_offset.to_be_bytes());
+        packet.extend_from_slice(&[64, proto, 0, 0]);
+        packet.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        packet.extend_from_slice(transport);
+        packet
+    }
+
+    fn tcp() -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&40000u16.to_be_bytes());
+        header.extend_from_slice(&443u16.to_be_bytes());
+        header.extend_from_slice(&1u32.to_be_bytes());
+        header.extend_from_slice(&2u32.to_be_bytes());
+        header.extend_from_slice(&[0x50, 0x12]); // data offset and flags
+        header.extend_from_slice(&1024u16.to_be_bytes());
+        header.extend_from_slice(&[0; 4]); // checksum and urgent pointer
+        header
+    }
+
+    fn ipv4_header(proto: u8, len: u16) -> IpHeader {
+        IpHeader {
+            src: IpAddr::from([10, 0, 0, 1]),
+            dst: IpAddr::from([10, 0, 0, 2]),
+            proto,
+            ttl: 64,
+            tos: 0x10,
+            len,
+        }
+    }
+
+    #[test]
+    fn decode_ethernet_packets() {
+        let frame = ethernet(&[0x2064, 200], ETHERTYPE_IPV4, &ipv4(6, 0, &tcp()));
+        assert_eq!(
+            decode_packet(LINKTYPE_ETHERNET, 5, 1500, &frame),
+            PacketHeaders {
+                ts_ns: 5,
+                wire_len: 1500,
+                src_mac: 0x0200_0000_0001,
+                dst_mac: 0x0200_0000_0002,
+                vlan: Some(100),
+                ethertype: ETHERTYPE_IPV4,
+                ip: Some(ipv4_header(6, 40)),
+                transport: Transport::Tcp {
+                    src_port: 40000,
+                    dst_port: 443,
+                    seq: 1,
+                    ack: 2,
+                    flags: 0x12,
+                    window: 1024,
+                },
+            }
+        );
+
+        // Non-IP frames.
+        let packet = decode_packet(LINKTYPE_ETHERNET, 0, 60, &ethernet(&[], 0x0806, &[0; 28]));
+        assert_eq!(packet.ethertype, 0x0806);
+        assert_eq!(packet.ip, None);
+        assert_eq!(packet.transport, Transport::Other);
+    }
+
+    #[test]
+    fn decode_ipv6_packets() {
+        let mut packet = vec![0x60, 0x20, 0, 0];
+        packet.extend_from_slice(&16u16.to_be_bytes()); // payload length
+        packet.extend_from_slice(&[0, 255]); // hop-by-hop options, hop limit
+        let src: std::net::Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: std::net::Ipv6Addr = "2001:db8::2".parse().unwrap();
+        packet.extend_from_slice(&src.octets());
+        packet.extend_from_slice(&dst.octets());
+        packet.extend_from_slice(&[17, 0, 0, 0, 0, 0, 0, 0]);
+        packet.extend_from_slice(&[0, 53, 0x14, 0xe9, 0, 8, 0, 0]);
+
+        let headers = decode_packet(LINKTYPE_RAW, 0, 64, &packet);
+        assert_eq!(headers.ethertype, ETHERTYPE_IPV6);
+        assert_eq!(
+            headers.ip,
+            Some(IpHeader {
+                src: IpAddr::from(src),
+                dst: IpAddr::from(dst),
+                proto: 17,
+                ttl: 255,
+                tos: 2,
+                len: 56,
+            })
+        );
+        assert_eq!(
+            headers.transport,
+            Transport::Udp {
+                src_port: 53,
+                dst_port: 5353,
+                len: 8
+            }
+        );
+    }
+
+    #[test]
+    fn decode_link_types() {
+        let icmp = ipv4(1, 0, &[8, 0, 0, 0]);
+        let mut sll = vec![0; 14];
+        sll.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        sll.extend_from_slice(&icmp);
+        let mut null = vec![2, 0, 0, 0];
+        null.extend_from_slice(&icmp);
+        for (linktype, data) in &[
+            (LINKTYPE_LINUX_SLL, sll),
+            (LINKTYPE_NULL, null.clone()),
+            (LINKTYPE_LOOP, null),
+            (LINKTYPE_RAW, icmp.clone()),
+        ] {
+            let headers = decode_packet(*linktype, 0, 24, data);
+            assert_eq!(headers.src_mac, 0);
+            assert_eq!(headers.ip, Some(ipv4_header(1, 24)), "{}", linktype);
+            assert_eq!(
+                headers.transport,
+                Transport::Icmp {
+                    icmp_type: 8,
+                    code: 0
+                },
+                "{}",
+                linktype
+            );
+        }
+        // Unknown link types.
+        assert_eq!(decode_packet(147, 0, 24, &icmp).ip, None);
+    }
+
+    #[test]
+    fn decode_truncated_packets() {
+        let frame = ethernet(&[], ETHERTYPE_IPV4, &ipv4(6, 0, &tcp()));
+        // Transport headers truncated by the capture length.
+        let packet = decode_packet(LINKTYPE_ETHERNET, 0, 54, &frame[..40]);
+        assert_eq!(packet.ip, Some(ipv4_header(6, 40)));
+        assert_eq!(packet.transport, Transport::Other);
+        // IP headers.
+        let packet = decode_packet(LINKTYPE_ETHERNET, 0, 54, &frame[..30]);
+        assert_eq!(packet.ethertype, ETHERTYPE_IPV4);
+        assert_eq!(packet.ip, None);
+        // Link-layer headers.
+        let packet = decode_packet(LINKTYPE_ETHERNET, 0, 54, &frame[..10]);
+        assert_eq!((packet.dst_mac, packet.ethertype), (0, 0));
+        // A truncated VLAN tag leaves the ethertype at the tag type.
+        let frame = ethernet(&[100], ETHERTYPE_IPV4, &[]);
+        assert_eq!(
+            decode_packet(LINKTYPE_ETHERNET, 0, 54, &frame[..16]).ethertype,
+            0x8100
+        );
+
+        // Non-first fragments have no transport header.
+        let packet = decode_packet(LINKTYPE_RAW, 0, 40, &ipv4(6, 185, &tcp()));
+        assert_eq!(packet.ip.unwrap().proto, 6);
+        assert_eq!(packet.transport, Transport::Other);
+    }
+
+    #[test]
+    fn packet_records() {
+        let frame = ethernet(&[100], ETHERTYPE_IPV4, &ipv4(1, 0, &[0, 0]));
+        let record = decode_packet(LINKTYPE_ETHERNET, 5, 60, &frame)
+            .to_record("eth0")
+            .to_string();
+        assert_eq!(
+            record,
+            "net::packet::Packet{.source = \"eth0\", .ts_ns = 5, .wire_len = 60, \
+             .src_mac = 2199023255553, .dst_mac = 2199023255554, \
+             .vlan = ddlog_std::Some{.x = 100}, .ethertype = 2048, \
+             .ip = ddlog_std::Some{.x = net::packet::IpHeader{\
+             .src = net::ipaddr::IpAddrV4{.addr4 = 167772161}, \
+             .dst = net::ipaddr::IpAddrV4{.addr4 = 167772162}, \
+             .proto = 1, .ttl = 64, .tos = 16, .len = 22}}, \
+             .transport = net::packet::Icmp{.icmp_type = 0, .code = 0}}"
+        );
+    }
+}
@@ -0,0 +1,592 @@
+//! Mirror output relations into PostgreSQL tables.
+//!
+//! `PostgresSink` is the PostgreSQL counterpart of `SqliteSink`: it keeps one
+//! table per selected output relation in sync with the relation, applying
+//! the changes made by each DDlog commit (see `HDDlog::subscribe_commits()`)
+//! in exactly one PostgreSQL transaction.  Tables have the same layout as
+//! those of the SQLite sink: a `_ddlog_key` primary key holding the text form
+//! of the record, one column per field of records that are structs with
+//! named fields, and a `_ddlog_weight` column.  Field columns are added as
+//! they are seen, with type `BOOLEAN`, `NUMERIC`, `DOUBLE PRECISION`, or
+//! `TEXT` (for all other values), so fields with the same name must have the
+//! same type in all constructors of a relation's type.
+//!
+//! Transactions are aligned with DDlog commits through a progress table,
+//! `_ddlog_sink_progress`, that records the last commit applied by each sink
+//! (identified by `PostgresSinkConfig::name`) in the same transaction as the
+//! changes.  A commit that fails to apply, e.g., because the connection was
+//! lost, is retried on a fresh connection up to `PostgresSinkConfig::retries`
+//! times; if the previous attempt did commit and only its acknowledgement
+//! was lost, the progress table shows it and the commit is not applied
+//! twice.  DDlog commits wait for the sink, so PostgreSQL is never more than
+//! one commit behind the program.  If all retries fail, the sink stops (see
+//! `PostgresSink::error()`).
+//!
+//! When the program stores the contents of output relations (`do_store`),
+//! the tables are truncated and loaded with `COPY` when the sink is
+//! attached; the sink should be attached while no transaction is in
+//! progress.  Per-commit changes are also copied, into a temporary staging
+//! table, and merged into the target table with a single upsert.  Requires
+//! the `postgresql` feature.
+//!
+//! ```ignore
+//! let sink = PostgresSink::attach(
+//!     &hddlog,
+//!     || postgres::Client::connect("host=localhost user=ddlog", postgres::NoTls),
+//!     &["Path", "Reachable"],
+//!     PostgresSinkConfig::default(),
+//! )?;
+//! ```
+
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use postgres::{Client, Transaction};
+
+use differential_datalog::ddval::DDValue;
+use differential_datalog::program::RelId;
+use differential_datalog::record::{IntoRecord, Record};
+
+use crate::api::{CommitSubscriptionId, HDDlog};
+use crate::update_handler::ChangelogBatch;
+use crate::Relations;
+
+const KEY_COLUMN: &str = "_ddlog_key";
+const WEIGHT_COLUMN: &str = "_ddlog_weight";
+const PROGRESS_TABLE: &str = "_ddlog_sink_progress";
+const STAGING_TABLE: &str = "_ddlog_staging";
+
+/// Opens a connection to the database, e.g., with TLS configured.
+pub type Connect = Box<dyn FnMut() -> Result<Client, postgres::Error> + Send>;
+
+/// Configuration of a PostgreSQL sink.
+#[derive(Debug, Clone)]
+pub struct PostgresSinkConfig {
+    /// Identifies the sink in the progress table; sinks writing to the same
+    /// database must have different names.
+    pub name: String,
+    /// Schema containing the tables; by default, the current schema of the
+    /// connection.
+    pub schema: Option<String>,
+    /// Table names of relations, by relation name.  Other relations are
+    /// stored in a table named after the relation in lower case, with
+    /// characters that are not valid in SQL identifiers replaced by `_`.
+    pub tables: BTreeMap<String, String>,
+    /// Number of times a failed commit is retried on a new connection.
+    pub retries: u32,
+    /// Delay before each retry.
+    pub retry_delay: Duration,
+}
+
+impl Default for PostgresSinkConfig {
+    fn default() -> Self {
+        Self {
+            name: "ddlog".to_string(),
+            schema: None,
+            tables: BTreeMap::new(),
+            retries: 3,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SqlTable {
+    /// Qualified and quoted table name.
+    name: String,
+    /// Unqualified table name, as stored in the catalog.
+    table: String,
+    /// Field columns created so far.
+    columns: HashSet<String>,
+}
+
+/* Changes to one relation merged by record: weight and field values. */
+type Merged = BTreeMap<String, (isize, Vec<(String, Record)>)>;
+
+struct State {
+    connect: Connect,
+    client: Option<Client>,
+    config: PostgresSinkConfig,
+    /// Qualified and quoted name of the progress table.
+    progress: String,
+    tables: BTreeMap<RelId, SqlTable>,
+    /// The columns of the tables must be re-read after a failed attempt.
+    stale: bool,
+    /// The error that stopped the sink.
+    error: Option<String>,
+}
+
+/// A sink attached to a running program.  Dropping it detaches the sink;
+/// the tables are left in the database.
+pub struct PostgresSink<'a> {
+    hddlog: &'a HDDlog,
+    subscription: CommitSubscriptionId,
+    state: Arc<Mutex<State>>,
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn table_name(relation: &str) -> String {
+    relation
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn column_type(record: &Record) -> &'static str {
+    match record {
+        Record::Bool(_) => "BOOLEAN",
+        Record::Int(_) => "NUMERIC",
+        Record::Float(_) | Record::Double(_) => "DOUBLE PRECISION",
+        _ => "TEXT",
+    }
+}
+
+/* Escapes a value for the text format of `COPY`. */
+fn copy_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t")
+}
+
+fn copy_float(f: f64) -> String {
+    if f.is_nan() {
+        "NaN".to_string()
+    } else if f.is_infinite() {
+        if f > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else {
+        f.to_string()
+    }
+}
+
+fn copy_value(record: &Record) -> String {
+    match record {
+        Record::Bool(b) => if *b { "t" } else { "f" }.to_string(),
+        Record::Int(i) => i.to_string(),
+        Record::Float(f) => copy_float(f.into_inner() as f64),
+        Record::Double(d) => copy_float(d.into_inner()),
+        Record::String(s) => copy_escape(s),
+        record => copy_escape(&record.to_string()),
+    }
+}
+
+fn merge<'b, I>(changes: I) -> Merged
+where
+    I: IntoIterator<Item = (&'b DDValue, isize)>,
+{
+    let mut merged = Merged::new();
+    for (value, weight) in changes {
+        let record = value.clone().into_record();
+        let key = record.to_string();
+        let entry = merged.entry(key).or_insert_with(|| {
+            let fields = match record {
+                Record::NamedStruct(_, fields) => fields
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            (0, fields)
+        });
+        entry.0 += weight;
+    }
+    merged
+}
+
+impl SqlTable {
+    fn new(schema: Option<&str>, table: String) -> Self {
+        let name = match schema {
+            Some(schema) => format!("{}.{}", quote_ident(schema), quote_ident(&table)),
+            None => quote_ident(&table),
+        };
+        Self {
+            name,
+            table,
+            columns: HashSet::new(),
+        }
+    }
+
+    fn create(
+        &mut self,
+        tx: &mut Transaction,
+        schema: Option<&str>,
+    ) -> Result<(), postgres::Error> {
+        tx.batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} ({} TEXT PRIMARY KEY, {} BIGINT NOT NULL)",
+            self.name, KEY_COLUMN, WEIGHT_COLUMN
+        ))?;
+        self.load_columns(tx, schema)
+    }
+
+    /* Reads the field columns of the table, which may be left over from a
+     * previous run. */
+    fn load_columns(
+        &mut self,
+        tx: &mut Transaction,
+        schema: Option<&str>,
+    ) -> Result<(), postgres::Error> {
+        let rows = tx.query(
+            "SELECT column_name FROM information_schema.columns \
+             WHERE table_schema = COALESCE($1, current_schema()) AND table_name = $2",
+            &[&schema, &self.table],
+        )?;
+        self.columns = rows
+            .iter()
+            .map(|row| row.get::<_, String>(0))
+            .filter(|column| column != KEY_COLUMN && column != WEIGHT_COLUMN)
+            .collect();
+        Ok(())
+    }
+
+    fn add_columns(
+        &mut self,
+        tx: &mut Transaction,
+        merged: &Merged,
+    ) -> Result<(), postgres::Error> {
+        for (_, fields) in merged.values() {
+            for (column, value) in fields.iter() {
+                if !self.columns.contains(column) {
+                    tx.batch_execute(&format!(
+                        "ALTER TABLE {} ADD COLUMN {} {}",
+                        self.name,
+                        quote_ident(column),
+                        column_type(value)
+                    ))?;
+                    self.columns.insert(column.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /* Copies `merged` into `table`, which has the columns of this table. */
+    fn copy(
+        &self,
+        tx: &mut Transaction,
+        table: &str,
+        merged: &Merged,
+    ) -> Result<(), postgres::Error> {
+        let columns: Vec<&String> = self.columns.iter().collect();
+        let mut header = vec![KEY_COLUMN.to_string(), WEIGHT_COLUMN.to_string()];
+        header.extend(columns.iter().map(|column| quote_ident(column)));
+        let mut writer = tx.copy_in(&*format!(
+            "COPY {} ({}) FROM STDIN",
+            table,
+            header.join(", ")
+        ))?;
+        for (key, (weight, fields)) in merged.iter() {
+            let mut line = format!("{}\t{}", copy_escape(key), weight);
+            for column in columns.iter() {
+                line.push('\t');
+                match fields.iter().find(|(name, _)| name == *column) {
+                    Some((_, value)) => line.push_str(&copy_value(value)),
+                    None => line.push_str("\\N"),
+                }
+            }
+            line.push('\n');
+            // Errors writing to the server are reported by `finish()`.
+            let _ = writer.write_all(line.as_bytes());
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    fn load(&mut self, tx: &mut Transaction, merged: &Merged) -> Result<(), postgres::Error> {
+        tx.batch_execute(&format!("TRUNCATE {}", self.name))?;
+        self.add_columns(tx, merged)?;
+        self.copy(tx, &self.name, merged)
+    }
+
+    fn upsert(&mut self, tx: &mut Transaction, merged: &Merged) -> Result<(), postgres::Error> {
+        self.add_columns(tx, merged)?;
+        tx.batch_execute(&format!(
+            "CREATE TEMPORARY TABLE {} (LIKE {}) ON COMMIT DROP",
+            STAGING_TABLE, self.name
+        ))?;
+        self.copy(tx, STAGING_TABLE, merged)?;
+        tx.batch_execute(&format!(
+            "INSERT INTO {table} AS t SELECT * FROM {staging} \
+             ON CONFLICT ({key}) DO UPDATE SET {weight} = t.{weight} + EXCLUDED.{weight};
+             DELETE FROM {table} AS t USING {staging} AS s \
+             WHERE t.{key} = s.{key} AND t.{weight} <= 0;
+             DROP TABLE {staging}",
+            table = self.name,
+            staging = STAGING_TABLE,
+            key = KEY_COLUMN,
+            weight = WEIGHT_COLUMN
+        ))
+    }
+}
+
+/* Returns the connection, reconnecting if it has been lost. */
+fn connection<'c>(
+    client: &'c mut Option<Client>,
+    connect: &mut Connect,
+) -> Result<&'c mut Client, postgres::Error> {
+    if client.as_ref().map_or(true, |client| client.is_closed()) {
+        *client = Some(connect()?);
+    }
+    Ok(client.as_mut().unwrap())
+}
+
+impl State {
+    fn create_tables(&mut self) -> Result<(), postgres::Error> {
+        let client = connection(&mut self.client, &mut self.connect)?;
+        let mut tx = client.transaction()?;
+        tx.batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (sink TEXT PRIMARY KEY, last_commit BIGINT NOT NULL)",
+            self.progress
+        ))?;
+        for table in self.tables.values_mut() {
+            table.create(&mut tx, self.config.schema.as_deref())?;
+        }
+        // Commit numbers start over with every run of the program.
+        tx.execute(
+            &*format!(
+                "INSERT INTO {} VALUES ($1, 0) ON CONFLICT (sink) DO UPDATE SET last_commit = 0",
+                self.progress
+            ),
+            &[&self.config.name],
+        )?;
+        tx.commit()
+    }
+
+    fn load(
+        &mut self,
+        snapshot: &BTreeMap<RelId, Vec<(DDValue, isize)>>,
+    ) -> Result<(), postgres::Error> {
+        let client = connection(&mut self.client, &mut self.connect)?;
+        let mut tx = client.transaction()?;
+        for (relid, table) in self.tables.iter_mut() {
+            let facts = snapshot.get(relid).into_iter().flatten();
+            table.load(&mut tx, &merge(facts.map(|(v, w)| (v, *w))))?;
+        }
+        tx.commit()
+    }
+
+    fn try_apply(&mut self, batches: &[ChangelogBatch]) -> Result<(), postgres::Error> {
+        let commit = batches.first().map_or(0, |batch| batch.commit) as i64;
+        let client = connection(&mut self.client, &mut self.connect)?;
+        let mut tx = client.transaction()?;
+        if self.stale {
+            // Columns added by a failed transaction have been rolled back.
+            for table in self.tables.values_mut() {
+                table.load_columns(&mut tx, self.config.schema.as_deref())?;
+            }
+            self.stale = false;
+        }
+        let applied: i64 = tx
+            .query_one(
+                &*format!(
+                    "SELECT last_commit FROM {} WHERE sink = $1 FOR UPDATE",
+                    self.progress
+                ),
+                &[&self.config.name],
+            )?
+            .get(0);
+        if applied >= commit {
+            // A previous attempt committed, but its acknowledgement was lost.
+            return Ok(());
+        }
+        for batch in batches {
+            if let Some(table) = self.tables.get_mut(&batch.relid) {
+                table.upsert(&mut tx, &merge(batch.changes.iter().map(|(v, w)| (v, *w))))?;
+            }
+        }
+        tx.execute(
+            &*format!(
+                "UPDATE {} SET last_commit = $2 WHERE sink = $1",
+                self.progress
+            ),
+            &[&self.config.name, &commit],
+        )?;
+        tx.commit()
+    }
+
+    fn apply_commit(&mut self, batches: &[ChangelogBatch]) {
+        if self.error.is_some() {
+            // The tables are out of sync; stop updating them.
+            return;
+        }
+        let mut attempt = 0;
+        loop {
+            let e = match self.try_apply(batches) {
+                Ok(()) => return,
+                Err(e) => e,
+            };
+            // Start over on a new connection.
+            self.client = None;
+            self.stale = true;
+            if attempt == self.config.retries {
+                self.error = Some(format!(
+                    "failed to apply commit {} after {} attempts: {}",
+                    batches.first().map_or(0, |batch| batch.commit),
+                    attempt + 1,
+                    e
+                ));
+                return;
+            }
+            attempt += 1;
+            thread::sleep(self.config.retry_delay);
+        }
+    }
+}
+
+impl<'a> PostgresSink<'a> {
+    /// Mirror output relations `relations` of `hddlog` into the database
+    /// that `connect` connects to, creating their tables if necessary.
+    pub fn attach<F>(
+        hddlog: &'a HDDlog,
+        connect: F,
+        relations: &[&str],
+        config: PostgresSinkConfig,
+    ) -> Result<Self, String>
+    where
+        F: FnMut() -> Result<Client, postgres::Error> + Send + 'static,
+    {
+        let mut tables = BTreeMap::new();
+        for relation in relations {
+            let relid = match Relations::try_from(*relation) {
+                Ok(rel) if rel.is_output() => rel as RelId,
+                _ => return Err(format!("unknown output relation {}", relation)),
+            };
+            let table = config
+                .tables
+                .get(*relation)
+                .cloned()
+                .unwrap_or_else(|| table_name(relation));
+            tables.insert(relid, SqlTable::new(config.schema.as_deref(), table));
+        }
+        let relids: Vec<RelId> = tables.keys().cloned().collect();
+        let mut state = State {
+            connect: Box::new(connect),
+            client: None,
+            progress: SqlTable::new(config.schema.as_deref(), PROGRESS_TABLE.to_string()).name,
+            config,
+            tables,
+            stale: false,
+            error: None,
+        };
+        state
+            .create_tables()
+            .map_err(|e| format!("failed to create tables in PostgreSQL: {}", e))?;
+
+        if hddlog.db.is_some() {
+            let snapshot = hddlog.dump_stored(&relids)?;
+            state
+                .load(&snapshot)
+                .map_err(|e| format!("failed to copy relation contents: {}", e))?;
+        }
+
+        let state = Arc::new(Mutex::new(state));
+        let subscription = {
+            let state = state.clone();
+            hddlog.subscribe_commits(
+                &relids,
+                Arc::new(move |batches| state.lock().unwrap().apply_commit(batches)),
+            )?
+        };
+        Ok(Self {
+            hddlog,
+            subscription,
+            state,
+        })
+    }
+
+    /// The error that stopped the sink, if any.  Once a commit fails to
+    /// apply after all retries, the tables no longer match the relations and
+    /// the sink ignores further commits; the relations can be mirrored again
+    /// by attaching a new sink.
+    pub fn error(&self) -> Option<String> {
+        self.state.lock().unwrap().error.clone()
+    }
+}
+
+impl Drop for PostgresSink<'_> {
+    fn drop(&mut self) {
+        self.hddlog.unsubscribe_commits(self.subscription);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use differential_datalog::ddval::DDValConvert;
+    use num::BigInt;
+    use ordered_float::OrderedFloat;
+
+    #[test]
+    fn table_names() {
+        assert_eq!(table_name("Reachable"), "reachable");
+        assert_eq!(table_name("net::Path"), "net__path");
+        assert_eq!(quote_ident(r#"a"b"#), r#""a""b""#);
+        assert_eq!(
+            SqlTable::new(Some("ddlog"), "path".to_string()).name,
+            r#""ddlog"."path""#
+        );
+    }
+
+    #[test]
+    fn copy_values() {
+        assert_eq!(copy_value(&Record::Bool(true)), "t");
+        assert_eq!(copy_value(&Record::Bool(false)), "f");
+        let big: BigInt = "1180591620717411303424".parse().unwrap();
+        assert_eq!(copy_value(&Record::Int(big)), "1180591620717411303424");
+        assert_eq!(copy_value(&Record::Double(OrderedFloat(0.5))), "0.5");
+        assert_eq!(copy_value(&Record::Double(OrderedFloat(f64::NAN))), "NaN");
+        assert_eq!(
+            copy_value(&Record::Float(OrderedFloat(f32::NEG_INFINITY))),
+            "-Infinity"
+        );
+        assert_eq!(
+            copy_value(&Record::String("a\\b\tc\nd\re".to_string())),
+            "a\\\\b\\tc\\nd\\re"
+        );
+        assert_eq!(
+            copy_value(&Record::Array(
+                differential_datalog::record::CollectionKind::Vector,
+                vec![Record::String("x\ty".to_string())]
+            )),
+            "[\"x\\ty\"]"
+        );
+    }
+
+    #[test]
+    fn column_types() {
+        assert_eq!(column_type(&Record::Bool(true)), "BOOLEAN");
+        assert_eq!(column_type(&Record::Int(BigInt::from(1))), "NUMERIC");
+        assert_eq!(
+            column_type(&Record::Double(OrderedFloat(1.0))),
+            "DOUBLE PRECISION"
+        );
+        assert_eq!(column_type(&Record::String(String::new())), "TEXT");
+    }
+
+    #[test]
+    fn merge_changes() {
+        let a = "a".to_string().into_ddvalue();
+        let b = "b".to_string().into_ddvalue();
+        let merged = merge(vec![(&a, 1), (&b, 1), (&a, -1), (&b, 1)]);
+        assert_eq!(
+            merged
+                .iter()
+                .map(|(key, (weight, fields))| (key.as_str(), *weight, fields.len()))
+                .collect::<Vec<_>>(),
+            vec![("\"a\"", 0, 0), ("\"b\"", 2, 0)]
+        );
+    }
+}
@@ -0,0 +1,1000 @@
+//! Zeek and Suricata log ingestion.
+//!
+//! Parses Zeek logs, in Zeek's TSV format or as JSON lines, and Suricata EVE
+//! JSON logs into records of the relations declared by the `zeek` and
+//! `suricata` libraries (see `lib/zeek.dl` and `lib/suricata.dl`):
+//!
+//! * `ZeekParser` parses the `conn`, `dns`, `http`, `ssl`, and `notice`
+//!   logs.  TSV logs describe their fields in their header; the log of JSON
+//!   lines is taken from their `_path` field or from the path passed to the
+//!   parser, e.g., derived from the file name.
+//! * `parse_eve()` parses `alert`, `flow`, `dns`, `http`, and `tls` events.
+//!
+//! Lines of other logs and event types are skipped.  `LogIngester` inserts
+//! parsed records into a program, and removes them again once they are older
+//! than a retention period, measured in log time so that replayed logs
+//! expire like live ones.  Requires the `security_logs` feature.
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::BufRead;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use serde_json::{Map, Number, Value};
+
+use differential_datalog::record::{CollectionKind, Record, RelIdentifier, UpdCmd};
+use differential_datalog::scheduler::ClientId;
+
+use crate::api::HDDlog;
+use crate::Relations;
+
+/// A record of one of the `zeek` or `suricata` relations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    /// Relation name, e.g., `zeek::Conn`.
+    pub relation: &'static str,
+    /// Log time in microseconds since the UNIX epoch.
+    pub ts_us: u64,
+    pub record: Record,
+}
+
+/* Builds the fields of a record from a JSON object, whose keys are Zeek or
+ * EVE field names. */
+struct Fields<'a> {
+    object: &'a Map<String, Value>,
+    fields: Vec<(Cow<'static, str>, Record)>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(object: &'a Map<String, Value>) -> Self {
+        Self {
+            object,
+            fields: Vec::new(),
+        }
+    }
+
+    /* The value at a path of nested objects, e.g., `alert.signature`.  Zeek
+     * JSON logs use dotted keys instead, e.g., `id.orig_h`. */
+    fn get(&self, path: &str) -> Option<&'a Value> {
+        if let Some(v) = self.object.get(path) {
+            return Some(v).filter(|v| !v.is_null());
+        }
+        let mut components = path.split('.');
+        let mut value = self.object.get(components.next()?)?;
+        for component in components {
+            value = match value {
+                Value::Array(elements) => elements.get(component.parse::<usize>().ok()?)?,
+                _ => value.get(component)?,
+            };
+        }
+        Some(value).filter(|v| !v.is_null())
+    }
+
+    fn push(&mut self, name: &'static str, value: Record) {
+        self.fields.push((Cow::from(name), value));
+    }
+
+    fn string(&mut self, name: &'static str, path: &str) {
+        let s = self.opt_string(path).unwrap_or_default();
+        self.push(name, Record::String(s));
+    }
+
+    fn opt_string(&self, path: &str) -> Option<String> {
+        self.get(path).map(|v| match v {
+            Value::String(s) => s.clone(),
+            v => v.to_string(),
+        })
+    }
+
+    fn option_string(&mut self, name: &'static str, path: &str) {
+        let s = self.opt_string(path).map(Record::String);
+        self.push(name, option_record(s));
+    }
+
+    fn opt_uint(&self, path: &str) -> Result<Option<u64>, String> {
+        match self.get(path) {
+            None => Ok(None),
+            Some(Value::Number(n)) => n
+                .as_u64()
+                .map(Some)
+                .ok_or_else(|| format!("invalid {} {}", path, n)),
+            Some(Value::String(s)) => s
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid {} '{}'", path, s)),
+            Some(v) => Err(format!("invalid {} {}", path, v)),
+        }
+    }
+
+    fn uint(&mut self, name: &'static str, path: &str) -> Result<(), String> {
+        let n = self.opt_uint(path)?.unwrap_or(0);
+        self.push(name, Record::Int(n.into()));
+        Ok(())
+    }
+
+    fn option_uint(&mut self, name: &'static str, path: &str) -> Result<(), String> {
+        let n = self.opt_uint(path)?.map(|n| Record::Int(n.into()));
+        self.push(name, option_record(n));
+        Ok(())
+    }
+
+    fn bool(&mut self, name: &'static str, path: &str) {
+        let b = match self.get(path) {
+            Some(Value::Bool(b)) => *b,
+            Some(Value::String(s)) => s == "T" || s == "true",
+            _ => false,
+        };
+        self.push(name, Record::Bool(b));
+    }
+
+    fn opt_ip(&self, path: &str) -> Result<Option<IpAddr>, String> {
+        self.opt_string(path)
+            .map(|s| s.parse().map_err(|_| format!("invalid {} '{}'", path, s)))
+            .transpose()
+    }
+
+    fn ip(&mut self, name: &'static str, path: &str) -> Result<(), String> {
+        let addr = self
+            .opt_ip(path)?
+            .ok_or_else(|| format!("missing {}", path))?;
+        self.push(name, ip_record(&addr));
+        Ok(())
+    }
+
+    fn option_ip(&mut self, name: &'static str, path: &str) -> Result<(), String> {
+        let addr = self.opt_ip(path)?.map(|addr| ip_record(&addr));
+        self.push(name, option_record(addr));
+        Ok(())
+    }
+
+    fn strings(&mut self, name: &'static str, values: Vec<String>) {
+        let values = values.into_iter().map(Record::String).collect();
+        self.push(name, Record::Array(CollectionKind::Vector, values));
+    }
+
+    /* A timestamp in seconds since the epoch (Zeek) or in RFC 3339 format
+     * (EVE, or Zeek with ISO 8601 JSON timestamps). */
+    fn timestamp_us(&self, path: &str) -> Result<u64, String> {
+        match self.get(path) {
+            Some(Value::Number(n)) => Ok(seconds_to_us(n)),
+            Some(Value::String(s)) => parse_timestamp_us(s),
+            _ => Err(format!("missing {}", path)),
+        }
+    }
+
+    fn timestamp(&mut self, name: &'static str, path: &str) -> Result<u64, String> {
+        let ts = self.timestamp_us(path)?;
+        self.push(name, Record::Int(ts.into()));
+        Ok(ts)
+    }
+
+    fn finish(self, relation: &'static str, ts_us: u64) -> LogRecord {
+        LogRecord {
+            relation,
+            ts_us,
+            record: Record::NamedStruct(Cow::from(relation), self.fields),
+        }
+    }
+}
+
+fn seconds_to_us(n: &Number) -> u64 {
+    (n.as_f64().unwrap_or(0.0) * 1e6).round().max(0.0) as u64
+}
+
+fn ip_record(addr: &IpAddr) -> Record {
+    match addr {
+        IpAddr::V4(addr) => Record::NamedStruct(
+            Cow::from("net::ipaddr::IpAddrV4"),
+            vec![(Cow::from("addr4"), Record::Int(u32::from(*addr).into()))],
+        ),
+        IpAddr::V6(addr) => Record::NamedStruct(
+            Cow::from("net::ipaddr::IpAddrV6"),
+            vec![(Cow::from("addr6"), Record::Int(u128::from(*addr).into()))],
+        ),
+    }
+}
+
+fn option_record(x: Option<Record>) -> Record {
+    match x {
+        Some(x) => Record::NamedStruct(Cow::from("ddlog_std::Some"), vec![(Cow::from("x"), x)]),
+        None => Record::NamedStruct(Cow::from("ddlog_std::None"), Vec::new()),
+    }
+}
+
+/* Days since 1970-01-01 of a proleptic Gregorian date. */
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Parse an RFC 3339 timestamp, e.g., `2023-04-01T12:34:56.789012+0000`,
+/// into microseconds since the UNIX epoch.  The colon in the offset is
+/// optional, as Suricata omits it.
+pub fn parse_timestamp_us(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid timestamp '{}'", s);
+    let num = |range: std::ops::Range<usize>| -> Result<i64, String> {
+        s.get(range)
+            .filter(|d| d.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|d| d.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let bytes = s.as_bytes();
+    if bytes.len() < 19 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' {
+        return Err(invalid());
+    }
+    let days = days_from_civil(num(0..4)?, num(5..7)?, num(8..10)?);
+    let mut seconds = days * 86400 + num(11..13)? * 3600 + num(14..16)? * 60 + num(17..19)?;
+    let mut at = 19;
+    let mut micros = 0;
+    if bytes.get(at) == Some(&b'.') {
+        at += 1;
+        let start = at;
+        while at < bytes.len() && bytes[at].is_ascii_digit() {
+            if at - start < 6 {
+                micros = micros * 10 + i64::from(bytes[at] - b'0');
+            }
+            at += 1;
+        }
+        for _ in (at - start).min(6)..6 {
+            micros *= 10;
+        }
+    }
+    match &s[at..] {
+        "" | "Z" | "z" => (),
+        offset => {
+            let sign = match offset.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return Err(invalid()),
+            };
+            let offset = offset[1..].replace(':', "");
+            if offset.len() != 4 {
+                return Err(invalid());
+            }
+            let hours: i64 = offset[..2].parse().map_err(|_| invalid())?;
+            let minutes: i64 = offset[2..].parse().map_err(|_| invalid())?;
+            seconds -= sign * (hours * 3600 + minutes * 60);
+        }
+    }
+    u64::try_from(seconds * 1_000_000 + micros).map_err(|_| invalid())
+}
+
+/* Fields of the connection endpoints in Zeek logs. */
+fn zeek_conn_id(f: &mut Fields) -> Result<u64, String> {
+    let ts = f.timestamp("ts_us", "ts")?;
+    f.string("uid", "uid");
+    f.ip("orig_h", "id.orig_h")?;
+    f.uint("orig_p", "id.orig_p")?;
+    f.ip("resp_h", "id.resp_h")?;
+    f.uint("resp_p", "id.resp_p")?;
+    Ok(ts)
+}
+
+fn zeek_strings(f: &Fields, path: &str) -> Vec<String> {
+    match f.get(path) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|v| match v {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Convert a Zeek log entry, as a JSON object, into a record.  Returns
+/// `None` for logs other than those declared by the `zeek` library.
+pub fn zeek_record(path: &str, entry: &Map<String, Value>) -> Result<Option<LogRecord>, String> {
+    let mut f = Fields::new(entry);
+    let record = match path {
+        "conn" => {
+            let ts = zeek_conn_id(&mut f)?;
+            f.string("proto", "proto");
+            f.option_string("service", "service");
+            let duration = f
+                .get("duration")
+                .and_then(Value::as_f64)
+                .map(|d| Record::Int(((d * 1e6).round().max(0.0) as u64).into()));
+            f.push("duration_us", option_record(duration));
+            f.option_uint("orig_bytes", "orig_bytes")?;
+            f.option_uint("resp_bytes", "resp_bytes")?;
+            f.string("conn_state", "conn_state");
+            f.string("history", "history");
+            f.uint("orig_pkts", "orig_pkts")?;
+            f.uint("resp_pkts", "resp_pkts")?;
+            f.finish("zeek::Conn", ts)
+        }
+        "dns" => {
+            let ts = zeek_conn_id(&mut f)?;
+            f.string("proto", "proto");
+            f.option_string("query", "query");
+            f.option_string("qtype_name", "qtype_name");
+            f.option_string("rcode_name", "rcode_name");
+            let answers = zeek_strings(&f, "answers");
+            f.strings("answers", answers);
+            f.bool("rejected", "rejected");
+            f.finish("zeek::Dns", ts)
+        }
+        "http" => {
+            let ts = zeek_conn_id(&mut f)?;
+            f.option_string("method", "method");
+            f.option_string("host", "host");
+            f.option_string("uri", "uri");
+            f.option_string("user_agent", "user_agent");
+            f.option_uint("status_code", "status_code")?;
+            f.uint("request_body_len", "request_body_len")?;
+            f.uint("response_body_len", "response_body_len")?;
+            f.finish("zeek::Http", ts)
+        }
+        "ssl" => {
+            let ts = zeek_conn_id(&mut f)?;
+            f.option_string("version", "version");
+            f.option_string("server_name", "server_name");
+            f.bool("established", "established");
+            f.option_string("validation_status", "validation_status");
+            f.finish("zeek::Ssl", ts)
+        }
+        "notice" => {
+            let ts = f.timestamp("ts_us", "ts")?;
+            let uid = f.opt_string("uid").map(Record::String);
+            f.push("uid", option_record(uid));
+            f.option_ip("src", "src")?;
+            f.option_ip("dst", "dst")?;
+            f.string("note", "note");
+            f.string("msg", "msg");
+            f.finish("zeek::Notice", ts)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(record))
+}
+
+/// Parser of Zeek logs in TSV or JSON format.  TSV logs must be parsed from
+/// their header on, since the header describes their fields.
+#[derive(Debug, Clone, Default)]
+pub struct ZeekParser {
+    /// Log of JSON lines without a `_path` field.
+    default_path: Option<String>,
+    /// State of the TSV header.
+    path: Option<String>,
+    separator: String,
+    set_separator: String,
+    empty_field: String,
+    unset_field: String,
+    fields: Vec<String>,
+    types: Vec<String>,
+}
+
+impl ZeekParser {
+    /// `default_path` names the log of JSON lines without a `_path` field,
+    /// e.g., `conn` for `conn.log`.
+    pub fn new(default_path: Option<&str>) -> Self {
+        Self {
+            default_path: default_path.map(str::to_string),
+            separator: "\t".to_string(),
+            set_separator: ",".to_string(),
+            empty_field: "(empty)".to_string(),
+            unset_field: "-".to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Parse a line of a log.  Returns `None` for header lines, blank lines,
+    /// and entries of other logs.
+    pub fn parse_line(&mut self, line: &str) -> Result<Option<LogRecord>, String> {
+        let line = line.trim_end_matches(&['\n', '\r'][..]);
+        if line.is_empty() {
+            Ok(None)
+        } else if line.starts_with('{') {
+            let entry: Map<String, Value> =
+                serde_json::from_str(line).map_err(|e| format!("invalid Zeek log entry: {}", e))?;
+            let path = match entry.get("_path").and_then(Value::as_str) {
+                Some(path) => path.to_string(),
+                None => match &self.default_path {
+                    Some(path) => path.clone(),
+                    None => return Err("Zeek log entry without _path".to_string()),
+                },
+            };
+            zeek_record(&path, &entry)
+        } else if let Some(header) = line.strip_prefix('#') {
+            self.parse_header(header);
+            Ok(None)
+        } else {
+            let path = match &self.path {
+                Some(path) if !self.fields.is_empty() => path.clone(),
+                _ => return Err("Zeek TSV log entry before the log header".to_string()),
+            };
+            let entry = self.tsv_entry(line)?;
+            zeek_record(&path, &entry)
+        }
+    }
+
+    fn parse_header(&mut self, header: &str) {
+        // The separator line uses a space, since the separator is not known.
+        if let Some(separator) = header.strip_prefix("separator ") {
+            self.separator = unescape(separator);
+            return;
+        }
+        let mut values = header.split(self.separator.as_str()).map(str::to_string);
+        match values.next() {
+            Some(key) if key == "set_separator" => {
+                self.set_separator = values.next().unwrap_or_else(|| ",".to_string())
+            }
+            Some(key) if key == "empty_field" => {
+                self.empty_field = values.next().unwrap_or_else(|| "(empty)".to_string())
+            }
+            Some(key) if key == "unset_field" => {
+                self.unset_field = values.next().unwrap_or_else(|| "-".to_string())
+            }
+            Some(key) if key == "path" => self.path = values.next(),
+            Some(key) if key == "fields" => self.fields = values.collect(),
+            Some(key) if key == "types" => self.types = values.collect(),
+            _ => (),
+        }
+    }
+
+    /* Converts a TSV line into the JSON object Zeek would have logged. */
+    fn tsv_entry(&self, line: &str) -> Result<Map<String, Value>, String> {
+        let mut entry = Map::new();
+        for (i, value) in line.split(self.separator.as_str()).enumerate() {
+            let name = match self.fields.get(i) {
+                Some(name) => name,
+                None => return Err("Zeek TSV log entry with too many fields".to_string()),
+            };
+            if value == self.unset_field {
+                continue;
+            }
+            let ty = self.types.get(i).map(String::as_str).unwrap_or("string");
+            let json = if ty.starts_with("set[") || ty.starts_with("vector[") {
+                let elements = if value == self.empty_field {
+                    Vec::new()
+                } else {
+                    value
+                        .split(self.set_separator.as_str())
+                        .map(|v| Value::String(v.to_string()))
+                        .collect()
+                };
+                Value::Array(elements)
+            } else {
+                match ty {
+                    "count" | "int" | "port" => value
+                        .parse::<i64>()
+                        .map(Value::from)
+                        .map_err(|_| format!("invalid {} '{}'", name, value))?,
+                    "time" | "interval" | "double" => value
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(Number::from_f64)
+                        .map(Value::Number)
+                        .ok_or_else(|| format!("invalid {} '{}'", name, value))?,
+                    "bool" => Value::Bool(value == "T"),
+                    _ if value == self.empty_field => Value::String(String::new()),
+                    _ => Value::String(value.to_string()),
+                }
+            };
+            entry.insert(name.clone(), json);
+        }
+        Ok(entry)
+    }
+}
+
+/* Zeek escapes the separator in the header, e.g., `\x09`. */
+fn unescape(s: &str) -> String {
+    match s.strip_prefix("\\x").map(|hex| u8::from_str_radix(hex, 16)) {
+        Some(Ok(b)) => (b as char).to_string(),
+        _ => s.to_string(),
+    }
+}
+
+/* Fields of the flow in EVE events. */
+fn eve_flow_id(f: &mut Fields) -> Result<u64, String> {
+    let ts = f.timestamp("ts_us", "timestamp")?;
+    f.uint("flow_id", "flow_id")?;
+    f.ip("src_ip", "src_ip")?;
+    f.uint("src_port", "src_port")?;
+    f.ip("dest_ip", "dest_ip")?;
+    f.uint("dest_port", "dest_port")?;
+    Ok(ts)
+}
+
+/// Parse a line of a Suricata EVE log.  Returns `None` for blank lines and
+/// events of other types.
+pub fn parse_eve(line: &str) -> Result<Option<LogRecord>, String> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let event: Map<String, Value> =
+        serde_json::from_str(line).map_err(|e| format!("invalid EVE event: {}", e))?;
+    let mut f = Fields::new(&event);
+    let record = match event.get("event_type").and_then(Value::as_str) {
+        Some("alert") => {
+            let ts = eve_flow_id(&mut f)?;
+            f.string("proto", "proto");
+            f.option_string("app_proto", "app_proto");
+            f.uint("signature_id", "alert.signature_id")?;
+            f.uint("rev", "alert.rev")?;
+            f.string("signature", "alert.signature");
+            f.string("category", "alert.category");
+            f.uint("severity", "alert.severity")?;
+            f.string("action", "alert.action");
+            f.finish("suricata::Alert", ts)
+        }
+        Some("flow") => {
+            let ts = eve_flow_id(&mut f)?;
+            f.string("proto", "proto");
+            f.option_string("app_proto", "app_proto");
+            f.uint("pkts_toserver", "flow.pkts_toserver")?;
+            f.uint("pkts_toclient", "flow.pkts_toclient")?;
+            f.uint("bytes_toserver", "flow.bytes_toserver")?;
+            f.uint("bytes_toclient", "flow.bytes_toclient")?;
+            let start = f.timestamp_us("flow.start")?;
+            f.push("start_us", Record::Int(start.into()));
+            let end = f.timestamp_us("flow.end").unwrap_or(start);
+            f.push("end_us", Record::Int(end.into()));
+            f.string("state", "flow.state");
+            f.bool("alerted", "flow.alerted");
+            f.finish("suricata::Flow", ts)
+        }
+        Some("dns") => {
+            let ts = eve_flow_id(&mut f)?;
+            f.string("proto", "proto");
+            f.string("dns_type", "dns.type");
+            // Suricata 7 logs queries in an array.
+            let rrname = f
+                .opt_string("dns.rrname")
+                .or_else(|| f.opt_string("dns.queries.0.rrname"))
+                .unwrap_or_default();
+            f.push("rrname", Record::String(rrname));
+            f.option_string("rrtype", "dns.rrtype");
+            f.option_string("rcode", "dns.rcode");
+            // Version 2 logs all answers of a response; version 1 logs one
+            // event per answer.
+            let answers = match f.get("dns.answers") {
+                Some(Value::Array(answers)) => answers
+                    .iter()
+                    .filter_map(|a| a.get("rdata").and_then(Value::as_str))
+                    .map(str::to_string)
+                    .collect(),
+                _ => f.opt_string("dns.rdata").into_iter().collect(),
+            };
+            f.strings("answers", answers);
+            f.finish("suricata::Dns", ts)
+        }
+        Some("http") => {
+            let ts = eve_flow_id(&mut f)?;
+            f.option_string("hostname", "http.hostname");
+            f.option_string("url", "http.url");
+            f.option_string("http_method", "http.http_method");
+            f.option_string("http_user_agent", "http.http_user_agent");
+            f.option_uint("status", "http.status")?;
+            f.uint("length", "http.length")?;
+            f.finish("suricata::Http", ts)
+        }
+        Some("tls") => {
+            let ts = eve_flow_id(&mut f)?;
+            f.option_string("sni", "tls.sni");
+            f.option_string("version", "tls.version");
+            f.option_string("subject", "tls.subject");
+            f.option_string("issuerdn", "tls.issuerdn");
+            f.option_string("ja3_hash", "tls.ja3.hash");
+            f.finish("suricata::Tls", ts)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(record))
+}
+
+/// Inserts log records into a program and expires them.
+pub struct LogIngester {
+    client: ClientId,
+    /// Log time after which records are removed; `None` keeps them forever.
+    retention: Option<Duration>,
+    /// Inserted records, in insertion order, for expiry.
+    inserted: VecDeque<LogRecord>,
+    /// Latest log time seen.
+    now_us: u64,
+}
+
+impl LogIngester {
+    pub fn new(client: ClientId, retention: Option<Duration>) -> Self {
+        Self {
+            client,
+            retention,
+            inserted: VecDeque::new(),
+            now_us: 0,
+        }
+    }
+
+    /// Insert `records` in one transaction, together with the removal of
+    /// expired records.  Returns the number of records inserted.
+    pub fn ingest(&mut self, hddlog: &HDDlog, records: Vec<LogRecord>) -> Result<usize, String> {
+        for record in records.iter() {
+            if Relations::try_from(record.relation).is_err() {
+                return Err(format!(
+                    "the program does not import the library of {}",
+                    record.relation
+                ));
+            }
+        }
+        let mut commands = Vec::with_capacity(records.len());
+        for record in records.iter() {
+            self.now_us = self.now_us.max(record.ts_us);
+            commands.push(UpdCmd::Insert(
+                RelIdentifier::RelName(Cow::from(record.relation)),
+                record.record.clone(),
+            ));
+        }
+        let mut expired = 0;
+        if let Some(retention) = self.retention {
+            let horizon = self.now_us.saturating_sub(retention.as_micros() as u64);
+            // Records arrive roughly in log time; a record that arrived late
+            // is removed with the records inserted after it.
+            while let Some(r) = self.inserted.get(expired).filter(|r| r.ts_us < horizon) {
+                commands.push(UpdCmd::Delete(
+                    RelIdentifier::RelName(Cow::from(r.relation)),
+                    r.record.clone(),
+                ));
+                expired += 1;
+            }
+        }
+        if commands.is_empty() {
+            return Ok(0);
+        }
+        let txn = hddlog.try_start_transaction(self.client, None)?;
+        txn.apply_updates_dynamic(&mut commands.into_iter())?;
+        txn.commit()?;
+        self.inserted.drain(..expired);
+        let inserted = records.len();
+        if self.retention.is_some() {
+            self.inserted.extend(records);
+        }
+        Ok(inserted)
+    }
+
+    fn ingest_lines<R: BufRead>(
+        &mut self,
+        hddlog: &HDDlog,
+        reader: R,
+        batch_size: usize,
+        mut parse: impl FnMut(&str) -> Result<Option<LogRecord>, String>,
+    ) -> Result<usize, String> {
+        let mut batch = Vec::new();
+        let mut total = 0;
+        for (n, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            if let Some(record) = parse(&line).map_err(|e| format!("line {}: {}", n + 1, e))? {
+                batch.push(record);
+            }
+            if batch.len() >= batch_size {
+                total += self.ingest(hddlog, std::mem::take(&mut batch))?;
+            }
+        }
+        total += self.ingest(hddlog, batch)?;
+        Ok(total)
+    }
+
+    /// Insert the entries of a Zeek log, `batch_size` entries per
+    /// transaction.  See `ZeekParser::new()` for `default_path`.
+    pub fn ingest_zeek<R: BufRead>(
+        &mut self,
+        hddlog: &HDDlog,
+        reader: R,
+        default_path: Option<&str>,
+        batch_size: usize,
+    ) -> Result<usize, String> {
+        let mut parser = ZeekParser::new(default_path);
+        self.ingest_lines(hddlog, reader, batch_size, |line| parser.parse_line(line))
+    }
+
+    /// Insert the events of a Suricata EVE log, `batch_size` events per
+    /// transaction.
+    pub fn ingest_eve<R: BufRead>(
+        &mut self,
+        hddlog: &HDDlog,
+        reader: R,
+        batch_size: usize,
+    ) -> Result<usize, String> {
+        self.ingest_lines(hddlog, reader, batch_size, parse_eve)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value of field `name` of `record`, in the dump format.
+    fn field(record: &LogRecord, name: &str) -> String {
+        match &record.record {
+            Record::NamedStruct(_, fields) => fields
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.to_string())
+                .unwrap_or_else(|| panic!("no field {} in {}", name, record.record)),
+            record => panic!("unexpected record {}", record),
+        }
+    }
+
+    const TS_US: u64 = 1680352496789012;
+
+    #[test]
+    fn parse_timestamps() {
+        for s in &[
+            "2023-04-01T12:34:56.789012+0000",
+            "2023-04-01T12:34:56.789012345Z",
+            "2023-04-01T14:34:56.789012+02:00",
+            "2023-04-01T10:34:56.789012-0200",
+        ] {
+            assert_eq!(parse_timestamp_us(s).unwrap(), TS_US, "{}", s);
+        }
+        assert_eq!(
+            parse_timestamp_us("2023-04-01T12:34:56.7").unwrap(),
+            1680352496700000
+        );
+        assert_eq!(parse_timestamp_us("1970-01-01T00:00:00Z").unwrap(), 0);
+        for s in &[
+            "yesterday",
+            "2023-04-01 12:34",
+            "2023-04-01T12:34:56 UTC",
+            "2023-04-01T12:34:56+2",
+            "2023-04-01T12:34:5x",
+            "1969-12-31T23:59:59Z",
+        ] {
+            assert_eq!(
+                parse_timestamp_us(s).unwrap_err(),
+                format!("invalid timestamp '{}'", s)
+            );
+        }
+    }
+
+    const CONN_HEADER: &str = "#separator \\x09
+#set_separator\t,
+#empty_field\t(empty)
+#unset_field\t-
+#path\tconn
+#open\t2023-04-01-12-00-00
+#fields\tts\tuid\tid.orig_h\tid.orig_p\tid.resp_h\tid.resp_p\tproto\tservice\tduration\torig_bytes\tresp_bytes\tconn_state\thistory\torig_pkts\tresp_pkts
+#types\ttime\tstring\taddr\tport\taddr\tport\tenum\tstring\tinterval\tcount\tcount\tstring\tstring\tcount\tcount";
+
+    fn parse_conn_log(entries: &[&str]) -> Result<Vec<LogRecord>, String> {
+        let mut parser = ZeekParser::new(None);
+        let mut records = Vec::new();
+        for line in CONN_HEADER.lines().chain(entries.iter().cloned()) {
+            records.extend(parser.parse_line(line)?);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn parse_zeek_tsv_logs() {
+        let records = parse_conn_log(&[
+            "1680352496.789012\tCAbc\t10.0.0.1\t40000\t10.0.0.2\t443\ttcp\tssl\t1.5\t100\t-\tSF\tShADadFf\t5\t4",
+            "",
+            "#close\t2023-04-01-13-00-00",
+        ])
+        .unwrap();
+        assert_eq!(records.len(), 1);
+        let conn = &records[0];
+        assert_eq!((conn.relation, conn.ts_us), ("zeek::Conn", TS_US));
+        assert_eq!(field(conn, "uid"), "\"CAbc\"");
+        assert_eq!(
+            field(conn, "orig_h"),
+            "net::ipaddr::IpAddrV4{.addr4 = 167772161}"
+        );
+        assert_eq!(field(conn, "resp_p"), "443");
+        assert_eq!(field(conn, "service"), "ddlog_std::Some{.x = \"ssl\"}");
+        assert_eq!(field(conn, "duration_us"), "ddlog_std::Some{.x = 1500000}");
+        assert_eq!(field(conn, "orig_bytes"), "ddlog_std::Some{.x = 100}");
+        // Unset fields.
+        assert_eq!(field(conn, "resp_bytes"), "ddlog_std::None{}");
+        assert_eq!(field(conn, "orig_pkts"), "5");
+
+        // Sets, with the separators of the header.
+        let mut parser = ZeekParser::new(None);
+        for line in &[
+            "#separator |",
+            "#set_separator|;",
+            "#path|dns",
+            "#fields|ts|uid|id.orig_h|id.orig_p|id.resp_h|id.resp_p|proto|query|answers|rejected",
+            "#types|time|string|addr|port|addr|port|enum|string|vector[string]|bool",
+        ] {
+            assert_eq!(parser.parse_line(line).unwrap(), None);
+        }
+        let dns = parser
+            .parse_line("1.5|C1|10.0.0.1|5353|10.0.0.2|53|udp|example.com|a;b|T")
+            .unwrap()
+            .unwrap();
+        assert_eq!((dns.relation, dns.ts_us), ("zeek::Dns", 1500000));
+        assert_eq!(field(&dns, "answers"), r#"["a", "b"]"#);
+        assert_eq!(field(&dns, "rejected"), "true");
+        let dns = parser
+            .parse_line("1.5|C1|10.0.0.1|5353|10.0.0.2|53|udp|(empty)|(empty)|F")
+            .unwrap()
+            .unwrap();
+        assert_eq!(field(&dns, "query"), r#"ddlog_std::Some{.x = ""}"#);
+        assert_eq!(field(&dns, "answers"), "[]");
+        assert_eq!(field(&dns, "rejected"), "false");
+
+        // Logs that the library does not declare.
+        let mut parser = ZeekParser::new(None);
+        parser.parse_line("#path\tweird").unwrap();
+        parser.parse_line("#fields\tts\tname").unwrap();
+        assert_eq!(parser.parse_line("1.5\tbad_checksum").unwrap(), None);
+    }
+
+    #[test]
+    fn parse_zeek_json_logs() {
+        let mut parser = ZeekParser::new(Some("notice"));
+        let dns = parser
+            .parse_line(
+                r#"{"_path":"dns","ts":"2023-04-01T12:34:56.789012Z","uid":"C1","id.orig_h":"10.0.0.1","id.orig_p":5353,"id.resp_h":"2001:db8::1","id.resp_p":53,"proto":"udp","query":"example.com","answers":["93.184.216.34"],"rejected":false}"#,
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!((dns.relation, dns.ts_us), ("zeek::Dns", TS_US));
+        assert_eq!(
+            field(&dns, "resp_h"),
+            "net::ipaddr::IpAddrV6{.addr6 = 42540766411282592856903984951653826561}"
+        );
+        assert_eq!(field(&dns, "answers"), r#"["93.184.216.34"]"#);
+        assert_eq!(field(&dns, "rcode_name"), "ddlog_std::None{}");
+
+        // Entries without `_path` belong to the default log.
+        let notice = parser
+            .parse_line(r#"{"ts":1.5,"note":"Scan::Port_Scan","msg":"scan","src":"10.0.0.1"}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(notice.relation, "zeek::Notice");
+        assert_eq!(field(&notice, "uid"), "ddlog_std::None{}");
+        assert_eq!(field(&notice, "dst"), "ddlog_std::None{}");
+        assert_eq!(field(&notice, "note"), r#""Scan::Port_Scan""#);
+        assert_eq!(
+            parser.parse_line(r#"{"_path":"weird","ts":1.5}"#).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn zeek_errors() {
+        let mut parser = ZeekParser::new(None);
+        assert_eq!(
+            parser.parse_line("1.5\tC1").unwrap_err(),
+            "Zeek TSV log entry before the log header"
+        );
+        assert_eq!(
+            parser.parse_line(r#"{"ts":1.5}"#).unwrap_err(),
+            "Zeek log entry without _path"
+        );
+        assert!(parser
+            .parse_line("{")
+            .unwrap_err()
+            .starts_with("invalid Zeek log entry: "));
+        assert_eq!(
+            parser
+                .parse_line(r#"{"_path":"conn","ts":1.5,"id.orig_p":1}"#)
+                .unwrap_err(),
+            "missing id.orig_h"
+        );
+        assert_eq!(
+            parser
+                .parse_line(r#"{"_path":"conn","ts":1.5,"id.orig_h":"nope"}"#)
+                .unwrap_err(),
+            "invalid id.orig_h 'nope'"
+        );
+        assert_eq!(
+            parser.parse_line(r#"{"_path":"conn"}"#).unwrap_err(),
+            "missing ts"
+        );
+
+        let entry = "1.5\tC1\t10.0.0.1\t40000\t10.0.0.2\t443\ttcp\t-\t-\t-\t-\tSF\tS\t1\t1";
+        assert_eq!(
+            parse_conn_log(&[&format!("{}\textra", entry)]).unwrap_err(),
+            "Zeek TSV log entry with too many fields"
+        );
+        assert_eq!(
+            parse_conn_log(&[&entry.replace("\t1\t1", "\tx\t1")]).unwrap_err(),
+            "invalid orig_pkts 'x'"
+        );
+        assert_eq!(
+            parse_conn_log(&[&entry.replacen("1.5", "soon", 1)]).unwrap_err(),
+            "invalid ts 'soon'"
+        );
+    }
+
+    const EVE_FLOW: &str = r#""timestamp":"2023-04-01T12:34:56.789012+0000","flow_id":1234,"src_ip":"10.0.0.1","src_port":40000,"dest_ip":"10.0.0.2","dest_port":443"#;
+
+    fn eve(event: &str) -> LogRecord {
+        parse_eve(&format!("{{{},{}}}", EVE_FLOW, event))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_eve_events() {
+        let alert = eve(
+            r#""event_type":"alert","proto":"TCP","alert":{"action":"allowed","signature_id":2000001,"rev":3,"signature":"ET TEST","category":"Misc","severity":2}"#,
+        );
+        assert_eq!((alert.relation, alert.ts_us), ("suricata::Alert", TS_US));
+        assert_eq!(field(&alert, "flow_id"), "1234");
+        assert_eq!(field(&alert, "signature_id"), "2000001");
+        assert_eq!(field(&alert, "signature"), r#""ET TEST""#);
+        assert_eq!(field(&alert, "app_proto"), "ddlog_std::None{}");
+
+        let flow = eve(
+            r#""event_type":"flow","proto":"TCP","app_proto":"tls","flow":{"pkts_toserver":5,"pkts_toclient":4,"bytes_toserver":500,"bytes_toclient":400,"start":"2023-04-01T12:34:50.000000+0000","state":"closed","alerted":true}"#,
+        );
+        assert_eq!(flow.relation, "suricata::Flow");
+        assert_eq!(field(&flow, "app_proto"), r#"ddlog_std::Some{.x = "tls"}"#);
+        assert_eq!(field(&flow, "start_us"), "1680352490000000");
+        // Flows without an end end when they start.
+        assert_eq!(field(&flow, "end_us"), "1680352490000000");
+        assert_eq!(field(&flow, "alerted"), "true");
+
+        // DNS answers in the formats of Suricata 6 and 7, and of version 1.
+        for event in &[
+            r#""event_type":"dns","proto":"UDP","dns":{"type":"answer","rrname":"example.com","rrtype":"A","rcode":"NOERROR","answers":[{"rdata":"93.184.216.34"},{"rrtype":"SOA"}]}"#,
+            r#""event_type":"dns","proto":"UDP","dns":{"type":"answer","queries":[{"rrname":"example.com"}],"rrtype":"A","rcode":"NOERROR","answers":[{"rdata":"93.184.216.34"}]}"#,
+            r#""event_type":"dns","proto":"UDP","dns":{"type":"answer","rrname":"example.com","rrtype":"A","rcode":"NOERROR","rdata":"93.184.216.34"}"#,
+        ] {
+            let dns = eve(event);
+            assert_eq!(dns.relation, "suricata::Dns");
+            assert_eq!(field(&dns, "rrname"), r#""example.com""#, "{}", event);
+            assert_eq!(field(&dns, "answers"), r#"["93.184.216.34"]"#, "{}", event);
+        }
+
+        let http = eve(
+            r#""event_type":"http","http":{"hostname":"example.com","url":"/","http_method":"GET","status":"200","length":10}"#,
+        );
+        assert_eq!(http.relation, "suricata::Http");
+        assert_eq!(field(&http, "status"), "ddlog_std::Some{.x = 200}");
+        assert_eq!(field(&http, "http_user_agent"), "ddlog_std::None{}");
+
+        let tls = eve(
+            r#""event_type":"tls","tls":{"sni":"example.com","version":"TLS 1.3","ja3":{"hash":"e7d7"}}"#,
+        );
+        assert_eq!(tls.relation, "suricata::Tls");
+        assert_eq!(field(&tls, "ja3_hash"), r#"ddlog_std::Some{.x = "e7d7"}"#);
+
+        assert_eq!(parse_eve("  ").unwrap(), None);
+        assert_eq!(
+            parse_eve(&format!(r#"{{{},"event_type":"stats"}}"#, EVE_FLOW)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn eve_errors() {
+        assert!(parse_eve("{")
+            .unwrap_err()
+            .starts_with("invalid EVE event: "));
+        assert_eq!(
+            parse_eve(r#"{"event_type":"alert"}"#).unwrap_err(),
+            "missing timestamp"
+        );
+        let event = |flow_id: &str| {
+            parse_eve(&format!(
+                r#"{{"event_type":"alert","timestamp":"2023-04-01T12:34:56Z","flow_id":{}}}"#,
+                flow_id
+            ))
+            .unwrap_err()
+        };
+        assert_eq!(event(r#""abc""#), "invalid flow_id 'abc'");
+        assert_eq!(event("-1"), "invalid flow_id -1");
+        assert_eq!(event("1"), "missing src_ip");
+        assert_eq!(
+            parse_eve(&format!(
+                r#"{{{},"event_type":"flow","flow":{{}}}}"#,
+                EVE_FLOW
+            ))
+            .unwrap_err(),
+            "missing flow.start"
+        );
+    }
+}
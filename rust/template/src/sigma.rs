@@ -0,0 +1,1089 @@
+//! Sigma rule compiler.
+//!
+//! Translates Sigma detection rules (YAML) into the facts consumed by the
+//! `sigma` library (see `lib/sigma.dl`), which evaluates them incrementally
+//! over the events in `sigma::Event`.  A rule compiles into its log source,
+//! one `sigma::Atom` per field condition of the selections its condition
+//! refers to, and the condition in disjunctive normal form over these atoms
+//! (`sigma::Clause`).
+//!
+//! The supported subset of Sigma:
+//!
+//! * Selections that are maps of field conditions, lists of such maps, or
+//!   lists of keywords.  Values may contain the `*` and `?` wildcards, or be
+//!   `null`.
+//! * The `contains`, `startswith`, `endswith`, `all`, `re`, and `exists`
+//!   modifiers.
+//! * Conditions with `and`, `or`, `not`, parentheses, `1 of` and `all of`
+//!   selection patterns or `them`.  A list of conditions is their
+//!   disjunction.
+//!
+//! Rules that use other modifiers, aggregations (`| count() ...`), or
+//! correlations are rejected with an error, as are conditions whose normal
+//! form exceeds `MAX_CLAUSES` clauses.  Requires the `sigma` feature.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+
+use serde_yaml::{Mapping, Value};
+
+use differential_datalog::record::{CollectionKind, Record, RelIdentifier, UpdCmd};
+use differential_datalog::scheduler::ClientId;
+
+use crate::api::HDDlog;
+use crate::Relations;
+
+/// Maximal number of clauses of a compiled condition.
+pub const MAX_CLAUSES: usize = 1024;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogSource {
+    pub category: String,
+    pub product: String,
+    pub service: String,
+}
+
+impl LogSource {
+    fn to_record(&self) -> Record {
+        Record::NamedStruct(
+            Cow::from("sigma::LogSource"),
+            vec![
+                (Cow::from("category"), Record::String(self.category.clone())),
+                (Cow::from("product"), Record::String(self.product.clone())),
+                (Cow::from("service"), Record::String(self.service.clone())),
+            ],
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchOp {
+    Equals,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Regex,
+    Exists,
+    IsNull,
+}
+
+/// A field condition.  An empty field name stands for keywords.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Atom {
+    pub field: String,
+    pub op: MatchOp,
+    pub values: Vec<String>,
+    pub all: bool,
+}
+
+/// A compiled rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigmaRule {
+    pub id: String,
+    pub title: String,
+    pub level: String,
+    pub logsource: LogSource,
+    pub atoms: Vec<Atom>,
+    /// The condition in disjunctive normal form: atom indices, and whether
+    /// they are negated.
+    pub clauses: Vec<Vec<(u32, bool)>>,
+}
+
+/* A condition over atoms. */
+#[derive(Debug, Clone)]
+enum Expr {
+    Atom(u32),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+fn scalar(value: &Value) -> Result<Option<String>, String> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Bool(b) => Ok(Some(b.to_string())),
+        Value::Number(n) => Ok(Some(n.to_string())),
+        Value::String(s) => Ok(Some(s.clone())),
+        v => Err(format!("unsupported value {:?}", v)),
+    }
+}
+
+fn string_field(map: &Mapping, name: &str) -> String {
+    map.get(name)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+/* Sigma wildcards: `*`, `?`, and backslash escapes. */
+enum Piece {
+    Char(char),
+    Any,
+    One,
+}
+
+fn pieces(value: &str) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        pieces.push(match c {
+            '\\' => match chars.next() {
+                Some(c @ '*') | Some(c @ '?') | Some(c @ '\\') => Piece::Char(c),
+                Some(c) => {
+                    pieces.push(Piece::Char('\\'));
+                    Piece::Char(c)
+                }
+                None => Piece::Char('\\'),
+            },
+            '*' => Piece::Any,
+            '?' => Piece::One,
+            c => Piece::Char(c),
+        });
+    }
+    pieces
+}
+
+fn regex_of<'a>(pieces: impl Iterator<Item = &'a Piece>) -> String {
+    let mut re = String::from("(?is)^");
+    for piece in pieces {
+        match piece {
+            Piece::Char(c) => {
+                if "\\.+*?()|[]{}^$#&-~".contains(*c) {
+                    re.push('\\');
+                }
+                re.push(*c);
+            }
+            Piece::Any => re.push_str(".*"),
+            Piece::One => re.push('.'),
+        }
+    }
+    re.push('$');
+    re
+}
+
+fn literal(pieces: &[Piece]) -> Option<String> {
+    pieces
+        .iter()
+        .map(|p| match p {
+            Piece::Char(c) => Some(*c),
+            _ => None,
+        })
+        .collect()
+}
+
+/* The op and lower-cased value a wildcard value compiles to for the given
+ * modifier, if it does not need a regular expression. */
+fn simple_match(op: MatchOp, pieces: &[Piece]) -> Option<(MatchOp, String)> {
+    let leading = matches!(pieces.first(), Some(Piece::Any));
+    let trailing = pieces.len() > 1 && matches!(pieces.last(), Some(Piece::Any));
+    let inner = &pieces[leading as usize..pieces.len() - trailing as usize];
+    let value = literal(inner)?.to_lowercase();
+    let op = match (op, leading, trailing) {
+        (op, false, false) => op,
+        (MatchOp::Equals, true, true) | (MatchOp::Contains, _, _) => MatchOp::Contains,
+        (MatchOp::Equals, true, false) | (MatchOp::EndsWith, true, false) => MatchOp::EndsWith,
+        (MatchOp::Equals, false, true) | (MatchOp::StartsWith, false, true) => MatchOp::StartsWith,
+        _ => return None,
+    };
+    Some((op, value))
+}
+
+struct Compiler {
+    atoms: Vec<Atom>,
+    selections: BTreeMap<String, Value>,
+    /// Selections compiled so far.
+    compiled: BTreeMap<String, Expr>,
+}
+
+impl Compiler {
+    fn atom(&mut self, atom: Atom) -> Expr {
+        self.atoms.push(atom);
+        Expr::Atom((self.atoms.len() - 1) as u32)
+    }
+
+    /* A field condition: `field|modifiers: values`. */
+    fn field_condition(&mut self, spec: &str, value: &Value) -> Result<Expr, String> {
+        let mut parts = spec.split('|');
+        let field = parts.next().unwrap_or_default().to_string();
+        let mut op = MatchOp::Equals;
+        let mut all = false;
+        for modifier in parts {
+            op = match modifier {
+                "contains" => MatchOp::Contains,
+                "startswith" => MatchOp::StartsWith,
+                "endswith" => MatchOp::EndsWith,
+                "re" => MatchOp::Regex,
+                "exists" => MatchOp::Exists,
+                "all" => {
+                    all = true;
+                    continue;
+                }
+                m => return Err(format!("unsupported modifier '{}' of {}", m, spec)),
+            };
+        }
+        let values = match value {
+            Value::Sequence(values) => values.iter().map(scalar).collect::<Result<_, _>>()?,
+            value => vec![scalar(value)?],
+        };
+
+        if op == MatchOp::Exists {
+            let exists = match values.as_slice() {
+                [Some(v)] => v == "true",
+                _ => return Err(format!("invalid value of {}", spec)),
+            };
+            let op = if exists {
+                MatchOp::Exists
+            } else {
+                MatchOp::IsNull
+            };
+            return Ok(self.atom(Atom {
+                field,
+                op,
+                values: Vec::new(),
+                all: false,
+            }));
+        }
+
+        // `null` values match absent fields; they form a separate atom.
+        let nulls = values.iter().any(Option::is_none);
+        let values: Vec<String> = values.into_iter().flatten().collect();
+        let mut alternatives = Vec::new();
+        if nulls {
+            alternatives.push(self.atom(Atom {
+                field: field.clone(),
+                op: MatchOp::IsNull,
+                values: Vec::new(),
+                all: false,
+            }));
+        }
+        if !values.is_empty() {
+            let atom = if op == MatchOp::Regex {
+                Atom {
+                    field,
+                    op,
+                    values,
+                    all,
+                }
+            } else {
+                // All values must compile to the same op; otherwise they
+                // are matched as regular expressions.
+                let parsed: Vec<Vec<Piece>> = values.iter().map(|v| pieces(v)).collect();
+                let simple: Option<Vec<(MatchOp, String)>> =
+                    parsed.iter().map(|p| simple_match(op, p)).collect();
+                match simple {
+                    Some(simple) if simple.iter().all(|(o, _)| *o == simple[0].0) => Atom {
+                        field,
+                        op: simple[0].0,
+                        values: simple.into_iter().map(|(_, v)| v).collect(),
+                        all,
+                    },
+                    _ => {
+                        let any: &[Piece] = &[Piece::Any];
+                        let prefix = match op {
+                            MatchOp::Contains | MatchOp::EndsWith => any,
+                            _ => &[],
+                        };
+                        let suffix = match op {
+                            MatchOp::Contains | MatchOp::StartsWith => any,
+                            _ => &[],
+                        };
+                        Atom {
+                            field,
+                            op: MatchOp::Regex,
+                            values: parsed
+                                .iter()
+                                .map(|p| regex_of(prefix.iter().chain(p).chain(suffix)))
+                                .collect(),
+                            all,
+                        }
+                    }
+                }
+            };
+            alternatives.push(self.atom(atom));
+        }
+        Ok(match alternatives.len() {
+            1 => alternatives.pop().unwrap(),
+            _ => Expr::Or(alternatives),
+        })
+    }
+
+    fn selection(&mut self, name: &str) -> Result<Expr, String> {
+        if let Some(expr) = self.compiled.get(name) {
+            return Ok(expr.clone());
+        }
+        let definition = self
+            .selections
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("unknown selection '{}'", name))?;
+        let expr = match &definition {
+            Value::Mapping(map) => self.selection_map(map)?,
+            Value::Sequence(items) if items.iter().all(|i| i.is_mapping()) => {
+                let mut alternatives = Vec::new();
+                for item in items {
+                    alternatives.push(self.selection_map(item.as_mapping().unwrap())?);
+                }
+                Expr::Or(alternatives)
+            }
+            Value::Sequence(_) => self.field_condition("", &definition)?,
+            _ => return Err(format!("invalid selection '{}'", name)),
+        };
+        self.compiled.insert(name.to_string(), expr.clone());
+        Ok(expr)
+    }
+
+    fn selection_map(&mut self, map: &Mapping) -> Result<Expr, String> {
+        let mut conditions = Vec::new();
+        for (spec, value) in map {
+            let spec = spec
+                .as_str()
+                .ok_or_else(|| format!("invalid field {:?}", spec))?;
+            conditions.push(self.field_condition(spec, value)?);
+        }
+        Ok(Expr::And(conditions))
+    }
+
+    /* Names of the selections matching a `1 of`/`all of` pattern. */
+    fn selections_matching(&self, pattern: &str) -> Result<Vec<String>, String> {
+        let names: Vec<String> = self
+            .selections
+            .keys()
+            .filter(|name| match pattern {
+                "them" => !name.starts_with('_'),
+                pattern => match pattern.strip_suffix('*') {
+                    Some(prefix) => name.starts_with(prefix),
+                    None => *name == pattern,
+                },
+            })
+            .cloned()
+            .collect();
+        if names.is_empty() {
+            return Err(format!("no selection matches '{}'", pattern));
+        }
+        Ok(names)
+    }
+}
+
+/* Recursive-descent parser of conditions. */
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(condition: &'a str) -> Result<Self, String> {
+        if condition.contains('|') {
+            return Err("aggregations in conditions are not supported".to_string());
+        }
+        let mut tokens = Vec::new();
+        let mut start = None;
+        for (i, c) in condition.char_indices() {
+            if c.is_whitespace() || c == '(' || c == ')' {
+                if let Some(s) = start.take() {
+                    tokens.push(&condition[s..i]);
+                }
+                if c == '(' || c == ')' {
+                    tokens.push(&condition[i..i + 1]);
+                }
+            } else if start.is_none() {
+                start = Some(i);
+            }
+        }
+        if let Some(s) = start {
+            tokens.push(&condition[s..]);
+        }
+        Ok(Self { tokens, pos: 0 })
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let token = self
+            .peek()
+            .ok_or_else(|| "unexpected end of condition".to_string())?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn parse(mut self, compiler: &mut Compiler) -> Result<Expr, String> {
+        let expr = self.or(compiler)?;
+        match self.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected '{}' in condition", token)),
+        }
+    }
+
+    fn or(&mut self, compiler: &mut Compiler) -> Result<Expr, String> {
+        let mut terms = vec![self.and(compiler)?];
+        while self.peek() == Some("or") {
+            self.pos += 1;
+            terms.push(self.and(compiler)?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.pop().unwrap()
+        } else {
+            Expr::Or(terms)
+        })
+    }
+
+    fn and(&mut self, compiler: &mut Compiler) -> Result<Expr, String> {
+        let mut terms = vec![self.not(compiler)?];
+        while self.peek() == Some("and") {
+            self.pos += 1;
+            terms.push(self.not(compiler)?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.pop().unwrap()
+        } else {
+            Expr::And(terms)
+        })
+    }
+
+    fn not(&mut self, compiler: &mut Compiler) -> Result<Expr, String> {
+        if self.peek() == Some("not") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not(compiler)?)));
+        }
+        self.primary(compiler)
+    }
+
+    fn primary(&mut self, compiler: &mut Compiler) -> Result<Expr, String> {
+        match self.next()? {
+            "(" => {
+                let expr = self.or(compiler)?;
+                match self.next()? {
+                    ")" => Ok(expr),
+                    token => Err(format!("expected ')' instead of '{}'", token)),
+                }
+            }
+            quantifier @ "1" | quantifier @ "all" => {
+                if self.next()? != "of" {
+                    return Err(format!("expected 'of' after '{}'", quantifier));
+                }
+                let names = compiler.selections_matching(self.next()?)?;
+                let terms = names
+                    .iter()
+                    .map(|name| compiler.selection(name))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(if quantifier == "1" {
+                    Expr::Or(terms)
+                } else {
+                    Expr::And(terms)
+                })
+            }
+            name => compiler.selection(name),
+        }
+    }
+}
+
+/* Disjunctive normal form of `expr`, or of its negation. */
+fn dnf(expr: &Expr, negated: bool) -> Result<Vec<Vec<(u32, bool)>>, String> {
+    match (expr, negated) {
+        (Expr::Atom(atom), _) => Ok(vec![vec![(*atom, negated)]]),
+        (Expr::Not(expr), _) => dnf(expr, !negated),
+        (Expr::Or(terms), false) | (Expr::And(terms), true) => {
+            let mut clauses = Vec::new();
+            for term in terms {
+                clauses.extend(dnf(term, negated)?);
+                if clauses.len() > MAX_CLAUSES {
+                    return Err("condition too complex".to_string());
+                }
+            }
+            Ok(clauses)
+        }
+        (Expr::And(terms), false) | (Expr::Or(terms), true) => {
+            let mut clauses = vec![Vec::new()];
+            for term in terms {
+                let term_clauses = dnf(term, negated)?;
+                if clauses.len() * term_clauses.len() > MAX_CLAUSES {
+                    return Err("condition too complex".to_string());
+                }
+                clauses = clauses
+                    .iter()
+                    .flat_map(|clause| {
+                        term_clauses.iter().map(move |term_clause| {
+                            let mut c: Vec<(u32, bool)> = clause.clone();
+                            c.extend(term_clause.iter().cloned());
+                            c
+                        })
+                    })
+                    .collect();
+            }
+            Ok(clauses)
+        }
+    }
+}
+
+/* Removes duplicate atoms, contradictory clauses, and duplicate clauses. */
+fn simplify(clauses: Vec<Vec<(u32, bool)>>) -> Vec<Vec<(u32, bool)>> {
+    let mut result = BTreeSet::new();
+    for clause in clauses {
+        let clause: BTreeSet<(u32, bool)> = clause.into_iter().collect();
+        if clause
+            .iter()
+            .any(|(atom, neg)| clause.contains(&(*atom, !neg)))
+        {
+            continue;
+        }
+        result.insert(clause.into_iter().collect::<Vec<_>>());
+    }
+    result.into_iter().collect()
+}
+
+/// Compile a Sigma rule from its YAML source.
+pub fn compile_rule(yaml: &str) -> Result<SigmaRule, String> {
+    let rule: Value = serde_yaml::from_str(yaml).map_err(|e| format!("invalid rule: {}", e))?;
+    let rule = rule
+        .as_mapping()
+        .ok_or_else(|| "invalid rule: not a map".to_string())?;
+    let title = string_field(rule, "title");
+    let id = match rule.get("id").and_then(Value::as_str) {
+        Some(id) => id.to_string(),
+        None if !title.is_empty() => title.clone(),
+        None => return Err("rule without id or title".to_string()),
+    };
+    let logsource = match rule.get("logsource").and_then(Value::as_mapping) {
+        Some(map) => LogSource {
+            category: string_field(map, "category"),
+            product: string_field(map, "product"),
+            service: string_field(map, "service"),
+        },
+        None => LogSource::default(),
+    };
+    let detection = rule
+        .get("detection")
+        .and_then(Value::as_mapping)
+        .ok_or_else(|| format!("rule {}: missing detection", id))?;
+
+    let mut selections = BTreeMap::new();
+    let mut conditions = Vec::new();
+    for (name, value) in detection {
+        match name.as_str() {
+            Some("condition") => match value {
+                Value::String(c) => conditions.push(c.clone()),
+                Value::Sequence(cs) => {
+                    for c in cs {
+                        conditions.push(
+                            c.as_str()
+                                .ok_or_else(|| format!("rule {}: invalid condition", id))?
+                                .to_string(),
+                        );
+                    }
+                }
+                _ => return Err(format!("rule {}: invalid condition", id)),
+            },
+            // Correlation windows only matter to aggregations.
+            Some("timeframe") => (),
+            Some(name) => {
+                selections.insert(name.to_string(), value.clone());
+            }
+            None => return Err(format!("rule {}: invalid selection name", id)),
+        }
+    }
+    if conditions.is_empty() {
+        return Err(format!("rule {}: missing condition", id));
+    }
+
+    let mut compiler = Compiler {
+        atoms: Vec::new(),
+        selections,
+        compiled: BTreeMap::new(),
+    };
+    let mut terms = Vec::new();
+    for condition in conditions.iter() {
+        terms.push(
+            Parser::new(condition)
+                .and_then(|p| p.parse(&mut compiler))
+                .map_err(|e| format!("rule {}: {}", id, e))?,
+        );
+    }
+    let clauses = dnf(&Expr::Or(terms), false).map_err(|e| format!("rule {}: {}", id, e))?;
+    Ok(SigmaRule {
+        id,
+        title,
+        level: string_field(rule, "level"),
+        logsource,
+        atoms: compiler.atoms,
+        clauses: simplify(clauses),
+    })
+}
+
+impl SigmaRule {
+    /// The updates that insert (or delete) the facts of the rule.
+    pub fn updates(&self, insert: bool) -> Vec<UpdCmd> {
+        let rel = |name: &str| RelIdentifier::RelName(Cow::from(format!("sigma::{}", name)));
+        let fact = |name: &str, fields: Vec<(&'static str, Record)>| {
+            let record = Record::NamedStruct(
+                Cow::from(format!("sigma::{}", name)),
+                fields.into_iter().map(|(n, v)| (Cow::from(n), v)).collect(),
+            );
+            if insert {
+                UpdCmd::Insert(rel(name), record)
+            } else {
+                UpdCmd::Delete(rel(name), record)
+            }
+        };
+        let id = || Record::String(self.id.clone());
+        let mut updates = Vec::new();
+        if insert {
+            updates.push(fact(
+                "Rule",
+                vec![
+                    ("rule_id", id()),
+                    ("title", Record::String(self.title.clone())),
+                    ("level", Record::String(self.level.clone())),
+                    ("logsource", self.logsource.to_record()),
+                ],
+            ));
+        } else {
+            updates.push(UpdCmd::DeleteKey(rel("Rule"), id()));
+        }
+        for (i, atom) in self.atoms.iter().enumerate() {
+            let op = match atom.op {
+                MatchOp::Equals => "sigma::Equals",
+                MatchOp::Contains => "sigma::Contains",
+                MatchOp::StartsWith => "sigma::StartsWith",
+                MatchOp::EndsWith => "sigma::EndsWith",
+                MatchOp::Regex => "sigma::Regex",
+                MatchOp::Exists => "sigma::Exists",
+                MatchOp::IsNull => "sigma::IsNull",
+            };
+            updates.push(fact(
+                "Atom",
+                vec![
+                    ("rule_id", id()),
+                    ("atom", Record::Int(i.into())),
+                    ("field", Record::String(atom.field.clone())),
+                    ("op", Record::NamedStruct(Cow::from(op), Vec::new())),
+                    (
+                        "values",
+                        Record::Array(
+                            CollectionKind::Vector,
+                            atom.values.iter().cloned().map(Record::String).collect(),
+                        ),
+                    ),
+                    ("all", Record::Bool(atom.all)),
+                ],
+            ));
+        }
+        for (i, clause) in self.clauses.iter().enumerate() {
+            for (atom, negated) in clause {
+                updates.push(fact(
+                    "Clause",
+                    vec![
+                        ("rule_id", id()),
+                        ("clause", Record::Int(i.into())),
+                        ("atom", Record::Int((*atom).into())),
+                        ("negated", Record::Bool(*negated)),
+                    ],
+                ));
+            }
+        }
+        updates
+    }
+}
+
+/// The update that inserts an event into `sigma::Event`, or replaces the
+/// event with the same id.
+pub fn event_update(id: u64, logsource: &LogSource, fields: &BTreeMap<String, String>) -> UpdCmd {
+    let fields = fields
+        .iter()
+        .map(|(k, v)| Record::Tuple(vec![Record::String(k.clone()), Record::String(v.clone())]))
+        .collect();
+    UpdCmd::InsertOrUpdate(
+        RelIdentifier::RelName(Cow::from("sigma::Event")),
+        Record::NamedStruct(
+            Cow::from("sigma::Event"),
+            vec![
+                (Cow::from("id"), Record::Int(id.into())),
+                (Cow::from("logsource"), logsource.to_record()),
+                (
+                    Cow::from("fields"),
+                    Record::Array(CollectionKind::Map, fields),
+                ),
+            ],
+        ),
+    )
+}
+
+/// The rules loaded into a program.
+pub struct SigmaRuleSet {
+    client: ClientId,
+    rules: BTreeMap<String, SigmaRule>,
+}
+
+impl SigmaRuleSet {
+    pub fn new(client: ClientId) -> Result<Self, String> {
+        if Relations::try_from("sigma::Rule").is_err() {
+            return Err("the program does not import the sigma library".to_string());
+        }
+        Ok(Self {
+            client,
+            rules: BTreeMap::new(),
+        })
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &SigmaRule> {
+        self.rules.values()
+    }
+
+    /// Load `rules` in one transaction, replacing loaded rules with the same
+    /// ids.
+    pub fn load(&mut self, hddlog: &HDDlog, rules: Vec<SigmaRule>) -> Result<(), String> {
+        let mut updates = Vec::new();
+        for rule in rules.iter() {
+            if let Some(old) = self.rules.get(&rule.id) {
+                updates.extend(old.updates(false));
+            }
+            updates.extend(rule.updates(true));
+        }
+        self.apply(hddlog, updates)?;
+        for rule in rules {
+            self.rules.insert(rule.id.clone(), rule);
+        }
+        Ok(())
+    }
+
+    /// Remove the rules with the given ids in one transaction.
+    pub fn remove(&mut self, hddlog: &HDDlog, ids: &[&str]) -> Result<(), String> {
+        let updates = ids
+            .iter()
+            .filter_map(|id| self.rules.get(*id))
+            .flat_map(|rule| rule.updates(false))
+            .collect();
+        self.apply(hddlog, updates)?;
+        for id in ids {
+            self.rules.remove(*id);
+        }
+        Ok(())
+    }
+
+    fn apply(&self, hddlog: &HDDlog, updates: Vec<UpdCmd>) -> Result<(), String> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+        let txn = hddlog.try_start_transaction(self.client, None)?;
+        txn.apply_updates_dynamic(&mut updates.into_iter())?;
+        txn.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(field: &str, op: MatchOp, values: &[&str], all: bool) -> Atom {
+        Atom {
+            field: field.to_string(),
+            op,
+            values: values.iter().map(|v| v.to_string()).collect(),
+            all,
+        }
+    }
+
+    /// An update in the form `<command> <relation> <record>`.
+    fn describe(update: &UpdCmd) -> String {
+        let (command, rel, record) = match update {
+            UpdCmd::Insert(rel, record) => ("insert", rel, record),
+            UpdCmd::InsertOrUpdate(rel, record) => ("insert_or_update", rel, record),
+            UpdCmd::Delete(rel, record) => ("delete", rel, record),
+            UpdCmd::DeleteKey(rel, record) => ("delete_key", rel, record),
+            update => panic!("unexpected update {:?}", update),
+        };
+        match rel {
+            RelIdentifier::RelName(name) => format!("{} {} {}", command, name, record),
+            rel => panic!("unexpected relation {:?}", rel),
+        }
+    }
+
+    #[test]
+    fn compile_rules() {
+        let rule = compile_rule(
+            r#"
+title: Whoami
+id: r1
+level: high
+logsource:
+  category: process_creation
+  product: windows
+detection:
+  selection:
+    Image|endswith: '\whoami.exe'
+    CommandLine|contains|all:
+      - '/user'
+      - '/PRIV'
+  filter:
+    User: null
+  condition: selection and not filter
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rule,
+            SigmaRule {
+                id: "r1".to_string(),
+                title: "Whoami".to_string(),
+                level: "high".to_string(),
+                logsource: LogSource {
+                    category: "process_creation".to_string(),
+                    product: "windows".to_string(),
+                    service: String::new(),
+                },
+                atoms: vec![
+                    atom("Image", MatchOp::EndsWith, &["\\whoami.exe"], false),
+                    atom("CommandLine", MatchOp::Contains, &["/user", "/priv"], true),
+                    atom("User", MatchOp::IsNull, &[], false),
+                ],
+                clauses: vec![vec![(0, false), (1, false), (2, true)]],
+            }
+        );
+
+        // The title doubles as the id.
+        let rule =
+            compile_rule("title: Untitled\ndetection: {sel: {a: 1}, condition: sel}").unwrap();
+        assert_eq!(rule.id, "Untitled");
+        assert_eq!(rule.logsource, LogSource::default());
+    }
+
+    #[test]
+    fn compile_values() {
+        let rule = compile_rule(
+            r#"
+id: r2
+detection:
+  sel:
+    a: 'Foo*'
+    b: '*Bar*'
+    c: '*baz'
+    d: 'a.?'
+    e: ['x*', '*y']
+    f|re: '^A+$'
+    g|exists: false
+    h|exists: true
+    i: 'a\*b'
+    j|contains: 'a*b'
+    k: [1, null]
+  condition: sel
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rule.atoms,
+            vec![
+                atom("a", MatchOp::StartsWith, &["foo"], false),
+                atom("b", MatchOp::Contains, &["bar"], false),
+                atom("c", MatchOp::EndsWith, &["baz"], false),
+                atom("d", MatchOp::Regex, &["(?is)^a\\..$"], false),
+                // Values that compile to different ops become regular
+                // expressions.
+                atom("e", MatchOp::Regex, &["(?is)^x.*$", "(?is)^.*y$"], false),
+                atom("f", MatchOp::Regex, &["^A+$"], false),
+                atom("g", MatchOp::IsNull, &[], false),
+                atom("h", MatchOp::Exists, &[], false),
+                atom("i", MatchOp::Equals, &["a*b"], false),
+                atom("j", MatchOp::Regex, &["(?is)^.*a.*b.*$"], false),
+                atom("k", MatchOp::IsNull, &[], false),
+                atom("k", MatchOp::Equals, &["1"], false),
+            ]
+        );
+        // `k` matches either of its atoms.
+        let conjunction: Vec<(u32, bool)> = (0..10).map(|i| (i, false)).collect();
+        let with = |atom: u32| {
+            let mut clause = conjunction.clone();
+            clause.push((atom, false));
+            clause
+        };
+        assert_eq!(rule.clauses, vec![with(10), with(11)]);
+    }
+
+    #[test]
+    fn compile_conditions() {
+        let rule = compile_rule(
+            r#"
+id: r3
+detection:
+  keywords:
+    - '*mimikatz*'
+    - '*sekurlsa*'
+  sel_a:
+    x: 1
+  sel_b:
+    y: 2
+  _internal:
+    z: 3
+  condition:
+    - keywords
+    - 1 of sel_* and not (_internal or keywords)
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rule.atoms,
+            vec![
+                atom("", MatchOp::Contains, &["mimikatz", "sekurlsa"], false),
+                atom("x", MatchOp::Equals, &["1"], false),
+                atom("y", MatchOp::Equals, &["2"], false),
+                atom("z", MatchOp::Equals, &["3"], false),
+            ]
+        );
+        assert_eq!(
+            rule.clauses,
+            vec![
+                vec![(0, false)],
+                vec![(0, true), (1, false), (3, true)],
+                vec![(0, true), (2, false), (3, true)],
+            ]
+        );
+
+        // `them` skips selections starting with `_`; contradictory and
+        // duplicate clauses are dropped.
+        let rule = compile_rule(
+            r#"
+id: r4
+detection:
+  sel:
+    a: 1
+  _filter:
+    b: 2
+  condition: all of them or (sel and not sel) or sel
+"#,
+        )
+        .unwrap();
+        assert_eq!(rule.atoms, vec![atom("a", MatchOp::Equals, &["1"], false)]);
+        assert_eq!(rule.clauses, vec![vec![(0, false)]]);
+    }
+
+    #[test]
+    fn compile_errors() {
+        let detection = |detection: &str| {
+            compile_rule(&format!("id: e1\ndetection:\n{}", detection)).unwrap_err()
+        };
+        let sel = "  sel: {a: 1}\n";
+        for (condition, err) in &[
+            (
+                "sel | count() > 5",
+                "aggregations in conditions are not supported",
+            ),
+            ("missing", "unknown selection 'missing'"),
+            ("1 of nothing*", "no selection matches 'nothing*'"),
+            ("sel and", "unexpected end of condition"),
+            ("(sel", "unexpected end of condition"),
+            ("(sel sel", "expected ')' instead of 'sel'"),
+            ("sel)", "unexpected ')' in condition"),
+            ("1 sel", "expected 'of' after '1'"),
+        ] {
+            assert_eq!(
+                detection(&format!("{}  condition: {}\n", sel, condition)),
+                format!("rule e1: {}", err),
+                "{}",
+                condition
+            );
+        }
+        assert_eq!(
+            detection("  sel: {a|base64: x}\n  condition: sel\n"),
+            "rule e1: unsupported modifier 'base64' of a|base64"
+        );
+        assert_eq!(
+            detection("  sel: {a|exists: [true, false]}\n  condition: sel\n"),
+            "rule e1: invalid value of a|exists"
+        );
+        assert!(detection("  sel: {a: {b: c}}\n  condition: sel\n")
+            .starts_with("rule e1: unsupported value "));
+        assert_eq!(
+            detection("  sel: 1\n  condition: sel\n"),
+            "rule e1: invalid selection 'sel'"
+        );
+        assert_eq!(detection(sel), "rule e1: missing condition");
+        assert_eq!(
+            detection(&format!("{}  condition: 1\n", sel)),
+            "rule e1: invalid condition"
+        );
+
+        assert_eq!(
+            compile_rule("id: e1").unwrap_err(),
+            "rule e1: missing detection"
+        );
+        assert_eq!(
+            compile_rule("detection: {sel: {a: 1}, condition: sel}").unwrap_err(),
+            "rule without id or title"
+        );
+        assert_eq!(compile_rule("- a").unwrap_err(), "invalid rule: not a map");
+        assert!(compile_rule("id: [")
+            .unwrap_err()
+            .starts_with("invalid rule: "));
+
+        // A conjunction of 11 disjunctions has 2^11 clauses.
+        let mut selections = String::new();
+        for i in 0..11 {
+            selections.push_str(&format!("  s{}: {{a: [1, null]}}\n", i));
+        }
+        assert_eq!(
+            detection(&format!("{}  condition: all of s*\n", selections)),
+            "rule e1: condition too complex"
+        );
+        let rule = compile_rule(&format!(
+            "id: e1\ndetection:\n{}  condition: 1 of s*\n",
+            selections
+        ))
+        .unwrap();
+        assert_eq!(rule.clauses.len(), 22);
+    }
+
+    const NETCAT: &str = "
+id: netcat
+title: Netcat
+level: low
+logsource: {product: linux}
+detection:
+  sel: {cmd|contains: nc}
+  filter: {user: root}
+  condition: sel and not filter
+";
+
+    #[test]
+    fn rule_updates() {
+        let rule = compile_rule(NETCAT).unwrap();
+        let facts = [
+            r#"sigma::Atom sigma::Atom{.rule_id = "netcat", .atom = 0, .field = "cmd", .op = sigma::Contains{}, .values = ["nc"], .all = false}"#,
+            r#"sigma::Atom sigma::Atom{.rule_id = "netcat", .atom = 1, .field = "user", .op = sigma::Equals{}, .values = ["root"], .all = false}"#,
+            r#"sigma::Clause sigma::Clause{.rule_id = "netcat", .clause = 0, .atom = 0, .negated = false}"#,
+            r#"sigma::Clause sigma::Clause{.rule_id = "netcat", .clause = 0, .atom = 1, .negated = true}"#,
+        ];
+        let mut inserts = vec![r#"insert sigma::Rule sigma::Rule{.rule_id = "netcat", .title = "Netcat", .level = "low", .logsource = sigma::LogSource{.category = "", .product = "linux", .service = ""}}"#.to_string()];
+        inserts.extend(facts.iter().map(|f| format!("insert {}", f)));
+        assert_eq!(
+            rule.updates(true).iter().map(describe).collect::<Vec<_>>(),
+            inserts
+        );
+
+        // Rules are deleted by key.
+        let mut deletes = vec![r#"delete_key sigma::Rule "netcat""#.to_string()];
+        deletes.extend(facts.iter().map(|f| format!("delete {}", f)));
+        assert_eq!(
+            rule.updates(false).iter().map(describe).collect::<Vec<_>>(),
+            deletes
+        );
+    }
+
+    #[test]
+    fn event_updates() {
+        let logsource = LogSource {
+            product: "linux".to_string(),
+            ..LogSource::default()
+        };
+        let mut fields = BTreeMap::new();
+        fields.insert("user".to_string(), "bob".to_string());
+        fields.insert("cmd".to_string(), "nc -l".to_string());
+        assert_eq!(
+            describe(&event_update(7, &logsource, &fields)),
+            r#"insert_or_update sigma::Event sigma::Event{.id = 7, .logsource = sigma::LogSource{.category = "", .product = "linux", .service = ""}, .fields = [("cmd", "nc -l"), ("user", "bob")]}"#
+        );
+        assert_eq!(
+            describe(&event_update(8, &LogSource::default(), &BTreeMap::new())),
+            r#"insert_or_update sigma::Event sigma::Event{.id = 8, .logsource = sigma::LogSource{.category = "", .product = "", .service = ""}, .fields = []}"#
+        );
+    }
+}
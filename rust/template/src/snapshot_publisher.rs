@@ -0,0 +1,535 @@
+//! Periodic publication of output relations to object stores.
+//!
+//! `SnapshotPublisher` writes the contents of, or the changes to, selected
+//! output relations to an object store such as S3 or GCS, so that batch jobs
+//! can consume the results of the program without a live connection to it.
+//! Each publication is a set of gzip-compressed JSON Lines files, one per
+//! relation, with one `{"record": <text form>, "weight": <weight>}` object
+//! per line, followed by a manifest:
+//!
+//! ```text
+//! <prefix><run>/<sequence>/<relation>.snapshot.jsonl.gz
+//! <prefix><run>/<sequence>/<relation>.delta.jsonl.gz
+//! <prefix><run>/<sequence>/manifest.json
+//! <prefix>latest.json
+//! ```
+//!
+//! `<run>` is the time the publisher was attached, in milliseconds since the
+//! Unix epoch, and `<sequence>` numbers publications from 1.  The manifest
+//! lists the files of the publication along with their record counts, the
+//! last DDlog commit they include, and the key of the previous manifest;
+//! it is written after all files and copied to `latest.json`, so a consumer
+//! that reads a manifest can rely on its files being complete.
+//!
+//! In `PublishMode::Snapshots` every publication contains the full contents
+//! of the relations.  In `PublishMode::Deltas` the first publication is a
+//! snapshot and the following ones contain the changes made since the
+//! previous publication; publications without changes are skipped.  The
+//! contents of the relations at the time the publisher is attached are
+//! only known if the program stores output relations (`do_store`);
+//! otherwise the initial snapshot is empty.
+//!
+//! Publications are made by a background thread every
+//! `PublisherConfig::interval` and/or every `PublisherConfig::every_commits`
+//! commits that change the relations, so commits never wait for the object
+//! store.  Changes that fail to upload are kept and included in the next
+//! publication.  Requires the `snapshots` feature; `S3Store` additionally
+//! requires the `s3` feature.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::json;
+
+use differential_datalog::ddval::DDValue;
+use differential_datalog::program::RelId;
+use differential_datalog::record::IntoRecord;
+
+use crate::api::{CommitSubscriptionId, HDDlog};
+use crate::update_handler::ChangelogBatch;
+use crate::{relid2name, Relations};
+
+/// Destination of published files.
+pub trait ObjectStore: Send {
+    /// Store `data` under `key`, replacing any existing object.
+    fn put(&mut self, key: &str, data: Vec<u8>, content_type: &str) -> Result<(), String>;
+}
+
+/// Stores objects as files under a directory, e.g., a locally mounted
+/// bucket.  Keys are relative paths.
+#[derive(Debug, Clone)]
+pub struct DirectoryStore {
+    root: PathBuf,
+}
+
+impl DirectoryStore {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl ObjectStore for DirectoryStore {
+    fn put(&mut self, key: &str, data: Vec<u8>, _content_type: &str) -> Result<(), String> {
+        let path = self.root.join(key);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("failed to create {}: {}", dir.display(), e))?;
+        }
+        // Write to a temporary file first, so that readers never see a
+        // partially written object.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data).map_err(|e| format!("failed to write {}: {}", tmp.display(), e))?;
+        fs::rename(&tmp, &path).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// Stores objects in an S3 bucket, or in any store with an S3-compatible
+/// API, such as GCS (endpoint `https://storage.googleapis.com`, with HMAC
+/// keys) or MinIO.  Credentials are taken from the environment
+/// (`AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`), the AWS profile, or the
+/// instance metadata.
+#[cfg(feature = "s3")]
+pub struct S3Store {
+    bucket: s3::bucket::Bucket,
+}
+
+#[cfg(feature = "s3")]
+impl S3Store {
+    /// Connect to `bucket` in `region`, at `endpoint` if specified.
+    pub fn new(bucket: &str, region: &str, endpoint: Option<&str>) -> Result<Self, String> {
+        let region = match endpoint {
+            Some(endpoint) => s3::region::Region::Custom {
+                region: region.to_string(),
+                endpoint: endpoint.to_string(),
+            },
+            None => region
+                .parse()
+                .map_err(|e| format!("invalid region {}: {}", region, e))?,
+        };
+        let credentials = s3::creds::Credentials::default()
+            .map_err(|e| format!("failed to load S3 credentials: {}", e))?;
+        let bucket = s3::bucket::Bucket::new(bucket, region, credentials)
+            .map_err(|e| format!("failed to open bucket {}: {}", bucket, e))?;
+        Ok(Self { bucket })
+    }
+}
+
+#[cfg(feature = "s3")]
+impl ObjectStore for S3Store {
+    fn put(&mut self, key: &str, data: Vec<u8>, content_type: &str) -> Result<(), String> {
+        let response = self
+            .bucket
+            .put_object_with_content_type(key, &data, content_type)
+            .map_err(|e| format!("failed to upload {}: {}", key, e))?;
+        if response.status_code() / 100 != 2 {
+            return Err(format!(
+                "failed to upload {}: HTTP status {}",
+                key,
+                response.status_code()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// What each publication contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublishMode {
+    /// The full contents of the relations.
+    Snapshots,
+    /// The changes since the previous publication, after an initial
+    /// snapshot.
+    Deltas,
+}
+
+/// Configuration of a `SnapshotPublisher`.
+#[derive(Debug, Clone)]
+pub struct PublisherConfig {
+    /// Prepended to all keys, e.g., `"ddlog/"`.
+    pub prefix: String,
+    pub mode: PublishMode,
+    /// Publish at this interval, if anything changed.
+    pub interval: Option<Duration>,
+    /// Publish after this many commits that change the relations.
+    pub every_commits: Option<u64>,
+}
+
+impl Default for PublisherConfig {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            mode: PublishMode::Deltas,
+            interval: Some(Duration::from_secs(60)),
+            every_commits: None,
+        }
+    }
+}
+
+type Contents = BTreeMap<RelId, BTreeMap<DDValue, isize>>;
+
+fn add(contents: &mut Contents, relid: RelId, value: &DDValue, weight: isize) {
+    let facts = contents.entry(relid).or_insert_with(BTreeMap::new);
+    let w = facts.entry(value.clone()).or_insert(0);
+    *w += weight;
+    if *w == 0 {
+        facts.remove(value);
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    /// Current contents of the relations, maintained in snapshot mode.
+    contents: Contents,
+    /// Changes not published yet, in delta mode.
+    changes: Contents,
+    /// The next publication must be a snapshot.
+    snapshot_due: bool,
+    /// Commits since the last publication.
+    commits: u64,
+    /// The last commit seen.
+    commit: u64,
+    /// Publish as soon as possible.
+    due: bool,
+    stop: bool,
+    error: Option<String>,
+}
+
+struct Shared {
+    state: Mutex<Pending>,
+    wakeup: Condvar,
+}
+
+/// A publisher attached to a running program.  Dropping it publishes any
+/// pending changes and detaches the publisher.
+pub struct SnapshotPublisher<'a> {
+    hddlog: &'a HDDlog,
+    subscription: CommitSubscriptionId,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/* Uploads publications; owned by the background thread. */
+struct Writer {
+    store: Box<dyn ObjectStore>,
+    config: PublisherConfig,
+    relids: Vec<RelId>,
+    /// Key prefix of this run.
+    run: String,
+    sequence: u64,
+    previous: Option<String>,
+}
+
+impl Writer {
+    fn encode(facts: &BTreeMap<DDValue, isize>) -> Result<Vec<u8>, String> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        for (value, weight) in facts.iter() {
+            let line =
+                json!({ "record": value.clone().into_record().to_string(), "weight": weight });
+            writeln!(encoder, "{}", line).map_err(|e| e.to_string())?;
+        }
+        encoder.finish().map_err(|e| e.to_string())
+    }
+
+    fn publish(&mut self, contents: &Contents, snapshot: bool, commit: u64) -> Result<(), String> {
+        let sequence = self.sequence + 1;
+        let dir = format!("{}{:08}/", self.run, sequence);
+        let kind = if snapshot { "snapshot" } else { "delta" };
+        let empty = BTreeMap::new();
+        let mut files = Vec::new();
+        for relid in self.relids.iter() {
+            let facts = contents.get(relid).unwrap_or(&empty);
+            if !snapshot && facts.is_empty() {
+                continue;
+            }
+            let name = relid2name(*relid).unwrap_or("?");
+            let key = format!("{}{}.{}.jsonl.gz", dir, name, kind);
+            let data = Self::encode(facts)?;
+            let bytes = data.len();
+            self.store.put(&key, data, "application/gzip")?;
+            files.push(json!({
+                "relation": name,
+                "key": key,
+                "records": facts.len(),
+                "bytes": bytes,
+            }));
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let manifest_key = format!("{}manifest.json", dir);
+        let manifest = json!({
+            "sequence": sequence,
+            "kind": kind,
+            "commit": commit,
+            "timestamp_ms": timestamp,
+            "previous": self.previous,
+            "files": files,
+        })
+        .to_string();
+        self.store.put(
+            &manifest_key,
+            manifest.clone().into_bytes(),
+            "application/json",
+        )?;
+        self.store.put(
+            &format!("{}latest.json", self.config.prefix),
+            manifest.into_bytes(),
+            "application/json",
+        )?;
+        self.sequence = sequence;
+        self.previous = Some(manifest_key);
+        Ok(())
+    }
+
+    /* Publishes whatever is pending. */
+    fn publish_pending(&mut self, shared: &Shared) {
+        let (contents, snapshot, commit) = {
+            let mut state = shared.state.lock().unwrap();
+            state.due = false;
+            let changed = state.commits > 0;
+            state.commits = 0;
+            let snapshot = state.snapshot_due || self.config.mode == PublishMode::Snapshots;
+            let contents = if self.config.mode == PublishMode::Snapshots {
+                if !changed && !state.snapshot_due {
+                    return;
+                }
+                state.contents.clone()
+            } else {
+                if !snapshot && state.changes.values().all(BTreeMap::is_empty) {
+                    return;
+                }
+                std::mem::take(&mut state.changes)
+            };
+            state.snapshot_due = false;
+            (contents, snapshot, state.commit)
+        };
+        let res = self.publish(&contents, snapshot, commit);
+        let mut state = shared.state.lock().unwrap();
+        match res {
+            Ok(()) => state.error = None,
+            Err(e) => {
+                state.error = Some(e);
+                // Keep the data for the next attempt.
+                if snapshot {
+                    state.snapshot_due = true;
+                }
+                if self.config.mode == PublishMode::Deltas {
+                    for (relid, facts) in contents.iter() {
+                        for (value, weight) in facts.iter() {
+                            add(&mut state.changes, *relid, value, *weight);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn run(mut self, shared: Arc<Shared>) {
+        loop {
+            let stop = {
+                let deadline = self
+                    .config
+                    .interval
+                    .map(|interval| Instant::now() + interval);
+                let mut state = shared.state.lock().unwrap();
+                while !state.due && !state.stop {
+                    match deadline {
+                        Some(deadline) => {
+                            let now = Instant::now();
+                            if now >= deadline {
+                                break;
+                            }
+                            state = shared.wakeup.wait_timeout(state, deadline - now).unwrap().0;
+                        }
+                        None => state = shared.wakeup.wait(state).unwrap(),
+                    }
+                }
+                state.stop
+            };
+            self.publish_pending(&shared);
+            if stop {
+                return;
+            }
+        }
+    }
+}
+
+impl<'a> SnapshotPublisher<'a> {
+    /// Publish output relations `relations` of `hddlog` to `store`.
+    pub fn attach(
+        hddlog: &'a HDDlog,
+        store: Box<dyn ObjectStore>,
+        relations: &[&str],
+        config: PublisherConfig,
+    ) -> Result<Self, String> {
+        if config.interval.is_none() && config.every_commits.is_none() {
+            return Err("either an interval or a number of commits is required".to_string());
+        }
+        let mut relids = Vec::new();
+        for relation in relations {
+            match Relations::try_from(*relation) {
+                Ok(rel) if rel.is_output() => relids.push(rel as RelId),
+                _ => return Err(format!("unknown output relation {}", relation)),
+            }
+        }
+
+        let mut pending = Pending {
+            snapshot_due: true,
+            due: true,
+            ..Pending::default()
+        };
+        if hddlog.db.is_some() {
+            let target = match config.mode {
+                PublishMode::Snapshots => &mut pending.contents,
+                PublishMode::Deltas => &mut pending.changes,
+            };
+            for (relid, facts) in hddlog.dump_stored(&relids)? {
+                for (value, weight) in facts.iter() {
+                    add(target, relid, value, *weight);
+                }
+            }
+        }
+        let shared = Arc::new(Shared {
+            state: Mutex::new(pending),
+            wakeup: Condvar::new(),
+        });
+
+        let run = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let writer = Writer {
+            store,
+            run: format!("{}{}/", config.prefix, run),
+            config: config.clone(),
+            relids: relids.clone(),
+            sequence: 0,
+            previous: None,
+        };
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || writer.run(shared))
+        };
+
+        let subscription = {
+            let shared = shared.clone();
+            let mode = config.mode;
+            let every_commits = config.every_commits;
+            hddlog.subscribe_commits(
+                &relids,
+                Arc::new(move |batches: &[ChangelogBatch]| {
+                    let mut state = shared.state.lock().unwrap();
+                    for batch in batches {
+                        let target = match mode {
+                            PublishMode::Snapshots => &mut state.contents,
+                            PublishMode::Deltas => &mut state.changes,
+                        };
+                        for (value, weight) in batch.changes.iter() {
+                            add(target, batch.relid, value, *weight);
+                        }
+                        state.commit = batch.commit;
+                    }
+                    state.commits += 1;
+                    if every_commits.map_or(false, |n| state.commits >= n) {
+                        state.due = true;
+                        shared.wakeup.notify_all();
+                    }
+                }),
+            )
+        };
+        let subscription = match subscription {
+            Ok(subscription) => subscription,
+            Err(e) => {
+                shared.state.lock().unwrap().stop = true;
+                shared.wakeup.notify_all();
+                let _ = thread.join();
+                return Err(e);
+            }
+        };
+        Ok(Self {
+            hddlog,
+            subscription,
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Publish pending changes now instead of waiting for the next
+    /// scheduled publication.
+    pub fn publish_now(&self) {
+        self.shared.state.lock().unwrap().due = true;
+        self.shared.wakeup.notify_all();
+    }
+
+    /// The error of the last publication, if it failed.
+    pub fn error(&self) -> Option<String> {
+        self.shared.state.lock().unwrap().error.clone()
+    }
+}
+
+impl Drop for SnapshotPublisher<'_> {
+    fn drop(&mut self) {
+        self.hddlog.unsubscribe_commits(self.subscription);
+        self.shared.state.lock().unwrap().stop = true;
+        self.shared.wakeup.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use differential_datalog::ddval::DDValConvert;
+    use flate2::read::GzDecoder;
+
+    #[test]
+    fn add_drops_retracted_facts() {
+        let a = "a".to_string().into_ddvalue();
+        let b = "b".to_string().into_ddvalue();
+        let mut contents = Contents::new();
+        add(&mut contents, 1, &a, 1);
+        add(&mut contents, 1, &b, 2);
+        add(&mut contents, 1, &a, -1);
+        assert_eq!(contents[&1].iter().collect::<Vec<_>>(), vec![(&b, &2)]);
+    }
+
+    #[test]
+    fn encode_json_lines() {
+        let mut facts = BTreeMap::new();
+        facts.insert("a".to_string().into_ddvalue(), 1);
+        facts.insert("c".to_string().into_ddvalue(), -2);
+        let mut text = String::new();
+        GzDecoder::new(&Writer::encode(&facts).unwrap()[..])
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(
+            text,
+            concat!(
+                r#"{"record":"\"a\"","weight":1}"#,
+                "\n",
+                r#"{"record":"\"c\"","weight":-2}"#,
+                "\n"
+            )
+        );
+        assert!(
+            GzDecoder::new(&Writer::encode(&BTreeMap::new()).unwrap()[..])
+                .bytes()
+                .next()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! Mirror output relations into SQLite tables.
+//!
+//! `SqliteSink` keeps one table per selected output relation in sync with
+//! the relation, so that the results of the program can be queried with SQL
+//! or inspected with standard SQLite tools.  The changes made by each commit
+//! (see `HDDlog::subscribe_commits()`) are applied in a single SQLite
+//! transaction, so readers never observe a partially applied commit.
+//!
+//! The table of relation `R` is named `R`, with characters that are not
+//! valid in SQL identifiers replaced by `_`, and has the following columns:
+//!
+//! * `_ddlog_key`: the text form of the record, which is the primary key.
+//! * One column per field when the record is a struct with named fields.
+//!   Integers that fit in 64 bits, floating point numbers, Booleans (as 0 or
+//!   1), and strings are stored natively; other values are stored in their
+//!   text form.  Columns are added as new struct constructors are seen.
+//! * `_ddlog_weight`: the weight of the record.
+//!
+//! Insertions are applied as upserts that add to the weight of the record;
+//! records whose weight drops to zero are deleted.  When the program stores
+//! the contents of output relations (`do_store`), the current contents are
+//! copied when the sink is attached; the sink should be attached while no
+//! transaction is in progress.  Requires the `sqlite` feature.
+
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+
+use num::ToPrimitive;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection, Transaction};
+
+use differential_datalog::ddval::DDValue;
+use differential_datalog::program::RelId;
+use differential_datalog::record::{IntoRecord, Record};
+
+use crate::api::{CommitSubscriptionId, HDDlog};
+use crate::update_handler::ChangelogBatch;
+use crate::{relid2name, Relations};
+
+const KEY_COLUMN: &str = "_ddlog_key";
+const WEIGHT_COLUMN: &str = "_ddlog_weight";
+
+#[derive(Debug)]
+struct SqlTable {
+    name: String,
+    /// Field columns created so far.
+    columns: HashSet<String>,
+}
+
+struct State {
+    conn: Connection,
+    tables: BTreeMap<RelId, SqlTable>,
+    /// The first error encountered while applying a commit.
+    error: Option<String>,
+}
+
+/// A sink attached to a running program.  Dropping it detaches the sink;
+/// the tables are left in the database.
+pub struct SqliteSink<'a> {
+    hddlog: &'a HDDlog,
+    subscription: CommitSubscriptionId,
+    state: Arc<Mutex<State>>,
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn table_name(relation: &str) -> String {
+    relation
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn sql_value(record: &Record) -> Value {
+    match record {
+        Record::Bool(b) => Value::Integer(*b as i64),
+        Record::Int(i) => i
+            .to_i64()
+            .map_or_else(|| Value::Text(i.to_string()), Value::Integer),
+        Record::Float(f) => Value::Real(f.into_inner() as f64),
+        Record::Double(d) => Value::Real(d.into_inner()),
+        Record::String(s) => Value::Text(s.clone()),
+        record => Value::Text(record.to_string()),
+    }
+}
+
+/* Field columns of a record and their values. */
+fn fields(record: &Record) -> Vec<(String, Value)> {
+    match record {
+        Record::NamedStruct(_, fields) => fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), sql_value(value)))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl SqlTable {
+    fn create(tx: &Transaction, relation: &str) -> Result<Self, rusqlite::Error> {
+        let name = table_name(relation);
+        tx.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {} ({} TEXT PRIMARY KEY, {} INTEGER NOT NULL)",
+                quote_ident(&name),
+                KEY_COLUMN,
+                WEIGHT_COLUMN
+            ),
+            [],
+        )?;
+        // The table may be left over from a previous run.
+        let mut columns = HashSet::new();
+        let mut stmt = tx.prepare(&format!("PRAGMA table_info({})", quote_ident(&name)))?;
+        for column in stmt.query_map([], |row| row.get::<_, String>(1))? {
+            let column = column?;
+            if column != KEY_COLUMN && column != WEIGHT_COLUMN {
+                columns.insert(column);
+            }
+        }
+        Ok(Self { name, columns })
+    }
+
+    fn apply(
+        &mut self,
+        tx: &Transaction,
+        value: &DDValue,
+        weight: isize,
+    ) -> Result<(), rusqlite::Error> {
+        let record = value.clone().into_record();
+        let fields = fields(&record);
+        for (column, _) in fields.iter() {
+            if !self.columns.contains(column) {
+                tx.execute(
+                    &format!(
+                        "ALTER TABLE {} ADD COLUMN {}",
+                        quote_ident(&self.name),
+                        quote_ident(column)
+                    ),
+                    [],
+                )?;
+                self.columns.insert(column.clone());
+            }
+        }
+        let columns: Vec<String> = fields.iter().map(|(c, _)| quote_ident(c)).collect();
+        let sql = format!(
+            "INSERT INTO {table} ({key}, {weight}{sep}{columns}) VALUES ({params}) \
+             ON CONFLICT({key}) DO UPDATE SET {weight} = {weight} + excluded.{weight}",
+            table = quote_ident(&self.name),
+            key = KEY_COLUMN,
+            weight = WEIGHT_COLUMN,
+            sep = if columns.is_empty() { "" } else { ", " },
+            columns = columns.join(", "),
+            params = vec!["?"; columns.len() + 2].join(", ")
+        );
+        let key = record.to_string();
+        let params = vec![Value::Text(key.clone()), Value::Integer(weight as i64)]
+            .into_iter()
+            .chain(fields.into_iter().map(|(_, v)| v));
+        tx.prepare_cached(&sql)?.execute(params_from_iter(params))?;
+        tx.prepare_cached(&format!(
+            "DELETE FROM {} WHERE {} = ? AND {} <= 0",
+            quote_ident(&self.name),
+            KEY_COLUMN,
+            WEIGHT_COLUMN
+        ))?
+        .execute([key])?;
+        Ok(())
+    }
+}
+
+impl State {
+    fn apply<'b, I>(&mut self, batches: I) -> Result<(), rusqlite::Error>
+    where
+        I: IntoIterator<Item = (RelId, &'b [(DDValue, isize)])>,
+    {
+        let tx = self.conn.transaction()?;
+        for (relid, changes) in batches {
+            if let Some(table) = self.tables.get_mut(&relid) {
+                for (value, weight) in changes {
+                    table.apply(&tx, value, *weight)?;
+                }
+            }
+        }
+        tx.commit()
+    }
+
+    fn apply_commit(&mut self, batches: &[ChangelogBatch]) {
+        if self.error.is_some() {
+            // The tables are out of sync; stop updating them.
+            return;
+        }
+        let commit = batches.first().map_or(0, |batch| batch.commit);
+        if let Err(e) = self.apply(
+            batches
+                .iter()
+                .map(|batch| (batch.relid, batch.changes.as_slice())),
+        ) {
+            self.error = Some(format!("failed to apply commit {}: {}", commit, e));
+        }
+    }
+}
+
+impl<'a> SqliteSink<'a> {
+    /// Mirror output relations `relations` of `hddlog` into the database
+    /// open in `conn`, creating their tables if necessary.
+    pub fn attach(
+        hddlog: &'a HDDlog,
+        mut conn: Connection,
+        relations: &[&str],
+    ) -> Result<Self, String> {
+        let mut relids = Vec::new();
+        let mut tables = BTreeMap::new();
+        {
+            let tx = conn
+                .transaction()
+                .map_err(|e| format!("failed to start SQLite transaction: {}", e))?;
+            for relation in relations {
+                let relid = match Relations::try_from(*relation) {
+                    Ok(rel) if rel.is_output() => rel as RelId,
+                    _ => return Err(format!("unknown output relation {}", relation)),
+                };
+                let table = SqlTable::create(&tx, relation)
+                    .map_err(|e| format!("failed to create table for {}: {}", relation, e))?;
+                relids.push(relid);
+                tables.insert(relid, table);
+            }
+            tx.commit()
+                .map_err(|e| format!("failed to create tables: {}", e))?;
+        }
+        let mut state = State {
+            conn,
+            tables,
+            error: None,
+        };
+
+        if hddlog.db.is_some() {
+            let snapshot = hddlog.dump_stored(&relids)?;
+            for relid in relids.iter() {
+                state
+                    .conn
+                    .execute(
+                        &format!("DELETE FROM {}", quote_ident(&state.tables[relid].name)),
+                        [],
+                    )
+                    .map_err(|e| {
+                        format!(
+                            "failed to clear table for {}: {}",
+                            relid2name(*relid).unwrap_or("?"),
+                            e
+                        )
+                    })?;
+            }
+            state
+                .apply(
+                    snapshot
+                        .iter()
+                        .map(|(relid, facts)| (*relid, facts.as_slice())),
+                )
+                .map_err(|e| format!("failed to copy relation contents: {}", e))?;
+        }
+
+        let state = Arc::new(Mutex::new(state));
+        let subscription = {
+            let state = state.clone();
+            hddlog.subscribe_commits(
+                &relids,
+                Arc::new(move |batches| state.lock().unwrap().apply_commit(batches)),
+            )?
+        };
+        Ok(Self {
+            hddlog,
+            subscription,
+            state,
+        })
+    }
+
+    /// The error that stopped the sink, if any.  Once a commit fails to
+    /// apply, e.g., because the database is locked or the disk is full, the
+    /// tables no longer match the relations and the sink ignores further
+    /// commits; the relations can be mirrored again by attaching a new sink.
+    pub fn error(&self) -> Option<String> {
+        self.state.lock().unwrap().error.clone()
+    }
+
+    /// Run `f` on the connection of the sink, e.g., to query the tables.
+    /// Commits wait until `f` returns.
+    pub fn with_connection<T, F: FnOnce(&Connection) -> T>(&self, f: F) -> T {
+        f(&self.state.lock().unwrap().conn)
+    }
+}
+
+impl Drop for SqliteSink<'_> {
+    fn drop(&mut self) {
+        self.hddlog.unsubscribe_commits(self.subscription);
+    }
+}
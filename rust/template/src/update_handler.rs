@@ -16,14 +16,24 @@
 use super::*;
 use crossbeam_channel::{Receiver, Sender};
 use differential_datalog::{
-    program::{RelId, RelationCallback},
+    program::{
+        tenant::{self, TenantId},
+        RelId, RelationCallback, Weight,
+    },
     Callback, DeltaMap,
 };
+use fnv::FnvHashSet;
 use std::{
     cell::Cell,
+    collections::{hash_map, BTreeMap, VecDeque},
     fmt::{self, Debug, Formatter},
-    sync::{Arc, Barrier, Mutex, MutexGuard},
+    mem, panic,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Barrier, Condvar, Mutex, MutexGuard, RwLock,
+    },
     thread,
+    time::{Duration, Instant, SystemTime},
 };
 
 /// Single-threaded (non-thread-safe callback)
@@ -40,6 +50,16 @@ where
     }
 }
 
+/// Converts the weight of an output change into the `isize` passed to
+/// update handler callbacks.
+///
+/// Workers never deliver changes whose weight does not fit into an `isize`
+/// (they poison the transaction instead, see
+/// `DDlogError::OutputWeightOverflow`), so failing the conversion is a bug.
+fn output_weight(w: Weight) -> isize {
+    isize::try_from(w).unwrap_or_else(|_| panic!("output weight {} does not fit into an isize", w))
+}
+
 pub trait UpdateHandler: Debug {
     /// Returns a handler to be invoked on each output relation update.
     fn update_cb(&self) -> Arc<dyn ST_RelationCallback>;
@@ -132,7 +152,7 @@ impl<F: Callback> UpdateHandler for CallbackUpdateHandler<F> {
 impl<F: Callback> MTUpdateHandler for CallbackUpdateHandler<F> {
     fn mt_update_cb(&self) -> Arc<dyn RelationCallback> {
         let cb = self.cb.clone();
-        Arc::new(move |relid, v, w| cb(relid, &v.clone().into_record(), w as isize))
+        Arc::new(move |relid, v, w| cb(relid, &v.clone().into_record(), output_weight(w)))
     }
 }
 
@@ -187,7 +207,7 @@ impl MTUpdateHandler for ExternCUpdateHandler {
                 cb_arg,
                 relid,
                 &v.clone().into_record() as *const record::Record,
-                w as isize,
+                output_weight(w),
             )
         })
     }
@@ -218,7 +238,7 @@ impl UpdateHandler for MTValMapUpdateHandler {
 impl MTUpdateHandler for MTValMapUpdateHandler {
     fn mt_update_cb(&self) -> Arc<dyn RelationCallback> {
         let db = self.db.clone();
-        Arc::new(move |relid, v, w| db.lock().unwrap().update(relid, v, w as isize))
+        Arc::new(move |relid, v, w| db.lock().unwrap().update(relid, v, output_weight(w)))
     }
 }
 
@@ -351,6 +371,581 @@ impl UpdateHandler for DeltaUpdateHandler {
     }
 }
 
+/// Callback invoked for updates to the relations of one tenant.
+pub type TenantCallback = Arc<dyn Fn(RelId, &record::Record, isize) + Send + Sync>;
+
+/// Callbacks subscribed to by tenants of a multi-tenant program.
+pub type TenantCallbacks = Arc<RwLock<FnvHashMap<TenantId, TenantCallback>>>;
+
+/// `UpdateHandler` implementation for multi-tenant programs that strips the
+/// tenant tag from updates and passes them to the callback subscribed to by
+/// their tenant, if any.
+#[derive(Clone)]
+pub struct TenantUpdateHandler {
+    callbacks: TenantCallbacks,
+}
+
+impl TenantUpdateHandler {
+    pub fn new(callbacks: TenantCallbacks) -> Self {
+        Self { callbacks }
+    }
+}
+
+impl Debug for TenantUpdateHandler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TenantUpdateHandler")
+            .field("tenants", &self.callbacks.read().unwrap().len())
+            .finish()
+    }
+}
+
+impl UpdateHandler for TenantUpdateHandler {
+    fn update_cb(&self) -> Arc<dyn ST_RelationCallback> {
+        let callbacks = self.callbacks.clone();
+        Arc::new(move |relid, v, w| {
+            if let (Some(tenant), v) = tenant::untag_ref(v) {
+                // Release the lock before invoking the callback, which may
+                // (un)subscribe tenants.
+                let cb = callbacks.read().unwrap().get(&tenant).cloned();
+                if let Some(cb) = cb {
+                    cb(relid, &v.clone().into_record(), w);
+                }
+            }
+        })
+    }
+    fn before_commit(&self) {}
+    fn after_commit(&self, _success: bool) {}
+}
+
+/// Changes to one output relation made by one commit.
+#[derive(Debug, Clone)]
+pub struct ChangelogBatch {
+    /// Sequence number of the commit.  Commit numbers start at 1 and
+    /// increase by one with every commit that changes a subscribed relation;
+    /// batches of different relations changed by the same commit have the
+    /// same number.
+    pub commit: u64,
+    /// Wall-clock time at which the commit completed.
+    pub timestamp: SystemTime,
+    pub relid: RelId,
+    /// Changed values and their weights, in the order they were produced,
+    /// or in the canonical order if the program was started with
+    /// `Config::canonical_order` (see `sort_canonically()`).
+    pub changes: Vec<(DDValue, isize)>,
+}
+
+/// Callback invoked with the changelog of an output relation.
+pub type ChangelogCallback = Arc<dyn Fn(&ChangelogBatch) + Send + Sync>;
+
+/// Changelog callbacks of output relations.
+pub type ChangelogCallbacks = Arc<RwLock<FnvHashMap<RelId, ChangelogCallback>>>;
+
+/// Callback invoked once per commit with the batches of all subscribed
+/// relations changed by the commit, e.g., to apply them to an external
+/// store in one transaction.
+pub type CommitCallback = Arc<dyn Fn(&[ChangelogBatch]) + Send + Sync>;
+
+/// Subscribers to the changes made by each commit to a set of output
+/// relations, by subscription id.
+pub type CommitCallbacks = Arc<RwLock<BTreeMap<u64, (FnvHashSet<RelId>, CommitCallback)>>>;
+
+#[derive(Debug, Default)]
+struct ChangelogState {
+    in_commit: bool,
+    /// Number of the last delivered commit.
+    commit: u64,
+    pending: BTreeMap<RelId, Vec<(DDValue, isize)>>,
+}
+
+/// Sort changes by value in the canonical order (see
+/// `Record::canonical_cmp()`), and then by weight, so that their order does
+/// not depend on the number of workers or on how values were interned.
+pub fn sort_canonically<W: Ord>(changes: &mut Vec<(DDValue, W)>) {
+    let mut keyed: Vec<(record::Record, DDValue, W)> = changes
+        .drain(..)
+        .map(|(v, w)| (v.clone().into_record(), v, w))
+        .collect();
+    keyed.sort_by(|(r1, _, w1), (r2, _, w2)| r1.canonical_cmp(r2).then(w1.cmp(w2)));
+    changes.extend(keyed.into_iter().map(|(_, v, w)| (v, w)));
+}
+
+/// `UpdateHandler` implementation that collects the changes to subscribed
+/// relations during a commit and passes them, annotated with a commit number
+/// and timestamp, to the relation's changelog callback and to commit
+/// callbacks once the commit is done.  Changes to other relations are
+/// ignored.
+#[derive(Clone)]
+pub struct ChangelogUpdateHandler {
+    callbacks: ChangelogCallbacks,
+    commit_callbacks: CommitCallbacks,
+    state: Arc<Mutex<ChangelogState>>,
+    /// Sort each batch with `sort_canonically()` before delivering it.
+    canonical_order: bool,
+}
+
+impl ChangelogUpdateHandler {
+    pub fn new(
+        callbacks: ChangelogCallbacks,
+        commit_callbacks: CommitCallbacks,
+        canonical_order: bool,
+    ) -> Self {
+        Self {
+            callbacks,
+            commit_callbacks,
+            state: Arc::new(Mutex::new(ChangelogState::default())),
+            canonical_order,
+        }
+    }
+}
+
+impl Debug for ChangelogUpdateHandler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChangelogUpdateHandler")
+            .field("relations", &self.callbacks.read().unwrap().len())
+            .field(
+                "commit_subscribers",
+                &self.commit_callbacks.read().unwrap().len(),
+            )
+            .field("commit", &self.state.lock().unwrap().commit)
+            .finish()
+    }
+}
+
+impl UpdateHandler for ChangelogUpdateHandler {
+    fn update_cb(&self) -> Arc<dyn ST_RelationCallback> {
+        let handler = self.clone();
+        Arc::new(move |relid, v, w| {
+            if !handler.callbacks.read().unwrap().contains_key(&relid)
+                && !handler
+                    .commit_callbacks
+                    .read()
+                    .unwrap()
+                    .values()
+                    .any(|(relids, _)| relids.contains(&relid))
+            {
+                return;
+            }
+            let mut state = handler.state.lock().unwrap();
+            // Ignore updates produced while the program is being stopped.
+            if state.in_commit {
+                state.pending.entry(relid).or_default().push((v.clone(), w));
+            }
+        })
+    }
+
+    fn before_commit(&self) {
+        self.state.lock().unwrap().in_commit = true;
+    }
+
+    /// Changes are delivered even if the commit failed: they have been
+    /// applied to output relations, and rolling the transaction back
+    /// retracts them.
+    fn after_commit(&self, _success: bool) {
+        let (commit, pending) = {
+            let mut state = self.state.lock().unwrap();
+            state.in_commit = false;
+            if state.pending.is_empty() {
+                return;
+            }
+            state.commit += 1;
+            (state.commit, mem::take(&mut state.pending))
+        };
+
+        let timestamp = SystemTime::now();
+        let batches: Vec<ChangelogBatch> = pending
+            .into_iter()
+            .map(|(relid, mut changes)| {
+                if self.canonical_order {
+                    sort_canonically(&mut changes);
+                }
+                ChangelogBatch {
+                    commit,
+                    timestamp,
+                    relid,
+                    changes,
+                }
+            })
+            .collect();
+        for batch in batches.iter() {
+            // Release the lock before invoking the callback, which may
+            // (un)subscribe relations.
+            let cb = self.callbacks.read().unwrap().get(&batch.relid).cloned();
+            if let Some(cb) = cb {
+                cb(batch);
+            }
+        }
+
+        let subscribers: Vec<(FnvHashSet<RelId>, CommitCallback)> = self
+            .commit_callbacks
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+        for (relids, cb) in subscribers {
+            let subscribed: Vec<ChangelogBatch> = batches
+                .iter()
+                .filter(|batch| relids.contains(&batch.relid))
+                .cloned()
+                .collect();
+            if !subscribed.is_empty() {
+                cb(&subscribed);
+            }
+        }
+    }
+}
+
+/// What a changelog delivery queue does with a batch that arrives when the
+/// queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Block the commit until the subscriber catches up, or until the
+    /// delivery thread stops (see `ChangelogQueueStats::error()`).
+    Block,
+    /// Discard the batch, counting it in `ChangelogQueueStats::dropped()`.
+    Drop,
+    /// Merge the batch into the last queued batch, which then covers several
+    /// commits and carries the number and timestamp of the last one.
+    /// Changes to the same value are consolidated.
+    Coalesce,
+}
+
+/// Configuration of a bounded changelog delivery queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeliveryConfig {
+    /// The maximal number of batches waiting to be delivered.
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+/// Batches waiting in a delivery queue: those of a changelog subscription,
+/// or those of a commit subscription, which are delivered one commit at a
+/// time.
+#[derive(Debug)]
+enum Delivery {
+    Batch(ChangelogBatch),
+    Commit(Vec<ChangelogBatch>),
+}
+
+impl Delivery {
+    /// Merge `next` into `self`.  Returns `false` if they cannot be merged.
+    fn coalesce(&mut self, next: &Delivery) -> bool {
+        match (self, next) {
+            (Delivery::Batch(last), Delivery::Batch(batch)) => coalesce_batch(last, batch),
+            (Delivery::Commit(last), Delivery::Commit(batches)) => {
+                for batch in batches.iter() {
+                    match last.iter_mut().find(|b| b.relid == batch.relid) {
+                        Some(b) => coalesce_batch(b, batch),
+                        None => last.push(batch.clone()),
+                    }
+                }
+                // Batches of relations that the later commits did not change
+                // now cover these commits as well.
+                if let Some(batch) = batches.first() {
+                    for b in last.iter_mut() {
+                        b.commit = batch.commit;
+                        b.timestamp = batch.timestamp;
+                    }
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[derive(Debug, Default)]
+struct DeliveryState {
+    queue: VecDeque<Delivery>,
+    closed: bool,
+    /// Set when the delivery thread has stopped because the subscriber
+    /// panicked.
+    error: Option<String>,
+    /// Number of batches queued and number of batches delivered so far.
+    queued: u64,
+    delivered: u64,
+}
+
+#[derive(Debug, Default)]
+struct DeliveryQueue {
+    state: Mutex<DeliveryState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    /// Signaled whenever a batch has been delivered.
+    progress: Condvar,
+    dropped: AtomicU64,
+    coalesced: AtomicU64,
+    failed: AtomicU64,
+}
+
+/// Statistics of a changelog delivery queue.
+#[derive(Clone, Debug)]
+pub struct ChangelogQueueStats {
+    queue: Arc<DeliveryQueue>,
+}
+
+impl ChangelogQueueStats {
+    /// The number of batches waiting to be delivered.
+    pub fn pending(&self) -> usize {
+        self.queue.state.lock().unwrap().queue.len()
+    }
+
+    /// The number of batches discarded under `OverflowPolicy::Drop`.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The number of batches merged into another batch under
+    /// `OverflowPolicy::Coalesce`.
+    pub fn coalesced(&self) -> u64 {
+        self.queue.coalesced.load(Ordering::Relaxed)
+    }
+
+    /// The number of batches that could not be queued because the delivery
+    /// thread has stopped (see `error()`).
+    pub fn failed(&self) -> u64 {
+        self.queue.failed.load(Ordering::Relaxed)
+    }
+
+    /// Returns an error if the delivery thread has stopped because the
+    /// subscriber panicked.  Batches that were still queued at that point
+    /// are never delivered, and subsequent batches are discarded, counting
+    /// them in `failed()`.
+    pub fn error(&self) -> Option<String> {
+        self.queue.state.lock().unwrap().error.clone()
+    }
+
+    /// The number of batches queued so far, including delivered ones.  A
+    /// batch coalesced with a queued batch does not count separately.
+    pub fn queued(&self) -> u64 {
+        self.queue.state.lock().unwrap().queued
+    }
+
+    /// The number of batches delivered so far.
+    pub fn delivered(&self) -> u64 {
+        self.queue.state.lock().unwrap().delivered
+    }
+
+    /// Wait until the first `queued` batches have been delivered, giving up
+    /// after `timeout`, if specified.  Fails on timeout or if the delivery
+    /// thread stops before delivering them (see `error()`).
+    pub fn wait_delivered(&self, queued: u64, timeout: Option<Duration>) -> Result<(), String> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.queue.state.lock().unwrap();
+        while state.delivered < queued {
+            if let Some(error) = &state.error {
+                return Err(error.clone());
+            }
+            state = match deadline {
+                None => self.queue.progress.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(format!(
+                            "changes were not delivered within {:?}",
+                            timeout.unwrap()
+                        ));
+                    }
+                    self.queue
+                        .progress
+                        .wait_timeout(state, deadline - now)
+                        .unwrap()
+                        .0
+                }
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Producer side of a changelog delivery queue.  Dropping it, which happens
+/// when the subscription is replaced or removed, stops the delivery thread
+/// once it has delivered the remaining batches.
+struct DeliverySender {
+    queue: Arc<DeliveryQueue>,
+    config: DeliveryConfig,
+}
+
+impl DeliverySender {
+    /// Queue `delivery`, applying the overflow policy if the queue is full.
+    /// Fails if the delivery thread has stopped.
+    fn send(&self, delivery: Delivery) -> Result<(), String> {
+        let mut state = self.queue.state.lock().unwrap();
+        if state.queue.len() >= self.config.capacity {
+            match self.config.overflow {
+                OverflowPolicy::Block => {
+                    while state.error.is_none() && state.queue.len() >= self.config.capacity {
+                        state = self.queue.not_full.wait(state).unwrap();
+                    }
+                }
+                OverflowPolicy::Drop => {
+                    self.queue.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                OverflowPolicy::Coalesce => {
+                    if let Some(last) = state.queue.back_mut() {
+                        if last.coalesce(&delivery) {
+                            self.queue.coalesced.fetch_add(1, Ordering::Relaxed);
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(error) = &state.error {
+            self.queue.failed.fetch_add(1, Ordering::Relaxed);
+            return Err(error.clone());
+        }
+        state.queue.push_back(delivery);
+        state.queued += 1;
+        self.queue.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Queue `delivery`, reporting failures on stderr the first time the
+    /// delivery thread is found to have stopped; later failures are only
+    /// counted (see `ChangelogQueueStats::failed()`).
+    fn deliver(&self, delivery: Delivery) {
+        if let Err(e) = self.send(delivery) {
+            if self.queue.failed.load(Ordering::Relaxed) == 1 {
+                eprintln!("{}", e);
+            }
+        }
+    }
+}
+
+impl Drop for DeliverySender {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().closed = true;
+        self.queue.not_empty.notify_one();
+    }
+}
+
+/// Merge `batch` into `last`, summing the weights of repeated values and
+/// dropping values whose weights cancel out.
+fn coalesce_batch(last: &mut ChangelogBatch, batch: &ChangelogBatch) {
+    let mut order: Vec<DDValue> = Vec::with_capacity(last.changes.len() + batch.changes.len());
+    let mut weights: FnvHashMap<DDValue, isize> = FnvHashMap::default();
+    for (v, w) in last.changes.drain(..).chain(batch.changes.iter().cloned()) {
+        match weights.entry(v) {
+            hash_map::Entry::Occupied(mut entry) => *entry.get_mut() += w,
+            hash_map::Entry::Vacant(entry) => {
+                order.push(entry.key().clone());
+                entry.insert(w);
+            }
+        }
+    }
+    last.changes = order
+        .into_iter()
+        .filter_map(|v| match weights[&v] {
+            0 => None,
+            w => Some((v, w)),
+        })
+        .collect();
+    last.commit = batch.commit;
+    last.timestamp = batch.timestamp;
+}
+
+/// Start a thread named `name` that passes the contents of a new delivery
+/// queue to `deliver`.  If `deliver` panics, the thread records the error
+/// in the queue and stops, waking up blocked senders and waiters.
+fn start_delivery<F>(
+    name: String,
+    config: DeliveryConfig,
+    deliver: F,
+) -> Result<(DeliverySender, ChangelogQueueStats), String>
+where
+    F: Fn(&Delivery) + Send + 'static,
+{
+    if config.capacity == 0 {
+        return Err("changelog delivery queue capacity must be positive".to_string());
+    }
+    let queue = Arc::new(DeliveryQueue::default());
+    let receiver = queue.clone();
+    let thread_name = name.clone();
+    thread::Builder::new()
+        .name(name)
+        .spawn(move || loop {
+            let delivery = {
+                let mut state = receiver.state.lock().unwrap();
+                loop {
+                    if let Some(delivery) = state.queue.pop_front() {
+                        receiver.not_full.notify_one();
+                        break delivery;
+                    }
+                    if state.closed {
+                        return;
+                    }
+                    state = receiver.not_empty.wait(state).unwrap();
+                }
+            };
+            let result = panic::catch_unwind(panic::AssertUnwindSafe(|| deliver(&delivery)));
+            let mut state = receiver.state.lock().unwrap();
+            match result {
+                Ok(()) => state.delivered += 1,
+                Err(_) => {
+                    state.error = Some(format!("subscriber in thread {} panicked", thread_name));
+                    receiver.not_full.notify_all();
+                }
+            }
+            receiver.progress.notify_all();
+            if state.error.is_some() {
+                return;
+            }
+        })
+        .map_err(|e| format!("failed to start changelog delivery thread: {}", e))?;
+
+    Ok((
+        DeliverySender {
+            queue: queue.clone(),
+            config,
+        },
+        ChangelogQueueStats { queue },
+    ))
+}
+
+/// Wrap `cb` so that batches are delivered to it from a separate thread
+/// through a bounded queue instead of before the commit returns.
+pub fn queued_changelog_callback(
+    relid: RelId,
+    cb: ChangelogCallback,
+    config: DeliveryConfig,
+) -> Result<(ChangelogCallback, ChangelogQueueStats), String> {
+    let (sender, stats) = start_delivery(
+        format!("ddlog-changelog-{}", relid),
+        config,
+        move |delivery| {
+            if let Delivery::Batch(batch) = delivery {
+                cb(batch)
+            }
+        },
+    )?;
+    Ok((
+        Arc::new(move |batch: &ChangelogBatch| sender.deliver(Delivery::Batch(batch.clone()))),
+        stats,
+    ))
+}
+
+/// Like `queued_changelog_callback()`, but for a commit callback.  Each
+/// queue entry holds the batches of one commit; `OverflowPolicy::Coalesce`
+/// merges the batches of consecutive commits relation by relation.
+pub fn queued_commit_callback(
+    cb: CommitCallback,
+    config: DeliveryConfig,
+) -> Result<(CommitCallback, ChangelogQueueStats), String> {
+    let (sender, stats) = start_delivery("ddlog-commits".to_string(), config, move |delivery| {
+        if let Delivery::Commit(batches) = delivery {
+            cb(batches)
+        }
+    })?;
+    Ok((
+        Arc::new(move |batches: &[ChangelogBatch]| {
+            sender.deliver(Delivery::Commit(batches.to_vec()))
+        }),
+        stats,
+    ))
+}
+
 /// `UpdateHandler` implementation that chains multiple single-threaded
 /// handlers.
 #[derive(Debug)]
@@ -546,7 +1141,7 @@ impl MTUpdateHandler for ThreadUpdateHandler {
                 .send(Msg::Update {
                     relid,
                     v: v.clone(),
-                    w: w as isize,
+                    w: output_weight(w),
                 })
                 .unwrap();
         })
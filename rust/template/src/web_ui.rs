@@ -0,0 +1,301 @@
+//! Web-based introspection UI.
+//!
+//! `WebUi` serves a small single-page application over HTTP that shows the
+//! dataflow graph of the program (see `HDDlog::explain_plan()`), lets users
+//! browse the contents of output relations page by page, and tails the
+//! changes to an output relation over a websocket.  The page is backed by
+//! the following JSON endpoints, which can also be used directly:
+//!
+//! * `GET /api/metadata`: program metadata and relation statistics.
+//! * `GET /api/plan`: the dataflow graph in `PlanFormat::Json`.
+//! * `GET /api/relations/<name>?offset=<n>&limit=<n>`: a page of the
+//!   contents of an output relation, along with its total size.
+//! * `GET /api/deltas/<name>` (websocket): one message per commit that
+//!   changes the relation.
+//!
+//! Deltas are received through the changelog of the relation (see
+//! `HDDlog::subscribe_changelog()`), which replaces any changelog callback
+//! registered by the application for that relation.  Messages are dropped
+//! for clients that cannot keep up, so that the UI never stalls commits.
+//! The UI has no authentication and should only be bound to trusted
+//! interfaces.  Requires the `web_ui` feature.
+
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use fnv::FnvHashMap;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use differential_datalog::ddval::DDValue;
+use differential_datalog::program::plan::PlanFormat;
+use differential_datalog::program::RelId;
+use differential_datalog::record::IntoRecord;
+
+use crate::api::HDDlog;
+use crate::update_handler::ChangelogBatch;
+use crate::{relid2name, Relations};
+
+/// Page size used when the request does not specify a limit.
+const DEFAULT_PAGE_SIZE: usize = 100;
+/// Number of delta messages buffered for each websocket client.
+const CLIENT_QUEUE: usize = 1024;
+
+const INDEX_HTML: &str = include_str!("web_ui.html");
+
+/// Websocket clients tailing each relation.
+type Subscribers = Arc<Mutex<FnvHashMap<RelId, Vec<Sender<String>>>>>;
+
+/// A running web UI.  Dropping it stops the server.
+pub struct WebUi {
+    server: Arc<Server>,
+    addr: String,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WebUi {
+    /// Serve the UI for `hddlog` on `addr`, e.g., `127.0.0.1:8080`.
+    pub fn start(hddlog: Arc<HDDlog>, addr: &str) -> Result<Self, String> {
+        let server =
+            Arc::new(Server::http(addr).map_err(|e| format!("failed to bind {}: {}", addr, e))?);
+        let addr = server.server_addr().to_string();
+        let thread = {
+            let server = server.clone();
+            let subscribers = Subscribers::default();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(&hddlog, &subscribers, request);
+                }
+            })
+        };
+        Ok(Self {
+            server,
+            addr,
+            thread: Some(thread),
+        })
+    }
+
+    /// The address the UI is served on.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+}
+
+impl Drop for WebUi {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn respond_json(request: Request, status: u16, body: &Value) {
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"));
+    let _ = request.respond(response);
+}
+
+fn respond_error(request: Request, status: u16, msg: String) {
+    respond_json(request, status, &json!({ "error": msg }));
+}
+
+fn handle(hddlog: &Arc<HDDlog>, subscribers: &Subscribers, request: Request) {
+    if *request.method() != Method::Get {
+        return respond_error(request, 405, "only GET requests are supported".to_string());
+    }
+    let url = request.url().to_string();
+    let (path, query) = match url.find('?') {
+        Some(i) => (&url[..i], &url[i + 1..]),
+        None => (url.as_str(), ""),
+    };
+    if path == "/" || path == "/index.html" {
+        let response = Response::from_string(INDEX_HTML)
+            .with_header(header("Content-Type", "text/html; charset=utf-8"));
+        let _ = request.respond(response);
+    } else if path == "/api/metadata" {
+        respond_json(request, 200, &metadata(hddlog));
+    } else if path == "/api/plan" {
+        let plan = hddlog.explain_plan(PlanFormat::Json);
+        let response =
+            Response::from_string(plan).with_header(header("Content-Type", "application/json"));
+        let _ = request.respond(response);
+    } else if let Some(name) = strip_prefix(path, "/api/relations/") {
+        match output_relation(name).and_then(|relid| page(hddlog, relid, query)) {
+            Ok(page) => respond_json(request, 200, &page),
+            Err(e) => respond_error(request, 400, e),
+        }
+    } else if let Some(name) = strip_prefix(path, "/api/deltas/") {
+        match output_relation(name) {
+            Ok(relid) => tail(hddlog, subscribers, relid, request),
+            Err(e) => respond_error(request, 400, e),
+        }
+    } else {
+        respond_error(request, 404, format!("no such page: {}", path));
+    }
+}
+
+fn strip_prefix<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.starts_with(prefix) {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+fn output_relation(name: &str) -> Result<RelId, String> {
+    match Relations::try_from(name) {
+        Ok(rel) if rel.is_output() => Ok(rel as RelId),
+        _ => Err(format!("unknown output relation {}", name)),
+    }
+}
+
+fn metadata(hddlog: &HDDlog) -> Value {
+    let metadata = hddlog.metadata();
+    let stats = hddlog.all_relation_stats();
+    let relations: Vec<Value> = metadata
+        .relations
+        .iter()
+        .map(|rel| {
+            let relation = Relations::try_from(rel.name).ok();
+            let size = relation.and_then(|r| stats.get(&(r as RelId)).map(|s| s.size));
+            json!({
+                "name": rel.name,
+                "input": rel.input,
+                "output": relation.map_or(false, |r| r.is_output()),
+                "fingerprint": format!("{:#018x}", rel.fingerprint),
+                "size": size,
+            })
+        })
+        .collect();
+    json!({
+        "program": metadata.program_name,
+        "compiler_version": metadata.compiler_version,
+        "relations": relations,
+    })
+}
+
+fn query_param(query: &str, name: &str, default: usize) -> Result<usize, String> {
+    for param in query.split('&') {
+        let mut kv = param.splitn(2, '=');
+        if kv.next() == Some(name) {
+            let value = kv.next().unwrap_or("");
+            return value
+                .parse()
+                .map_err(|_| format!("invalid value of {}: {}", name, value));
+        }
+    }
+    Ok(default)
+}
+
+fn change(value: &DDValue, weight: isize) -> Value {
+    json!({ "record": value.clone().into_record().to_string(), "weight": weight })
+}
+
+fn page(hddlog: &HDDlog, relid: RelId, query: &str) -> Result<Value, String> {
+    let offset = query_param(query, "offset", 0)?;
+    let limit = query_param(query, "limit", DEFAULT_PAGE_SIZE)?;
+    let snapshot = hddlog.dump_stored(&[relid])?;
+    let facts = snapshot.get(&relid).map(Vec::as_slice).unwrap_or(&[]);
+    let records: Vec<Value> = facts
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(|(v, w)| change(v, *w))
+        .collect();
+    Ok(json!({
+        "relation": relid2name(relid).unwrap_or("?"),
+        "total": facts.len(),
+        "offset": offset,
+        "records": records,
+    }))
+}
+
+fn batch_message(batch: &ChangelogBatch) -> String {
+    let changes: Vec<Value> = batch.changes.iter().map(|(v, w)| change(v, *w)).collect();
+    json!({
+        "commit": batch.commit,
+        "relation": relid2name(batch.relid).unwrap_or("?"),
+        "changes": changes,
+    })
+    .to_string()
+}
+
+/* Registers a client tailing `relid`; subscribes to the changelog of the
+ * relation when the first client arrives.  The changelog callback locks
+ * `subscribers`, so the subscription must happen without holding it. */
+fn add_subscriber(
+    hddlog: &HDDlog,
+    subscribers: &Subscribers,
+    relid: RelId,
+) -> Result<Receiver<String>, String> {
+    let (tx, rx) = crossbeam_channel::bounded(CLIENT_QUEUE);
+    let first = {
+        let mut subscribers = subscribers.lock().unwrap();
+        let clients = subscribers.entry(relid).or_insert_with(Vec::new);
+        clients.push(tx);
+        clients.len() == 1
+    };
+    if first {
+        let subscribers = subscribers.clone();
+        hddlog.subscribe_changelog(
+            relid,
+            Arc::new(move |batch: &ChangelogBatch| {
+                let msg = batch_message(batch);
+                if let Some(clients) = subscribers.lock().unwrap().get_mut(&batch.relid) {
+                    clients.retain(|tx| match tx.try_send(msg.clone()) {
+                        Err(TrySendError::Disconnected(_)) => false,
+                        // A full queue drops the message for this client.
+                        _ => true,
+                    });
+                }
+            }),
+        )?;
+    }
+    Ok(rx)
+}
+
+fn tail(hddlog: &HDDlog, subscribers: &Subscribers, relid: RelId, request: Request) {
+    let key = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Sec-WebSocket-Key"))
+        .map(|h| h.value.as_str().to_string());
+    let key = match key {
+        Some(key) => key,
+        None => {
+            return respond_error(request, 400, "expected a websocket request".to_string());
+        }
+    };
+    let rx = match add_subscriber(hddlog, subscribers, relid) {
+        Ok(rx) => rx,
+        Err(e) => return respond_error(request, 400, e),
+    };
+    let response = Response::empty(StatusCode(101))
+        .with_header(header("Connection", "Upgrade"))
+        .with_header(header(
+            "Sec-WebSocket-Accept",
+            &derive_accept_key(key.as_bytes()),
+        ));
+    let stream = request.upgrade("websocket", response);
+    thread::spawn(move || {
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        // Dropping `rx` when the client disconnects removes it from the
+        // subscribers on the next commit.
+        for msg in rx.iter() {
+            if socket.write_message(Message::Text(msg)).is_err() {
+                break;
+            }
+        }
+    });
+}
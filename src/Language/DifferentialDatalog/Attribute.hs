@@ -29,7 +29,11 @@ module Language.DifferentialDatalog.Attribute where
 
 import Data.Maybe
 import Data.List
+import qualified Data.Map as M
 import Control.Monad.Except
+import qualified Data.Aeson              as JSON
+import qualified Data.Text.Lazy          as TL
+import qualified Data.Text.Lazy.Encoding as TL
 
 import Language.DifferentialDatalog.Name
 import Language.DifferentialDatalog.NS
@@ -45,6 +49,7 @@ progValidateAttributes :: (MonadError String me) => DatalogProgram -> me ()
 progValidateAttributes d = do
     mapM_ (typedefValidateAttrs d) $ progTypedefs d
     mapM_ (indexValidateAttrs d) $ progIndexes d
+    mapM_ (relValidateAttrs d) $ progRelations d
     mapM_ (mapM_ (funcValidateAttrs d)) $ progFunctions d
 
 typedefValidateAttrs :: (MonadError String me) => DatalogProgram -> TypeDef -> me ()
@@ -102,6 +107,9 @@ typedefValidateAttr d tdef@TypeDef{..} attr = do
          "iterate_by_val" -> do
             check d (tdefIsExtern tdef) (pos attr)
                 $ "Only extern types can have a 'iterate_by_val' attribute."
+         "small" -> do
+            -- Validated and applied by 'progApplySmallAttrs'.
+            return ()
          n -> err d (pos attr) $ "Unknown attribute " ++ n
 
 typeValidateAttrs :: (MonadError String me) => DatalogProgram -> Type -> me ()
@@ -123,6 +131,21 @@ consValidateAttr d struct Constructor{} attr = do
          "rust" -> check d (length typeCons > 1) (pos attr) $ "Per-constructor 'rust' attributes are only supported for types with multiple constructors"
          n -> err d (pos attr) $ "Unknown attribute " ++ n
 
+relValidateAttrs :: (MonadError String me) => DatalogProgram -> Relation -> me ()
+relValidateAttrs d rel@Relation{..} = do
+    uniqNames (Just d) ("Multiple definitions of attribute " ++) relAttrs
+    mapM_ (relValidateAttr d) relAttrs
+    _ <- relCheckColumnarAttr d rel
+    _ <- relCheckJsonSchemaAttr d rel
+    return ()
+
+relValidateAttr :: (MonadError String me) => DatalogProgram -> Attribute -> me ()
+relValidateAttr d attr = do
+    case name attr of
+         "columnar" -> return ()
+         "json_schema" -> return ()
+         n -> err d (pos attr) $ "Unknown attribute " ++ n
+
 indexValidateAttrs :: (MonadError String me) => DatalogProgram -> Index -> me ()
 indexValidateAttrs d Index{..} = mapM_ (fieldValidateAttrs d) idxVars
 
@@ -146,6 +169,7 @@ funcValidateAttrs d f@Function{..} = do
     mapM_ (funcValidateAttr d) funcAttrs
     _ <- checkSideEffectAttr d f
     _ <- checkReturnByRefAttr d f
+    _ <- checkViewArgsAttr d f
     return ()
 
 funcValidateAttr :: (MonadError String me) => DatalogProgram -> Attribute -> me ()
@@ -153,6 +177,7 @@ funcValidateAttr d attr = do
     case name attr of
          "has_side_effects" -> return ()
          "return_by_ref" -> return ()
+         "view_args" -> return ()
          n -> err d (pos attr) $ "Unknown attribute " ++ n
 
 {- 'size' attribute: Gives DDlog a hint about the size of an extern data type in bytes. -}
@@ -249,6 +274,99 @@ tdefGetAliasAttr d tdef =
          Left e  -> error e
          Right b -> b
 
+{- 'small' attribute: when applied to an alias of 'Vec' or 'Set', e.g.,
+ -
+ - #[small]
+ - typedef Tags = Vec<string>
+ -
+ - replaces the aliased type with 'SmallVec' or 'SmallSet' respectively, which
+ - have the same API, but store a few elements inline.  This is applied before
+ - the program is validated, so that the rest of the compiler only sees the
+ - rewritten type. -}
+progApplySmallAttrs :: (MonadError String me) => DatalogProgram -> me DatalogProgram
+progApplySmallAttrs d = do
+    tdefs' <- mapM (tdefApplySmallAttr d) $ progTypedefs d
+    return d{progTypedefs = tdefs'}
+
+tdefApplySmallAttr :: (MonadError String me) => DatalogProgram -> TypeDef -> me TypeDef
+tdefApplySmallAttr d tdef@TypeDef{..} =
+    case find ((== "small") . name) tdefAttrs of
+         Nothing   -> return tdef
+         Just attr -> do
+             check d (attrVal attr == eTrue) (pos attr)
+                   "The value of 'small' attribute must be 'true' or empty"
+             case tdefType of
+                  Just t@TUser{typeName = "ddlog_std::Vec"} ->
+                      return tdef{tdefType = Just t{typeName = "ddlog_std::SmallVec"}}
+                  Just t@TUser{typeName = "ddlog_std::Set"} ->
+                      return tdef{tdefType = Just t{typeName = "ddlog_std::SmallSet"}}
+                  -- Already rewritten (the program is validated more than once).
+                  Just TUser{typeName = n} | elem n ["ddlog_std::SmallVec", "ddlog_std::SmallSet"] ->
+                      return tdef
+                  _ -> err d (pos attr) "The 'small' attribute only applies to aliases of 'Vec' and 'Set' types."
+
+{- 'columnar' attribute: when applied to an input relation whose records are
+ - tuples or structs of primitive fields, e.g.,
+ -
+ - #[columnar]
+ - input relation Flow(src: bit<32>, dst: bit<32>, bytes: bit<64>)
+ -
+ - tells the runtime to store the relation column by column (see
+ - 'differential_datalog::program::columnar'). -}
+relCheckColumnarAttr :: (MonadError String me) => DatalogProgram -> Relation -> me Bool
+relCheckColumnarAttr d rel@Relation{..} =
+    case find ((== "columnar") . name) relAttrs of
+         Nothing   -> return False
+         Just attr -> do
+             check d (attrVal attr == eTrue) (pos attr)
+                   "The value of 'columnar' attribute must be 'true' or empty"
+             check d (relRole == RelInput && relSemantics == RelSet && isNothing relPrimaryKey) (pos attr)
+                   "The 'columnar' attribute only applies to input relations without a primary key."
+             check d (all is_primitive $ fields $ typ' d rel) (pos attr)
+                   "The 'columnar' attribute only applies to relations whose records are tuples or structs of primitive fields."
+             return True
+    where
+    is_primitive t = isBool d t || isInteger d t || isString d t || isFloat d t || isDouble d t
+    fields TTuple{..}               = typeTupArgs
+    fields TStruct{typeCons = [c]}  = map typ $ consArgs c
+    fields t                        = [t]
+
+relGetColumnarAttr :: DatalogProgram -> Relation -> Bool
+relGetColumnarAttr d rel =
+    case relCheckColumnarAttr d rel of
+         Left e  -> error e
+         Right b -> b
+
+{- 'json_schema' attribute: attaches a JSON Schema to an input relation, e.g.,
+ -
+ - #[json_schema=[|{"type": "object", "required": ["id"]}|]]
+ - input relation Event[json::JsonValue]
+ -
+ - Records inserted into the relation are serialized to JSON and validated
+ - against the schema by the generated 'relval_validate()' function, which
+ - calls 'validate_relval()' from the 'jsonschema' library. -}
+relCheckJsonSchemaAttr :: (MonadError String me) => DatalogProgram -> Relation -> me (Maybe String)
+relCheckJsonSchemaAttr d Relation{..} =
+    case find ((== "json_schema") . name) relAttrs of
+         Nothing   -> return Nothing
+         Just attr -> do
+             schema <- case attrVal attr of
+                            E (EString _ str) -> return str
+                            _ -> err d (pos attr) "The value of 'json_schema' attribute must be a string literal containing a JSON Schema, e.g., #[json_schema=[|{\"type\": \"object\"}|]]"
+             check d (relRole == RelInput) (pos attr)
+                   "The 'json_schema' attribute only applies to input relations."
+             check d (M.member "jsonschema::jsonschema_validate" $ progFunctions d) (pos attr)
+                   "The 'json_schema' attribute requires importing the 'jsonschema' library."
+             case (JSON.eitherDecode $ TL.encodeUtf8 $ TL.pack schema) :: Either String JSON.Value of
+                  Left e  -> err d (pos attr) $ "Invalid JSON in 'json_schema' attribute: " ++ e
+                  Right _ -> return $ Just schema
+
+relGetJsonSchemaAttr :: DatalogProgram -> Relation -> Maybe String
+relGetJsonSchemaAttr d rel =
+    case relCheckJsonSchemaAttr d rel of
+         Left e  -> error e
+         Right s -> s
+
 {- 'rust' attribute is transferred directly to the generated Rust code. -}
 
 checkRustAttrs :: (MonadError String me) => DatalogProgram -> [Attribute] -> me [String]
@@ -321,6 +439,24 @@ funcGetReturnByRefAttr d f =
          Left e -> error e
          Right return_by_ref -> return_by_ref
 
+{- 'view_args' attribute: extern function takes its immutable 'Vec', 'Set',
+ - and 'Map' arguments as borrowed views ('VecView', 'SetView', 'MapView')
+ - instead of references to the collection. -}
+checkViewArgsAttr :: (MonadError String me) => DatalogProgram -> Function -> me Bool
+checkViewArgsAttr d Function{..} =
+    case find ((== "view_args") . name) funcAttrs of
+         Nothing -> return False
+         Just attr -> do check d (isNothing funcDef) (pos attr) "'view_args' attribute is supported for extern functions only"
+                         check d (attrVal attr == eTrue) (pos attr)
+                            "The value of 'view_args' attribute must be 'true' or empty"
+                         return True
+
+funcGetViewArgsAttr :: DatalogProgram -> Function -> Bool
+funcGetViewArgsAttr d f =
+    case checkViewArgsAttr d f of
+         Left e -> error e
+         Right view_args -> view_args
+
 {- 'iterate_by_val', 'iterate_by_ref' attributes: Tell DDlog that the type can be iterated over and thus
    can be used in a for-loop or a 'FlatMap' operator.  The first form indicates tha the 'iter()' method
    returns a by-value iterator, the second form indicates that 'iter()' returns a by-reference iterator. -}
@@ -56,6 +56,8 @@ import qualified Data.Set as S
 import qualified Data.Map as M
 import qualified Data.Graph.Inductive as G
 import Data.WideWord
+import Data.Word (Word64)
+import Data.Char (ord)
 --import Debug.Trace
 
 import Language.DifferentialDatalog.Config
@@ -82,6 +84,7 @@ import Language.DifferentialDatalog.Attribute
 import Language.DifferentialDatalog.Var
 import Language.DifferentialDatalog.Rust
 import Language.DifferentialDatalog.D3log
+import Language.DifferentialDatalog.Version
 
 -- Some OSs think that they run on a typewriter and insert '\r'
 -- at the end of each line.  We eliminate these characters as they confuse
@@ -169,11 +172,18 @@ mainCargo crate_types toml_footer =
                                  "path = \"types/" <> pp (crateDirPath crate) <> "\"")
                 $ cgCrates ?crate_graph
     template = replace "\"differential_datalog/c_api\"" "\"differential_datalog/c_api\", \"types/c_api\""
+               $ replace "\"differential_datalog/proptest\"" "\"differential_datalog/proptest\", \"types/proptest\""
                $ replace "\"differential_datalog/flatbuf\"" "\"differential_datalog/flatbuf\", \"types/flatbuf\""
-               $ (if confNestedTS32 ?cfg
-                  then replace "[dependencies.differential_datalog]" "[dependencies.differential_datalog]\nfeatures=[\"nested_ts_32\"]"
-                  else id)
+               $ (if null ddlog_features
+                  then id
+                  else replace "[dependencies.differential_datalog]" ("[dependencies.differential_datalog]\nfeatures=[" ++ intercalate ", " (map show ddlog_features) ++ "]"))
                $ unpackFixNewline $ $(embedFile "rust/template/Cargo.toml")
+    ddlog_features :: [String]
+    ddlog_features = (if confNestedTS32 ?cfg then ["nested_ts_32"] else [])
+                     ++ (case confWeightWidth ?cfg of
+                              64  -> ["weight_64"]
+                              128 -> ["weight_128"]
+                              _   -> [])
 
 rustProjectDir :: (?specname::String) => String
 rustProjectDir = ?specname ++ "_ddlog"
@@ -185,9 +195,39 @@ templateFiles =
         [ ("src/build.rs"               , $(embedFile "rust/template/src/build.rs"))
         , ("src/main.rs"                , $(embedFile "rust/template/src/main.rs"))
         , ("src/api/mod.rs"             , $(embedFile "rust/template/src/api/mod.rs"))
+        , ("src/api/archive.rs"         , $(embedFile "rust/template/src/api/archive.rs"))
+        , ("src/api/audit_log.rs"       , $(embedFile "rust/template/src/api/audit_log.rs"))
         , ("src/api/c_api.rs"           , $(embedFile "rust/template/src/api/c_api.rs"))
+        , ("src/api/changelog.rs"       , $(embedFile "rust/template/src/api/changelog.rs"))
+        , ("src/api/compression.rs"     , $(embedFile "rust/template/src/api/compression.rs"))
+        , ("src/api/dynamic_rules.rs"   , $(embedFile "rust/template/src/api/dynamic_rules.rs"))
+        , ("src/api/history.rs"         , $(embedFile "rust/template/src/api/history.rs"))
+        , ("src/api/integrity.rs"       , $(embedFile "rust/template/src/api/integrity.rs"))
+        , ("src/api/key_conflicts.rs"   , $(embedFile "rust/template/src/api/key_conflicts.rs"))
+        , ("src/api/scheduler.rs"       , $(embedFile "rust/template/src/api/scheduler.rs"))
+        , ("src/api/self_check.rs"      , $(embedFile "rust/template/src/api/self_check.rs"))
+        , ("src/api/session.rs"         , $(embedFile "rust/template/src/api/session.rs"))
+        , ("src/api/settings_file.rs"   , $(embedFile "rust/template/src/api/settings_file.rs"))
+        , ("src/api/tenant.rs"          , $(embedFile "rust/template/src/api/tenant.rs"))
+        , ("src/api/view_cache.rs"      , $(embedFile "rust/template/src/api/view_cache.rs"))
+        , ("src/alert_sink.rs"          , $(embedFile "rust/template/src/alert_sink.rs"))
+        , ("src/bgp_feed.rs"            , $(embedFile "rust/template/src/bgp_feed.rs"))
+        , ("src/dashboard.rs"           , $(embedFile "rust/template/src/dashboard.rs"))
+        , ("src/ddlog_testing.rs"       , $(embedFile "rust/template/src/ddlog_testing.rs"))
+        , ("src/flow_collector.rs"      , $(embedFile "rust/template/src/flow_collector.rs"))
+        , ("src/k8s_controller.rs"      , $(embedFile "rust/template/src/k8s_controller.rs"))
+        , ("src/notebook.rs"            , $(embedFile "rust/template/src/notebook.rs"))
+        , ("src/otlp.rs"                , $(embedFile "rust/template/src/otlp.rs"))
         , ("src/ovsdb_api.rs"           , $(embedFile "rust/template/src/ovsdb_api.rs"))
+        , ("src/packet_source.rs"       , $(embedFile "rust/template/src/packet_source.rs"))
+        , ("src/postgres_sink.rs"       , $(embedFile "rust/template/src/postgres_sink.rs"))
+        , ("src/security_logs.rs"       , $(embedFile "rust/template/src/security_logs.rs"))
+        , ("src/sigma.rs"               , $(embedFile "rust/template/src/sigma.rs"))
+        , ("src/snapshot_publisher.rs"  , $(embedFile "rust/template/src/snapshot_publisher.rs"))
+        , ("src/sqlite_sink.rs"         , $(embedFile "rust/template/src/sqlite_sink.rs"))
         , ("src/update_handler.rs"      , $(embedFile "rust/template/src/update_handler.rs"))
+        , ("src/web_ui.rs"              , $(embedFile "rust/template/src/web_ui.rs"))
+        , ("src/web_ui.html"            , $(embedFile "rust/template/src/web_ui.html"))
         , ("ddlog.h"                    , $(embedFile "rust/template/ddlog.h"))
         , ("ddlog_ovsdb_test.c"         , $(embedFile "rust/template/ddlog_ovsdb_test.c"))
         ]
@@ -197,24 +237,54 @@ rustLibFiles :: (?specname::String) => [(String, String)]
 rustLibFiles =
     map (mapSnd (unpackFixNewline)) $
         [ ("differential_datalog/Cargo.toml"                      , $(embedFile "rust/template/differential_datalog/Cargo.toml"))
+        , ("differential_datalog/src/access.rs"                   , $(embedFile "rust/template/differential_datalog/src/access.rs"))
         , ("differential_datalog/src/callback.rs"                 , $(embedFile "rust/template/differential_datalog/src/callback.rs"))
+        , ("differential_datalog/src/coordinator.rs"              , $(embedFile "rust/template/differential_datalog/src/coordinator.rs"))
         , ("differential_datalog/src/ddlog.rs"                    , $(embedFile "rust/template/differential_datalog/src/ddlog.rs"))
         , ("differential_datalog/src/ddval/mod.rs"                , $(embedFile "rust/template/differential_datalog/src/ddval/mod.rs"))
         , ("differential_datalog/src/ddval/ddvalue.rs"            , $(embedFile "rust/template/differential_datalog/src/ddval/ddvalue.rs"))
         , ("differential_datalog/src/ddval/ddval_convert.rs"      , $(embedFile "rust/template/differential_datalog/src/ddval/ddval_convert.rs"))
+        , ("differential_datalog/src/ddval/batch.rs"              , $(embedFile "rust/template/differential_datalog/src/ddval/batch.rs"))
+        , ("differential_datalog/src/ddval/hashed.rs"             , $(embedFile "rust/template/differential_datalog/src/ddval/hashed.rs"))
+        , ("differential_datalog/src/ddval/intern.rs"             , $(embedFile "rust/template/differential_datalog/src/ddval/intern.rs"))
+        , ("differential_datalog/src/ddval/slab.rs"               , $(embedFile "rust/template/differential_datalog/src/ddval/slab.rs"))
+        , ("differential_datalog/src/graph.rs"                    , $(embedFile "rust/template/differential_datalog/src/graph.rs"))
+        , ("differential_datalog/src/interpreter.rs"              , $(embedFile "rust/template/differential_datalog/src/interpreter.rs"))
         , ("differential_datalog/src/lib.rs"                      , $(embedFile "rust/template/differential_datalog/src/lib.rs"))
+        , ("differential_datalog/src/metadata.rs"                 , $(embedFile "rust/template/differential_datalog/src/metadata.rs"))
         , ("differential_datalog/src/profile.rs"                  , $(embedFile "rust/template/differential_datalog/src/profile.rs"))
         , ("differential_datalog/src/profile_statistics.rs"       , $(embedFile "rust/template/differential_datalog/src/profile_statistics.rs"))
         , ("differential_datalog/src/program/mod.rs"              , $(embedFile "rust/template/differential_datalog/src/program/mod.rs"))
         , ("differential_datalog/src/program/update.rs"           , $(embedFile "rust/template/differential_datalog/src/program/update.rs"))
         , ("differential_datalog/src/program/arrange.rs"          , $(embedFile "rust/template/differential_datalog/src/program/arrange.rs"))
+        , ("differential_datalog/src/program/batching.rs"         , $(embedFile "rust/template/differential_datalog/src/program/batching.rs"))
+        , ("differential_datalog/src/program/columnar.rs"         , $(embedFile "rust/template/differential_datalog/src/program/columnar.rs"))
+        , ("differential_datalog/src/program/compaction.rs"       , $(embedFile "rust/template/differential_datalog/src/program/compaction.rs"))
+        , ("differential_datalog/src/program/conflict.rs"         , $(embedFile "rust/template/differential_datalog/src/program/conflict.rs"))
+        , ("differential_datalog/src/program/history.rs"          , $(embedFile "rust/template/differential_datalog/src/program/history.rs"))
+        , ("differential_datalog/src/program/lazy.rs"             , $(embedFile "rust/template/differential_datalog/src/program/lazy.rs"))
+        , ("differential_datalog/src/program/overflow.rs"         , $(embedFile "rust/template/differential_datalog/src/program/overflow.rs"))
+        , ("differential_datalog/src/program/placement.rs"        , $(embedFile "rust/template/differential_datalog/src/program/placement.rs"))
+        , ("differential_datalog/src/program/plan.rs"             , $(embedFile "rust/template/differential_datalog/src/program/plan.rs"))
+        , ("differential_datalog/src/program/stratification.rs"   , $(embedFile "rust/template/differential_datalog/src/program/stratification.rs"))
         , ("differential_datalog/src/program/timestamp.rs"        , $(embedFile "rust/template/differential_datalog/src/program/timestamp.rs"))
         , ("differential_datalog/src/program/worker.rs"           , $(embedFile "rust/template/differential_datalog/src/program/worker.rs"))
         , ("differential_datalog/src/program/config.rs"           , $(embedFile "rust/template/differential_datalog/src/program/config.rs"))
+        , ("differential_datalog/src/program/poison.rs"           , $(embedFile "rust/template/differential_datalog/src/program/poison.rs"))
+        , ("differential_datalog/src/program/progress.rs"         , $(embedFile "rust/template/differential_datalog/src/program/progress.rs"))
+        , ("differential_datalog/src/program/sharing.rs"          , $(embedFile "rust/template/differential_datalog/src/program/sharing.rs"))
+        , ("differential_datalog/src/program/stats.rs"            , $(embedFile "rust/template/differential_datalog/src/program/stats.rs"))
+        , ("differential_datalog/src/program/tenant.rs"           , $(embedFile "rust/template/differential_datalog/src/program/tenant.rs"))
+        , ("differential_datalog/src/program/time_travel.rs"      , $(embedFile "rust/template/differential_datalog/src/program/time_travel.rs"))
+        , ("differential_datalog/src/program/txn_ids.rs"          , $(embedFile "rust/template/differential_datalog/src/program/txn_ids.rs"))
         , ("differential_datalog/src/record/mod.rs"               , $(embedFile "rust/template/differential_datalog/src/record/mod.rs"))
         , ("differential_datalog/src/record/tuples.rs"            , $(embedFile "rust/template/differential_datalog/src/record/tuples.rs"))
         , ("differential_datalog/src/record/arrays.rs"            , $(embedFile "rust/template/differential_datalog/src/record/arrays.rs"))
+        , ("differential_datalog/src/record/canonical.rs"         , $(embedFile "rust/template/differential_datalog/src/record/canonical.rs"))
+        , ("differential_datalog/src/record/diff.rs"              , $(embedFile "rust/template/differential_datalog/src/record/diff.rs"))
+        , ("differential_datalog/src/record/pretty.rs"            , $(embedFile "rust/template/differential_datalog/src/record/pretty.rs"))
         , ("differential_datalog/src/replay.rs"                   , $(embedFile "rust/template/differential_datalog/src/replay.rs"))
+        , ("differential_datalog/src/scheduler.rs"                , $(embedFile "rust/template/differential_datalog/src/scheduler.rs"))
         , ("differential_datalog/src/test_record.rs"              , $(embedFile "rust/template/differential_datalog/src/test_record.rs"))
         , ("differential_datalog/src/valmap.rs"                   , $(embedFile "rust/template/differential_datalog/src/valmap.rs"))
         , ("differential_datalog/src/variable.rs"                 , $(embedFile "rust/template/differential_datalog/src/variable.rs"))
@@ -231,12 +301,15 @@ rustLibFiles =
         , ("differential_datalog_test/test_value.rs"              , $(embedFile "rust/template/differential_datalog_test/test_value.rs"))
         , ("ddlog_derive/Cargo.toml"                              , $(embedFile "rust/template/ddlog_derive/Cargo.toml"))
         , ("ddlog_derive/src/lib.rs"                              , $(embedFile "rust/template/ddlog_derive/src/lib.rs"))
+        , ("ddlog_derive/src/arbitrary.rs"                        , $(embedFile "rust/template/ddlog_derive/src/arbitrary.rs"))
         , ("ddlog_derive/src/from_record.rs"                      , $(embedFile "rust/template/ddlog_derive/src/from_record.rs"))
         , ("ddlog_derive/src/into_record.rs"                      , $(embedFile "rust/template/ddlog_derive/src/into_record.rs"))
         , ("ddlog_derive/src/mutator.rs"                          , $(embedFile "rust/template/ddlog_derive/src/mutator.rs"))
         , ("cmd_parser/Cargo.toml"                                , $(embedFile "rust/template/cmd_parser/Cargo.toml"))
         , ("cmd_parser/lib.rs"                                    , $(embedFile "rust/template/cmd_parser/lib.rs"))
+        , ("cmd_parser/hardened.rs"                               , $(embedFile "rust/template/cmd_parser/hardened.rs"))
         , ("cmd_parser/parse.rs"                                  , $(embedFile "rust/template/cmd_parser/parse.rs"))
+        , ("cmd_parser/preprocess.rs"                             , $(embedFile "rust/template/cmd_parser/preprocess.rs"))
         , ("distributed_datalog/Cargo.toml"                       , $(embedFile "rust/template/distributed_datalog/Cargo.toml"))
         , ("distributed_datalog/src/assign.rs"                    , $(embedFile "rust/template/distributed_datalog/src/assign.rs"))
         , ("distributed_datalog/src/accumulate/mod.rs"            , $(embedFile "rust/template/distributed_datalog/src/accumulate/mod.rs"))
@@ -600,7 +673,8 @@ simplifyDelayedRels d =
                      relSemantics    = relSemantics rel,
                      relName         = mk_delayed_rel_name (rname, delay),
                      relType         = typ rel,
-                     relPrimaryKey   = Nothing
+                     relPrimaryKey   = Nothing,
+                     relAttrs        = []
                  }
                  delayed_rule = Rule {
                     rulePos     = nopos,
@@ -612,7 +686,8 @@ simplifyDelayedRels d =
                             atomRelation   = name delayed_rel,
                             atomDelay      = delayZero,
                             atomDiff       = False,
-                            atomVal        = eTypedVar "x" $ typ rel
+                            atomVal        = eTypedVar "x" $ typ rel,
+                            atomCountOf    = Nothing
                         },
                         lhsLocation = Nothing
                      }],
@@ -623,7 +698,8 @@ simplifyDelayedRels d =
                              atomRelation   = rname,
                              atomDelay      = delay,
                              atomDiff       = False,
-                             atomVal        = eTypedVar "x" $ typ rel
+                             atomVal        = eTypedVar "x" $ typ rel,
+                             atomCountOf    = Nothing
                          }
                      }]
                  }
@@ -666,7 +742,8 @@ simplifyDifferentiation d =
                      relSemantics    = RelMultiset,
                      relName         = mk_diff_rel_name rname,
                      relType         = typ rel,
-                     relPrimaryKey   = Nothing
+                     relPrimaryKey   = Nothing,
+                     relAttrs        = []
                  }
                  diff_rule = Rule {
                     rulePos     = nopos,
@@ -678,7 +755,8 @@ simplifyDifferentiation d =
                             atomRelation   = name diff_rel,
                             atomDelay      = delayZero,
                             atomDiff       = False,
-                            atomVal        = eTypedVar "x" $ typ rel
+                            atomVal        = eTypedVar "x" $ typ rel,
+                            atomCountOf    = Nothing
                         },
                         lhsLocation = Nothing
                      }],
@@ -689,7 +767,8 @@ simplifyDifferentiation d =
                              atomRelation   = rname,
                              atomDelay      = delayZero,
                              atomDiff       = True,
-                             atomVal        = eTypedVar "x" $ typ rel
+                             atomVal        = eTypedVar "x" $ typ rel,
+                             atomCountOf    = Nothing
                          }
                      }]
                  }
@@ -768,6 +847,7 @@ mkCargoToml rs_code crate crate_id =
            "default = []"                                                                  $$
            "flatbuf = [\"differential_datalog/flatbuf\"," <> fb_features <> "]"            $$
            "c_api = [\"differential_datalog/c_api\"," <> capi_features <> "]"              $$
+           "proptest = [\"differential_datalog/proptest\"," <> proptest_features <> "]"   $$
            ""                                                                              $$
            "[dependencies]"                                                                $$
            "differential_datalog = { path = \"" <> pp root <> "../differential_datalog\" }"$$
@@ -782,6 +862,7 @@ mkCargoToml rs_code crate crate_id =
            "serde = { version = \"1.0\", features = [\"derive\"] }"                        $$
            "num = \"0.3\""                                                                 $$
            "erased-serde = \"0.3\""                                                        $$
+           "smallvec = \"1.6\""                                                            $$
            --"differential-dataflow = \"0.11.0\""                                            $$
            --"timely = \"0.11\""                                                             $$
            "differential-dataflow = { git = \"https://github.com/ddlog-dev/differential-dataflow\", branch = \"ddlog-4\" }" $$
@@ -810,6 +891,7 @@ mkCargoToml rs_code crate crate_id =
     -- Enable 'flatbuf' and 'capi' features in all dependencies.
     fb_features = commaSep $ map (\dep -> "\"" <> pp (crateName dep) <> "/flatbuf\"") deps
     capi_features = commaSep $ map (\dep -> "\"" <> pp (crateName dep) <> "/c_api\"") deps
+    proptest_features = commaSep $ map (\dep -> "\"" <> pp (crateName dep) <> "/proptest\"") deps
     -- Add 'toml' code from 'rs_code'.
     extra_toml_code = vcat $ map (sel3 . snd) $ filter ((\mname -> S.member mname crate) . fst) $ M.toList rs_code
 
@@ -955,6 +1037,7 @@ compileMainLib d d3log_rel_map nodes cstate =
     mkRelEnum d                            $+$ -- 'enum Relations'
     mkIdxEnum d                            $+$ -- 'enum Indexes'
     mkD3logImpl d d3log_rel_map            $+$
+    mkProgramMetadata d                    $+$ -- 'PROGRAM_METADATA'
     mkProg d cstate nodes
     where
     mod_type_reexports = foldl' (mrAddTypedef d) emptyModuleReexports
@@ -1108,10 +1191,10 @@ addDummyRel d =
     d {progRelations = rels, progIndexes = idxs}
     where
     rels = if (M.null $ progRelations d) || (M.null $ progIndexes d)
-              then M.insert "__Null" (Relation nopos RelInternal RelSet "__Null" (tTuple []) Nothing) (progRelations d)
+              then M.insert "__Null" (Relation nopos RelInternal RelSet "__Null" (tTuple []) Nothing []) (progRelations d)
               else progRelations d
     idxs = if M.null $ progIndexes d
-              then M.singleton "__Null_by_none" $ Index nopos "__Null_by_none" [] $ Atom nopos "__Null" delayZero False $ eTuple []
+              then M.singleton "__Null_by_none" $ Index nopos "__Null_by_none" [] $ Atom nopos "__Null" delayZero False (eTuple []) Nothing
               else progIndexes d
 
 mkTypedef :: (?crate_graph::CrateGraph, ?specname::String) => DatalogProgram -> TypeDef -> Doc
@@ -1123,16 +1206,19 @@ mkTypedef d tdef@TypeDef{..} =
          Just TStruct{..} | length typeCons == 1
                           -> let (fields, extras) = unzip $ map (mkField tdefName True) $ consArgs $ head typeCons in
                              derive_struct                                                             $$
+                             derive_arbitrary                                                          $$
                              "#[ddlog(rename = \"" <> pp (name $ head typeCons) <> "\")]"              $$
                              "pub struct" <+> nameLocal tdefName <> targs <+> "{"                      $$
                              (nest' $ vcat $ punctuate comma fields)                                   $$
                              "}"                                                                       $$
+                             plain_data                                                                $$
                              impl_abomonate                                                            $$
                              display                                                                   $$
                              vcat extras
                           | otherwise
                           -> let (constructors, extras) = unzip $ map mkConstructor typeCons in
                              derive_enum                                                               $$
+                             derive_arbitrary                                                          $$
                              "#[ddlog(rename = \"" <> pp tdefName <> "\")]"                            $$
                              "pub enum" <+> nameLocal tdefName <> targs <+> "{"                        $$
                              (nest' $ vcat $ punctuate comma constructors)                             $$
@@ -1147,8 +1233,19 @@ mkTypedef d tdef@TypeDef{..} =
     rustAttrs = getRustAttrs d tdefAttrs
     derive_serialize = if tdefGetCustomSerdeAttr d tdef then empty else ", Serialize, Deserialize"
     derive_fromrec = if tdefGetCustomFromRecord d tdef then empty else ", FromRecord"
-    derive_struct = "#[derive(Eq, Ord, Clone, Hash, PartialEq, PartialOrd, IntoRecord, Mutator, Default" <> derive_serialize <> derive_fromrec <> ")]"
+    -- Plain-old-data structs get `PartialEq` and `Hash` implementations that
+    -- work on raw bytes from 'plain_data_traits!' instead of derived ones.
+    is_plain = tdefIsPlainData d tdef
+    derive_cmp = if is_plain then empty else ", Hash, PartialEq"
+    derive_struct = "#[derive(Eq, Ord, Clone" <> derive_cmp <> ", PartialOrd, IntoRecord, Mutator, Default" <> derive_serialize <> derive_fromrec <> ")]"
+    plain_data = if is_plain
+                 then "::ddlog_rt::plain_data_traits!(" <> nameLocal tdefName <>
+                      (hcat $ map (\f -> "," <+> pp (name f) <> ":" <+> mkType d scope f) $ consArgs $ head $ typeCons $ fromJust tdefType) <> ");"
+                 else empty
     derive_enum = "#[derive(Eq, Ord, Clone, Hash, PartialEq, PartialOrd, IntoRecord, Mutator" <> derive_serialize <> derive_fromrec <> ")]"
+    derive_arbitrary = if tdefIsArbitrary d tdef
+                       then "#[cfg_attr(feature = \"proptest\", derive(::ddlog_derive::Arbitrary))]"
+                       else empty
     targs = if null tdefArgs
                then empty
                else "<" <> (hsep $ punctuate comma $ map pp tdefArgs) <> ">"
@@ -1241,6 +1338,61 @@ mkTypedef d tdef@TypeDef{..} =
               cname = mkConstructorName scope tdefName (fromJust tdefType) (name c)
               def_args = commaSep $ map (\a -> (pp $ name a) <+> ": ::std::default::Default::default()") $ consArgs c
 
+-- True if 'tdef' is a non-generic struct whose fields are all fixed-width
+-- integers, Booleans, or other plain-old-data structs.  Values of such types
+-- are compared and hashed as raw bytes (see 'ddlog_rt::PlainData').
+tdefIsPlainData :: DatalogProgram -> TypeDef -> Bool
+tdefIsPlainData d tdef@TypeDef{..} =
+    null tdefArgs && not (tdefGetAliasAttr d tdef) &&
+    case tdefType of
+         Just TStruct{..} | length typeCons == 1
+                          -> all (typeIsPlainData d . typ) $ consArgs $ head typeCons
+         _                -> False
+
+typeIsPlainData :: DatalogProgram -> Type -> Bool
+typeIsPlainData d TUser{..} | null typeArgs =
+    case tdefType tdef of
+         Just TStruct{} -> tdefIsPlainData d tdef
+         Just t'        -> typeIsPlainData d t'
+         Nothing        -> False
+    where tdef = getType d typeName
+typeIsPlainData d t = isBool d t || smallInt d t
+
+-- True if random values of 'tdef' can be generated by 'ddlog_derive::Arbitrary'
+-- when the 'proptest' feature is enabled: 'tdef' is not recursive and its
+-- fields only contain Booleans, strings, fixed-width integers, tuples, and
+-- other such types, possibly wrapped in the 'ddlog_std' collections that
+-- implement 'Arbitrary'.
+tdefIsArbitrary :: DatalogProgram -> TypeDef -> Bool
+tdefIsArbitrary d tdef@TypeDef{..} =
+    not (tdefGetAliasAttr d tdef) &&
+    case tdefType of
+         Just TStruct{..} -> all (typeIsArbitrary d [tdefName] . typ) $ concatMap consArgs typeCons
+         _                -> False
+
+arbitraryExternTypes :: [String]
+arbitraryExternTypes = [ "ddlog_std::Ref", "ddlog_std::Vec", "ddlog_std::SmallVec"
+                       , "ddlog_std::Set", "ddlog_std::SmallSet", "ddlog_std::Map"]
+
+-- 'visited' lists the type definitions being checked, so that recursive types,
+-- for which 'ddlog_derive::Arbitrary' would not terminate, are rejected.
+typeIsArbitrary :: DatalogProgram -> [String] -> Type -> Bool
+typeIsArbitrary d visited TUser{..}
+    | elem typeName visited              = False
+    | elem typeName arbitraryExternTypes = args_arbitrary
+    | otherwise                          =
+        args_arbitrary &&
+        case tdefType tdef of
+             Just TStruct{typeCons = cons} -> all (typeIsArbitrary d visited' . typ) $ concatMap consArgs cons
+             Just t'                       -> typeIsArbitrary d visited' t'
+             Nothing                       -> False
+    where tdef = getType d typeName
+          visited' = typeName : visited
+          args_arbitrary = all (typeIsArbitrary d visited) typeArgs
+typeIsArbitrary d visited TTuple{..} = all (typeIsArbitrary d visited) typeTupArgs
+typeIsArbitrary _ _ TVar{} = True
+typeIsArbitrary d _ t = isBool d t || isString d t || smallInt d t
+
 -- Generate #[ddlog(rename=)] attribute to rename fields that clash with
 -- reserved names.
 ddlog_rename :: (WithName a) => a -> Doc
@@ -1269,6 +1421,7 @@ mkDDValueFromRecord d@DatalogProgram{..} =
     mkRelationsTryFromRelId d                                                                       $$
     mkRelId2Name d                                                                                  $$
     mkRelId2NameC                                                                                   $$
+    mkRelId2Type d                                                                                  $$
     mkRelIdMap d                                                                                    $$
     mkRelIdMapC d                                                                                   $$
     mkInputRelIdMap d                                                                               $$
@@ -1284,12 +1437,23 @@ mkDDValueFromRecord d@DatalogProgram{..} =
     (nest' $ nest' $ vcommaSep entries)                                                             $$
     "    }"                                                                                         $$
     "}"                                                                                             $$
+    "pub fn relvals_from_records(rel: Relations, _recs: &[differential_datalog::record::Record]) -> ::std::result::Result<Vec<DDValue>, String> {" $$
+    "    match rel {"                                                                               $$
+    (nest' $ nest' $ vcommaSep batch_entries)                                                       $$
+    "    }"                                                                                         $$
+    "}"                                                                                             $$
     "pub fn relkey_from_record(rel: Relations, _rec: &differential_datalog::record::Record) -> ::std::result::Result<DDValue, String> {" $$
     "    match rel {"                                                                               $$
     (nest' $ nest' $ vcommaSep key_entries)                                                         $$
     "        _ => Err(format!(\"relation {:?} does not have a primary key\", rel))"                 $$
     "    }"                                                                                         $$
     "}"                                                                                             $$
+    "pub fn relval_validate(rel: Relations, _val: &DDValue) -> ::std::result::Result<(), String> {" $$
+    "    match rel {"                                                                               $$
+    (nest' $ nest' $ vcat validate_entries)                                                         $$
+    "        _ => Ok(())"                                                                           $$
+    "    }"                                                                                         $$
+    "}"                                                                                             $$
     "pub fn idxkey_from_record(idx: Indexes, _rec: &differential_datalog::record::Record) -> ::std::result::Result<DDValue, String> {"   $$
     "    match idx {"                                                                               $$
     (nest' $ nest' $ vcommaSep idx_entries)                                                         $$
@@ -1303,6 +1467,20 @@ mkDDValueFromRecord d@DatalogProgram{..} =
         "    Ok(<" <> mkType d Nothing t <> ">::from_record(_rec)?.into_ddvalue())"   $$
         "}"
         where t = typeNormalize d relType
+    batch_entries = map mkrelvals $ M.elems progRelations
+    mkrelvals :: Relation ->  Doc
+    mkrelvals rel@Relation{..} =
+        "Relations::" <> rnameFlat (name rel) <+> "=> {"                                                                  $$
+        "    let vals = _recs.iter().map(<" <> t <> ">::from_record).collect::<::std::result::Result<Vec<_>, String>>()?;" $$
+        "    Ok(<" <> t <> ">::into_ddvalue_batch(vals))"                                                                  $$
+        "}"
+        where t = mkType d Nothing $ typeNormalize d relType
+    validate_entries = mapMaybe mkrelvalidate $ M.elems progRelations
+    mkrelvalidate :: Relation -> Maybe Doc
+    mkrelvalidate rel = do
+        schema <- relGetJsonSchemaAttr d rel
+        return $ "Relations::" <> rnameFlat (name rel) <+> "=>" <+> rnameScoped Nothing "jsonschema::validate_relval" <>
+                 "(r###\"" <> pp schema <> "\"###, \"" <> pp (name rel) <> "\", _val),"
     key_entries = map mkrelkey $ filter (isJust . relPrimaryKey) $ M.elems progRelations
     mkrelkey :: Relation ->  Doc
     mkrelkey rel =
@@ -1413,6 +1591,20 @@ mkRelId2Name d =
     mkrel :: Relation -> Doc
     mkrel rel = pp (relIdentifier d rel) <+> "=> Some(&\"" <> pp (name rel) <> "\"),"
 
+mkRelId2Type :: DatalogProgram -> Doc
+mkRelId2Type d =
+    "/// Returns the DDlog type of the records in a relation."        $$
+    "pub fn relid2type(rid: program::RelId) -> ::std::option::Option<&'static str> {" $$
+    "   match rid {"                                          $$
+    (nest' $ nest' $ vcat $ entries)                          $$
+    "       _  => None"                                       $$
+    "   }"                                                    $$
+    "}"
+    where
+    entries = map mkrel $ M.elems $ progRelations d
+    mkrel :: Relation -> Doc
+    mkrel rel = pp (relIdentifier d rel) <+> "=> Some(" <> pp (show $ show $ relType rel) <> "),"
+
 mkRelId2NameC :: Doc
 mkRelId2NameC =
     -- TODO: Documentation on the generated function
@@ -1494,6 +1686,44 @@ mkOutputRelIdMap prog =
                   c_api = False
                 }
 
+-- Metadata of the program: its name, a hash of its source, the version of
+-- the compiler, and schema fingerprints of all relations (see
+-- `differential_datalog::metadata`).
+mkProgramMetadata :: (?specname::String) => DatalogProgram -> Doc
+mkProgramMetadata d =
+    "/// Metadata of the program this crate was generated from."                                      $$
+    "pub static PROGRAM_METADATA: ::differential_datalog::metadata::ProgramMetadata ="                $$
+    "    ::differential_datalog::metadata::ProgramMetadata {"                                         $$
+    "        program_name: \"" <> pp ?specname <> "\","                                               $$
+    "        source_hash:" <+> hex (fnv1a64 $ show d) <> ","                                          $$
+    "        compiler_version: \"" <> pp dDLOG_VERSION <+> "(" <> pp gitHash <> ")\","                $$
+    "        relations: &["                                                                           $$
+    (nest' $ nest' $ nest' $ vcat $ map mkrel $ M.elems $ progRelations d)                            $$
+    "        ],"                                                                                      $$
+    "    };"
+    where
+    mkrel rel = "::differential_datalog::metadata::RelationMetadata {"                                <+>
+                "name: \"" <> pp (name rel) <> "\","                                                   <+>
+                "input:" <+> (if relRole rel == RelInput then "true" else "false") <> ","               <+>
+                "fingerprint:" <+> hex (fnv1a64 $ relSchema d rel)                                    <+>
+                "},"
+    hex h = pp $ "0x" ++ showHex h ""
+
+-- The schema of a relation: its declaration followed by the definitions of
+-- all user-defined types it refers to, directly or indirectly.
+relSchema :: DatalogProgram -> Relation -> String
+relSchema d rel = render $ vcat $ pp rel : map (pp . getType d) (S.toList tnames)
+    where
+    tnames = closure S.empty $ typeUserTypes $ relType rel
+    closure visited [] = visited
+    closure visited (t:ts) | S.member t visited = closure visited ts
+                           | otherwise = closure (S.insert t visited)
+                                                 (ts ++ maybe [] typeUserTypes (tdefType $ getType d t))
+
+-- 64-bit FNV-1a hash.
+fnv1a64 :: String -> Word64
+fnv1a64 = foldl' (\h c -> (h `xor` fromIntegral (ord c)) * 1099511628211) 14695981039346656037
+
 -- Convert string to `enum Indexes`
 mkIndexesTryFromStr :: DatalogProgram -> Doc
 mkIndexesTryFromStr d =
@@ -1680,11 +1910,35 @@ mkFunc d f@Function{..} | isJust funcDef =
     where
     scope = Just $ nameScope f
     mkArg :: FuncArg -> Doc
-    mkArg a = pp (name a) <> ":" <+> "&" <> (if argMut a then "mut" else empty) <+> mkType d scope a
+    mkArg a | funcArgIsView d f a = pp (name a) <> ":" <+> mkType d scope (funcArgViewType d a)
+            | otherwise           = pp (name a) <> ":" <+> "&" <> (if argMut a then "mut" else empty) <+> mkType d scope a
     tvars = case funcTypeVars f of
                  []  -> empty
                  tvs -> "<" <> (hcat $ punctuate comma $ map ((<> ": ::ddlog_rt::Val") . pp) tvs) <> ">"
 
+-- Arguments of '#[view_args]' extern functions whose type is 'Vec', 'Set', or
+-- 'Map' are passed as borrowed views rather than references.
+funcArgIsView :: DatalogProgram -> Function -> FuncArg -> Bool
+funcArgIsView d f a =
+    funcGetViewArgsAttr d f && not (argMut a) &&
+    case typ' d a of
+         TOpaque _ t _ -> elem t [mOD_STD ++ "::Vec", mOD_STD ++ "::Set", mAP_TYPE]
+         _             -> False
+
+-- 'VecView<T>', 'SetView<T>', or 'MapView<K,V>' type of a view argument.
+funcArgViewType :: DatalogProgram -> FuncArg -> Type
+funcArgViewType d a =
+    case typ' d a of
+         TOpaque p t as -> TOpaque p (t ++ "View") as
+         t              -> error $ "Compile.funcArgViewType " ++ show t
+
+-- Function arguments are passed as read-only or mutable references,
+-- or as views (see 'funcArgIsView').
+mkFuncCallArg :: DatalogProgram -> Function -> FuncArg -> (Doc, EKind, ENode) -> Doc
+mkFuncCallArg d f farg a | argMut farg            = mutref a
+                         | funcArgIsView d f farg = parens (ref a) <> ".view()"
+                         | otherwise              = ref a
+
 -- Find all delayed relations used in the program and assign each a RelId.
 allocDelayedRelIds :: DatalogProgram -> CompilerMonad ()
 allocDelayedRelIds d = do
@@ -2752,6 +3006,9 @@ mkProg d cstate nodes =
         $$ "    program::Program {"
         $$ "        nodes,"
         $$ "        delayed_rels,"
+        $$ "        lazy_rels: vec![],"
+        $$ "        shared_arrangements: vec![],"
+        $$ "        columnar_rels: vec![" <> columnar_rels <> "],"
         $$ "        init_data,"
         $$ "    }"
         $$ "}"
@@ -2766,6 +3023,10 @@ mkProg d cstate nodes =
         vcommaSep $
             concatMap ((map sel2) . prelFacts) $
                 concatMap nodeRels nodes
+    columnar_rels = commaSep
+                    $ map (\rel -> "(" <> relId d (name rel) <> ", (|rec: &differential_datalog::record::Record| relval_from_record(Relations::" <> rnameFlat (name rel) <> ", rec)) as program::columnar::ColumnarDecodeFunc)")
+                    $ filter (relGetColumnarAttr d)
+                    $ M.elems $ progRelations d
     delayed_rels = vcommaSep
                    $ map (\((rel, delay), delayed_relid) ->
                            "program::DelayedRelation {"                                 $$
@@ -2843,7 +3104,7 @@ mkArrangementKey d rel pattern is_ref =
         -- Make sure that the context belongs to 'rel', so that the expression is
         -- generated within the same module.
         pattern_ctx = CtxTyped (ETyped nopos pattern $ relType rel)
-                               (CtxIndex $ Index nopos "" [] $ Atom nopos (name rel) delayZero False ePHolder)
+                               (CtxIndex $ Index nopos "" [] $ Atom nopos (name rel) delayZero False ePHolder Nothing)
         from_ddvalue = if is_ref then "from_ddvalue_ref" else "from_ddvalue"
         mtch = mkMatch (mkPatExpr d pattern_ctx pattern EReference False) res "None"
         in "match" <+> "<" <> relt' <> ">::" <> from_ddvalue <> "(" <> vALUE_VAR <> ") {"    $$
@@ -3015,8 +3276,8 @@ mkExpr' d ctx EApply{exprFunc = (_, _, EFunc{exprFuncName}), exprArgs} =
      -- execute that code.
      mkFuncName d (Just $ ctxModule ctx) func
      <> (parens $ commaSep
-                $ map (\(a, mut) -> if mut then mutref a else ref a)
-                $ zip exprArgs (map argMut funcArgs)), kind)
+                $ map (\(a, farg) -> mkFuncCallArg d func farg a)
+                $ zip exprArgs funcArgs), kind)
     where
     [fname] = exprFuncName
     [func@Function{..}] = getFuncs d fname $ Just $ length exprArgs
@@ -3032,27 +3293,31 @@ mkExpr' d ctx e@(EApply{..}) =
                      $ zip exprArgs (map atypeMut arg_types)), kind)
     else (sel1 exprFunc
           <> (parens $ commaSep
-                     $ map (\(a, mut) -> if mut then mutref a else ref a)
-                     $ zip exprArgs (map atypeMut arg_types)), kind)
+                     $ map (\(a, farg) -> mkFuncCallArg d (fromJust func) farg a)
+                     $ zip exprArgs (funcArgs $ fromJust func)), kind)
     where
     e' = exprMap (E . sel3) e
     efunc = E $ sel3 exprFunc
     efunc_ctx = CtxApplyFunc e' ctx
     TFunction _ arg_types _ = exprType' d efunc_ctx efunc
-    (kind, is_closure) =
+    (kind, is_closure, func) =
         case exprStripTypeAnnotations efunc efunc_ctx of
              (E efunc'@EFunc{}, ctx'') -> let (f, _) = funcExprGetFunc d ctx'' efunc'
-                                          in (if funcGetReturnByRefAttr d f then EReference else EVal, False)
+                                          in (if funcGetReturnByRefAttr d f then EReference else EVal, False, Just f)
              -- TODO: support return_by_ref attribute on closures?
-             _ -> (EVal, True)
+             _ -> (EVal, True, Nothing)
 
 -- If the function is referenced inside a function invocation, simply return the
 -- name of the function; otherwise wrap the function in a closure.
 mkExpr' d ctx e@EFunc{} =
     (res, EVal)
     where
-    arg_deref :: FuncArg -> Doc
-    arg_deref a = if argMut a then "&mut *" else "&*"
+    -- Dereference closure argument 'x'; pass collections to '#[view_args]'
+    -- functions as views.
+    arg_deref :: FuncArg -> Doc -> Doc
+    arg_deref a x | argMut a            = "&mut *" <> x
+                  | funcArgIsView d f a = "(&*" <> x <> ").view()"
+                  | otherwise           = "&*" <> x
     -- Clone return value if function returns by-reference.
     clone_ref = if funcGetReturnByRefAttr d f then ".clone()" else empty
     res = case parctx ctx of
@@ -3062,8 +3327,8 @@ mkExpr' d ctx e@EFunc{} =
                     "    captured: (),"                                                                                 $$
                     "    f:" <+> (braces' $ "fn __f(__args:" <> (tuple $ map mkarg funcArgs) <> ", __captured: &()) ->" <+> ret_type_code       $$
                                             (if length funcArgs == 1
-                                             then "{unsafe{" <> fname <> "(" <> arg_deref (funcArgs !! 0) <> "__args)}" <> clone_ref <> "};"
-                                             else "{unsafe{" <> fname <> "(" <> commaSep (mapIdx (\a i -> arg_deref a <> "__args." <> pp i) funcArgs) <> ")}" <> clone_ref <> "};") $$
+                                             then "{unsafe{" <> fname <> "(" <> arg_deref (funcArgs !! 0) "__args" <> ")}" <> clone_ref <> "};"
+                                             else "{unsafe{" <> fname <> "(" <> commaSep (mapIdx (\a i -> arg_deref a ("__args." <> pp i)) funcArgs) <> ")}" <> clone_ref <> "};") $$
                                             "__f")                                                                      $$
                     "}) as Box<dyn ::ddlog_rt::Closure<(" <> commaSep (map mkarg funcArgs) <> ")," <+> ret_type_code <> ">>)"
     local_module = Just $ ctxModule ctx
@@ -56,6 +56,7 @@ data Config = Config { confDatalogFile     :: FilePath
                      , confRunRustfmt      :: Bool
                      , confRustFlatBuffers :: Bool
                      , confNestedTS32      :: Bool
+                     , confWeightWidth     :: Int
                      , confD3log           :: Bool
                      }
 
@@ -80,5 +81,6 @@ defaultConfig = Config { confDatalogFile     = ""
                        , confRunRustfmt      = False
                        , confRustFlatBuffers = False
                        , confNestedTS32      = False
+                       , confWeightWidth     = 32
                        , confD3log           = False
                        }
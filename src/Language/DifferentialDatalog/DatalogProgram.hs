@@ -310,7 +310,8 @@ progMirrorInputRelations d prefix =
     inputRels = M.toList $ M.filter (\r -> relRole r == RelInput) $ progRelations d
     relCopies = map (\(n,r) -> (output_relname n, r { relRole = RelOutput,
                                                  relName = output_relname (relName r),
-                                                 relPrimaryKey = Nothing
+                                                 relPrimaryKey = Nothing,
+                                                 relAttrs = []
                                                })) $ inputRels
     makeRule relName relation = Rule { rulePos = relPos relation,
                                        ruleModule = nameScope relName,
@@ -320,7 +321,8 @@ progMirrorInputRelations d prefix =
                                                                      atomRelation = output_relname relName,
                                                                      atomDelay = delayZero,
                                                                      atomDiff = False,
-                                                                     atomVal = eVar "x"
+                                                                     atomVal = eVar "x",
+                                                                     atomCountOf = Nothing
                                                                    },
                                                     lhsLocation = Nothing
                                                   }],
@@ -329,7 +331,8 @@ progMirrorInputRelations d prefix =
                                                                                 atomRelation = relName,
                                                                                 atomDelay = delayZero,
                                                                                 atomDiff = False,
-                                                                                atomVal = eVar "x"
+                                                                                atomVal = eVar "x",
+                                                                                atomCountOf = Nothing
                                                                               }}]}
     rules = map (\(n,r) -> makeRule n r) inputRels
   in d { progRelations = M.union (progRelations d) $ M.fromList relCopies,
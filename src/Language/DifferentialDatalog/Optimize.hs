@@ -86,11 +86,12 @@ expandMultiheadRule d rl ruleidx = (Just rel, rule1 : rules)
                    , relName       = relname
                    , relType       = tTuple $ map (varType d) lhsvars
                    , relPrimaryKey = Nothing
+                   , relAttrs      = []
                    }
     -- rule to compute the new relation
     rule1 = Rule { rulePos = nopos
                  , ruleModule = ruleModule rl
-                 , ruleLHS = [RuleLHS nopos (Atom nopos relname delayZero False $ eTuple $ map (\v -> eTypedVar (name v) (varType d v)) lhsvars) Nothing]
+                 , ruleLHS = [RuleLHS nopos (Atom nopos relname delayZero False (eTuple $ map (\v -> eTypedVar (name v) (varType d v)) lhsvars) Nothing) Nothing]
                  , ruleRHS = ruleRHS rl
                  }
     -- rule per head of the original rule
@@ -99,7 +100,7 @@ expandMultiheadRule d rl ruleidx = (Just rel, rule1 : rules)
                               , ruleLHS = [lhs]
                               , ruleRHS = [RHSLiteral True 
                                           $ Atom nopos relname delayZero False
-                                          $ eTuple $ map (\v -> eTypedVar (name v) (varType d v)) lhsvars]})
+                                                 (eTuple $ map (\v -> eTypedVar (name v) (varType d v)) lhsvars) Nothing]})
                 $ ruleLHS rl
 
 -- | Common prefix elimination.
@@ -182,6 +183,7 @@ replacePrefix d (pref, crate_name) = {-trace ("replacePrefix " ++ show pref) $-}
                        , relName       = relname
                        , relType       = tTuple $ map (varType d) vars
                        , relPrimaryKey = Nothing
+                       , relAttrs      = []
                        }
     -- rule
     let atom = Atom { atomPos      = nopos
@@ -189,6 +191,7 @@ replacePrefix d (pref, crate_name) = {-trace ("replacePrefix " ++ show pref) $-}
                     , atomDelay    = delayZero
                     , atomDiff     = False
                     , atomVal      = eTuple $ map (\v -> eTypedVar (name v) (varType d v)) vars
+                    , atomCountOf  = Nothing
                     }
     let rule = Rule { rulePos      = nopos
                     , ruleModule   = mname
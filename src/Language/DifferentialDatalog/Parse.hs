@@ -279,8 +279,10 @@ decl =  do attrs <- attributes
            case items of
                 [SpType t] -> return [SpType t{tdefAttrs = attrs}]
                 [SpFunc f] -> return [SpFunc f{funcAttrs = attrs}]
+                [SpRelation r] -> return [SpRelation r{relAttrs = attrs}]
+                [SpType t, SpRelation r] -> return [SpType t, SpRelation r{relAttrs = attrs}]
                 _          -> do
-                    when (not $ null attrs) $ fail "#-attributes are currently only supported for type and function declarations"
+                    when (not $ null attrs) $ fail "#-attributes are currently only supported for type, function, and relation declarations"
                     return items
 
 imprt = Import nopos <$ reserved "import" <*> modname <*> (option (ModuleName []) $ reserved "as" *> modname)
@@ -347,11 +349,11 @@ relation = do
          let t = if isref
                     then TUser p "Ref" [TUser p relName []]
                     else TUser p relName []
-         let rel = Relation nopos role mult relName t pkey
+         let rel = Relation nopos role mult relName t pkey []
          return [SpType tdef, SpRelation rel])
       <|>
-       (do rel <- (\tspec p2 -> let t = if isref then TUser (p1,p2) "Ref" [tspec] else tspec
-                                in Relation nopos role mult relName t)
+       (do rel <- (\tspec p2 pkey -> let t = if isref then TUser (p1,p2) "Ref" [tspec] else tspec
+                                     in Relation nopos role mult relName t pkey [])
                   <$> (brackets typeSpecSimple) <*> getPosition <*>
                       (optionMaybe $ symbol "primary" *> symbol "key" *> key_expr)
            return [SpRelation rel]))
@@ -412,6 +414,7 @@ rulelhs = withPos $ RuleLHS nopos <$> atom True <*> optionMaybe location
 rulerhs :: ParsecT String () Identity [RuleRHS]
 rulerhs =  (do _ <- try $ lookAhead $ (optional $ reserved "not") *> (optional $ try $ varIdent <* reserved "in") *> (optional $ reservedOp "&") *> relIdent *> delay *> (optional $ reservedOp "'") *> (symbol "(" <|> symbol "[")
                (\x -> [x]) <$> (RHSLiteral <$> (option True (False <$ reserved "not")) <*> atom False))
+          <|> countOf
           <|> aggregate
           <|> do _ <- try $ lookAhead $ flatmap_pattern *> reservedOp "=" *> reserved "FlatMap"
                  (\x -> [x]) <$> (RHSFlatMap <$> flatmap_pattern <*>
@@ -448,6 +451,14 @@ extractGroupBy (EApply p (E (EFunc _ [f])) args) | f == "group_by" = do
     return $ E $ EVar p "__group"
 extractGroupBy e  = return $ E e
 
+-- 'var n = count_of(R(x, y))' matches 'R(x, y)' like a positive literal and
+-- binds 'n' to the multiplicity of the matching fact in 'R'.
+countOf = do
+    _ <- try $ lookAhead $ reserved "var" *> varIdent *> reservedOp "=" *> reserved "count_of" *> symbol "(" *> relIdent
+    var <- reserved "var" *> varIdent
+    a <- reservedOp "=" *> reserved "count_of" *> parens (atom False)
+    return [RHSLiteral True a{atomCountOf = Just var}]
+
 -- Deprecated Aggregate syntax.
 -- TODO: generate warning.
 aggregate = do
@@ -493,7 +504,7 @@ atom is_head = withPos $ do
                      else if isref
                           then E (ERef (p2,p3) val)
                           else val
-       return $ Atom nopos rname del diff $ maybe val' (\b -> E $ EBinding (p1, p2) b val') binding
+       return $ Atom nopos rname del diff (maybe val' (\b -> E $ EBinding (p1, p2) b val') binding) Nothing
 
 delay = withPos $ Delay nopos <$> (option 0 $ reservedOp "-" *> delay32)
 
@@ -156,7 +156,7 @@ ruleTypeMapM fun rule@Rule{..} = do
                     return $ lhs { lhsAtom = lhsAtom { atomVal = ea }
                                  , lhsLocation = el}) ruleLHS
     rhs <- mapM (\rhs -> case rhs of
-                  RHSLiteral pol (Atom p r del diff v) -> (RHSLiteral pol . Atom p r del diff) <$> exprTypeMapM fun v
+                  RHSLiteral pol a@Atom{atomVal = v}   -> (\v' -> RHSLiteral pol a{atomVal = v'}) <$> exprTypeMapM fun v
                   RHSCondition c                       -> RHSCondition <$> exprTypeMapM fun c
                   RHSGroupBy v p g                     -> RHSGroupBy v <$> exprTypeMapM fun p <*> exprTypeMapM fun g
                   RHSFlatMap vs e                      -> RHSFlatMap <$> exprTypeMapM fun vs <*> exprTypeMapM fun e
@@ -535,14 +535,15 @@ data Relation = Relation { relPos        :: Pos
                          , relName       :: String
                          , relType       :: Type
                          , relPrimaryKey :: Maybe KeyExpr
+                         , relAttrs      :: [Attribute]
                          }
 
 instance Eq Relation where
-    (==) (Relation _ r1 m1 n1 t1 k1) (Relation _ r2 m2 n2 t2 k2) = (r1, m1, n1, t1, k1) == (r2, m2, n2, t2, k2)
+    (==) (Relation _ r1 m1 n1 t1 k1 a1) (Relation _ r2 m2 n2 t2 k2 a2) = (r1, m1, n1, t1, k1, a1) == (r2, m2, n2, t2, k2, a2)
 
 instance Ord Relation where
-    compare (Relation _ r1 m1 n1 t1 k1) (Relation _ r2 m2 n2 t2 k2) =
-        compare (r1, m1, n1, t1, k1) (r2, m2, n2, t2, k2)
+    compare (Relation _ r1 m1 n1 t1 k1 a1) (Relation _ r2 m2 n2 t2 k2 a2) =
+        compare (r1, m1, n1, t1, k1, a1) (r2, m2, n2, t2, k2, a2)
 
 instance WithPos Relation where
     pos = relPos
@@ -553,8 +554,9 @@ instance WithName Relation where
     setName r n = r{relName = n}
 
 instance PP Relation where
-    pp Relation{..} = pp relRole <+>
-                      pp relSemantics <+> pp relName <+> "[" <> pp relType <> "]" <+> pkey
+    pp Relation{..} = ppAttributes relAttrs $$
+                      (pp relRole <+>
+                       pp relSemantics <+> pp relName <+> "[" <> pp relType <> "]" <+> pkey)
         where pkey = maybe empty (("primary key" <+>) . pp) relPrimaryKey
 
 instance Show Relation where
@@ -616,20 +618,25 @@ data Atom = Atom { atomPos      :: Pos
                  , atomDelay    :: Delay
                  , atomDiff     :: Bool
                  , atomVal      :: Expr
+                   -- 'var <atomCountOf> = count_of(R[v])' binds the multiplicity
+                   -- of 'v' in 'R' to a variable.  Only occurs in the RHS of a
+                   -- rule; desugared into an auxiliary relation during validation.
+                 , atomCountOf  :: Maybe String
                  }
 
 instance Eq Atom where
-    (==) (Atom _ r1 d1 df1 v1) (Atom _ r2 d2 df2 v2) = (r1, d1, df1, v1) == (r2, d2, df2, v2)
+    (==) (Atom _ r1 d1 df1 v1 c1) (Atom _ r2 d2 df2 v2 c2) = (r1, d1, df1, v1, c1) == (r2, d2, df2, v2, c2)
 
 instance Ord Atom where
-    compare (Atom _ r1 d1 df1 v1) (Atom _ r2 d2 df2 v2) = compare (r1, d1, df1, v1) (r2, d2, df2, v2)
+    compare (Atom _ r1 d1 df1 v1 c1) (Atom _ r2 d2 df2 v2 c2) = compare (r1, d1, df1, v1, c1) (r2, d2, df2, v2, c2)
 
 instance WithPos Atom where
     pos = atomPos
     atPos a p = a{atomPos = p}
 
 instance PP Atom where
-    pp (Atom _ rel delay diff (E (EStruct _ cons as))) | rel == cons
+    pp a@Atom{atomCountOf = Just v} = "var" <+> pp v <+> "=" <+> "count_of" <> (parens $ pp a{atomCountOf = Nothing})
+    pp (Atom _ rel delay diff (E (EStruct _ cons as)) _) | rel == cons
                 = pp rel <> pp delay <> (if diff then "'" else empty) <>
                   (parens $ commaSep $
                             map (\(n,e) -> (if null (name n) then empty else ("." <> pp n <> "=")) <> pp e) as)
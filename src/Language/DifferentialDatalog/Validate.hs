@@ -28,7 +28,9 @@ module Language.DifferentialDatalog.Validate (
     typeValidate) where
 
 import qualified Data.Map as M
+import qualified Data.Set as S
 import Control.Monad.Except
+import Control.Monad.State
 import Data.Maybe
 import Data.List
 import Data.Char
@@ -40,6 +42,7 @@ import Language.DifferentialDatalog.NS
 import Language.DifferentialDatalog.Util
 import Language.DifferentialDatalog.Pos
 import Language.DifferentialDatalog.Name
+import Language.DifferentialDatalog.Module
 import {-# SOURCE #-} Language.DifferentialDatalog.Type
 import Language.DifferentialDatalog.TypeInference
 import Language.DifferentialDatalog.ECtx
@@ -56,7 +59,9 @@ sINGLETON_RELATION = "ddlog_std::Singleton"
 
 -- | Validate Datalog program
 validate :: (MonadError String me, ?cfg::Config) => DatalogProgram -> me DatalogProgram
-validate d = do
+validate d0 = do
+    -- Replace 'Vec' and 'Set' with their small versions where requested.
+    d <- progApplySmallAttrs d0
     uniqNames (Just d) ("Multiple definitions of constructor " ++)
               $ progConstructors d
     -- Validate typedef's
@@ -69,8 +74,10 @@ validate d = do
     checkAcyclicTypeAliases d
     -- 2. Cycles only via dynamically allocated fields.
     checkAcyclicTypes d
+    -- Expand 'count_of' literals into auxiliary relations.
+    d_count <- progExpandCountOf d
     -- Desugar.  Must be called after typeValidate.
-    d' <- progDesugar d
+    d' <- progDesugar d_count
     -- Validate function prototypes
     mapM_ (funcValidateProto d') $ M.elems $ progFunctions d'
     -- Validate function implementations
@@ -97,6 +104,57 @@ validate d = do
     progValidateAttributes d''
     return d''
 
+-- For each relation 'R' that occurs in a 'var n = count_of(R[v])' literal,
+-- add an auxiliary relation 'R__count_of' that stores the multiplicity of each
+-- fact in 'R', computed by a 'group_by' over the fact itself:
+-- 'R__count_of[(x, n)] :- R[x], var n = x.group_by(x).group_weight().'
+-- and replace the literal with 'R__count_of[(v, n)]'.  Unlike other
+-- auxiliary relations, this one is added before validation, so its name
+-- must be a valid relation identifier.
+progExpandCountOf :: (MonadError String me) => DatalogProgram -> me DatalogProgram
+progExpandCountOf d = do
+    (d', counted) <- runStateT (progAtomMapM d expandAtom) S.empty
+    return $ foldl' addCountRel d' $ S.toList counted
+    where
+    mk_count_rel_name :: String -> String
+    mk_count_rel_name rname = scoped (nameScope rname) $ nameLocalStr rname ++ "__count_of"
+
+    expandAtom a@Atom{atomCountOf = Nothing} = return a
+    expandAtom a@Atom{atomCountOf = Just v, ..} = do
+        let rel = getRelation d atomRelation
+        lift $ check d (not $ relIsStream rel) (pos a)
+             $ "'count_of' cannot be applied to a stream (relation '" ++ name rel ++ "' is declared as a stream at " ++ show (pos rel) ++ ")."
+        lift $ check d (not $ atomIsDelayed a) (pos a)
+             $ "'count_of' cannot be applied to a delayed relation."
+        lift $ check d (not atomDiff) (pos a)
+             $ "'count_of' cannot be applied to a differentiated relation."
+        modify $ S.insert atomRelation
+        return a{ atomRelation = mk_count_rel_name atomRelation
+                , atomVal      = E $ ETuple atomPos [atomVal, E $ EVar atomPos v]
+                , atomCountOf  = Nothing }
+
+    addCountRel d_ rname =
+        let rel = getRelation d rname
+            count_rel = Relation {
+                relPos          = nopos,
+                relRole         = RelInternal,
+                relSemantics    = RelSet,
+                relName         = mk_count_rel_name rname,
+                relType         = tTuple [relType rel, tUser wEIGHT_TYPE []],
+                relPrimaryKey   = Nothing,
+                relAttrs        = []
+            }
+            count_rule = Rule {
+                rulePos     = nopos,
+                ruleModule  = nameScope rel,
+                ruleLHS     = [RuleLHS nopos (Atom nopos (name count_rel) delayZero False (eTuple [eVar "x", eVar "n"]) Nothing) Nothing],
+                ruleRHS     = [ RHSLiteral True $ Atom nopos rname delayZero False (eVar "x") Nothing
+                              , RHSGroupBy "__group" (eVar "x") (eVar "x")
+                              , RHSCondition $ eSet (E $ EVarDecl nopos "n")
+                                                    (eApplyFunc (mOD_STD ++ "::group_weight") [eVar "__group"])]
+            }
+        in progAddRules [count_rule] $ progAddRel count_rel d_
+
 -- Remove syntactic sugar
 progDesugar :: (MonadError String me) => DatalogProgram -> me DatalogProgram
 progDesugar d = progExprMapCtxM d (exprDesugar d)
@@ -311,7 +369,7 @@ ruleValidate d rl = do
     -- singleton literal in the RHS of rules that don't start with a positive
     -- literal.
     let singleton_literal = RHSLiteral True
-                            $ Atom nopos sINGLETON_RELATION delayZero False (E $ EStruct nopos sINGLETON_RELATION [])
+                            $ Atom nopos sINGLETON_RELATION delayZero False (E $ EStruct nopos sINGLETON_RELATION []) Nothing
     let rl' = if (not $ null $ ruleRHS rl)
               then case head $ ruleRHS rl of
                         RHSLiteral True _ -> rl
@@ -161,7 +161,9 @@ ovsdb() {
 }
 
 main_crate() {
-    (cd "${THIS_DIR}/rust/template" && cargo test --features command-line,ovsdb,c_api)
+    (cd "${THIS_DIR}/rust/template" && cargo test --features command-line,ovsdb,c_api) &&
+    # Encoders and decoders of the adapters.
+    (cd "${THIS_DIR}/rust/template" && cargo test --lib --features postgresql,snapshots,kubernetes,otlp,flows,bgp,packet_capture,security_logs,sigma,alerts)
 }
 
 # 'basic' test group.
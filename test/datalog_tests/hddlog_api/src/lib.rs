@@ -0,0 +1,2 @@
+//! Tests of the Rust API of the crate generated for `hddlog_api.dl` live in
+//! `tests/`.
@@ -0,0 +1,131 @@
+//! Exporting and importing snapshots of a program (`HDDlog::export()`,
+//! `HDDlog::import()`).
+
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::api::{ArchiveFormat, HDDlog};
+use hddlog_api_ddlog::ddlog_testing::{self, assert_relation, transaction};
+
+fn populated() -> HDDlog {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(
+        &hddlog,
+        r#"insert Item(1, "one"), insert Item(2, "two"), insert Item(3, "three");"#,
+    )
+    .unwrap();
+    hddlog
+}
+
+fn roundtrip(file_name: &str, format: ArchiveFormat) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(file_name);
+
+    let source = populated();
+    source.export(&path, format, true).unwrap();
+    source.stop().unwrap();
+
+    let target = ddlog_testing::start(1).unwrap();
+    transaction(&target, r#"insert Item(4, "four");"#).unwrap();
+    target.import(&path, format).unwrap();
+    // Importing replaces the contents of the archived input relations.
+    assert_relation(
+        &target,
+        "ItemName",
+        &[
+            r#"ItemName(1, "one")"#,
+            r#"ItemName(2, "two")"#,
+            r#"ItemName(3, "three")"#,
+        ],
+    );
+    target.stop().unwrap();
+}
+
+#[test]
+fn json_roundtrip() {
+    roundtrip("snapshot.json", ArchiveFormat::Json);
+}
+
+#[test]
+fn binary_roundtrip() {
+    roundtrip("snapshot.bin", ArchiveFormat::Binary);
+}
+
+#[test]
+fn archive_contents() {
+    let hddlog = populated();
+    let archive = hddlog.archive(true).unwrap();
+    let item = archive.relations.iter().find(|r| r.name == "Item").unwrap();
+    assert!(item.input);
+    assert_eq!(item.facts.len(), 3);
+    let item_name = archive
+        .relations
+        .iter()
+        .find(|r| r.name == "ItemName")
+        .unwrap();
+    assert!(!item_name.input);
+    assert_eq!(item_name.facts.len(), 3);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn outputs_require_do_store() {
+    let (hddlog, _) = HDDlog::run(1, false).unwrap();
+    assert!(hddlog.archive(false).is_ok());
+    assert!(hddlog
+        .archive(true)
+        .unwrap_err()
+        .contains("do_store is disabled"));
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn rejects_other_versions() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("snapshot.json");
+    let hddlog = populated();
+    let mut archive = hddlog.archive(false).unwrap();
+    archive.version += 1;
+    archive.write(&path, ArchiveFormat::Json).unwrap();
+    assert!(hddlog
+        .import(&path, ArchiveFormat::Json)
+        .unwrap_err()
+        .contains("unsupported archive version"));
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn rejects_mismatched_archives() {
+    let hddlog = populated();
+
+    let mut unknown = hddlog.archive(false).unwrap();
+    unknown.relations[0].name = "NoSuchRelation".to_string();
+    assert!(hddlog
+        .restore(&unknown)
+        .unwrap_err()
+        .contains("unknown relation NoSuchRelation"));
+
+    let mut retyped = hddlog.archive(false).unwrap();
+    retyped.relations[0].type_name = "OtherType".to_string();
+    assert!(hddlog.restore(&retyped).unwrap_err().contains("has type"));
+
+    // Failed restores leave the program alone.
+    assert_eq!(
+        ddlog_testing::relation_contents(&hddlog, "ItemName")
+            .unwrap()
+            .len(),
+        3
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn rejects_garbage() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("snapshot.json");
+    std::fs::write(&path, "not an archive").unwrap();
+    let hddlog = ddlog_testing::start(1).unwrap();
+    assert!(hddlog
+        .import(&path, ArchiveFormat::Json)
+        .unwrap_err()
+        .contains("failed to read archive"));
+    hddlog.stop().unwrap();
+}
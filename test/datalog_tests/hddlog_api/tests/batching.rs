@@ -0,0 +1,125 @@
+//! Commits deferred under a latency budget (`Config::commit_latency_budget`)
+//! together with time travel and rules that panic.
+
+use std::time::Duration;
+
+use differential_datalog::program::config::Config;
+use differential_datalog::program::IdxId;
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::api::HDDlog;
+use hddlog_api_ddlog::ddlog_testing::{assert_relation, transaction};
+use hddlog_api_ddlog::Indexes;
+
+const INDEX: IdxId = Indexes::ItemNameById as IdxId;
+
+/// Start the program with a latency budget of `budget`.  With a zero
+/// budget, every other commit is deferred: a commit is deferred if no
+/// commit is, and flushes the deferred ones otherwise.
+fn start(budget: Duration) -> HDDlog {
+    let config = Config {
+        commit_latency_budget: Some(budget),
+        ..Default::default()
+    };
+    HDDlog::run_with_config(config, true).unwrap().0
+}
+
+fn dump_asof(hddlog: &HDDlog, commit: u64) -> Vec<String> {
+    hddlog
+        .dump_index_asof(INDEX, commit)
+        .unwrap()
+        .iter()
+        .map(|v| v.to_string())
+        .collect()
+}
+
+#[test]
+fn deferred_commits_share_their_epoch() {
+    let hddlog = start(Duration::from_secs(0));
+    hddlog.set_index_time_travel(INDEX, 10).unwrap();
+
+    // Deferred commits are numbered right away.
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    assert_eq!(hddlog.commit_number(), 1);
+    assert_relation(&hddlog, "ItemName", &[]);
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    assert_eq!(hddlog.commit_number(), 2);
+    assert_relation(
+        &hddlog,
+        "ItemName",
+        &[r#"ItemName(1, "one")"#, r#"ItemName(2, "two")"#],
+    );
+
+    // Commits propagated together cannot be told apart.
+    let both = vec![
+        r#"ItemName{.id = 1, .name = "one"}"#.to_string(),
+        r#"ItemName{.id = 2, .name = "two"}"#.to_string(),
+    ];
+    assert_eq!(dump_asof(&hddlog, 1), both);
+    assert_eq!(dump_asof(&hddlog, 2), both);
+
+    // A query as of a commit that has not been propagated flushes it.
+    transaction(&hddlog, r#"delete Item(1, "one");"#).unwrap();
+    assert_eq!(hddlog.commit_number(), 3);
+    assert_eq!(
+        dump_asof(&hddlog, 3),
+        vec![r#"ItemName{.id = 2, .name = "two"}"#.to_string()]
+    );
+    assert_eq!(dump_asof(&hddlog, 2), both);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn panics_fail_the_flushing_commit() {
+    let hddlog = start(Duration::from_secs(0));
+    hddlog.set_index_time_travel(INDEX, 10).unwrap();
+
+    // The panic is raised when the deferred commit is propagated, by the
+    // next commit, which fails.  The deferred commit stands.
+    transaction(&hddlog, "insert Divisor(0);").unwrap();
+    assert!(transaction(&hddlog, r#"insert Item(1, "one");"#)
+        .unwrap_err()
+        .contains("panic while evaluating"));
+    hddlog.transaction_rollback().unwrap();
+    assert_eq!(hddlog.commit_number(), 1);
+    assert_relation(&hddlog, "ItemName", &[]);
+    assert_relation(&hddlog, "Quotient", &[]);
+
+    // The state as of the deferred commit does not include the changes of
+    // the failed commit.
+    assert!(dump_asof(&hddlog, 1).is_empty());
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    transaction(&hddlog, r#"insert Item(3, "three");"#).unwrap();
+    assert_eq!(hddlog.commit_number(), 3);
+    assert!(dump_asof(&hddlog, 1).is_empty());
+    assert_eq!(dump_asof(&hddlog, 2).len(), 2);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn panics_fail_the_flushing_query() {
+    let hddlog = start(Duration::from_secs(3600));
+    hddlog.set_index_time_travel(INDEX, 10).unwrap();
+
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    transaction(&hddlog, "insert Divisor(0);").unwrap();
+    assert!(hddlog
+        .dump_index_asof(INDEX, 1)
+        .unwrap_err()
+        .contains("panic while evaluating"));
+
+    // The query consumed the panic: later commits succeed.
+    assert_eq!(
+        dump_asof(&hddlog, 1),
+        vec![r#"ItemName{.id = 1, .name = "one"}"#.to_string()]
+    );
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    hddlog.flush_deferred().unwrap();
+    assert_eq!(hddlog.commit_number(), 3);
+    assert_relation(
+        &hddlog,
+        "ItemName",
+        &[r#"ItemName(1, "one")"#, r#"ItemName(2, "two")"#],
+    );
+    assert_relation(&hddlog, "Quotient", &[]);
+    hddlog.stop().unwrap();
+}
@@ -0,0 +1,92 @@
+//! Changelogs of output relations (`HDDlog::subscribe_changelog()`).
+
+use std::sync::{Arc, Mutex};
+
+use differential_datalog::program::RelId;
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::api::HDDlog;
+use hddlog_api_ddlog::ddlog_testing::{self, transaction};
+use hddlog_api_ddlog::update_handler::ChangelogBatch;
+use hddlog_api_ddlog::Relations;
+
+/// Subscribe to the changelog of `ItemName` and collect the batches it
+/// delivers.
+fn subscribe(hddlog: &HDDlog) -> Arc<Mutex<Vec<ChangelogBatch>>> {
+    let batches = Arc::new(Mutex::new(Vec::new()));
+    let batches2 = batches.clone();
+    hddlog
+        .subscribe_changelog(
+            Relations::ItemName as RelId,
+            Arc::new(move |batch: &ChangelogBatch| batches2.lock().unwrap().push(batch.clone())),
+        )
+        .unwrap();
+    batches
+}
+
+fn changes(batch: &ChangelogBatch) -> Vec<(String, i64)> {
+    let mut changes: Vec<(String, i64)> = batch
+        .changes
+        .iter()
+        .map(|(v, w)| (v.to_string(), *w as i64))
+        .collect();
+    changes.sort();
+    changes
+}
+
+#[test]
+fn batches_per_commit() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let batches = subscribe(&hddlog);
+
+    transaction(&hddlog, r#"insert Item(1, "one"), insert Item(2, "two");"#).unwrap();
+    // Commits that do not change the relation produce no batch and do not
+    // advance the commit number.
+    transaction(&hddlog, "").unwrap();
+    transaction(&hddlog, r#"delete Item(1, "one");"#).unwrap();
+
+    let batches = batches.lock().unwrap();
+    assert_eq!(batches.len(), 2);
+    assert_eq!(batches[0].commit, 1);
+    assert_eq!(batches[1].commit, 2);
+    assert!(batches[0].timestamp <= batches[1].timestamp);
+    assert!(batches
+        .iter()
+        .all(|b| b.relid == Relations::ItemName as RelId));
+    assert_eq!(
+        changes(&batches[0]),
+        vec![
+            (r#"ItemName{.id = 1, .name = "one"}"#.to_string(), 1),
+            (r#"ItemName{.id = 2, .name = "two"}"#.to_string(), 1),
+        ]
+    );
+    assert_eq!(
+        changes(&batches[1]),
+        vec![(r#"ItemName{.id = 1, .name = "one"}"#.to_string(), -1)]
+    );
+    drop(batches);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn unsubscribe() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let batches = subscribe(&hddlog);
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    hddlog.unsubscribe_changelog(Relations::ItemName as RelId);
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    assert_eq!(batches.lock().unwrap().len(), 1);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn only_output_relations() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let err = hddlog
+        .subscribe_changelog(Relations::Item as RelId, Arc::new(|_: &ChangelogBatch| ()))
+        .unwrap_err();
+    assert!(err.contains("is not an output relation"));
+    assert!(hddlog
+        .subscribe_changelog(12345, Arc::new(|_: &ChangelogBatch| ()))
+        .is_err());
+    hddlog.stop().unwrap();
+}
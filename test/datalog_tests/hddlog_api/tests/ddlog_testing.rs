@@ -0,0 +1,86 @@
+//! The test helpers in `ddlog_testing`.
+
+use differential_datalog::ddval::DDValConvert;
+use differential_datalog::program::Update;
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::ddlog_testing::{self, assert_relation, transaction};
+use hddlog_api_ddlog::typedefs::Item;
+use hddlog_api_ddlog::Relations;
+
+#[test]
+fn transactions_and_assertions() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one"), insert Item(2, "two");"#).unwrap();
+    transaction(&hddlog, r#"delete Item(1, "one");"#).unwrap();
+    assert_relation(&hddlog, "ItemName", &[r#"ItemName(2, "two")"#]);
+    assert_eq!(
+        ddlog_testing::relation_contents(&hddlog, "ItemName").unwrap(),
+        vec![r#"ItemName{.id = 2, .name = "two"}"#.to_string()]
+    );
+    assert!(ddlog_testing::dump_outputs(&hddlog)
+        .unwrap()
+        .contains(r#"ItemName{.id = 2, .name = "two"}"#));
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn failed_transactions_are_rolled_back() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    // Duplicate key.
+    assert!(transaction(&hddlog, r#"insert Item(2, "two"), insert Item(1, "uno");"#).is_err());
+    assert!(transaction(&hddlog, r#"insert NoSuchRelation(1);"#).is_err());
+    assert_relation(&hddlog, "ItemName", &[r#"ItemName(1, "one")"#]);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+#[should_panic(expected = "unexpected contents of relation ItemName")]
+fn assert_relation_reports_differences() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    assert_relation(&hddlog, "ItemName", &[r#"ItemName(1, "uno")"#]);
+}
+
+#[test]
+fn rejects_unknown_relations() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    assert!(ddlog_testing::relation_contents(&hddlog, "NoSuchRelation").is_err());
+    // Input relations cannot be checked.
+    assert!(ddlog_testing::relation_contents(&hddlog, "Item").is_err());
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn incremental_outputs_are_consistent() {
+    let relid = Relations::Item as usize;
+    let item = |id: u32, name: &str| {
+        Item {
+            id,
+            name: name.to_string(),
+        }
+        .into_ddvalue()
+    };
+    let transactions = vec![
+        vec![
+            Update::Insert {
+                relid,
+                v: item(1, "one"),
+            },
+            Update::Insert {
+                relid,
+                v: item(2, "two"),
+            },
+        ],
+        vec![Update::DeleteValue {
+            relid,
+            v: item(1, "one"),
+        }],
+        // Fails because of the duplicate key and is skipped.
+        vec![Update::Insert {
+            relid,
+            v: item(2, "deux"),
+        }],
+    ];
+    ddlog_testing::check_consistency(2, &transactions).unwrap();
+}
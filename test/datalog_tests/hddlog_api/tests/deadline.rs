@@ -0,0 +1,140 @@
+//! Commits with a deadline (`HDDlog::transaction_commit_with_deadline()`).
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use differential_datalog::program::RelId;
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::api::HDDlog;
+use hddlog_api_ddlog::ddlog_testing::{self, assert_relation, parse_updates, transaction};
+use hddlog_api_ddlog::update_handler::ChangelogBatch;
+use hddlog_api_ddlog::Relations;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Enough steps to keep the workers busy well past a zero budget.
+const MANY_STEPS: u32 = 20000;
+
+/// Subscribe to the changelog of `ItemName` and collect the changes it
+/// delivers, one vector per batch.
+fn subscribe(hddlog: &HDDlog) -> Arc<Mutex<Vec<Vec<(String, i64)>>>> {
+    let batches = Arc::new(Mutex::new(Vec::new()));
+    let batches2 = batches.clone();
+    hddlog
+        .subscribe_changelog(
+            Relations::ItemName as RelId,
+            Arc::new(move |batch: &ChangelogBatch| {
+                let mut changes: Vec<(String, i64)> = batch
+                    .changes
+                    .iter()
+                    .map(|(v, w)| (v.to_string(), *w as i64))
+                    .collect();
+                changes.sort();
+                batches2.lock().unwrap().push(changes);
+            }),
+        )
+        .unwrap();
+    batches
+}
+
+fn start_transaction(hddlog: &HDDlog, updates: &str) {
+    hddlog.transaction_start().unwrap();
+    hddlog
+        .apply_updates_dynamic(&mut parse_updates(updates).unwrap().into_iter())
+        .unwrap();
+}
+
+/// The contents of `ItemName` according to the workers.
+fn stored_names(hddlog: &HDDlog) -> usize {
+    hddlog.dump_all(&[Relations::ItemName as RelId]).unwrap()[&(Relations::ItemName as RelId)].len()
+}
+
+#[test]
+fn meets_deadline() {
+    let hddlog = ddlog_testing::start(2).unwrap();
+    let batches = subscribe(&hddlog);
+
+    start_transaction(&hddlog, r#"insert Item(1, "one"), insert Steps(10);"#);
+    hddlog.transaction_commit_with_deadline(TIMEOUT).unwrap();
+    assert_eq!(
+        *batches.lock().unwrap(),
+        vec![vec![(r#"ItemName{.id = 1, .name = "one"}"#.to_string(), 1)]]
+    );
+    assert_relation(&hddlog, "ItemName", &[r#"ItemName(1, "one")"#]);
+    assert_relation(&hddlog, "StepCount", &["StepCount(11)"]);
+
+    // Commits without changes to propagate take the regular path.
+    hddlog.transaction_start().unwrap();
+    hddlog.transaction_commit_with_deadline(TIMEOUT).unwrap();
+    assert_eq!(batches.lock().unwrap().len(), 1);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn misses_deadline() {
+    let hddlog = ddlog_testing::start(2).unwrap();
+    let batches = subscribe(&hddlog);
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+
+    start_transaction(
+        &hddlog,
+        &format!(
+            r#"insert Item(2, "two"), delete Item(1, "one"), insert Steps({});"#,
+            MANY_STEPS
+        ),
+    );
+    let err = hddlog
+        .transaction_commit_with_deadline(Duration::from_secs(0))
+        .unwrap_err();
+    assert!(err.contains("exceeded the budget"), "{}", err);
+    assert!(err.contains("has been rolled back"), "{}", err);
+
+    // The transaction is over; the next one waits for the workers to undo
+    // it.  Neither the changes of the rolled back transaction nor their
+    // retraction reach the callbacks.
+    transaction(&hddlog, r#"insert Item(3, "three");"#).unwrap();
+    assert_eq!(
+        *batches.lock().unwrap(),
+        vec![
+            vec![(r#"ItemName{.id = 1, .name = "one"}"#.to_string(), 1)],
+            vec![(r#"ItemName{.id = 3, .name = "three"}"#.to_string(), 1)],
+        ]
+    );
+    assert_relation(
+        &hddlog,
+        "ItemName",
+        &[r#"ItemName(1, "one")"#, r#"ItemName(3, "three")"#],
+    );
+    assert_relation(&hddlog, "StepCount", &[]);
+    assert_eq!(stored_names(&hddlog), 2);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn consecutive_misses() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let batches = subscribe(&hddlog);
+
+    // Deadline commits made while the workers are still undoing the last
+    // one count the wait against their budget.
+    for id in 1..=2 {
+        start_transaction(
+            &hddlog,
+            &format!(
+                r#"insert Item({}, "item"), insert Steps({});"#,
+                id, MANY_STEPS
+            ),
+        );
+        assert!(hddlog
+            .transaction_commit_with_deadline(Duration::from_secs(0))
+            .unwrap_err()
+            .contains("has been rolled back"));
+    }
+    start_transaction(&hddlog, r#"insert Item(3, "three");"#);
+    hddlog.transaction_commit_with_deadline(TIMEOUT).unwrap();
+
+    assert_eq!(batches.lock().unwrap().len(), 1);
+    assert_relation(&hddlog, "ItemName", &[r#"ItemName(3, "three")"#]);
+    assert_eq!(stored_names(&hddlog), 1);
+    hddlog.stop().unwrap();
+}
@@ -0,0 +1,207 @@
+//! Delivery of changelogs through bounded queues
+//! (`HDDlog::subscribe_changelog_queued()`,
+//! `HDDlog::subscribe_commits_queued()`).
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use differential_datalog::program::RelId;
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::api::HDDlog;
+use hddlog_api_ddlog::ddlog_testing::{self, transaction};
+use hddlog_api_ddlog::update_handler::{
+    ChangelogBatch, ChangelogQueueStats, DeliveryConfig, OverflowPolicy,
+};
+use hddlog_api_ddlog::Relations;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Subscriber that reports each batch it receives, then waits until the
+/// test lets it return.
+struct Gate {
+    received: Receiver<ChangelogBatch>,
+    release: Sender<()>,
+}
+
+fn subscribe(
+    hddlog: &HDDlog,
+    overflow: OverflowPolicy,
+) -> Result<(Gate, ChangelogQueueStats), String> {
+    let (received_tx, received) = mpsc::channel();
+    let (release, release_rx) = mpsc::channel::<()>();
+    let channels = Mutex::new((received_tx, release_rx));
+    let stats = hddlog.subscribe_changelog_queued(
+        Relations::ItemName as RelId,
+        Arc::new(move |batch: &ChangelogBatch| {
+            let channels = channels.lock().unwrap();
+            channels.0.send(batch.clone()).unwrap();
+            channels.1.recv_timeout(TIMEOUT).unwrap();
+        }),
+        DeliveryConfig {
+            capacity: 1,
+            overflow,
+        },
+    )?;
+    Ok((Gate { received, release }, stats))
+}
+
+impl Gate {
+    fn next(&self) -> ChangelogBatch {
+        self.received.recv_timeout(TIMEOUT).unwrap()
+    }
+
+    fn open(&self) {
+        self.release.send(()).unwrap();
+    }
+}
+
+fn insert(hddlog: &HDDlog, id: u32) {
+    transaction(hddlog, &format!(r#"insert Item({}, "item{}");"#, id, id)).unwrap();
+}
+
+/// Commit three transactions while the subscriber is busy with the first
+/// one, which fills the queue.  Returns the first batch.
+fn fill_queue(hddlog: &HDDlog, gate: &Gate) -> ChangelogBatch {
+    insert(hddlog, 1);
+    let first = gate.next();
+    insert(hddlog, 2);
+    insert(hddlog, 3);
+    first
+}
+
+#[test]
+fn zero_capacity() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let err = hddlog
+        .subscribe_changelog_queued(
+            Relations::ItemName as RelId,
+            Arc::new(|_: &ChangelogBatch| ()),
+            DeliveryConfig {
+                capacity: 0,
+                overflow: OverflowPolicy::Block,
+            },
+        )
+        .unwrap_err();
+    assert!(err.contains("capacity must be positive"));
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn block_when_full() {
+    let hddlog = Arc::new(ddlog_testing::start(1).unwrap());
+    let (gate, stats) = subscribe(&hddlog, OverflowPolicy::Block).unwrap();
+
+    insert(&hddlog, 1);
+    assert_eq!(gate.next().commit, 1);
+    insert(&hddlog, 2);
+    // The queue is full: the next commit waits for the subscriber.
+    let hddlog2 = hddlog.clone();
+    let blocked = thread::spawn(move || insert(&hddlog2, 3));
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(stats.queued(), 2);
+
+    for commit in 2..=3 {
+        gate.open();
+        assert_eq!(gate.next().commit, commit);
+    }
+    gate.open();
+    blocked.join().unwrap();
+    stats.wait_delivered(3, Some(TIMEOUT)).unwrap();
+    assert_eq!(stats.dropped(), 0);
+    assert_eq!(stats.coalesced(), 0);
+    assert_eq!(stats.pending(), 0);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn drop_when_full() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let (gate, stats) = subscribe(&hddlog, OverflowPolicy::Drop).unwrap();
+
+    assert_eq!(fill_queue(&hddlog, &gate).commit, 1);
+    assert_eq!(stats.dropped(), 1);
+    gate.open();
+    let second = gate.next();
+    assert_eq!(second.commit, 2);
+    gate.open();
+    stats.wait_delivered(2, Some(TIMEOUT)).unwrap();
+    assert_eq!(stats.delivered(), 2);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn coalesce_when_full() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let (gate, stats) = subscribe(&hddlog, OverflowPolicy::Coalesce).unwrap();
+
+    fill_queue(&hddlog, &gate);
+    assert_eq!(stats.coalesced(), 1);
+    gate.open();
+    // The second batch covers the second and third commits.
+    let merged = gate.next();
+    assert_eq!(merged.commit, 3);
+    assert_eq!(merged.changes.len(), 2);
+    gate.open();
+    stats.wait_delivered(2, Some(TIMEOUT)).unwrap();
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn panicking_subscriber() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let stats = hddlog
+        .subscribe_changelog_queued(
+            Relations::ItemName as RelId,
+            Arc::new(|_: &ChangelogBatch| panic!("subscriber failure")),
+            DeliveryConfig {
+                capacity: 1,
+                overflow: OverflowPolicy::Block,
+            },
+        )
+        .unwrap();
+
+    insert(&hddlog, 1);
+    let err = stats.wait_delivered(1, Some(TIMEOUT)).unwrap_err();
+    assert!(err.contains("panicked"));
+    assert!(stats.error().is_some());
+    // Commits neither block on the full queue nor fail.
+    for id in 2..5 {
+        insert(&hddlog, id);
+    }
+    assert_eq!(stats.failed(), 3);
+    assert_eq!(stats.delivered(), 0);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn queued_commits() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let commits = Arc::new(Mutex::new(Vec::new()));
+    let commits2 = commits.clone();
+    let (_, stats) = hddlog
+        .subscribe_commits_queued(
+            &[Relations::ItemName as RelId],
+            Arc::new(move |batches: &[ChangelogBatch]| {
+                commits2.lock().unwrap().push(batches.to_vec())
+            }),
+            DeliveryConfig {
+                capacity: 4,
+                overflow: OverflowPolicy::Block,
+            },
+        )
+        .unwrap();
+
+    insert(&hddlog, 1);
+    insert(&hddlog, 2);
+    stats.wait_delivered(2, Some(TIMEOUT)).unwrap();
+    let commits = commits.lock().unwrap();
+    assert_eq!(commits.len(), 2);
+    assert!(commits
+        .iter()
+        .zip(1..)
+        .all(|(batches, commit)| batches.len() == 1 && batches[0].commit == commit));
+    drop(commits);
+    hddlog.stop().unwrap();
+}
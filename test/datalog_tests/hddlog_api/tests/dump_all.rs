@@ -0,0 +1,74 @@
+//! Snapshots of several output relations (`HDDlog::dump_all()`).
+
+use differential_datalog::program::RelId;
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::api::HDDlog;
+use hddlog_api_ddlog::ddlog_testing::{self, transaction};
+use hddlog_api_ddlog::Relations;
+
+#[test]
+fn dump_outputs() {
+    let hddlog = ddlog_testing::start(2).unwrap();
+    transaction(
+        &hddlog,
+        r#"insert Item(1, "one"), insert Item(2, "two"), insert Item(3, "three");"#,
+    )
+    .unwrap();
+    transaction(&hddlog, r#"delete Item(2, "two");"#).unwrap();
+
+    let snapshot = hddlog
+        .dump_all(&[Relations::ItemName as RelId, Relations::ItemCount as RelId])
+        .unwrap();
+    assert_eq!(snapshot.len(), 2);
+    let mut names: Vec<(String, i64)> = snapshot[&(Relations::ItemName as RelId)]
+        .iter()
+        .map(|(v, w)| (v.to_string(), *w as i64))
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            (r#"ItemName{.id = 1, .name = "one"}"#.to_string(), 1),
+            (r#"ItemName{.id = 3, .name = "three"}"#.to_string(), 1),
+        ]
+    );
+    let counts: Vec<(String, i64)> = snapshot[&(Relations::ItemCount as RelId)]
+        .iter()
+        .map(|(v, w)| (v.to_string(), *w as i64))
+        .collect();
+    assert_eq!(counts, vec![("ItemCount{.n = 2}".to_string(), 1)]);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn empty_relations() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let snapshot = hddlog.dump_all(&[Relations::ItemName as RelId]).unwrap();
+    assert!(snapshot[&(Relations::ItemName as RelId)].is_empty());
+    assert!(hddlog.dump_all(&[]).unwrap().is_empty());
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn rejects_input_and_unknown_relations() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    assert!(hddlog
+        .dump_all(&[Relations::ItemName as RelId, Relations::Item as RelId])
+        .unwrap_err()
+        .contains("unknown output relation"));
+    assert!(hddlog
+        .dump_all(&[1000])
+        .unwrap_err()
+        .contains("unknown output relation 1000"));
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn requires_do_store() {
+    let (hddlog, _) = HDDlog::run(1, false).unwrap();
+    assert!(hddlog
+        .dump_all(&[Relations::ItemName as RelId])
+        .unwrap_err()
+        .contains("do_store is disabled"));
+    hddlog.stop().unwrap();
+}
@@ -0,0 +1,147 @@
+//! Helpers for driving programs from notebooks (`notebook`).
+
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::notebook::{self, Table, Transaction, Transcript, TranscriptItem};
+
+/// The table titled `title` in `transcript`.
+fn table<'a>(transcript: &'a Transcript, title: &str) -> &'a Table {
+    transcript
+        .items
+        .iter()
+        .find_map(|item| match item {
+            TranscriptItem::Table(table) if table.title == title => Some(table),
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn relation_contents() {
+    let hddlog = notebook::start(1).unwrap();
+    notebook::run_script(
+        &hddlog,
+        r#"start; insert Item(1, "one"), insert Item(2, "two"); commit;"#,
+    )
+    .unwrap();
+    let table = notebook::relation(&hddlog, "ItemName").unwrap();
+    assert_eq!(table.title, "ItemName");
+    assert_eq!(table.rows.len(), 2);
+    assert!(table.rows.iter().all(|(_, w)| *w == 1));
+    assert!(notebook::relation(&hddlog, "Item")
+        .unwrap_err()
+        .contains("unknown output relation Item"));
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn scripts() {
+    let hddlog = notebook::start(1).unwrap();
+    let transcript = notebook::run_script(
+        &hddlog,
+        r#"
+        # Comments are ignored.
+        start;
+        insert Item(1, "one");
+        commit dump_changes;
+        echo done;
+        dump ItemName;
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        table(&transcript, "ItemName (changes)").rows,
+        vec![(r#"ItemName{.id = 1, .name = "one"}"#.to_string(), 1)]
+    );
+    assert!(transcript
+        .items
+        .contains(&TranscriptItem::Text("done".to_string())));
+    assert_eq!(table(&transcript, "ItemName").rows.len(), 1);
+    assert!(transcript.to_string().contains("done\n"));
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn script_errors() {
+    let hddlog = notebook::start(1).unwrap();
+    assert!(notebook::run_script(&hddlog, "timestamp;")
+        .unwrap_err()
+        .contains("command not supported in notebooks"));
+    assert!(notebook::run_script(&hddlog, "insert Item(1").is_err());
+    assert!(notebook::run_script(&hddlog, "insert NoSuchRelation(1);").is_err());
+
+    // A failing script leaves the transaction it started open.
+    assert!(notebook::run_script(&hddlog, r#"start; insert Item(1, "one"); bogus;"#).is_err());
+    hddlog.transaction_rollback().unwrap();
+    assert!(notebook::relation(&hddlog, "ItemName")
+        .unwrap()
+        .rows
+        .is_empty());
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn load_script() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("items.dat");
+    std::fs::write(&path, "start;\ninsert Item(1, \"one\");\ncommit;\n").unwrap();
+    let hddlog = notebook::start(1).unwrap();
+    notebook::load_script(&hddlog, &path).unwrap();
+    assert_eq!(
+        notebook::relation(&hddlog, "ItemName").unwrap().rows.len(),
+        1
+    );
+    assert!(notebook::load_script(&hddlog, dir.path().join("missing.dat")).is_err());
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn transactions() {
+    let hddlog = notebook::start(1).unwrap();
+
+    let mut txn = Transaction::start(&hddlog).unwrap();
+    txn.run(r#"insert Item(1, "one");"#).unwrap();
+    let transcript = txn.commit().unwrap();
+    assert_eq!(table(&transcript, "ItemName (changes)").rows.len(), 1);
+
+    // Dropping a transaction rolls it back, like leaving a Python context
+    // manager with an exception.
+    {
+        let mut txn = Transaction::start(&hddlog).unwrap();
+        txn.run(r#"insert Item(2, "two");"#).unwrap();
+    }
+    let mut txn = Transaction::start(&hddlog).unwrap();
+    txn.run(r#"insert Item(3, "three");"#).unwrap();
+    txn.rollback().unwrap();
+    assert_eq!(
+        notebook::relation(&hddlog, "ItemName").unwrap().rows.len(),
+        1
+    );
+
+    let mut txn = Transaction::start(&hddlog).unwrap();
+    assert!(txn.run("insert Item(").is_err());
+    drop(txn);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn rendering() {
+    let hddlog = notebook::start(1).unwrap();
+    notebook::run_script(
+        &hddlog,
+        r#"start; insert Item(1, "<b>"), insert Item(2, "two"), insert Item(3, "three"); commit;"#,
+    )
+    .unwrap();
+    let table = notebook::relation(&hddlog, "ItemName").unwrap();
+    let html = table.to_html();
+    assert!(html.contains("ItemName (3 rows)"));
+    assert!(html.contains("&quot;&lt;b&gt;&quot;"));
+    assert!(!html.contains("<b>"));
+
+    let limited = table.clone().limit(1);
+    assert!(limited.to_html().contains("... 2 more"));
+    let text = limited.to_string();
+    assert!(text.starts_with("ItemName:\n"));
+    assert!(text.contains(": +1\n"));
+    assert!(text.ends_with("... 2 more\n"));
+    hddlog.stop().unwrap();
+}
@@ -0,0 +1,89 @@
+//! Two-phase commits (`DDlogDynamic::transaction_prepare()`).
+
+use std::sync::{Arc, Mutex};
+
+use differential_datalog::program::RelId;
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::api::HDDlog;
+use hddlog_api_ddlog::ddlog_testing::{self, assert_relation, parse_updates, transaction};
+use hddlog_api_ddlog::update_handler::ChangelogBatch;
+use hddlog_api_ddlog::Relations;
+
+/// Subscribe to the changelog of `ItemName` and collect the changes it
+/// delivers, one vector per batch.
+fn subscribe(hddlog: &HDDlog) -> Arc<Mutex<Vec<Vec<(String, i64)>>>> {
+    let batches = Arc::new(Mutex::new(Vec::new()));
+    let batches2 = batches.clone();
+    hddlog
+        .subscribe_changelog(
+            Relations::ItemName as RelId,
+            Arc::new(move |batch: &ChangelogBatch| {
+                let mut changes: Vec<(String, i64)> = batch
+                    .changes
+                    .iter()
+                    .map(|(v, w)| (v.to_string(), *w as i64))
+                    .collect();
+                changes.sort();
+                batches2.lock().unwrap().push(changes);
+            }),
+        )
+        .unwrap();
+    batches
+}
+
+fn prepare(hddlog: &HDDlog, updates: &str) {
+    hddlog.transaction_start().unwrap();
+    hddlog
+        .apply_updates_dynamic(&mut parse_updates(updates).unwrap().into_iter())
+        .unwrap();
+    hddlog.transaction_prepare().unwrap();
+}
+
+#[test]
+fn commit_prepared() {
+    let hddlog = ddlog_testing::start(2).unwrap();
+    let batches = subscribe(&hddlog);
+
+    // Subscribers only see the changes of a prepared transaction once it
+    // is committed.
+    prepare(&hddlog, r#"insert Item(1, "one");"#);
+    assert!(batches.lock().unwrap().is_empty());
+    assert_relation(&hddlog, "ItemName", &[]);
+    assert_eq!(hddlog.commit_number(), 0);
+
+    hddlog.transaction_commit().unwrap();
+    assert_eq!(
+        *batches.lock().unwrap(),
+        vec![vec![(r#"ItemName{.id = 1, .name = "one"}"#.to_string(), 1)]]
+    );
+    assert_relation(&hddlog, "ItemName", &[r#"ItemName(1, "one")"#]);
+    assert_eq!(hddlog.commit_number(), 1);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn roll_back_prepared() {
+    let hddlog = ddlog_testing::start(2).unwrap();
+    let batches = subscribe(&hddlog);
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+
+    // Neither the changes of a rolled back transaction nor their retraction
+    // reach the subscribers.
+    prepare(&hddlog, r#"delete Item(1, "one"), insert Item(2, "two");"#);
+    hddlog.transaction_rollback().unwrap();
+    transaction(&hddlog, r#"insert Item(3, "three");"#).unwrap();
+    assert_eq!(
+        *batches.lock().unwrap(),
+        vec![
+            vec![(r#"ItemName{.id = 1, .name = "one"}"#.to_string(), 1)],
+            vec![(r#"ItemName{.id = 3, .name = "three"}"#.to_string(), 1)],
+        ]
+    );
+    assert_relation(
+        &hddlog,
+        "ItemName",
+        &[r#"ItemName(1, "one")"#, r#"ItemName(3, "three")"#],
+    );
+    assert_eq!(hddlog.commit_number(), 2);
+    hddlog.stop().unwrap();
+}
@@ -0,0 +1,63 @@
+//! Differential consistency self-check (`HDDlog::self_check()`).
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use differential_datalog::program::RelId;
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::api::{Divergence, HDDlog};
+use hddlog_api_ddlog::ddlog_testing::{self, transaction};
+use hddlog_api_ddlog::Relations;
+
+#[test]
+fn consistent_outputs() {
+    let hddlog = ddlog_testing::start(2).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one"), insert Item(2, "two");"#).unwrap();
+    transaction(
+        &hddlog,
+        r#"delete Item(1, "one"), insert Item(3, "three");"#,
+    )
+    .unwrap();
+    assert_eq!(hddlog.self_check(&[]).unwrap(), vec![]);
+    assert_eq!(
+        hddlog.self_check(&[Relations::ItemName as RelId]).unwrap(),
+        vec![]
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn invalid_checks() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    assert!(hddlog
+        .self_check(&[Relations::Item as RelId])
+        .unwrap_err()
+        .contains("unknown output relation"));
+    hddlog.stop().unwrap();
+
+    let (hddlog, _) = HDDlog::run(1, false).unwrap();
+    assert!(hddlog
+        .self_check(&[])
+        .unwrap_err()
+        .contains("requires do_store"));
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn periodic_checks_report_nothing_when_consistent() {
+    let hddlog = Arc::new(ddlog_testing::start(1).unwrap());
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    let reports = Arc::new(Mutex::new(0));
+    let reports2 = reports.clone();
+    let check = HDDlog::start_self_check(
+        &hddlog,
+        vec![],
+        Duration::from_millis(10),
+        Arc::new(move |_: Result<&[Divergence], &str>| *reports2.lock().unwrap() += 1),
+    )
+    .unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    drop(check);
+    assert_eq!(*reports.lock().unwrap(), 0);
+    hddlog.stop().unwrap();
+}
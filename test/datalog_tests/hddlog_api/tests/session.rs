@@ -0,0 +1,155 @@
+//! Read-your-writes sessions (`Session`,
+//! `HDDlog::transaction_commit_in_session()`).
+
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use differential_datalog::program::RelId;
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::api::{HDDlog, Session};
+use hddlog_api_ddlog::ddlog_testing::{self, parse_updates};
+use hddlog_api_ddlog::update_handler::{
+    ChangelogBatch, ChangelogCallback, DeliveryConfig, OverflowPolicy,
+};
+use hddlog_api_ddlog::Relations;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn config() -> DeliveryConfig {
+    DeliveryConfig {
+        capacity: 16,
+        overflow: OverflowPolicy::Block,
+    }
+}
+
+/// Subscriber that takes a while to process each batch, and the batches it
+/// has processed.
+fn slow_subscriber() -> (ChangelogCallback, Arc<Mutex<Vec<ChangelogBatch>>>) {
+    let batches = Arc::new(Mutex::new(Vec::new()));
+    let batches2 = batches.clone();
+    let cb: ChangelogCallback = Arc::new(move |batch: &ChangelogBatch| {
+        thread::sleep(Duration::from_millis(50));
+        batches2.lock().unwrap().push(batch.clone());
+    });
+    (cb, batches)
+}
+
+fn start_transaction(hddlog: &HDDlog, updates: &str) {
+    hddlog.transaction_start().unwrap();
+    hddlog
+        .apply_updates_dynamic(&mut parse_updates(updates).unwrap().into_iter())
+        .unwrap();
+}
+
+#[test]
+fn read_your_writes() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let mut session = Session::new();
+    let (cb, batches) = slow_subscriber();
+    hddlog
+        .subscribe_changelog_in_session(&mut session, Relations::ItemName as RelId, cb, config())
+        .unwrap();
+
+    for id in 1..=3 {
+        start_transaction(&hddlog, &format!(r#"insert Item({}, "item");"#, id));
+        hddlog
+            .transaction_commit_in_session(&session, Some(TIMEOUT))
+            .unwrap();
+        // The commit's changes have been delivered when it returns.
+        assert_eq!(batches.lock().unwrap().len(), id);
+    }
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn tokens() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let mut session = Session::new();
+    let (cb, batches) = slow_subscriber();
+    hddlog
+        .subscribe_changelog_in_session(&mut session, Relations::ItemName as RelId, cb, config())
+        .unwrap();
+
+    start_transaction(&hddlog, r#"insert Item(1, "one");"#);
+    hddlog.transaction_commit().unwrap();
+    let token = session.token();
+    // Another thread can wait for the changes.
+    let waiter = {
+        let session = session.clone();
+        let token = token.clone();
+        thread::spawn(move || session.wait_for(&token, Some(TIMEOUT)))
+    };
+    waiter.join().unwrap().unwrap();
+    assert_eq!(batches.lock().unwrap().len(), 1);
+
+    // Tokens of sessions with more subscriptions are rejected.
+    let mut other = Session::new();
+    for _ in 0..2 {
+        let (cb, _) = slow_subscriber();
+        hddlog
+            .subscribe_changelog_in_session(&mut other, Relations::ItemName as RelId, cb, config())
+            .unwrap();
+    }
+    assert!(session
+        .wait_for(&other.token(), None)
+        .unwrap_err()
+        .contains("does not belong to this session"));
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn timeout() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let mut session = Session::new();
+    let (release, released) = mpsc::channel::<()>();
+    let released = Mutex::new(released);
+    hddlog
+        .subscribe_changelog_in_session(
+            &mut session,
+            Relations::ItemName as RelId,
+            Arc::new(move |_: &ChangelogBatch| {
+                released.lock().unwrap().recv_timeout(TIMEOUT).unwrap();
+            }),
+            config(),
+        )
+        .unwrap();
+
+    start_transaction(&hddlog, r#"insert Item(1, "one");"#);
+    let err = hddlog
+        .transaction_commit_in_session(&session, Some(Duration::from_millis(50)))
+        .unwrap_err();
+    assert!(err.contains("were not delivered within"), "{}", err);
+    // The transaction has been committed nevertheless.
+    assert_eq!(
+        ddlog_testing::relation_contents(&hddlog, "ItemName")
+            .unwrap()
+            .len(),
+        1
+    );
+    release.send(()).unwrap();
+    session.wait_for(&session.token(), Some(TIMEOUT)).unwrap();
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn failed_subscribers() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let mut session = Session::new();
+    hddlog
+        .subscribe_changelog_in_session(
+            &mut session,
+            Relations::ItemName as RelId,
+            Arc::new(|_: &ChangelogBatch| panic!("subscriber failed")),
+            config(),
+        )
+        .unwrap();
+
+    start_transaction(&hddlog, r#"insert Item(1, "one");"#);
+    let err = hddlog
+        .transaction_commit_in_session(&session, Some(TIMEOUT))
+        .unwrap_err();
+    assert!(err.starts_with("session subscribers: "), "{}", err);
+    hddlog.stop().unwrap();
+}
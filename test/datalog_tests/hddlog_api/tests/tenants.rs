@@ -0,0 +1,153 @@
+//! Serving several tenants with one program (`Config::multi_tenant`).
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use differential_datalog::ddval::{DDValConvert, DDValue};
+use differential_datalog::program::config::Config;
+use differential_datalog::program::tenant::TenantId;
+use differential_datalog::program::{IdxId, RelId, Update};
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::api::HDDlog;
+use hddlog_api_ddlog::typedefs::{Item, Purchase};
+use hddlog_api_ddlog::{relid2name, Indexes, Relations};
+
+const INDEX: IdxId = Indexes::ItemNameById as IdxId;
+
+/// Changes delivered to the callback of a tenant, as `relation: record`
+/// strings with their accumulated weight.
+type Changes = Arc<Mutex<BTreeMap<String, isize>>>;
+
+fn start() -> HDDlog {
+    let config = Config {
+        multi_tenant: true,
+        num_timely_workers: 2,
+        ..Default::default()
+    };
+    HDDlog::run_with_config(config, true).unwrap().0
+}
+
+fn subscribe(hddlog: &HDDlog, tenant: TenantId) -> Changes {
+    let changes = Changes::default();
+    let changes2 = changes.clone();
+    hddlog.subscribe_tenant(
+        tenant,
+        Arc::new(move |relid, rec, w| {
+            let change = format!("{}: {}", relid2name(relid).unwrap(), rec);
+            let mut changes = changes2.lock().unwrap();
+            *changes.entry(change.clone()).or_insert(0) += w;
+            if changes[&change] == 0 {
+                changes.remove(&change);
+            }
+        }),
+    );
+    changes
+}
+
+fn item(id: u32, name: &str) -> Update<DDValue> {
+    Update::Insert {
+        relid: Relations::Item as RelId,
+        v: Item {
+            id,
+            name: name.to_string(),
+        }
+        .into_ddvalue(),
+    }
+}
+
+fn purchase(id: u32, item: u32) -> Update<DDValue> {
+    Update::Insert {
+        relid: Relations::Purchase as RelId,
+        v: Purchase { id, item }.into_ddvalue(),
+    }
+}
+
+fn transaction(hddlog: &HDDlog, updates: Vec<(TenantId, Update<DDValue>)>) {
+    hddlog.transaction_start().unwrap();
+    for (tenant, update) in updates {
+        hddlog
+            .apply_tenant_updates(tenant, &mut Some(update).into_iter())
+            .unwrap();
+    }
+    hddlog.transaction_commit().unwrap();
+}
+
+fn contents(changes: &Changes) -> Vec<String> {
+    changes
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(change, w)| {
+            assert_eq!(*w, 1, "{}", change);
+            change.clone()
+        })
+        .collect()
+}
+
+#[test]
+fn tenants_are_isolated() {
+    let hddlog = start();
+    let changes1 = subscribe(&hddlog, 1);
+    let changes2 = subscribe(&hddlog, 2);
+
+    // Both tenants insert the same item, which each of them counts once;
+    // only tenant 1 has item 2.
+    transaction(
+        &hddlog,
+        vec![
+            (1, item(1, "one")),
+            (2, item(1, "one")),
+            (1, item(2, "two")),
+        ],
+    );
+    assert_eq!(
+        contents(&changes1),
+        vec![
+            "ItemCount: ItemCount{.n = 2}",
+            r#"ItemName: ItemName{.id = 1, .name = "one"}"#,
+            r#"ItemName: ItemName{.id = 2, .name = "two"}"#,
+        ]
+    );
+    assert_eq!(
+        contents(&changes2),
+        vec![
+            "ItemCount: ItemCount{.n = 1}",
+            r#"ItemName: ItemName{.id = 1, .name = "one"}"#,
+        ]
+    );
+
+    // A purchase of item 2 by tenant 2 does not join tenant 1's item.
+    transaction(&hddlog, vec![(2, purchase(10, 2)), (2, purchase(11, 1))]);
+    assert!(contents(&changes1)
+        .iter()
+        .all(|c| !c.starts_with("Purchased")));
+    assert_eq!(
+        contents(&changes2),
+        vec![
+            "ItemCount: ItemCount{.n = 1}",
+            r#"ItemName: ItemName{.id = 1, .name = "one"}"#,
+            r#"PurchasedItem: PurchasedItem{.purchase = 11, .name = "one"}"#,
+        ]
+    );
+
+    // Queries and clearing a relation are confined to the tenant.
+    assert_eq!(hddlog.dump_tenant_index(1, INDEX).unwrap().len(), 2);
+    assert!(hddlog
+        .query_tenant_index(2, INDEX, 2u32.into_ddvalue())
+        .unwrap()
+        .is_empty());
+    hddlog.transaction_start().unwrap();
+    hddlog
+        .clear_tenant_relation(1, Relations::Item as RelId)
+        .unwrap();
+    hddlog.transaction_commit().unwrap();
+    assert!(contents(&changes1).is_empty());
+    assert_eq!(contents(&changes2).len(), 3);
+    assert_eq!(hddlog.dump_tenant_index(2, INDEX).unwrap().len(), 1);
+
+    // Tenants without a callback are not delivered anything.
+    hddlog.unsubscribe_tenant(2);
+    transaction(&hddlog, vec![(2, item(3, "three"))]);
+    assert_eq!(contents(&changes2).len(), 3);
+    hddlog.stop().unwrap();
+}
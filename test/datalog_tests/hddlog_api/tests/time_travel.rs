@@ -0,0 +1,137 @@
+//! Querying indexes as of past commits (`HDDlog::query_index_asof()`).
+
+use differential_datalog::ddval::DDValConvert;
+use differential_datalog::program::IdxId;
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::api::HDDlog;
+use hddlog_api_ddlog::ddlog_testing::{self, transaction};
+use hddlog_api_ddlog::Indexes;
+
+const INDEX: IdxId = Indexes::ItemNameById as IdxId;
+
+/// The names of item `id` right after commit `commit`.
+fn names_asof(hddlog: &HDDlog, id: u32, commit: u64) -> Vec<String> {
+    hddlog
+        .query_index_asof(INDEX, id.into_ddvalue(), commit)
+        .unwrap()
+        .iter()
+        .map(|v| v.to_string())
+        .collect()
+}
+
+fn dump_asof(hddlog: &HDDlog, commit: u64) -> Vec<String> {
+    hddlog
+        .dump_index_asof(INDEX, commit)
+        .unwrap()
+        .iter()
+        .map(|v| v.to_string())
+        .collect()
+}
+
+/// Run `updates` in a transaction and return its commit number.
+fn commit(hddlog: &HDDlog, updates: &str) -> u64 {
+    transaction(hddlog, updates).unwrap();
+    hddlog.commit_number()
+}
+
+#[test]
+fn query_past_commits() {
+    let hddlog = ddlog_testing::start(2).unwrap();
+    hddlog.set_index_time_travel(INDEX, 10).unwrap();
+    assert_eq!(hddlog.commit_number(), 0);
+
+    let c1 = commit(&hddlog, r#"insert Item(1, "one");"#);
+    let c2 = commit(&hddlog, r#"insert Item(2, "two");"#);
+    let c3 = commit(
+        &hddlog,
+        r#"delete Item(1, "one"), insert Item(3, "three");"#,
+    );
+    let c4 = commit(&hddlog, r#"insert_or_update Item(2, "dos");"#);
+    assert_eq!((c1, c2, c3, c4), (1, 2, 3, 4));
+
+    let one = vec![r#"ItemName{.id = 1, .name = "one"}"#.to_string()];
+    assert_eq!(names_asof(&hddlog, 1, c1), one);
+    assert_eq!(names_asof(&hddlog, 1, c2), one);
+    // The item was deleted by commit 3.
+    assert!(names_asof(&hddlog, 1, c3).is_empty());
+    assert!(names_asof(&hddlog, 1, c4).is_empty());
+    assert!(names_asof(&hddlog, 2, c1).is_empty());
+    assert_eq!(
+        names_asof(&hddlog, 2, c3),
+        vec![r#"ItemName{.id = 2, .name = "two"}"#.to_string()]
+    );
+    assert_eq!(
+        names_asof(&hddlog, 2, c4),
+        vec![r#"ItemName{.id = 2, .name = "dos"}"#.to_string()]
+    );
+
+    assert_eq!(
+        dump_asof(&hddlog, c2),
+        vec![
+            r#"ItemName{.id = 1, .name = "one"}"#.to_string(),
+            r#"ItemName{.id = 2, .name = "two"}"#.to_string(),
+        ]
+    );
+    assert_eq!(
+        dump_asof(&hddlog, c3),
+        vec![
+            r#"ItemName{.id = 2, .name = "two"}"#.to_string(),
+            r#"ItemName{.id = 3, .name = "three"}"#.to_string(),
+        ]
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn rolled_back_transactions() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    hddlog.set_index_time_travel(INDEX, 10).unwrap();
+    let c1 = commit(&hddlog, r#"insert Item(1, "one");"#);
+
+    // Rolled back and failed transactions are not commits.
+    hddlog.transaction_start().unwrap();
+    hddlog.transaction_rollback().unwrap();
+    assert!(transaction(&hddlog, r#"insert Item(1, "uno");"#).is_err());
+    assert_eq!(hddlog.commit_number(), c1);
+
+    let c2 = commit(&hddlog, r#"delete Item(1, "one");"#);
+    assert_eq!(c2, c1 + 1);
+    assert_eq!(
+        names_asof(&hddlog, 1, c1),
+        vec![r#"ItemName{.id = 1, .name = "one"}"#.to_string()]
+    );
+    assert!(names_asof(&hddlog, 1, c2).is_empty());
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn retained_commits() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    hddlog.set_index_time_travel(INDEX, 2).unwrap();
+    for id in 1..=4 {
+        commit(&hddlog, &format!(r#"insert Item({}, "item");"#, id));
+    }
+
+    // Only the last two commits, and the state they started from, are
+    // retained.
+    assert_eq!(dump_asof(&hddlog, 2).len(), 2);
+    assert_eq!(dump_asof(&hddlog, 4).len(), 4);
+    assert!(hddlog
+        .dump_index_asof(INDEX, 1)
+        .unwrap_err()
+        .contains("commit 1 is no longer retained"));
+    for commit in &[0, 5] {
+        assert!(hddlog
+            .dump_index_asof(INDEX, *commit)
+            .unwrap_err()
+            .contains(&format!(
+                "commit {} has not been made (last commit: 4)",
+                commit
+            )));
+    }
+    assert!(hddlog
+        .set_index_time_travel(1000, 2)
+        .unwrap_err()
+        .contains("unknown index 1000"));
+    hddlog.stop().unwrap();
+}
@@ -0,0 +1,214 @@
+//! Materialized views of output relations (`HDDlog::materialize_view()`).
+
+use std::sync::{Arc, Mutex};
+
+use differential_datalog::ddval::DDValue;
+use differential_datalog::program::RelId;
+use differential_datalog::record::{IntoRecord, Record};
+use differential_datalog::DDlogDynamic;
+use hddlog_api_ddlog::api::{HDDlog, InvalidationCallback, MaterializedView};
+use hddlog_api_ddlog::ddlog_testing::{self, parse_updates, transaction};
+use hddlog_api_ddlog::Relations;
+
+/// Key of `ItemName` records: the name.
+fn name(value: &DDValue) -> String {
+    match value.clone().into_record() {
+        Record::NamedStruct(_, fields) => match &fields[1].1 {
+            Record::String(name) => name.clone(),
+            field => panic!("unexpected name {}", field),
+        },
+        record => panic!("unexpected record {}", record),
+    }
+}
+
+fn view(hddlog: &HDDlog) -> MaterializedView<'_, String> {
+    hddlog
+        .materialize_view(Relations::ItemName as RelId, name)
+        .unwrap()
+}
+
+fn records(view: &MaterializedView<String>, key: &str) -> Vec<String> {
+    view.get(&key.to_string())
+        .iter()
+        .map(|v| v.to_string())
+        .collect()
+}
+
+/// Callback that records the keys and records it is invoked with.
+fn recorder() -> (
+    InvalidationCallback<String>,
+    Arc<Mutex<Vec<(String, Vec<String>)>>>,
+) {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let calls2 = calls.clone();
+    let cb: InvalidationCallback<String> = Arc::new(move |key: &String, records: &[DDValue]| {
+        calls2
+            .lock()
+            .unwrap()
+            .push((key.clone(), records.iter().map(|v| v.to_string()).collect()))
+    });
+    (cb, calls)
+}
+
+#[test]
+fn track_commits() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "a"), insert Item(2, "b");"#).unwrap();
+
+    // The view starts with the stored contents of the relation.
+    let view = view(&hddlog);
+    assert_eq!(view.len(), 2);
+    assert_eq!(
+        records(&view, "a"),
+        vec![r#"ItemName{.id = 1, .name = "a"}"#]
+    );
+
+    transaction(
+        &hddlog,
+        r#"insert Item(3, "a"), delete Item(2, "b"), insert Item(4, "c");"#,
+    )
+    .unwrap();
+    assert_eq!(
+        records(&view, "a"),
+        vec![
+            r#"ItemName{.id = 1, .name = "a"}"#,
+            r#"ItemName{.id = 3, .name = "a"}"#,
+        ]
+    );
+    assert_eq!(
+        view.get_one(&"a".to_string()).unwrap().to_string(),
+        r#"ItemName{.id = 1, .name = "a"}"#
+    );
+    assert!(!view.contains_key(&"b".to_string()));
+    assert!(records(&view, "b").is_empty());
+    assert_eq!(view.get_one(&"b".to_string()), None);
+    let mut keys = view.keys();
+    keys.sort();
+    assert_eq!(keys, vec!["a", "c"]);
+    let map = view.to_map();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["c"].len(), 1);
+
+    // Replacing a record by its primary key moves it to its new key.
+    transaction(&hddlog, r#"insert_or_update Item(4, "a");"#).unwrap();
+    assert_eq!(records(&view, "a").len(), 3);
+    assert!(!view.contains_key(&"c".to_string()));
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn invalidation_callbacks() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let view = view(&hddlog);
+    let (cb, key_calls) = recorder();
+    view.on_invalidate("a".to_string(), cb);
+    let (cb, any_calls) = recorder();
+    let any = view.on_any_invalidate(cb);
+
+    transaction(&hddlog, r#"insert Item(1, "a"), insert Item(2, "b");"#).unwrap();
+    // Each changed key is reported once per commit, with all its records.
+    transaction(&hddlog, r#"insert Item(3, "a"), insert Item(4, "a");"#).unwrap();
+    transaction(
+        &hddlog,
+        r#"delete Item(1, "a"), delete Item(3, "a"), delete Item(4, "a");"#,
+    )
+    .unwrap();
+    // Commits that do not change the key do not invoke its callbacks.
+    transaction(&hddlog, r#"delete Item(2, "b");"#).unwrap();
+    assert_eq!(
+        *key_calls.lock().unwrap(),
+        vec![
+            (
+                "a".to_string(),
+                vec![r#"ItemName{.id = 1, .name = "a"}"#.to_string()]
+            ),
+            (
+                "a".to_string(),
+                vec![
+                    r#"ItemName{.id = 1, .name = "a"}"#.to_string(),
+                    r#"ItemName{.id = 3, .name = "a"}"#.to_string(),
+                    r#"ItemName{.id = 4, .name = "a"}"#.to_string(),
+                ]
+            ),
+            ("a".to_string(), Vec::new()),
+        ]
+    );
+    let mut keys: Vec<String> = any_calls
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(k, _)| k.clone())
+        .collect();
+    keys.sort();
+    assert_eq!(keys, vec!["a", "a", "a", "b", "b"]);
+
+    // Removed callbacks are no longer invoked.
+    view.remove_callback(any);
+    transaction(&hddlog, r#"insert Item(5, "a");"#).unwrap();
+    assert_eq!(key_calls.lock().unwrap().len(), 4);
+    assert_eq!(any_calls.lock().unwrap().len(), 5);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn ignore_uncommitted_changes() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let view = view(&hddlog);
+    let (cb, calls) = recorder();
+    view.on_any_invalidate(cb);
+
+    hddlog.transaction_start().unwrap();
+    hddlog
+        .apply_updates_dynamic(
+            &mut parse_updates(r#"insert Item(1, "a");"#)
+                .unwrap()
+                .into_iter(),
+        )
+        .unwrap();
+    hddlog.transaction_rollback().unwrap();
+    // Transactions with invalid updates are rolled back.
+    assert!(transaction(&hddlog, r#"insert Item(2, "b"), insert Item("c", 3);"#).is_err());
+    assert!(view.is_empty());
+    assert!(calls.lock().unwrap().is_empty());
+
+    // Dropped views are no longer updated.
+    drop(view);
+    transaction(&hddlog, r#"insert Item(3, "c");"#).unwrap();
+    assert!(calls.lock().unwrap().is_empty());
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn views_without_stored_contents() {
+    let (hddlog, _) = HDDlog::run(1, false).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "a");"#).unwrap();
+    // Records committed before the view was created are not in it.
+    let view = view(&hddlog);
+    assert!(view.is_empty());
+    transaction(&hddlog, r#"insert Item(2, "b");"#).unwrap();
+    assert_eq!(view.keys(), vec!["b"]);
+    drop(view);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn reject_non_output_relations() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let err = hddlog
+        .materialize_view(Relations::Item as RelId, name)
+        .err()
+        .unwrap();
+    assert_eq!(
+        err,
+        format!("unknown output relation {}", Relations::Item as RelId)
+    );
+    hddlog.stop().unwrap();
+
+    let (hddlog, _) = HDDlog::run(1, false).unwrap();
+    let err = hddlog
+        .materialize_view(Relations::Item as RelId, name)
+        .err()
+        .unwrap();
+    assert_eq!(err, "Item is not an output relation");
+    hddlog.stop().unwrap();
+}
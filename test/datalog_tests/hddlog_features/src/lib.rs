@@ -0,0 +1,2 @@
+//! Tests of the optional features of the crate generated for
+//! `hddlog_features.dl` live in `tests/`.
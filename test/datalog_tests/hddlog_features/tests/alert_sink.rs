@@ -0,0 +1,372 @@
+//! Delivering alerts as webhooks (`alerts` feature).
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use differential_datalog::DDlogDynamic;
+use hddlog_features_ddlog::alert_sink::{
+    AlertChannel, AlertSink, AlertSinkConfig, AlertStats, EmailConfig,
+};
+use hddlog_features_ddlog::ddlog_testing::{self, transaction};
+use serde_json::{json, Value};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request received by a `Receiver`.
+#[derive(Debug, Clone)]
+struct Request {
+    path: String,
+    /// Headers, with lower-case names.
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// HTTP server that records the requests it receives and responds to them
+/// with `status`.
+struct Receiver {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Receiver {
+    fn start(status: u16) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let requests2 = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let path = line.split(' ').nth(1).unwrap().to_string();
+                let mut headers = Vec::new();
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    let header = line.trim_end();
+                    if header.is_empty() {
+                        break;
+                    }
+                    let (name, value) = header.split_once(": ").unwrap();
+                    headers.push((name.to_lowercase(), value.to_string()));
+                }
+                let mut request = Request {
+                    path,
+                    headers,
+                    body: String::new(),
+                };
+                let len = request
+                    .header("content-length")
+                    .map_or(0, |len| len.parse().unwrap());
+                let mut body = vec![0; len];
+                reader.read_exact(&mut body).unwrap();
+                request.body = String::from_utf8(body).unwrap();
+                // The request is recorded before the sender sees the response.
+                requests2.lock().unwrap().push(request);
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+            }
+        });
+        Self { addr, requests }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn webhook(url: String, template: Option<&str>) -> AlertChannel {
+    AlertChannel::Webhook {
+        url,
+        headers: vec![("Authorization".to_string(), "Bearer token".to_string())],
+        content_type: "text/plain".to_string(),
+        template: template.map(str::to_string),
+    }
+}
+
+fn config(channels: Vec<AlertChannel>) -> AlertSinkConfig {
+    AlertSinkConfig {
+        channels,
+        retry_delay: Duration::from_millis(1),
+        ..AlertSinkConfig::default()
+    }
+}
+
+fn wait_for(sink: &AlertSink, what: &str, condition: impl Fn(&AlertStats) -> bool) {
+    let start = Instant::now();
+    while !condition(&sink.stats()) {
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "{}: {:?}, last error {:?}",
+            what,
+            sink.stats(),
+            sink.last_error()
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn deliver_alerts() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let receiver = Receiver::start(200);
+    let sink = AlertSink::attach(
+        &hddlog,
+        "ItemName",
+        AlertSinkConfig {
+            notify_resolved: true,
+            ..config(vec![
+                webhook(
+                    receiver.url("/hook"),
+                    Some("{{ alert.name }}{% if resolved %} resolved{% endif %}"),
+                ),
+                AlertChannel::Slack {
+                    webhook_url: receiver.url("/slack"),
+                    template: "{{ relation }}: {{ text }}".to_string(),
+                },
+            ])
+        },
+    )
+    .unwrap();
+
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    wait_for(&sink, "alert not delivered", |stats| stats.delivered == 1);
+    let requests = receiver.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].path, "/hook");
+    assert_eq!(requests[0].body, "one");
+    assert_eq!(requests[0].header("content-type"), Some("text/plain"));
+    assert_eq!(requests[0].header("authorization"), Some("Bearer token"));
+    assert_eq!(requests[1].path, "/slack");
+    assert_eq!(requests[1].header("content-type"), Some("application/json"));
+    assert_eq!(
+        serde_json::from_str::<Value>(&requests[1].body).unwrap(),
+        json!({"text": r#"ItemName: ItemName{.id = 1, .name = "one"}"#})
+    );
+
+    // Deleted records are delivered as resolved alerts.
+    transaction(&hddlog, r#"delete Item(1, "one");"#).unwrap();
+    wait_for(&sink, "resolved alert not delivered", |stats| {
+        stats.delivered == 2
+    });
+    assert_eq!(receiver.requests()[2].body, "one resolved");
+    assert_eq!(
+        sink.stats(),
+        AlertStats {
+            delivered: 2,
+            ..AlertStats::default()
+        }
+    );
+    assert_eq!(sink.last_error(), None);
+    drop(sink);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn default_webhook_body() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let receiver = Receiver::start(200);
+    let sink = AlertSink::attach(
+        &hddlog,
+        "ItemName",
+        config(vec![webhook(receiver.url("/"), None)]),
+    )
+    .unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    // Deleted records are not delivered by default.
+    transaction(&hddlog, r#"delete Item(1, "one");"#).unwrap();
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    wait_for(&sink, "alerts not delivered", |stats| stats.delivered == 2);
+
+    let requests = receiver.requests();
+    assert_eq!(requests.len(), 2);
+    let first: Value = serde_json::from_str(&requests[0].body).unwrap();
+    assert_eq!(first["relation"], "ItemName");
+    assert_eq!(first["alert"], json!({"id": 1, "name": "one"}));
+    assert_eq!(first["text"], r#"ItemName{.id = 1, .name = "one"}"#);
+    assert_eq!(first["resolved"], false);
+    assert!(first["timestamp"].as_f64().unwrap() > 0.0, "{}", first);
+    let second: Value = serde_json::from_str(&requests[1].body).unwrap();
+    assert_eq!(second["alert"], json!({"id": 2, "name": "two"}));
+    assert!(
+        second["commit"].as_u64().unwrap() > first["commit"].as_u64().unwrap(),
+        "{} {}",
+        first,
+        second
+    );
+    drop(sink);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn failed_deliveries() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let receiver = Receiver::start(500);
+    let url = receiver.url("/");
+    let sink = AlertSink::attach(
+        &hddlog,
+        "ItemName",
+        AlertSinkConfig {
+            retries: 2,
+            ..config(vec![webhook(url.clone(), None)])
+        },
+    )
+    .unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    wait_for(&sink, "delivery not failed", |stats| stats.failed == 1);
+    // The first attempt and two retries.
+    assert_eq!(receiver.requests().len(), 3);
+    let err = sink.last_error().unwrap();
+    assert!(
+        err.starts_with(&format!("POST {} failed: ", url)),
+        "{}",
+        err
+    );
+    assert!(err.ends_with(" (after 3 attempts)"), "{}", err);
+    drop(sink);
+
+    // Alerts that cannot be rendered are not sent.
+    let receiver = Receiver::start(200);
+    let sink = AlertSink::attach(
+        &hddlog,
+        "ItemName",
+        AlertSinkConfig {
+            retries: 0,
+            ..config(vec![webhook(
+                receiver.url("/"),
+                Some("{{ alert.missing }}"),
+            )])
+        },
+    )
+    .unwrap();
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    wait_for(&sink, "delivery not failed", |stats| stats.failed == 1);
+    let err = sink.last_error().unwrap();
+    assert!(err.starts_with("failed to render alert: "), "{}", err);
+    assert!(receiver.requests().is_empty());
+    drop(sink);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn drop_alerts() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let receiver = Receiver::start(200);
+    let sink = AlertSink::attach(
+        &hddlog,
+        "ItemName",
+        AlertSinkConfig {
+            rate_limit: Some((2, Duration::from_secs(3600))),
+            ..config(vec![webhook(receiver.url("/"), None)])
+        },
+    )
+    .unwrap();
+    transaction(
+        &hddlog,
+        r#"insert Item(1, "one"), insert Item(2, "two"), insert Item(3, "three");"#,
+    )
+    .unwrap();
+    wait_for(&sink, "alerts not processed", |stats| {
+        stats.delivered + stats.rate_limited == 3
+    });
+    assert_eq!(sink.stats().rate_limited, 1);
+    assert_eq!(receiver.requests().len(), 2);
+    drop(sink);
+
+    // The alerts of a commit are queued at once, so all but one overflow a
+    // queue of one.
+    let sink = AlertSink::attach(
+        &hddlog,
+        "ItemName",
+        AlertSinkConfig {
+            queue_capacity: 1,
+            ..config(vec![webhook(receiver.url("/"), None)])
+        },
+    )
+    .unwrap();
+    transaction(
+        &hddlog,
+        r#"insert Item(4, "four"), insert Item(5, "five"), insert Item(6, "six");"#,
+    )
+    .unwrap();
+    assert_eq!(sink.stats().overflowed, 2);
+    // Dropping the sink delivers the queued alert.
+    drop(sink);
+    assert_eq!(receiver.requests().len(), 3);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn attach_errors() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let receiver = Receiver::start(200);
+    let channels = || vec![webhook(receiver.url("/"), None)];
+    for relation in &["NoSuchRelation", "Item"] {
+        let err = AlertSink::attach(&hddlog, relation, config(channels()))
+            .err()
+            .unwrap();
+        assert_eq!(err, format!("unknown output relation {}", relation));
+    }
+    assert_eq!(
+        AlertSink::attach(&hddlog, "ItemName", config(Vec::new()))
+            .err()
+            .unwrap(),
+        "no alert channels configured"
+    );
+    let err = AlertSink::attach(
+        &hddlog,
+        "ItemName",
+        config(vec![webhook(receiver.url("/"), Some("{{ alert"))]),
+    )
+    .err()
+    .unwrap();
+    assert!(err.starts_with("invalid alert template: "), "{}", err);
+    let email = EmailConfig {
+        relay: "smtp.example.com".to_string(),
+        port: Some(2525),
+        credentials: None,
+        from: "alerts".to_string(),
+        to: vec!["ops@example.com".to_string()],
+        subject: "{{ relation }}".to_string(),
+        body: "{{ text }}".to_string(),
+    };
+    let err = AlertSink::attach(
+        &hddlog,
+        "ItemName",
+        config(vec![AlertChannel::Email(email)]),
+    )
+    .err()
+    .unwrap();
+    assert!(
+        err.starts_with("invalid email address 'alerts': "),
+        "{}",
+        err
+    );
+
+    // Nothing is delivered by sinks that failed to attach.
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    assert!(receiver.requests().is_empty());
+    hddlog.stop().unwrap();
+}
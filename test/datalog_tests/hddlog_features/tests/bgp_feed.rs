@@ -0,0 +1,206 @@
+//! BGP routes from MRT files and BMP sessions (`bgp` feature).
+
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use differential_datalog::DDlogDynamic;
+use hddlog_features_ddlog::bgp_feed::{BmpCollector, BmpConfig, MrtLoader, MAX_BMP_MESSAGE_LEN};
+use hddlog_features_ddlog::ddlog_testing::{self, assert_relation};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const ROUTE: &str = r#"BgpRoute(65001, "198.51.100.0/24", 2)"#;
+
+fn attribute(type_code: u8, value: &[u8]) -> Vec<u8> {
+    let mut attr = vec![0x40, type_code, value.len() as u8];
+    attr.extend_from_slice(value);
+    attr
+}
+
+/// Path attributes with the AS path 65001 65002 in 4-byte encoding.
+fn attributes() -> Vec<u8> {
+    let mut attrs = attribute(1, &[0]);
+    attrs.extend(attribute(2, &[2, 2, 0, 0, 0xfd, 0xe9, 0, 0, 0xfd, 0xea]));
+    attrs.extend(attribute(3, &[192, 0, 2, 1]));
+    attrs
+}
+
+fn bgp_update(withdrawn: &[u8], attributes: &[u8], nlri: &[u8]) -> Vec<u8> {
+    let mut message = vec![0xff; 16];
+    let length = 23 + withdrawn.len() + attributes.len() + nlri.len();
+    message.extend_from_slice(&(length as u16).to_be_bytes());
+    message.push(2);
+    message.extend_from_slice(&(withdrawn.len() as u16).to_be_bytes());
+    message.extend_from_slice(withdrawn);
+    message.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+    message.extend_from_slice(attributes);
+    message.extend_from_slice(nlri);
+    message
+}
+
+const PREFIX: &[u8] = &[24, 198, 51, 100];
+
+fn mrt_record(record_type: u16, subtype: u16, body: &[u8]) -> Vec<u8> {
+    let mut record = vec![0; 4]; // timestamp
+    record.extend_from_slice(&record_type.to_be_bytes());
+    record.extend_from_slice(&subtype.to_be_bytes());
+    record.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    record.extend_from_slice(body);
+    record
+}
+
+/// A `TABLE_DUMP_V2` file: peer 192.0.2.1 of AS 65001, and its route to
+/// 198.51.100.0/24.
+fn rib_dump() -> (Vec<u8>, usize) {
+    let mut peers = vec![0; 4]; // collector BGP id
+    peers.extend_from_slice(&[0, 0, 0, 1]); // view name length and peer count
+    peers.push(2); // IPv4 peer with a 4-byte AS number
+    peers.extend_from_slice(&[10, 0, 0, 1, 192, 0, 2, 1]);
+    peers.extend_from_slice(&65001u32.to_be_bytes());
+    let peer_index = mrt_record(13, 1, &peers);
+
+    let mut rib = vec![0; 4]; // sequence number
+    rib.extend_from_slice(PREFIX);
+    rib.extend_from_slice(&[0, 1, 0, 0]); // entry count and peer index
+    rib.extend_from_slice(&[0; 4]); // originated time
+    let attrs = attributes();
+    rib.extend_from_slice(&(attrs.len() as u16).to_be_bytes());
+    rib.extend(attrs);
+
+    let mut file = peer_index;
+    let first_record = file.len();
+    file.extend(mrt_record(13, 2, &rib));
+    (file, first_record)
+}
+
+/// A `BGP4MP_MESSAGE_AS4` file withdrawing the route of `rib_dump()`.
+fn withdrawal() -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&65001u32.to_be_bytes());
+    body.extend_from_slice(&65000u32.to_be_bytes());
+    body.extend_from_slice(&[0, 0, 0, 1]); // interface index and AFI
+    body.extend_from_slice(&[192, 0, 2, 1, 192, 0, 2, 254]);
+    body.extend(bgp_update(PREFIX, &[], &[]));
+    mrt_record(16, 4, &body)
+}
+
+#[test]
+fn load_mrt_files() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let mut loader = MrtLoader::new(1).unwrap();
+    assert_eq!(loader.load(&hddlog, &rib_dump().0[..]).unwrap(), 2);
+    assert_relation(&hddlog, "BgpRoute", &[ROUTE]);
+    assert_eq!(loader.rib().len(), 1);
+
+    assert_eq!(loader.load(&hddlog, &withdrawal()[..]).unwrap(), 1);
+    assert_relation(&hddlog, "BgpRoute", &[]);
+    assert!(loader.rib().is_empty());
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn reject_invalid_mrt_files() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let (dump, first_record) = rib_dump();
+    let mut loader = MrtLoader::new(1).unwrap();
+
+    // Nothing is applied from files that end within a record.
+    for len in &[dump.len() - 1, first_record + 5] {
+        assert_eq!(
+            loader.load(&hddlog, &dump[..*len]).unwrap_err(),
+            "invalid MRT record 2: truncated message"
+        );
+    }
+    assert_relation(&hddlog, "BgpRoute", &[]);
+
+    // Records are checked against the maximal length before they are read.
+    loader.set_max_record_len(first_record - 12);
+    let err = loader.load(&hddlog, &dump[..]).unwrap_err();
+    assert!(err.starts_with("invalid MRT record 2: length "), "{}", err);
+    assert!(
+        err.ends_with(&format!("exceeds the maximum of {}", first_record - 12)),
+        "{}",
+        err
+    );
+    let mut header = mrt_record(13, 2, &[]);
+    header[8..12].copy_from_slice(&u32::max_value().to_be_bytes());
+    assert!(loader
+        .load(&hddlog, &header[..])
+        .unwrap_err()
+        .starts_with("invalid MRT record 1: length 4294967295 exceeds"));
+    assert_relation(&hddlog, "BgpRoute", &[]);
+    hddlog.stop().unwrap();
+}
+
+/// A BMP message with a per-peer header for peer 192.0.2.1 of AS 65001.
+fn bmp_message(message_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = vec![3];
+    message.extend_from_slice(&((6 + 42 + body.len()) as u32).to_be_bytes());
+    message.push(message_type);
+    message.extend_from_slice(&[0; 22]); // peer type, flags, and distinguisher
+    message.extend_from_slice(&[192, 0, 2, 1]);
+    message.extend_from_slice(&65001u32.to_be_bytes());
+    message.extend_from_slice(&[10, 0, 0, 1]);
+    message.extend_from_slice(&[0; 8]); // timestamp
+    message.extend_from_slice(body);
+    message
+}
+
+fn wait_for(what: &str, condition: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < TIMEOUT, "{}", what);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn routes(hddlog: &hddlog_features_ddlog::api::HDDlog) -> Vec<String> {
+    ddlog_testing::relation_contents(hddlog, "BgpRoute").unwrap()
+}
+
+#[test]
+fn bmp_sessions() {
+    let hddlog = Arc::new(ddlog_testing::start(1).unwrap());
+    let collector = BmpCollector::start(
+        hddlog.clone(),
+        "127.0.0.1:0",
+        BmpConfig {
+            flush_interval: Duration::from_millis(10),
+            ..BmpConfig::default()
+        },
+    )
+    .unwrap();
+    let mut router = TcpStream::connect(collector.addr()).unwrap();
+    router.write_all(&bmp_message(3, &[0; 20])).unwrap();
+    let update = bgp_update(&[], &attributes(), PREFIX);
+    router.write_all(&bmp_message(0, &update)).unwrap();
+    wait_for("route not applied", || routes(&hddlog).len() == 1);
+    assert_relation(&hddlog, "BgpRoute", &[ROUTE]);
+    wait_for("route not counted", || collector.routes() == 1);
+
+    // Oversized messages end the session, and its peers go down.
+    let mut header = vec![3];
+    header.extend_from_slice(&(MAX_BMP_MESSAGE_LEN as u32 + 1).to_be_bytes());
+    header.push(0);
+    router.write_all(&header).unwrap();
+    wait_for("peer still up", || routes(&hddlog).is_empty());
+    let error = collector.last_error().unwrap();
+    assert!(
+        error.starts_with("BMP session with 127.0.0.1:"),
+        "{}",
+        error
+    );
+    assert!(
+        error.ends_with(&format!(
+            ": invalid BMP message length {}",
+            MAX_BMP_MESSAGE_LEN + 1
+        )),
+        "{}",
+        error
+    );
+    drop(collector);
+    hddlog.stop().unwrap();
+}
@@ -0,0 +1,152 @@
+//! NetFlow and sFlow collection (`flows` feature).
+
+use std::net::UdpSocket;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use differential_datalog::program::RelId;
+use differential_datalog::DDlogDynamic;
+use hddlog_features_ddlog::api::HDDlog;
+use hddlog_features_ddlog::ddlog_testing;
+use hddlog_features_ddlog::flow_collector::{FlowCollector, FlowCollectorConfig};
+use hddlog_features_ddlog::update_handler::ChangelogBatch;
+use hddlog_features_ddlog::Relations;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A NetFlow v5 datagram with one record per destination port.
+fn netflow_v5(dst_ports: &[u16]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&5u16.to_be_bytes());
+    buf.extend_from_slice(&(dst_ports.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&[0; 20]); // uptime, time, sequence, engine, sampling
+    for port in dst_ports {
+        buf.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        buf.extend_from_slice(&[0; 8]); // next hop and interfaces
+        buf.extend_from_slice(&1u32.to_be_bytes()); // packets
+        buf.extend_from_slice(&100u32.to_be_bytes()); // bytes
+        buf.extend_from_slice(&[0; 8]); // first and last
+        buf.extend_from_slice(&40000u16.to_be_bytes());
+        buf.extend_from_slice(&port.to_be_bytes());
+        buf.extend_from_slice(&[0, 0, 6, 0]);
+        buf.extend_from_slice(&[0; 8]);
+    }
+    buf
+}
+
+/// Collect the flows inserted into the program.
+fn subscribe(hddlog: &HDDlog) -> Arc<Mutex<Vec<String>>> {
+    let flows = Arc::new(Mutex::new(Vec::new()));
+    let flows2 = flows.clone();
+    hddlog
+        .subscribe_changelog(
+            Relations::FlowPorts as RelId,
+            Arc::new(move |batch: &ChangelogBatch| {
+                let mut flows = flows2.lock().unwrap();
+                flows.extend(
+                    batch
+                        .changes
+                        .iter()
+                        .filter(|(_, w)| *w > 0)
+                        .map(|(v, _)| v.to_string()),
+                );
+                flows.sort();
+            }),
+        )
+        .unwrap();
+    flows
+}
+
+fn start() -> (Arc<HDDlog>, FlowCollector) {
+    let hddlog = Arc::new(ddlog_testing::start(1).unwrap());
+    let collector = FlowCollector::start(
+        hddlog.clone(),
+        "127.0.0.1:0",
+        FlowCollectorConfig {
+            flush_interval: Duration::from_millis(10),
+            ..FlowCollectorConfig::default()
+        },
+    )
+    .unwrap();
+    (hddlog, collector)
+}
+
+fn send(collector: &FlowCollector, datagram: &[u8]) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.send_to(datagram, collector.addr()).unwrap();
+}
+
+fn wait_for(what: &str, condition: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < TIMEOUT, "{}", what);
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn collect_flows() {
+    let (hddlog, collector) = start();
+    let flows = subscribe(&hddlog);
+    send(&collector, &netflow_v5(&[443, 80]));
+    send(&collector, &netflow_v5(&[53]));
+    wait_for("flows not inserted", || flows.lock().unwrap().len() == 3);
+    assert_eq!(
+        *flows.lock().unwrap(),
+        vec![
+            "FlowPorts{.src_port = 40000, .dst_port = 443, .bytes = 100}".to_string(),
+            "FlowPorts{.src_port = 40000, .dst_port = 53, .bytes = 100}".to_string(),
+            "FlowPorts{.src_port = 40000, .dst_port = 80, .bytes = 100}".to_string(),
+        ]
+    );
+    let stats = collector.stats();
+    assert_eq!(stats.datagrams.load(Ordering::Relaxed), 2);
+    assert_eq!(stats.flows.load(Ordering::Relaxed), 3);
+    assert_eq!(stats.errors.load(Ordering::Relaxed), 0);
+    assert_eq!(collector.last_error(), None);
+    drop(collector);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn invalid_datagrams() {
+    let (hddlog, collector) = start();
+    let flows = subscribe(&hddlog);
+    send(&collector, &[0, 10, 0, 0]);
+    // Truncated datagrams are rejected as a whole.
+    let datagram = netflow_v5(&[443, 80]);
+    send(&collector, &datagram[..datagram.len() - 1]);
+    wait_for("errors not counted", || {
+        collector.stats().errors.load(Ordering::Relaxed) == 2
+    });
+    let error = collector.last_error().unwrap();
+    assert!(
+        error.starts_with("invalid datagram from 127.0.0.1:"),
+        "{}",
+        error
+    );
+    assert!(error.ends_with(": truncated datagram"), "{}", error);
+
+    // The collector keeps going.
+    send(&collector, &netflow_v5(&[53]));
+    wait_for("flows not inserted", || flows.lock().unwrap().len() == 1);
+    assert_eq!(collector.stats().flows.load(Ordering::Relaxed), 1);
+    drop(collector);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn bind_errors() {
+    let hddlog = Arc::new(ddlog_testing::start(1).unwrap());
+    let err = FlowCollector::start(
+        hddlog.clone(),
+        "256.0.0.1:0",
+        FlowCollectorConfig::default(),
+    )
+    .err()
+    .unwrap();
+    assert!(err.starts_with("failed to bind 256.0.0.1:0: "), "{}", err);
+    hddlog.stop().unwrap();
+}
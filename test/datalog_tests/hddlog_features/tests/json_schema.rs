@@ -0,0 +1,48 @@
+//! Validating inserted records against the JSON schema of their relation
+//! (`json_schema` attribute).
+
+use differential_datalog::DDlogDynamic;
+use hddlog_features_ddlog::ddlog_testing::{self, assert_relation, transaction};
+
+#[test]
+fn accept_valid_records() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(
+        &hddlog,
+        r#"insert Labeled(1, "one"), insert Labeled(2, "two");"#,
+    )
+    .unwrap();
+    // Deletions are not validated.
+    transaction(&hddlog, r#"delete Labeled(2, "two");"#).unwrap();
+    assert_relation(&hddlog, "Label", &[r#"Label(1, "one")"#]);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn reject_invalid_records() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let err = transaction(
+        &hddlog,
+        r#"insert Labeled(1, "one"), insert Labeled(2, "");"#,
+    )
+    .unwrap_err();
+    assert!(
+        err.contains(
+            r#"record {"id":2,"label":""} does not conform to the JSON schema of relation Labeled:"#
+        ),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains("  at '/label' (schema '/properties/label/minLength'): "),
+        "{}",
+        err
+    );
+    // The transaction is rolled back as a whole.
+    assert_relation(&hddlog, "Label", &[]);
+
+    // Relations without a schema accept any record.
+    transaction(&hddlog, r#"insert Item(1, "");"#).unwrap();
+    assert_relation(&hddlog, "ItemName", &[r#"ItemName(1, "")"#]);
+    hddlog.stop().unwrap();
+}
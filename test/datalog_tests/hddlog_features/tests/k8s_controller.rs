@@ -0,0 +1,25 @@
+//! Kubernetes controllers (`kubernetes` feature).  Watching and applying
+//! resources needs a cluster; the conversions between resources and records
+//! are tested in `k8s_controller.rs` itself.
+
+use std::sync::Arc;
+
+use differential_datalog::DDlogDynamic;
+use hddlog_features_ddlog::ddlog_testing;
+use hddlog_features_ddlog::k8s_controller::{Controller, ControllerConfig, WatchSpec};
+
+#[test]
+fn requires_k8s_library() {
+    let hddlog = Arc::new(ddlog_testing::start(1).unwrap());
+    let err = Controller::start(
+        hddlog.clone(),
+        ControllerConfig {
+            watches: vec![WatchSpec::new("v1", "ConfigMap")],
+            ..ControllerConfig::default()
+        },
+    )
+    .err()
+    .unwrap();
+    assert_eq!(err, "the program does not import the k8s library");
+    hddlog.stop().unwrap();
+}
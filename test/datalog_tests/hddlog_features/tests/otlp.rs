@@ -0,0 +1,204 @@
+//! OpenTelemetry span ingestion (`otlp` feature).
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use differential_datalog::DDlogDynamic;
+use hddlog_features_ddlog::api::HDDlog;
+use hddlog_features_ddlog::ddlog_testing::{self, assert_relation};
+use hddlog_features_ddlog::otlp::{ingest_otlp_json, OtlpConfig, OtlpReceiver};
+use serde_json::json;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn export(spans: &[(&str, &str, &str)]) -> String {
+    let spans: Vec<_> = spans
+        .iter()
+        .map(|(trace_id, span_id, name)| json!({"traceId": trace_id, "spanId": span_id, "name": name}))
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{"key": "service.name", "value": {"stringValue": "cart"}}]
+            },
+            "scopeSpans": [{"spans": spans}]
+        }]
+    })
+    .to_string()
+}
+
+/// Send a request and return the status of the response.
+fn post(receiver: &OtlpReceiver, method: &str, path: &str, content_type: &str, body: &str) -> u16 {
+    let mut stream = TcpStream::connect(receiver.addr()).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        receiver.addr(),
+        content_type,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response[9..12].parse().unwrap()
+}
+
+fn start(config: OtlpConfig) -> (Arc<HDDlog>, OtlpReceiver) {
+    let hddlog = Arc::new(ddlog_testing::start(1).unwrap());
+    let receiver = OtlpReceiver::start(hddlog.clone(), "127.0.0.1:0", config).unwrap();
+    (hddlog, receiver)
+}
+
+#[test]
+fn ingest_exports() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let client = OtlpConfig::default().client;
+    assert_eq!(
+        ingest_otlp_json(
+            &hddlog,
+            client,
+            &export(&[("01", "0a", "GET"), ("01", "0b", "SQL")])
+        )
+        .unwrap(),
+        2
+    );
+    // Spans with the same ids replace earlier ones.
+    ingest_otlp_json(&hddlog, client, &export(&[("01", "0A", "POST")])).unwrap();
+    assert_relation(
+        &hddlog,
+        "SpanName",
+        &[
+            r#"SpanName("01", "0a", "cart", "POST")"#,
+            r#"SpanName("01", "0b", "cart", "SQL")"#,
+        ],
+    );
+
+    // Exports with invalid spans are rejected as a whole.
+    assert_eq!(
+        ingest_otlp_json(
+            &hddlog,
+            client,
+            &export(&[("02", "0c", "GET"), ("", "0d", "GET")])
+        )
+        .unwrap_err(),
+        "span without a trace or span id"
+    );
+    assert_eq!(
+        ddlog_testing::relation_contents(&hddlog, "SpanName")
+            .unwrap()
+            .len(),
+        2
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn receive_exports() {
+    let (hddlog, receiver) = start(OtlpConfig::default());
+    assert_eq!(
+        post(
+            &receiver,
+            "POST",
+            "/v1/traces",
+            "application/json",
+            &export(&[("01", "0a", "GET")])
+        ),
+        200
+    );
+    assert_relation(
+        &hddlog,
+        "SpanName",
+        &[r#"SpanName("01", "0a", "cart", "GET")"#],
+    );
+    assert_eq!(receiver.last_error(), None);
+    drop(receiver);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn reject_requests() {
+    let (hddlog, receiver) = start(OtlpConfig::default());
+    let body = export(&[("01", "0a", "GET")]);
+    assert_eq!(
+        post(&receiver, "GET", "/v1/traces", "application/json", ""),
+        404
+    );
+    assert_eq!(
+        post(&receiver, "POST", "/v1/metrics", "application/json", &body),
+        404
+    );
+    assert_eq!(
+        post(
+            &receiver,
+            "POST",
+            "/v1/traces",
+            "application/x-protobuf",
+            &body
+        ),
+        415
+    );
+    assert_eq!(
+        post(&receiver, "POST", "/v1/traces", "application/json", "{"),
+        400
+    );
+    // The error is recorded after the response has been sent.
+    let start = Instant::now();
+    while receiver.last_error().is_none() {
+        assert!(start.elapsed() < TIMEOUT, "error not recorded");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(receiver
+        .last_error()
+        .unwrap()
+        .starts_with("invalid OTLP request: "));
+    assert!(ddlog_testing::relation_contents(&hddlog, "SpanName")
+        .unwrap()
+        .is_empty());
+    drop(receiver);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn expire_traces() {
+    let (hddlog, receiver) = start(OtlpConfig {
+        retention: Some(Duration::from_millis(10)),
+        ..OtlpConfig::default()
+    });
+    assert_eq!(
+        post(
+            &receiver,
+            "POST",
+            "/v1/traces",
+            "application/json",
+            &export(&[("01", "0a", "GET"), ("01", "0b", "SQL")])
+        ),
+        200
+    );
+    let start = Instant::now();
+    while !ddlog_testing::relation_contents(&hddlog, "SpanName")
+        .unwrap()
+        .is_empty()
+    {
+        assert!(start.elapsed() < TIMEOUT, "trace not expired");
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(receiver.last_error(), None);
+    drop(receiver);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn bind_errors() {
+    let hddlog = Arc::new(ddlog_testing::start(1).unwrap());
+    let err = OtlpReceiver::start(hddlog.clone(), "256.0.0.1:0", OtlpConfig::default())
+        .err()
+        .unwrap();
+    assert!(err.starts_with("failed to bind 256.0.0.1:0: "), "{}", err);
+    hddlog.stop().unwrap();
+}
@@ -0,0 +1,151 @@
+//! Packets from PCAP files and live interfaces (`packet_capture` feature).
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use differential_datalog::program::RelId;
+use differential_datalog::DDlogDynamic;
+use hddlog_features_ddlog::api::HDDlog;
+use hddlog_features_ddlog::ddlog_testing;
+use hddlog_features_ddlog::packet_source::{load_pcap_file, PacketSource, PacketSourceConfig};
+use hddlog_features_ddlog::update_handler::ChangelogBatch;
+use hddlog_features_ddlog::Relations;
+
+/// An Ethernet frame with an IPv4 TCP or UDP header for `dst_port`.
+fn frame(proto: u8, dst_port: u16) -> Vec<u8> {
+    let mut frame = vec![0; 12]; // MAC addresses
+    frame.extend_from_slice(&[0x08, 0x00]);
+    frame.extend_from_slice(&[0x45, 0, 0, 40, 0, 0, 0, 0, 64, proto, 0, 0]);
+    frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+    frame.extend_from_slice(&40000u16.to_be_bytes());
+    frame.extend_from_slice(&dst_port.to_be_bytes());
+    frame.extend_from_slice(&[0; 16]);
+    frame
+}
+
+/// Write a PCAP file of Ethernet frames captured at 1s, 2s, ...
+fn write_pcap(path: &Path, frames: &[Vec<u8>]) {
+    let mut file = Vec::new();
+    file.extend_from_slice(&0xa1b2_c3d4u32.to_le_bytes());
+    file.extend_from_slice(&[2, 0, 4, 0]); // version 2.4
+    file.extend_from_slice(&[0; 8]); // time zone and accuracy
+    file.extend_from_slice(&65535u32.to_le_bytes());
+    file.extend_from_slice(&1u32.to_le_bytes()); // Ethernet
+    for (i, frame) in frames.iter().enumerate() {
+        file.extend_from_slice(&(i as u32 + 1).to_le_bytes());
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        file.extend_from_slice(frame);
+    }
+    std::fs::write(path, file).unwrap();
+}
+
+/// Collect the ports of the packets inserted into the program, in the order
+/// of their timestamps.
+fn subscribe(hddlog: &HDDlog) -> Arc<Mutex<Vec<String>>> {
+    let ports = Arc::new(Mutex::new(Vec::new()));
+    let ports2 = ports.clone();
+    hddlog
+        .subscribe_changelog(
+            Relations::PacketPort as RelId,
+            Arc::new(move |batch: &ChangelogBatch| {
+                let mut ports = ports2.lock().unwrap();
+                ports.extend(
+                    batch
+                        .changes
+                        .iter()
+                        .filter(|(_, w)| *w > 0)
+                        .map(|(v, _)| v.to_string()),
+                );
+                ports.sort();
+            }),
+        )
+        .unwrap();
+    ports
+}
+
+#[test]
+fn load_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("capture.pcap");
+    write_pcap(&path, &[frame(6, 443), frame(17, 53), frame(6, 22)]);
+    let path = path.to_str().unwrap();
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let ports = subscribe(&hddlog);
+
+    // Batches of two packets.
+    let config = PacketSourceConfig {
+        batch_size: 2,
+        ..PacketSourceConfig::default()
+    };
+    assert_eq!(load_pcap_file(&hddlog, path, &config).unwrap(), 3);
+    assert_eq!(
+        *ports.lock().unwrap(),
+        vec![
+            "PacketPort{.ts_ns = 1000000000, .dst_port = 443}".to_string(),
+            "PacketPort{.ts_ns = 2000000000, .dst_port = 53}".to_string(),
+            "PacketPort{.ts_ns = 3000000000, .dst_port = 22}".to_string(),
+        ]
+    );
+
+    // Filters are applied before packets are inserted.
+    ports.lock().unwrap().clear();
+    let config = PacketSourceConfig {
+        filter: Some("udp".to_string()),
+        ..PacketSourceConfig::default()
+    };
+    assert_eq!(load_pcap_file(&hddlog, path, &config).unwrap(), 1);
+    assert_eq!(
+        *ports.lock().unwrap(),
+        vec!["PacketPort{.ts_ns = 2000000000, .dst_port = 53}".to_string()]
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn reject_invalid_sources() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("capture.pcap");
+    write_pcap(&path, &[frame(6, 443)]);
+    let path = path.to_str().unwrap();
+    let hddlog = Arc::new(ddlog_testing::start(1).unwrap());
+    let ports = subscribe(&hddlog);
+
+    let config = PacketSourceConfig {
+        filter: Some("no such filter".to_string()),
+        ..PacketSourceConfig::default()
+    };
+    assert!(load_pcap_file(&hddlog, path, &config)
+        .unwrap_err()
+        .starts_with("invalid filter 'no such filter': "));
+    let missing = dir.path().join("missing.pcap");
+    let missing = missing.to_str().unwrap();
+    assert!(
+        load_pcap_file(&hddlog, missing, &PacketSourceConfig::default())
+            .unwrap_err()
+            .starts_with(&format!("failed to open {}: ", missing))
+    );
+    std::fs::write(dir.path().join("invalid.pcap"), b"not a capture").unwrap();
+    assert!(load_pcap_file(
+        &hddlog,
+        dir.path().join("invalid.pcap").to_str().unwrap(),
+        &PacketSourceConfig::default()
+    )
+    .is_err());
+    assert!(ports.lock().unwrap().is_empty());
+
+    let err = PacketSource::live(
+        hddlog.clone(),
+        "ddlog-no-such-device",
+        PacketSourceConfig::default(),
+    )
+    .err()
+    .unwrap();
+    assert!(
+        err.starts_with("failed to capture from ddlog-no-such-device: "),
+        "{}",
+        err
+    );
+    hddlog.stop().unwrap();
+}
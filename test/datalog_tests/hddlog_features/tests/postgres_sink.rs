@@ -0,0 +1,215 @@
+//! Mirroring output relations into PostgreSQL tables (`postgresql`
+//! feature).
+//!
+//! Tests that need a server connect to the one named by the
+//! `DDLOG_TEST_POSTGRES` connection string, e.g.,
+//! `host=localhost user=postgres`, and are skipped when it is not set.
+
+use std::env;
+use std::time::Duration;
+
+use differential_datalog::DDlogDynamic;
+use hddlog_features_ddlog::ddlog_testing::{self, transaction};
+use hddlog_features_ddlog::postgres_sink::{PostgresSink, PostgresSinkConfig};
+use postgres::{Client, NoTls};
+
+const SERVER_VAR: &str = "DDLOG_TEST_POSTGRES";
+
+fn server() -> Option<String> {
+    env::var(SERVER_VAR).ok()
+}
+
+/// A configuration that writes to a schema of its own, so that tests can
+/// run concurrently.
+fn config(schema: &str) -> PostgresSinkConfig {
+    let mut client = Client::connect(&server().unwrap(), NoTls).unwrap();
+    client
+        .batch_execute(&format!(
+            "DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema}",
+            schema = schema
+        ))
+        .unwrap();
+    PostgresSinkConfig {
+        schema: Some(schema.to_string()),
+        retries: 1,
+        retry_delay: Duration::from_millis(10),
+        ..PostgresSinkConfig::default()
+    }
+}
+
+fn connect() -> Result<Client, postgres::Error> {
+    Client::connect(&server().unwrap(), NoTls)
+}
+
+/// The keys and weights of the rows of `table`.
+fn rows(table: &str) -> Vec<(String, i64)> {
+    let mut client = connect().unwrap();
+    client
+        .query(
+            &*format!(
+                "SELECT _ddlog_key, _ddlog_weight FROM {} ORDER BY _ddlog_key",
+                table
+            ),
+            &[],
+        )
+        .unwrap()
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect()
+}
+
+fn key(id: u32, name: &str) -> (String, i64) {
+    (
+        format!(r#"ItemName{{.id = {}, .name = "{}"}}"#, id, name),
+        1,
+    )
+}
+
+#[test]
+fn rejects_unknown_relations() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    // Relations are checked before connecting.
+    for relation in &["NoSuchRelation", "Item"] {
+        let err = PostgresSink::attach(
+            &hddlog,
+            || panic!("connected"),
+            &[*relation],
+            PostgresSinkConfig::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err, format!("unknown output relation {}", relation));
+    }
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn reports_connection_failures() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let err = PostgresSink::attach(
+        &hddlog,
+        || Client::connect("host=127.0.0.1 port=1 connect_timeout=1", NoTls),
+        &["ItemName"],
+        PostgresSinkConfig::default(),
+    )
+    .err()
+    .unwrap();
+    assert!(
+        err.starts_with("failed to create tables in PostgreSQL: "),
+        "{}",
+        err
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn mirrors_commits() {
+    if server().is_none() {
+        return;
+    }
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    let sink = PostgresSink::attach(&hddlog, connect, &["ItemName"], config("pg_mirror")).unwrap();
+    assert_eq!(rows("pg_mirror.itemname"), vec![key(1, "one")]);
+
+    transaction(&hddlog, r#"insert Item(2, "two"), delete Item(1, "one");"#).unwrap();
+    transaction(&hddlog, r#"insert Item(3, "three");"#).unwrap();
+    assert_eq!(
+        rows("pg_mirror.itemname"),
+        vec![key(2, "two"), key(3, "three")]
+    );
+    let mut client = connect().unwrap();
+    let row = client
+        .query_one(
+            "SELECT last_commit FROM pg_mirror._ddlog_sink_progress WHERE sink = 'ddlog'",
+            &[],
+        )
+        .unwrap();
+    assert_eq!(row.get::<_, i64>(0), 2);
+    assert_eq!(sink.error(), None);
+    drop(sink);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn converts_values() {
+    if server().is_none() {
+        return;
+    }
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let mut config = config("pg_values");
+    config.tables = vec![("ReadingOut".to_string(), "readings".to_string())]
+        .into_iter()
+        .collect();
+    let sink = PostgresSink::attach(&hddlog, connect, &["ReadingOut"], config).unwrap();
+    transaction(
+        &hddlog,
+        r#"insert Reading(1, 1180591620717411303424, true, 0.5, "a", ["x"]);"#,
+    )
+    .unwrap();
+
+    let mut client = connect().unwrap();
+    let row = client
+        .query_one(
+            "SELECT big::TEXT, flag, ratio, label, tags FROM pg_values.readings",
+            &[],
+        )
+        .unwrap();
+    assert_eq!(row.get::<_, String>(0), "1180591620717411303424");
+    assert!(row.get::<_, bool>(1));
+    assert_eq!(row.get::<_, f64>(2), 0.5);
+    assert_eq!(row.get::<_, String>(3), "a");
+    assert_eq!(row.get::<_, String>(4), r#"["x"]"#);
+    assert_eq!(sink.error(), None);
+    drop(sink);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn skips_applied_commits() {
+    if server().is_none() {
+        return;
+    }
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let sink =
+        PostgresSink::attach(&hddlog, connect, &["ItemName"], config("pg_progress")).unwrap();
+    // Pretend that the acknowledgement of the first commit was lost.
+    let mut client = connect().unwrap();
+    client
+        .batch_execute("UPDATE pg_progress._ddlog_sink_progress SET last_commit = 1")
+        .unwrap();
+
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    assert!(rows("pg_progress.itemname").is_empty());
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    assert_eq!(rows("pg_progress.itemname"), vec![key(2, "two")]);
+    drop(sink);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn stops_after_failed_retries() {
+    if server().is_none() {
+        return;
+    }
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let sink =
+        PostgresSink::attach(&hddlog, connect, &["ItemName"], config("pg_failures")).unwrap();
+    let mut client = connect().unwrap();
+    client
+        .batch_execute("DROP TABLE pg_failures.itemname")
+        .unwrap();
+
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    let error = sink.error().unwrap();
+    assert!(
+        error.starts_with("failed to apply commit 1 after 2 attempts: "),
+        "{}",
+        error
+    );
+    // Later commits are ignored.
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    assert_eq!(sink.error().unwrap(), error);
+    drop(sink);
+    hddlog.stop().unwrap();
+}
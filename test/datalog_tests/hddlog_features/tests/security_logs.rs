@@ -0,0 +1,124 @@
+//! Zeek and Suricata log ingestion (`security_logs` feature).
+
+use std::time::Duration;
+
+use differential_datalog::record::Record;
+use differential_datalog::DDlogDynamic;
+use hddlog_features_ddlog::ddlog_testing::{self, assert_relation};
+use hddlog_features_ddlog::security_logs::{LogIngester, LogRecord};
+
+const CLIENT: u64 = 1;
+
+const CONN_LOG: &str = "#separator \\x09
+#set_separator\t,
+#empty_field\t(empty)
+#unset_field\t-
+#path\tconn
+#fields\tts\tuid\tid.orig_h\tid.orig_p\tid.resp_h\tid.resp_p\tproto\tconn_state
+#types\ttime\tstring\taddr\tport\taddr\tport\tenum\tstring
+1.5\tC1\t10.0.0.1\t40000\t10.0.0.2\t443\ttcp\tSF
+2.5\tC2\t10.0.0.1\t40001\t10.0.0.2\t443\ttcp\tREJ
+3.5\tC3\t10.0.0.1\t40002\t10.0.0.3\t22\ttcp\tS0
+";
+
+/// An EVE alert raised at `time` (`HH:MM:SS`) for flow `flow_id`.
+fn alert(time: &str, flow_id: u64, signature: &str) -> String {
+    format!(
+        r#"{{"timestamp":"2023-04-01T{}.000000+0000","flow_id":{},"event_type":"alert","src_ip":"10.0.0.1","src_port":40000,"dest_ip":"10.0.0.2","dest_port":443,"proto":"TCP","alert":{{"signature_id":1,"rev":1,"signature":"{}","category":"Misc","severity":2,"action":"allowed"}}}}"#,
+        time, flow_id, signature
+    )
+}
+
+#[test]
+fn ingest_zeek_logs() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let mut ingester = LogIngester::new(CLIENT, None);
+    assert_eq!(
+        ingester
+            .ingest_zeek(&hddlog, CONN_LOG.as_bytes(), None, 2)
+            .unwrap(),
+        3
+    );
+    assert_relation(
+        &hddlog,
+        "ConnState",
+        &[
+            r#"ConnState("C1", "SF")"#,
+            r#"ConnState("C2", "REJ")"#,
+            r#"ConnState("C3", "S0")"#,
+        ],
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn expire_records() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let mut ingester = LogIngester::new(CLIENT, Some(Duration::from_secs(60)));
+    let log = format!(
+        "{}\n{}\n",
+        alert("12:00:00", 1, "first"),
+        alert("12:00:30", 2, "second")
+    );
+    assert_eq!(ingester.ingest_eve(&hddlog, log.as_bytes(), 10).unwrap(), 2);
+    assert_relation(
+        &hddlog,
+        "AlertSignature",
+        &[
+            r#"AlertSignature(1, "first")"#,
+            r#"AlertSignature(2, "second")"#,
+        ],
+    );
+
+    // Records expire in log time, as newer records arrive.
+    let log = alert("12:01:10", 3, "third");
+    assert_eq!(ingester.ingest_eve(&hddlog, log.as_bytes(), 10).unwrap(), 1);
+    assert_relation(
+        &hddlog,
+        "AlertSignature",
+        &[
+            r#"AlertSignature(2, "second")"#,
+            r#"AlertSignature(3, "third")"#,
+        ],
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn reject_invalid_logs() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let mut ingester = LogIngester::new(CLIENT, None);
+    let log = format!("{}\n{{\"event_type\":\n", alert("12:00:00", 1, "first"));
+    let err = ingester
+        .ingest_eve(&hddlog, log.as_bytes(), 10)
+        .unwrap_err();
+    assert!(err.starts_with("line 2: invalid EVE event: "), "{}", err);
+    assert_relation(&hddlog, "AlertSignature", &[]);
+
+    // Batches before the invalid line have been inserted.
+    assert!(ingester.ingest_eve(&hddlog, log.as_bytes(), 1).is_err());
+    assert_relation(
+        &hddlog,
+        "AlertSignature",
+        &[r#"AlertSignature(1, "first")"#],
+    );
+
+    assert_eq!(ingester.ingest_zeek(&hddlog, &b""[..], None, 1).unwrap(), 0);
+    assert_eq!(
+        ingester
+            .ingest_zeek(&hddlog, "1.5\tC1".as_bytes(), None, 1)
+            .unwrap_err(),
+        "line 1: Zeek TSV log entry before the log header"
+    );
+
+    let record = LogRecord {
+        relation: "nids::Alert",
+        ts_us: 0,
+        record: Record::Bool(true),
+    };
+    assert_eq!(
+        ingester.ingest(&hddlog, vec![record]).unwrap_err(),
+        "the program does not import the library of nids::Alert"
+    );
+    hddlog.stop().unwrap();
+}
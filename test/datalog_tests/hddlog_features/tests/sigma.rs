@@ -0,0 +1,201 @@
+//! Sigma rule matching (`sigma` feature).
+
+use std::collections::BTreeMap;
+
+use differential_datalog::record::UpdCmd;
+use differential_datalog::DDlogDynamic;
+use hddlog_features_ddlog::api::HDDlog;
+use hddlog_features_ddlog::ddlog_testing::{self, assert_relation};
+use hddlog_features_ddlog::sigma::{compile_rule, event_update, LogSource, SigmaRuleSet};
+
+const CLIENT: u64 = 1;
+
+const NETCAT: &str = "
+id: netcat
+title: Netcat listener
+level: high
+logsource:
+  product: linux
+detection:
+  sel:
+    cmd|contains: 'nc -l'
+  filter:
+    user: root
+  condition: sel and not filter
+";
+
+const WHOAMI: &str = "
+id: whoami
+level: low
+detection:
+  keywords:
+    - whoami
+  condition: keywords
+";
+
+fn product(product: &str) -> LogSource {
+    LogSource {
+        product: product.to_string(),
+        ..LogSource::default()
+    }
+}
+
+fn event(id: u64, logsource: &LogSource, fields: &[(&str, &str)]) -> UpdCmd {
+    let fields: BTreeMap<String, String> = fields
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    event_update(id, logsource, &fields)
+}
+
+fn send(hddlog: &HDDlog, events: Vec<UpdCmd>) {
+    hddlog.transaction_start().unwrap();
+    hddlog
+        .apply_updates_dynamic(&mut events.into_iter())
+        .unwrap();
+    hddlog.transaction_commit().unwrap();
+}
+
+#[test]
+fn detect_events() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let mut rules = SigmaRuleSet::new(CLIENT).unwrap();
+    rules
+        .load(
+            &hddlog,
+            vec![compile_rule(NETCAT).unwrap(), compile_rule(WHOAMI).unwrap()],
+        )
+        .unwrap();
+
+    let linux = product("linux");
+    let windows = product("windows");
+    send(
+        &hddlog,
+        vec![
+            // Matching is case-insensitive.
+            event(1, &linux, &[("cmd", "NC -L -p 4444"), ("user", "bob")]),
+            event(2, &linux, &[("cmd", "nc -l -p 4444"), ("user", "root")]),
+            event(3, &windows, &[("cmd", "nc -l -p 4444")]),
+            event(4, &windows, &[("cmd", "WhoAmI")]),
+        ],
+    );
+    assert_relation(
+        &hddlog,
+        "SigmaAlert",
+        &[
+            r#"SigmaAlert(1, "netcat", "high")"#,
+            r#"SigmaAlert(4, "whoami", "low")"#,
+        ],
+    );
+
+    // Events with the same id replace earlier ones.
+    send(
+        &hddlog,
+        vec![event(2, &linux, &[("cmd", "nc -l"), ("user", "alice")])],
+    );
+    assert_relation(
+        &hddlog,
+        "SigmaAlert",
+        &[
+            r#"SigmaAlert(1, "netcat", "high")"#,
+            r#"SigmaAlert(2, "netcat", "high")"#,
+            r#"SigmaAlert(4, "whoami", "low")"#,
+        ],
+    );
+
+    // Reloading a rule replaces it; removing it retracts its detections.
+    rules
+        .load(
+            &hddlog,
+            vec![compile_rule(&NETCAT.replace("level: high", "level: critical")).unwrap()],
+        )
+        .unwrap();
+    assert_eq!(rules.rules().count(), 2);
+    assert_relation(
+        &hddlog,
+        "SigmaAlert",
+        &[
+            r#"SigmaAlert(1, "netcat", "critical")"#,
+            r#"SigmaAlert(2, "netcat", "critical")"#,
+            r#"SigmaAlert(4, "whoami", "low")"#,
+        ],
+    );
+    rules.remove(&hddlog, &["netcat", "unknown"]).unwrap();
+    assert_eq!(
+        rules.rules().map(|r| r.id.as_str()).collect::<Vec<_>>(),
+        vec!["whoami"]
+    );
+    assert_relation(
+        &hddlog,
+        "SigmaAlert",
+        &[r#"SigmaAlert(4, "whoami", "low")"#],
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn negated_conditions() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let mut rules = SigmaRuleSet::new(CLIENT).unwrap();
+    let rule = compile_rule(
+        "
+id: not_root
+level: medium
+logsource: {product: linux}
+detection:
+  filter: {user: root}
+  condition: not filter
+",
+    )
+    .unwrap();
+    rules.load(&hddlog, vec![rule]).unwrap();
+
+    let linux = product("linux");
+    send(
+        &hddlog,
+        vec![
+            event(1, &linux, &[("user", "root")]),
+            event(2, &linux, &[("user", "bob")]),
+            // Absent fields do not match the filter.
+            event(3, &linux, &[]),
+            event(4, &product("windows"), &[("user", "bob")]),
+        ],
+    );
+    assert_relation(
+        &hddlog,
+        "SigmaAlert",
+        &[
+            r#"SigmaAlert(2, "not_root", "medium")"#,
+            r#"SigmaAlert(3, "not_root", "medium")"#,
+        ],
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn failed_loads() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let mut rules = SigmaRuleSet::new(CLIENT).unwrap();
+
+    // Rules that could not be loaded are not recorded.
+    hddlog.transaction_start().unwrap();
+    assert!(rules
+        .load(&hddlog, vec![compile_rule(NETCAT).unwrap()])
+        .is_err());
+    assert_eq!(rules.rules().count(), 0);
+    hddlog.transaction_rollback().unwrap();
+
+    rules
+        .load(&hddlog, vec![compile_rule(NETCAT).unwrap()])
+        .unwrap();
+    send(
+        &hddlog,
+        vec![event(1, &product("linux"), &[("cmd", "nc -l")])],
+    );
+    assert_relation(
+        &hddlog,
+        "SigmaAlert",
+        &[r#"SigmaAlert(1, "netcat", "high")"#],
+    );
+    hddlog.stop().unwrap();
+}
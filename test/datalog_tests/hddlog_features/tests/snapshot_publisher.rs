@@ -0,0 +1,243 @@
+//! Publishing output relations to object stores (`snapshots` feature).
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use differential_datalog::DDlogDynamic;
+use flate2::read::GzDecoder;
+use hddlog_features_ddlog::ddlog_testing::{self, transaction};
+use hddlog_features_ddlog::snapshot_publisher::{
+    ObjectStore, PublishMode, PublisherConfig, SnapshotPublisher,
+};
+use serde_json::Value;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Keeps objects in memory; fails all uploads while `fail` is set.
+#[derive(Clone, Default)]
+struct MemoryStore {
+    objects: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+    fail: Arc<AtomicBool>,
+}
+
+impl ObjectStore for MemoryStore {
+    fn put(&mut self, key: &str, data: Vec<u8>, _content_type: &str) -> Result<(), String> {
+        if self.fail.load(Ordering::SeqCst) {
+            return Err(format!("failed to upload {}", key));
+        }
+        self.objects.lock().unwrap().insert(key.to_string(), data);
+        Ok(())
+    }
+}
+
+impl MemoryStore {
+    fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.objects.lock().unwrap().get(key).cloned()
+    }
+
+    /// Wait for publication `sequence` and return its manifest.
+    fn manifest(&self, sequence: u64) -> Value {
+        let start = Instant::now();
+        loop {
+            if let Some(latest) = self.get("ddlog/latest.json") {
+                let manifest: Value = serde_json::from_slice(&latest).unwrap();
+                if manifest["sequence"].as_u64().unwrap() >= sequence {
+                    assert_eq!(manifest["sequence"], sequence);
+                    return manifest;
+                }
+            }
+            assert!(
+                start.elapsed() < TIMEOUT,
+                "publication {} not made",
+                sequence
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// The records and weights in the file of `relation` listed in
+    /// `manifest`.
+    fn records(&self, manifest: &Value, relation: &str) -> Vec<(String, i64)> {
+        let file = manifest["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|file| file["relation"] == relation)
+            .unwrap();
+        let data = self.get(file["key"].as_str().unwrap()).unwrap();
+        let mut records: Vec<(String, i64)> = BufReader::new(GzDecoder::new(&data[..]))
+            .lines()
+            .map(|line| {
+                let line: Value = serde_json::from_str(&line.unwrap()).unwrap();
+                (
+                    line["record"].as_str().unwrap().to_string(),
+                    line["weight"].as_i64().unwrap(),
+                )
+            })
+            .collect();
+        records.sort();
+        assert_eq!(file["records"], records.len());
+        records
+    }
+}
+
+fn config(mode: PublishMode) -> PublisherConfig {
+    PublisherConfig {
+        prefix: "ddlog/".to_string(),
+        mode,
+        interval: Some(Duration::from_secs(3600)),
+        every_commits: None,
+    }
+}
+
+fn record(id: u32, name: &str) -> String {
+    format!(r#"ItemName{{.id = {}, .name = "{}"}}"#, id, name)
+}
+
+#[test]
+fn deltas() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    let store = MemoryStore::default();
+    let publisher = SnapshotPublisher::attach(
+        &hddlog,
+        Box::new(store.clone()),
+        &["ItemName"],
+        config(PublishMode::Deltas),
+    )
+    .unwrap();
+
+    // The first publication is a snapshot.
+    let first = store.manifest(1);
+    assert_eq!(first["kind"], "snapshot");
+    assert!(first["previous"].is_null());
+    assert_eq!(
+        store.records(&first, "ItemName"),
+        vec![(record(1, "one"), 1)]
+    );
+
+    transaction(&hddlog, r#"insert Item(2, "two"), delete Item(1, "one");"#).unwrap();
+    publisher.publish_now();
+    let second = store.manifest(2);
+    assert_eq!(second["kind"], "delta");
+    assert_eq!(second["commit"], 2);
+    assert!(second["previous"]
+        .as_str()
+        .unwrap()
+        .ends_with("/00000001/manifest.json"));
+    assert_eq!(
+        store.records(&second, "ItemName"),
+        vec![(record(1, "one"), -1), (record(2, "two"), 1)]
+    );
+
+    // Publications without changes are skipped.
+    publisher.publish_now();
+    drop(publisher);
+    assert_eq!(store.manifest(2), second);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn snapshots() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let store = MemoryStore::default();
+    let publisher = SnapshotPublisher::attach(
+        &hddlog,
+        Box::new(store.clone()),
+        &["ItemName"],
+        PublisherConfig {
+            interval: None,
+            every_commits: Some(1),
+            ..config(PublishMode::Snapshots)
+        },
+    )
+    .unwrap();
+    assert!(store.records(&store.manifest(1), "ItemName").is_empty());
+
+    transaction(&hddlog, r#"insert Item(1, "one"), insert Item(2, "two");"#).unwrap();
+    let manifest = store.manifest(2);
+    assert_eq!(manifest["kind"], "snapshot");
+    transaction(&hddlog, r#"delete Item(1, "one");"#).unwrap();
+    let manifest = store.manifest(3);
+    assert_eq!(
+        store.records(&manifest, "ItemName"),
+        vec![(record(2, "two"), 1)]
+    );
+    drop(publisher);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn failed_uploads_are_retried() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let store = MemoryStore::default();
+    let publisher = SnapshotPublisher::attach(
+        &hddlog,
+        Box::new(store.clone()),
+        &["ItemName"],
+        config(PublishMode::Deltas),
+    )
+    .unwrap();
+    store.manifest(1);
+
+    store.fail.store(true, Ordering::SeqCst);
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    publisher.publish_now();
+    let start = Instant::now();
+    while publisher.error().is_none() {
+        assert!(start.elapsed() < TIMEOUT);
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(publisher
+        .error()
+        .unwrap()
+        .starts_with("failed to upload ddlog/"));
+
+    // The next publication includes the changes that failed to upload.
+    store.fail.store(false, Ordering::SeqCst);
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    publisher.publish_now();
+    let manifest = store.manifest(2);
+    assert_eq!(
+        store.records(&manifest, "ItemName"),
+        vec![(record(1, "one"), 1), (record(2, "two"), 1)]
+    );
+    assert_eq!(publisher.error(), None);
+    drop(publisher);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn invalid_configurations() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let attach = |relation: &str, config: PublisherConfig| {
+        SnapshotPublisher::attach(
+            &hddlog,
+            Box::new(MemoryStore::default()),
+            &[relation],
+            config,
+        )
+        .err()
+        .unwrap()
+    };
+    assert_eq!(
+        attach(
+            "ItemName",
+            PublisherConfig {
+                interval: None,
+                every_commits: None,
+                ..PublisherConfig::default()
+            }
+        ),
+        "either an interval or a number of commits is required"
+    );
+    assert_eq!(
+        attach("Item", PublisherConfig::default()),
+        "unknown output relation Item"
+    );
+    hddlog.stop().unwrap();
+}
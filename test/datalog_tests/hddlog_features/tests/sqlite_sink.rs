@@ -0,0 +1,173 @@
+//! Mirroring output relations into SQLite tables (`sqlite` feature).
+
+use differential_datalog::DDlogDynamic;
+use hddlog_features_ddlog::ddlog_testing::{self, transaction};
+use hddlog_features_ddlog::sqlite_sink::SqliteSink;
+use rusqlite::types::Value;
+use rusqlite::Connection;
+
+/// The rows of `table`, ordered by key, with the columns `columns`.
+fn rows(sink: &SqliteSink, table: &str, columns: &[&str]) -> Vec<Vec<Value>> {
+    sink.with_connection(|conn| {
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM {} ORDER BY _ddlog_key",
+                columns.join(", "),
+                table
+            ))
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| {
+                (0..columns.len()).map(|i| row.get::<_, Value>(i)).collect()
+            })
+            .unwrap();
+        rows.map(|row| row.unwrap()).collect()
+    })
+}
+
+fn item(id: i64, name: &str) -> Vec<Value> {
+    vec![
+        Value::Text(format!(r#"ItemName{{.id = {}, .name = "{}"}}"#, id, name)),
+        Value::Integer(1),
+        Value::Integer(id),
+        Value::Text(name.to_string()),
+    ]
+}
+
+const ITEM_COLUMNS: &[&str] = &["_ddlog_key", "_ddlog_weight", "id", "name"];
+
+#[test]
+fn mirrors_commits() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let sink = SqliteSink::attach(
+        &hddlog,
+        Connection::open_in_memory().unwrap(),
+        &["ItemName"],
+    )
+    .unwrap();
+
+    transaction(&hddlog, r#"insert Item(1, "one"), insert Item(2, "two");"#).unwrap();
+    assert_eq!(
+        rows(&sink, "ItemName", ITEM_COLUMNS),
+        vec![item(1, "one"), item(2, "two")]
+    );
+    transaction(
+        &hddlog,
+        r#"delete Item(1, "one"), insert Item(3, "three");"#,
+    )
+    .unwrap();
+    assert_eq!(
+        rows(&sink, "ItemName", ITEM_COLUMNS),
+        vec![item(2, "two"), item(3, "three")]
+    );
+    assert_eq!(sink.error(), None);
+    drop(sink);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn converts_values() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let sink = SqliteSink::attach(
+        &hddlog,
+        Connection::open_in_memory().unwrap(),
+        &["ReadingOut"],
+    )
+    .unwrap();
+    transaction(
+        &hddlog,
+        r#"insert Reading(1, 5, true, 0.5, "a", ["x", "y"]),
+           insert Reading(2, 1180591620717411303424, false, 2.0, "b", []);"#,
+    )
+    .unwrap();
+
+    let columns = &["big", "flag", "ratio", "label", "tags"];
+    assert_eq!(
+        rows(&sink, "ReadingOut", columns),
+        vec![
+            vec![
+                Value::Integer(5),
+                Value::Integer(1),
+                Value::Real(0.5),
+                Value::Text("a".to_string()),
+                Value::Text(r#"["x", "y"]"#.to_string()),
+            ],
+            // Integers that do not fit in 64 bits are stored as text.
+            vec![
+                Value::Text("1180591620717411303424".to_string()),
+                Value::Integer(0),
+                Value::Real(2.0),
+                Value::Text("b".to_string()),
+                Value::Text("[]".to_string()),
+            ],
+        ]
+    );
+    drop(sink);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn copies_contents_on_attach() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+
+    // A table left over from a previous run is reused and cleared.
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch(
+        "CREATE TABLE ItemName (_ddlog_key TEXT PRIMARY KEY, _ddlog_weight INTEGER NOT NULL, id INTEGER, name TEXT);
+         INSERT INTO ItemName VALUES ('stale', 1, 7, 'stale');",
+    )
+    .unwrap();
+    let sink = SqliteSink::attach(&hddlog, conn, &["ItemName"]).unwrap();
+    assert_eq!(rows(&sink, "ItemName", ITEM_COLUMNS), vec![item(1, "one")]);
+
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    assert_eq!(
+        rows(&sink, "ItemName", ITEM_COLUMNS),
+        vec![item(1, "one"), item(2, "two")]
+    );
+    drop(sink);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn rejects_unknown_relations() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    for relation in &["NoSuchRelation", "Item"] {
+        let err = SqliteSink::attach(&hddlog, Connection::open_in_memory().unwrap(), &[*relation])
+            .err()
+            .unwrap();
+        assert_eq!(err, format!("unknown output relation {}", relation));
+    }
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn stops_after_failed_commits() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let sink = SqliteSink::attach(
+        &hddlog,
+        Connection::open_in_memory().unwrap(),
+        &["ItemName"],
+    )
+    .unwrap();
+    sink.with_connection(|conn| conn.execute("DROP TABLE ItemName", []).unwrap());
+
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    let error = sink.error().unwrap();
+    assert!(error.starts_with("failed to apply commit 1: "), "{}", error);
+
+    // Later commits are ignored, even once they could be applied.
+    sink.with_connection(|conn| {
+        conn.execute(
+            "CREATE TABLE ItemName (_ddlog_key TEXT PRIMARY KEY, _ddlog_weight INTEGER NOT NULL)",
+            [],
+        )
+        .unwrap()
+    });
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    assert_eq!(sink.error().unwrap(), error);
+    assert!(rows(&sink, "ItemName", &["_ddlog_key"]).is_empty());
+    drop(sink);
+    hddlog.stop().unwrap();
+}
@@ -0,0 +1,164 @@
+//! The web introspection UI (`web_ui` feature).
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use differential_datalog::DDlogDynamic;
+use hddlog_features_ddlog::api::HDDlog;
+use hddlog_features_ddlog::ddlog_testing::{self, transaction};
+use hddlog_features_ddlog::web_ui::WebUi;
+use serde_json::Value;
+use tungstenite::Message;
+
+fn start() -> (Arc<HDDlog>, WebUi) {
+    let hddlog = Arc::new(ddlog_testing::start(1).unwrap());
+    let web_ui = WebUi::start(hddlog.clone(), "127.0.0.1:0").unwrap();
+    (hddlog, web_ui)
+}
+
+/// Send a request without a body and return the status and body of the
+/// response.
+fn request(web_ui: &WebUi, method: &str, path: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(web_ui.addr()).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        method,
+        path,
+        web_ui.addr()
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.splitn(2, "\r\n\r\n").nth(1).unwrap_or("");
+    (status, body.to_string())
+}
+
+fn get_json(web_ui: &WebUi, path: &str) -> (u16, Value) {
+    let (status, body) = request(web_ui, "GET", path);
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+#[test]
+fn index_and_plan() {
+    let (hddlog, web_ui) = start();
+    let (status, body) = request(&web_ui, "GET", "/");
+    assert_eq!(status, 200);
+    assert!(body.starts_with("<!DOCTYPE html>"));
+    let (status, _) = get_json(&web_ui, "/api/plan");
+    assert_eq!(status, 200);
+    drop(web_ui);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn metadata() {
+    let (hddlog, web_ui) = start();
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    let (status, metadata) = get_json(&web_ui, "/api/metadata");
+    assert_eq!(status, 200);
+    let relations = metadata["relations"].as_array().unwrap();
+    let relation = |name: &str| {
+        relations
+            .iter()
+            .find(|r| r["name"] == name)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(relation("Item")["input"], true);
+    assert_eq!(relation("ItemName")["input"], false);
+    assert_eq!(relation("ItemName")["output"], true);
+    drop(web_ui);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn relation_pages() {
+    let (hddlog, web_ui) = start();
+    transaction(
+        &hddlog,
+        r#"insert Item(1, "one"), insert Item(2, "two"), insert Item(3, "three");"#,
+    )
+    .unwrap();
+
+    let (status, page) = get_json(&web_ui, "/api/relations/ItemName");
+    assert_eq!(status, 200);
+    assert_eq!(page["relation"], "ItemName");
+    assert_eq!(page["total"], 3);
+    assert_eq!(page["records"].as_array().unwrap().len(), 3);
+    assert!(page["records"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|r| r["weight"] == 1));
+
+    let (status, page) = get_json(&web_ui, "/api/relations/ItemName?offset=1&limit=1");
+    assert_eq!(status, 200);
+    assert_eq!(page["total"], 3);
+    assert_eq!(page["offset"], 1);
+    assert_eq!(page["records"].as_array().unwrap().len(), 1);
+
+    let (_, page) = get_json(&web_ui, "/api/relations/ItemName?offset=5");
+    assert_eq!(page["total"], 3);
+    assert!(page["records"].as_array().unwrap().is_empty());
+    drop(web_ui);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn errors() {
+    let (hddlog, web_ui) = start();
+    let (status, error) = get_json(&web_ui, "/api/relations/Item");
+    assert_eq!(status, 400);
+    assert_eq!(error["error"], "unknown output relation Item");
+
+    let (status, error) = get_json(&web_ui, "/api/relations/ItemName?limit=many");
+    assert_eq!(status, 400);
+    assert_eq!(error["error"], "invalid value of limit: many");
+
+    let (status, error) = get_json(&web_ui, "/api/deltas/ItemName");
+    assert_eq!(status, 400);
+    assert_eq!(error["error"], "expected a websocket request");
+
+    let (status, _) = get_json(&web_ui, "/no/such/page");
+    assert_eq!(status, 404);
+
+    let (status, body) = request(&web_ui, "POST", "/api/metadata");
+    assert_eq!(status, 405);
+    assert!(body.contains("only GET requests are supported"));
+    drop(web_ui);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn tail_deltas() {
+    let (hddlog, web_ui) = start();
+    let (mut socket, _) =
+        tungstenite::connect(format!("ws://{}/api/deltas/ItemName", web_ui.addr())).unwrap();
+
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    transaction(&hddlog, r#"delete Item(1, "one");"#).unwrap();
+
+    let mut messages = Vec::new();
+    while messages.len() < 2 {
+        if let Message::Text(text) = socket.read_message().unwrap() {
+            messages.push(serde_json::from_str::<Value>(&text).unwrap());
+        }
+    }
+    assert_eq!(messages[0]["relation"], "ItemName");
+    assert_eq!(messages[0]["commit"], 1);
+    assert_eq!(messages[0]["changes"][0]["weight"], 1);
+    assert!(messages[0]["changes"][0]["record"]
+        .as_str()
+        .unwrap()
+        .contains("\"one\""));
+    assert_eq!(messages[1]["commit"], 2);
+    assert_eq!(messages[1]["changes"][0]["weight"], -1);
+
+    // Clients cannot tail input relations.
+    assert!(tungstenite::connect(format!("ws://{}/api/deltas/Item", web_ui.addr())).is_err());
+    drop(web_ui);
+    hddlog.stop().unwrap();
+}
@@ -0,0 +1,2 @@
+//! Tests of the relations maintained by the runtime of the crate generated
+//! for `hddlog_logs.dl` live in `tests/`.
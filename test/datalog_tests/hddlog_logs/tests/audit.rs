@@ -0,0 +1,96 @@
+//! The audit log of API calls (`audit::AuditLog`).
+
+use std::time::Duration;
+
+use differential_datalog::DDlogDynamic;
+use hddlog_logs_ddlog::ddlog_testing::{self, assert_relation, parse_updates, transaction};
+
+#[test]
+fn accesses_are_logged() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one"), insert Item(2, "two");"#).unwrap();
+    transaction(&hddlog, r#"delete Item(1, "one");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "AuditedAccess",
+        &[
+            r#"AuditedAccess(0, "insert", "Item", 2)"#,
+            r#"AuditedAccess(1, "delete", "Item", 1)"#,
+        ],
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn failed_commits_keep_accesses() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    assert!(transaction(&hddlog, "insert Divisor(0);")
+        .unwrap_err()
+        .contains("poisoned"));
+    hddlog.transaction_rollback().unwrap();
+    assert_relation(&hddlog, "AuditedAccess", &[]);
+
+    // The next commit logs the accesses of the failed transaction as well.
+    transaction(&hddlog, "insert Divisor(5);").unwrap();
+    assert_relation(&hddlog, "Quotient", &["Quotient(20)"]);
+    assert_relation(
+        &hddlog,
+        "AuditedAccess",
+        &[
+            r#"AuditedAccess(0, "insert", "Divisor", 1)"#,
+            r#"AuditedAccess(1, "insert", "Divisor", 1)"#,
+        ],
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn rolled_back_prepares_keep_accesses() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    hddlog.transaction_start().unwrap();
+    hddlog
+        .apply_updates_dynamic(
+            &mut parse_updates(r#"insert Item(1, "one");"#)
+                .unwrap()
+                .into_iter(),
+        )
+        .unwrap();
+    hddlog.transaction_prepare().unwrap();
+    hddlog.transaction_rollback().unwrap();
+
+    transaction(&hddlog, r#"insert Item(3, "three");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "AuditedAccess",
+        &[
+            r#"AuditedAccess(0, "insert", "Item", 1)"#,
+            r#"AuditedAccess(1, "insert", "Item", 1)"#,
+        ],
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn missed_deadlines_keep_accesses() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    hddlog.transaction_start().unwrap();
+    hddlog
+        .apply_updates_dynamic(&mut parse_updates("insert Steps(20000);").unwrap().into_iter())
+        .unwrap();
+    assert!(hddlog
+        .transaction_commit_with_deadline(Duration::from_secs(0))
+        .unwrap_err()
+        .contains("has been rolled back"));
+    assert_relation(&hddlog, "AuditedAccess", &[]);
+
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "AuditedAccess",
+        &[
+            r#"AuditedAccess(0, "insert", "Steps", 1)"#,
+            r#"AuditedAccess(1, "insert", "Item", 1)"#,
+        ],
+    );
+    hddlog.stop().unwrap();
+}
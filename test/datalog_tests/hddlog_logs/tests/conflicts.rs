@@ -0,0 +1,223 @@
+//! Primary-key conflict policies and the conflict relation
+//! (`key_conflicts::KeyConflict`).
+
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+
+use differential_datalog::ddval::DDValue;
+use differential_datalog::program::conflict::ConflictPolicy;
+use differential_datalog::program::RelId;
+use differential_datalog::record::{IntoRecord, Record};
+use differential_datalog::DDlogDynamic;
+use hddlog_logs_ddlog::api::HDDlog;
+use hddlog_logs_ddlog::ddlog_testing::{self, assert_relation, parse_updates, transaction};
+use hddlog_logs_ddlog::{relval_from_record, Relations};
+
+fn set_policy(hddlog: &HDDlog, policy: Option<ConflictPolicy>) {
+    hddlog
+        .set_conflict_policy(Relations::Item as RelId, policy)
+        .unwrap();
+}
+
+/// Merges two items into one whose name joins their names.
+fn merge_names(existing: &DDValue, new: &DDValue) -> Result<DDValue, String> {
+    let fields = |v: &DDValue| match v.clone().into_record() {
+        Record::NamedStruct(_, fields) => Ok(fields),
+        rec => Err(format!("unexpected record {}", rec)),
+    };
+    let name = |fields: &[(Cow<'static, str>, Record)]| {
+        fields
+            .iter()
+            .find(|(f, _)| f == "name")
+            .and_then(|(_, v)| match v {
+                Record::String(s) => Some(s.clone()),
+                _ => None,
+            })
+            .ok_or_else(|| "item without a name".to_string())
+    };
+    let existing = fields(existing)?;
+    let mut new = fields(new)?;
+    let merged = format!("{}+{}", name(&existing)?, name(&new)?);
+    for (f, v) in new.iter_mut() {
+        if f == "name" {
+            *v = Record::String(merged.clone());
+        }
+    }
+    relval_from_record(
+        Relations::Item,
+        &Record::NamedStruct(Cow::from("Item"), new),
+    )
+}
+
+fn start_transaction(hddlog: &HDDlog, updates: &str) {
+    hddlog.transaction_start().unwrap();
+    hddlog
+        .apply_updates_dynamic(&mut parse_updates(updates).unwrap().into_iter())
+        .unwrap();
+}
+
+#[test]
+fn rejected_conflicts() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    assert!(transaction(&hddlog, r#"insert Item(1, "uno");"#)
+        .unwrap_err()
+        .contains("duplicate key"));
+    assert_relation(&hddlog, "Conflict", &[]);
+
+    // The rejected insertion is recorded by the next commit.
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "ItemName",
+        &[r#"ItemName(1, "one")"#, r#"ItemName(2, "two")"#],
+    );
+    assert_relation(
+        &hddlog,
+        "Conflict",
+        &[r#"Conflict(0, "Item", "1", "Item{.id = 1, .name = \"uno\"}", "rejected")"#],
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn resolved_conflicts() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+
+    set_policy(&hddlog, Some(ConflictPolicy::KeepExisting));
+    transaction(&hddlog, r#"insert Item(1, "uno");"#).unwrap();
+    assert_relation(&hddlog, "ItemName", &[r#"ItemName(1, "one")"#]);
+
+    set_policy(&hddlog, Some(ConflictPolicy::Replace));
+    transaction(&hddlog, r#"insert Item(1, "eins");"#).unwrap();
+    assert_relation(&hddlog, "ItemName", &[r#"ItemName(1, "eins")"#]);
+
+    set_policy(&hddlog, Some(ConflictPolicy::Merge(Arc::new(merge_names))));
+    transaction(&hddlog, r#"insert Item(1, "un");"#).unwrap();
+    assert_relation(&hddlog, "ItemName", &[r#"ItemName(1, "eins+un")"#]);
+
+    // Removing the policy restores the default.
+    set_policy(&hddlog, None);
+    assert!(transaction(&hddlog, r#"insert Item(1, "ein");"#).is_err());
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "Conflict",
+        &[
+            r#"Conflict(0, "Item", "1", "Item{.id = 1, .name = \"uno\"}", "kept_existing")"#,
+            r#"Conflict(1, "Item", "1", "Item{.id = 1, .name = \"eins\"}", "replaced")"#,
+            r#"Conflict(2, "Item", "1", "Item{.id = 1, .name = \"un\"}", "merged")"#,
+            r#"Conflict(3, "Item", "1", "Item{.id = 1, .name = \"ein\"}", "rejected")"#,
+        ],
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn policy_errors() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    // Only input relations with a primary key have conflicts.
+    assert!(hddlog
+        .set_conflict_policy(Relations::Divisor as RelId, Some(ConflictPolicy::Replace))
+        .unwrap_err()
+        .contains("does not have a primary key"));
+
+    // Merges that fail or change the key reject the insertion.
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    set_policy(
+        &hddlog,
+        Some(ConflictPolicy::Merge(Arc::new(
+            |_: &DDValue, _: &DDValue| Err("cannot merge".to_string()),
+        ))),
+    );
+    assert!(transaction(&hddlog, r#"insert Item(1, "uno");"#)
+        .unwrap_err()
+        .contains("cannot merge"));
+    set_policy(
+        &hddlog,
+        Some(ConflictPolicy::Merge(Arc::new(
+            |_: &DDValue, _: &DDValue| {
+                relval_from_record(
+                    Relations::Item,
+                    &Record::NamedStruct(
+                        Cow::from("Item"),
+                        vec![
+                            (Cow::from("id"), Record::Int(2.into())),
+                            (Cow::from("name"), Record::String("two".to_string())),
+                        ],
+                    ),
+                )
+            },
+        ))),
+    );
+    assert!(transaction(&hddlog, r#"insert Item(1, "uno");"#)
+        .unwrap_err()
+        .contains("different key"));
+    assert_relation(&hddlog, "ItemName", &[r#"ItemName(1, "one")"#]);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn failed_commits_keep_conflicts() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    set_policy(&hddlog, Some(ConflictPolicy::Replace));
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+
+    // The commit inserts the conflict into the conflict relation before it
+    // fails; the next commit inserts it again.
+    assert!(
+        transaction(&hddlog, r#"insert Item(1, "uno"), insert Divisor(0);"#)
+            .unwrap_err()
+            .contains("poisoned")
+    );
+    hddlog.transaction_rollback().unwrap();
+    assert_relation(&hddlog, "Conflict", &[]);
+
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "Conflict",
+        &[r#"Conflict(0, "Item", "1", "Item{.id = 1, .name = \"uno\"}", "replaced")"#],
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn missed_deadlines_keep_conflicts() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    set_policy(&hddlog, Some(ConflictPolicy::Replace));
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+
+    start_transaction(&hddlog, r#"insert Item(1, "uno"), insert Steps(20000);"#);
+    assert!(hddlog
+        .transaction_commit_with_deadline(Duration::from_secs(0))
+        .unwrap_err()
+        .contains("has been rolled back"));
+    assert_relation(&hddlog, "Conflict", &[]);
+
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "ItemName",
+        &[r#"ItemName(1, "one")"#, r#"ItemName(2, "two")"#],
+    );
+    assert_relation(
+        &hddlog,
+        "Conflict",
+        &[r#"Conflict(0, "Item", "1", "Item{.id = 1, .name = \"uno\"}", "replaced")"#],
+    );
+
+    // Later conflicts continue the sequence.
+    transaction(&hddlog, r#"insert Item(2, "dos");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "Conflict",
+        &[
+            r#"Conflict(0, "Item", "1", "Item{.id = 1, .name = \"uno\"}", "replaced")"#,
+            r#"Conflict(1, "Item", "2", "Item{.id = 2, .name = \"dos\"}", "replaced")"#,
+        ],
+    );
+    hddlog.stop().unwrap();
+}
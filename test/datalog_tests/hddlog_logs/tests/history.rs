@@ -0,0 +1,207 @@
+//! The history of input relations (`history::Fact`).
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use differential_datalog::access::AccessRequest;
+use differential_datalog::ddval::DDValConvert;
+use differential_datalog::program::{IdxId, RelId};
+use differential_datalog::DDlogDynamic;
+use hddlog_logs_ddlog::api::HDDlog;
+use hddlog_logs_ddlog::ddlog_testing::{self, assert_relation, parse_updates, transaction};
+use hddlog_logs_ddlog::{Indexes, Relations};
+
+const RETENTION: Duration = Duration::from_secs(3600);
+
+fn retain(hddlog: &HDDlog, retention: Option<Duration>) {
+    hddlog
+        .set_history_retention(Relations::Item as RelId, retention)
+        .unwrap();
+}
+
+#[test]
+fn insertions_and_deletions() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    retain(&hddlog, Some(RETENTION));
+
+    // The changes of a transaction are part of its own history.
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "ItemHistory",
+        &[
+            r#"ItemHistory("Item{.id = 1, .name = \"one\"}", true, false)"#,
+            r#"ItemHistory("Item{.id = 2, .name = \"two\"}", false, false)"#,
+        ],
+    );
+
+    transaction(&hddlog, r#"delete Item(1, "one"), delete Item(2, "two");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "ItemHistory",
+        &[
+            r#"ItemHistory("Item{.id = 1, .name = \"one\"}", true, true)"#,
+            r#"ItemHistory("Item{.id = 2, .name = \"two\"}", false, true)"#,
+        ],
+    );
+
+    // A fact inserted again gets a new row next to its tombstone.
+    transaction(&hddlog, r#"insert Item(2, "two");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "ItemHistory",
+        &[
+            r#"ItemHistory("Item{.id = 1, .name = \"one\"}", true, true)"#,
+            r#"ItemHistory("Item{.id = 2, .name = \"two\"}", false, false)"#,
+            r#"ItemHistory("Item{.id = 2, .name = \"two\"}", false, true)"#,
+        ],
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn retention() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    retain(&hddlog, Some(Duration::from_millis(1)));
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    transaction(&hddlog, r#"delete Item(1, "one"), insert Item(2, "two");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "ItemHistory",
+        &[
+            r#"ItemHistory("Item{.id = 1, .name = \"one\"}", false, true)"#,
+            r#"ItemHistory("Item{.id = 2, .name = \"two\"}", false, false)"#,
+        ],
+    );
+
+    // Tombstones expire when a transaction commits after their retention
+    // period.
+    thread::sleep(Duration::from_millis(10));
+    transaction(&hddlog, r#"insert Item(3, "three");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "ItemHistory",
+        &[
+            r#"ItemHistory("Item{.id = 2, .name = \"two\"}", false, false)"#,
+            r#"ItemHistory("Item{.id = 3, .name = \"three\"}", false, false)"#,
+        ],
+    );
+
+    // Stopping retention removes the history with the next commit.
+    retain(&hddlog, None);
+    transaction(&hddlog, r#"insert Item(4, "four");"#).unwrap();
+    assert_relation(&hddlog, "ItemHistory", &[]);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn failed_commits() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, r#"insert Item(1, "one");"#).unwrap();
+    retain(&hddlog, Some(RETENTION));
+
+    // Neither the history of the facts present when retention started nor
+    // that of the failed transaction is lost or recorded by the failure.
+    assert!(
+        transaction(&hddlog, r#"delete Item(1, "one"), insert Divisor(0);"#)
+            .unwrap_err()
+            .contains("poisoned")
+    );
+    hddlog.transaction_rollback().unwrap();
+    assert_relation(&hddlog, "ItemHistory", &[]);
+
+    hddlog.transaction_start().unwrap();
+    hddlog
+        .apply_updates_dynamic(
+            &mut parse_updates(r#"insert Item(2, "two"), insert Steps(20000);"#)
+                .unwrap()
+                .into_iter(),
+        )
+        .unwrap();
+    assert!(hddlog
+        .transaction_commit_with_deadline(Duration::from_secs(0))
+        .unwrap_err()
+        .contains("has been rolled back"));
+    assert_relation(&hddlog, "ItemHistory", &[]);
+
+    transaction(&hddlog, r#"insert Item(3, "three");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "ItemHistory",
+        &[
+            r#"ItemHistory("Item{.id = 1, .name = \"one\"}", true, false)"#,
+            r#"ItemHistory("Item{.id = 3, .name = \"three\"}", false, false)"#,
+        ],
+    );
+
+    // The tombstone of a fact deleted later refers to its row.
+    transaction(&hddlog, r#"delete Item(1, "one");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "ItemHistory",
+        &[
+            r#"ItemHistory("Item{.id = 1, .name = \"one\"}", true, true)"#,
+            r#"ItemHistory("Item{.id = 3, .name = \"three\"}", false, false)"#,
+        ],
+    );
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn time_travel() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    let index = Indexes::ItemHistoryByDeleted as IdxId;
+    hddlog.set_index_time_travel(index, 10).unwrap();
+    retain(&hddlog, Some(RETENTION));
+    let count = |deleted: bool, commit: u64| {
+        hddlog
+            .query_index_asof(index, deleted.into_ddvalue(), commit)
+            .unwrap()
+            .len()
+    };
+
+    transaction(&hddlog, r#"insert Item(1, "one"), insert Item(2, "two");"#).unwrap();
+    let inserted = hddlog.commit_number();
+    transaction(&hddlog, r#"delete Item(1, "one");"#).unwrap();
+    let deleted = hddlog.commit_number();
+
+    // The history as of a commit includes the changes of that commit.
+    assert_eq!((count(false, inserted), count(true, inserted)), (2, 0));
+    assert_eq!((count(false, deleted), count(true, deleted)), (1, 1));
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn retention_errors() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    assert!(hddlog
+        .set_history_retention(Relations::history_Fact as RelId, Some(RETENTION))
+        .unwrap_err()
+        .contains("cannot retain the history of history::Fact"));
+
+    hddlog.transaction_start().unwrap();
+    assert!(hddlog
+        .set_history_retention(Relations::Item as RelId, Some(RETENTION))
+        .unwrap_err()
+        .contains("transaction in progress"));
+    hddlog.transaction_rollback().unwrap();
+
+    // Retaining the history of a relation requires query access to it.
+    hddlog.set_access_policy(Some(Arc::new(|req: &AccessRequest| {
+        if req.operation.is_read_only() {
+            Err("no queries".to_string())
+        } else {
+            Ok(())
+        }
+    })));
+    assert_eq!(
+        hddlog
+            .set_history_retention(Relations::Item as RelId, Some(RETENTION))
+            .unwrap_err(),
+        "query on relation Item denied: no queries"
+    );
+    hddlog.set_access_policy(None);
+    hddlog.stop().unwrap();
+}
@@ -0,0 +1,103 @@
+//! Integrity constraints (`integrity::Violation`).
+
+use differential_datalog::DDlogDynamic;
+use hddlog_logs_ddlog::api::ConstraintMode;
+use hddlog_logs_ddlog::ddlog_testing::{self, assert_relation, transaction};
+
+#[test]
+fn report_violations() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    assert_eq!(
+        hddlog.constraint_mode("purchase_item"),
+        ConstraintMode::Report
+    );
+
+    transaction(&hddlog, "insert Purchase(1, 5);").unwrap();
+    assert_relation(
+        &hddlog,
+        "Violated",
+        &[r#"Violated("purchase_item", "dangling_reference", "Purchase", "1")"#],
+    );
+
+    // Violations are retracted once fixed.
+    transaction(
+        &hddlog,
+        r#"insert Item(5, "five"), insert Item(6, "five");"#,
+    )
+    .unwrap();
+    assert_relation(
+        &hddlog,
+        "Violated",
+        &[r#"Violated("item_name", "duplicate_key", "Item", "five")"#],
+    );
+    transaction(&hddlog, r#"delete Item(6, "five");"#).unwrap();
+    assert_relation(&hddlog, "Violated", &[]);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn reject_violations() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    hddlog.set_constraint_mode("purchase_item", ConstraintMode::Reject);
+    assert_eq!(
+        hddlog.constraint_mode("purchase_item"),
+        ConstraintMode::Reject
+    );
+
+    let err = transaction(
+        &hddlog,
+        r#"insert Item(5, "five"), insert Purchase(1, 5), insert Purchase(2, 7);"#,
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        "transaction violates integrity constraints and has been rolled back: \
+         purchase_item (dangling_reference in Purchase: 2)"
+    );
+    assert_relation(&hddlog, "ItemName", &[]);
+    assert_relation(&hddlog, "Violated", &[]);
+
+    // Violations of constraints that only report them do not fail commits.
+    transaction(
+        &hddlog,
+        r#"insert Item(5, "five"), insert Item(6, "five"), insert Purchase(1, 5);"#,
+    )
+    .unwrap();
+    assert_relation(
+        &hddlog,
+        "Violated",
+        &[r#"Violated("item_name", "duplicate_key", "Item", "five")"#],
+    );
+
+    // Had the rejected transaction been committed, its dangling purchase
+    // would now be reported.
+    hddlog.set_constraint_mode("purchase_item", ConstraintMode::Report);
+    transaction(&hddlog, r#"delete Item(6, "five");"#).unwrap();
+    assert_relation(&hddlog, "Violated", &[]);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn keep_existing_violations() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    transaction(&hddlog, "insert Purchase(1, 5);").unwrap();
+
+    // Violations that predate the switch to `Reject` do not fail commits.
+    hddlog.set_constraint_mode("purchase_item", ConstraintMode::Reject);
+    transaction(&hddlog, r#"insert Item(6, "six");"#).unwrap();
+    assert_relation(
+        &hddlog,
+        "Violated",
+        &[r#"Violated("purchase_item", "dangling_reference", "Purchase", "1")"#],
+    );
+    assert!(transaction(&hddlog, "insert Purchase(2, 7);")
+        .unwrap_err()
+        .contains("purchase_item (dangling_reference in Purchase: 2)"));
+    assert_relation(&hddlog, "ItemName", &[r#"ItemName(6, "six")"#]);
+    assert_relation(
+        &hddlog,
+        "Violated",
+        &[r#"Violated("purchase_item", "dangling_reference", "Purchase", "1")"#],
+    );
+    hddlog.stop().unwrap();
+}
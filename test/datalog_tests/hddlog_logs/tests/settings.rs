@@ -0,0 +1,113 @@
+//! Hot-reloadable settings (`settings::Setting`).
+
+use std::fs;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use differential_datalog::DDlogDynamic;
+use hddlog_logs_ddlog::api::{HDDlog, SettingValue, Settings, SettingsChange};
+use hddlog_logs_ddlog::ddlog_testing::{self, assert_relation};
+
+fn settings(threshold: Option<i64>) -> Settings {
+    let mut settings = Settings::new();
+    settings.insert("name".to_string(), SettingValue::String("x".to_string()));
+    if let Some(threshold) = threshold {
+        settings.insert("threshold".to_string(), SettingValue::Int(threshold));
+    }
+    settings
+}
+
+#[test]
+fn apply_settings() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    assert_relation(&hddlog, "Threshold", &["Threshold(100)"]);
+
+    let change = hddlog.apply_settings(settings(Some(5))).unwrap();
+    assert_eq!(change.updated, vec!["name", "threshold"]);
+    assert_relation(&hddlog, "Threshold", &["Threshold(5)"]);
+
+    // Only changed settings are updated.
+    let change = hddlog.apply_settings(settings(Some(7))).unwrap();
+    assert_eq!(
+        change,
+        SettingsChange {
+            updated: vec!["threshold".to_string()],
+            removed: vec![]
+        }
+    );
+    assert!(hddlog.apply_settings(settings(Some(7))).unwrap().is_empty());
+
+    let change = hddlog.apply_settings(settings(None)).unwrap();
+    assert_eq!(change.removed, vec!["threshold"]);
+    assert_relation(&hddlog, "Threshold", &["Threshold(100)"]);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn fails_during_transactions() {
+    let hddlog = ddlog_testing::start(1).unwrap();
+    hddlog.transaction_start().unwrap();
+    assert!(hddlog.apply_settings(settings(Some(5))).is_err());
+    hddlog.transaction_commit().unwrap();
+    // The failed update is not remembered as applied.
+    assert!(!hddlog.apply_settings(settings(Some(5))).unwrap().is_empty());
+    assert_relation(&hddlog, "Threshold", &["Threshold(5)"]);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn load_settings() {
+    let dir = tempfile::tempdir().unwrap();
+    let json = dir.path().join("settings.json");
+    fs::write(&json, r#"{"threshold": 3}"#).unwrap();
+    let toml = dir.path().join("settings.toml");
+    fs::write(&toml, "threshold = 4").unwrap();
+    let invalid = dir.path().join("invalid.toml");
+    fs::write(&invalid, "threshold = ").unwrap();
+
+    let hddlog = ddlog_testing::start(1).unwrap();
+    hddlog.load_settings(&json).unwrap();
+    assert_relation(&hddlog, "Threshold", &["Threshold(3)"]);
+    hddlog.load_settings(&toml).unwrap();
+    assert_relation(&hddlog, "Threshold", &["Threshold(4)"]);
+    assert!(hddlog
+        .load_settings(&invalid)
+        .unwrap_err()
+        .contains("invalid settings file"));
+    assert_relation(&hddlog, "Threshold", &["Threshold(4)"]);
+    hddlog.stop().unwrap();
+}
+
+#[test]
+fn watch_settings() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("settings.toml");
+    fs::write(&path, "threshold = 1").unwrap();
+
+    let hddlog = Arc::new(ddlog_testing::start(1).unwrap());
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    let watcher = HDDlog::watch_settings(
+        &hddlog,
+        &path,
+        Duration::from_millis(10),
+        Arc::new(move |res: Result<&SettingsChange, &str>| {
+            let _ = tx
+                .lock()
+                .unwrap()
+                .send(res.map(Clone::clone).map_err(str::to_string));
+        }),
+    )
+    .unwrap();
+    assert_relation(&hddlog, "Threshold", &["Threshold(1)"]);
+
+    // Make sure that the modification time changes.
+    std::thread::sleep(Duration::from_millis(1100));
+    fs::write(&path, "threshold = 2").unwrap();
+    let change = rx.recv_timeout(Duration::from_secs(10)).unwrap().unwrap();
+    assert_eq!(change.updated, vec!["threshold"]);
+    assert_relation(&hddlog, "Threshold", &["Threshold(2)"]);
+
+    drop(watcher);
+    hddlog.stop().unwrap();
+}
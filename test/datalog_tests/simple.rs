@@ -13,3 +13,13 @@ pub fn g(a: &ddlog_bigint::Int) -> ddlog_bigint::Int {
 pub fn h(a: &ddlog_std::tuple2<ddlog_bigint::Int, ddlog_bigint::Int>) -> ddlog_std::tuple2<ddlog_bigint::Int, ddlog_bigint::Int> {
     ddlog_std::tuple2::default()
 }
+
+pub fn view_lengths(
+    v: ddlog_std::VecView<String>,
+    s: ddlog_std::SetView<String>,
+    m: ddlog_std::MapView<String, ddlog_bigint::Int>,
+    n: &mut u64,
+) -> u64 {
+    *n = (v.len() + s.len() + m.len()) as u64;
+    *n
+}
@@ -28,6 +28,25 @@ test_lib set_test
 test_lib hashset_test
 test_lib group_test
 test_lib base64_test
+test_lib ddlog_strings_test
+test_lib codec_test
+test_lib bytes_test
+test_lib ddlog_crypto_test
+test_lib compression_test
+test_lib xml_test
+test_lib graph_test
+test_lib ddlog_linalg_test
+test_lib ddlog_stats_test
+test_lib ddlog_random_test
+test_lib ddlog_geo_test
+test_lib semver_test
+test_lib money_test
+test_lib smallvec_test
+test_lib ddlog_fmt_test
+test_lib cel_test
+test_lib jsonschema_test
+test_lib jinja_test
+test_lib bitemporal_test
 
 # No flatbuf support for Time, Date, etc yet
 FLATBUF=0 ./run-test.sh time_test.dl release